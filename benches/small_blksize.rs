@@ -0,0 +1,95 @@
+//! Throughput of a transfer with the 128-byte blocks of constrained
+//! bootloaders and a window of 16 blocks, over loopback.
+//!
+//! Run with `cargo bench --bench small_blksize > /dev/null`, the results are
+//! printed to standard error and the log of the server to standard output.
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use tftpd::test_util::{TestDir, TestServer};
+use tftpd::{OptionType, Packet, TransferOption};
+
+const BLKSIZE: usize = 128;
+const WINDOWSIZE: usize = 16;
+/// Two wraps of the block number, plus a short last block.
+const SIZE: usize = 2 * 65536 * BLKSIZE + 77;
+const RUNS: u32 = 3;
+
+fn main() {
+    let dir = TestDir::new();
+    dir.write("rom.bin", &vec![0x5A; SIZE]);
+    let server = TestServer::start(dir.path());
+    let server_addr = server.addr();
+
+    for run in 1..=RUNS {
+        let started = Instant::now();
+        let received = transfer(server_addr);
+        let elapsed = started.elapsed();
+        assert_eq!(received, SIZE);
+
+        eprintln!(
+            "run {run}: {SIZE} bytes with blksize {BLKSIZE} and windowsize {WINDOWSIZE} in {elapsed:?}, {:.2} MB/s, {:.0} blocks/s",
+            SIZE as f64 / elapsed.as_secs_f64() / 1e6,
+            SIZE.div_ceil(BLKSIZE) as f64 / elapsed.as_secs_f64(),
+        );
+    }
+}
+
+/// Downloads `rom.bin`, acknowledging every window, and returns the number
+/// of bytes received.
+fn transfer(server: SocketAddr) -> usize {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let request = Packet::Rrq {
+        filename: "rom.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: BLKSIZE as u64,
+            },
+            TransferOption {
+                option: OptionType::Windowsize,
+                value: WINDOWSIZE as u64,
+            },
+        ],
+    };
+    client
+        .send_to(&request.serialize().unwrap(), server)
+        .unwrap();
+
+    let mut buf = [0; 65536];
+    let (_, from) = client.recv_from(&mut buf).unwrap();
+    client
+        .send_to(&Packet::Ack(0).serialize().unwrap(), from)
+        .unwrap();
+
+    let mut received = 0;
+    let mut last_block = 0u16;
+    loop {
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        let Ok(Packet::Data { block_num, data }) = Packet::deserialize(&buf[..size]) else {
+            panic!("expected a DATA packet");
+        };
+        if block_num != last_block.wrapping_add(1) {
+            continue;
+        }
+        last_block = block_num;
+        received += data.len();
+
+        let short = data.len() < BLKSIZE;
+        if short || (block_num as usize).is_multiple_of(WINDOWSIZE) {
+            client
+                .send_to(&Packet::Ack(block_num).serialize().unwrap(), from)
+                .unwrap();
+        }
+        if short {
+            return received;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Embeds the server in another program, using only the items of the
+//! `tftpd::prelude`: serves a directory on a free loopback port, refuses
+//! one file, reports the finished transfers and downloads a file back.
+//!
+//! ```sh
+//! cargo run --example embedded
+//! ```
+
+use std::{fs, net::SocketAddr, sync::mpsc, sync::Arc, thread};
+
+use tftpd::prelude::*;
+
+/// Prints the end of every transfer.
+struct Reporter;
+
+impl Observer for Reporter {
+    fn on_event(&self, event: &TransferEvent) {
+        match event {
+            TransferEvent::Completed { client, bytes, .. } => {
+                println!("served {bytes} bytes to {client}")
+            }
+            TransferEvent::Failed { client, reason, .. } => {
+                println!("transfer to {client} failed: {reason}")
+            }
+            _ => {}
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let directory = std::env::temp_dir().join(format!("tftpd-embedded-{}", std::process::id()));
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join("pxelinux.0"), vec![0x55; 3000])?;
+    fs::write(directory.join("secret.key"), b"not for clients")?;
+
+    let config = Config {
+        port: 0,
+        directory: directory.clone(),
+        ..Config::default()
+    };
+    let (started, address) = mpsc::channel::<Result<SocketAddr, TftpError>>();
+    thread::spawn(move || {
+        let mut server = match Server::new(&config) {
+            Ok(server) => server,
+            Err(err) => {
+                let _ = started.send(Err(err));
+                return;
+            }
+        };
+        server.set_observer(Arc::new(Reporter));
+        server.set_authorizer(Arc::new(|_: &SocketAddr, filename: &str| {
+            if filename.ends_with(".key") {
+                Decision::Deny {
+                    code: ErrorCode::AccessViolation,
+                    message: "keys are not served".to_string(),
+                }
+            } else {
+                Decision::Allow
+            }
+        }));
+        started.send(Ok(server.listen_addresses()[0])).unwrap();
+        if let Err(err) = server.listen() {
+            eprintln!("server stopped: {err}");
+        }
+    });
+    let server = address.recv()??;
+
+    let client = Client::new(server);
+    let options = vec![TransferOption {
+        option: OptionType::BlockSize,
+        value: 1024,
+    }];
+    let download = client.get("pxelinux.0", options)?;
+    println!("downloaded {} bytes", download.data.len());
+    if let Err(err) = client.get("secret.key", vec![]) {
+        println!("secret.key refused: {err}");
+    }
+
+    fs::remove_dir_all(directory)?;
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{ErrorCode, QuirkSet, StateOptions, TransferOption};
+
+/// Decision `enum` is the answer of an [`Authorizer`] to a read request.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Decision {
+    /// The file is served
+    Allow,
+    /// The request is answered with an ERROR
+    Deny {
+        /// Code of the ERROR
+        code: ErrorCode,
+        /// Message of the ERROR
+        message: String,
+    },
+    /// The request is answered as if the file did not exist, so that
+    /// unauthorized clients cannot tell which files do
+    NotFoundMask,
+}
+
+/// Authorizer `trait` decides which client may read which file. It is
+/// consulted by the [`Server`](crate::Server) for every read request, after
+/// the filename is decoded and before the file is looked up.
+///
+/// Closures taking the client and the filename are authorizers, and
+/// several can be combined with [`AllOf`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::{net::SocketAddr, sync::Arc};
+/// use tftpd::{Authorizer, Decision};
+///
+/// let lab_only = |client: &SocketAddr, _: &str| {
+///     if client.ip().is_loopback() {
+///         Decision::Allow
+///     } else {
+///         Decision::NotFoundMask
+///     }
+/// };
+/// let authorizer: Arc<dyn Authorizer> = Arc::new(lab_only);
+/// ```
+pub trait Authorizer: Send + Sync {
+    /// Returns whether `client` may read `filename`, relative to the served
+    /// directory.
+    fn authorize(&self, client: &SocketAddr, filename: &str) -> Decision;
+
+    /// Returns whether the client of `request` may read or write its file,
+    /// knowing its options and how they negotiate. Authorizers telling
+    /// clients apart by their options, or allowing uploads with
+    /// `--writable`, implement this one. The default calls
+    /// [`authorize()`](Authorizer::authorize) with the client and filename
+    /// for read requests, and denies write requests, which `authorize()`
+    /// cannot tell from reads.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::net::SocketAddr;
+    /// use tftpd::{Authorizer, Decision, ErrorCode, RequestInfo};
+    ///
+    /// /// Chainloads vendor ROMs into iPXE, which asks for a window size.
+    /// struct IpxeOnly;
+    ///
+    /// impl Authorizer for IpxeOnly {
+    ///     fn authorize(&self, _: &SocketAddr, _: &str) -> Decision {
+    ///         Decision::Allow
+    ///     }
+    ///
+    ///     fn authorize_request(&self, request: &RequestInfo) -> Decision {
+    ///         match &request.negotiated {
+    ///             Some(negotiated) if negotiated.windowsize > 1 => Decision::Allow,
+    ///             _ if request.filename == "undionly.kpxe" => Decision::Allow,
+    ///             _ => Decision::Deny {
+    ///                 code: ErrorCode::AccessViolation,
+    ///                 message: "boot undionly.kpxe first".to_string(),
+    ///             },
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        if request.write {
+            return Decision::Deny {
+                code: ErrorCode::AccessViolation,
+                message: "file access violation".to_string(),
+            };
+        }
+        self.authorize(&request.client, &request.filename)
+    }
+}
+
+/// RequestInfo `struct` describes a read or write request to
+/// [`Authorizer::authorize_request()`], before its file is looked up.
+///
+/// New fields may be added, so it cannot be built or matched exhaustively
+/// outside of the crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RequestInfo {
+    /// Address of the client
+    pub client: SocketAddr,
+    /// Requested filename, decoded and relative to the served directory
+    pub filename: String,
+    /// Options of the request in the order received. Options the server
+    /// does not know are dropped when the request is parsed.
+    pub options: Vec<TransferOption>,
+    /// Parameters the options negotiate to, as long as the size of the file
+    /// is unknown, so without the transfer size. `None` when the options
+    /// cannot be negotiated and the request will be refused.
+    pub negotiated: Option<StateOptions>,
+    /// ID the transfer gets if the request is served, distinguishing it
+    /// from earlier transfers of the server
+    pub transfer: u64,
+    /// ID of the journey of the client host the transfer joins
+    pub journey: u64,
+    /// Compatibility workarounds of the `--quirk`s matching the request
+    pub quirks: QuirkSet,
+    /// Whether the request is a write request, which `--writable` accepts
+    pub write: bool,
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&SocketAddr, &str) -> Decision + Send + Sync,
+{
+    fn authorize(&self, client: &SocketAddr, filename: &str) -> Decision {
+        self(client, filename)
+    }
+}
+
+/// AllOf `struct` is an [`Authorizer`] allowing what all of its authorizers
+/// allow. The first decision other than [`Decision::Allow`] is returned.
+pub struct AllOf(pub Vec<Arc<dyn Authorizer>>);
+
+impl Authorizer for AllOf {
+    fn authorize(&self, client: &SocketAddr, filename: &str) -> Decision {
+        self.0
+            .iter()
+            .map(|authorizer| authorizer.authorize(client, filename))
+            .find(|decision| *decision != Decision::Allow)
+            .unwrap_or(Decision::Allow)
+    }
+
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        self.0
+            .iter()
+            .map(|authorizer| authorizer.authorize_request(request))
+            .find(|decision| *decision != Decision::Allow)
+            .unwrap_or(Decision::Allow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> SocketAddr {
+        SocketAddr::from(([192, 168, 0, 10], 1234))
+    }
+
+    #[test]
+    fn returns_first_refusal() {
+        let deny = Decision::Deny {
+            code: ErrorCode::AccessViolation,
+            message: "not yet".to_string(),
+        };
+        let denied = deny.clone();
+        let authorizer = AllOf(vec![
+            Arc::new(|_: &SocketAddr, _: &str| Decision::Allow),
+            Arc::new(move |_: &SocketAddr, filename: &str| {
+                if filename == "pxelinux.0" {
+                    denied.clone()
+                } else {
+                    Decision::Allow
+                }
+            }),
+            Arc::new(|_: &SocketAddr, _: &str| Decision::NotFoundMask),
+        ]);
+
+        assert_eq!(authorizer.authorize(&client(), "pxelinux.0"), deny);
+        assert_eq!(
+            authorizer.authorize(&client(), "initrd"),
+            Decision::NotFoundMask
+        );
+        assert_eq!(
+            AllOf(vec![]).authorize(&client(), "initrd"),
+            Decision::Allow
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Client, NegotiationNote, OptionType, TransferOption};
+
+/// Pause of a client after a failed transfer, so that a server refusing
+/// the file is not flooded with requests.
+const ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// BenchOptions `struct` describes a load test run by [`bench()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Address of the server under test
+    pub server: SocketAddr,
+    /// File every client downloads again and again
+    pub file: String,
+    /// Number of concurrent clients
+    pub clients: usize,
+    /// Time after which the clients stop starting transfers
+    pub duration: Duration,
+    /// Time over which the client starts are spread, so that the clients
+    /// do not all start in the same instant
+    pub ramp: Duration,
+    /// Block size requested, if any
+    pub blk_size: Option<u64>,
+    /// Window size requested, if any
+    pub windowsize: Option<u64>,
+    /// Time without an answer after which a client sends its last packet
+    /// again
+    pub timeout: Duration,
+}
+
+impl BenchOptions {
+    /// Creates the options of a 10 seconds test of one client downloading
+    /// `file` from `server`, started over one second.
+    pub fn new(server: SocketAddr, file: &str) -> BenchOptions {
+        BenchOptions {
+            server,
+            file: file.to_string(),
+            clients: 1,
+            duration: Duration::from_secs(10),
+            ramp: Duration::from_secs(1),
+            blk_size: None,
+            windowsize: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn options(&self) -> Vec<TransferOption> {
+        [
+            (OptionType::BlockSize, self.blk_size),
+            (OptionType::Windowsize, self.windowsize),
+        ]
+        .into_iter()
+        .filter_map(|(option, value)| value.map(|value| TransferOption { option, value }))
+        .collect()
+    }
+}
+
+/// BenchReport `struct` sums up the transfers of a load test, returned by
+/// [`bench()`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BenchReport {
+    /// Number of clients that ran
+    pub clients: usize,
+    /// Time from the first client start to the end of the last transfer
+    pub elapsed: Duration,
+    /// Number of transfers that completed
+    pub transfers: u64,
+    /// Number of transfers that failed
+    pub errors: u64,
+    /// Number of bytes received by completed transfers
+    pub bytes: u64,
+    /// Number of DATA packets of completed transfers
+    pub packets: u64,
+    /// Number of packets the clients sent again after a timeout
+    pub resent: u64,
+    /// Number of DATA packets received again or out of order
+    pub out_of_order: u64,
+    /// Durations of the completed transfers, shortest first
+    pub latencies: Vec<Duration>,
+    /// Why the server granted options less than requested, each note once
+    pub notes: Vec<NegotiationNote>,
+}
+
+impl BenchReport {
+    /// Returns the bytes received per second.
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the share of DATA packets the server had to send again,
+    /// seen from the clients.
+    pub fn retransmit_rate(&self) -> f64 {
+        if self.packets == 0 {
+            return 0.0;
+        }
+        (self.resent + self.out_of_order) as f64 / self.packets as f64
+    }
+
+    /// Returns the transfer duration below which `percent` of the completed
+    /// transfers took, by nearest rank.
+    pub fn percentile(&self, percent: u32) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (self.latencies.len() * percent as usize).div_ceil(100);
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+
+    /// Returns the report as a JSON object, durations in milliseconds.
+    pub fn to_json(&self) -> String {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or("null".to_string(), |duration| {
+                format!("{:.3}", duration.as_secs_f64() * 1000.0)
+            })
+        };
+        format!(
+            concat!(
+                "{{\"clients\":{},\"elapsed_ms\":{},\"transfers\":{},\"errors\":{},",
+                "\"bytes\":{},\"throughput\":{:.1},\"resent\":{},\"out_of_order\":{},",
+                "\"retransmit_rate\":{:.6},\"latency_ms\":{{\"p50\":{},\"p90\":{},",
+                "\"p99\":{},\"max\":{}}}}}"
+            ),
+            self.clients,
+            self.elapsed.as_millis(),
+            self.transfers,
+            self.errors,
+            self.bytes,
+            self.throughput(),
+            self.resent,
+            self.out_of_order,
+            self.retransmit_rate(),
+            millis(self.percentile(50)),
+            millis(self.percentile(90)),
+            millis(self.percentile(99)),
+            millis(self.latencies.last().copied()),
+        )
+    }
+
+    fn merge(&mut self, other: BenchReport) {
+        self.transfers += other.transfers;
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+        self.packets += other.packets;
+        self.resent += other.resent;
+        self.out_of_order += other.out_of_order;
+        self.latencies.extend(other.latencies);
+        self.add_notes(&other.notes);
+    }
+
+    fn add_notes(&mut self, notes: &[NegotiationNote]) {
+        for note in notes {
+            if !self.notes.contains(note) {
+                self.notes.push(*note);
+            }
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or("-".to_string(), |duration| format!("{duration:.1?}"))
+        };
+        writeln!(
+            f,
+            "{} clients, {} transfers, {} errors in {:.1?}",
+            self.clients, self.transfers, self.errors, self.elapsed
+        )?;
+        writeln!(
+            f,
+            "Throughput: {:.0} bytes/s ({} bytes)",
+            self.throughput(),
+            self.bytes
+        )?;
+        writeln!(
+            f,
+            "Latency: p50 {}, p90 {}, p99 {}, max {}",
+            millis(self.percentile(50)),
+            millis(self.percentile(90)),
+            millis(self.percentile(99)),
+            millis(self.latencies.last().copied())
+        )?;
+        write!(
+            f,
+            "Retransmissions: {} resent, {} out of order, {:.2}% of packets",
+            self.resent,
+            self.out_of_order,
+            self.retransmit_rate() * 100.0
+        )?;
+        for note in &self.notes {
+            write!(f, "\nServer note {note}: {}", note.description())?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a load test: [`BenchOptions::clients`] threads download the file
+/// again and again with a [`Client`] until [`BenchOptions::duration`]
+/// elapsed, their starts spread over [`BenchOptions::ramp`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::{net::SocketAddr, time::Duration};
+/// use tftpd::{bench, BenchOptions};
+///
+/// let options = BenchOptions {
+///     clients: 16,
+///     duration: Duration::from_secs(30),
+///     blk_size: Some(1468),
+///     ..BenchOptions::new(SocketAddr::from(([192, 0, 2, 1], 69)), "pxelinux.0")
+/// };
+/// let report = bench(&options);
+/// println!("{report}");
+/// ```
+pub fn bench(options: &BenchOptions) -> BenchReport {
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let clients = options.clients.max(1);
+
+    let workers: Vec<_> = (0..clients)
+        .map(|index| {
+            let options = options.clone();
+            let delay = options.ramp.mul_f64(index as f64 / clients as f64);
+            thread::spawn(move || {
+                thread::sleep(delay);
+                fetch_until(&options, deadline)
+            })
+        })
+        .collect();
+
+    let mut report = BenchReport {
+        clients,
+        ..BenchReport::default()
+    };
+    for worker in workers {
+        match worker.join() {
+            Ok(worker) => report.merge(worker),
+            Err(_) => report.errors += 1,
+        }
+    }
+    report.elapsed = started.elapsed();
+    report.latencies.sort();
+    report
+}
+
+/// Downloads the file again and again until `deadline`.
+fn fetch_until(options: &BenchOptions, deadline: Instant) -> BenchReport {
+    let mut client = Client::new(options.server);
+    client.set_timeout(options.timeout);
+    let requested = options.options();
+
+    let mut report = BenchReport::default();
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        match client.get(&options.file, requested.clone()) {
+            Ok(download) => {
+                let blk_size = download
+                    .options
+                    .iter()
+                    .find(|option| option.option == OptionType::BlockSize)
+                    .map_or(512, |option| option.value);
+                report.transfers += 1;
+                report.bytes += download.data.len() as u64;
+                report.packets += download.data.len() as u64 / blk_size + 1;
+                report.resent += download.resent as u64;
+                report.out_of_order += download.out_of_order as u64;
+                report.add_notes(&download.notes);
+                report.latencies.push(started.elapsed());
+            }
+            Err(_) => {
+                report.errors += 1;
+                thread::sleep(ERROR_BACKOFF);
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(latencies_ms: &[u64]) -> BenchReport {
+        BenchReport {
+            clients: 1,
+            elapsed: Duration::from_secs(2),
+            transfers: latencies_ms.len() as u64,
+            bytes: 1000,
+            packets: 50,
+            resent: 1,
+            out_of_order: 4,
+            latencies: latencies_ms
+                .iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect(),
+            ..BenchReport::default()
+        }
+    }
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        let report = report(&(1..=100).collect::<Vec<_>>());
+
+        assert_eq!(report.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(report.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(report.percentile(100), Some(Duration::from_millis(100)));
+        assert_eq!(report.percentile(0), Some(Duration::from_millis(1)));
+        assert_eq!(BenchReport::default().percentile(50), None);
+    }
+
+    #[test]
+    fn computes_rates() {
+        let report = report(&[10]);
+
+        assert_eq!(report.throughput(), 500.0);
+        assert_eq!(report.retransmit_rate(), 0.1);
+        assert_eq!(BenchReport::default().throughput(), 0.0);
+        assert_eq!(BenchReport::default().retransmit_rate(), 0.0);
+    }
+
+    #[test]
+    fn writes_json() {
+        assert_eq!(
+            report(&[10, 20]).to_json(),
+            concat!(
+                "{\"clients\":1,\"elapsed_ms\":2000,\"transfers\":2,\"errors\":0,",
+                "\"bytes\":1000,\"throughput\":500.0,\"resent\":1,\"out_of_order\":4,",
+                "\"retransmit_rate\":0.100000,\"latency_ms\":{\"p50\":10.000,",
+                "\"p90\":20.000,\"p99\":20.000,\"max\":20.000}}"
+            )
+        );
+        assert!(BenchReport::default()
+            .to_json()
+            .ends_with("\"latency_ms\":{\"p50\":null,\"p90\":null,\"p99\":null,\"max\":null}}"));
+    }
+
+    #[test]
+    fn displays_server_notes_once() {
+        let mut report = report(&[10]);
+        let mut other = report.clone();
+        report.notes = vec![NegotiationNote::BlockSizeClamped];
+        other.notes = vec![
+            NegotiationNote::BlockSizeClamped,
+            NegotiationNote::WindowsizeClamped,
+        ];
+
+        report.merge(other);
+
+        let displayed = report.to_string();
+        let notes: Vec<&str> = displayed
+            .lines()
+            .filter(|line| line.starts_with("Server note"))
+            .collect();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(report.notes.len(), 2);
+    }
+
+    #[test]
+    fn requests_only_given_options() {
+        let server = SocketAddr::from(([127, 0, 0, 1], 69));
+        assert!(BenchOptions::new(server, "boot.img").options().is_empty());
+
+        let options = BenchOptions {
+            windowsize: Some(8),
+            ..BenchOptions::new(server, "boot.img")
+        };
+        assert_eq!(
+            options.options(),
+            [TransferOption {
+                option: OptionType::Windowsize,
+                value: 8
+            }]
+        );
+    }
+}
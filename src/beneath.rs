@@ -0,0 +1,323 @@
+#[cfg(not(target_os = "linux"))]
+use std::path::PathBuf;
+use std::{fs::File, io, path::Path};
+
+#[cfg(not(target_os = "linux"))]
+use crate::logger::elogln;
+
+/// Beneath `struct` opens requested files relative to a handle of the
+/// served directory, which is opened once at startup.
+///
+/// On Linux the resolution is done by the kernel with `openat2()` and
+/// `RESOLVE_BENEATH`, so `..` components, absolute paths and symlinks
+/// leading out of the directory fail even if the userland path validation
+/// was bypassed. Elsewhere, files are opened by joining the path onto the
+/// directory and only the userland validation applies.
+#[derive(Debug)]
+pub(crate) struct Beneath {
+    #[cfg(target_os = "linux")]
+    root: File,
+    #[cfg(not(target_os = "linux"))]
+    root: PathBuf,
+}
+
+impl Beneath {
+    /// Opens the served directory.
+    pub(crate) fn new(directory: &Path) -> io::Result<Beneath> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            let root = File::options()
+                .read(true)
+                .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+                .open(directory)?;
+            Ok(Beneath { root })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            elogln!("Kernel path resolution is only available on Linux, falling back to path validation");
+            Ok(Beneath {
+                root: PathBuf::from(directory),
+            })
+        }
+    }
+
+    /// Opens `relative` for reading below the served directory.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn open(&self, relative: &Path) -> io::Result<File> {
+        self.openat2(relative, libc::O_RDONLY, 0)
+    }
+
+    /// Creates `relative` below the served directory for writing, failing
+    /// if it already exists.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn create_new(&self, relative: &Path) -> io::Result<File> {
+        self.openat2(
+            relative,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+            0o666,
+        )
+    }
+
+    /// Moves the received `temporary` file to `relative`, both below the
+    /// served directory and in the same directory, replacing an existing
+    /// file only with `overwrite`.
+    ///
+    /// The parent directory is resolved once with `openat2()`, and the
+    /// file is moved relative to it, so a directory swapped for a symlink
+    /// during the upload cannot make the file land outside.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn place(
+        &self,
+        temporary: &Path,
+        relative: &Path,
+        overwrite: bool,
+    ) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let parent = relative.parent().unwrap_or(Path::new(""));
+        if temporary.parent().unwrap_or(Path::new("")) != parent {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "temporary file in another directory",
+            ));
+        }
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let directory = self.openat2(parent, libc::O_PATH | libc::O_DIRECTORY, 0)?;
+        let fd = directory.as_raw_fd();
+        let from = file_name(temporary)?;
+        let to = file_name(relative)?;
+
+        // SAFETY: the names are NUL-terminated and the descriptor is open
+        // for the duration of the calls.
+        let result = unsafe {
+            if overwrite {
+                libc::renameat(fd, from.as_ptr(), fd, to.as_ptr())
+            } else {
+                // Unlike a rename, linking does not replace an existing file.
+                match libc::linkat(fd, from.as_ptr(), fd, to.as_ptr(), 0) {
+                    0 => libc::unlinkat(fd, from.as_ptr(), 0),
+                    failed => failed,
+                }
+            }
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn openat2(&self, relative: &Path, flags: libc::c_int, mode: u64) -> io::Result<File> {
+        use std::{
+            ffi::CString,
+            mem,
+            os::{
+                fd::{AsRawFd, FromRawFd},
+                unix::ffi::OsStrExt,
+            },
+        };
+
+        let path = CString::new(relative.as_os_str().as_bytes())?;
+        // SAFETY: open_how is a plain C struct for which all zeroes is valid.
+        let mut how: libc::open_how = unsafe { mem::zeroed() };
+        how.flags = (flags | libc::O_CLOEXEC) as u64;
+        how.mode = mode;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+        // SAFETY: the path is NUL-terminated and how outlives the call.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.root.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the descriptor was just returned by the kernel and is
+        // owned by nothing else.
+        Ok(unsafe { File::from_raw_fd(fd as i32) })
+    }
+
+    /// Opens `relative` for reading below the served directory.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn open(&self, relative: &Path) -> io::Result<File> {
+        File::open(self.root.join(relative))
+    }
+
+    /// Creates `relative` below the served directory for writing, failing
+    /// if it already exists.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn create_new(&self, relative: &Path) -> io::Result<File> {
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(self.root.join(relative))
+    }
+
+    /// Moves the received `temporary` file to `relative`, both below the
+    /// served directory, replacing an existing file only with `overwrite`.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn place(
+        &self,
+        temporary: &Path,
+        relative: &Path,
+        overwrite: bool,
+    ) -> io::Result<()> {
+        crate::upload::place(
+            &self.root.join(temporary),
+            &self.root.join(relative),
+            overwrite,
+        )
+    }
+}
+
+/// Returns the last component of `path` as a C string.
+#[cfg(target_os = "linux")]
+fn file_name(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing file name"))?;
+    Ok(CString::new(name.as_bytes())?)
+}
+
+/// Returns whether an error returned by [`Beneath::open()`] means the path
+/// tried to escape the served directory.
+pub(crate) fn is_escape(err: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        matches!(err.raw_os_error(), Some(libc::EXDEV) | Some(libc::ELOOP))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{fs, io::Read, os::unix::fs::symlink};
+
+    #[test]
+    fn opens_files_below_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/file.bin"), b"data").unwrap();
+        symlink("sub/file.bin", dir.path().join("link.bin")).unwrap();
+        let beneath = Beneath::new(dir.path()).unwrap();
+
+        let mut contents = String::new();
+        beneath
+            .open(Path::new("sub/file.bin"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "data");
+        assert!(beneath.open(Path::new("link.bin")).is_ok());
+    }
+
+    #[test]
+    fn kernel_rejects_escapes() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret"), b"secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        symlink(outside.path().join("secret"), dir.path().join("absolute")).unwrap();
+        symlink(
+            "../../../../../../../../etc/passwd",
+            dir.path().join("relative"),
+        )
+        .unwrap();
+        let beneath = Beneath::new(dir.path()).unwrap();
+
+        // No userland validation happens here, only the kernel resolution.
+        for path in [
+            "absolute",
+            "relative",
+            "../secret",
+            outside.path().join("secret").to_str().unwrap(),
+        ] {
+            let err = beneath.open(Path::new(path)).unwrap_err();
+            assert!(is_escape(&err), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn creates_files_below_the_directory() {
+        let outside = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        symlink(outside.path(), dir.path().join("out")).unwrap();
+        let beneath = Beneath::new(dir.path()).unwrap();
+
+        beneath.create_new(Path::new("new.cfg")).unwrap();
+        assert!(dir.path().join("new.cfg").is_file());
+        let err = beneath.create_new(Path::new("new.cfg")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = beneath.create_new(Path::new("out/new.cfg")).unwrap_err();
+        assert!(is_escape(&err), "{err}");
+        assert!(!outside.path().join("new.cfg").exists());
+    }
+
+    #[test]
+    fn places_files_below_the_directory() {
+        let outside = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("configs")).unwrap();
+        let beneath = Beneath::new(dir.path()).unwrap();
+
+        fs::write(dir.path().join("configs/new.cfg.part"), b"new").unwrap();
+        beneath
+            .place(
+                Path::new("configs/new.cfg.part"),
+                Path::new("configs/new.cfg"),
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("configs/new.cfg")).unwrap(),
+            b"new"
+        );
+        assert!(!dir.path().join("configs/new.cfg.part").exists());
+
+        // The directory is swapped for a symlink while the file is received.
+        fs::write(dir.path().join("configs/new.cfg.part"), b"newer").unwrap();
+        fs::rename(dir.path().join("configs"), dir.path().join("real")).unwrap();
+        symlink(outside.path(), dir.path().join("configs")).unwrap();
+        fs::write(outside.path().join("new.cfg.part"), b"planted").unwrap();
+        for overwrite in [false, true] {
+            let err = beneath
+                .place(
+                    Path::new("configs/new.cfg.part"),
+                    Path::new("configs/new.cfg"),
+                    overwrite,
+                )
+                .unwrap_err();
+            assert!(is_escape(&err), "{err}");
+        }
+        assert!(!outside.path().join("new.cfg").exists());
+        assert!(outside.path().join("new.cfg.part").exists());
+    }
+
+    #[test]
+    fn missing_file_is_not_an_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let beneath = Beneath::new(dir.path()).unwrap();
+
+        let err = beneath.open(Path::new("missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!is_escape(&err));
+    }
+}
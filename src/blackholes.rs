@@ -0,0 +1,92 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Maximum number of remembered client hosts, the oldest are forgotten
+/// first.
+pub(crate) const MAX_BLACKHOLES: usize = 1024;
+
+/// Time during which the requests of a client host whose large DATA packets
+/// were lost get their block size clamped.
+pub(crate) const BLACKHOLE_MEMORY: Duration = Duration::from_secs(300);
+
+/// Blackholes `struct` remembers the client hosts that never acknowledged
+/// the first window of a transfer with a large block size, so that their
+/// next requests are served with small blocks with `--auto-shrink-blksize`.
+#[derive(Debug, Default)]
+pub(crate) struct Blackholes {
+    expiries: HashMap<IpAddr, Instant>,
+    order: VecDeque<IpAddr>,
+}
+
+impl Blackholes {
+    pub(crate) fn new() -> Blackholes {
+        Blackholes::default()
+    }
+
+    /// Remembers `client` for [`BLACKHOLE_MEMORY`] after `now`.
+    pub(crate) fn remember(&mut self, client: IpAddr, now: Instant) {
+        if self.expiries.len() >= MAX_BLACKHOLES && !self.expiries.contains_key(&client) {
+            if let Some(oldest) = self.order.pop_front() {
+                self.expiries.remove(&oldest);
+            }
+        }
+        self.order.retain(|&remembered| remembered != client);
+        self.order.push_back(client);
+        self.expiries.insert(client, now + BLACKHOLE_MEMORY);
+    }
+
+    /// Returns whether `client` is remembered at `now`, forgetting it once
+    /// expired.
+    pub(crate) fn contains(&mut self, client: IpAddr, now: Instant) -> bool {
+        match self.expiries.get(&client) {
+            Some(&expires) if now < expires => true,
+            Some(_) => {
+                self.expiries.remove(&client);
+                self.order.retain(|&remembered| remembered != client);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    fn client(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn remembers_until_expiry() {
+        let clock = MockClock::new();
+        let mut blackholes = Blackholes::new();
+        blackholes.remember(client(1), clock.now());
+
+        clock.advance(BLACKHOLE_MEMORY - Duration::from_secs(1));
+        assert!(blackholes.contains(client(1), clock.now()));
+        assert!(!blackholes.contains(client(2), clock.now()));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!blackholes.contains(client(1), clock.now()));
+        assert!(blackholes.order.is_empty());
+    }
+
+    #[test]
+    fn forgets_oldest_when_full() {
+        let now = MockClock::new().now();
+        let mut blackholes = Blackholes::new();
+        for n in 0..=MAX_BLACKHOLES {
+            blackholes.remember(IpAddr::from([10, 0, (n >> 8) as u8, n as u8]), now);
+        }
+
+        assert_eq!(blackholes.expiries.len(), MAX_BLACKHOLES);
+        assert!(!blackholes.contains(client(0), now));
+        assert!(blackholes.contains(client(1), now));
+    }
+}
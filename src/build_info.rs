@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// BuildInfo `struct` describes the build of the running server, embedded at
+/// compile time. It is printed by `--version` and in the startup banner.
+///
+/// # Example
+///
+/// ```rust
+/// let info = tftpd::build_info();
+///
+/// assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+/// println!("{info}");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Version of the crate
+    pub version: &'static str,
+    /// Abbreviated hash of the built commit, `unknown` outside of a git
+    /// checkout unless `TFTPD_GIT_COMMIT` is set
+    pub commit: &'static str,
+    /// When the crate was built, as a UTC RFC 3339 timestamp, taken from
+    /// `SOURCE_DATE_EPOCH` when set
+    pub timestamp: &'static str,
+    /// Enabled cargo features, sorted
+    pub features: Vec<&'static str>,
+    /// Target triple the crate was built for
+    pub target: &'static str,
+}
+
+/// Returns the [`BuildInfo`] of this build.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("TFTPD_GIT_COMMIT"),
+        timestamp: env!("TFTPD_BUILD_TIMESTAMP"),
+        features: env!("TFTPD_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+        target: env!("TFTPD_TARGET"),
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tftpd {} ({}, built {} for {}, features: {})",
+            self.version,
+            self.commit,
+            self.timestamp,
+            self.target,
+            self.features.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_this_build() {
+        let info = build_info();
+
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.commit.is_empty());
+        assert!(!info.target.is_empty());
+        assert_eq!(info.features.contains(&"core"), cfg!(feature = "core"));
+        assert_eq!(info.timestamp.len(), "2024-01-01T00:00:00Z".len());
+        assert!(info.timestamp.ends_with('Z'));
+        assert!(info.to_string().contains(info.version));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Direction;
+
+/// Number of leading bytes of a datagram kept in a [`CapturedPacket`].
+pub const CAPTURED_BYTES: usize = 128;
+
+/// CaptureReason `enum` tells why a datagram was captured.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CaptureReason {
+    /// The datagram could not be parsed as a TFTP packet
+    Malformed,
+    /// The error answering a read request refused by an authorizer
+    Denied,
+    /// A packet answered with an `IllegalOperation` error
+    ProtocolError,
+}
+
+/// CapturedPacket `struct` is a datagram kept for debugging, returned by
+/// [`Server::debug_capture()`](crate::Server::debug_capture).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// When the datagram was received or sent
+    pub captured_at: SystemTime,
+    /// Client that sent or was sent the datagram
+    pub peer: SocketAddr,
+    /// Who sent the datagram
+    pub direction: Direction,
+    /// First [`CAPTURED_BYTES`] bytes of the datagram
+    pub bytes: Vec<u8>,
+    /// Length of the whole datagram
+    pub len: usize,
+    /// Why the datagram was captured
+    pub reason: CaptureReason,
+    /// What went wrong, such as the parse error
+    pub detail: String,
+}
+
+impl fmt::Display for CapturedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self
+            .captured_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let direction = match self.direction {
+            Direction::ToServer => "from",
+            Direction::ToClient => "to",
+        };
+        write!(
+            f,
+            "{at} {:?} {direction} {} ({}): {} bytes",
+            self.reason, self.peer, self.detail, self.len
+        )?;
+        for byte in &self.bytes {
+            write!(f, " {byte:02x}")?;
+        }
+        if self.bytes.len() < self.len {
+            write!(f, " ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the last captured datagrams, dropping the oldest one once
+/// `capacity` are kept. The lock is only taken by the thread polling the
+/// server and by embedders reading the capture, so it is hardly ever
+/// contended.
+#[derive(Debug)]
+pub(crate) struct DebugCapture {
+    capacity: usize,
+    entries: Mutex<VecDeque<CapturedPacket>>,
+}
+
+impl DebugCapture {
+    pub(crate) fn new(capacity: usize) -> DebugCapture {
+        DebugCapture {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Keeps the first bytes of `datagram`, unless the capture is disabled.
+    pub(crate) fn record(
+        &self,
+        peer: SocketAddr,
+        direction: Direction,
+        datagram: &[u8],
+        reason: CaptureReason,
+        detail: String,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let packet = CapturedPacket {
+            captured_at: SystemTime::now(),
+            peer,
+            direction,
+            bytes: datagram[..datagram.len().min(CAPTURED_BYTES)].to_vec(),
+            len: datagram.len(),
+            reason,
+            detail,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(packet);
+    }
+
+    /// Returns the captured datagrams, oldest first.
+    pub(crate) fn entries(&self) -> Vec<CapturedPacket> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:4000".parse().unwrap()
+    }
+
+    #[test]
+    fn keeps_last_entries_and_truncates_bytes() {
+        let capture = DebugCapture::new(2);
+        for len in [10, 20, 300] {
+            capture.record(
+                peer(),
+                Direction::ToServer,
+                &vec![0xab; len],
+                CaptureReason::Malformed,
+                format!("packet of {len}"),
+            );
+        }
+
+        let entries = capture.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].detail, "packet of 20");
+        assert_eq!(entries[1].len, 300);
+        assert_eq!(entries[1].bytes.len(), CAPTURED_BYTES);
+
+        capture.clear();
+        assert!(capture.entries().is_empty());
+    }
+
+    #[test]
+    fn captures_nothing_when_disabled() {
+        let capture = DebugCapture::new(0);
+        capture.record(
+            peer(),
+            Direction::ToServer,
+            b"\x00\x09",
+            CaptureReason::Malformed,
+            String::new(),
+        );
+
+        assert!(capture.entries().is_empty());
+    }
+
+    #[test]
+    fn formats_bytes_as_hex() {
+        let packet = CapturedPacket {
+            captured_at: UNIX_EPOCH,
+            peer: peer(),
+            direction: Direction::ToClient,
+            bytes: vec![0, 5, 0, 2],
+            len: 4,
+            reason: CaptureReason::Denied,
+            detail: "refused boot.ipxe".to_string(),
+        };
+
+        assert_eq!(
+            packet.to_string(),
+            "0 Denied to 10.0.0.1:4000 (refused boot.ipxe): 4 bytes 00 05 00 02"
+        );
+    }
+}
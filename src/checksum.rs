@@ -0,0 +1,141 @@
+use std::{fmt, str::FromStr};
+
+/// Reflected CRC-32C (Castagnoli) polynomial.
+const CRC32C_POLYNOMIAL: u32 = 0x82F6_3B78;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+/// ChecksumAlgorithm `enum` represents the digests of the nonstandard
+/// `xsum` option, with which the server sends a
+/// [`Packet::Checksum`](crate::Packet::Checksum) of the whole file once its
+/// last block is acknowledged.
+///
+/// In a [`TransferOption`](crate::TransferOption), the algorithm is stored
+/// as its [`ChecksumAlgorithm::value()`] and sent by name.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::ChecksumAlgorithm;
+///
+/// assert_eq!("crc32c".parse(), Ok(ChecksumAlgorithm::Crc32c));
+/// assert_eq!(ChecksumAlgorithm::from_value(1), Some(ChecksumAlgorithm::Crc32c));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32C (Castagnoli), sent as 4 bytes in network order
+    Crc32c = 1,
+}
+
+impl ChecksumAlgorithm {
+    /// Returns the name of the algorithm in the `xsum` option.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32c => "crc32c",
+        }
+    }
+
+    /// Returns the value standing for the algorithm in a
+    /// [`TransferOption`](crate::TransferOption).
+    pub fn value(self) -> u64 {
+        self as u64
+    }
+
+    /// Returns the algorithm standing for `value` in a
+    /// [`TransferOption`](crate::TransferOption).
+    pub fn from_value(value: u64) -> Option<ChecksumAlgorithm> {
+        match value {
+            1 => Some(ChecksumAlgorithm::Crc32c),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, &'static str> {
+        match value {
+            "crc32c" => Ok(ChecksumAlgorithm::Crc32c),
+            _ => Err("Invalid checksum algorithm"),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Checksum `struct` computes the digest of a file incrementally, as its
+/// blocks are read.
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    crc: u32,
+}
+
+impl Checksum {
+    /// Creates the digest of an empty file.
+    pub fn new(algorithm: ChecksumAlgorithm) -> Checksum {
+        Checksum { algorithm, crc: !0 }
+    }
+
+    /// Adds the next bytes of the file.
+    pub fn update(&mut self, data: &[u8]) {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32c => {
+                for &byte in data {
+                    self.crc =
+                        CRC32C_TABLE[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+                }
+            }
+        }
+    }
+
+    /// Returns the digest of the bytes added so far.
+    pub fn digest(&self) -> Vec<u8> {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32c => (!self.crc).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_crc32c_check_value() {
+        let mut checksum = Checksum::new(ChecksumAlgorithm::Crc32c);
+        checksum.update(b"1234");
+        checksum.update(b"56789");
+
+        assert_eq!(checksum.digest(), 0xE306_9283u32.to_be_bytes());
+        assert_eq!(
+            Checksum::new(ChecksumAlgorithm::Crc32c).digest(),
+            [0, 0, 0, 0]
+        );
+    }
+}
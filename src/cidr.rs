@@ -0,0 +1,149 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use crate::TftpError;
+
+/// Cidr `struct` is a block of IP addresses in CIDR notation, such as
+/// `10.0.0.0/8` or `fd00::/8`. A bare address stands for itself alone.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Cidr;
+///
+/// let lab: Cidr = "10.1.0.0/16".parse().unwrap();
+/// assert!(lab.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!lab.contains("10.2.0.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Creates the block of the addresses sharing the first `prefix_len`
+    /// bits of `address`, failing when `prefix_len` is longer than the
+    /// address.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Cidr, TftpError> {
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > bits {
+            return Err(format!("Prefix length {prefix_len} is longer than {bits} bits").into());
+        }
+        Ok(Cidr {
+            network: mask(address, prefix_len),
+            prefix_len,
+        })
+    }
+
+    /// Returns the number of leading bits that addresses of the block share.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `ip` belongs to the block. IPv4 addresses mapped to
+    /// IPv6, as seen on dual-stack sockets, match IPv4 blocks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix_len) == self.network
+    }
+}
+
+/// Clears the bits of `address` after the first `prefix_len`.
+fn mask(address: IpAddr, prefix_len: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let bits = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::from((u32::from(v4) & bits).to_be_bytes())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::from((u128::from(v6) & bits).to_be_bytes())
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = TftpError;
+
+    fn from_str(s: &str) -> Result<Cidr, TftpError> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address.parse::<IpAddr>()?, Some(prefix_len.parse()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let prefix_len = prefix_len.unwrap_or(match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        });
+        Cidr::new(address, prefix_len)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = TftpError;
+
+    fn try_from(s: String) -> Result<Cidr, TftpError> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> String {
+        cidr.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_addresses_of_block() {
+        let block: Cidr = "192.168.10.77/24".parse().unwrap();
+
+        assert_eq!(block.to_string(), "192.168.10.0/24");
+        assert!(block.contains(ip("192.168.10.1")));
+        assert!(block.contains(ip("192.168.10.255")));
+        assert!(!block.contains(ip("192.168.11.1")));
+        assert!(block.contains(ip("::ffff:192.168.10.5")));
+        assert!(!block.contains(ip("fd00::1")));
+    }
+
+    #[test]
+    fn matches_ipv6_and_edge_lengths() {
+        let block: Cidr = "fd00:1::/32".parse().unwrap();
+        assert!(block.contains(ip("fd00:1:ffff::1")));
+        assert!(!block.contains(ip("fd00:2::1")));
+
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("203.0.113.9")));
+        assert!(!all.contains(ip("::1")));
+
+        let single: Cidr = "10.0.0.5".parse().unwrap();
+        assert_eq!(single.prefix_len(), 32);
+        assert!(single.contains(ip("10.0.0.5")));
+        assert!(!single.contains(ip("10.0.0.6")));
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("lab/24".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    error::Error,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::packet::MAX_BLOCK_SIZE;
+use crate::state::{DEFAULT_BLOCK_SIZE, DEFAULT_TIMEOUT, MAX_RETRIES};
+use crate::{Checksum, ChecksumAlgorithm, ErrorCode, Message, NegotiationNote, OptionType};
+use crate::{Packet, TransferOption};
+
+/// Client `struct` downloads files from a TFTP server in octet mode,
+/// acknowledging every window of blocks as in RFC 7440.
+///
+/// When the `xsum` option is requested and acknowledged in the OACK, the
+/// [`Packet::Checksum`] sent by the server after the last block is checked
+/// against the received file, so that corruption the UDP checksum missed
+/// fails the download.
+///
+/// The [`NegotiationNote`]s of a `srvnote` option in the OACK are printed
+/// and kept in the [`Download`]. A strict client refuses such an OACK
+/// instead, like any option it did not request.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::net::SocketAddr;
+/// use tftpd::{ChecksumAlgorithm, Client, OptionType, TransferOption};
+///
+/// let client = Client::new(SocketAddr::from(([127, 0, 0, 1], 69)));
+/// let xsum = TransferOption {
+///     option: OptionType::Checksum,
+///     value: ChecksumAlgorithm::Crc32c.value(),
+/// };
+/// let download = client.get("pxelinux.0", vec![xsum]).unwrap();
+/// assert_eq!(download.checksum, Some(ChecksumAlgorithm::Crc32c));
+/// ```
+pub struct Client {
+    server: SocketAddr,
+    timeout: Duration,
+    max_retries: u32,
+    strict: bool,
+}
+
+/// Download `struct` is a file received by a [`Client`].
+#[derive(Debug)]
+pub struct Download {
+    /// Contents of the file
+    pub data: Vec<u8>,
+    /// Options acknowledged by the server
+    pub options: Vec<TransferOption>,
+    /// Algorithm of the checksum the file was verified with, if the server
+    /// acknowledged one
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Why the server granted options less than requested, if it said so
+    pub notes: Vec<NegotiationNote>,
+    /// Number of packets sent again after the server stopped answering
+    pub resent: u32,
+    /// Number of DATA packets received again or out of order, as when the
+    /// server retransmits a window
+    pub out_of_order: u32,
+    /// Number of packets that could not be parsed and were ignored
+    pub malformed: u32,
+}
+
+impl Client {
+    /// Creates a [`Client`] of the server listening on `server`.
+    pub fn new(server: SocketAddr) -> Client {
+        Client {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: MAX_RETRIES,
+            strict: false,
+        }
+    }
+
+    /// Sets the time without an answer after which the last packet is sent
+    /// again (default: 5 seconds).
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets whether an OACK with an option that was not requested is
+    /// refused with an ERROR, as RFC 2347 asks (default: false).
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Downloads `filename`, requesting `options`. Fails when the server
+    /// answers with an ERROR, stops answering, or sends a checksum that
+    /// does not match the received file.
+    pub fn get(
+        &self,
+        filename: &str,
+        options: Vec<TransferOption>,
+    ) -> Result<Download, Box<dyn Error>> {
+        let bind: SocketAddr = if self.server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        let requested: Vec<OptionType> = options.iter().map(|option| option.option).collect();
+
+        let request = Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options,
+        };
+        let mut last_sent = request;
+        Message::send_packet(&socket, &self.server, &last_sent)?;
+
+        let mut buf = vec![0; MAX_BLOCK_SIZE + 4];
+        let mut peer = None;
+        let mut retries = 0;
+        let mut acknowledged = vec![];
+        let mut notes = vec![];
+        let mut blk_size = DEFAULT_BLOCK_SIZE;
+        let mut windowsize = 1;
+        let mut data = vec![];
+        let mut block_number: u16 = 0;
+        // Blocks received in order since the last ACK
+        let mut received = 0;
+        // Whether the last ACK answered a block out of order, so that the
+        // rest of the window does not trigger more
+        let mut gap_acked = false;
+        let mut resent = 0;
+        let mut out_of_order = 0;
+        let mut malformed = 0;
+        loop {
+            let Some((packet, from)) = self.recv(&socket, &mut buf, peer, &mut malformed)? else {
+                retries += 1;
+                resent += 1;
+                if retries > self.max_retries {
+                    return Err(format!("timed out after {} retries", self.max_retries).into());
+                }
+                if received > 0 {
+                    // Acknowledges the part of the window received, as in
+                    // RFC 7440, so that a server sending windows in parts
+                    // moves on.
+                    last_sent = Packet::Ack(block_number);
+                }
+                Message::send_packet(&socket, &peer.unwrap_or(self.server), &last_sent)?;
+                received = 0;
+                continue;
+            };
+            retries = 0;
+
+            match packet {
+                Packet::Oack(options) if peer.is_none() => {
+                    if let Some(unrequested) = options
+                        .iter()
+                        .find(|option| self.strict && !requested.contains(&option.option))
+                    {
+                        let msg = format!("unrequested option {}", unrequested.option.as_str());
+                        Message::send_error(&socket, &from, ErrorCode::OptionNegotiation, &msg)?;
+                        return Err(format!("Refused OACK: {msg}").into());
+                    }
+                    for option in &options {
+                        match option.option {
+                            OptionType::BlockSize => blk_size = usize::try_from(option.value)?,
+                            OptionType::Windowsize => windowsize = option.value,
+                            OptionType::ServerNote => {
+                                notes = NegotiationNote::from_mask(option.value);
+                            }
+                            _ => {}
+                        }
+                    }
+                    acknowledged = options;
+                    peer = Some(from);
+                    last_sent = Packet::Ack(0);
+                    Message::send_packet(&socket, &from, &last_sent)?;
+                }
+                Packet::Data {
+                    block_num,
+                    data: block,
+                } if block_num == block_number.wrapping_add(1) => {
+                    peer = Some(from);
+                    let last = block.len() < blk_size;
+                    data.extend_from_slice(&block);
+                    block_number = block_num;
+                    received += 1;
+                    gap_acked = false;
+                    if last || received == windowsize {
+                        received = 0;
+                        last_sent = Packet::Ack(block_number);
+                        Message::send_packet(&socket, &from, &last_sent)?;
+                    }
+                    if last {
+                        break;
+                    }
+                }
+                Packet::Data { .. } if !gap_acked => {
+                    // A block was lost or an ACK was, the window restarts
+                    // after the last block received in order.
+                    out_of_order += 1;
+                    peer = Some(from);
+                    received = 0;
+                    gap_acked = true;
+                    last_sent = Packet::Ack(block_number);
+                    Message::send_packet(&socket, &from, &last_sent)?;
+                }
+                Packet::Data { .. } => out_of_order += 1,
+                _ => {}
+            }
+        }
+
+        let checksum = acknowledged
+            .iter()
+            .find(|option| option.option == OptionType::Checksum)
+            .and_then(|option| ChecksumAlgorithm::from_value(option.value));
+        if let Some(algorithm) = checksum {
+            let expected = self.recv_checksum(
+                &socket,
+                &mut buf,
+                peer,
+                &last_sent,
+                block_number,
+                &mut malformed,
+            )?;
+            let mut computed = Checksum::new(algorithm);
+            computed.update(&data);
+            if computed.digest() != expected {
+                return Err(format!(
+                    "{algorithm} checksum mismatch: expected {:02x?}, computed {:02x?}",
+                    expected,
+                    computed.digest()
+                )
+                .into());
+            }
+        }
+
+        Ok(Download {
+            data,
+            options: acknowledged,
+            checksum,
+            notes,
+            resent,
+            out_of_order,
+            malformed,
+        })
+    }
+
+    /// Waits for the [`Packet::Checksum`] following the last block, whose
+    /// ACK `last_sent` is sent again when the block is.
+    fn recv_checksum(
+        &self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+        peer: Option<SocketAddr>,
+        last_sent: &Packet,
+        last_block: u16,
+        malformed: &mut u32,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut retries = 0;
+        loop {
+            match self.recv(socket, buf, peer, malformed)? {
+                Some((Packet::Checksum(digest), _)) => return Ok(digest),
+                Some((Packet::Data { block_num, .. }, from)) if block_num == last_block => {
+                    Message::send_packet(socket, &from, last_sent)?;
+                }
+                Some(_) => {}
+                None if retries < self.max_retries => {
+                    retries += 1;
+                    Message::send_packet(socket, &peer.unwrap_or(self.server), last_sent)?;
+                }
+                None => return Err("no checksum received after the last block".into()),
+            }
+        }
+    }
+
+    /// Waits for the next packet of the server, or returns `None` after the
+    /// timeout. Packets from other ports than `peer`, once known, are
+    /// answered with an ERROR and otherwise ignored, malformed packets are
+    /// counted in `malformed` and ignored.
+    fn recv(
+        &self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+        peer: Option<SocketAddr>,
+        malformed: &mut u32,
+    ) -> Result<Option<(Packet, SocketAddr)>, Box<dyn Error>> {
+        loop {
+            let (size, from) = match socket.recv_from(buf) {
+                Ok(received) => received,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if peer.is_some_and(|peer| peer != from) {
+                let _ =
+                    Message::send_error(socket, &from, ErrorCode::UnknownId, "unknown transfer ID");
+                continue;
+            }
+            if size < 2 {
+                continue;
+            }
+
+            match Packet::deserialize(&buf[..size]) {
+                Ok(Packet::Error { code, msg }) => {
+                    return Err(format!("Received error code {code}: {msg}").into())
+                }
+                Ok(packet) => return Ok(Some((packet, from))),
+                Err(_) => *malformed += 1,
+            }
+        }
+    }
+}
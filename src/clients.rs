@@ -0,0 +1,593 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{Quota, QuotaUsage};
+
+/// Number of distinct source ports of one client host with a stalled
+/// session or an orphan ACK within [`SPRAY_WINDOW`] from which the host is
+/// flagged as sprayed across servers.
+pub(crate) const SPRAY_THRESHOLD: usize = 8;
+
+/// Window in which the anomalies of a client host are counted.
+pub(crate) const SPRAY_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of client hosts whose anomalies are counted, of flagged ones kept,
+/// of journeys followed and of quota windows open, further ones are
+/// ignored.
+const MAX_TRACKED_HOSTS: usize = 1024;
+
+/// Number of files listed in a [`Journey`], further ones are only counted.
+const MAX_JOURNEY_FILES: usize = 64;
+
+/// ClientSessions `struct` lists the transfers of one client host, returned
+/// by [`Server::active_sessions()`](crate::Server::active_sessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSessions {
+    /// Address of the client host
+    pub ip: IpAddr,
+    /// Addresses of its transfers, one per source port, oldest first
+    pub sessions: Vec<SocketAddr>,
+}
+
+/// Journey `struct` sums up the transfers of one client host that followed
+/// each other without a quiet window between them, such as the files of a
+/// network boot, sent with
+/// [`TransferEvent::JourneyEnded`](crate::TransferEvent::JourneyEnded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journey {
+    /// Identifier of the journey, also carried by the events of its
+    /// transfers
+    pub id: u64,
+    /// Address of the client host
+    pub client: IpAddr,
+    /// Files of the transfers in the order they were requested, the first
+    /// 64 only
+    pub files: Vec<PathBuf>,
+    /// Number of transfers started
+    pub transfers: u64,
+    /// Number of transfers that completed
+    pub completed: u64,
+    /// Number of transfers that failed
+    pub failed: u64,
+    /// Number of bytes acknowledged by the client over all transfers
+    pub bytes: u64,
+    /// Time from the first read request to the end of the last transfer
+    pub duration: Duration,
+}
+
+/// Journey of a client host still being followed.
+#[derive(Debug)]
+struct OpenJourney {
+    journey: Journey,
+    started: Instant,
+    last_activity: Instant,
+}
+
+/// Bytes a client host downloaded in its current quota window.
+#[derive(Debug)]
+struct Usage {
+    started: Instant,
+    bytes: u64,
+}
+
+/// Sign that the packets of a client are spread over several servers, for
+/// example by a UDP load balancer or a NAT changing the source port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Anomaly {
+    /// A session timed out before any of its data was acknowledged
+    StalledSession,
+    /// An ACK arrived from a port without a session
+    OrphanAck,
+}
+
+/// Anomalies of one client host within the current window.
+#[derive(Debug)]
+struct Anomalies {
+    window_started: Instant,
+    stalled_sessions: u64,
+    orphan_acks: u64,
+    ports: HashSet<u16>,
+}
+
+/// Groups the sessions of the server by client IP address, so that a host
+/// opening several transfers from different ports counts as one client.
+/// Also follows the journey and the quota usage of every host and flags the
+/// hosts whose packets look sprayed across servers.
+#[derive(Debug, Default)]
+pub(crate) struct ClientRegistry {
+    clients: HashMap<IpAddr, Vec<SocketAddr>>,
+    journeys: HashMap<IpAddr, OpenJourney>,
+    last_journey: u64,
+    usage: HashMap<IpAddr, Usage>,
+    anomalies: HashMap<IpAddr, Anomalies>,
+    sprayed: BTreeSet<IpAddr>,
+}
+
+impl ClientRegistry {
+    /// Registers a session, doing nothing if it is already registered.
+    pub(crate) fn add(&mut self, session: SocketAddr) {
+        let sessions = self.clients.entry(session.ip()).or_default();
+        if !sessions.contains(&session) {
+            sessions.push(session);
+        }
+    }
+
+    /// Unregisters a session, forgetting its client once it has none left.
+    pub(crate) fn remove(&mut self, session: &SocketAddr) {
+        if let Some(sessions) = self.clients.get_mut(&session.ip()) {
+            sessions.retain(|s| s != session);
+            if sessions.is_empty() {
+                self.clients.remove(&session.ip());
+            }
+        }
+    }
+
+    /// Returns the sessions of the client host `ip`.
+    pub(crate) fn sessions(&self, ip: IpAddr) -> &[SocketAddr] {
+        self.clients.get(&ip).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns whether the client of `session` can start it with fewer than
+    /// `max` other sessions. A session replacing itself always has room.
+    pub(crate) fn has_room(&self, session: &SocketAddr, max: usize) -> bool {
+        self.clients
+            .get(&session.ip())
+            .is_none_or(|sessions| sessions.contains(session) || sessions.len() < max)
+    }
+
+    /// Returns the sessions of every client, by client address.
+    pub(crate) fn groups(&self) -> Vec<ClientSessions> {
+        let mut groups: Vec<ClientSessions> = self
+            .clients
+            .iter()
+            .map(|(&ip, sessions)| ClientSessions {
+                ip,
+                sessions: sessions.clone(),
+            })
+            .collect();
+        groups.sort_by_key(|group| group.ip);
+        groups
+    }
+
+    /// Returns the ID of the journey a transfer of `ip` starting `now`
+    /// would join, without joining it.
+    pub(crate) fn next_journey(&self, ip: IpAddr, now: Instant, window: Duration) -> u64 {
+        match self.journeys.get(&ip) {
+            Some(open) if !self.is_quiet(ip, open, now, window) => open.journey.id,
+            _ => self.last_journey + 1,
+        }
+    }
+
+    /// Adds a transfer of `file` to the journey of the client host `ip`,
+    /// starting a new journey when the host has none or went quiet for
+    /// `window`. Returns the ID of the journey, and the previous one of the
+    /// host if it just ended.
+    pub(crate) fn join_journey(
+        &mut self,
+        ip: IpAddr,
+        file: &Path,
+        now: Instant,
+        window: Duration,
+    ) -> (u64, Option<Journey>) {
+        let ended = self
+            .journeys
+            .get(&ip)
+            .filter(|open| self.is_quiet(ip, open, now, window))
+            .is_some()
+            .then(|| self.journeys.remove(&ip))
+            .flatten()
+            .map(|open| open.journey);
+
+        if !self.journeys.contains_key(&ip) {
+            self.last_journey += 1;
+            if self.journeys.len() >= MAX_TRACKED_HOSTS {
+                return (self.last_journey, ended);
+            }
+            self.journeys.insert(
+                ip,
+                OpenJourney {
+                    journey: Journey {
+                        id: self.last_journey,
+                        client: ip,
+                        files: vec![],
+                        transfers: 0,
+                        completed: 0,
+                        failed: 0,
+                        bytes: 0,
+                        duration: Duration::ZERO,
+                    },
+                    started: now,
+                    last_activity: now,
+                },
+            );
+        }
+        let open = self.journeys.get_mut(&ip).unwrap();
+        open.journey.transfers += 1;
+        if open.journey.files.len() < MAX_JOURNEY_FILES {
+            open.journey.files.push(file.to_path_buf());
+        }
+        open.last_activity = now;
+        (open.journey.id, ended)
+    }
+
+    /// Counts the end of a transfer of the client host `ip` in its journey.
+    pub(crate) fn end_journey_transfer(
+        &mut self,
+        ip: IpAddr,
+        bytes: u64,
+        completed: bool,
+        now: Instant,
+    ) {
+        let Some(open) = self.journeys.get_mut(&ip) else {
+            return;
+        };
+        if completed {
+            open.journey.completed += 1;
+        } else {
+            open.journey.failed += 1;
+        }
+        open.journey.bytes += bytes;
+        open.journey.duration = now.saturating_duration_since(open.started);
+        open.last_activity = now;
+    }
+
+    /// Removes and returns the journeys of the client hosts without a
+    /// transfer for `window`, by ID.
+    pub(crate) fn end_quiet_journeys(&mut self, now: Instant, window: Duration) -> Vec<Journey> {
+        let quiet: Vec<IpAddr> = self
+            .journeys
+            .iter()
+            .filter(|(&ip, open)| self.is_quiet(ip, open, now, window))
+            .map(|(&ip, _)| ip)
+            .collect();
+        let mut ended: Vec<Journey> = quiet
+            .iter()
+            .filter_map(|ip| self.journeys.remove(ip))
+            .map(|open| open.journey)
+            .collect();
+        ended.sort_by_key(|journey| journey.id);
+        ended
+    }
+
+    /// Returns whether the journey `open` of `ip` has no transfer running
+    /// and none ended within `window`.
+    fn is_quiet(&self, ip: IpAddr, open: &OpenJourney, now: Instant, window: Duration) -> bool {
+        !self.clients.contains_key(&ip)
+            && now.saturating_duration_since(open.last_activity) >= window
+    }
+
+    /// Counts an `anomaly` of the session `from`. Returns the pattern seen
+    /// when its client host reaches [`SPRAY_THRESHOLD`] source ports within
+    /// [`SPRAY_WINDOW`] and gets flagged, `None` otherwise. A host is
+    /// flagged only once.
+    pub(crate) fn record_anomaly(
+        &mut self,
+        from: SocketAddr,
+        anomaly: Anomaly,
+        now: Instant,
+    ) -> Option<String> {
+        let ip = from.ip();
+        if self.sprayed.contains(&ip) {
+            return None;
+        }
+        if !self.anomalies.contains_key(&ip) && self.anomalies.len() >= MAX_TRACKED_HOSTS {
+            self.anomalies.retain(|_, anomalies| {
+                now.saturating_duration_since(anomalies.window_started) < SPRAY_WINDOW
+            });
+            if self.anomalies.len() >= MAX_TRACKED_HOSTS {
+                return None;
+            }
+        }
+
+        let new_window = || Anomalies {
+            window_started: now,
+            stalled_sessions: 0,
+            orphan_acks: 0,
+            ports: HashSet::new(),
+        };
+        let anomalies = self.anomalies.entry(ip).or_insert_with(new_window);
+        if now.saturating_duration_since(anomalies.window_started) >= SPRAY_WINDOW {
+            *anomalies = new_window();
+        }
+        match anomaly {
+            Anomaly::StalledSession => anomalies.stalled_sessions += 1,
+            Anomaly::OrphanAck => anomalies.orphan_acks += 1,
+        }
+        anomalies.ports.insert(from.port());
+        if anomalies.ports.len() < SPRAY_THRESHOLD {
+            return None;
+        }
+
+        let pattern = format!(
+            "{} sessions stalled before the first ACK and {} orphan ACKs from {} source ports in {}s",
+            anomalies.stalled_sessions,
+            anomalies.orphan_acks,
+            anomalies.ports.len(),
+            now.saturating_duration_since(anomalies.window_started)
+                .as_secs()
+        );
+        self.anomalies.remove(&ip);
+        if self.sprayed.len() < MAX_TRACKED_HOSTS {
+            self.sprayed.insert(ip);
+        }
+        Some(pattern)
+    }
+
+    /// Returns the client hosts flagged by
+    /// [`ClientRegistry::record_anomaly`], by address.
+    pub(crate) fn sprayed(&self) -> Vec<IpAddr> {
+        self.sprayed.iter().copied().collect()
+    }
+
+    /// Adds `bytes` downloaded by the client host `ip` to its window of
+    /// `quota`, opening a window if it has none, and returns the time left
+    /// in the window once the host used up its quota.
+    pub(crate) fn charge(
+        &mut self,
+        ip: IpAddr,
+        bytes: u64,
+        now: Instant,
+        quota: &Quota,
+    ) -> Option<Duration> {
+        if bytes == 0 {
+            return self.over_quota(ip, now, quota);
+        }
+        self.end_quota_window(ip, now, quota);
+        if !self.usage.contains_key(&ip) && self.usage.len() >= MAX_TRACKED_HOSTS {
+            self.usage
+                .retain(|_, usage| now < usage.started + quota.window);
+            if self.usage.len() >= MAX_TRACKED_HOSTS {
+                return None;
+            }
+        }
+        self.usage
+            .entry(ip)
+            .or_insert(Usage {
+                started: now,
+                bytes: 0,
+            })
+            .bytes += bytes;
+        self.over_quota(ip, now, quota)
+    }
+
+    /// Returns the time left in the window of the client host `ip` if it
+    /// used up its `quota`.
+    pub(crate) fn over_quota(
+        &mut self,
+        ip: IpAddr,
+        now: Instant,
+        quota: &Quota,
+    ) -> Option<Duration> {
+        self.end_quota_window(ip, now, quota);
+        let usage = self.usage.get(&ip)?;
+        (usage.bytes >= quota.bytes).then(|| usage.started + quota.window - now)
+    }
+
+    /// Returns the usage of the client hosts with an open window of
+    /// `quota`, by address.
+    pub(crate) fn quota_usage(&self, now: Instant, quota: &Quota) -> Vec<QuotaUsage> {
+        let mut usage: Vec<_> = self
+            .usage
+            .iter()
+            .filter(|(_, usage)| now < usage.started + quota.window)
+            .map(|(&ip, usage)| QuotaUsage {
+                ip,
+                bytes: usage.bytes,
+                window_left: usage.started + quota.window - now,
+            })
+            .collect();
+        usage.sort_by_key(|usage| usage.ip);
+        usage
+    }
+
+    /// Forgets the usage of the client host `ip` once its window is over.
+    fn end_quota_window(&mut self, ip: IpAddr, now: Instant, quota: &Quota) {
+        if self
+            .usage
+            .get(&ip)
+            .is_some_and(|usage| now >= usage.started + quota.window)
+        {
+            self.usage.remove(&ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_sessions_by_ip() {
+        let mut registry = ClientRegistry::default();
+        let first: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:5001".parse().unwrap();
+        let other: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        registry.add(first);
+        registry.add(second);
+        registry.add(other);
+        registry.add(first);
+
+        assert_eq!(
+            registry.groups(),
+            vec![
+                ClientSessions {
+                    ip: other.ip(),
+                    sessions: vec![other],
+                },
+                ClientSessions {
+                    ip: first.ip(),
+                    sessions: vec![first, second],
+                },
+            ]
+        );
+        assert!(!registry.has_room(&"10.0.0.2:5002".parse().unwrap(), 2));
+        assert!(registry.has_room(&second, 2));
+        assert!(registry.has_room(&"10.0.0.3:5000".parse().unwrap(), 1));
+
+        registry.remove(&first);
+        registry.remove(&second);
+        assert_eq!(registry.groups().len(), 1);
+        assert!(!registry.clients.contains_key(&first.ip()));
+    }
+
+    #[test]
+    fn groups_transfers_into_journeys() {
+        let mut registry = ClientRegistry::default();
+        let window = Duration::from_secs(30);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let client: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+
+        assert_eq!(
+            registry.join_journey(client.ip(), Path::new("pxelinux.0"), at(0), window),
+            (1, None)
+        );
+        registry.add(client);
+        // A long transfer keeps the journey going past the window.
+        assert!(registry.end_quiet_journeys(at(40), window).is_empty());
+        registry.remove(&client);
+        registry.end_journey_transfer(client.ip(), 1000, true, at(40));
+        assert_eq!(
+            registry.join_journey(other, Path::new("pxelinux.0"), at(50), window),
+            (2, None)
+        );
+        assert_eq!(
+            registry.join_journey(client.ip(), Path::new("vmlinuz"), at(60), window),
+            (1, None)
+        );
+        registry.end_journey_transfer(client.ip(), 24, false, at(61));
+
+        let (id, ended) =
+            registry.join_journey(client.ip(), Path::new("pxelinux.0"), at(100), window);
+        assert_eq!(id, 3);
+        assert_eq!(
+            ended,
+            Some(Journey {
+                id: 1,
+                client: client.ip(),
+                files: vec!["pxelinux.0".into(), "vmlinuz".into()],
+                transfers: 2,
+                completed: 1,
+                failed: 1,
+                bytes: 1024,
+                duration: Duration::from_secs(61),
+            })
+        );
+        let ended = registry.end_quiet_journeys(at(130), window);
+        assert_eq!(
+            ended.iter().map(|journey| journey.id).collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert!(registry.journeys.is_empty());
+    }
+
+    fn usage(ip: IpAddr, bytes: u64, secs: u64) -> QuotaUsage {
+        QuotaUsage {
+            ip,
+            bytes,
+            window_left: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn charges_quota_per_window() {
+        let mut registry = ClientRegistry::default();
+        let quota: Quota = "1000/60s".parse().unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+
+        assert_eq!(registry.charge(ip, 600, at(0), &quota), None);
+        assert_eq!(registry.charge(other, 100, at(10), &quota), None);
+        assert_eq!(registry.over_quota(ip, at(20), &quota), None);
+        assert_eq!(
+            registry.charge(ip, 400, at(20), &quota),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            registry.over_quota(ip, at(50), &quota),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            registry.quota_usage(at(50), &quota),
+            [usage(ip, 1000, 10), usage(other, 100, 20),]
+        );
+
+        // The window of the host ends a minute after its first bytes.
+        assert_eq!(registry.over_quota(ip, at(60), &quota), None);
+        assert_eq!(registry.charge(ip, 999, at(61), &quota), None);
+        assert_eq!(
+            registry.quota_usage(at(61), &quota),
+            [usage(ip, 999, 60), usage(other, 100, 9)]
+        );
+    }
+
+    /// Feeds `anomalies` of `(port, anomaly, seconds since start)` to a new
+    /// registry and returns the flagged hosts.
+    fn flagged(anomalies: &[(u16, Anomaly, u64)]) -> Vec<IpAddr> {
+        let mut registry = ClientRegistry::default();
+        let start = Instant::now();
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        for &(port, anomaly, secs) in anomalies {
+            registry.record_anomaly(
+                SocketAddr::new(ip, port),
+                anomaly,
+                start + Duration::from_secs(secs),
+            );
+        }
+        registry.sprayed()
+    }
+
+    #[test]
+    fn does_not_flag_healthy_client() {
+        // A late duplicate ACK once in a while, the rest completes.
+        let anomalies: Vec<_> = (0..5)
+            .map(|i| (5000 + i, Anomaly::OrphanAck, u64::from(i) * 30))
+            .collect();
+
+        assert!(flagged(&anomalies).is_empty());
+    }
+
+    #[test]
+    fn does_not_flag_retrying_client() {
+        // Retries from the same port, then from a new port after each of
+        // its own timeouts.
+        let mut anomalies: Vec<_> = (0..20)
+            .map(|i| (5000, Anomaly::StalledSession, i))
+            .collect();
+        anomalies.extend((0..20).map(|i| (6000 + i, Anomaly::StalledSession, u64::from(i) * 10)));
+
+        assert!(flagged(&anomalies).is_empty());
+    }
+
+    #[test]
+    fn flags_sprayed_client_once() {
+        let mut registry = ClientRegistry::default();
+        let now = Instant::now();
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut patterns = vec![];
+        for port in 5000..5020 {
+            let anomaly = if port % 2 == 0 {
+                Anomaly::StalledSession
+            } else {
+                Anomaly::OrphanAck
+            };
+            patterns.extend(registry.record_anomaly(SocketAddr::new(ip, port), anomaly, now));
+        }
+
+        assert_eq!(
+            patterns,
+            vec![
+                "4 sessions stalled before the first ACK and 4 orphan ACKs from 8 source ports in 0s"
+            ]
+        );
+        assert_eq!(registry.sprayed(), vec![ip]);
+        assert!(registry.anomalies.is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Clock `trait` is the source of time used by the [`Server`](crate::Server)
+/// for retransmission and expiry decisions.
+///
+/// The server uses [`SystemClock`] by default. Tests can substitute a
+/// [`MockClock`] to drive timeouts deterministically.
+pub trait Clock: Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+}
+
+/// SystemClock `struct` reads the monotonic system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// MockClock `struct` is a manually advanced [`Clock`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tftpd::{Clock, MockClock};
+///
+/// let clock = MockClock::new();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(5));
+///
+/// assert_eq!(clock.now() - start, Duration::from_secs(5));
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl MockClock {
+    /// Creates a [`MockClock`] starting at the current instant.
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Moves the clock forward by the supplied [`Duration`].
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
@@ -1,13 +1,22 @@
-use std::error::Error;
-use std::net::Ipv4Addr;
-use std::path::{Path, PathBuf};
+use crate::preflight;
+use crate::TsizeMode;
+use crate::{
+    Cidr, ConfigWarning, DuplicatePolicy, OptionLimits, OptionType, Quirk, Quota, TftpError,
+};
+use std::ffi::OsString;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{env, process};
 
 /// Configuration `struct` used for parsing TFTP options from user
 /// input.
 ///
 /// This `struct` is meant to be created by [`Config::new()`]. See its
-/// documentation for more.
+/// documentation for more. When embedding the server, it can also be built
+/// from [`Config::default()`].
+///
+/// With the `serde` feature, it implements `Serialize` and `Deserialize`.
 ///
 /// # Example
 ///
@@ -17,42 +26,449 @@ use std::{env, process};
 /// use tftpd::Config;
 ///
 /// let config = Config::new(env::args()).unwrap();
+///
+/// // Or build it directly.
+/// let config = Config {
+///     port: 6969,
+///     ..Config::default()
+/// };
 /// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Config {
     /// Local IP address of the TFTP Server. (default: 127.0.0.1)
     pub ip_address: Ipv4Addr,
+    /// Further local IP addresses the server listens on, on the same port,
+    /// from a repeated `-i` or a comma-separated list. (default: none)
+    pub additional_ip_addresses: Vec<Ipv4Addr>,
+    /// Host name resolved to the address listened on instead of
+    /// [`Config::ip_address`], resolved again on `SIGHUP` to follow a
+    /// changed address. (default: none)
+    pub bind_host: Option<String>,
     /// Local Port number of the TFTP Server. (default: 69)
     pub port: u16,
     /// Default directory of the TFTP Server. (default: current working directory)
     pub directory: PathBuf,
+    /// Filename that returns a generated listing of the directory. (default: disabled)
+    pub listing_file: Option<String>,
+    /// Number of directory levels included in the listing. (default: 1)
+    pub listing_depth: usize,
+    /// Maximum size of the listing in bytes. (default: 65536)
+    pub listing_max_bytes: usize,
+    /// Filename that returns a generated boot menu, with the directory of
+    /// the images it lists relative to the served directory. (default: disabled)
+    pub autogen_menu: Option<(String, String)>,
+    /// File whose contents start the generated boot menu. (default: none)
+    pub autogen_menu_header: Option<PathBuf>,
+    /// File whose contents end the generated boot menu. (default: none)
+    pub autogen_menu_footer: Option<PathBuf>,
+    /// Resolve requested files below a handle of the directory, enforced by
+    /// the kernel on Linux. (default: false)
+    pub beneath: bool,
+    /// Maximum number of concurrent transfers of the same file. (default: unlimited)
+    pub max_readers_per_file: Option<usize>,
+    /// Maximum number of concurrent transfers of the same client host,
+    /// whatever their source port. (default: unlimited)
+    pub max_per_ip: Option<usize>,
+    /// Number of bytes a client host may download within a window, further
+    /// requests being refused until the window ends. (default: unlimited)
+    pub quota: Option<Quota>,
+    /// Abort the running transfers of a client host that used up its
+    /// quota, instead of letting them complete. (default: false)
+    pub quota_hard: bool,
+    /// What to do with a request for a file at its reader limit. (default: queue)
+    pub when_busy: BusyStrategy,
+    /// File listing the only relative paths that may be served, reloaded on
+    /// `SIGHUP`. (default: disabled)
+    pub manifest: Option<PathBuf>,
+    /// Retransmission timeout when the client does not negotiate one. (default: 5s)
+    pub retransmit_timeout: Duration,
+    /// Number of times a window is sent again without an ACK before the
+    /// transfer is given up with an ERROR. (default: 6)
+    pub max_retries: u32,
+    /// Only answer requests sent to one of these local addresses, if any. (default: any)
+    pub answer_on: Vec<IpAddr>,
+    /// Address of a statsd agent to send the metrics to. (default: disabled)
+    pub statsd: Option<String>,
+    /// Prefix of the statsd metric names. (default: tftpd)
+    pub statsd_prefix: String,
+    /// Tags attached to the statsd metrics. (default: none)
+    pub statsd_tags: Vec<String>,
+    /// File the counters and per-file stats are saved to and restored from
+    /// at startup, so that they carry over a restart. (default: none)
+    pub metrics_snapshot: Option<PathBuf>,
+    /// Interval between two saves of the metrics snapshot, which is also
+    /// saved when the server stops. (default: 60s)
+    pub metrics_snapshot_interval: Duration,
+    /// Limits applied when negotiating the options of a transfer.
+    pub option_limits: OptionLimits,
+    /// Largest number of bytes sent to a client and not acknowledged yet,
+    /// windows of larger blocks being sent in parts without changing the
+    /// negotiated window size. At least one block is always sent.
+    /// (default: `option_limits.max_window_bytes`)
+    pub max_in_flight_bytes: Option<usize>,
+    /// Which read requests sent to a broadcast address are answered. (default: if-file-exists)
+    pub answer_broadcast: BroadcastPolicy,
+    /// Serve the decompressed `<name>.gz` when `<name>` does not exist,
+    /// requires the `gzip` feature. (default: false)
+    pub compressed_fallback: bool,
+    /// Shell command run when a transfer completes. (default: none)
+    pub exec_on_complete: Option<String>,
+    /// Shell command run when a transfer fails. (default: none)
+    pub exec_on_fail: Option<String>,
+    /// Decode `%XX` sequences in requested filenames. (default: false)
+    pub percent_decode: bool,
+    /// Strip leading and trailing ASCII whitespace from requested filenames.
+    /// (default: false)
+    pub trim_request_whitespace: bool,
+    /// Refuse requested filenames beginning with `/` instead of reading
+    /// them relative to the served directory. Absolute paths into the
+    /// served directory are read either way. (default: false)
+    pub strict_paths: bool,
+    /// File served to read requests for an empty filename or the bare root,
+    /// which are otherwise refused. (default: none)
+    pub empty_request_file: Option<String>,
+    /// Time a tick may spend handling packets and timers before a warning is
+    /// logged. (default: 5ms)
+    pub tick_budget: Duration,
+    /// Directory in which the datagrams of every transfer are recorded.
+    /// (default: none)
+    pub record: Option<PathBuf>,
+    /// Number of times every DATA packet is sent back to back. (default: 1)
+    pub duplicate_data: usize,
+    /// Time after which a transfer is aborted, however active the client.
+    /// (default: unlimited)
+    pub max_transfer_duration: Option<Duration>,
+    /// Time without a packet from the client after which its transfer is
+    /// given up with an ERROR, whatever the retransmission settings, 0 to
+    /// never do. (default: 60s)
+    pub session_timeout: Option<Duration>,
+    /// Accept an ACK from an unknown port of a client host with a single
+    /// transfer, moving the transfer to that port. For middleboxes rewriting
+    /// source ports mid-transfer. (default: false)
+    pub loose_tid: bool,
+    /// Time after which a listen loop that stopped polling aborts the
+    /// process, so that a supervisor restarts it. (default: disabled)
+    pub watchdog_timeout: Option<Duration>,
+    /// Virtual filename under which standard input is streamed to the
+    /// first client requesting it. (default: none)
+    pub pipe: Option<String>,
+    /// Stop listening once the first transfer ends. (default: false)
+    pub oneshot: bool,
+    /// Clamp the block size to 512 for a while for client hosts that never
+    /// acknowledged the first window of a transfer with larger blocks, as
+    /// when a tunnel drops large datagrams. (default: false)
+    pub auto_shrink_blksize: bool,
+    /// Fill the holes of sparse files with zeros instead of reading them,
+    /// on Linux. (default: false)
+    pub skip_holes: bool,
+    /// Refuse to start when opening and reading a file of the served
+    /// directory takes longer, as on FUSE mounts of object stores.
+    /// (default: none, only warn)
+    pub require_fast_storage: Option<Duration>,
+    /// Log a warning naming the file when a single read of a transfer takes
+    /// longer. (default: none)
+    pub slow_storage_warn: Option<Duration>,
+    /// Explain the options granted less than requested in a nonstandard
+    /// `srvnote` option of the OACK, with the codes of
+    /// [`NegotiationNote`](crate::NegotiationNote). (default: false)
+    pub negotiation_report: bool,
+    /// Acknowledge the options in the order of the request instead of
+    /// [`OACK_ORDER`](crate::OACK_ORDER), for clients expecting their own
+    /// order mirrored. (default: false)
+    pub preserve_option_order: bool,
+    /// Regularly check that the timers, the client registry, the reader
+    /// counts and the metrics agree with the sessions, which debug builds
+    /// always do. (default: false)
+    pub self_check: bool,
+    /// Number of consecutive ACKs for a block more than a window below the
+    /// one in flight after which the client is taken to have restarted the
+    /// transfer, 0 to never do. (default: 3)
+    pub restart_acks: u32,
+    /// Resume the transfer of a client that restarted from the block it
+    /// acknowledged again, instead of aborting it so that the client sends
+    /// a new read request. Only plain files can be read again. (default:
+    /// false)
+    pub allow_mid_session_restart: bool,
+    /// Refuse files needing more than 65535 blocks at the negotiated block
+    /// size, for clients that cannot wrap the block number around. Files of
+    /// unknown size are still sent. (default: false)
+    pub no_rollover: bool,
+    /// Check the configuration, print its warnings and exit without
+    /// serving. (default: false)
+    pub dry_run: bool,
+    /// Delay before the first packet of a transfer, the OACK or the first
+    /// DATA, for clients that are not ready to receive it right after their
+    /// request. (default: 0)
+    pub initial_delay: Duration,
+    /// Delays replacing `initial_delay` for the clients of a block of
+    /// addresses, the longest matching prefix winning. (default: none)
+    pub initial_delay_for: Vec<(Cidr, Duration)>,
+    /// Time without a transfer after which the journey of a client host
+    /// ends and its summary is logged, transfers within it sharing a
+    /// journey ID. (default: 30s)
+    pub journey_window: Duration,
+    /// Compatibility workarounds for the clients of a block of addresses
+    /// or requesting matching filenames, applied on top of the global
+    /// flags. (default: none)
+    pub quirks: Vec<Quirk>,
+    /// Whether a panic while handling a packet stops the server, instead
+    /// of aborting the session of its client only. (default: false)
+    pub abort_on_panic: bool,
+    /// Whether read and write requests from a source port below 1024 are
+    /// dropped, against spoofed requests aiming the transfer at a
+    /// well-known service. (default: false)
+    pub reject_privileged_source_ports: bool,
+    /// Source ports below 1024 accepted anyway with
+    /// `reject_privileged_source_ports`. (default: none)
+    pub allowed_source_ports: Vec<u16>,
+    /// Number of malformed packets, denied requests and packets answered
+    /// with a protocol error kept for debugging, 0 to keep none.
+    /// (default: 256)
+    pub debug_capture: usize,
+    /// Most detailed log lines written, applied by the `tftpd` binary with
+    /// [`set_log_level()`](crate::set_log_level). (default: info)
+    pub log_level: LogLevel,
+    /// Accept write requests, storing the uploaded files in the served
+    /// directory. (default: false)
+    pub writable: bool,
+    /// Let write requests replace existing files, which are otherwise
+    /// refused with a `FileExists` error. (default: false)
+    pub overwrite: bool,
+    /// Largest file a write request may upload, refused with a `DiskFull`
+    /// error beyond. (default: unlimited)
+    pub max_upload_size: Option<u64>,
+    /// Send every file from its own ephemeral port on its own thread, as
+    /// in RFC 1350, instead of the single port of the server, so that a
+    /// slow client cannot delay the others. Clients behind NAT may not
+    /// accept packets from another port. (default: false)
+    pub threaded: bool,
+    /// Block number following 65535 in files of more blocks, 0 or 1, for
+    /// clients not requesting the `rollover` option. (default: 0)
+    pub rollover: u16,
+    /// Refuse files reached through a symlink, even one staying inside
+    /// the served directory. (default: false)
+    pub no_follow_symlinks: bool,
+    /// What becomes of a read request from the address and port of a
+    /// transfer in progress, other than a retransmission of its own.
+    /// (default: replace)
+    pub conflicting_request: ConflictPolicy,
+}
+
+/// BroadcastPolicy `enum` selects which read requests sent to a broadcast
+/// or multicast address are answered. Some clients broadcast a request to
+/// discover a server before sending the real one.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+#[non_exhaustive]
+pub enum BroadcastPolicy {
+    /// Answer every request
+    Always,
+    /// Ignore every request
+    Never,
+    /// Only answer requests for a file that can be served, so that servers
+    /// without the file stay silent
+    #[default]
+    IfFileExists,
+}
+
+/// LogLevel `enum` selects the most detailed log lines written, each level
+/// including the ones before it.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[non_exhaustive]
+pub enum LogLevel {
+    /// Transfers, refusals and problems
+    #[default]
+    Info,
+    /// Also requests retransmitted by clients and how filenames were
+    /// normalized
+    Debug,
+    /// Also every packet received and every block sent
+    Trace,
+}
+
+/// BusyStrategy `enum` selects how requests for a file that reached
+/// [`Config::max_readers_per_file`] are handled.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[non_exhaustive]
+pub enum BusyStrategy {
+    /// Hold the request until a transfer of the file finishes, as long as
+    /// the client keeps retrying
+    #[default]
+    Queue,
+    /// Answer with an error asking the client to retry later
+    Reject,
+}
+
+/// ConflictPolicy `enum` selects how a read request from the address and
+/// port of a transfer in progress is handled, which a client reusing its
+/// port after giving up sends.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[non_exhaustive]
+pub enum ConflictPolicy {
+    /// End the transfer in progress as failed and serve the new request
+    #[default]
+    Replace,
+    /// Refuse the new request and keep the transfer in progress
+    Reject,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ip_address: Ipv4Addr::new(127, 0, 0, 1),
+            additional_ip_addresses: vec![],
+            bind_host: None,
+            port: 69,
+            directory: env::current_dir().unwrap_or_else(|_| env::temp_dir()),
+            listing_file: None,
+            listing_depth: 1,
+            listing_max_bytes: 65536,
+            autogen_menu: None,
+            autogen_menu_header: None,
+            autogen_menu_footer: None,
+            beneath: false,
+            max_readers_per_file: None,
+            max_per_ip: None,
+            quota: None,
+            quota_hard: false,
+            when_busy: BusyStrategy::Queue,
+            manifest: None,
+            retransmit_timeout: Duration::from_secs(5),
+            max_retries: 6,
+            answer_on: vec![],
+            statsd: None,
+            statsd_prefix: "tftpd".to_string(),
+            statsd_tags: vec![],
+            metrics_snapshot: None,
+            metrics_snapshot_interval: Duration::from_secs(60),
+            option_limits: OptionLimits::default(),
+            max_in_flight_bytes: None,
+            answer_broadcast: BroadcastPolicy::IfFileExists,
+            compressed_fallback: false,
+            exec_on_complete: None,
+            exec_on_fail: None,
+            percent_decode: false,
+            trim_request_whitespace: false,
+            strict_paths: false,
+            empty_request_file: None,
+            tick_budget: Duration::from_millis(5),
+            record: None,
+            duplicate_data: 1,
+            max_transfer_duration: None,
+            session_timeout: Some(Duration::from_secs(60)),
+            loose_tid: false,
+            watchdog_timeout: None,
+            pipe: None,
+            oneshot: false,
+            auto_shrink_blksize: false,
+            skip_holes: false,
+            require_fast_storage: None,
+            slow_storage_warn: None,
+            negotiation_report: false,
+            preserve_option_order: false,
+            self_check: false,
+            restart_acks: 3,
+            allow_mid_session_restart: false,
+            no_rollover: false,
+            dry_run: false,
+            initial_delay: Duration::ZERO,
+            initial_delay_for: vec![],
+            journey_window: Duration::from_secs(30),
+            quirks: vec![],
+            abort_on_panic: false,
+            reject_privileged_source_ports: false,
+            allowed_source_ports: vec![],
+            debug_capture: 256,
+            log_level: LogLevel::Info,
+            writable: false,
+            overwrite: false,
+            max_upload_size: None,
+            threaded: false,
+            rollover: 0,
+            no_follow_symlinks: false,
+            conflicting_request: ConflictPolicy::Replace,
+        }
+    }
 }
 
+/// Flags accepted by [`Config::minimal()`], all of them taking a value.
+#[cfg(feature = "cli-min")]
+const MINIMAL_FLAGS: [&str; 8] = [
+    "-i",
+    "--ip-address",
+    "-p",
+    "--port",
+    "-d",
+    "--directory",
+    "--max-blksize",
+    "--retransmit-timeout",
+];
+
 impl Config {
     /// Creates a new configuration by parsing the supplied arguments. It is
-    /// intended for use with [`env::args()`].
-    pub fn new<T>(mut args: T) -> Result<Config, Box<dyn Error>>
+    /// intended for use with [`env::args_os()`], which keeps paths that are
+    /// not valid UTF-8, but also accepts [`String`] arguments.
+    pub fn new<T, A>(args: T) -> Result<Config, TftpError>
     where
-        T: Iterator<Item = String>,
+        T: Iterator<Item = A>,
+        A: Into<OsString>,
     {
-        let mut config = Config {
-            ip_address: Ipv4Addr::new(127, 0, 0, 1),
-            port: 69,
-            directory: env::current_dir().unwrap_or_else(|_| env::temp_dir()),
-        };
+        let mut args = args.map(Into::into);
+        let mut config = Config::default();
+        let mut ip_address_set = false;
 
         args.next();
 
         while let Some(arg) = args.next() {
+            let arg = arg
+                .into_string()
+                .map_err(|arg| format!("Invalid flag: {}", arg.to_string_lossy()))?;
             match arg.as_str() {
                 "-i" | "--ip-address" => {
-                    if let Some(ip_str) = args.next() {
-                        config.ip_address = ip_str.parse::<Ipv4Addr>()?;
+                    if let Some(ip_str) = next_string(&mut args)? {
+                        for ip_str in ip_str.split(',') {
+                            let ip_address = ip_str.parse::<Ipv4Addr>()?;
+                            if ip_address_set {
+                                config.additional_ip_addresses.push(ip_address);
+                            } else {
+                                config.ip_address = ip_address;
+                                ip_address_set = true;
+                            }
+                        }
                     } else {
                         return Err("Missing ip address after flag".into());
                     }
                 }
+                "--bind-host" => {
+                    if let Some(host) = next_string(&mut args)? {
+                        config.bind_host = Some(host);
+                    } else {
+                        return Err("Missing host name after flag".into());
+                    }
+                }
                 "-p" | "--port" => {
-                    if let Some(port_str) = args.next() {
+                    if let Some(port_str) = next_string(&mut args)? {
                         config.port = port_str.parse::<u16>()?;
                     } else {
                         return Err("Missing port number after flag".into());
@@ -60,32 +476,741 @@ impl Config {
                 }
                 "-d" | "--directory" => {
                     if let Some(dir_str) = args.next() {
-                        if !Path::new(&dir_str).exists() {
-                            return Err(format!("{dir_str} does not exist").into());
+                        let directory = PathBuf::from(dir_str);
+                        if !directory.exists() {
+                            return Err(TftpError::Directory(format!(
+                                "{} does not exist",
+                                directory.display()
+                            )));
                         }
-                        config.directory = PathBuf::from(dir_str);
+                        config.directory = directory;
                     } else {
                         return Err("Missing directory after flag".into());
                     }
                 }
+                "--listing-file" => {
+                    if let Some(name) = next_string(&mut args)? {
+                        config.listing_file = Some(name);
+                    } else {
+                        return Err("Missing listing filename after flag".into());
+                    }
+                }
+                "--listing-depth" => {
+                    if let Some(depth_str) = next_string(&mut args)? {
+                        config.listing_depth = depth_str.parse::<usize>()?;
+                    } else {
+                        return Err("Missing listing depth after flag".into());
+                    }
+                }
+                "--listing-max-bytes" => {
+                    if let Some(max_str) = next_string(&mut args)? {
+                        config.listing_max_bytes = max_str.parse::<usize>()?;
+                    } else {
+                        return Err("Missing listing size after flag".into());
+                    }
+                }
+                "--autogen-menu" => {
+                    if let Some(menu_str) = next_string(&mut args)? {
+                        let Some((name, scan_dir)) = menu_str.split_once('=') else {
+                            return Err(format!(
+                                "Invalid boot menu {menu_str}, expected NAME=SCAN-DIR"
+                            )
+                            .into());
+                        };
+                        if name.is_empty() {
+                            return Err("Boot menu name must not be empty".into());
+                        }
+                        config.autogen_menu = Some((name.to_string(), scan_dir.to_string()));
+                    } else {
+                        return Err("Missing boot menu after flag".into());
+                    }
+                }
+                "--autogen-menu-header" => {
+                    if let Some(header_str) = args.next() {
+                        config.autogen_menu_header = Some(PathBuf::from(header_str));
+                    } else {
+                        return Err("Missing boot menu header file after flag".into());
+                    }
+                }
+                "--autogen-menu-footer" => {
+                    if let Some(footer_str) = args.next() {
+                        config.autogen_menu_footer = Some(PathBuf::from(footer_str));
+                    } else {
+                        return Err("Missing boot menu footer file after flag".into());
+                    }
+                }
+                "--beneath" => {
+                    config.beneath = true;
+                }
+                "--max-readers-per-file" => {
+                    if let Some(max_str) = next_string(&mut args)? {
+                        let max = max_str.parse::<usize>()?;
+                        if max == 0 {
+                            return Err("Maximum readers per file must be at least 1".into());
+                        }
+                        config.max_readers_per_file = Some(max);
+                    } else {
+                        return Err("Missing reader count after flag".into());
+                    }
+                }
+                "--max-per-ip" => {
+                    if let Some(max_str) = next_string(&mut args)? {
+                        let max = max_str.parse::<usize>()?;
+                        if max == 0 {
+                            return Err("Maximum transfers per IP must be at least 1".into());
+                        }
+                        config.max_per_ip = Some(max);
+                    } else {
+                        return Err("Missing transfer count after flag".into());
+                    }
+                }
+                "--quota" => {
+                    if let Some(quota_str) = next_string(&mut args)? {
+                        config.quota = Some(quota_str.parse()?);
+                    } else {
+                        return Err("Missing quota after flag".into());
+                    }
+                }
+                "--quota-hard" => {
+                    config.quota_hard = true;
+                }
+                "--when-busy" => {
+                    if let Some(strategy_str) = next_string(&mut args)? {
+                        config.when_busy = match strategy_str.as_str() {
+                            "queue" => BusyStrategy::Queue,
+                            "reject" => BusyStrategy::Reject,
+                            invalid => {
+                                return Err(format!("Invalid busy strategy: {invalid}").into())
+                            }
+                        };
+                    } else {
+                        return Err("Missing busy strategy after flag".into());
+                    }
+                }
+                "--manifest" => {
+                    if let Some(manifest_str) = args.next() {
+                        config.manifest = Some(PathBuf::from(manifest_str));
+                    } else {
+                        return Err("Missing manifest file after flag".into());
+                    }
+                }
+                "--retransmit-timeout" => {
+                    if let Some(timeout_str) = next_string(&mut args)? {
+                        let millis = timeout_str.parse::<u64>()?;
+                        if millis == 0 {
+                            return Err("Retransmit timeout must be at least 1 ms".into());
+                        }
+                        config.retransmit_timeout = Duration::from_millis(millis);
+                    } else {
+                        return Err("Missing retransmit timeout after flag".into());
+                    }
+                }
+                "--max-retries" => {
+                    if let Some(count_str) = next_string(&mut args)? {
+                        config.max_retries = count_str.parse::<u32>()?;
+                    } else {
+                        return Err("Missing retry count after flag".into());
+                    }
+                }
+                "--answer-on" => {
+                    if let Some(ip_str) = next_string(&mut args)? {
+                        config.answer_on.push(ip_str.parse::<IpAddr>()?);
+                    } else {
+                        return Err("Missing ip address after flag".into());
+                    }
+                }
+                "--statsd" => {
+                    if let Some(target) = next_string(&mut args)? {
+                        config.statsd = Some(target);
+                    } else {
+                        return Err("Missing statsd address after flag".into());
+                    }
+                }
+                "--statsd-prefix" => {
+                    if let Some(prefix) = next_string(&mut args)? {
+                        config.statsd_prefix = prefix;
+                    } else {
+                        return Err("Missing statsd prefix after flag".into());
+                    }
+                }
+                "--statsd-tag" => {
+                    if let Some(tag) = next_string(&mut args)? {
+                        config.statsd_tags.push(tag);
+                    } else {
+                        return Err("Missing statsd tag after flag".into());
+                    }
+                }
+                "--metrics-snapshot" => {
+                    if let Some(path_str) = args.next() {
+                        config.metrics_snapshot = Some(PathBuf::from(path_str));
+                    } else {
+                        return Err("Missing snapshot path after flag".into());
+                    }
+                }
+                "--metrics-snapshot-interval" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        let secs = secs_str.parse::<u64>()?;
+                        if secs == 0 {
+                            return Err("Snapshot interval must be at least 1 second".into());
+                        }
+                        config.metrics_snapshot_interval = Duration::from_secs(secs);
+                    } else {
+                        return Err("Missing snapshot interval after flag".into());
+                    }
+                }
+                "--tsize" => {
+                    if let Some(mode_str) = next_string(&mut args)? {
+                        config.option_limits.tsize = match mode_str.as_str() {
+                            "echo" => TsizeMode::Echo,
+                            "omit" => TsizeMode::Omit,
+                            "zero" => TsizeMode::Zero,
+                            invalid => return Err(format!("Invalid tsize mode: {invalid}").into()),
+                        };
+                    } else {
+                        return Err("Missing tsize mode after flag".into());
+                    }
+                }
+                "--max-blksize" => {
+                    if let Some(size_str) = next_string(&mut args)? {
+                        config.option_limits.max_blksize = size_str.parse::<usize>()?;
+                    } else {
+                        return Err("Missing block size after flag".into());
+                    }
+                }
+                "--max-windowsize" => {
+                    if let Some(size_str) = next_string(&mut args)? {
+                        config.option_limits.max_windowsize = size_str.parse::<u16>()?;
+                    } else {
+                        return Err("Missing window size after flag".into());
+                    }
+                }
+                "--max-window-bytes" => {
+                    if let Some(bytes_str) = next_string(&mut args)? {
+                        config.option_limits.max_window_bytes = Some(bytes_str.parse::<usize>()?);
+                    } else {
+                        return Err("Missing byte count after flag".into());
+                    }
+                }
+                "--max-retransmit-rate" => {
+                    if let Some(rate_str) = next_string(&mut args)? {
+                        let rate = rate_str.parse::<u64>()?;
+                        config.option_limits.max_retransmit_rate = (rate > 0).then_some(rate);
+                    } else {
+                        return Err("Missing byte rate after flag".into());
+                    }
+                }
+                "--max-in-flight-bytes" => {
+                    if let Some(bytes_str) = next_string(&mut args)? {
+                        let bytes = bytes_str.parse::<usize>()?;
+                        if bytes == 0 {
+                            return Err("Maximum in-flight bytes must be at least 1".into());
+                        }
+                        config.max_in_flight_bytes = Some(bytes);
+                    } else {
+                        return Err("Missing byte count after flag".into());
+                    }
+                }
+                "--min-timeout" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        config.option_limits.min_timeout = secs_str.parse::<u64>()?;
+                    } else {
+                        return Err("Missing timeout after flag".into());
+                    }
+                }
+                "--max-timeout" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        config.option_limits.max_timeout = secs_str.parse::<u64>()?;
+                    } else {
+                        return Err("Missing timeout after flag".into());
+                    }
+                }
+                "--disable-option" => {
+                    if let Some(option_str) = next_string(&mut args)? {
+                        let option = option_str.parse::<OptionType>()?;
+                        config.option_limits.disabled.push(option);
+                    } else {
+                        return Err("Missing option name after flag".into());
+                    }
+                }
+                "--duplicate-options" => {
+                    if let Some(policy_str) = next_string(&mut args)? {
+                        config.option_limits.duplicates = match policy_str.as_str() {
+                            "reject" => DuplicatePolicy::Reject,
+                            "first" => DuplicatePolicy::First,
+                            _ => {
+                                return Err(format!("Invalid duplicate policy: {policy_str}").into())
+                            }
+                        };
+                    } else {
+                        return Err("Missing duplicate policy after flag".into());
+                    }
+                }
+                "--max-options-bytes" => {
+                    if let Some(bytes_str) = next_string(&mut args)? {
+                        config.option_limits.max_options_bytes = bytes_str.parse::<usize>()?;
+                    } else {
+                        return Err("Missing byte count after flag".into());
+                    }
+                }
+                "--answer-broadcast" => {
+                    if let Some(policy_str) = next_string(&mut args)? {
+                        config.answer_broadcast = match policy_str.as_str() {
+                            "always" => BroadcastPolicy::Always,
+                            "never" => BroadcastPolicy::Never,
+                            "if-file-exists" => BroadcastPolicy::IfFileExists,
+                            invalid => {
+                                return Err(format!("Invalid broadcast policy: {invalid}").into())
+                            }
+                        };
+                    } else {
+                        return Err("Missing broadcast policy after flag".into());
+                    }
+                }
+                "--serve-compressed-fallback" => {
+                    config.compressed_fallback = true;
+                }
+                "--exec-on-complete" => {
+                    if let Some(command) = next_string(&mut args)? {
+                        config.exec_on_complete = Some(command);
+                    } else {
+                        return Err("Missing command after flag".into());
+                    }
+                }
+                "--exec-on-fail" => {
+                    if let Some(command) = next_string(&mut args)? {
+                        config.exec_on_fail = Some(command);
+                    } else {
+                        return Err("Missing command after flag".into());
+                    }
+                }
+                "--percent-decode" => {
+                    config.percent_decode = true;
+                }
+                "--trim-request-whitespace" => {
+                    config.trim_request_whitespace = true;
+                }
+                "--strict-paths" => {
+                    config.strict_paths = true;
+                }
+                "--empty-request-file" => {
+                    if let Some(name) = next_string(&mut args)? {
+                        config.empty_request_file = Some(name);
+                    } else {
+                        return Err("Missing file name after flag".into());
+                    }
+                }
+                "--pipe" => {
+                    if let Some(name) = next_string(&mut args)? {
+                        config.pipe = Some(name);
+                    } else {
+                        return Err("Missing filename after flag".into());
+                    }
+                }
+                "--oneshot" => {
+                    config.oneshot = true;
+                }
+                "--auto-shrink-blksize" => {
+                    config.auto_shrink_blksize = true;
+                }
+                "--require-fast-storage" => {
+                    if let Some(threshold_str) = next_string(&mut args)? {
+                        config.require_fast_storage =
+                            Some(Duration::from_millis(threshold_str.parse::<u64>()?));
+                    } else {
+                        return Err("Missing storage latency after flag".into());
+                    }
+                }
+                "--slow-storage-warn" => {
+                    if let Some(threshold_str) = next_string(&mut args)? {
+                        config.slow_storage_warn =
+                            Some(Duration::from_millis(threshold_str.parse::<u64>()?));
+                    } else {
+                        return Err("Missing read duration after flag".into());
+                    }
+                }
+                "--skip-holes" => {
+                    config.skip_holes = true;
+                }
+                "--loose-tid" => {
+                    config.loose_tid = true;
+                }
+                "--negotiation-report" => {
+                    config.negotiation_report = true;
+                }
+                "--preserve-option-order" => {
+                    config.preserve_option_order = true;
+                }
+                "--self-check" => {
+                    config.self_check = true;
+                }
+                "--restart-threshold" => {
+                    if let Some(count_str) = next_string(&mut args)? {
+                        config.restart_acks = count_str.parse::<u32>()?;
+                    } else {
+                        return Err("Missing ACK count after flag".into());
+                    }
+                }
+                "--allow-mid-session-restart" => {
+                    config.allow_mid_session_restart = true;
+                }
+                "--no-rollover" => {
+                    config.no_rollover = true;
+                }
+                "--writable" => {
+                    config.writable = true;
+                }
+                "--overwrite" => {
+                    config.overwrite = true;
+                }
+                "--max-upload-size" => {
+                    if let Some(bytes_str) = next_string(&mut args)? {
+                        config.max_upload_size = Some(bytes_str.parse::<u64>()?);
+                    } else {
+                        return Err("Missing byte count after flag".into());
+                    }
+                }
+                "--threaded" => {
+                    config.threaded = true;
+                }
+                "--no-follow-symlinks" => {
+                    config.no_follow_symlinks = true;
+                }
+                "--conflicting-request" => {
+                    if let Some(policy_str) = next_string(&mut args)? {
+                        config.conflicting_request = match policy_str.as_str() {
+                            "replace" => ConflictPolicy::Replace,
+                            "reject" => ConflictPolicy::Reject,
+                            invalid => {
+                                return Err(format!("Invalid conflict policy: {invalid}").into())
+                            }
+                        };
+                    } else {
+                        return Err("Missing conflict policy after flag".into());
+                    }
+                }
+                "--rollover" => {
+                    if let Some(rollover_str) = next_string(&mut args)? {
+                        config.rollover = match rollover_str.as_str() {
+                            "0" => 0,
+                            "1" => 1,
+                            invalid => return Err(format!("Invalid rollover: {invalid}").into()),
+                        };
+                    } else {
+                        return Err("Missing rollover after flag".into());
+                    }
+                }
+                "--abort-on-panic" => {
+                    config.abort_on_panic = true;
+                }
+                "--reject-privileged-source-ports" => {
+                    config.reject_privileged_source_ports = true;
+                }
+                "--allow-source-port" => {
+                    if let Some(port_str) = next_string(&mut args)? {
+                        config.allowed_source_ports.push(port_str.parse::<u16>()?);
+                    } else {
+                        return Err("Missing source port after flag".into());
+                    }
+                }
+                "--debug-capture" => {
+                    if let Some(count_str) = next_string(&mut args)? {
+                        config.debug_capture = count_str.parse::<usize>()?;
+                    } else {
+                        return Err("Missing capture size after flag".into());
+                    }
+                }
+                "--log-level" => {
+                    if let Some(level_str) = next_string(&mut args)? {
+                        config.log_level = match level_str.as_str() {
+                            "info" => LogLevel::Info,
+                            "debug" => LogLevel::Debug,
+                            "trace" => LogLevel::Trace,
+                            invalid => return Err(format!("Invalid log level: {invalid}").into()),
+                        };
+                    } else {
+                        return Err("Missing log level after flag".into());
+                    }
+                }
+                "--dry-run" => {
+                    config.dry_run = true;
+                }
+                "--initial-delay" => {
+                    if let Some(delay_str) = next_string(&mut args)? {
+                        config.initial_delay = Duration::from_millis(delay_str.parse::<u64>()?);
+                    } else {
+                        return Err("Missing initial delay after flag".into());
+                    }
+                }
+                "--initial-delay-for" => {
+                    if let Some(delay_str) = next_string(&mut args)? {
+                        let Some((cidr, millis)) = delay_str.split_once('=') else {
+                            return Err(format!(
+                                "Invalid initial delay {delay_str}, expected CIDR=MS"
+                            )
+                            .into());
+                        };
+                        config
+                            .initial_delay_for
+                            .push((cidr.parse()?, Duration::from_millis(millis.parse::<u64>()?)));
+                    } else {
+                        return Err("Missing initial delay after flag".into());
+                    }
+                }
+                "--quirk" => {
+                    if let Some(quirk_str) = next_string(&mut args)? {
+                        config.quirks.push(quirk_str.parse()?);
+                    } else {
+                        return Err("Missing quirk after flag".into());
+                    }
+                }
+                "--journey-window" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        let secs = secs_str.parse::<u64>()?;
+                        if secs == 0 {
+                            return Err("Journey window must be at least 1".into());
+                        }
+                        config.journey_window = Duration::from_secs(secs);
+                    } else {
+                        return Err("Missing journey window after flag".into());
+                    }
+                }
+                "--watchdog-timeout" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        let secs = secs_str.parse::<u64>()?;
+                        if secs == 0 {
+                            return Err("Watchdog timeout must be at least 1".into());
+                        }
+                        config.watchdog_timeout = Some(Duration::from_secs(secs));
+                    } else {
+                        return Err("Missing timeout after flag".into());
+                    }
+                }
+                "--tick-budget" => {
+                    if let Some(budget_str) = next_string(&mut args)? {
+                        config.tick_budget = Duration::from_millis(budget_str.parse::<u64>()?);
+                    } else {
+                        return Err("Missing tick budget after flag".into());
+                    }
+                }
+                "--record" => {
+                    if let Some(dir_str) = args.next() {
+                        config.record = Some(PathBuf::from(dir_str));
+                    } else {
+                        return Err("Missing record directory after flag".into());
+                    }
+                }
+                "--duplicate-data" => {
+                    if let Some(count_str) = next_string(&mut args)? {
+                        let count = count_str.parse::<usize>()?;
+                        if count == 0 {
+                            return Err("Duplicate data count must be at least 1".into());
+                        }
+                        config.duplicate_data = count;
+                    } else {
+                        return Err("Missing duplicate count after flag".into());
+                    }
+                }
+                "--max-transfer-duration" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        let secs = secs_str.parse::<u64>()?;
+                        if secs == 0 {
+                            return Err("Maximum transfer duration must be at least 1".into());
+                        }
+                        config.max_transfer_duration = Some(Duration::from_secs(secs));
+                    } else {
+                        return Err("Missing duration after flag".into());
+                    }
+                }
+                "--session-timeout" => {
+                    if let Some(secs_str) = next_string(&mut args)? {
+                        let secs = secs_str.parse::<u64>()?;
+                        config.session_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                    } else {
+                        return Err("Missing duration after flag".into());
+                    }
+                }
+                "-V" | "--version" => {
+                    println!("{}", crate::build_info());
+                    process::exit(0);
+                }
                 "-h" | "--help" => {
                     println!("TFTP Server Daemon\n");
                     println!("Usage: tftpd [OPTIONS]\n");
                     println!("Options:");
-                    println!("  -i, --ip-address <IP ADDRESS>\tSet the ip address of the server (default: 127.0.0.1)");
+                    println!("  -i, --ip-address <IP ADDRESS>\tSet the ip address of the server, can be repeated or comma-separated (default: 127.0.0.1)");
+                    println!("  --bind-host <HOST>\t\tListen on the address of HOST instead of the ip address, resolved again on SIGHUP (default: none)");
                     println!(
                         "  -p, --port <PORT>\t\tSet the listening port of the server (default: 69)"
                     );
                     println!("  -d, --directory <DIRECTORY>\tSet the listening port of the server (default: Current Working Directory)");
+                    println!("  --listing-file <NAME>\t\tServe a generated listing of the directory under NAME (default: disabled)");
+                    println!("  --listing-depth <DEPTH>\tSet the number of directory levels in the listing (default: 1)");
+                    println!("  --listing-max-bytes <SIZE>\tSet the maximum size of the listing (default: 65536)");
+                    println!("  --autogen-menu <NAME>=<SCAN-DIR>\tServe under NAME an iPXE (.ipxe) or PXELINUX boot menu of the SCAN-DIR/<image>/vmlinuz and initrd pairs (default: disabled)");
+                    println!("  --autogen-menu-header <FILE>\tStart the boot menu with the contents of FILE (default: none)");
+                    println!("  --autogen-menu-footer <FILE>\tEnd the boot menu with the contents of FILE (default: none)");
+                    println!("  --beneath\t\t\tOpen files below a handle of the directory, enforced by the kernel on Linux (default: disabled)");
+                    println!("  --max-readers-per-file <N>\tLimit the concurrent transfers of the same file (default: unlimited)");
+                    println!("  --max-per-ip <N>\t\tLimit the concurrent transfers of a client host (default: unlimited)");
+                    println!("  --quota <BYTES>/<WINDOW>\tLimit the bytes a client host downloads per window, such as 10GB/1h (default: unlimited)");
+                    println!("  --quota-hard\t\t\tAbort the transfers of a client host over its quota (default: disabled)");
+                    println!("  --when-busy <queue|reject>\tQueue or reject requests for a file at its limit (default: queue)");
+                    println!("  --manifest <FILE>\t\tOnly serve the relative paths listed in FILE, reloaded on SIGHUP (default: disabled)");
+                    println!("  --retransmit-timeout <MS>\tRetransmit after MS milliseconds unless the client negotiates a timeout (default: 5000)");
+                    println!("  --max-retries <N>\tGive up a transfer after sending a window N times again without an ACK (default: 6)");
+                    println!("  --answer-on <IP ADDRESS>\tOnly answer requests sent to this address, can be repeated (default: any)");
+                    println!("  --statsd <HOST:PORT>\t\tSend metrics to a statsd agent (default: disabled)");
+                    println!("  --statsd-prefix <PREFIX>\tSet the prefix of the statsd metrics (default: tftpd)");
+                    println!("  --statsd-tag <TAG>\t\tAttach a tag to the statsd metrics, can be repeated (default: none)");
+                    println!("  --metrics-snapshot <PATH>\tSave the counters to PATH and restore them at startup (default: disabled)");
+                    println!("  --metrics-snapshot-interval <SECS>\tSave the metrics snapshot every SECS seconds (default: 60)");
+                    println!("  --tsize <echo|omit|zero>\tAnswer the transfer size option with the file size, not at all or 0 (default: echo)");
+                    println!("  --max-blksize <SIZE>\t\tClamp negotiated block sizes to SIZE bytes (default: 65464)");
+                    println!("  --max-windowsize <N>\t\tClamp negotiated window sizes to N blocks, at most 8192 (default: 8192)");
+                    println!("  --max-window-bytes <SIZE>\tClamp negotiated window sizes to SIZE bytes of data (default: unlimited)");
+                    println!("  --max-retransmit-rate <BYTES/S>\tStretch timeouts, then shrink windows, so that retransmitting a window takes at most BYTES/S, 0 for unlimited (default: 125000000)");
+                    println!("  --max-in-flight-bytes <SIZE>\tSend windows in parts of at most SIZE unacknowledged bytes (default: --max-window-bytes)");
+                    println!("  --min-timeout <SECS>\t\tIgnore requested timeouts shorter than SECS seconds (default: 1)");
+                    println!("  --max-timeout <SECS>\t\tIgnore requested timeouts longer than SECS seconds (default: 255)");
+                    println!("  --disable-option <NAME>\tNever acknowledge the option NAME, can be repeated (default: none)");
+                    println!("  --duplicate-options <reject|first>\n\t\t\t\tReject requests repeating an option or keep its first value (default: first)");
+                    println!("  --max-options-bytes <SIZE>\tReject requests whose options take more than SIZE bytes (default: 512)");
+                    println!("  --negotiation-report\t\tExplain options granted less than requested in a srvnote option (default: disabled)");
+                    println!("  --preserve-option-order\tAcknowledge options in the order of the request (default: blksize, timeout, tsize, windowsize)");
+                    println!("  --answer-broadcast <always|never|if-file-exists>\n\t\t\t\tAnswer requests sent to a broadcast address (default: if-file-exists)");
+                    println!("  --serve-compressed-fallback\tServe the decompressed NAME.gz when NAME does not exist (default: disabled)");
+                    println!("  --exec-on-complete <CMD>\tRun CMD with sh when a transfer completes (default: none)");
+                    println!("  --exec-on-fail <CMD>\t\tRun CMD with sh when a transfer fails (default: none)");
+                    println!("  --percent-decode\t\tDecode %XX sequences in requested filenames (default: disabled)");
+                    println!("  --trim-request-whitespace\tStrip whitespace around requested filenames (default: disabled)");
+                    println!("  --strict-paths\t\tRefuse filenames beginning with / instead of reading them from the directory (default: disabled)");
+                    println!("  --empty-request-file <NAME>\tServe NAME to requests for an empty filename (default: none)");
+                    println!("  --tick-budget <MS>\t\tWarn when a tick takes longer than MS milliseconds (default: 5)");
+                    println!("  --record <DIRECTORY>\t\tRecord the datagrams of every transfer in DIRECTORY (default: disabled)");
+                    println!("  --duplicate-data <N>\t\tSend every DATA packet N times back to back (default: 1)");
+                    println!("  --max-transfer-duration <SECS>\tAbort transfers lasting longer than SECS seconds (default: unlimited)");
+                    println!("  --session-timeout <SECS>\tAbort transfers whose client sent nothing for SECS seconds, 0 to never (default: 60)");
+                    println!("  --loose-tid\t\t\tAccept ACKs from a new port of a client with a single transfer (default: disabled)");
+                    println!("  --watchdog-timeout <SECS>\tAbort when the server stops polling for SECS seconds (default: disabled)");
+                    println!("  --pipe <NAME>\t\t\tStream standard input to the first client requesting NAME (default: none)");
+                    println!(
+                        "  --oneshot\t\t\tExit once the first transfer ends (default: disabled)"
+                    );
+                    println!("  --auto-shrink-blksize\tUse 512-byte blocks for a while for clients whose large blocks are lost (default: disabled)");
+                    println!("  --skip-holes\t\t\tServe the holes of sparse files without reading them, on Linux (default: disabled)");
+                    println!("  --require-fast-storage <MS>\tRefuse to start if reading a file of the directory takes longer than MS milliseconds (default: disabled)");
+                    println!("  --slow-storage-warn <MS>\tWarn when a read of a file takes longer than MS milliseconds (default: disabled)");
+                    println!("  --restart-threshold <N>\tTake N consecutive ACKs of earlier blocks as a client restart, 0 to never (default: 3)");
+                    println!("  --allow-mid-session-restart\tResume transfers whose client restarted instead of aborting them (default: disabled)");
+                    println!("  --no-rollover\t\t\tRefuse files needing more than 65535 blocks at the negotiated block size (default: disabled)");
+                    println!("  --abort-on-panic\t\tStop the server when handling a packet panics, instead of aborting its session (default: disabled)");
+                    println!("  --reject-privileged-source-ports\tDrop read and write requests sent from a port below 1024 (default: disabled)");
+                    println!("  --allow-source-port <PORT>\tAccept requests from PORT anyway, can be repeated (default: none)");
+                    println!("  --writable\t\t\tAccept write requests, storing uploads in the directory (default: disabled)");
+                    println!("  --overwrite\t\t\tLet write requests replace existing files (default: disabled)");
+                    println!("  --max-upload-size <BYTES>\tLargest file a write request may upload (default: unlimited)");
+                    println!("  --conflicting-request <replace|reject>\tReplace the transfer of a port sending a new request, or reject the request (default: replace)");
+                    println!("  --no-follow-symlinks\t\tRefuse files reached through a symlink (default: disabled)");
+                    println!("  --rollover <0|1>\t\tBlock number following 65535, for clients not requesting the rollover option (default: 0)");
+                    println!("  --threaded\t\t\tSend each file from its own port and thread instead of the server port (default: disabled)");
+                    println!("  --log-level <info|debug|trace>\tLog retransmitted requests at debug, and every packet and block at trace (default: info)");
+                    println!("  --debug-capture <N>\tKeep the last N malformed, denied or invalid packets for debugging, 0 to disable (default: 256)");
+                    println!("  --initial-delay <MS>\t\tWait MS milliseconds before sending the first packet of a transfer (default: 0)");
+                    println!("  --initial-delay-for <CIDR>=<MS>\tWait MS milliseconds instead for clients in CIDR, can be repeated (default: none)");
+                    println!("  --quirk <MATCHER>=<FLAGS>\tEnable no-oack, loose-tid, allow-restart, initial-delay=MS or max-blksize=N for clients in a CIDR or requesting a filename glob, can be repeated (default: none)");
+                    println!("  --journey-window <SECS>\tEnd the journey of a client host after SECS seconds without a transfer (default: 30)");
+                    println!(
+                        "  --dry-run\t\t\tCheck the configuration, print its warnings and exit"
+                    );
+                    println!("  --self-check\t\t\tRegularly check the session bookkeeping for inconsistencies (default: disabled, enabled in debug builds)");
+                    println!("  -V, --version\t\t\tPrint version and build information");
                     println!("  -h, --help\t\t\tPrint help information");
+                    println!("\nReplay a recorded transfer:");
+                    println!("  tftpd replay <FILE> [--server <HOST:PORT>] [--no-delay]");
+                    println!("\nCheck that a running server is serving (exits with 1 if not):");
+                    println!("  tftpd healthcheck [--server <HOST:PORT>] [--timeout <SECS>]");
+                    println!("\nMeasure what a server sustains with concurrent clients downloading a file:");
+                    println!("  tftpd bench --file <NAME> [--server <HOST:PORT>] [--clients <N>] [--duration <SECS>]");
+                    println!("              [--ramp <MS>] [--timeout <SECS>] [--blocksize <N>] [--windowsize <N>] [--json]");
+                    println!("\nExit codes:");
+                    println!("  1\tFatal error while serving");
+                    println!("  2\tInvalid arguments");
+                    println!("  3\tCannot bind the address or port");
+                    println!("  4\tDirectory problem");
                     process::exit(0);
                 }
                 invalid => return Err(format!("Invalid flag: {invalid}").into()),
             }
         }
 
+        config.option_limits.validate()?;
+
         Ok(config)
     }
+
+    /// Creates a new configuration like [`Config::new()`], accepting only
+    /// the flags of the minimal binary: `-i`, `-p`, `-d`, `--max-blksize`
+    /// and `--retransmit-timeout`. Other flags are refused rather than
+    /// ignored, and the accepted ones are parsed by [`Config::new()`], so
+    /// that they behave as in the full binary.
+    #[cfg(feature = "cli-min")]
+    pub fn minimal<T, A>(args: T) -> Result<Config, TftpError>
+    where
+        T: Iterator<Item = A>,
+        A: Into<OsString>,
+    {
+        let args: Vec<OsString> = args.map(Into::into).collect();
+        // Skips the program name, then every flag with its value.
+        for flag in args.iter().skip(1).step_by(2) {
+            if !flag
+                .to_str()
+                .is_some_and(|flag| MINIMAL_FLAGS.contains(&flag))
+            {
+                return Err(format!(
+                    "Invalid flag: {}, the minimal build only accepts {}",
+                    flag.to_string_lossy(),
+                    MINIMAL_FLAGS.join(", ")
+                )
+                .into());
+            }
+        }
+        Config::new(args.into_iter())
+    }
+
+    /// Checks the configuration for combinations of settings that
+    /// contradict each other, which are errors, or that have no effect,
+    /// which are returned as warnings with a stable code.
+    /// [`Server::new()`](crate::Server::new) fails on the errors and leaves
+    /// the warnings to the caller.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tftpd::Config;
+    ///
+    /// let config = Config {
+    ///     statsd_tags: vec!["site:lab".to_string()],
+    ///     ..Config::default()
+    /// };
+    /// let warnings = config.validate().unwrap();
+    /// assert_eq!(warnings[0].code, "W007");
+    /// ```
+    pub fn validate(&self) -> Result<Vec<ConfigWarning>, TftpError> {
+        preflight::check(self)
+    }
+}
+
+/// Returns the next argument, which must be valid UTF-8.
+fn next_string<T>(args: &mut T) -> Result<Option<String>, TftpError>
+where
+    T: Iterator<Item = OsString>,
+{
+    args.next()
+        .map(|arg| {
+            arg.into_string()
+                .map_err(|arg| format!("Invalid argument: {}", arg.to_string_lossy()).into())
+        })
+        .transpose()
 }
 
 #[cfg(test)]
@@ -97,7 +1222,7 @@ mod tests {
     #[test]
     fn parses_full_config() {
         let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/"]
+            ["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -108,24 +1233,969 @@ mod tests {
         assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
     }
 
+    #[cfg(unix)]
     #[test]
-    fn parses_some_config() {
+    fn rejects_non_utf8_values() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let args = [
+            OsString::from("/"),
+            OsString::from("--statsd-prefix"),
+            OsString::from_vec(b"tftpd-\xff".to_vec()),
+        ];
+
+        assert!(Config::new(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn parses_several_ip_addresses() {
         let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-d", "/"]
+            ["/", "-i", "10.0.0.1,192.168.10.1", "-i", "172.16.0.1"]
                 .iter()
                 .map(|s| s.to_string()),
         )
         .unwrap();
 
-        assert_eq!(config.ip_address, Ipv4Addr::new(0, 0, 0, 0));
-        assert_eq!(config.port, 69);
-        assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
+        assert_eq!(config.ip_address, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(
+            config.additional_ip_addresses,
+            vec![Ipv4Addr::new(192, 168, 10, 1), Ipv4Addr::new(172, 16, 0, 1)]
+        );
+        assert!(Config::default().additional_ip_addresses.is_empty());
+    }
+
+    #[test]
+    fn parses_bind_host() {
+        let config = Config::new(
+            ["/", "--bind-host", "tftp.lab.example"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.bind_host.as_deref(), Some("tftp.lab.example"));
+        assert!(Config::default().bind_host.is_none());
+        assert!(Config::new(["/", "--bind-host"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_some_config() {
+        let config = Config::new(
+            ["/", "-i", "0.0.0.0", "-d", "/"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.ip_address, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(config.port, 69);
+        assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
+    }
+
+    #[test]
+    fn parses_listing_config() {
+        let config = Config::new(
+            ["/", "--listing-file", ".dirlist", "--listing-depth", "3"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.listing_file, Some(".dirlist".to_string()));
+        assert_eq!(config.listing_depth, 3);
+        assert_eq!(config.listing_max_bytes, 65536);
+    }
+
+    #[test]
+    fn parses_beneath_flag() {
+        let config = Config::new(["/", "--beneath"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.beneath);
+    }
+
+    #[test]
+    fn parses_max_per_ip() {
+        let config = Config::new(["/", "--max-per-ip", "4"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.max_per_ip, Some(4));
+        assert!(Config::new(["/", "--max-per-ip", "0"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_quota() {
+        let config = Config::new(
+            ["/", "--quota", "10GB/1h", "--quota-hard"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.quota,
+            Some(Quota {
+                bytes: 10_000_000_000,
+                window: Duration::from_secs(3600),
+            })
+        );
+        assert!(config.quota_hard);
+        assert_eq!(Config::default().quota, None);
+        assert!(!Config::default().quota_hard);
+        assert!(Config::new(["/", "--quota", "10GB"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_reader_limit_config() {
+        let config = Config::new(
+            ["/", "--max-readers-per-file", "4", "--when-busy", "reject"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.max_readers_per_file, Some(4));
+        assert_eq!(config.when_busy, BusyStrategy::Reject);
+        assert!(Config::new(
+            ["/", "--max-readers-per-file", "0"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+        assert!(Config::new(["/", "--when-busy", "wait"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_autogen_menu() {
+        let config = Config::new(
+            [
+                "/",
+                "--autogen-menu",
+                "autogen/menu.ipxe=images",
+                "--autogen-menu-header",
+                "/etc/tftpd/header.ipxe",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.autogen_menu,
+            Some(("autogen/menu.ipxe".to_string(), "images".to_string()))
+        );
+        assert_eq!(
+            config.autogen_menu_header,
+            Some(PathBuf::from("/etc/tftpd/header.ipxe"))
+        );
+        assert_eq!(config.autogen_menu_footer, None);
+        assert_eq!(Config::default().autogen_menu, None);
+        assert!(Config::new(
+            ["/", "--autogen-menu", "menu.ipxe"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "cli-min")]
+    #[test]
+    fn parses_minimal_flags_like_full_parser() {
+        let args = [
+            "/",
+            "-i",
+            "0.0.0.0",
+            "-p",
+            "6969",
+            "-d",
+            "/",
+            "--max-blksize",
+            "1468",
+            "--retransmit-timeout",
+            "200",
+        ];
+
+        assert_eq!(
+            Config::minimal(args.iter().map(|s| s.to_string())).unwrap(),
+            Config::new(args.iter().map(|s| s.to_string())).unwrap()
+        );
+    }
+
+    #[cfg(feature = "cli-min")]
+    #[test]
+    fn refuses_flags_outside_minimal_build() {
+        for args in [
+            &["/", "--quirk", "*.efi=no-oack"][..],
+            &["/", "-p", "6969", "--loose-tid"],
+            &["/", "-p", "6969", "-h"],
+        ] {
+            let err = Config::minimal(args.iter().map(|s| s.to_string())).unwrap_err();
+            assert_eq!(err.exit_code(), 2, "{args:?}");
+        }
+        assert!(Config::minimal(["/", "-p"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_manifest_flag() {
+        let config = Config::new(
+            ["/", "--manifest", "/etc/tftpd.manifest"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.manifest, Some(PathBuf::from("/etc/tftpd.manifest")));
+    }
+
+    #[test]
+    fn parses_retransmit_timeout() {
+        let config = Config::new(
+            ["/", "--retransmit-timeout", "200"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.retransmit_timeout, Duration::from_millis(200));
+        assert!(Config::new(
+            ["/", "--retransmit-timeout", "0"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_max_retries() {
+        let config =
+            Config::new(["/", "--max-retries", "2"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.max_retries, 2);
+        assert_eq!(Config::default().max_retries, 6);
+        assert!(Config::new(["/", "--max-retries", "-1"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_tick_budget() {
+        let config =
+            Config::new(["/", "--tick-budget", "20"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.tick_budget, Duration::from_millis(20));
+        assert_eq!(Config::default().tick_budget, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn parses_record() {
+        let config =
+            Config::new(["/", "--record", "/tmp/rec"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.record, Some(PathBuf::from("/tmp/rec")));
+        assert!(Config::new(["/", "--record"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_metrics_snapshot() {
+        let config = Config::new(
+            [
+                "/",
+                "--metrics-snapshot",
+                "/var/lib/tftpd/metrics.json",
+                "--metrics-snapshot-interval",
+                "300",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.metrics_snapshot,
+            Some(PathBuf::from("/var/lib/tftpd/metrics.json"))
+        );
+        assert_eq!(config.metrics_snapshot_interval, Duration::from_secs(300));
+        assert!(Config::new(
+            ["/", "--metrics-snapshot-interval", "0"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_conflicting_request() {
+        let config = Config::new(
+            ["/", "--conflicting-request", "reject"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.conflicting_request, ConflictPolicy::Reject);
+        assert_eq!(
+            Config::default().conflicting_request,
+            ConflictPolicy::Replace
+        );
+        assert!(Config::new(
+            ["/", "--conflicting-request", "queue"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_no_follow_symlinks_flag() {
+        let config =
+            Config::new(["/", "--no-follow-symlinks"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.no_follow_symlinks);
+        assert!(!Config::default().no_follow_symlinks);
+    }
+
+    #[test]
+    fn parses_rollover() {
+        let config = Config::new(["/", "--rollover", "1"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.rollover, 1);
+        assert_eq!(Config::default().rollover, 0);
+        assert!(Config::new(["/", "--rollover", "2"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_threaded_flag() {
+        let config = Config::new(["/", "--threaded"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.threaded);
+        assert!(!Config::default().threaded);
+    }
+
+    #[test]
+    fn parses_writable_flags() {
+        let config = Config::new(
+            ["/", "--writable", "--overwrite"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.writable && config.overwrite);
+        assert!(!Config::default().writable);
+        assert!(!Config::default().overwrite);
+    }
+
+    #[test]
+    fn parses_max_upload_size() {
+        let config = Config::new(
+            ["/", "--writable", "--max-upload-size", "1048576"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.max_upload_size, Some(1048576));
+        assert_eq!(Config::default().max_upload_size, None);
+    }
+
+    #[test]
+    fn parses_duplicate_data() {
+        let config =
+            Config::new(["/", "--duplicate-data", "2"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.duplicate_data, 2);
+        assert_eq!(Config::default().duplicate_data, 1);
+        assert!(Config::new(["/", "--duplicate-data", "0"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_max_transfer_duration() {
+        let config = Config::new(
+            ["/", "--max-transfer-duration", "600"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.max_transfer_duration, Some(Duration::from_secs(600)));
+        assert_eq!(Config::default().max_transfer_duration, None);
+    }
+
+    #[test]
+    fn parses_session_timeout() {
+        let parse = |secs: &str| {
+            Config::new(
+                ["/", "--session-timeout", secs]
+                    .iter()
+                    .map(|s| s.to_string()),
+            )
+            .unwrap()
+            .session_timeout
+        };
+
+        assert_eq!(parse("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("0"), None);
+        assert_eq!(
+            Config::default().session_timeout,
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn parses_repeated_answer_on() {
+        let config = Config::new(
+            ["/", "--answer-on", "10.0.0.5", "--answer-on", "fd00::5"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.answer_on,
+            vec![
+                IpAddr::from([10, 0, 0, 5]),
+                IpAddr::from_str("fd00::5").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_statsd_config() {
+        let config = Config::new(
+            [
+                "/",
+                "--statsd",
+                "localhost:8125",
+                "--statsd-prefix",
+                "pxe",
+                "--statsd-tag",
+                "env:prod",
+                "--statsd-tag",
+                "instance:a",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.statsd, Some("localhost:8125".to_string()));
+        assert_eq!(config.statsd_prefix, "pxe");
+        assert_eq!(config.statsd_tags, vec!["env:prod", "instance:a"]);
+    }
+
+    #[test]
+    fn parses_tsize_mode() {
+        let config = Config::new(["/", "--tsize", "omit"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.option_limits.tsize, TsizeMode::Omit);
+        assert!(Config::new(["/", "--tsize", "fake"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_max_in_flight_bytes() {
+        let config = Config::new(
+            ["/", "--max-in-flight-bytes", "131072"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.max_in_flight_bytes, Some(131072));
+        assert_eq!(Config::default().max_in_flight_bytes, None);
+        assert!(Config::new(
+            ["/", "--max-in-flight-bytes", "0"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_option_limits() {
+        let config = Config::new(
+            [
+                "/",
+                "--max-blksize",
+                "1468",
+                "--max-windowsize",
+                "16",
+                "--max-window-bytes",
+                "65536",
+                "--max-retransmit-rate",
+                "1000000",
+                "--min-timeout",
+                "2",
+                "--max-timeout",
+                "30",
+                "--disable-option",
+                "tsize",
+                "--duplicate-options",
+                "reject",
+                "--max-options-bytes",
+                "1024",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.option_limits,
+            OptionLimits {
+                max_blksize: 1468,
+                max_windowsize: 16,
+                max_window_bytes: Some(65536),
+                max_retransmit_rate: Some(1_000_000),
+                min_timeout: 2,
+                max_timeout: 30,
+                tsize: TsizeMode::Echo,
+                disabled: vec![OptionType::TransferSize],
+                duplicates: DuplicatePolicy::Reject,
+                max_options_bytes: 1024,
+            }
+        );
+        assert!(Config::new(["/", "--max-blksize", "4"].iter().map(|s| s.to_string())).is_err());
+        assert!(Config::new(
+            ["/", "--disable-option", "fake"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+        assert!(Config::new(
+            ["/", "--duplicate-options", "last"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+        assert_eq!(
+            Config::new(
+                ["/", "--max-retransmit-rate", "0"]
+                    .iter()
+                    .map(|s| s.to_string())
+            )
+            .unwrap()
+            .option_limits
+            .max_retransmit_rate,
+            None
+        );
+    }
+
+    #[test]
+    fn parses_broadcast_policy() {
+        let config = Config::new(
+            ["/", "--answer-broadcast", "never"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.answer_broadcast, BroadcastPolicy::Never);
+        assert_eq!(
+            Config::default().answer_broadcast,
+            BroadcastPolicy::IfFileExists
+        );
+    }
+
+    #[test]
+    fn parses_exec_hooks() {
+        let config = Config::new(
+            [
+                "/",
+                "--exec-on-complete",
+                "logger done",
+                "--exec-on-fail",
+                "logger failed",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.exec_on_complete, Some("logger done".to_string()));
+        assert_eq!(config.exec_on_fail, Some("logger failed".to_string()));
+        assert!(Config::new(["/", "--exec-on-fail"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_percent_decode() {
+        let config = Config::new(["/", "--percent-decode"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.percent_decode);
+        assert!(!Config::default().percent_decode);
+    }
+
+    #[test]
+    fn parses_trim_request_whitespace() {
+        let config = Config::new(
+            ["/", "--trim-request-whitespace"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.trim_request_whitespace);
+    }
+
+    #[test]
+    fn parses_strict_paths() {
+        let config = Config::new(["/", "--strict-paths"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.strict_paths);
+        assert!(!Config::default().strict_paths);
+    }
+
+    #[test]
+    fn parses_empty_request_file() {
+        let config = Config::new(
+            ["/", "--empty-request-file", "pxelinux.0"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.empty_request_file, Some("pxelinux.0".to_string()));
+        assert_eq!(Config::default().empty_request_file, None);
+        assert!(Config::new(["/", "--empty-request-file"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_watchdog_timeout() {
+        let config = Config::new(
+            ["/", "--watchdog-timeout", "30"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.watchdog_timeout, Some(Duration::from_secs(30)));
+        assert!(Config::new(
+            ["/", "--watchdog-timeout", "0"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_pipe_and_oneshot() {
+        let config = Config::new(
+            ["/", "--oneshot", "--pipe", "firmware.bin"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.pipe, Some("firmware.bin".to_string()));
+        assert!(config.oneshot);
+        assert!(Config::new(["/", "--pipe"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_loose_tid() {
+        let config = Config::new(["/", "--loose-tid"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.loose_tid);
+        assert!(!Config::default().loose_tid);
+    }
+
+    #[test]
+    fn parses_negotiation_report() {
+        let config =
+            Config::new(["/", "--negotiation-report"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.negotiation_report);
+        assert!(!Config::default().negotiation_report);
+    }
+
+    #[test]
+    fn parses_preserve_option_order() {
+        let config = Config::new(
+            ["/", "--preserve-option-order"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.preserve_option_order);
+        assert!(!Config::default().preserve_option_order);
+    }
+
+    #[test]
+    fn parses_restart_handling() {
+        let config = Config::new(
+            [
+                "/",
+                "--restart-threshold",
+                "5",
+                "--allow-mid-session-restart",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.restart_acks, 5);
+        assert!(config.allow_mid_session_restart);
+        assert_eq!(Config::default().restart_acks, 3);
+        assert!(!Config::default().allow_mid_session_restart);
+        assert!(Config::new(["/", "--restart-threshold"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_dry_run_flag() {
+        let config = Config::new(["/", "--dry-run"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.dry_run);
+        assert!(!Config::default().dry_run);
+    }
+
+    #[test]
+    fn parses_no_rollover_flag() {
+        let config = Config::new(["/", "--no-rollover"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.no_rollover);
+        assert!(!Config::default().no_rollover);
+    }
+
+    #[test]
+    fn parses_abort_on_panic_flag() {
+        let config = Config::new(["/", "--abort-on-panic"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.abort_on_panic);
+        assert!(!Config::default().abort_on_panic);
+    }
+
+    #[test]
+    fn parses_source_port_policy() {
+        let config = Config::new(
+            [
+                "/",
+                "--reject-privileged-source-ports",
+                "--allow-source-port",
+                "69",
+                "--allow-source-port",
+                "1001",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.reject_privileged_source_ports);
+        assert_eq!(config.allowed_source_ports, [69, 1001]);
+        assert!(!Config::default().reject_privileged_source_ports);
+        assert!(Config::new(
+            ["/", "--allow-source-port", "70000"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_debug_capture_size() {
+        let config =
+            Config::new(["/", "--debug-capture", "16"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.debug_capture, 16);
+        assert_eq!(Config::default().debug_capture, 256);
+    }
+
+    #[test]
+    fn parses_log_level() {
+        let config =
+            Config::new(["/", "--log-level", "trace"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.log_level, LogLevel::Trace);
+        assert_eq!(Config::default().log_level, LogLevel::Info);
+        assert!(Config::new(["/", "--log-level", "warn"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_quirks() {
+        let config = Config::new(
+            [
+                "/",
+                "--quirk",
+                "10.4.0.0/16=no-oack,loose-tid",
+                "--quirk",
+                "*.efi=max-blksize=1024",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        let quirks: Vec<_> = config.quirks.iter().map(Quirk::to_string).collect();
+        assert_eq!(
+            quirks,
+            ["10.4.0.0/16=no-oack,loose-tid", "*.efi=max-blksize=1024"]
+        );
+        assert!(Config::default().quirks.is_empty());
+        assert!(Config::new(
+            ["/", "--quirk", "*.efi=turbo"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_journey_window() {
+        let config = Config::new(
+            ["/", "--journey-window", "120"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.journey_window, Duration::from_secs(120));
+        assert_eq!(Config::default().journey_window, Duration::from_secs(30));
+        assert!(Config::new(["/", "--journey-window", "0"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_initial_delay() {
+        let config = Config::new(
+            [
+                "/",
+                "--initial-delay",
+                "50",
+                "--initial-delay-for",
+                "10.0.0.0/8=200",
+                "--initial-delay-for",
+                "fd00::5=0",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.initial_delay, Duration::from_millis(50));
+        assert_eq!(
+            config.initial_delay_for,
+            vec![
+                ("10.0.0.0/8".parse().unwrap(), Duration::from_millis(200)),
+                ("fd00::5/128".parse().unwrap(), Duration::ZERO),
+            ]
+        );
+        assert_eq!(Config::default().initial_delay, Duration::ZERO);
+        for invalid in ["10.0.0.0/8", "10.0.0.0/40=5", "10.0.0.0/8=soon"] {
+            assert!(Config::new(
+                ["/", "--initial-delay-for", invalid]
+                    .iter()
+                    .map(|s| s.to_string())
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn parses_self_check() {
+        let config = Config::new(["/", "--self-check"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.self_check);
+        assert!(!Config::default().self_check);
+    }
+
+    #[test]
+    fn parses_auto_shrink_blksize() {
+        let config =
+            Config::new(["/", "--auto-shrink-blksize"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.auto_shrink_blksize);
+        assert!(!Config::default().auto_shrink_blksize);
+    }
+
+    #[test]
+    fn parses_skip_holes() {
+        let config = Config::new(["/", "--skip-holes"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.skip_holes);
+        assert!(!Config::default().skip_holes);
+    }
+
+    #[test]
+    fn parses_slow_storage_warn() {
+        let config = Config::new(
+            ["/", "--slow-storage-warn", "20"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.slow_storage_warn, Some(Duration::from_millis(20)));
+        assert_eq!(Config::default().slow_storage_warn, None);
+        assert!(Config::new(
+            ["/", "--slow-storage-warn", "x"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_require_fast_storage() {
+        let config = Config::new(
+            ["/", "--require-fast-storage", "50"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.require_fast_storage, Some(Duration::from_millis(50)));
+        assert_eq!(Config::default().require_fast_storage, None);
+        assert!(Config::new(
+            ["/", "--require-fast-storage"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parsed_config_equals_built_config() {
+        let parsed = Config::new(
+            ["/", "-p", "6969", "-d", "/", "--listing-file", ".dirlist"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+        let built = Config {
+            port: 6969,
+            directory: PathBuf::from("/"),
+            listing_file: Some(".dirlist".to_string()),
+            ..Config::default()
+        };
+
+        assert_eq!(parsed, built);
+        assert_eq!(parsed.clone(), built);
+        assert_ne!(parsed, Config::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_serde() {
+        let config = Config::new(
+            ["/", "-i", "0.0.0.0", "-d", "/", "--beneath"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+
+        let partial: Config = serde_json::from_str(r#"{"port": 6969}"#).unwrap();
+        assert_eq!(
+            partial,
+            Config {
+                port: 6969,
+                ..Config::default()
+            }
+        );
     }
 
     #[test]
     fn returns_error_on_invalid_ip() {
         assert!(Config::new(
-            vec!["/", "-i", "1234.5678.9012.3456"]
+            ["/", "-i", "1234.5678.9012.3456"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -134,13 +2204,13 @@ mod tests {
 
     #[test]
     fn returns_error_on_invalid_port() {
-        assert!(Config::new(vec!["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
+        assert!(Config::new(["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
     }
 
     #[test]
     fn returns_error_on_invalid_directory() {
         assert!(Config::new(
-            vec!["/", "-d", "/this/does/not/exist"]
+            ["/", "-d", "/this/does/not/exist"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -0,0 +1,99 @@
+use std::{error::Error, fmt, io, net::AddrParseError, num::ParseIntError};
+
+/// TftpError `enum` represents the failures that prevent the server from
+/// starting or from continuing to serve.
+///
+/// Each variant maps to a distinct process exit code, see
+/// [`TftpError::exit_code()`].
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TftpError {
+    /// Invalid command line arguments or configuration
+    Argument(String),
+    /// The served directory is missing or unusable
+    Directory(String),
+    /// The server socket could not be bound
+    Bind(io::Error),
+    /// Unrecoverable error while serving
+    Io(io::Error),
+}
+
+impl TftpError {
+    /// Returns the process exit code for the error: `2` for invalid
+    /// arguments, `3` for bind or permission failures, `4` for directory
+    /// problems and `1` for runtime failures.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TftpError::Argument(_) => 2,
+            TftpError::Bind(_) => 3,
+            TftpError::Directory(_) => 4,
+            TftpError::Io(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for TftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TftpError::Argument(msg) => write!(f, "{msg}"),
+            TftpError::Directory(msg) => write!(f, "{msg}"),
+            TftpError::Bind(err) => write!(f, "{err}"),
+            TftpError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for TftpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TftpError::Bind(err) | TftpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for TftpError {
+    fn from(msg: &str) -> Self {
+        TftpError::Argument(msg.to_string())
+    }
+}
+
+impl From<String> for TftpError {
+    fn from(msg: String) -> Self {
+        TftpError::Argument(msg)
+    }
+}
+
+impl From<ParseIntError> for TftpError {
+    fn from(err: ParseIntError) -> Self {
+        TftpError::Argument(err.to_string())
+    }
+}
+
+impl From<AddrParseError> for TftpError {
+    fn from(err: AddrParseError) -> Self {
+        TftpError::Argument(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_exit_codes() {
+        assert_eq!(TftpError::from("bad flag").exit_code(), 2);
+        assert_eq!(
+            TftpError::Bind(io::Error::from(io::ErrorKind::PermissionDenied)).exit_code(),
+            3
+        );
+        assert_eq!(TftpError::Directory("gone".to_string()).exit_code(), 4);
+        assert_eq!(
+            TftpError::Io(io::Error::from(io::ErrorKind::Other)).exit_code(),
+            1
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{Decision, Journey, NegotiatedOption};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval between two progress events of the same transfer.
+pub(crate) const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Observer `trait` receives [`TransferEvent`]s from the
+/// [`Server`](crate::Server).
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{Observer, TransferEvent};
+///
+/// struct PrintObserver;
+///
+/// impl Observer for PrintObserver {
+///     fn on_event(&self, event: &TransferEvent) {
+///         if let TransferEvent::Progress { client, progress, .. } = event {
+///             println!("{client}: {} bytes, ETA {:?}", progress.bytes_acked, progress.eta);
+///         }
+///     }
+/// }
+/// ```
+pub trait Observer: Send + Sync {
+    /// Called for every event, on the thread running the server.
+    fn on_event(&self, event: &TransferEvent);
+}
+
+/// TransferEvent `enum` represents the notable moments of a transfer.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum TransferEvent {
+    /// A read request was accepted
+    Started {
+        /// Address of the client
+        client: SocketAddr,
+        /// Path of the served file
+        file: PathBuf,
+        /// ID of the [`Journey`] of the client host
+        journey: u64,
+    },
+    /// A read request was refused by the manifest or the
+    /// [`Authorizer`](crate::Authorizer)
+    Denied {
+        /// Address of the client
+        client: SocketAddr,
+        /// Requested file, relative to the served directory
+        filename: String,
+        /// Decision of the authorizer, never [`Decision::Allow`]
+        decision: Decision,
+    },
+    /// Periodic progress, emitted at most once per second per transfer
+    Progress {
+        /// Address of the client
+        client: SocketAddr,
+        /// Path of the served file
+        file: PathBuf,
+        /// Progress of the transfer
+        progress: TransferProgress,
+    },
+    /// The last block was acknowledged
+    Completed {
+        /// Address of the client
+        client: SocketAddr,
+        /// Path of the served file
+        file: PathBuf,
+        /// Number of bytes acknowledged by the client
+        bytes: u64,
+        /// Time since the read request was accepted
+        duration: Duration,
+        /// Options requested by the client and acknowledged by the server
+        options: Vec<NegotiatedOption>,
+        /// Apparent size of the served file, if known
+        size: Option<u64>,
+        /// Size allocated on disk, when the served file is sparse
+        allocated: Option<u64>,
+        /// Time spent in filesystem calls: looking the file up, opening it
+        /// and reading it
+        storage_time: Duration,
+        /// Time spent waiting for the client to acknowledge what was sent
+        network_wait: Duration,
+        /// ID of the [`Journey`] of the client host
+        journey: u64,
+    },
+    /// The transfer was aborted
+    Failed {
+        /// Address of the client
+        client: SocketAddr,
+        /// Path of the served file
+        file: PathBuf,
+        /// Number of bytes acknowledged by the client
+        bytes: u64,
+        /// Time since the read request was accepted
+        duration: Duration,
+        /// Options requested by the client and acknowledged by the server
+        options: Vec<NegotiatedOption>,
+        /// Reason of the failure
+        reason: String,
+        /// ID of the [`Journey`] of the client host
+        journey: u64,
+    },
+    /// A client host made no transfer for the `--journey-window`, or is
+    /// starting a new journey
+    JourneyEnded {
+        /// Summary of the transfers of the journey
+        journey: Journey,
+    },
+}
+
+/// TransferProgress `struct` describes how far along a transfer is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferProgress {
+    /// Number of bytes acknowledged by the client
+    pub bytes_acked: u64,
+    /// Total size of the transfer, if known
+    pub tsize: Option<u64>,
+    /// Size allocated on disk, when the served file is sparse, so that
+    /// most of `tsize` is holes sent without reading the disk
+    pub allocated: Option<u64>,
+    /// Throughput in bytes per second since the previous progress event
+    pub throughput: f64,
+    /// Throughput in bytes per second since the start of the transfer
+    pub average_throughput: f64,
+    /// Number of windows sent again without progress from the client
+    pub retransmits: u64,
+    /// Estimated time until completion, if the total size is known
+    pub eta: Option<Duration>,
+}
+
+/// Keeps the samples needed to compute [`TransferProgress`].
+#[derive(Debug)]
+pub(crate) struct ProgressTracker {
+    started: Instant,
+    last_at: Instant,
+    last_bytes: u64,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(now: Instant) -> ProgressTracker {
+        ProgressTracker {
+            started: now,
+            last_at: now,
+            last_bytes: 0,
+        }
+    }
+
+    /// Returns the time since the transfer started.
+    pub(crate) fn elapsed(&self, now: Instant) -> Duration {
+        now.duration_since(self.started)
+    }
+
+    /// Returns when the next progress event is due.
+    pub(crate) fn next_due(&self) -> Instant {
+        self.last_at + PROGRESS_INTERVAL
+    }
+
+    /// Returns whether a progress event is due.
+    pub(crate) fn due(&self, now: Instant) -> bool {
+        now.duration_since(self.last_at) >= PROGRESS_INTERVAL
+    }
+
+    /// Computes the progress at `now` and starts a new sampling interval.
+    pub(crate) fn sample(
+        &mut self,
+        now: Instant,
+        bytes_acked: u64,
+        tsize: Option<u64>,
+        allocated: Option<u64>,
+        retransmits: u64,
+    ) -> TransferProgress {
+        let throughput = rate(
+            bytes_acked.saturating_sub(self.last_bytes),
+            now.duration_since(self.last_at),
+        );
+        let average_throughput = rate(bytes_acked, now.duration_since(self.started));
+        let eta = tsize.and_then(|tsize| {
+            let remaining = tsize.saturating_sub(bytes_acked);
+            if remaining == 0 {
+                Some(Duration::ZERO)
+            } else if average_throughput > 0.0 {
+                Some(Duration::from_secs_f64(
+                    remaining as f64 / average_throughput,
+                ))
+            } else {
+                None
+            }
+        });
+
+        self.last_at = now;
+        self.last_bytes = bytes_acked;
+
+        TransferProgress {
+            bytes_acked,
+            tsize,
+            allocated,
+            throughput,
+            average_throughput,
+            retransmits,
+            eta,
+        }
+    }
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        bytes as f64 / elapsed.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    #[test]
+    fn computes_throughput_and_eta() {
+        let clock = MockClock::new();
+        let mut tracker = ProgressTracker::new(clock.now());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(tracker.due(clock.now()));
+        let progress = tracker.sample(clock.now(), 1000, Some(10000), None, 0);
+        assert_eq!(progress.throughput, 1000.0);
+        assert_eq!(progress.average_throughput, 1000.0);
+        assert_eq!(progress.eta, Some(Duration::from_secs(9)));
+        assert!(!tracker.due(clock.now()));
+
+        clock.advance(Duration::from_secs(1));
+        let progress = tracker.sample(clock.now(), 4000, Some(10000), None, 2);
+        assert_eq!(progress.throughput, 3000.0);
+        assert_eq!(progress.average_throughput, 2000.0);
+        assert_eq!(progress.retransmits, 2);
+        assert_eq!(progress.eta, Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn stalled_transfer_reports_zero_throughput() {
+        let clock = MockClock::new();
+        let mut tracker = ProgressTracker::new(clock.now());
+
+        clock.advance(Duration::from_secs(2));
+        let progress = tracker.sample(clock.now(), 0, Some(10000), None, 1);
+        assert_eq!(progress.throughput, 0.0);
+        assert_eq!(progress.eta, None);
+    }
+
+    #[test]
+    fn unknown_tsize_has_no_eta() {
+        let clock = MockClock::new();
+        let mut tracker = ProgressTracker::new(clock.now());
+
+        clock.advance(Duration::from_millis(500));
+        let progress = tracker.sample(clock.now(), 5000, None, None, 0);
+        assert_eq!(progress.throughput, 10000.0);
+        assert_eq!(progress.eta, None);
+    }
+
+    #[test]
+    fn finished_transfer_has_zero_eta() {
+        let clock = MockClock::new();
+        let mut tracker = ProgressTracker::new(clock.now());
+
+        clock.advance(Duration::from_secs(1));
+        let progress = tracker.sample(clock.now(), 10000, Some(10000), None, 0);
+        assert_eq!(progress.eta, Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,102 @@
+#[cfg(feature = "gzip")]
+use std::io::{BufReader, Seek, SeekFrom};
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+/// Decompressed content of a file and its size, if known.
+pub(crate) type Decompressed = (Box<dyn Read + Send>, Option<u64>);
+
+/// Opens the gzip compressed `file` for reading its decompressed content,
+/// which is decompressed as it is read.
+///
+/// Also returns the decompressed size from the `ISIZE` footer, unless the
+/// file is too large for it to be reliable.
+#[cfg(feature = "gzip")]
+pub(crate) fn decompress(mut file: File) -> io::Result<Decompressed> {
+    let size = footer_size(&mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok((
+        Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+        size,
+    ))
+}
+
+/// Compressed fallbacks are only available with the `gzip` feature.
+#[cfg(not(feature = "gzip"))]
+pub(crate) fn decompress(_file: File) -> io::Result<Decompressed> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without gzip support",
+    ))
+}
+
+/// Largest ratio between the decompressed and compressed sizes of deflate.
+#[cfg(feature = "gzip")]
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// Reads `ISIZE`, the decompressed size modulo 2^32 stored in the last four
+/// bytes of a gzip file.
+#[cfg(feature = "gzip")]
+fn footer_size(file: &mut File) -> io::Result<Option<u64>> {
+    // Smallest gzip member: 10 bytes header, 2 bytes deflate and 8 bytes
+    // footer. Larger files may decompress to 4 GiB or more, which `ISIZE`
+    // cannot tell apart from its remainder.
+    let len = file.metadata()?.len();
+    if !(20..=u32::MAX as u64 / MAX_DEFLATE_RATIO).contains(&len) {
+        return Ok(None);
+    }
+
+    let mut footer = [0; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut footer)?;
+    Ok(Some(u32::from_le_bytes(footer) as u64))
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn decompresses_with_footer_size() {
+        let contents: Vec<u8> = (0..5000).map(|i| (i % 7) as u8).collect();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&contents).unwrap();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&encoder.finish().unwrap()).unwrap();
+
+        let (mut source, size) = decompress(file).unwrap();
+        let mut decompressed = vec![];
+        source.read_to_end(&mut decompressed).unwrap();
+
+        assert_eq!(size, Some(5000));
+        assert_eq!(decompressed, contents);
+    }
+
+    #[test]
+    fn truncated_file_has_no_size() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0x1f, 0x8b, 8]).unwrap();
+
+        let (_, size) = decompress(file).unwrap();
+
+        assert_eq!(size, None);
+    }
+
+    #[test]
+    fn file_that_may_exceed_4_gib_has_no_size() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(u32::MAX as u64 / MAX_DEFLATE_RATIO + 1)
+            .unwrap();
+
+        let (_, size) = decompress(file).unwrap();
+
+        assert_eq!(size, None);
+    }
+}
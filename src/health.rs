@@ -0,0 +1,65 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{ErrorCode, Message, Packet};
+
+/// ERROR message answering the read requests received while the served
+/// directory is unavailable.
+pub(crate) const STORAGE_UNAVAILABLE: &str = "storage temporarily unavailable";
+
+/// File requested by [`check_health()`], any answer but
+/// [`Health::StorageUnavailable`] means the server is serving.
+const PROBE_FILENAME: &str = ".healthcheck";
+
+/// Health `enum` is the state of a running server found by
+/// [`check_health()`].
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Health {
+    /// The server answers read requests
+    Serving,
+    /// The server refuses read requests until its directory returns
+    StorageUnavailable,
+}
+
+/// Sends a read request to the server at `server` and tells from its answer
+/// whether it is serving. Fails when the server does not answer within
+/// `timeout`.
+///
+/// A transfer started by the request is cancelled with an ERROR.
+pub fn check_health(server: SocketAddr, timeout: Duration) -> io::Result<Health> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let request = Packet::Rrq {
+        filename: PROBE_FILENAME.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    socket.send_to(&request.serialize().map_err(io::Error::other)?, server)?;
+
+    let mut buf = [0; 65536];
+    let (size, from) = socket.recv_from(&mut buf)?;
+    match Packet::deserialize(&buf[..size]) {
+        Ok(Packet::Error { msg, .. }) if msg == STORAGE_UNAVAILABLE => {
+            Ok(Health::StorageUnavailable)
+        }
+        Ok(Packet::Error { .. }) => Ok(Health::Serving),
+        Ok(_) => {
+            let _ = Message::send_error(&socket, &from, ErrorCode::NotDefined, "health check done");
+            Ok(Health::Serving)
+        }
+        Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    process::Command,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::logger::{elogln, logln};
+use crate::{Observer, TransferEvent};
+
+/// Maximum number of hook commands running at the same time.
+const MAX_RUNNING: usize = 4;
+/// Maximum number of hook commands waiting for a free executor.
+const MAX_QUEUED: usize = 64;
+
+/// Hooks `struct` runs the `--exec-on-complete` and `--exec-on-fail`
+/// commands when a transfer ends.
+///
+/// Commands are run with `sh -c` by a few executor threads, so they never
+/// block the server. The transfer is described by the `TFTP_CLIENT`,
+/// `TFTP_FILE`, `TFTP_BYTES`, `TFTP_DURATION_MS`, `TFTP_RESULT` and
+/// `TFTP_JOURNEY` environment variables.
+pub(crate) struct Hooks {
+    on_complete: Option<String>,
+    on_fail: Option<String>,
+    jobs: SyncSender<Job>,
+}
+
+struct Job {
+    command: String,
+    env: Vec<(&'static str, String)>,
+}
+
+impl Hooks {
+    /// Starts the executor threads.
+    pub(crate) fn new(on_complete: Option<String>, on_fail: Option<String>) -> Hooks {
+        let (jobs, receiver) = mpsc::sync_channel(MAX_QUEUED);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..MAX_RUNNING {
+            let receiver = receiver.clone();
+            thread::spawn(move || execute(&receiver));
+        }
+
+        Hooks {
+            on_complete,
+            on_fail,
+            jobs,
+        }
+    }
+}
+
+impl Observer for Hooks {
+    fn on_event(&self, event: &TransferEvent) {
+        let (command, client, file, bytes, duration, journey, result) = match event {
+            TransferEvent::Completed {
+                client,
+                file,
+                bytes,
+                duration,
+                journey,
+                ..
+            } => (
+                &self.on_complete,
+                client,
+                file,
+                bytes,
+                duration,
+                journey,
+                "complete",
+            ),
+            TransferEvent::Failed {
+                client,
+                file,
+                bytes,
+                duration,
+                journey,
+                ..
+            } => (
+                &self.on_fail,
+                client,
+                file,
+                bytes,
+                duration,
+                journey,
+                "failed",
+            ),
+            _ => return,
+        };
+        let Some(command) = command else {
+            return;
+        };
+
+        let job = Job {
+            command: command.clone(),
+            env: vec![
+                ("TFTP_CLIENT", client.to_string()),
+                ("TFTP_FILE", file.display().to_string()),
+                ("TFTP_BYTES", bytes.to_string()),
+                ("TFTP_DURATION_MS", duration.as_millis().to_string()),
+                ("TFTP_RESULT", result.to_string()),
+                ("TFTP_JOURNEY", journey.to_string()),
+            ],
+        };
+        match self.jobs.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                elogln!("{client}: Too many hook commands queued, skipped {command}")
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                elogln!("{client}: Hook executor stopped, skipped {command}")
+            }
+        }
+    }
+}
+
+fn execute(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let Ok(job) = receiver.lock().unwrap().recv() else {
+            return;
+        };
+
+        match Command::new("sh")
+            .arg("-c")
+            .arg(&job.command)
+            .envs(job.env.iter().map(|(key, value)| (key, value)))
+            .output()
+        {
+            Ok(output) => {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    logln!("[hook] {line}");
+                }
+                for line in String::from_utf8_lossy(&output.stderr).lines() {
+                    elogln!("[hook] {line}");
+                }
+                if !output.status.success() {
+                    elogln!("Hook {} exited with {}", job.command, output.status);
+                }
+            }
+            Err(err) => elogln!("Cannot run hook {}: {err}", job.command),
+        }
+    }
+}
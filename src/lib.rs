@@ -0,0 +1,274 @@
+#![warn(missing_docs)]
+
+//! A transmit-only, singlethreaded, single-port with no server-side dynamic ports, TFTP server.
+//!
+//! # Features
+//!
+//! - `core`: the [`Packet`] parser, option negotiation, the sans-IO
+//!   [`Session`] state machine, [`Convert`], [`TftpError`] and
+//!   [`build_info()`], without dependencies.
+//! - `server`: the [`Server`] and its [`Message`] building block, a
+//!   [`Client`] and [`send_file()`](crate::send_file).
+//! - `metrics`: sending the server counters to a statsd agent.
+//! - `cli-min`: a minimal `tftpd` binary, accepting only the flags of
+//!   [`Config::minimal()`](crate::Config::minimal). Together with the
+//!   `minimal` profile it makes the smallest build, for initramfs images:
+//!   `cargo build --profile minimal --no-default-features --features cli-min`.
+//! - `cli`: signal handling and the full `tftpd` binary, with its
+//!   `replay`, `healthcheck` and `bench` subcommands.
+//! - `gzip`, `serde`: see [`Config`].
+//! - `mmap`: sending plain files from a memory mapping on Linux, instead
+//!   of reading them. A file truncated while it is sent crashes the
+//!   server with `SIGBUS`.
+//! - `test-util`: the `test_util` module, scaffolding for testing code
+//!   that embeds the server.
+//!
+//! The `cli` and `metrics` features are enabled by default.
+//!
+//! The items most embedders need are gathered in the [`prelude`]:
+//!
+//! ```
+//! use tftpd::prelude::*;
+//! ```
+
+#[cfg(feature = "server")]
+mod authorize;
+#[cfg(feature = "server")]
+mod bench;
+#[cfg(feature = "server")]
+mod beneath;
+#[cfg(feature = "server")]
+mod blackholes;
+mod build_info;
+#[cfg(feature = "server")]
+mod capture;
+mod checksum;
+#[cfg(feature = "server")]
+mod cidr;
+#[cfg(feature = "server")]
+mod client;
+#[cfg(feature = "server")]
+mod clients;
+#[cfg(feature = "server")]
+mod clock;
+#[cfg(feature = "server")]
+mod config;
+mod convert;
+mod error;
+#[cfg(feature = "server")]
+mod event;
+#[cfg(feature = "server")]
+mod gzip;
+#[cfg(feature = "server")]
+mod health;
+#[cfg(feature = "server")]
+mod hooks;
+#[cfg(feature = "server")]
+mod listeners;
+#[cfg(feature = "server")]
+mod listing;
+#[cfg(feature = "server")]
+mod logger;
+#[cfg(feature = "server")]
+mod manifest;
+#[cfg(feature = "server")]
+mod menu;
+#[cfg(feature = "server")]
+mod message;
+#[cfg(feature = "server")]
+mod metrics;
+#[cfg(feature = "server")]
+mod metrics_file;
+#[cfg(feature = "server")]
+mod missing;
+mod negotiation;
+#[cfg(feature = "server")]
+mod netascii;
+mod packet;
+#[cfg(feature = "server")]
+mod peaks;
+#[cfg(feature = "server")]
+mod percent;
+#[cfg(feature = "server")]
+mod pipe;
+#[cfg(all(feature = "server", target_os = "linux"))]
+mod pktinfo;
+#[cfg(feature = "server")]
+mod preflight;
+pub mod prelude;
+#[cfg(feature = "server")]
+mod quirks;
+#[cfg(feature = "server")]
+mod quota;
+#[cfg(feature = "server")]
+mod readers;
+#[cfg(feature = "server")]
+mod record;
+#[cfg(feature = "server")]
+mod replay;
+#[cfg(feature = "server")]
+mod server;
+mod session;
+#[cfg(feature = "cli")]
+mod signal;
+#[cfg(feature = "server")]
+mod socket;
+#[cfg(feature = "server")]
+mod sparse;
+#[cfg(feature = "server")]
+mod state;
+#[cfg(feature = "server")]
+mod stats;
+#[cfg(feature = "metrics")]
+mod statsd;
+#[cfg(feature = "server")]
+mod storage;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "server")]
+mod timers;
+#[cfg(feature = "server")]
+mod tombstones;
+#[cfg(feature = "server")]
+mod transfer;
+#[cfg(feature = "server")]
+mod upload;
+#[cfg(feature = "server")]
+mod watchdog;
+#[cfg(feature = "server")]
+mod worker;
+
+#[cfg(feature = "server")]
+pub use authorize::AllOf;
+#[cfg(feature = "server")]
+pub use authorize::Authorizer;
+#[cfg(feature = "server")]
+pub use authorize::Decision;
+#[cfg(feature = "server")]
+pub use authorize::RequestInfo;
+#[cfg(feature = "server")]
+pub use bench::bench;
+#[cfg(feature = "server")]
+pub use bench::BenchOptions;
+#[cfg(feature = "server")]
+pub use bench::BenchReport;
+pub use build_info::build_info;
+pub use build_info::BuildInfo;
+#[cfg(feature = "server")]
+pub use capture::CaptureReason;
+#[cfg(feature = "server")]
+pub use capture::CapturedPacket;
+#[cfg(feature = "server")]
+pub use capture::CAPTURED_BYTES;
+pub use checksum::Checksum;
+pub use checksum::ChecksumAlgorithm;
+#[cfg(feature = "server")]
+pub use cidr::Cidr;
+#[cfg(feature = "server")]
+pub use client::Client;
+#[cfg(feature = "server")]
+pub use client::Download;
+#[cfg(feature = "server")]
+pub use clients::ClientSessions;
+#[cfg(feature = "server")]
+pub use clients::Journey;
+#[cfg(feature = "server")]
+pub use clock::Clock;
+#[cfg(feature = "server")]
+pub use clock::MockClock;
+#[cfg(feature = "server")]
+pub use clock::SystemClock;
+#[cfg(feature = "server")]
+pub use config::BroadcastPolicy;
+#[cfg(feature = "server")]
+pub use config::BusyStrategy;
+#[cfg(feature = "server")]
+pub use config::Config;
+#[cfg(feature = "server")]
+pub use config::ConflictPolicy;
+#[cfg(feature = "server")]
+pub use config::LogLevel;
+pub use convert::Convert;
+pub use error::TftpError;
+#[cfg(feature = "server")]
+pub use event::Observer;
+#[cfg(feature = "server")]
+pub use event::TransferEvent;
+#[cfg(feature = "server")]
+pub use event::TransferProgress;
+#[cfg(feature = "server")]
+pub use health::check_health;
+#[cfg(feature = "server")]
+pub use health::Health;
+#[cfg(feature = "server")]
+pub use logger::flush_logs;
+#[cfg(feature = "server")]
+pub use logger::set_log_level;
+#[cfg(feature = "server")]
+pub use logger::start_logger;
+#[cfg(feature = "server")]
+pub use message::Message;
+#[cfg(feature = "server")]
+pub use metrics::MetricsSnapshot;
+#[cfg(feature = "server")]
+pub use missing::MissingFile;
+pub use negotiation::negotiated;
+pub use negotiation::DuplicatePolicy;
+pub use negotiation::NegotiatedOption;
+pub use negotiation::NegotiationNote;
+pub use negotiation::OptionLimits;
+pub use negotiation::OptionOutcome;
+pub use negotiation::TsizeMode;
+pub use negotiation::MAX_WINDOWSIZE;
+pub use negotiation::OACK_ORDER;
+pub use packet::ErrorCode;
+pub use packet::Opcode;
+pub use packet::OptionType;
+pub use packet::Packet;
+pub use packet::TransferOption;
+#[cfg(feature = "server")]
+pub use preflight::ConfigWarning;
+#[cfg(feature = "server")]
+pub use quirks::Quirk;
+#[cfg(feature = "server")]
+pub use quirks::QuirkMatcher;
+#[cfg(feature = "server")]
+pub use quirks::QuirkSet;
+#[cfg(feature = "server")]
+pub use quota::Quota;
+#[cfg(feature = "server")]
+pub use quota::QuotaUsage;
+#[cfg(feature = "server")]
+pub use record::Datagram;
+#[cfg(feature = "server")]
+pub use record::Direction;
+#[cfg(feature = "server")]
+pub use record::Recording;
+#[cfg(feature = "server")]
+pub use replay::Divergence;
+#[cfg(feature = "server")]
+pub use server::Server;
+pub use session::Session;
+pub use session::SessionAction;
+pub use session::SessionEvent;
+pub use session::SessionOptions;
+#[cfg(feature = "server")]
+pub use socket::FaultySocket;
+#[cfg(feature = "server")]
+pub use socket::Socket;
+#[cfg(feature = "server")]
+pub use state::StateOptions;
+#[cfg(feature = "server")]
+pub use stats::FileStats;
+#[cfg(feature = "server")]
+pub use storage::StorageProbe;
+#[cfg(feature = "server")]
+pub use watchdog::Stall;
+#[cfg(feature = "server")]
+pub use worker::send_file;
+#[cfg(feature = "server")]
+pub use worker::TransferHandle;
+#[cfg(feature = "server")]
+pub use worker::TransferSummary;
+#[cfg(feature = "server")]
+pub use worker::Worker;
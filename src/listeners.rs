@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::logger::{elogln, logln};
+use crate::{Opcode, Socket};
+
+/// How often the receiving threads check whether the set was dropped.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Maximum number of clients whose listener is remembered, the map is
+/// cleared when it is full.
+const MAX_REMEMBERED_CLIENTS: usize = 65536;
+
+/// A datagram received by one of the listeners.
+struct Received {
+    buf: Vec<u8>,
+    from: SocketAddr,
+    listener: usize,
+}
+
+/// A bound socket of the set and the state of its receiving thread.
+struct Listener {
+    /// `None` once the listener is closed
+    socket: Option<UdpSocket>,
+    /// Whether requests received on the listener are ignored, until its
+    /// sessions end and it is closed
+    draining: bool,
+    /// Stops the receiving thread of the listener
+    closed: Arc<AtomicBool>,
+}
+
+impl Listener {
+    fn addr(&self) -> Option<SocketAddr> {
+        self.socket.as_ref()?.local_addr().ok()
+    }
+}
+
+/// ListenerSet `struct` serves several bound [`UdpSocket`]s as a single
+/// [`Socket`].
+///
+/// Every socket is read by its own thread, which hands the datagrams over to
+/// the server. Replies to a client are sent from the socket that last
+/// received a datagram from it, so that the client sees them come from the
+/// address it sent its request to.
+///
+/// The primary listener can be [rebound](ListenerSet::rebind) to another
+/// address while serving. The previous one then drains: it keeps carrying
+/// the sessions started on it but ignores new requests, until it is
+/// [closed](ListenerSet::close_drained) once they ended.
+pub(crate) struct ListenerSet {
+    listeners: RwLock<Vec<Listener>>,
+    /// Index of the listener moved by a rebind, which also replies to the
+    /// clients whose listener is closed
+    primary: Mutex<usize>,
+    sender: Sender<Received>,
+    received: Mutex<Receiver<Received>>,
+    clients: Mutex<HashMap<SocketAddr, usize>>,
+    read_timeout: Mutex<Option<Duration>>,
+    nonblocking: AtomicBool,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ListenerSet {
+    pub(crate) fn bind(addresses: &[SocketAddr]) -> io::Result<ListenerSet> {
+        let (sender, receiver) = mpsc::channel();
+        let set = ListenerSet {
+            listeners: RwLock::new(vec![]),
+            primary: Mutex::new(0),
+            sender,
+            received: Mutex::new(receiver),
+            clients: Mutex::new(HashMap::new()),
+            read_timeout: Mutex::new(None),
+            nonblocking: AtomicBool::new(false),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        };
+        for address in addresses {
+            set.add(*address)?;
+        }
+        Ok(set)
+    }
+
+    /// Binds a new listener to `address` and starts receiving on it,
+    /// returning its index.
+    fn add(&self, address: SocketAddr) -> io::Result<usize> {
+        let socket = UdpSocket::bind(address)?;
+        let receiving = socket.try_clone()?;
+        receiving.set_read_timeout(Some(SHUTDOWN_CHECK_INTERVAL))?;
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let mut listeners = self.listeners.write().unwrap();
+        let listener = listeners.len();
+        let sender = self.sender.clone();
+        let shutdown = self.shutdown.clone();
+        let stopped = closed.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 65536];
+            while !shutdown.load(Ordering::Relaxed) && !stopped.load(Ordering::Relaxed) {
+                match receiving.recv_from(&mut buf) {
+                    Ok((size, from)) => {
+                        let received = Received {
+                            buf: buf[..size].to_vec(),
+                            from,
+                            listener,
+                        };
+                        if sender.send(received).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) => {}
+                    Err(err) => elogln!("Error while receiving: {err}"),
+                }
+            }
+        });
+        listeners.push(Listener {
+            socket: Some(socket),
+            draining: false,
+            closed,
+        });
+        Ok(listener)
+    }
+
+    /// Moves the primary listener to `ip`, on the same port, and drains the
+    /// previous one. Returns the previous and new addresses, `None` when the
+    /// primary listener is already on `ip`.
+    pub(crate) fn rebind(&self, ip: IpAddr) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+        let mut primary = self.primary.lock().unwrap();
+        let previous = self.listeners.read().unwrap()[*primary]
+            .addr()
+            .ok_or(io::ErrorKind::NotConnected)?;
+        if previous.ip() == ip {
+            return Ok(None);
+        }
+
+        let listener = self.add(SocketAddr::new(ip, previous.port()))?;
+        let mut listeners = self.listeners.write().unwrap();
+        listeners[*primary].draining = true;
+        *primary = listener;
+        let current = listeners[listener]
+            .addr()
+            .ok_or(io::ErrorKind::NotConnected)?;
+        Ok(Some((previous, current)))
+    }
+
+    /// Closes the draining listeners that no client with a session, as told
+    /// by `in_session`, last sent to, returning their addresses.
+    pub(crate) fn close_drained<F>(&self, in_session: F) -> Vec<SocketAddr>
+    where
+        F: Fn(&SocketAddr) -> bool,
+    {
+        let mut listeners = self.listeners.write().unwrap();
+        let draining: Vec<usize> = (0..listeners.len())
+            .filter(|&listener| {
+                listeners[listener].draining && listeners[listener].socket.is_some()
+            })
+            .collect();
+        if draining.is_empty() {
+            return vec![];
+        }
+
+        let clients = self.clients.lock().unwrap();
+        let mut closed = vec![];
+        for listener in draining {
+            let busy = clients
+                .iter()
+                .any(|(client, &last)| last == listener && in_session(client));
+            if !busy {
+                let listener = &mut listeners[listener];
+                closed.extend(listener.addr());
+                listener.closed.store(true, Ordering::Relaxed);
+                listener.socket = None;
+            }
+        }
+        closed
+    }
+
+    /// Returns the addresses of the open listeners, draining ones included.
+    pub(crate) fn addresses(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(Listener::addr)
+            .collect()
+    }
+
+    fn next(&self) -> io::Result<Received> {
+        loop {
+            let next = self.receive()?;
+            let request = [Opcode::Rrq.as_bytes(), Opcode::Wrq.as_bytes()]
+                .iter()
+                .any(|opcode| next.buf.get(..2) == Some(opcode));
+            let draining = {
+                let listeners = self.listeners.read().unwrap();
+                let listener = &listeners[next.listener];
+                listener.draining || listener.socket.is_none()
+            };
+            if request && draining {
+                logln!(
+                    "{}: Request ignored on draining listener {}",
+                    next.from,
+                    self.listener_addr(next.listener)
+                );
+                continue;
+            }
+
+            let mut clients = self.clients.lock().unwrap();
+            if clients.len() >= MAX_REMEMBERED_CLIENTS && !clients.contains_key(&next.from) {
+                clients.clear();
+            }
+            clients.insert(next.from, next.listener);
+            if next.buf.get(..2) == Some(&Opcode::Rrq.as_bytes()) {
+                logln!(
+                    "{}: Request received on listener {}",
+                    next.from,
+                    self.listener_addr(next.listener)
+                );
+            }
+            return Ok(next);
+        }
+    }
+
+    fn receive(&self) -> io::Result<Received> {
+        let received = self.received.lock().unwrap();
+        if self.nonblocking.load(Ordering::Relaxed) {
+            received.try_recv().map_err(|err| match err {
+                TryRecvError::Empty => io::ErrorKind::WouldBlock.into(),
+                TryRecvError::Disconnected => io::ErrorKind::BrokenPipe.into(),
+            })
+        } else {
+            let timeout = *self.read_timeout.lock().unwrap();
+            match timeout {
+                Some(timeout) => received.recv_timeout(timeout).map_err(|err| match err {
+                    RecvTimeoutError::Timeout => io::ErrorKind::WouldBlock.into(),
+                    RecvTimeoutError::Disconnected => io::ErrorKind::BrokenPipe.into(),
+                }),
+                None => received
+                    .recv()
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe)),
+            }
+        }
+    }
+
+    fn listener_addr(&self, listener: usize) -> String {
+        self.listeners.read().unwrap()[listener]
+            .addr()
+            .map_or_else(|| "unknown".to_string(), |addr| addr.to_string())
+    }
+}
+
+impl Drop for ListenerSet {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Socket for ListenerSet {
+    /// Sends from the listener the client last sent to, or from the primary
+    /// listener when that one is closed.
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        let last = self.clients.lock().unwrap().get(addr).copied();
+        let primary = *self.primary.lock().unwrap();
+        let listeners = self.listeners.read().unwrap();
+        let socket = last
+            .and_then(|listener| listeners[listener].socket.as_ref())
+            .or_else(|| listeners[primary].socket.as_ref())
+            .ok_or(io::ErrorKind::NotConnected)?;
+        socket.send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, from, _) = self.recv_with_destination(buf)?;
+        Ok((size, from))
+    }
+
+    /// Returns the address of the primary listener.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        let primary = *self.primary.lock().unwrap();
+        self.listeners.read().unwrap()[primary]
+            .addr()
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Destinations are the addresses of the listeners, which are always
+    /// known.
+    fn enable_destination(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let received = self.next()?;
+        let size = received.buf.len().min(buf.len());
+        buf[..size].copy_from_slice(&received.buf[..size]);
+        let destination = self.listeners.read().unwrap()[received.listener]
+            .addr()
+            .map(|addr| addr.ip())
+            .filter(|ip| !ip.is_unspecified());
+        Ok((size, received.from, destination))
+    }
+}
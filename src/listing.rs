@@ -0,0 +1,219 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// Listing `struct` generates the virtual directory listing file served
+/// for `--listing-file`.
+///
+/// Every line holds the path relative to the served directory, the size in
+/// bytes and the modification time in seconds since the Unix epoch,
+/// separated by tabs.
+#[derive(Debug)]
+pub(crate) struct Listing {
+    /// Requested filename that returns the listing
+    pub(crate) name: String,
+    /// Number of directory levels to descend into, 1 listing only the root
+    pub(crate) depth: usize,
+    /// Maximum size of the generated listing
+    pub(crate) max_bytes: usize,
+}
+
+impl Listing {
+    /// Generates the listing of `root`, only including the files for which
+    /// `servable` returns `true`. Lines that would exceed the size limit are
+    /// left out.
+    pub(crate) fn generate(
+        &self,
+        root: &Path,
+        servable: &dyn Fn(&Path) -> bool,
+    ) -> io::Result<Vec<u8>> {
+        let mut files = vec![];
+        collect_files(root, self.depth, &mut files)?;
+        files.sort();
+
+        let mut content = vec![];
+        for path in files {
+            if !servable(&path) {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|time| time.as_secs())
+                .unwrap_or(0);
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let line = format!("{relative}\t{}\t{mtime}\n", metadata.len());
+            if content.len() + line.len() > self.max_bytes {
+                break;
+            }
+            content.extend_from_slice(line.as_bytes());
+        }
+
+        Ok(content)
+    }
+}
+
+/// Collects the files of `dir` down to `depth` levels. Symlinked
+/// directories are not entered, so that the scan stays below `dir`, and
+/// entries or subdirectories that cannot be read are left out.
+fn collect_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if depth == 0 {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)?.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            let _ = collect_files(&path, depth - 1, files);
+        } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(depth: usize, max_bytes: usize) -> Listing {
+        Listing {
+            name: ".dirlist".to_string(),
+            depth,
+            max_bytes,
+        }
+    }
+
+    fn lines(content: &[u8]) -> Vec<(String, String)> {
+        String::from_utf8(content.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                assert_eq!(fields.len(), 3);
+                (fields[0].to_string(), fields[1].to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lists_files_with_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.bin"), [0; 10]).unwrap();
+        fs::write(dir.path().join("a.bin"), [0; 3]).unwrap();
+        fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+        fs::write(dir.path().join("sub/c.bin"), [0; 7]).unwrap();
+        fs::write(dir.path().join("sub/deeper/d.bin"), [0; 1]).unwrap();
+
+        let content = listing(2, 4096).generate(dir.path(), &|_| true).unwrap();
+
+        assert_eq!(
+            lines(&content),
+            vec![
+                ("a.bin".to_string(), "3".to_string()),
+                ("b.bin".to_string(), "10".to_string()),
+                ("sub/c.bin".to_string(), "7".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_enter_symlinked_directories() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.bin"), [0; 5]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.bin"), [0; 3]).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a.bin"), dir.path().join("b.bin")).unwrap();
+
+        let content = listing(4, 4096).generate(dir.path(), &|_| true).unwrap();
+
+        assert_eq!(
+            lines(&content),
+            vec![
+                ("a.bin".to_string(), "3".to_string()),
+                ("b.bin".to_string(), "3".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.bin"), [0; 3]).unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("c.bin"), [0; 7]).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root reads the directory anyway.
+        let readable = fs::read_dir(&locked).is_ok();
+        let content = listing(2, 4096).generate(dir.path(), &|_| true);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let lines = lines(&content.unwrap());
+        assert_eq!(lines[0], ("a.bin".to_string(), "3".to_string()));
+        assert_eq!(lines.len(), if readable { 2 } else { 1 });
+    }
+
+    #[test]
+    fn fails_on_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(listing(1, 4096)
+            .generate(&dir.path().join("missing"), &|_| true)
+            .is_err());
+    }
+
+    #[test]
+    fn caps_listing_size() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..100 {
+            fs::write(dir.path().join(format!("file{i:03}.bin")), [0; 1]).unwrap();
+        }
+
+        let content = listing(1, 200).generate(dir.path(), &|_| true).unwrap();
+
+        assert!(content.len() <= 200);
+        assert!(content.ends_with(b"\n"));
+        assert!(!lines(&content).is_empty());
+        assert!(lines(&content).len() < 100);
+    }
+
+    #[test]
+    fn omits_files_that_are_not_servable() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("public.bin"), [0; 1]).unwrap();
+        fs::write(dir.path().join("secret.key"), [0; 1]).unwrap();
+
+        let content = listing(1, 4096)
+            .generate(dir.path(), &|path| path.extension().unwrap() != "key")
+            .unwrap();
+
+        assert_eq!(
+            lines(&content),
+            vec![("public.bin".to_string(), "1".to_string())]
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::packet::Escaped;
+use crate::LogLevel;
+
+/// Maximum number of lines waiting for the writer thread, further lines are
+/// dropped rather than blocking the server.
+const MAX_QUEUED: usize = 4096;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Most detailed [`LogLevel`] written, as its discriminant.
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Stream `enum` selects where a line is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Out,
+    Err,
+}
+
+enum Record {
+    Line(Stream, String),
+    /// Answered once every line sent before was written
+    Flush(SyncSender<()>),
+}
+
+/// Logger `struct` writes the lines of every thread from a single writer
+/// thread, so that a line is always written whole, and never blocks the
+/// threads logging: when the writer cannot keep up, lines are dropped and
+/// counted.
+pub(crate) struct Logger {
+    records: SyncSender<Record>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Logger {
+    /// Starts the writer thread writing to `out` and `err`, with up to
+    /// `capacity` lines waiting. It stops once the logger is dropped.
+    pub(crate) fn spawn<O, E>(out: O, err: E, capacity: usize) -> Logger
+    where
+        O: Write + Send + 'static,
+        E: Write + Send + 'static,
+    {
+        let (records, receiver) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counted = dropped.clone();
+        thread::spawn(move || write_lines(&counted, &receiver, out, err));
+        Logger { records, dropped }
+    }
+
+    pub(crate) fn log(&self, stream: Stream, line: String) {
+        match self.records.try_send(Record::Line(stream, line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(Record::Line(stream, line))) => {
+                write_directly(stream, &line)
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// Waits until every line logged before was written.
+    pub(crate) fn flush(&self) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        if self.records.send(Record::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Waits up to `limit` until every line logged before was written,
+    /// returning whether they were. Gives up at once when the queue is
+    /// full, as when the writer is stuck on a blocked output.
+    pub(crate) fn flush_within(&self, limit: Duration) -> bool {
+        let (done, flushed) = mpsc::sync_channel(1);
+        self.records.try_send(Record::Flush(done)).is_ok() && flushed.recv_timeout(limit).is_ok()
+    }
+
+    /// Returns the number of lines dropped because too many were waiting.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Writes the lines received in batches, then notes how many were dropped
+/// since the previous batch.
+fn write_lines<O: Write, E: Write>(
+    dropped: &AtomicU64,
+    receiver: &Receiver<Record>,
+    out: O,
+    err: E,
+) {
+    let mut out = BufWriter::new(out);
+    let mut err = BufWriter::new(err);
+    let mut reported = 0;
+    while let Ok(first) = receiver.recv() {
+        let mut flushed = vec![];
+        for record in std::iter::once(first).chain(receiver.try_iter()) {
+            let _ = match record {
+                Record::Line(Stream::Out, line) => out.write_all(line.as_bytes()),
+                Record::Line(Stream::Err, line) => err.write_all(line.as_bytes()),
+                Record::Flush(done) => {
+                    flushed.push(done);
+                    Ok(())
+                }
+            };
+        }
+
+        let total = dropped.load(Ordering::Relaxed);
+        if total > reported {
+            let _ = writeln!(
+                err,
+                "Dropped {} log lines, the output could not keep up",
+                total - reported
+            );
+            reported = total;
+        }
+        let _ = out.flush();
+        let _ = err.flush();
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn write_directly(stream: Stream, line: &str) {
+    match stream {
+        Stream::Out => print!("{line}"),
+        Stream::Err => eprint!("{line}"),
+    }
+}
+
+/// Logs a line, through the writer thread once [`start_logger`] was called
+/// and directly otherwise.
+pub(crate) fn log(stream: Stream, args: fmt::Arguments) {
+    let line = format_line(args);
+    match LOGGER.get() {
+        Some(logger) => logger.log(stream, line),
+        None => write_directly(stream, &line),
+    }
+}
+
+/// Formats a line with its control characters escaped, so that the strings
+/// of clients logged in it cannot break it into several lines.
+fn format_line(args: fmt::Arguments) -> String {
+    let line = args.to_string();
+    if line.contains(char::is_control) {
+        format!("{}\n", Escaped::whole(&line))
+    } else {
+        line + "\n"
+    }
+}
+
+/// Logs a line to standard output, like `println!`.
+macro_rules! logln {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Stream::Out, format_args!($($arg)*))
+    };
+}
+
+/// Logs a line to standard output at [`LogLevel::Debug`], without even
+/// formatting it below.
+macro_rules! debuglog {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::LogLevel::Debug) {
+            $crate::logger::log($crate::logger::Stream::Out, format_args!($($arg)*))
+        }
+    };
+}
+
+/// Logs a line to standard output at [`LogLevel::Trace`], for every packet
+/// or block, without even formatting it below.
+macro_rules! tracelog {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::LogLevel::Trace) {
+            $crate::logger::log($crate::logger::Stream::Out, format_args!($($arg)*))
+        }
+    };
+}
+
+/// Logs a line to standard error, like `eprintln!`.
+macro_rules! elogln {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Stream::Err, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use debuglog;
+pub(crate) use elogln;
+pub(crate) use logln;
+pub(crate) use tracelog;
+
+/// Sets the most detailed lines logged by every server of the process,
+/// [`LogLevel::Info`] by default.
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns whether lines at `level` are logged.
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Routes the log lines of every thread of the server through a single
+/// writer thread, so that concurrent lines are never interleaved in the
+/// output and logging never blocks packet processing. Lines are dropped and
+/// counted in [`MetricsSnapshot::dropped_log_lines`](crate::MetricsSnapshot)
+/// when the output cannot keep up.
+///
+/// Without it, lines are written directly, where tests capture them. Call
+/// [`flush_logs`] before exiting the process.
+pub fn start_logger() {
+    LOGGER.get_or_init(|| Logger::spawn(io::stdout(), io::stderr(), MAX_QUEUED));
+}
+
+/// Waits until the lines logged so far were written, see [`start_logger`].
+pub fn flush_logs() {
+    if let Some(logger) = LOGGER.get() {
+        logger.flush();
+    }
+}
+
+/// Like [`flush_logs`], but waits at most `limit`, for a process about to
+/// abort whose output may be what got it stuck.
+pub(crate) fn flush_logs_within(limit: Duration) -> bool {
+    LOGGER.get().is_none_or(|logger| logger.flush_within(limit))
+}
+
+/// Returns the number of log lines dropped since [`start_logger`].
+pub(crate) fn dropped() -> u64 {
+    LOGGER.get().map_or(0, Logger::dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A sink shared with the test, taking at most 7 bytes per write so
+    /// that concurrent writers would tear lines.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(7);
+            self.0.lock().unwrap().extend_from_slice(&buf[..len]);
+            thread::yield_now();
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn lines(&self) -> Vec<String> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            output.lines().map(str::to_string).collect()
+        }
+    }
+
+    #[test]
+    fn enables_levels_up_to_the_set_one() {
+        assert!(enabled(LogLevel::Info));
+        assert!(!enabled(LogLevel::Trace));
+
+        set_log_level(LogLevel::Trace);
+        let trace = (enabled(LogLevel::Debug), enabled(LogLevel::Trace));
+        set_log_level(LogLevel::Info);
+
+        assert_eq!(trace, (true, true));
+    }
+
+    #[test]
+    fn escapes_control_characters_of_lines() {
+        let filename = "boot.img\n[forged] admin login";
+        assert_eq!(
+            format_line(format_args!("Serving {filename}")),
+            "Serving boot.img\\n[forged] admin login\n"
+        );
+        assert_eq!(format_line(format_args!("Sent {}", 3)), "Sent 3\n");
+    }
+
+    #[test]
+    fn writes_concurrent_lines_whole() {
+        let (out, err) = (Captured::default(), Captured::default());
+        let logger = Arc::new(Logger::spawn(out.clone(), err.clone(), 16 * 500));
+
+        let threads: Vec<_> = (0..16)
+            .map(|thread| {
+                let logger = logger.clone();
+                thread::spawn(move || {
+                    for line in 0..500 {
+                        let stream = if line % 3 == 0 {
+                            Stream::Err
+                        } else {
+                            Stream::Out
+                        };
+                        let padding = "x".repeat(line % 50);
+                        logger.log(stream, format!("thread {thread} line {line} {padding}\n"));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        logger.flush();
+
+        let mut lines = out.lines();
+        lines.extend(err.lines());
+        assert_eq!(lines.len(), 16 * 500);
+        for line in &lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let [_, _, _, number, padding] = fields[..] else {
+                panic!("torn line {line:?}");
+            };
+            assert_eq!(fields[0], "thread", "torn line {line:?}");
+            assert_eq!(fields[2], "line", "torn line {line:?}");
+            assert_eq!(padding, "x".repeat(number.parse::<usize>().unwrap() % 50));
+        }
+        assert_eq!(logger.dropped(), 0);
+    }
+
+    /// A sink announcing every write, then waiting for `gate`.
+    struct Stuck {
+        inner: Captured,
+        gate: Arc<Mutex<()>>,
+        writing: SyncSender<()>,
+    }
+
+    impl Write for Stuck {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.writing.try_send(());
+            let _gate = self.gate.lock().unwrap();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drops_lines_instead_of_blocking() {
+        let (out, err) = (Captured::default(), Captured::default());
+        let gate = Arc::new(Mutex::new(()));
+        let (writing, written) = mpsc::sync_channel(1);
+        let stuck = Stuck {
+            inner: out.clone(),
+            gate: gate.clone(),
+            writing,
+        };
+        let logger = Logger::spawn(stuck, err.clone(), 4);
+
+        // The writer is stuck on the first line while the others are logged.
+        let held = gate.lock().unwrap();
+        logger.log(Stream::Out, "line 0\n".to_string());
+        written.recv().unwrap();
+        for line in 1..=100 {
+            logger.log(Stream::Out, format!("line {line}\n"));
+        }
+        assert_eq!(logger.dropped(), 96);
+        drop(held);
+        logger.flush();
+
+        assert_eq!(
+            out.lines(),
+            ["line 0", "line 1", "line 2", "line 3", "line 4"]
+        );
+        assert_eq!(
+            err.lines(),
+            ["Dropped 96 log lines, the output could not keep up"]
+        );
+    }
+
+    #[test]
+    fn gives_up_flushing_stuck_writer() {
+        let gate = Arc::new(Mutex::new(()));
+        let (writing, written) = mpsc::sync_channel(1);
+        let stuck = Stuck {
+            inner: Captured::default(),
+            gate: gate.clone(),
+            writing,
+        };
+        let logger = Logger::spawn(stuck, Captured::default(), 4);
+
+        let held = gate.lock().unwrap();
+        logger.log(Stream::Out, "line 0\n".to_string());
+        written.recv().unwrap();
+        let started = std::time::Instant::now();
+        assert!(!logger.flush_within(Duration::from_millis(50)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // With the queue full, it does not even wait.
+        for line in 1..=10 {
+            logger.log(Stream::Out, format!("line {line}\n"));
+        }
+        assert!(!logger.flush_within(Duration::from_secs(60)));
+        drop(held);
+        logger.flush();
+        assert!(logger.flush_within(Duration::from_secs(5)));
+    }
+}
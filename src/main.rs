@@ -1,47 +1,272 @@
-#![warn(missing_docs)]
-
-//! A transmit-only, singlethreaded, single-port with no server-side dynamic ports, TFTP server.
-
-mod config;
-mod convert;
-mod message;
-mod packet;
-mod server;
-mod state;
-
-pub use config::Config;
-pub use convert::Convert;
-pub use message::Message;
-pub use packet::ErrorCode;
-pub use packet::Opcode;
-pub use packet::OptionType;
-pub use packet::Packet;
-pub use packet::TransferOption;
-pub use server::Server;
-pub use state::State;
-
 use std::{env, process};
+#[cfg(feature = "cli")]
+use std::{ffi::OsString, net::SocketAddr, path::PathBuf, time::Duration};
+#[cfg(feature = "cli")]
+use tftpd::{bench, check_health, BenchOptions, Health, Recording, TftpError};
+use tftpd::{build_info, flush_logs, set_log_level, start_logger, Config, Server};
 
 fn main() {
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
+    #[cfg(feature = "cli")]
+    run_subcommand();
+
+    #[cfg(feature = "cli")]
+    let config = Config::new(env::args_os());
+    #[cfg(not(feature = "cli"))]
+    let config = Config::minimal(env::args_os());
+    let config = config.unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1)
+        process::exit(err.exit_code())
     });
+    let warnings = config.validate().unwrap_or_else(|err| {
+        eprintln!("Problem with the configuration: {err}");
+        process::exit(err.exit_code())
+    });
+    for warning in &warnings {
+        eprintln!("Warning {warning}");
+    }
+    if config.dry_run {
+        println!("Configuration checked, {} warnings", warnings.len());
+        process::exit(0);
+    }
+
+    let first = match &config.bind_host {
+        Some(host) => host.clone(),
+        None => config.ip_address.to_string(),
+    };
+    let addresses = [first]
+        .into_iter()
+        .chain(
+            config
+                .additional_ip_addresses
+                .iter()
+                .map(|ip| ip.to_string()),
+        )
+        .map(|ip_address| format!("{ip_address}:{}", config.port))
+        .collect::<Vec<_>>()
+        .join(", ");
 
     let mut server = Server::new(&config).unwrap_or_else(|err| {
-        eprintln!(
-            "Problem creating server on {}:{}: {err}",
-            config.ip_address, config.port
-        );
-        process::exit(1)
+        eprintln!("Problem creating server on {addresses}: {err}");
+        process::exit(err.exit_code())
     });
 
+    println!("{}", build_info());
+    let addresses = server
+        .listen_addresses()
+        .iter()
+        .map(|address| address.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
     println!(
-        "Running TFTP Server on {}:{} in {}",
-        config.ip_address,
-        config.port,
+        "Running TFTP Server on {addresses} in {}",
         config.directory.display()
     );
+    if let Some(probe) = server.storage_probe() {
+        println!("Storage: {probe}");
+    }
+
+    set_log_level(config.log_level);
+    start_logger();
+    let stopped = server.listen();
+    flush_logs();
+    if let Err(err) = stopped {
+        eprintln!("Server stopped: {err}");
+        process::exit(err.exit_code());
+    }
+}
+
+/// Runs the subcommand named by the first argument, if any, and exits.
+#[cfg(feature = "cli")]
+fn run_subcommand() {
+    if env::args_os().nth(1).is_some_and(|arg| arg == "replay") {
+        replay(env::args_os().skip(2));
+    }
+    if env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "healthcheck")
+    {
+        healthcheck(env::args_os().skip(2));
+    }
+    if env::args_os().nth(1).is_some_and(|arg| arg == "bench") {
+        run_bench(env::args_os().skip(2));
+    }
+}
+
+/// Replays a recording made with `--record` and exits with 0 if the server
+/// answered as recorded, 1 otherwise.
+#[cfg(feature = "cli")]
+fn replay<T: Iterator<Item = OsString>>(args: T) -> ! {
+    let (path, server, no_delay) = parse_replay_args(args).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(err.exit_code())
+    });
+
+    let recording = Recording::load(&path).unwrap_or_else(|err| {
+        eprintln!("Problem loading {}: {err}", path.display());
+        process::exit(1)
+    });
+    let divergences = recording.replay(server, no_delay).unwrap_or_else(|err| {
+        eprintln!("Replay stopped: {err}");
+        process::exit(1)
+    });
+
+    for divergence in &divergences {
+        println!("{divergence}");
+    }
+    println!(
+        "Replayed {} datagrams, {} divergences",
+        recording.datagrams.len(),
+        divergences.len()
+    );
+    process::exit(if divergences.is_empty() { 0 } else { 1 })
+}
+
+#[cfg(feature = "cli")]
+fn parse_replay_args<T: Iterator<Item = OsString>>(
+    mut args: T,
+) -> Result<(PathBuf, SocketAddr, bool), TftpError> {
+    let mut path = None;
+    let mut server = SocketAddr::from(([127, 0, 0, 1], 69));
+    let mut no_delay = false;
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--server") => {
+                if let Some(server_str) = args.next() {
+                    server = server_str.to_string_lossy().parse()?;
+                } else {
+                    return Err("Missing server address after flag".into());
+                }
+            }
+            Some("--no-delay") => {
+                no_delay = true;
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Invalid flag: {}", arg.to_string_lossy()).into()),
+        }
+    }
+
+    let path = path.ok_or("Missing recording file")?;
+    Ok((path, server, no_delay))
+}
+
+/// Asks the server whether it is serving and exits with 0 if it is, 1 if it
+/// does not answer or its directory is unavailable.
+#[cfg(feature = "cli")]
+fn healthcheck<T: Iterator<Item = OsString>>(args: T) -> ! {
+    let (server, timeout) = parse_healthcheck_args(args).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(err.exit_code())
+    });
+
+    match check_health(server, timeout) {
+        Ok(Health::Serving) => {
+            println!("{server}: serving");
+            process::exit(0)
+        }
+        Ok(Health::StorageUnavailable) => {
+            println!("{server}: served directory unavailable");
+            process::exit(1)
+        }
+        Ok(health) => {
+            println!("{server}: {health:?}");
+            process::exit(1)
+        }
+        Err(err) => {
+            println!("{server}: no answer: {err}");
+            process::exit(1)
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+fn parse_healthcheck_args<T: Iterator<Item = OsString>>(
+    mut args: T,
+) -> Result<(SocketAddr, Duration), TftpError> {
+    let mut server = SocketAddr::from(([127, 0, 0, 1], 69));
+    let mut timeout = Duration::from_secs(2);
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--server") => {
+                if let Some(server_str) = args.next() {
+                    server = server_str.to_string_lossy().parse()?;
+                } else {
+                    return Err("Missing server address after flag".into());
+                }
+            }
+            Some("--timeout") => {
+                if let Some(timeout_str) = args.next() {
+                    timeout = Duration::from_secs(timeout_str.to_string_lossy().parse()?);
+                    if timeout.is_zero() {
+                        return Err("Timeout must be at least 1 second".into());
+                    }
+                } else {
+                    return Err("Missing timeout after flag".into());
+                }
+            }
+            _ => return Err(format!("Invalid flag: {}", arg.to_string_lossy()).into()),
+        }
+    }
+
+    Ok((server, timeout))
+}
+
+/// Runs a load test against a server and prints its report, exits with 1 if
+/// no transfer completed.
+#[cfg(feature = "cli")]
+fn run_bench<T: Iterator<Item = OsString>>(args: T) -> ! {
+    let (options, json) = parse_bench_args(args).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(err.exit_code())
+    });
+
+    let report = bench(&options);
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{report}");
+    }
+    process::exit(if report.transfers > 0 { 0 } else { 1 })
+}
+
+#[cfg(feature = "cli")]
+fn parse_bench_args<T: Iterator<Item = OsString>>(
+    mut args: T,
+) -> Result<(BenchOptions, bool), TftpError> {
+    let mut options = BenchOptions::new(SocketAddr::from(([127, 0, 0, 1], 69)), "");
+    let mut json = false;
+
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy().to_string();
+        if arg == "--json" {
+            json = true;
+            continue;
+        }
+        let Some(value) = args.next() else {
+            return Err(format!("Missing value after {arg}").into());
+        };
+        let value = value.to_string_lossy();
+        match arg.as_str() {
+            "--server" => options.server = value.parse()?,
+            "--file" => options.file = value.to_string(),
+            "--clients" => {
+                options.clients = value.parse()?;
+                if options.clients == 0 {
+                    return Err("Clients must be at least 1".into());
+                }
+            }
+            "--duration" => options.duration = Duration::from_secs(value.parse()?),
+            "--ramp" => options.ramp = Duration::from_millis(value.parse()?),
+            "--timeout" => options.timeout = Duration::from_secs(value.parse()?),
+            "--blocksize" => options.blk_size = Some(value.parse()?),
+            "--windowsize" => options.windowsize = Some(value.parse()?),
+            _ => return Err(format!("Invalid flag: {arg}").into()),
+        }
+    }
 
-    server.listen();
+    if options.file.is_empty() {
+        return Err("Missing file to download".into());
+    }
+    Ok((options, json))
 }
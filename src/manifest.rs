@@ -0,0 +1,147 @@
+use std::{
+    collections::HashSet,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use crate::{Authorizer, Decision, ErrorCode, RequestInfo};
+
+/// Manifest `struct` holds the relative paths of the only files that may be
+/// served when `--manifest` is set.
+///
+/// The manifest file lists one path per line, relative to the served
+/// directory. Empty lines and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    /// File the manifest was loaded from
+    pub(crate) path: PathBuf,
+    entries: HashSet<String>,
+}
+
+impl Manifest {
+    /// Loads and parses the manifest file at `path`.
+    pub(crate) fn load(path: &Path) -> Result<Manifest, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read manifest {}: {err}", path.display()))?;
+        let entries =
+            parse(&content).map_err(|err| format!("Invalid manifest {}: {err}", path.display()))?;
+
+        Ok(Manifest {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Returns the number of listed files.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the requested `filename` is listed.
+    pub(crate) fn allows(&self, filename: &str) -> bool {
+        normalize(filename).is_some_and(|name| self.entries.contains(&name))
+    }
+
+    /// Returns whether the file at `path` below `directory` is listed.
+    pub(crate) fn allows_path(&self, path: &Path, directory: &Path) -> bool {
+        path.strip_prefix(directory)
+            .ok()
+            .and_then(Path::to_str)
+            .is_some_and(|relative| self.allows(relative))
+    }
+}
+
+/// The manifest is the built-in [`Authorizer`], refusing the files it does
+/// not list to every client, for reading and writing alike.
+impl Authorizer for Manifest {
+    fn authorize(&self, _: &SocketAddr, filename: &str) -> Decision {
+        if self.allows(filename) {
+            Decision::Allow
+        } else {
+            Decision::Deny {
+                code: ErrorCode::AccessViolation,
+                message: "file access violation".to_string(),
+            }
+        }
+    }
+
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        self.authorize(&request.client, &request.filename)
+    }
+}
+
+fn parse(content: &str) -> Result<HashSet<String>, String> {
+    let mut entries = HashSet::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('/') {
+            return Err(format!("line {}: path must be relative", index + 1));
+        }
+        match normalize(line) {
+            Some(entry) => entries.insert(entry),
+            None => return Err(format!("line {}: invalid path {line}", index + 1)),
+        };
+    }
+
+    Ok(entries)
+}
+
+/// Normalizes a relative path to its components joined by `/`, ignoring
+/// leading, duplicate and `.` separators. Returns `None` for paths with
+/// `..` components or without any file name.
+fn normalize(path: &str) -> Option<String> {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    if components.is_empty() || components.contains(&"..") {
+        return None;
+    }
+
+    Some(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries_and_comments() {
+        let entries = parse("# boot files\n\npxelinux.0\n  ./images//kernel \n").unwrap();
+
+        assert_eq!(
+            entries,
+            HashSet::from(["pxelinux.0".to_string(), "images/kernel".to_string()])
+        );
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        assert_eq!(
+            parse("a.bin\n# comment\n/etc/passwd\n"),
+            Err("line 3: path must be relative".to_string())
+        );
+        assert_eq!(
+            parse("a.bin\nimages/../../secret\n"),
+            Err("line 2: invalid path images/../../secret".to_string())
+        );
+    }
+
+    #[test]
+    fn matches_normalized_requests() {
+        let manifest = Manifest {
+            path: PathBuf::new(),
+            entries: parse("images/kernel\n").unwrap(),
+        };
+
+        assert!(manifest.allows("images/kernel"));
+        assert!(manifest.allows("/images/kernel"));
+        assert!(!manifest.allows("images/initrd"));
+        assert!(!manifest.allows("images/../images/kernel"));
+        assert!(manifest.allows_path(Path::new("/srv/tftp/images/kernel"), Path::new("/srv/tftp")));
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Time during which a generated menu is served again without scanning the
+/// images anew.
+pub(crate) const MENU_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Names of the kernel and initial ramdisk files of an image directory.
+const KERNEL: &str = "vmlinuz";
+const INITRD: &str = "initrd";
+
+/// Menu `struct` generates the boot menu served for `--autogen-menu`.
+///
+/// Every directory of the scan directory holding a `vmlinuz` and an
+/// `initrd` file is a bootable image, named after the directory. A name
+/// ending in `.ipxe` generates an iPXE script, any other one a PXELINUX
+/// configuration. The entries come sorted by image name, between the
+/// contents of the header and footer files when given.
+#[derive(Debug)]
+pub(crate) struct Menu {
+    /// Requested filename that returns the menu
+    pub(crate) name: String,
+    /// Directory of the images, relative to the served directory
+    pub(crate) scan_dir: String,
+    /// File whose contents start the menu
+    pub(crate) header: Option<PathBuf>,
+    /// File whose contents end the menu
+    pub(crate) footer: Option<PathBuf>,
+    /// Last generated menu and when it was generated
+    pub(crate) cached: Option<(Instant, Vec<u8>)>,
+}
+
+impl Menu {
+    /// Returns the menu for the images of `root`, only including the files
+    /// for which `servable` returns `true`. The menu generated less than
+    /// [`MENU_CACHE_TTL`] before `now` is returned as is.
+    pub(crate) fn generate(
+        &mut self,
+        root: &Path,
+        now: Instant,
+        servable: &dyn Fn(&Path) -> bool,
+    ) -> io::Result<Vec<u8>> {
+        if let Some((generated, content)) = &self.cached {
+            if now.saturating_duration_since(*generated) < MENU_CACHE_TTL {
+                return Ok(content.clone());
+            }
+        }
+
+        let images = self.images(root, servable)?;
+        let mut content = match &self.header {
+            Some(header) => fs::read(header)?,
+            None if self.is_ipxe() => b"#!ipxe\n".to_vec(),
+            None => vec![],
+        };
+        if self.is_ipxe() {
+            self.write_ipxe(&images, &mut content);
+        } else {
+            self.write_pxelinux(&images, &mut content);
+        }
+        if let Some(footer) = &self.footer {
+            content.extend(fs::read(footer)?);
+        }
+
+        self.cached = Some((now, content.clone()));
+        Ok(content)
+    }
+
+    fn is_ipxe(&self) -> bool {
+        self.name.ends_with(".ipxe")
+    }
+
+    /// Returns the sorted names of the bootable images.
+    fn images(&self, root: &Path, servable: &dyn Fn(&Path) -> bool) -> io::Result<Vec<String>> {
+        let mut images = vec![];
+        for entry in fs::read_dir(root.join(&self.scan_dir))? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_dir()
+                && [KERNEL, INITRD]
+                    .iter()
+                    .all(|file| path.join(file).is_file() && servable(&path.join(file)))
+            {
+                images.push(name.to_string());
+            }
+        }
+        images.sort();
+        Ok(images)
+    }
+
+    /// Returns the path of `file` of `image`, relative to the served
+    /// directory.
+    fn path(&self, image: &str, file: &str) -> String {
+        let scan_dir = self.scan_dir.trim_matches('/');
+        if scan_dir.is_empty() {
+            format!("{image}/{file}")
+        } else {
+            format!("{scan_dir}/{image}/{file}")
+        }
+    }
+
+    /// Writes a menu choosing an image and a label booting each. Paths
+    /// start with `/`, as iPXE resolves them against the menu URI.
+    fn write_ipxe(&self, images: &[String], content: &mut Vec<u8>) {
+        let mut script = String::from("menu\n");
+        for image in images {
+            script.push_str(&format!("item {image} {image}\n"));
+        }
+        script.push_str("choose image && goto ${image}\n");
+        for image in images {
+            script.push_str(&format!(
+                ":{image}\nkernel /{}\ninitrd /{}\nboot\n",
+                self.path(image, KERNEL),
+                self.path(image, INITRD)
+            ));
+        }
+        content.extend(script.into_bytes());
+    }
+
+    /// Writes a label for each image.
+    fn write_pxelinux(&self, images: &[String], content: &mut Vec<u8>) {
+        let mut config = String::new();
+        for image in images {
+            config.push_str(&format!(
+                "LABEL {image}\n  KERNEL {}\n  INITRD {}\n",
+                self.path(image, KERNEL),
+                self.path(image, INITRD)
+            ));
+        }
+        content.extend(config.into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu(name: &str) -> Menu {
+        Menu {
+            name: name.to_string(),
+            scan_dir: "images".to_string(),
+            header: None,
+            footer: None,
+            cached: None,
+        }
+    }
+
+    fn image(root: &Path, name: &str, files: &[&str]) {
+        fs::create_dir_all(root.join("images").join(name)).unwrap();
+        for file in files {
+            fs::write(root.join("images").join(name).join(file), [0; 4]).unwrap();
+        }
+    }
+
+    #[test]
+    fn lists_complete_images_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        image(dir.path(), "debian", &[KERNEL, INITRD]);
+        image(dir.path(), "alpine", &[KERNEL, INITRD]);
+        image(dir.path(), "partial", &[KERNEL]);
+
+        let content = menu("pxelinux.cfg/default")
+            .generate(dir.path(), Instant::now(), &|_| true)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(content).unwrap(),
+            "LABEL alpine\n  KERNEL images/alpine/vmlinuz\n  INITRD images/alpine/initrd\n\
+             LABEL debian\n  KERNEL images/debian/vmlinuz\n  INITRD images/debian/initrd\n"
+        );
+    }
+
+    #[test]
+    fn wraps_ipxe_script_in_header_and_footer() {
+        let dir = tempfile::tempdir().unwrap();
+        image(dir.path(), "alpine", &[KERNEL, INITRD]);
+        fs::write(dir.path().join("header"), "#!ipxe\ndhcp\n").unwrap();
+        fs::write(dir.path().join("footer"), "# end\n").unwrap();
+        let mut menu = menu("autogen/menu.ipxe");
+        menu.header = Some(dir.path().join("header"));
+        menu.footer = Some(dir.path().join("footer"));
+
+        let content = menu
+            .generate(dir.path(), Instant::now(), &|_| true)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(content).unwrap(),
+            "#!ipxe\ndhcp\nmenu\nitem alpine alpine\nchoose image && goto ${image}\n\
+             :alpine\nkernel /images/alpine/vmlinuz\ninitrd /images/alpine/initrd\nboot\n# end\n"
+        );
+    }
+
+    #[test]
+    fn omits_images_that_are_not_servable() {
+        let dir = tempfile::tempdir().unwrap();
+        image(dir.path(), "alpine", &[KERNEL, INITRD]);
+        image(dir.path(), "secret", &[KERNEL, INITRD]);
+
+        let content = menu("menu.ipxe")
+            .generate(dir.path(), Instant::now(), &|path| {
+                !path.to_string_lossy().contains("secret")
+            })
+            .unwrap();
+
+        let content = String::from_utf8(content).unwrap();
+        assert!(content.contains(":alpine\n"));
+        assert!(!content.contains("secret"));
+    }
+
+    #[test]
+    fn caches_menu_briefly() {
+        let dir = tempfile::tempdir().unwrap();
+        image(dir.path(), "alpine", &[KERNEL, INITRD]);
+        let mut menu = menu("menu.ipxe");
+        let now = Instant::now();
+        menu.generate(dir.path(), now, &|_| true).unwrap();
+
+        image(dir.path(), "debian", &[KERNEL, INITRD]);
+        let cached = menu.generate(dir.path(), now + Duration::from_secs(1), &|_| true);
+        assert!(!String::from_utf8(cached.unwrap())
+            .unwrap()
+            .contains("debian"));
+
+        let fresh = menu.generate(dir.path(), now + MENU_CACHE_TTL, &|_| true);
+        assert!(String::from_utf8(fresh.unwrap())
+            .unwrap()
+            .contains("debian"));
+    }
+}
@@ -1,12 +1,14 @@
 use std::{
     error::Error,
-    net::{SocketAddr, UdpSocket},
+    io,
+    net::{IpAddr, SocketAddr},
 };
 
-use crate::{ErrorCode, Packet, TransferOption};
+use crate::packet::MAX_REQUEST_SIZE;
+use crate::{ErrorCode, Packet, Socket, TransferOption};
 
 /// Message `struct` is used for easy message transmission of common TFTP
-/// message types.
+/// message types over any [`Socket`].
 ///
 /// # Example
 ///
@@ -15,7 +17,7 @@ use crate::{ErrorCode, Packet, TransferOption};
 /// use tftpd::{Message, ErrorCode};
 ///
 /// // Send a FileNotFound error.
-/// Message::send_error_to(
+/// Message::send_error(
 ///     &UdpSocket::bind(SocketAddr::from_str("127.0.0.1:6969").unwrap()).unwrap(),
 ///     &SocketAddr::from_str("127.0.0.1:1234").unwrap(),
 ///     ErrorCode::FileNotFound,
@@ -24,70 +26,206 @@ use crate::{ErrorCode, Packet, TransferOption};
 /// ```
 pub struct Message;
 
-const MAX_REQUEST_PACKET_SIZE: usize = 512;
+/// Maximum number of attempts at sending a packet that is interrupted or
+/// only partially sent.
+const MAX_SEND_ATTEMPTS: usize = 3;
 
 impl Message {
     /// Sends a data packet to the supplied [`SocketAddr`].
     pub fn send_data(
-        socket: &UdpSocket,
+        socket: &dyn Socket,
         to: &SocketAddr,
         block_num: u16,
         data: Vec<u8>,
     ) -> Result<(), Box<dyn Error>> {
-        socket.send_to(&Packet::Data { block_num, data }.serialize()?, to)?;
-
-        Ok(())
+        Self::send_packet(socket, to, &Packet::Data { block_num, data })
     }
 
     /// Sends an acknowledgement packet to the supplied [`SocketAddr`].
     pub fn send_ack(
-        socket: &UdpSocket,
+        socket: &dyn Socket,
         to: &SocketAddr,
         block_number: u16,
     ) -> Result<(), Box<dyn Error>> {
-        socket.send_to(&Packet::Ack(block_number).serialize()?, to)?;
-
-        Ok(())
+        Self::send_packet(socket, to, &Packet::Ack(block_number))
     }
 
-    /// Sends an error packet to the supplied [`SocketAddr`].
+    /// Sends an error packet to the supplied [`SocketAddr`]. Messages longer
+    /// than 511 bytes are cut, see [`Packet::serialize()`].
     pub fn send_error(
-        socket: &UdpSocket,
+        socket: &dyn Socket,
         to: &SocketAddr,
         code: ErrorCode,
         msg: &str,
     ) -> Result<(), Box<dyn Error>> {
-        let buf = Packet::Error {
+        let packet = Packet::Error {
             code,
             msg: msg.to_string(),
         };
-        socket.send_to(&buf.serialize()?, to)?;
-
-        Ok(())
+        Self::send_packet(socket, to, &packet)
     }
 
     /// Sends an option acknowledgement packet to the supplied [`SocketAddr`].
+    /// Fails without sending anything when `options` is empty, the client
+    /// then expects the first data packet instead.
     pub fn send_oack(
-        socket: &UdpSocket,
+        socket: &dyn Socket,
         to: &SocketAddr,
         options: Vec<TransferOption>,
     ) -> Result<(), Box<dyn Error>> {
-        socket.send_to(&Packet::Oack(options).serialize()?, to)?;
+        if options.is_empty() {
+            return Err("Refusing to send an OACK without options".into());
+        }
+        Self::send_packet(socket, to, &Packet::Oack(options))
+    }
 
-        Ok(())
+    /// Serializes and sends `packet`, retrying when the send is interrupted
+    /// or only part of the datagram was sent, so that a truncated packet is
+    /// never taken for a sent one.
+    pub(crate) fn send_packet(
+        socket: &dyn Socket,
+        to: &SocketAddr,
+        packet: &Packet,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut last_err = None;
+        for _ in 0..MAX_SEND_ATTEMPTS {
+            let buf = packet.serialize()?;
+            match socket.send_to(&buf, to) {
+                Ok(size) if size == buf.len() => return Ok(()),
+                Ok(size) => {
+                    last_err = Some(format!("short send of {size} out of {} bytes", buf.len()))
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    last_err = Some(err.to_string())
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(format!(
+            "Giving up sending after {MAX_SEND_ATTEMPTS} attempts: {}",
+            last_err.unwrap_or_default()
+        )
+        .into())
     }
 
     /// Receives a packet from any incoming remote request, and returns the
     /// parsed [`Packet`] and the requesting [`SocketAddr`]. This function cannot handle
     /// large data packets due to the limited buffer size, so it is intended for
     /// only accepting incoming requests.
-    pub fn recv_from(socket: &UdpSocket) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
-        let mut buf = [0; MAX_REQUEST_PACKET_SIZE];
+    pub fn recv_from(socket: &dyn Socket) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let mut buf = [0; MAX_REQUEST_SIZE];
         let (number_of_bytes, from) = socket.recv_from(&mut buf)?;
         let packet = Packet::deserialize(&buf[..number_of_bytes])?;
 
-        println!("{}: [Packet] {}", from, packet);
-
         Ok((packet, from))
     }
+
+    /// Receives a packet like [`Message::recv_from()`], also returning the
+    /// local address it was sent to, if the [`Socket`] reports it.
+    pub fn recv_with_destination(
+        socket: &dyn Socket,
+    ) -> Result<(Packet, SocketAddr, Option<IpAddr>), Box<dyn Error>> {
+        let mut buf = [0; MAX_REQUEST_SIZE];
+        let (number_of_bytes, from, destination) = socket.recv_with_destination(&mut buf)?;
+        let packet = Packet::deserialize(&buf[..number_of_bytes])?;
+
+        Ok((packet, from, destination))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+    use super::*;
+
+    /// A [`Socket`] returning scripted results from `send_to`, then full
+    /// sends once the script is exhausted.
+    struct ScriptedSocket {
+        results: Mutex<VecDeque<io::Result<usize>>>,
+        attempts: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl ScriptedSocket {
+        fn new(results: Vec<io::Result<usize>>) -> ScriptedSocket {
+            ScriptedSocket {
+                results: Mutex::new(results.into()),
+                attempts: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    impl Socket for ScriptedSocket {
+        fn send_to(&self, buf: &[u8], _: &SocketAddr) -> io::Result<usize> {
+            self.attempts.lock().unwrap().push(buf.to_vec());
+            self.results
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Ok(buf.len()))
+        }
+
+        fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(SocketAddr::from(([127, 0, 0, 1], 69)))
+        }
+
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 1234))
+    }
+
+    #[test]
+    fn retries_interrupted_sends() {
+        let socket = ScriptedSocket::new(vec![Err(io::ErrorKind::Interrupted.into())]);
+
+        Message::send_ack(&socket, &client(), 7).unwrap();
+
+        let attempts = socket.attempts.lock().unwrap();
+        assert_eq!(*attempts, vec![vec![0, 4, 0, 7], vec![0, 4, 0, 7]]);
+    }
+
+    #[test]
+    fn resends_after_short_send() {
+        let socket = ScriptedSocket::new(vec![Ok(3)]);
+
+        Message::send_data(&socket, &client(), 1, b"hello".to_vec()).unwrap();
+
+        let attempts = socket.attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[1], b"\x00\x03\x00\x01hello");
+    }
+
+    #[test]
+    fn gives_up_after_repeated_short_sends() {
+        let socket =
+            ScriptedSocket::new(vec![Ok(1), Err(io::ErrorKind::Interrupted.into()), Ok(2)]);
+
+        let err = Message::send_error(&socket, &client(), ErrorCode::NotDefined, "oops")
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(socket.attempts.lock().unwrap().len(), MAX_SEND_ATTEMPTS);
+        assert!(err.contains("short send of 2"));
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let socket = ScriptedSocket::new(vec![Err(io::ErrorKind::ConnectionRefused.into())]);
+
+        assert!(Message::send_ack(&socket, &client(), 1).is_err());
+        assert_eq!(socket.attempts.lock().unwrap().len(), 1);
+    }
 }
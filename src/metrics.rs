@@ -0,0 +1,534 @@
+use crate::logger;
+use crate::negotiation::{option_index, OPTION_TYPES};
+use crate::{OptionOutcome, OptionType};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the tick duration histogram buckets. Longer ticks are
+/// counted in a last bucket.
+pub(crate) const TICK_BUCKETS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+];
+
+/// Upper bounds of the storage call and network wait histogram buckets.
+/// Longer durations are counted in a last bucket.
+pub(crate) const LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(20),
+    Duration::from_millis(100),
+    Duration::from_millis(1000),
+];
+
+/// Counters updated by the [`Server`](crate::Server) while it handles
+/// requests.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) requests: AtomicU64,
+    pub(crate) completed: AtomicU64,
+    pub(crate) failed: AtomicU64,
+    pub(crate) bytes_sent: AtomicU64,
+    pub(crate) retransmits: AtomicU64,
+    pub(crate) queue_length: AtomicU64,
+    pub(crate) active_sessions: AtomicU64,
+    pub(crate) queue_served: AtomicU64,
+    pub(crate) queue_wait_micros: AtomicU64,
+    pub(crate) busy_rejections: AtomicU64,
+    pub(crate) wrong_destination: AtomicU64,
+    pub(crate) ignored_discovery: AtomicU64,
+    /// Negotiation outcomes, indexed by option type and outcome.
+    pub(crate) option_outcomes: [[AtomicU64; 4]; 6],
+    /// Tick durations, indexed by [`TICK_BUCKETS`].
+    pub(crate) tick_durations: [AtomicU64; 6],
+    /// Durations of filesystem calls, indexed by [`LATENCY_BUCKETS`].
+    pub(crate) storage_durations: [AtomicU64; 6],
+    /// Times waited for the client between sending and its next ACK,
+    /// indexed by [`LATENCY_BUCKETS`].
+    pub(crate) network_waits: [AtomicU64; 6],
+    pub(crate) slow_ticks: AtomicU64,
+    pub(crate) duplicate_data: AtomicU64,
+    pub(crate) retransmitted_requests: AtomicU64,
+    pub(crate) client_rejections: AtomicU64,
+    pub(crate) tid_migrations: AtomicU64,
+    pub(crate) duplicate_options: AtomicU64,
+    pub(crate) blksize_blackholes: AtomicU64,
+    pub(crate) blksize_shrinks: AtomicU64,
+    pub(crate) storage_unavailable: AtomicU64,
+    pub(crate) unavailable_rejections: AtomicU64,
+    pub(crate) unreachable_clients: AtomicU64,
+    pub(crate) empty_requests: AtomicU64,
+    pub(crate) self_check_failures: AtomicU64,
+    pub(crate) client_restarts: AtomicU64,
+    pub(crate) not_found: AtomicU64,
+    pub(crate) suppressed_not_found: AtomicU64,
+    pub(crate) missing_files: AtomicU64,
+    pub(crate) orphan_acks: AtomicU64,
+    pub(crate) sprayed_clients: AtomicU64,
+    pub(crate) quota_rejections: AtomicU64,
+    pub(crate) quota_aborts: AtomicU64,
+    pub(crate) panics: AtomicU64,
+    pub(crate) privileged_source_drops: AtomicU64,
+    pub(crate) start_failures: AtomicU64,
+    pub(crate) idle_sessions: AtomicU64,
+    pub(crate) restarts: AtomicU64,
+    /// When the server started, in milliseconds since the epoch.
+    pub(crate) started_at: AtomicU64,
+    /// High-water marks and when they occurred, in milliseconds since the
+    /// epoch, 0 before any.
+    pub(crate) peak_sessions: AtomicU64,
+    pub(crate) peak_sessions_at: AtomicU64,
+    pub(crate) peak_request_rate: AtomicU64,
+    pub(crate) peak_request_rate_at: AtomicU64,
+    pub(crate) peak_throughput: AtomicU64,
+    pub(crate) peak_throughput_at: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set(counter: &AtomicU64, value: u64) {
+        counter.store(value, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_outcome(&self, option: OptionType, outcome: OptionOutcome) {
+        if let Some(index) = option_index(option) {
+            Metrics::inc(&self.option_outcomes[index][outcome as usize]);
+        }
+    }
+
+    pub(crate) fn record_tick(&self, elapsed: Duration) {
+        let bucket = TICK_BUCKETS
+            .iter()
+            .position(|&bound| elapsed <= bound)
+            .unwrap_or(TICK_BUCKETS.len());
+        Metrics::inc(&self.tick_durations[bucket]);
+    }
+
+    pub(crate) fn record_storage(&self, elapsed: Duration) {
+        Metrics::inc(&self.storage_durations[latency_bucket(elapsed)]);
+    }
+
+    pub(crate) fn record_network_wait(&self, elapsed: Duration) {
+        Metrics::inc(&self.network_waits[latency_bucket(elapsed)]);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            retransmits: self.retransmits.load(Ordering::Relaxed),
+            queue_length: self.queue_length.load(Ordering::Relaxed),
+            active_sessions: self.active_sessions.load(Ordering::Relaxed),
+            queue_served: self.queue_served.load(Ordering::Relaxed),
+            queue_wait: Duration::from_micros(self.queue_wait_micros.load(Ordering::Relaxed)),
+            busy_rejections: self.busy_rejections.load(Ordering::Relaxed),
+            wrong_destination: self.wrong_destination.load(Ordering::Relaxed),
+            ignored_discovery: self.ignored_discovery.load(Ordering::Relaxed),
+            option_outcomes: self.option_outcomes.each_ref().map(|outcomes| {
+                outcomes
+                    .each_ref()
+                    .map(|count| count.load(Ordering::Relaxed))
+            }),
+            tick_durations: self
+                .tick_durations
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            storage_durations: self
+                .storage_durations
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            network_waits: self
+                .network_waits
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            slow_ticks: self.slow_ticks.load(Ordering::Relaxed),
+            duplicate_data: self.duplicate_data.load(Ordering::Relaxed),
+            retransmitted_requests: self.retransmitted_requests.load(Ordering::Relaxed),
+            client_rejections: self.client_rejections.load(Ordering::Relaxed),
+            tid_migrations: self.tid_migrations.load(Ordering::Relaxed),
+            duplicate_options: self.duplicate_options.load(Ordering::Relaxed),
+            blksize_blackholes: self.blksize_blackholes.load(Ordering::Relaxed),
+            blksize_shrinks: self.blksize_shrinks.load(Ordering::Relaxed),
+            storage_unavailable: self.storage_unavailable.load(Ordering::Relaxed),
+            unavailable_rejections: self.unavailable_rejections.load(Ordering::Relaxed),
+            unreachable_clients: self.unreachable_clients.load(Ordering::Relaxed),
+            empty_requests: self.empty_requests.load(Ordering::Relaxed),
+            self_check_failures: self.self_check_failures.load(Ordering::Relaxed),
+            client_restarts: self.client_restarts.load(Ordering::Relaxed),
+            not_found: self.not_found.load(Ordering::Relaxed),
+            suppressed_not_found: self.suppressed_not_found.load(Ordering::Relaxed),
+            missing_files: self.missing_files.load(Ordering::Relaxed),
+            orphan_acks: self.orphan_acks.load(Ordering::Relaxed),
+            sprayed_clients: self.sprayed_clients.load(Ordering::Relaxed),
+            quota_rejections: self.quota_rejections.load(Ordering::Relaxed),
+            quota_aborts: self.quota_aborts.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+            privileged_source_drops: self.privileged_source_drops.load(Ordering::Relaxed),
+            start_failures: self.start_failures.load(Ordering::Relaxed),
+            idle_sessions: self.idle_sessions.load(Ordering::Relaxed),
+            restarts: self.restarts.load(Ordering::Relaxed),
+            started_at: timestamp(&self.started_at),
+            dropped_log_lines: logger::dropped(),
+            peak_sessions: self.peak_sessions.load(Ordering::Relaxed),
+            peak_sessions_at: timestamp(&self.peak_sessions_at),
+            peak_request_rate: self.peak_request_rate.load(Ordering::Relaxed),
+            peak_request_rate_at: timestamp(&self.peak_request_rate_at),
+            peak_throughput: self.peak_throughput.load(Ordering::Relaxed),
+            peak_throughput_at: timestamp(&self.peak_throughput_at),
+        }
+    }
+
+    /// Returns the counters and peaks that carry over a restart with
+    /// `--metrics-snapshot`, by name. The gauges describing the current
+    /// state of the server are left out.
+    pub(crate) fn persisted(&self) -> Vec<(String, &AtomicU64)> {
+        let mut persisted: Vec<(String, &AtomicU64)> = [
+            ("requests", &self.requests),
+            ("completed", &self.completed),
+            ("failed", &self.failed),
+            ("bytes_sent", &self.bytes_sent),
+            ("retransmits", &self.retransmits),
+            ("queue_served", &self.queue_served),
+            ("queue_wait_micros", &self.queue_wait_micros),
+            ("busy_rejections", &self.busy_rejections),
+            ("wrong_destination", &self.wrong_destination),
+            ("ignored_discovery", &self.ignored_discovery),
+            ("slow_ticks", &self.slow_ticks),
+            ("duplicate_data", &self.duplicate_data),
+            ("retransmitted_requests", &self.retransmitted_requests),
+            ("client_rejections", &self.client_rejections),
+            ("tid_migrations", &self.tid_migrations),
+            ("duplicate_options", &self.duplicate_options),
+            ("blksize_blackholes", &self.blksize_blackholes),
+            ("blksize_shrinks", &self.blksize_shrinks),
+            ("unavailable_rejections", &self.unavailable_rejections),
+            ("unreachable_clients", &self.unreachable_clients),
+            ("empty_requests", &self.empty_requests),
+            ("self_check_failures", &self.self_check_failures),
+            ("client_restarts", &self.client_restarts),
+            ("not_found", &self.not_found),
+            ("suppressed_not_found", &self.suppressed_not_found),
+            ("orphan_acks", &self.orphan_acks),
+            ("sprayed_clients", &self.sprayed_clients),
+            ("quota_rejections", &self.quota_rejections),
+            ("quota_aborts", &self.quota_aborts),
+            ("panics", &self.panics),
+            ("privileged_source_drops", &self.privileged_source_drops),
+            ("start_failures", &self.start_failures),
+            ("idle_sessions", &self.idle_sessions),
+            ("restarts", &self.restarts),
+            ("peak_sessions", &self.peak_sessions),
+            ("peak_sessions_at", &self.peak_sessions_at),
+            ("peak_request_rate", &self.peak_request_rate),
+            ("peak_request_rate_at", &self.peak_request_rate_at),
+            ("peak_throughput", &self.peak_throughput),
+            ("peak_throughput_at", &self.peak_throughput_at),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name.to_string(), counter))
+        .collect();
+
+        for (&option, outcomes) in OPTION_TYPES.iter().zip(&self.option_outcomes) {
+            for (outcome, count) in OptionOutcome::ALL.iter().zip(outcomes) {
+                persisted.push((
+                    format!("options.{}.{}", option.as_str(), outcome.as_str()),
+                    count,
+                ));
+            }
+        }
+        for (name, buckets) in [
+            ("tick", &self.tick_durations),
+            ("storage", &self.storage_durations),
+            ("network_wait", &self.network_waits),
+        ] {
+            for (bucket, count) in buckets.iter().enumerate() {
+                persisted.push((format!("{name}.{bucket}"), count));
+            }
+        }
+        persisted
+    }
+}
+
+fn latency_bucket(elapsed: Duration) -> usize {
+    LATENCY_BUCKETS
+        .iter()
+        .position(|&bound| elapsed <= bound)
+        .unwrap_or(LATENCY_BUCKETS.len())
+}
+
+/// Returns the time stored in milliseconds since the epoch in `millis`, if
+/// any.
+fn timestamp(millis: &AtomicU64) -> Option<SystemTime> {
+    match millis.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+    }
+}
+
+/// MetricsSnapshot `struct` is a point-in-time copy of the server counters,
+/// returned by [`Server::metrics()`](crate::Server::metrics).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of read requests received
+    pub requests: u64,
+    /// Number of transfers that were fully acknowledged
+    pub completed: u64,
+    /// Number of transfers that were aborted or timed out
+    pub failed: u64,
+    /// Number of data payload bytes sent, including retransmissions
+    pub bytes_sent: u64,
+    /// Number of windows sent again without progress from the client
+    pub retransmits: u64,
+    /// Number of read requests currently waiting for a free reader slot
+    pub queue_length: u64,
+    /// Number of transfers in progress
+    pub active_sessions: u64,
+    /// Number of read requests served after waiting in the queue
+    pub queue_served: u64,
+    /// Total time the served requests spent waiting in the queue
+    pub queue_wait: Duration,
+    /// Number of read requests refused because their file was busy
+    pub busy_rejections: u64,
+    /// Number of packets ignored because they were not sent to an address
+    /// the server answers on
+    pub wrong_destination: u64,
+    /// Number of read requests sent to a broadcast address that were not
+    /// answered
+    pub ignored_discovery: u64,
+    /// Number of transfers per negotiation outcome, indexed by option type
+    /// (`blksize`, `tsize`, `timeout`, `windowsize`, `xsum`, `rollover`)
+    /// and by [`OptionOutcome::ALL`]. See [`MetricsSnapshot::option_outcome()`].
+    pub option_outcomes: [[u64; 4]; 6],
+    /// Number of ticks per duration: up to 1, 2, 5, 10 and 50 ms, then
+    /// longer. See [`MetricsSnapshot::tick_counters()`].
+    pub tick_durations: [u64; 6],
+    /// Number of filesystem calls per duration: up to 1, 5, 20, 100 and
+    /// 1000 ms, then longer. See [`MetricsSnapshot::latency_counters()`].
+    pub storage_durations: [u64; 6],
+    /// Number of waits for an ACK per duration, with the buckets of
+    /// `storage_durations`
+    pub network_waits: [u64; 6],
+    /// Number of ticks that took longer than the tick budget
+    pub slow_ticks: u64,
+    /// Number of DATA packets sent again back to back with `--duplicate-data`,
+    /// their bytes are included in `bytes_sent`
+    pub duplicate_data: u64,
+    /// Number of read requests repeated by a client still waiting for the
+    /// first answer to it, which are not counted in `requests`
+    pub retransmitted_requests: u64,
+    /// Number of read requests rejected because their client host was at
+    /// `--max-per-ip`
+    pub client_rejections: u64,
+    /// Number of transfers moved to another port of their client with
+    /// `--loose-tid`
+    pub tid_migrations: u64,
+    /// Number of repeated options ignored in read requests
+    pub duplicate_options: u64,
+    /// Number of transfers that timed out without an ACK for the first
+    /// window of blocks larger than 512 bytes, a hint of datagrams dropped
+    /// on the path
+    pub blksize_blackholes: u64,
+    /// Number of read requests whose block size was clamped to 512 with
+    /// `--auto-shrink-blksize`
+    pub blksize_shrinks: u64,
+    /// 1 while the served directory cannot be opened, 0 otherwise
+    pub storage_unavailable: u64,
+    /// Number of read requests refused because the served directory could
+    /// not be opened
+    pub unavailable_rejections: u64,
+    /// Number of transfers failed early because sends to their client
+    /// kept failing with an unreachable host or network
+    pub unreachable_clients: u64,
+    /// Number of read requests for an empty filename or the bare root,
+    /// usually from a misconfigured client
+    pub empty_requests: u64,
+    /// Number of self-checks that found the session bookkeeping out of
+    /// step, see `--self-check`
+    pub self_check_failures: u64,
+    /// Number of transfers whose client went back to acknowledging earlier
+    /// blocks, as if it restarted reading the file
+    pub client_restarts: u64,
+    /// Number of read requests for a file that does not exist
+    pub not_found: u64,
+    /// Number of read requests for a missing file that were not logged,
+    /// only summed up in a roll-up
+    pub suppressed_not_found: u64,
+    /// Number of missing files tracked, see
+    /// [`Server::missing_files()`](crate::Server::missing_files)
+    pub missing_files: u64,
+    /// Number of ACKs from a port without a session or a recently ended
+    /// transfer
+    pub orphan_acks: u64,
+    /// Number of client hosts flagged because their packets look spread
+    /// over several servers by a NAT or load balancer
+    pub sprayed_clients: u64,
+    /// Number of read requests refused because their client host used up
+    /// its `--quota`
+    pub quota_rejections: u64,
+    /// Number of transfers aborted because their client host used up its
+    /// `--quota`, with `--quota-hard`
+    pub quota_aborts: u64,
+    /// Number of packets whose handling panicked, aborting the session of
+    /// their client
+    pub panics: u64,
+    /// Number of read requests dropped for coming from a port below 1024,
+    /// with `--reject-privileged-source-ports`
+    pub privileged_source_drops: u64,
+    /// Number of transfers failed because their OACK or first DATA could
+    /// not be sent, also counted in `failed`
+    pub start_failures: u64,
+    /// Number of transfers aborted after their client sent nothing for
+    /// `--session-timeout`, also counted in `failed`
+    pub idle_sessions: u64,
+    /// Number of times the server started again from the counters saved
+    /// with `--metrics-snapshot`
+    pub restarts: u64,
+    /// When the server started, the counters having been reset or restored
+    /// then
+    pub started_at: Option<SystemTime>,
+    /// Number of log lines dropped because the output could not keep up,
+    /// see [`start_logger`](crate::start_logger)
+    pub dropped_log_lines: u64,
+    /// Highest number of simultaneous sessions
+    pub peak_sessions: u64,
+    /// When `peak_sessions` was reached
+    pub peak_sessions_at: Option<SystemTime>,
+    /// Highest number of read requests received within one second
+    pub peak_request_rate: u64,
+    /// When `peak_request_rate` was reached
+    pub peak_request_rate_at: Option<SystemTime>,
+    /// Highest number of DATA bytes sent within one second
+    pub peak_throughput: u64,
+    /// When `peak_throughput` was reached
+    pub peak_throughput_at: Option<SystemTime>,
+}
+
+impl MetricsSnapshot {
+    /// Returns the monotonically increasing counters with their exported
+    /// names.
+    pub fn counters(&self) -> [(&'static str, u64); 35] {
+        [
+            ("requests", self.requests),
+            ("completed", self.completed),
+            ("failed", self.failed),
+            ("bytes_sent", self.bytes_sent),
+            ("retransmits", self.retransmits),
+            ("queue_served", self.queue_served),
+            ("queue_wait_ms", self.queue_wait.as_millis() as u64),
+            ("busy_rejections", self.busy_rejections),
+            ("wrong_destination", self.wrong_destination),
+            ("ignored_discovery", self.ignored_discovery),
+            ("slow_ticks", self.slow_ticks),
+            ("duplicate_data", self.duplicate_data),
+            ("retransmitted_requests", self.retransmitted_requests),
+            ("client_rejections", self.client_rejections),
+            ("tid_migrations", self.tid_migrations),
+            ("duplicate_options", self.duplicate_options),
+            ("blksize_blackholes", self.blksize_blackholes),
+            ("blksize_shrinks", self.blksize_shrinks),
+            ("unavailable_rejections", self.unavailable_rejections),
+            ("unreachable_clients", self.unreachable_clients),
+            ("empty_requests", self.empty_requests),
+            ("self_check_failures", self.self_check_failures),
+            ("client_restarts", self.client_restarts),
+            ("not_found", self.not_found),
+            ("suppressed_not_found", self.suppressed_not_found),
+            ("orphan_acks", self.orphan_acks),
+            ("sprayed_clients", self.sprayed_clients),
+            ("quota_rejections", self.quota_rejections),
+            ("quota_aborts", self.quota_aborts),
+            ("panics", self.panics),
+            ("privileged_source_drops", self.privileged_source_drops),
+            ("start_failures", self.start_failures),
+            ("idle_sessions", self.idle_sessions),
+            ("restarts", self.restarts),
+            ("dropped_log_lines", self.dropped_log_lines),
+        ]
+    }
+
+    /// Returns the tick duration histogram buckets with their exported names.
+    pub fn tick_counters(&self) -> [(&'static str, u64); 6] {
+        let [le_1ms, le_2ms, le_5ms, le_10ms, le_50ms, gt_50ms] = self.tick_durations;
+        [
+            ("tick.le_1ms", le_1ms),
+            ("tick.le_2ms", le_2ms),
+            ("tick.le_5ms", le_5ms),
+            ("tick.le_10ms", le_10ms),
+            ("tick.le_50ms", le_50ms),
+            ("tick.gt_50ms", gt_50ms),
+        ]
+    }
+
+    /// Returns the storage call and network wait histogram buckets with
+    /// their exported names.
+    pub fn latency_counters(&self) -> [(&'static str, u64); 12] {
+        let [s_1ms, s_5ms, s_20ms, s_100ms, s_1s, s_over] = self.storage_durations;
+        let [n_1ms, n_5ms, n_20ms, n_100ms, n_1s, n_over] = self.network_waits;
+        [
+            ("storage.le_1ms", s_1ms),
+            ("storage.le_5ms", s_5ms),
+            ("storage.le_20ms", s_20ms),
+            ("storage.le_100ms", s_100ms),
+            ("storage.le_1000ms", s_1s),
+            ("storage.gt_1000ms", s_over),
+            ("network_wait.le_1ms", n_1ms),
+            ("network_wait.le_5ms", n_5ms),
+            ("network_wait.le_20ms", n_20ms),
+            ("network_wait.le_100ms", n_100ms),
+            ("network_wait.le_1000ms", n_1s),
+            ("network_wait.gt_1000ms", n_over),
+        ]
+    }
+
+    /// Returns the number of transfers in which `option` had `outcome`.
+    pub fn option_outcome(&self, option: OptionType, outcome: OptionOutcome) -> u64 {
+        option_index(option).map_or(0, |index| self.option_outcomes[index][outcome as usize])
+    }
+
+    /// Returns the negotiation outcome counters with their exported names,
+    /// labeled by option and outcome.
+    pub fn option_counters(&self) -> Vec<(String, u64)> {
+        OPTION_TYPES
+            .iter()
+            .flat_map(|&option| {
+                OptionOutcome::ALL.iter().map(move |&outcome| {
+                    (
+                        format!("options.{}.{}", option.as_str(), outcome.as_str()),
+                        self.option_outcome(option, outcome),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the values that can go up and down with their exported names.
+    pub fn gauges(&self) -> [(&'static str, u64); 8] {
+        [
+            ("queue_length", self.queue_length),
+            ("active_sessions", self.active_sessions),
+            ("missing_files", self.missing_files),
+            ("storage_unavailable", self.storage_unavailable),
+            ("peak_sessions", self.peak_sessions),
+            ("peak_request_rate", self.peak_request_rate),
+            ("peak_throughput", self.peak_throughput),
+            (
+                "process_start_time_seconds",
+                self.started_at
+                    .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |since| since.as_secs()),
+            ),
+        ]
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use crate::metrics::Metrics;
+use crate::stats::FileStatsMap;
+use crate::FileStats;
+
+/// Version of the snapshot format, snapshots of another version are ignored.
+const VERSION: u64 = 1;
+
+/// MetricsFile `struct` saves the server counters and the per-file stats to
+/// the `--metrics-snapshot` file, periodically and when the server stops,
+/// so that they carry over a restart.
+///
+/// The snapshot is a JSON object:
+///
+/// ```json
+/// {"version":1,"metrics":{"requests":12,...},
+///  "files":{"pxelinux.0":{"requests":3,"completed":3,"bytes_served":80000,"last_served":1700000000000}}}
+/// ```
+///
+/// with `last_served` in milliseconds since the epoch, or `null`. It is
+/// written to a temporary file renamed over the previous one, so a crash
+/// leaves either snapshot whole.
+#[derive(Debug)]
+pub(crate) struct MetricsFile {
+    path: PathBuf,
+    interval: Duration,
+    last_write: Option<Instant>,
+}
+
+/// Counters and per-file stats read from a snapshot.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Saved {
+    metrics: Vec<(String, u64)>,
+    files: Vec<(String, FileStats)>,
+}
+
+impl MetricsFile {
+    pub(crate) fn new(path: PathBuf, interval: Duration) -> MetricsFile {
+        MetricsFile {
+            path,
+            interval,
+            last_write: None,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns whether the snapshot is due to be written again, which it
+    /// is right away before the first write.
+    pub(crate) fn due(&self, now: Instant) -> bool {
+        self.last_write
+            .is_none_or(|last_write| now.saturating_duration_since(last_write) >= self.interval)
+    }
+
+    /// Writes the snapshot of `metrics` and `files`.
+    pub(crate) fn write(
+        &mut self,
+        now: Instant,
+        metrics: &Metrics,
+        files: &FileStatsMap,
+    ) -> io::Result<()> {
+        self.last_write = Some(now);
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let temporary = self.path.with_file_name(name);
+        let mut file = File::create(&temporary)?;
+        file.write_all(encode(metrics, files).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)
+    }
+
+    /// Reads the snapshot, `None` when there is none yet.
+    pub(crate) fn load(&self) -> Result<Option<Saved>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => decode(&content).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+impl Saved {
+    /// Sets the counters of `metrics` and `files` to the saved values.
+    /// Counters unknown to this version are ignored.
+    pub(crate) fn restore(self, metrics: &Metrics, files: &mut FileStatsMap) {
+        for (name, counter) in metrics.persisted() {
+            if let Some((_, value)) = self.metrics.iter().find(|(saved, _)| *saved == name) {
+                Metrics::set(counter, *value);
+            }
+        }
+        for (file, stats) in self.files {
+            files.restore(file, stats);
+        }
+    }
+}
+
+fn encode(metrics: &Metrics, files: &FileStatsMap) -> String {
+    let mut json = format!("{{\"version\":{VERSION},\"metrics\":{{");
+    for (i, (name, counter)) in metrics.persisted().into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let value = counter.load(std::sync::atomic::Ordering::Relaxed);
+        let _ = write!(json, "{separator}{}:{value}", quote(&name));
+    }
+    json.push_str("},\"files\":{");
+    for (i, (file, stats)) in files.sorted().into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let last_served = stats
+            .last_served
+            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+            .map_or("null".to_string(), |since| since.as_millis().to_string());
+        let _ = write!(
+            json,
+            "{separator}{}:{{\"requests\":{},\"completed\":{},\"bytes_served\":{},\"last_served\":{last_served}}}",
+            quote(&file),
+            stats.requests,
+            stats.completed,
+            stats.bytes_served,
+        );
+    }
+    json.push_str("}}\n");
+    json
+}
+
+fn decode(content: &str) -> Result<Saved, String> {
+    let mut parser = Parser { rest: content };
+    let snapshot = parser.value()?;
+    parser.skip_whitespace();
+    if !parser.rest.is_empty() {
+        return Err("trailing characters after the snapshot".to_string());
+    }
+
+    let version = snapshot.field("version")?.number()?;
+    if version != VERSION {
+        return Err(format!("snapshot version {version}, expected {VERSION}"));
+    }
+    let metrics = snapshot
+        .field("metrics")?
+        .object()?
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), value.number()?)))
+        .collect::<Result<_, String>>()?;
+    let files = snapshot
+        .field("files")?
+        .object()?
+        .iter()
+        .map(|(file, stats)| {
+            let last_served = match stats.field("last_served")? {
+                Value::Null => None,
+                value => Some(UNIX_EPOCH + Duration::from_millis(value.number()?)),
+            };
+            Ok((
+                file.clone(),
+                FileStats {
+                    requests: stats.field("requests")?.number()?,
+                    completed: stats.field("completed")?.number()?,
+                    bytes_served: stats.field("bytes_served")?.number()?,
+                    last_served,
+                },
+            ))
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Saved { metrics, files })
+}
+
+/// Returns `text` as a JSON string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c < ' ' => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The subset of JSON values a snapshot is made of.
+#[derive(Debug)]
+enum Value {
+    Null,
+    Number(u64),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn number(&self) -> Result<u64, String> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+
+    fn object(&self) -> Result<&[(String, Value)], String> {
+        match self {
+            Value::Object(fields) => Ok(fields),
+            _ => Err("expected an object".to_string()),
+        }
+    }
+
+    fn field(&self, name: &str) -> Result<&Value, String> {
+        self.object()?
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("missing {name}"))
+    }
+}
+
+/// Parses the JSON written by [`encode()`].
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), String> {
+        self.skip_whitespace();
+        self.rest = self
+            .rest
+            .strip_prefix(token)
+            .ok_or_else(|| format!("expected {token}"))?;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.rest.chars().next() {
+            Some('{') => self.object(),
+            Some('0'..='9') => {
+                let end = self
+                    .rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(self.rest.len());
+                let number = self.rest[..end].parse().map_err(|_| "number too large")?;
+                self.rest = &self.rest[end..];
+                Ok(Value::Number(number))
+            }
+            _ if self.rest.starts_with("null") => {
+                self.rest = &self.rest[4..];
+                Ok(Value::Null)
+            }
+            Some(c) => Err(format!("unexpected {c:?}")),
+            None => Err("truncated snapshot".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if let Some(rest) = self.rest.strip_prefix('}') {
+            self.rest = rest;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.rest.chars().next() {
+                Some(',') => self.rest = &self.rest[1..],
+                Some('}') => {
+                    self.rest = &self.rest[1..];
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err("expected , or }".to_string()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(string);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or("invalid \\u escape")?;
+                        string.push(c);
+                    }
+                    _ => return Err("invalid escape".to_string()),
+                },
+                c => string.push(c),
+            }
+        }
+        Err("truncated snapshot".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_counters_and_files() {
+        let metrics = Metrics::default();
+        Metrics::set(&metrics.requests, 12);
+        Metrics::set(&metrics.tick_durations[2], 4);
+        let mut files = FileStatsMap::new(10);
+        files.record_request("dir/\"quoted\"\n.bin");
+        files.record_end("dir/\"quoted\"\n.bin", 100, true);
+        files.record_request("pxelinux.0");
+
+        let saved = decode(&encode(&metrics, &files)).unwrap();
+        let restored = Metrics::default();
+        let mut restored_files = FileStatsMap::new(10);
+        saved.restore(&restored, &mut restored_files);
+
+        assert_eq!(restored.snapshot().requests, 12);
+        assert_eq!(restored.snapshot().tick_durations[2], 4);
+        let expected: Vec<_> = files
+            .sorted()
+            .into_iter()
+            .map(|(file, mut stats)| {
+                // Saved to the millisecond.
+                stats.last_served = stats.last_served.map(|at| {
+                    UNIX_EPOCH
+                        + Duration::from_millis(
+                            at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+                        )
+                });
+                (file, stats)
+            })
+            .collect();
+        assert_eq!(restored_files.sorted(), expected);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_snapshots() {
+        let content = encode(&Metrics::default(), &FileStatsMap::new(10));
+
+        assert!(decode(&content[..content.len() / 2]).is_err());
+        assert!(decode("").is_err());
+        assert!(decode("{\"version\":2,\"metrics\":{},\"files\":{}}").is_err());
+        assert!(decode("{\"version\":1,\"metrics\":{\"requests\":-1},\"files\":{}}").is_err());
+    }
+
+    #[test]
+    fn ignores_unknown_counters() {
+        let saved = decode(
+            "{\"version\":1,\"metrics\":{\"requests\":3,\"from_the_future\":7},\"files\":{}}",
+        )
+        .unwrap();
+        let metrics = Metrics::default();
+
+        saved.restore(&metrics, &mut FileStatsMap::new(10));
+
+        assert_eq!(metrics.snapshot().requests, 3);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Number of missing files tracked before the least recently requested one
+/// is forgotten.
+pub(crate) const MAX_MISSING_FILES: usize = 256;
+
+/// Number of distinct clients counted per file and roll-up, further ones
+/// are not told apart.
+const MAX_CLIENTS: usize = 1024;
+
+/// Period between two roll-ups of the requests for missing files.
+pub(crate) const ROLLUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// MissingFile `struct` holds the requests for a file that does not exist,
+/// returned by [`Server::missing_files()`](crate::Server::missing_files).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingFile {
+    /// Number of read requests answered with `FileNotFound`
+    pub requests: u64,
+    /// When the file was first requested
+    pub first_requested: SystemTime,
+    /// When the file was last requested
+    pub last_requested: SystemTime,
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: MissingFile,
+    /// Requests since the last roll-up
+    period_requests: u64,
+    /// Requests since the last roll-up that were not logged
+    suppressed: u64,
+    /// Clients that requested the file since the last roll-up
+    clients: HashSet<IpAddr>,
+    /// Whether the file was not requested during the last roll-up period,
+    /// so that its next request is logged again
+    quiet: bool,
+    /// Tick of the last request, for forgetting the least recent file
+    tick: u64,
+}
+
+/// Keeps the requests for the most recently requested missing files, so
+/// that a missing file is logged once and then summed up in a roll-up every
+/// [`ROLLUP_INTERVAL`], instead of once per request.
+#[derive(Debug)]
+pub(crate) struct MissingFiles {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    tick: u64,
+    /// Start of the roll-up period, set by the first roll-up
+    period_started: Option<Instant>,
+}
+
+impl MissingFiles {
+    pub(crate) fn new(capacity: usize) -> MissingFiles {
+        MissingFiles {
+            capacity,
+            entries: HashMap::new(),
+            tick: 0,
+            period_started: None,
+        }
+    }
+
+    /// Counts a request of `client` for the missing `file`, forgetting the
+    /// least recently requested file when the map is full. Returns whether
+    /// the request should be logged, which only the first one of a file is
+    /// until the file goes a whole roll-up period without requests.
+    pub(crate) fn record(&mut self, file: &str, client: IpAddr) -> bool {
+        self.tick += 1;
+        if !self.entries.contains_key(file) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.tick)
+                .map(|(file, _)| file.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        let now = SystemTime::now();
+        let entry = self.entries.entry(file.to_string()).or_insert(Entry {
+            file: MissingFile {
+                requests: 0,
+                first_requested: now,
+                last_requested: now,
+            },
+            period_requests: 0,
+            suppressed: 0,
+            clients: HashSet::new(),
+            quiet: true,
+            tick: 0,
+        });
+        entry.file.requests += 1;
+        entry.file.last_requested = now;
+        entry.period_requests += 1;
+        if entry.clients.len() < MAX_CLIENTS {
+            entry.clients.insert(client);
+        }
+        entry.tick = self.tick;
+
+        let logged = entry.quiet;
+        if logged {
+            entry.quiet = false;
+        } else {
+            entry.suppressed += 1;
+        }
+        logged
+    }
+
+    /// Returns the roll-up lines of the files with requests that were not
+    /// logged, once the roll-up period ended, and starts the next one.
+    pub(crate) fn roll_up(&mut self, now: Instant) -> Vec<String> {
+        let started = *self.period_started.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed < ROLLUP_INTERVAL {
+            return vec![];
+        }
+        self.period_started = Some(now);
+
+        let mut lines = vec![];
+        for (file, entry) in &mut self.entries {
+            if entry.suppressed > 0 {
+                lines.push(format!(
+                    "{file} requested and missing {} times in last {}s from {} clients",
+                    entry.period_requests,
+                    elapsed.as_secs(),
+                    entry.clients.len()
+                ));
+            }
+            if entry.period_requests == 0 {
+                entry.quiet = true;
+            }
+            entry.period_requests = 0;
+            entry.suppressed = 0;
+            entry.clients.clear();
+        }
+        lines.sort();
+        lines
+    }
+
+    /// Returns the number of tracked missing files.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the requests of every tracked missing file, most requested
+    /// first.
+    pub(crate) fn sorted(&self) -> Vec<(String, MissingFile)> {
+        let mut files: Vec<(String, MissingFile)> = self
+            .entries
+            .iter()
+            .map(|(file, entry)| (file.clone(), entry.file))
+            .collect();
+        files.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(&b.0)));
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn client(host: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, host))
+    }
+
+    #[test]
+    fn logs_first_request_and_rolls_up_the_others() {
+        let start = Instant::now();
+        let mut missing = MissingFiles::new(10);
+        missing.roll_up(start);
+
+        let logged: Vec<bool> = (0..412)
+            .map(|request| missing.record("pxelinux.0", client((request % 37) as u8)))
+            .collect();
+        assert_eq!(logged.iter().filter(|&&logged| logged).count(), 1);
+        assert!(logged[0]);
+
+        assert!(missing.roll_up(start + ROLLUP_INTERVAL / 2).is_empty());
+        assert_eq!(
+            missing.roll_up(start + ROLLUP_INTERVAL),
+            ["pxelinux.0 requested and missing 412 times in last 60s from 37 clients"]
+        );
+        assert_eq!(missing.sorted()[0].1.requests, 412);
+    }
+
+    #[test]
+    fn rolls_up_only_files_with_suppressed_requests() {
+        let start = Instant::now();
+        let mut missing = MissingFiles::new(10);
+        missing.roll_up(start);
+        missing.record("once.bin", client(1));
+        missing.record("twice.bin", client(1));
+        missing.record("twice.bin", client(2));
+
+        assert_eq!(
+            missing.roll_up(start + ROLLUP_INTERVAL),
+            ["twice.bin requested and missing 2 times in last 60s from 2 clients"]
+        );
+
+        // Requested again during the next period: still suppressed.
+        assert!(!missing.record("twice.bin", client(1)));
+        assert_eq!(
+            missing.roll_up(start + 2 * ROLLUP_INTERVAL),
+            ["twice.bin requested and missing 1 times in last 60s from 1 clients"]
+        );
+    }
+
+    #[test]
+    fn logs_again_after_a_quiet_period() {
+        let start = Instant::now();
+        let mut missing = MissingFiles::new(10);
+        missing.roll_up(start);
+        assert!(missing.record("pxelinux.0", client(1)));
+        assert!(!missing.record("pxelinux.0", client(1)));
+
+        missing.roll_up(start + ROLLUP_INTERVAL);
+        assert!(missing.roll_up(start + 2 * ROLLUP_INTERVAL).is_empty());
+
+        assert!(missing.record("pxelinux.0", client(1)));
+        assert_eq!(missing.sorted()[0].1.requests, 3);
+    }
+
+    #[test]
+    fn forgets_least_recently_requested_file() {
+        let mut missing = MissingFiles::new(2);
+        missing.record("a", client(1));
+        missing.record("b", client(1));
+        missing.record("a", client(1));
+        missing.record("c", client(1));
+
+        let files: Vec<String> = missing.sorted().into_iter().map(|(file, _)| file).collect();
+        assert_eq!(files, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(missing.len(), 2);
+    }
+}
@@ -0,0 +1,684 @@
+use crate::packet::MAX_BLOCK_SIZE;
+use crate::{OptionType, TftpError, TransferOption};
+use std::{fmt, str::FromStr};
+
+/// Smallest block size allowed by RFC 2348.
+pub(crate) const MIN_BLOCK_SIZE: usize = 8;
+
+/// Default [`OptionLimits::max_options_bytes`], the largest request RFC 2347
+/// allows.
+const MAX_OPTIONS_BYTES: usize = 512;
+
+/// Largest window size granted. Block numbers wrap at 65536, so an ACK
+/// from half the sequence space ago would look like one of the window in
+/// flight with windows of 32768 blocks or more; RFC 7440 measured gains
+/// with windows of at most a few dozen blocks anyway.
+pub const MAX_WINDOWSIZE: u16 = 8192;
+
+/// Default [`OptionLimits::max_retransmit_rate`], 1 Gbit/s.
+const DEFAULT_MAX_RETRANSMIT_RATE: u64 = 125_000_000;
+
+/// Option types a client requests, in the order used by the metrics.
+pub(crate) const OPTION_TYPES: [OptionType; 6] = [
+    OptionType::BlockSize,
+    OptionType::TransferSize,
+    OptionType::Timeout,
+    OptionType::Windowsize,
+    OptionType::Checksum,
+    OptionType::Rollover,
+];
+
+/// Order of the options in an OACK, whatever their order in the request:
+/// the standard options, then the nonstandard ones. Options added later
+/// are appended, so that the order of the existing ones never changes.
+pub const OACK_ORDER: [OptionType; 7] = [
+    OptionType::BlockSize,
+    OptionType::Timeout,
+    OptionType::TransferSize,
+    OptionType::Windowsize,
+    OptionType::Checksum,
+    OptionType::ServerNote,
+    OptionType::Rollover,
+];
+
+/// TsizeMode `enum` selects how the server answers a client requesting the
+/// transfer size option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum TsizeMode {
+    /// Answer with the size of the file
+    #[default]
+    Echo,
+    /// Leave the option out of the OACK
+    Omit,
+    /// Answer with a size of 0
+    Zero,
+}
+
+/// DuplicatePolicy `enum` selects what happens to a request repeating an
+/// option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DuplicatePolicy {
+    /// Reject the request
+    Reject,
+    /// Keep the first occurrence and ignore the repetitions
+    #[default]
+    First,
+}
+
+/// OptionLimits `struct` gathers the limits applied when negotiating the
+/// options of a transfer, carried in [`Config::option_limits`](crate::Config).
+///
+/// The defaults accept every value RFC 2347 to RFC 7440 allow, except
+/// for windows so large and timeouts so short that retransmitting a whole
+/// window every timeout would take more than 1 Gbit/s.
+///
+/// The window size and the timeout interact: a lost window is sent again
+/// after every timeout, so `blksize * windowsize / timeout` bytes per
+/// second are sent without progress until the client answers. Keeping it
+/// under [`OptionLimits::max_retransmit_rate`] stops a single request for
+/// `timeout=1` with large windows from saturating a slow link.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::OptionLimits;
+///
+/// let limits = OptionLimits {
+///     max_blksize: 1468,
+///     max_window_bytes: Some(64 * 1024),
+///     ..OptionLimits::default()
+/// };
+/// assert!(limits.validate().is_ok());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OptionLimits {
+    /// Largest block size granted, larger requests are clamped. (default: 65464)
+    pub max_blksize: usize,
+    /// Largest window size granted, larger requests are clamped. At most
+    /// [`MAX_WINDOWSIZE`]. (default: 8192)
+    pub max_windowsize: u16,
+    /// Largest number of data bytes in a window, the window size is clamped
+    /// to fit. (default: unlimited)
+    pub max_window_bytes: Option<usize>,
+    /// Largest number of bytes per second a window sent again on every
+    /// timeout may take. A request over it has its timeout stretched when
+    /// it negotiated one, up to `max_timeout`, then its window size shrunk
+    /// until a window fits. (default: 125000000, 1 Gbit/s)
+    pub max_retransmit_rate: Option<u64>,
+    /// Shortest timeout in seconds acknowledged, shorter requests are not
+    /// acknowledged. (default: 1)
+    pub min_timeout: u64,
+    /// Longest timeout in seconds acknowledged, longer requests are not
+    /// acknowledged. (default: 255)
+    pub max_timeout: u64,
+    /// How the transfer size option is answered. (default: echo)
+    pub tsize: TsizeMode,
+    /// Options that are never acknowledged. (default: none)
+    pub disabled: Vec<OptionType>,
+    /// What happens to a request repeating an option. (default: first)
+    pub duplicates: DuplicatePolicy,
+    /// Largest serialized size of the known option names and values of a
+    /// request, larger requests are rejected. (default: 512, the request
+    /// size of RFC 2347)
+    pub max_options_bytes: usize,
+}
+
+impl Default for OptionLimits {
+    fn default() -> Self {
+        OptionLimits {
+            max_blksize: MAX_BLOCK_SIZE,
+            max_windowsize: MAX_WINDOWSIZE,
+            max_window_bytes: None,
+            max_retransmit_rate: Some(DEFAULT_MAX_RETRANSMIT_RATE),
+            min_timeout: 1,
+            max_timeout: 255,
+            tsize: TsizeMode::Echo,
+            disabled: vec![],
+            duplicates: DuplicatePolicy::First,
+            max_options_bytes: MAX_OPTIONS_BYTES,
+        }
+    }
+}
+
+impl OptionLimits {
+    /// Checks that the limits can be satisfied by some transfer.
+    pub fn validate(&self) -> Result<(), TftpError> {
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.max_blksize) {
+            return Err(format!(
+                "Maximum block size must be between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE}"
+            )
+            .into());
+        }
+        if !(1..=MAX_WINDOWSIZE).contains(&self.max_windowsize) {
+            return Err(
+                format!("Maximum window size must be between 1 and {MAX_WINDOWSIZE}").into(),
+            );
+        }
+        if self
+            .max_window_bytes
+            .is_some_and(|bytes| bytes < MIN_BLOCK_SIZE)
+        {
+            return Err(format!("Maximum window bytes must be at least {MIN_BLOCK_SIZE}").into());
+        }
+        if self
+            .max_retransmit_rate
+            .is_some_and(|rate| rate < MIN_BLOCK_SIZE as u64)
+        {
+            return Err(format!(
+                "Maximum retransmit rate must be at least {MIN_BLOCK_SIZE} bytes per second"
+            )
+            .into());
+        }
+        if self.min_timeout == 0 || self.min_timeout > self.max_timeout || self.max_timeout > 255 {
+            return Err("Timeout range must be within 1 to 255 seconds".into());
+        }
+        Ok(())
+    }
+
+    /// Returns whether `option` is acknowledged at all.
+    pub fn allows(&self, option: OptionType) -> bool {
+        !self.disabled.contains(&option)
+    }
+
+    /// Returns the largest window size granted with blocks of `blk_size`
+    /// bytes.
+    pub fn max_windowsize_for(&self, blk_size: usize) -> u16 {
+        let by_bytes = self
+            .max_window_bytes
+            .map_or(usize::MAX, |bytes| bytes / blk_size.max(1));
+        by_bytes.clamp(1, self.max_windowsize as usize) as u16
+    }
+}
+
+/// OptionOutcome `enum` describes what became of an option during the
+/// negotiation of a transfer.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OptionOutcome {
+    /// Acknowledged with the requested value
+    Granted,
+    /// Acknowledged with a different value than requested
+    Clamped,
+    /// Requested but left out of the OACK
+    Dropped,
+    /// Not requested by the client
+    Absent,
+}
+
+impl OptionOutcome {
+    /// Every outcome, in the order used by the metrics.
+    pub const ALL: [OptionOutcome; 4] = [
+        OptionOutcome::Granted,
+        OptionOutcome::Clamped,
+        OptionOutcome::Dropped,
+        OptionOutcome::Absent,
+    ];
+
+    /// Converts an [`OptionOutcome`] to a [`str`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptionOutcome::Granted => "granted",
+            OptionOutcome::Clamped => "clamped",
+            OptionOutcome::Dropped => "dropped",
+            OptionOutcome::Absent => "absent",
+        }
+    }
+}
+
+/// NegotiatedOption `struct` pairs the value of an option requested by the
+/// client with the value acknowledged by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiatedOption {
+    /// Type of the option
+    pub option: OptionType,
+    /// Value requested by the client, if any
+    pub requested: Option<u64>,
+    /// Value acknowledged in the OACK, if any
+    pub granted: Option<u64>,
+}
+
+impl NegotiatedOption {
+    /// Returns what became of the option.
+    pub fn outcome(&self) -> OptionOutcome {
+        match (self.requested, self.granted) {
+            (None, _) => OptionOutcome::Absent,
+            (Some(_), None) => OptionOutcome::Dropped,
+            // The transfer size is answered with the file size by design.
+            (Some(_), Some(_)) if self.option == OptionType::TransferSize => OptionOutcome::Granted,
+            (Some(requested), Some(granted)) if requested != granted => OptionOutcome::Clamped,
+            _ => OptionOutcome::Granted,
+        }
+    }
+}
+
+/// NegotiationNote `enum` lists the reasons a client got less than it asked
+/// for, sent in the nonstandard `srvnote` option of the OACK when
+/// `--negotiation-report` is set, and always logged by the server.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+///
+/// | Code   | Meaning                  |
+/// |--------|--------------------------|
+/// | `BKCL` | `blksize` clamped        |
+/// | `BKDR` | `blksize` dropped        |
+/// | `TSDR` | `tsize` dropped          |
+/// | `TOCL` | `timeout` clamped        |
+/// | `TODR` | `timeout` dropped        |
+/// | `WSCL` | `windowsize` clamped     |
+/// | `WSDR` | `windowsize` dropped     |
+/// | `XSDR` | `xsum` dropped           |
+///
+/// The value of a [`TransferOption`] of type [`OptionType::ServerNote`] is
+/// the [`NegotiationNote::mask()`] of its notes, written as their codes
+/// separated by commas.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{NegotiationNote, OptionType, TransferOption};
+///
+/// let notes = [NegotiationNote::BlockSizeClamped, NegotiationNote::TimeoutDropped];
+/// let srvnote = TransferOption {
+///     option: OptionType::ServerNote,
+///     value: NegotiationNote::mask(&notes),
+/// };
+/// assert_eq!(srvnote.as_bytes(), b"srvnote\0BKCL,TODR\0");
+/// assert_eq!(NegotiationNote::from_mask(srvnote.value), notes);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NegotiationNote {
+    /// The block size was lowered
+    BlockSizeClamped,
+    /// The block size option was left out
+    BlockSizeDropped,
+    /// The transfer size option was left out
+    TransferSizeDropped,
+    /// The timeout option was left out
+    TimeoutDropped,
+    /// The window size was lowered
+    WindowsizeClamped,
+    /// The window size option was left out
+    WindowsizeDropped,
+    /// The checksum option was left out
+    ChecksumDropped,
+    /// The timeout was raised
+    TimeoutClamped,
+}
+
+impl NegotiationNote {
+    /// Every note, in the order of their bits in a mask.
+    pub const ALL: [NegotiationNote; 8] = [
+        NegotiationNote::BlockSizeClamped,
+        NegotiationNote::BlockSizeDropped,
+        NegotiationNote::TransferSizeDropped,
+        NegotiationNote::TimeoutDropped,
+        NegotiationNote::WindowsizeClamped,
+        NegotiationNote::WindowsizeDropped,
+        NegotiationNote::ChecksumDropped,
+        NegotiationNote::TimeoutClamped,
+    ];
+
+    /// Converts a [`NegotiationNote`] to its code.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NegotiationNote::BlockSizeClamped => "BKCL",
+            NegotiationNote::BlockSizeDropped => "BKDR",
+            NegotiationNote::TransferSizeDropped => "TSDR",
+            NegotiationNote::TimeoutDropped => "TODR",
+            NegotiationNote::WindowsizeClamped => "WSCL",
+            NegotiationNote::WindowsizeDropped => "WSDR",
+            NegotiationNote::ChecksumDropped => "XSDR",
+            NegotiationNote::TimeoutClamped => "TOCL",
+        }
+    }
+
+    /// Returns what the note means, for people reading it.
+    pub fn description(&self) -> &'static str {
+        match self {
+            NegotiationNote::BlockSizeClamped => "blksize clamped",
+            NegotiationNote::BlockSizeDropped => "blksize dropped",
+            NegotiationNote::TransferSizeDropped => "tsize dropped",
+            NegotiationNote::TimeoutDropped => "timeout dropped",
+            NegotiationNote::WindowsizeClamped => "windowsize clamped",
+            NegotiationNote::WindowsizeDropped => "windowsize dropped",
+            NegotiationNote::ChecksumDropped => "xsum dropped",
+            NegotiationNote::TimeoutClamped => "timeout clamped",
+        }
+    }
+
+    /// Returns the note explaining the outcome of `option`, if it got less
+    /// than requested.
+    pub fn of(option: &NegotiatedOption) -> Option<NegotiationNote> {
+        match (option.option, option.outcome()) {
+            (OptionType::BlockSize, OptionOutcome::Clamped) => {
+                Some(NegotiationNote::BlockSizeClamped)
+            }
+            (OptionType::BlockSize, OptionOutcome::Dropped) => {
+                Some(NegotiationNote::BlockSizeDropped)
+            }
+            (OptionType::TransferSize, OptionOutcome::Dropped) => {
+                Some(NegotiationNote::TransferSizeDropped)
+            }
+            (OptionType::Timeout, OptionOutcome::Clamped) => Some(NegotiationNote::TimeoutClamped),
+            (OptionType::Timeout, OptionOutcome::Dropped) => Some(NegotiationNote::TimeoutDropped),
+            (OptionType::Windowsize, OptionOutcome::Clamped) => {
+                Some(NegotiationNote::WindowsizeClamped)
+            }
+            (OptionType::Windowsize, OptionOutcome::Dropped) => {
+                Some(NegotiationNote::WindowsizeDropped)
+            }
+            (OptionType::Checksum, OptionOutcome::Dropped) => {
+                Some(NegotiationNote::ChecksumDropped)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the notes explaining the outcomes of `negotiated`.
+    pub fn notes(negotiated: &[NegotiatedOption]) -> Vec<NegotiationNote> {
+        negotiated.iter().filter_map(NegotiationNote::of).collect()
+    }
+
+    /// Packs `notes` into the value of a [`OptionType::ServerNote`] option.
+    pub fn mask(notes: &[NegotiationNote]) -> u64 {
+        notes.iter().fold(0, |mask, note| mask | note.bit())
+    }
+
+    /// Unpacks the value of a [`OptionType::ServerNote`] option, unknown
+    /// bits being ignored.
+    pub fn from_mask(mask: u64) -> Vec<NegotiationNote> {
+        NegotiationNote::ALL
+            .into_iter()
+            .filter(|note| mask & note.bit() != 0)
+            .collect()
+    }
+
+    fn bit(&self) -> u64 {
+        1 << *self as u64
+    }
+}
+
+impl FromStr for NegotiationNote {
+    type Err = &'static str;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        NegotiationNote::ALL
+            .into_iter()
+            .find(|note| note.as_str() == code)
+            .ok_or("Unknown negotiation note")
+    }
+}
+
+impl fmt::Display for NegotiationNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Pairs every known option type with its `requested` and `granted` value.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{negotiated, OptionOutcome, OptionType, TransferOption};
+///
+/// let requested = [TransferOption { option: OptionType::BlockSize, value: 9000 }];
+/// let granted = [TransferOption { option: OptionType::BlockSize, value: 1432 }];
+///
+/// let options = negotiated(&requested, &granted);
+/// assert_eq!(options[0].outcome(), OptionOutcome::Clamped);
+/// ```
+pub fn negotiated(
+    requested: &[TransferOption],
+    granted: &[TransferOption],
+) -> Vec<NegotiatedOption> {
+    let value = |options: &[TransferOption], option| {
+        options
+            .iter()
+            .rev()
+            .find(|o| o.option == option)
+            .map(|o| o.value)
+    };
+
+    OPTION_TYPES
+        .iter()
+        .map(|&option| NegotiatedOption {
+            option,
+            requested: value(requested, option),
+            granted: value(granted, option),
+        })
+        .collect()
+}
+
+/// Returns the index of `option` in [`OPTION_TYPES`], if clients request
+/// it.
+#[cfg(feature = "server")]
+pub(crate) fn option_index(option: OptionType) -> Option<usize> {
+    match option {
+        OptionType::BlockSize => Some(0),
+        OptionType::TransferSize => Some(1),
+        OptionType::Timeout => Some(2),
+        OptionType::Windowsize => Some(3),
+        OptionType::Checksum => Some(4),
+        OptionType::Rollover => Some(5),
+        OptionType::ServerNote => None,
+    }
+}
+
+/// Sorts the options of an OACK in [`OACK_ORDER`].
+#[cfg(feature = "server")]
+pub(crate) fn sort_oack(options: &mut [TransferOption]) {
+    options.sort_by_key(|option| OACK_ORDER.iter().position(|&o| o == option.option));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(option: OptionType, value: u64) -> TransferOption {
+        TransferOption { option, value }
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn sorts_oack_options() {
+        let mut options = vec![
+            option(OptionType::ServerNote, 1),
+            option(OptionType::Rollover, 0),
+            option(OptionType::Windowsize, 8),
+            option(OptionType::Checksum, 1),
+            option(OptionType::TransferSize, 4096),
+            option(OptionType::Timeout, 3),
+            option(OptionType::BlockSize, 1468),
+        ];
+        sort_oack(&mut options);
+
+        let order: Vec<_> = options.iter().map(|option| option.option).collect();
+        assert_eq!(order, OACK_ORDER);
+    }
+
+    #[test]
+    fn notes_options_granted_less_than_requested() {
+        let requested = [
+            option(OptionType::BlockSize, 65464),
+            option(OptionType::TransferSize, 0),
+            option(OptionType::Timeout, 1),
+            option(OptionType::Windowsize, 8),
+        ];
+        let granted = [
+            option(OptionType::BlockSize, 1468),
+            option(OptionType::TransferSize, 4096),
+            option(OptionType::Windowsize, 8),
+        ];
+
+        let notes = NegotiationNote::notes(&negotiated(&requested, &granted));
+
+        assert_eq!(
+            notes,
+            vec![
+                NegotiationNote::BlockSizeClamped,
+                NegotiationNote::TimeoutDropped
+            ]
+        );
+        let mask = NegotiationNote::mask(&notes);
+        assert_eq!(mask, 0b1001);
+        assert_eq!(NegotiationNote::from_mask(mask | 1 << 40), notes);
+        for note in NegotiationNote::ALL {
+            assert_eq!(note.as_str().parse(), Ok(note));
+        }
+    }
+
+    #[test]
+    fn classifies_outcomes() {
+        let requested = [
+            option(OptionType::BlockSize, 65464),
+            option(OptionType::TransferSize, 0),
+            option(OptionType::Windowsize, 8),
+        ];
+        let granted = [
+            option(OptionType::BlockSize, 1468),
+            option(OptionType::Windowsize, 8),
+        ];
+
+        let outcomes: Vec<_> = negotiated(&requested, &granted)
+            .iter()
+            .map(|n| (n.option, n.outcome()))
+            .collect();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (OptionType::BlockSize, OptionOutcome::Clamped),
+                (OptionType::TransferSize, OptionOutcome::Dropped),
+                (OptionType::Timeout, OptionOutcome::Absent),
+                (OptionType::Windowsize, OptionOutcome::Granted),
+                (OptionType::Checksum, OptionOutcome::Absent),
+                (OptionType::Rollover, OptionOutcome::Absent),
+            ]
+        );
+    }
+
+    #[test]
+    fn transfer_size_answer_is_granted() {
+        let negotiated = negotiated(
+            &[option(OptionType::TransferSize, 0)],
+            &[option(OptionType::TransferSize, 4096)],
+        );
+
+        assert_eq!(negotiated[1].outcome(), OptionOutcome::Granted);
+    }
+
+    #[test]
+    fn validates_limits() {
+        let default = OptionLimits::default();
+        let cases = [
+            (default.clone(), true),
+            (
+                OptionLimits {
+                    max_blksize: MIN_BLOCK_SIZE,
+                    max_windowsize: 1,
+                    max_window_bytes: Some(MIN_BLOCK_SIZE),
+                    min_timeout: 255,
+                    ..default.clone()
+                },
+                true,
+            ),
+            (
+                OptionLimits {
+                    max_blksize: 4,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_blksize: MAX_BLOCK_SIZE + 1,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_windowsize: MAX_WINDOWSIZE + 1,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_windowsize: 0,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_window_bytes: Some(0),
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_retransmit_rate: Some(1),
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    min_timeout: 0,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    min_timeout: 10,
+                    max_timeout: 5,
+                    ..default.clone()
+                },
+                false,
+            ),
+            (
+                OptionLimits {
+                    max_timeout: 256,
+                    ..default.clone()
+                },
+                false,
+            ),
+        ];
+
+        for (limits, valid) in cases {
+            assert_eq!(limits.validate().is_ok(), valid, "{limits:?}");
+        }
+    }
+
+    #[test]
+    fn limits_window_by_bytes() {
+        let limits = OptionLimits {
+            max_windowsize: 32,
+            max_window_bytes: Some(64 * 1024),
+            ..OptionLimits::default()
+        };
+
+        assert_eq!(limits.max_windowsize_for(512), 32);
+        assert_eq!(limits.max_windowsize_for(8192), 8);
+        assert_eq!(limits.max_windowsize_for(65464), 1);
+        assert_eq!(
+            OptionLimits::default().max_windowsize_for(512),
+            MAX_WINDOWSIZE
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{self, Read};
+
+/// Size of the chunks read from the file being translated.
+const CHUNK_SIZE: usize = 4096;
+
+/// Transfer mode of a read request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// Bytes sent as they are
+    Octet,
+    /// Text sent with the line endings of RFC 1350, see [`NetasciiReader`]
+    Netascii,
+}
+
+impl Mode {
+    /// Parses the mode of a request, which is case insensitive. The `mail`
+    /// mode, obsoleted by RFC 1350 itself, and unknown modes are `None`.
+    pub(crate) fn parse(mode: &str) -> Option<Mode> {
+        if mode.eq_ignore_ascii_case("octet") {
+            Some(Mode::Octet)
+        } else if mode.eq_ignore_ascii_case("netascii") {
+            Some(Mode::Netascii)
+        } else {
+            None
+        }
+    }
+}
+
+/// NetasciiReader `struct` reads a text file as netascii: every LF becomes
+/// CR LF and every bare CR becomes CR NUL. The translated stream is longer
+/// than the file, so it is cut into blocks as it is read and a pair may
+/// straddle two blocks.
+#[derive(Debug)]
+pub(crate) struct NetasciiReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    /// Translated up to here
+    pos: usize,
+    /// Read from `inner` up to here
+    len: usize,
+    /// Second byte of a pair that did not fit in the last read
+    pending: Option<u8>,
+}
+
+impl<R: Read> NetasciiReader<R> {
+    pub(crate) fn new(inner: R) -> NetasciiReader<R> {
+        NetasciiReader {
+            inner,
+            buf: vec![0; CHUNK_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            pending: None,
+        }
+    }
+}
+
+impl<R: Read> Read for NetasciiReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < out.len() {
+            if let Some(byte) = self.pending.take() {
+                out[written] = byte;
+                written += 1;
+                continue;
+            }
+            if self.pos == self.len {
+                // An error after translating some bytes would lose them.
+                if written > 0 {
+                    break;
+                }
+                self.len = self.inner.read(&mut self.buf)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    break;
+                }
+            }
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            let (first, second) = match byte {
+                b'\n' => (b'\r', Some(b'\n')),
+                b'\r' => (b'\r', Some(0)),
+                byte => (byte, None),
+            };
+            out[written] = first;
+            written += 1;
+            self.pending = second;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Translates `text` in blocks of `blk_size` bytes, as a transfer does.
+    fn blocks(text: &[u8], blk_size: usize) -> Vec<Vec<u8>> {
+        let mut reader = NetasciiReader::new(text);
+        let mut blocks = vec![];
+        loop {
+            let mut block = vec![0; blk_size];
+            let mut read = 0;
+            while read < blk_size {
+                match reader.read(&mut block[read..]).unwrap() {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+            block.truncate(read);
+            blocks.push(block);
+            if read < blk_size {
+                return blocks;
+            }
+        }
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(Mode::parse("octet"), Some(Mode::Octet));
+        assert_eq!(Mode::parse("NetASCII"), Some(Mode::Netascii));
+        assert_eq!(Mode::parse("mail"), None);
+        assert_eq!(Mode::parse("binary"), None);
+    }
+
+    #[test]
+    fn translates_line_endings() {
+        assert_eq!(
+            blocks(b"a\nb\rc\r\nd", 512),
+            [b"a\r\nb\r\0c\r\0\r\nd".to_vec()]
+        );
+    }
+
+    #[test]
+    fn translates_trailing_lf() {
+        assert_eq!(blocks(b"line\n", 512), [b"line\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn translates_trailing_cr() {
+        assert_eq!(blocks(b"line\r", 512), [b"line\r\0".to_vec()]);
+    }
+
+    #[test]
+    fn splits_pair_across_blocks() {
+        let mut text = vec![b'x'; 3];
+        text.push(b'\n');
+
+        assert_eq!(blocks(&text, 4), [b"xxx\r".to_vec(), b"\n".to_vec()]);
+    }
+
+    #[test]
+    fn ends_with_empty_block_at_boundary() {
+        let mut text = vec![b'x'; 510];
+        text.push(b'\n');
+
+        let blocks = blocks(&text, 512);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(&blocks[0][510..], b"\r\n");
+        assert!(blocks[1].is_empty());
+    }
+
+    #[test]
+    fn translates_beyond_a_chunk() {
+        let text = vec![b'\n'; CHUNK_SIZE + 1];
+        let translated: Vec<u8> = blocks(&text, 512).concat();
+
+        assert_eq!(translated.len(), 2 * text.len());
+        assert!(translated.chunks(2).all(|pair| pair == b"\r\n"));
+    }
+}
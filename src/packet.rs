@@ -1,5 +1,19 @@
-use crate::Convert;
-use std::{error::Error, fmt, str::FromStr};
+use crate::{ChecksumAlgorithm, Convert, NegotiationNote};
+use std::{borrow::Cow, error::Error, fmt, str::FromStr};
+
+/// Largest DATA payload, the maximum `blksize` of RFC 2348.
+pub(crate) const MAX_BLOCK_SIZE: usize = 65464;
+
+/// Largest request, the size of the buffer requests are received in.
+pub(crate) const MAX_REQUEST_SIZE: usize = 512;
+
+/// Largest ERROR message in bytes, so that with its header and terminating
+/// NUL the datagram fits the 516 bytes of a default DATA packet, which is
+/// all some clients have room for.
+pub(crate) const MAX_ERROR_MESSAGE_SIZE: usize = 511;
+
+/// Appended to ERROR messages cut at [`MAX_ERROR_MESSAGE_SIZE`].
+const ELLIPSIS: &str = "\u{2026}";
 
 /// Packet `enum` represents the valid TFTP packet types.
 ///
@@ -15,7 +29,7 @@ use std::{error::Error, fmt, str::FromStr};
 /// assert_eq!(packet.serialize().unwrap(), vec![0x00, 0x03, 0x00, 0x0F, 0x01, 0x02, 0x03]);
 /// assert_eq!(Packet::deserialize(&[0x00, 0x03, 0x00, 0x0F, 0x01, 0x02, 0x03]).unwrap(), packet);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Packet {
     /// Read Request `struct`
     Rrq {
@@ -53,34 +67,109 @@ pub enum Packet {
     },
     /// Option acknowledgement `tuple` with transfer options
     Oack(Vec<TransferOption>),
+    /// Checksum `tuple` with the digest of the whole file, sent after its
+    /// last block was acknowledged when the `xsum` option was negotiated
+    Checksum(Vec<u8>),
 }
 
 impl Packet {
     /// Deserializes a [`u8`] slice into a [`Packet`].
+    ///
+    /// Trailing bytes of a request that do not form a complete
+    /// zero-terminated option name and value pair are ignored. Filenames are
+    /// kept byte-exact, including any whitespace.
     pub fn deserialize(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
-        let opcode = Opcode::from_u16(Convert::to_u16(&buf[0..=1])?)?;
-
-        match opcode {
-            Opcode::Rrq | Opcode::Wrq => parse_rq(buf, opcode),
-            Opcode::Data => parse_data(buf),
-            Opcode::Ack => parse_ack(buf),
-            Opcode::Error => parse_error(buf),
-            _ => Err("Invalid packet".into()),
-        }
+        deserialize(buf, false)
+    }
+
+    /// Deserializes a [`u8`] slice into a [`Packet`] like
+    /// [`Packet::deserialize()`], but rejects requests with trailing bytes.
+    pub fn deserialize_strict(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
+        deserialize(buf, true)
     }
 
     /// Serializes a [`Packet`] into a [`Vec<u8>`].
+    ///
+    /// Fails for DATA payloads larger than 65464 bytes, the maximum block
+    /// size, for requests larger than 512 bytes, which a server may not
+    /// receive whole, and for OACKs without any option, which must never be
+    /// sent. ERROR messages longer than 511 bytes are cut and end with `…`,
+    /// so that the datagram never exceeds 516 bytes.
     pub fn serialize(&self) -> Result<Vec<u8>, &'static str> {
         match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+            } => serialize_rq(Opcode::Rrq, filename, mode, options),
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+            } => serialize_rq(Opcode::Wrq, filename, mode, options),
+            Packet::Data { data, .. } if data.len() > MAX_BLOCK_SIZE => {
+                Err("Data payload larger than the maximum block size")
+            }
             Packet::Data { block_num, data } => Ok(serialize_data(block_num, data)),
             Packet::Ack(block_num) => Ok(serialize_ack(block_num)),
             Packet::Error { code, msg } => Ok(serialize_error(code, msg)),
+            Packet::Oack(options) if options.is_empty() => {
+                Err("Option acknowledgement without options")
+            }
             Packet::Oack(options) => Ok(serialize_oack(options)),
-            _ => Err("Invalid packet"),
+            Packet::Checksum(digest) => Ok(serialize_checksum(digest)),
         }
     }
 }
 
+/// Maximum number of characters of a client string shown by [`Escaped`].
+const MAX_DISPLAYED_CHARS: usize = 128;
+
+/// Escaped `struct` displays a string received from a client with its
+/// control characters escaped, so that a newline or terminal escape cannot
+/// forge or garble a log line, cut after a number of characters.
+pub(crate) struct Escaped<'a> {
+    text: &'a str,
+    max_chars: usize,
+}
+
+impl<'a> Escaped<'a> {
+    /// Shows at most [`MAX_DISPLAYED_CHARS`] characters of `text`.
+    pub(crate) fn bounded(text: &'a str) -> Escaped<'a> {
+        Escaped {
+            text,
+            max_chars: MAX_DISPLAYED_CHARS,
+        }
+    }
+
+    /// Shows the whole of `text`.
+    #[cfg(feature = "server")]
+    pub(crate) fn whole(text: &'a str) -> Escaped<'a> {
+        Escaped {
+            text,
+            max_chars: usize::MAX,
+        }
+    }
+}
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (count, (offset, c)) in self.text.char_indices().enumerate() {
+            if count == self.max_chars {
+                return write!(f, "{ELLIPSIS}({} more bytes)", self.text.len() - offset);
+            }
+            if c.is_control() {
+                write!(f, "{}", c.escape_default())?;
+            } else {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shows the strings of the packet escaped and bounded with `Escaped`,
+/// and the payload of DATA as its length.
 impl fmt::Display for Packet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -91,7 +180,9 @@ impl fmt::Display for Packet {
             } => write!(
                 f,
                 "Rrq {{ filename: {}, mode: {}, options: {:?} }}",
-                filename, mode, options
+                Escaped::bounded(filename),
+                Escaped::bounded(mode),
+                options
             ),
             Packet::Wrq {
                 filename,
@@ -100,14 +191,27 @@ impl fmt::Display for Packet {
             } => write!(
                 f,
                 "Wrq {{ filename: {}, mode: {}, options: {:?} }}",
-                filename, mode, options
+                Escaped::bounded(filename),
+                Escaped::bounded(mode),
+                options
             ),
             Packet::Data { block_num, data } => {
-                write!(f, "Data {{ block_num: {}, data: {:?} }}", block_num, data)
+                write!(
+                    f,
+                    "Data {{ block_num: {}, data: {} bytes }}",
+                    block_num,
+                    data.len()
+                )
             }
             Packet::Ack(block_num) => write!(f, "Ack({})", block_num),
-            Packet::Error { code, msg } => write!(f, "Error {{ code: {}, msg: {} }}", code, msg),
+            Packet::Error { code, msg } => write!(
+                f,
+                "Error {{ code: {}, msg: {} }}",
+                code,
+                Escaped::bounded(msg)
+            ),
             Packet::Oack(options) => write!(f, "Oack({:?})", options),
+            Packet::Checksum(digest) => write!(f, "Checksum({:02x?})", digest),
         }
     }
 }
@@ -126,7 +230,7 @@ impl fmt::Display for Packet {
 /// assert_eq!(Opcode::Ack.as_bytes(), [0x00, 0x04]);
 /// ```
 #[repr(u16)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Opcode {
     /// Read request opcode
     Rrq = 0x0001,
@@ -140,6 +244,8 @@ pub enum Opcode {
     Error = 0x0005,
     /// Option acknowledgement opcode
     Oack = 0x0006,
+    /// Checksum opcode, outside the range of the standard opcodes
+    Checksum = 0x0100,
 }
 
 impl Opcode {
@@ -152,6 +258,7 @@ impl Opcode {
             0x0004 => Ok(Opcode::Ack),
             0x0005 => Ok(Opcode::Error),
             0x0006 => Ok(Opcode::Oack),
+            0x0100 => Ok(Opcode::Checksum),
             _ => Err("Invalid opcode"),
         }
     }
@@ -177,21 +284,37 @@ impl Opcode {
 ///     0x00,
 /// ]);
 /// ```
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct TransferOption {
     /// Type of the option
     pub option: OptionType,
-    /// Value of the option
-    pub value: usize,
+    /// Value of the option, wide enough for the size of any file
+    pub value: u64,
 }
 
 impl TransferOption {
     /// Converts a [`TransferOption`] to a [`Vec<u8>`].
+    ///
+    /// The value of [`OptionType::Checksum`] is written as the name of its
+    /// [`ChecksumAlgorithm`], the one of [`OptionType::ServerNote`] as the
+    /// codes of its [`NegotiationNote`]s.
     pub fn as_bytes(&self) -> Vec<u8> {
+        let value = match self.option {
+            OptionType::Checksum => match ChecksumAlgorithm::from_value(self.value) {
+                Some(algorithm) => algorithm.as_str().to_string(),
+                None => self.value.to_string(),
+            },
+            OptionType::ServerNote => NegotiationNote::from_mask(self.value)
+                .iter()
+                .map(NegotiationNote::as_str)
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => self.value.to_string(),
+        };
         [
             self.option.as_str().as_bytes(),
             &[0x00],
-            self.value.to_string().as_bytes(),
+            value.as_bytes(),
             &[0x00],
         ]
         .concat()
@@ -211,16 +334,33 @@ impl TransferOption {
 /// assert_eq!(OptionType::BlockSize, "blksize".parse().unwrap());
 /// assert_eq!("tsize", OptionType::TransferSize.as_str());
 /// ```
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OptionType {
     /// Block Size option type
+    #[cfg_attr(feature = "serde", serde(rename = "blksize"))]
     BlockSize,
     /// Transfer Size option type
+    #[cfg_attr(feature = "serde", serde(rename = "tsize"))]
     TransferSize,
     /// Timeout option type
+    #[cfg_attr(feature = "serde", serde(rename = "timeout"))]
     Timeout,
     /// Windowsize option type
+    #[cfg_attr(feature = "serde", serde(rename = "windowsize"))]
     Windowsize,
+    /// Nonstandard checksum option type, valued with a
+    /// [`ChecksumAlgorithm`]
+    #[cfg_attr(feature = "serde", serde(rename = "xsum"))]
+    Checksum,
+    /// Nonstandard option of the OACK explaining the options granted less
+    /// than requested, valued with a mask of [`NegotiationNote`]s
+    #[cfg_attr(feature = "serde", serde(rename = "srvnote"))]
+    ServerNote,
+    /// Nonstandard option choosing the block number following 65535, 0 or
+    /// 1
+    #[cfg_attr(feature = "serde", serde(rename = "rollover"))]
+    Rollover,
 }
 
 impl OptionType {
@@ -231,6 +371,9 @@ impl OptionType {
             OptionType::TransferSize => "tsize",
             OptionType::Timeout => "timeout",
             OptionType::Windowsize => "windowsize",
+            OptionType::Checksum => "xsum",
+            OptionType::ServerNote => "srvnote",
+            OptionType::Rollover => "rollover",
         }
     }
 }
@@ -245,6 +388,9 @@ impl FromStr for OptionType {
             "tsize" => Ok(OptionType::TransferSize),
             "timeout" => Ok(OptionType::Timeout),
             "windowsize" => Ok(OptionType::Windowsize),
+            "xsum" => Ok(OptionType::Checksum),
+            "srvnote" => Ok(OptionType::ServerNote),
+            "rollover" => Ok(OptionType::Rollover),
             _ => Err("Invalid option type"),
         }
     }
@@ -264,7 +410,7 @@ impl FromStr for OptionType {
 /// assert_eq!(ErrorCode::FileExists.as_bytes(), [0x00, 0x06]);
 /// ```
 #[repr(u16)]
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ErrorCode {
     /// Not Defined error code
     NotDefined = 0,
@@ -282,6 +428,8 @@ pub enum ErrorCode {
     FileExists = 6,
     /// No such user error code
     NoSuchUser = 7,
+    /// Option negotiation error code of RFC 2347
+    OptionNegotiation = 8,
 }
 
 impl ErrorCode {
@@ -296,6 +444,7 @@ impl ErrorCode {
             5 => Ok(ErrorCode::UnknownId),
             6 => Ok(ErrorCode::FileExists),
             7 => Ok(ErrorCode::NoSuchUser),
+            8 => Ok(ErrorCode::OptionNegotiation),
             _ => Err("Invalid error code"),
         }
     }
@@ -317,12 +466,25 @@ impl fmt::Display for ErrorCode {
             ErrorCode::UnknownId => write!(f, "Unknown ID"),
             ErrorCode::FileExists => write!(f, "File Exists"),
             ErrorCode::NoSuchUser => write!(f, "No Such User"),
+            ErrorCode::OptionNegotiation => write!(f, "Option Negotiation"),
         }
     }
 }
 
-fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
-    let mut options = vec![];
+fn deserialize(buf: &[u8], strict: bool) -> Result<Packet, Box<dyn Error>> {
+    let opcode = Opcode::from_u16(Convert::to_u16(&buf[0..=1])?)?;
+
+    match opcode {
+        Opcode::Rrq | Opcode::Wrq => parse_rq(buf, opcode, strict),
+        Opcode::Data => parse_data(buf),
+        Opcode::Ack => parse_ack(buf),
+        Opcode::Error => parse_error(buf),
+        Opcode::Oack => parse_oack(buf),
+        Opcode::Checksum => Ok(Packet::Checksum(buf[2..].to_vec())),
+    }
+}
+
+fn parse_rq(buf: &[u8], opcode: Opcode, strict: bool) -> Result<Packet, Box<dyn Error>> {
     let filename: String;
     let mode: String;
     let mut zero_index: usize;
@@ -330,18 +492,9 @@ fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
     (filename, zero_index) = Convert::to_string(buf, 2)?;
     (mode, zero_index) = Convert::to_string(buf, zero_index + 1)?;
 
-    let mut value: String;
-    let mut option;
-    while zero_index < buf.len() - 1 {
-        (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
-            options.push(TransferOption {
-                option,
-                value: value.parse()?,
-            });
-        }
+    let (options, trailing) = parse_options(buf, zero_index, true)?;
+    if strict && trailing > 0 {
+        return Err(format!("{trailing} trailing bytes after request").into());
     }
 
     match opcode {
@@ -382,6 +535,97 @@ fn parse_error(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
     }
 }
 
+fn parse_oack(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
+    match parse_options(buf, 1, false)? {
+        (options, 0) => Ok(Packet::Oack(options)),
+        _ => Err("Incomplete option".into()),
+    }
+}
+
+/// Parses the option name and value pairs following the zero byte at
+/// `zero_index`, skipping unknown options, checksums with an unknown
+/// algorithm and server notes without a known code. Also returns the number of trailing bytes not forming a
+/// complete pair.
+///
+/// Values that are not numbers fail the packet, unless `skip_invalid`: a
+/// request keeps the options the server can honor, as RFC 2347 asks, while
+/// an OACK must be answered with an ERROR.
+fn parse_options(
+    buf: &[u8],
+    mut zero_index: usize,
+    skip_invalid: bool,
+) -> Result<(Vec<TransferOption>, usize), Box<dyn Error>> {
+    let mut options = vec![];
+
+    let mut value: String;
+    let mut option;
+    while buf[zero_index + 1..]
+        .iter()
+        .filter(|&&b| b == 0)
+        .nth(1)
+        .is_some()
+    {
+        (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
+        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
+
+        match OptionType::from_str(option.to_lowercase().as_str()) {
+            Ok(OptionType::Checksum) => {
+                if let Ok(algorithm) = ChecksumAlgorithm::from_str(&value.to_lowercase()) {
+                    options.push(TransferOption {
+                        option: OptionType::Checksum,
+                        value: algorithm.value(),
+                    });
+                }
+            }
+            Ok(OptionType::ServerNote) => {
+                let notes: Vec<_> = value
+                    .split(',')
+                    .filter_map(|code| NegotiationNote::from_str(code.trim()).ok())
+                    .collect();
+                if !notes.is_empty() {
+                    options.push(TransferOption {
+                        option: OptionType::ServerNote,
+                        value: NegotiationNote::mask(&notes),
+                    });
+                }
+            }
+            Ok(option) => match value.parse() {
+                Ok(value) => options.push(TransferOption { option, value }),
+                Err(_) if skip_invalid => {}
+                Err(err) => return Err(err.into()),
+            },
+            Err(_) => {}
+        }
+    }
+
+    Ok((options, buf.len() - zero_index - 1))
+}
+
+fn serialize_rq(
+    opcode: Opcode,
+    filename: &String,
+    mode: &String,
+    options: &Vec<TransferOption>,
+) -> Result<Vec<u8>, &'static str> {
+    let mut buf = [
+        &opcode.as_bytes(),
+        filename.as_bytes(),
+        &[0x00],
+        mode.as_bytes(),
+        &[0x00],
+    ]
+    .concat();
+
+    for option in options {
+        buf = [buf, option.as_bytes()].concat();
+    }
+
+    if buf.len() > MAX_REQUEST_SIZE {
+        return Err("Request larger than 512 bytes");
+    }
+    Ok(buf)
+}
+
 fn serialize_data(block_num: &u16, data: &Vec<u8>) -> Vec<u8> {
     [
         &Opcode::Data.as_bytes(),
@@ -395,16 +639,30 @@ fn serialize_ack(block_num: &u16) -> Vec<u8> {
     [Opcode::Ack.as_bytes(), block_num.to_be_bytes()].concat()
 }
 
-fn serialize_error(code: &ErrorCode, msg: &String) -> Vec<u8> {
+fn serialize_error(code: &ErrorCode, msg: &str) -> Vec<u8> {
     [
         &Opcode::Error.as_bytes()[..],
         &code.as_bytes()[..],
-        msg.as_bytes(),
+        truncate_message(msg).as_bytes(),
         &[0x00],
     ]
     .concat()
 }
 
+/// Cuts `msg` on a character boundary to fit in [`MAX_ERROR_MESSAGE_SIZE`]
+/// bytes, [`ELLIPSIS`] included.
+fn truncate_message(msg: &str) -> Cow<'_, str> {
+    if msg.len() <= MAX_ERROR_MESSAGE_SIZE {
+        return Cow::Borrowed(msg);
+    }
+
+    let mut end = MAX_ERROR_MESSAGE_SIZE - ELLIPSIS.len();
+    while !msg.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}{ELLIPSIS}", &msg[..end]))
+}
+
 fn serialize_oack(options: &Vec<TransferOption>) -> Vec<u8> {
     let mut buf = Opcode::Oack.as_bytes().to_vec();
 
@@ -415,10 +673,52 @@ fn serialize_oack(options: &Vec<TransferOption>) -> Vec<u8> {
     buf
 }
 
+fn serialize_checksum(digest: &[u8]) -> Vec<u8> {
+    [&Opcode::Checksum.as_bytes(), digest].concat()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn displays_client_strings_escaped_on_one_line() {
+        let request = Packet::Rrq {
+            filename: "boot.img\n[forged] admin login\x1b[2J".to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+        };
+
+        let shown = request.to_string();
+        assert_eq!(
+            shown,
+            "Rrq { filename: boot.img\\n[forged] admin login\\u{1b}[2J, mode: octet, options: [] }"
+        );
+        assert!(!shown.contains('\n'));
+
+        let error = Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "x".repeat(300),
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Error {{ code: Not Defined, msg: {}{ELLIPSIS}(172 more bytes) }}",
+                "x".repeat(128)
+            )
+        );
+    }
+
+    #[test]
+    fn summarizes_data_payload() {
+        let data = Packet::Data {
+            block_num: 7,
+            data: vec![0x1b; 1024],
+        };
+
+        assert_eq!(data.to_string(), "Data { block_num: 7, data: 1024 bytes }");
+    }
+
     #[test]
     fn parses_read_request() {
         let buf = [
@@ -434,7 +734,7 @@ mod tests {
             filename,
             mode,
             options,
-        }) = parse_rq(&buf, Opcode::Rrq)
+        }) = parse_rq(&buf, Opcode::Rrq, true)
         {
             assert_eq!(filename, "test.png");
             assert_eq!(mode, "octet");
@@ -471,7 +771,7 @@ mod tests {
             filename,
             mode,
             options,
-        }) = parse_rq(&buf, Opcode::Rrq)
+        }) = parse_rq(&buf, Opcode::Rrq, true)
         {
             assert_eq!(filename, "test.png");
             assert_eq!(mode, "octet");
@@ -517,7 +817,7 @@ mod tests {
             filename,
             mode,
             options,
-        }) = parse_rq(&buf, Opcode::Wrq)
+        }) = parse_rq(&buf, Opcode::Wrq, true)
         {
             assert_eq!(filename, "test.png");
             assert_eq!(mode, "octet");
@@ -550,7 +850,7 @@ mod tests {
             filename,
             mode,
             options,
-        }) = parse_rq(&buf, Opcode::Wrq)
+        }) = parse_rq(&buf, Opcode::Wrq, true)
         {
             assert_eq!(filename, "test.png");
             assert_eq!(mode, "octet");
@@ -667,10 +967,7 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_error(
-                &ErrorCode::IllegalOperation,
-                &"illegal operation".to_string()
-            ),
+            serialize_error(&ErrorCode::IllegalOperation, "illegal operation"),
             serialized_error
         );
     }
@@ -690,4 +987,461 @@ mod tests {
             serialized_oack
         );
     }
+
+    #[test]
+    fn parses_checksum_option_by_algorithm_name() {
+        let request = [
+            &Opcode::Rrq.as_bytes()[..],
+            b"test.png\0octet\0",
+            b"xsum\0CRC32C\0",
+            b"xsum\0md5\0",
+        ]
+        .concat();
+        let option = TransferOption {
+            option: OptionType::Checksum,
+            value: ChecksumAlgorithm::Crc32c.value(),
+        };
+
+        assert_eq!(
+            Packet::deserialize(&request).unwrap(),
+            Packet::Rrq {
+                filename: "test.png".to_string(),
+                mode: "octet".to_string(),
+                options: vec![option],
+            }
+        );
+        assert_eq!(option.as_bytes(), b"xsum\0crc32c\0");
+    }
+
+    #[test]
+    fn round_trips_checksum() {
+        let packet = Packet::Checksum(vec![0xE3, 0x06, 0x92, 0x83]);
+        let serialized = packet.serialize().unwrap();
+
+        assert_eq!(serialized, [0x01, 0x00, 0xE3, 0x06, 0x92, 0x83]);
+        assert_eq!(Packet::deserialize(&serialized).unwrap(), packet);
+    }
+
+    #[test]
+    fn parses_server_note_codes() {
+        let oack = b"\x00\x06blksize\x001468\x00srvnote\x00BKCL,ZZZZ,TSDR\x00";
+        let expected = Packet::Oack(vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1468,
+            },
+            TransferOption {
+                option: OptionType::ServerNote,
+                value: NegotiationNote::mask(&[
+                    NegotiationNote::BlockSizeClamped,
+                    NegotiationNote::TransferSizeDropped,
+                ]),
+            },
+        ]);
+
+        assert_eq!(Packet::deserialize(oack).unwrap(), expected);
+        assert_eq!(Packet::deserialize_strict(oack).unwrap(), expected);
+        // Unknown codes are dropped, and the note with them.
+        assert_eq!(
+            Packet::deserialize(b"\x00\x06blksize\x001468\x00srvnote\x00ZZZZ\x00").unwrap(),
+            Packet::Oack(vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 1468,
+            }])
+        );
+    }
+
+    #[test]
+    fn golden_serializes_read_request() {
+        let packet = Packet::Rrq {
+            filename: "pxelinux.0".to_string(),
+            mode: "octet".to_string(),
+            options: vec![
+                TransferOption {
+                    option: OptionType::TransferSize,
+                    value: 0,
+                },
+                TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1468,
+                },
+            ],
+        };
+        let golden = b"\x00\x01pxelinux.0\x00octet\x00tsize\x000\x00blksize\x001468\x00".to_vec();
+
+        assert_eq!(packet.serialize().unwrap(), golden);
+        assert_eq!(Packet::deserialize(&golden).unwrap(), packet);
+    }
+
+    #[test]
+    fn golden_serializes_data() {
+        assert_eq!(
+            Packet::Data {
+                block_num: 1,
+                data: vec![],
+            }
+            .serialize()
+            .unwrap(),
+            b"\x00\x03\x00\x01".to_vec()
+        );
+
+        assert_eq!(
+            Packet::Data {
+                block_num: 0x1234,
+                data: vec![0xAB],
+            }
+            .serialize()
+            .unwrap(),
+            b"\x00\x03\x12\x34\xAB".to_vec()
+        );
+
+        let full = Packet::Data {
+            block_num: 0xFFFF,
+            data: vec![0x5A; 512],
+        }
+        .serialize()
+        .unwrap();
+        assert_eq!(full.len(), 516);
+        assert_eq!(&full[..4], b"\x00\x03\xFF\xFF");
+        assert!(full[4..].iter().all(|&b| b == 0x5A));
+    }
+
+    #[test]
+    fn golden_serializes_data_at_maximum_block_size() {
+        let packet = Packet::Data {
+            block_num: 7,
+            data: vec![0xC3; MAX_BLOCK_SIZE],
+        };
+
+        let serialized = packet.serialize().unwrap();
+        assert_eq!(serialized.len(), 65468);
+        assert_eq!(&serialized[..4], b"\x00\x03\x00\x07");
+        assert_eq!(Packet::deserialize(&serialized).unwrap(), packet);
+
+        assert!(Packet::Data {
+            block_num: 7,
+            data: vec![0xC3; MAX_BLOCK_SIZE + 1],
+        }
+        .serialize()
+        .is_err());
+    }
+
+    #[test]
+    fn refuses_to_serialize_empty_oack() {
+        assert!(Packet::Oack(vec![]).serialize().is_err());
+    }
+
+    #[test]
+    fn golden_serializes_ack() {
+        assert_eq!(
+            Packet::Ack(0).serialize().unwrap(),
+            b"\x00\x04\x00\x00".to_vec()
+        );
+        assert_eq!(
+            Packet::Ack(0xFFFE).serialize().unwrap(),
+            b"\x00\x04\xFF\xFE".to_vec()
+        );
+    }
+
+    #[test]
+    fn golden_serializes_error() {
+        assert_eq!(
+            Packet::Error {
+                code: ErrorCode::FileNotFound,
+                msg: "file does not exist".to_string(),
+            }
+            .serialize()
+            .unwrap(),
+            b"\x00\x05\x00\x01file does not exist\x00".to_vec()
+        );
+
+        assert_eq!(
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: String::new(),
+            }
+            .serialize()
+            .unwrap(),
+            b"\x00\x05\x00\x00\x00".to_vec()
+        );
+    }
+
+    #[test]
+    fn golden_truncates_long_error_message() {
+        let packet = Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "x".repeat(1000),
+        };
+        let golden = [
+            b"\x00\x05\x00\x00".as_slice(),
+            "x".repeat(508).as_bytes(),
+            "\u{2026}".as_bytes(),
+            b"\x00",
+        ]
+        .concat();
+
+        let buf = packet.serialize().unwrap();
+        assert_eq!(buf, golden);
+        assert_eq!(buf.len(), 516);
+        assert_eq!(
+            Packet::deserialize(&buf).unwrap(),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: format!("{}\u{2026}", "x".repeat(508)),
+            }
+        );
+    }
+
+    #[test]
+    fn truncates_error_message_on_character_boundary() {
+        for msg in ["\u{e9}".repeat(600), format!("x{}", "\u{e9}".repeat(600))] {
+            let buf = Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: msg.clone(),
+            }
+            .serialize()
+            .unwrap();
+
+            assert!(buf.len() <= 516);
+            assert_eq!(buf.last(), Some(&0));
+            let Packet::Error { msg: truncated, .. } = Packet::deserialize(&buf).unwrap() else {
+                panic!("expected an error");
+            };
+            let kept = truncated.strip_suffix('\u{2026}').unwrap();
+            assert!(msg.starts_with(kept));
+        }
+
+        let msg = "x".repeat(MAX_ERROR_MESSAGE_SIZE);
+        let buf = Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: msg.clone(),
+        }
+        .serialize()
+        .unwrap();
+        assert_eq!(buf[4..buf.len() - 1], *msg.as_bytes());
+    }
+
+    #[test]
+    fn rejects_oversized_requests() {
+        let request = |filename: String| Packet::Rrq {
+            filename,
+            mode: "octet".to_string(),
+            options: vec![],
+        };
+
+        // Opcode, filename, NUL, "octet" and NUL.
+        let longest = "f".repeat(MAX_REQUEST_SIZE - 2 - 1 - 5 - 1);
+        assert_eq!(
+            request(longest.clone()).serialize().unwrap().len(),
+            MAX_REQUEST_SIZE
+        );
+        assert!(request(format!("{longest}f")).serialize().is_err());
+    }
+
+    #[test]
+    fn golden_serializes_oack() {
+        let packet = Packet::Oack(vec![
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 1048576,
+            },
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1468,
+            },
+            TransferOption {
+                option: OptionType::Windowsize,
+                value: 8,
+            },
+        ]);
+
+        let golden = b"\x00\x06tsize\x001048576\x00blksize\x001468\x00windowsize\x008\x00".to_vec();
+
+        assert_eq!(packet.serialize().unwrap(), golden);
+        assert_eq!(Packet::deserialize(&golden).unwrap(), packet);
+    }
+
+    #[test]
+    fn golden_round_trips_tsize_beyond_32_bits() {
+        let packet = Packet::Oack(vec![TransferOption {
+            option: OptionType::TransferSize,
+            value: (5 << 32) + 7,
+        }]);
+
+        let golden = b"\x00\x06tsize\x0021474836487\x00".to_vec();
+
+        assert_eq!(packet.serialize().unwrap(), golden);
+        assert_eq!(Packet::deserialize(&golden).unwrap(), packet);
+        assert!(Packet::deserialize(b"\x00\x06tsize\x0018446744073709551616\x00").is_err());
+    }
+
+    #[test]
+    fn golden_deserializes_u_boot_request() {
+        let captured =
+            b"\x00\x01boot.scr.uimg\x00octet\x00timeout\x005\x00tsize\x000\x00blksize\x001468\x00";
+
+        assert_eq!(
+            Packet::deserialize(captured).unwrap(),
+            Packet::Rrq {
+                filename: "boot.scr.uimg".to_string(),
+                mode: "octet".to_string(),
+                options: vec![
+                    TransferOption {
+                        option: OptionType::Timeout,
+                        value: 5,
+                    },
+                    TransferOption {
+                        option: OptionType::TransferSize,
+                        value: 0,
+                    },
+                    TransferOption {
+                        option: OptionType::BlockSize,
+                        value: 1468,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn golden_deserializes_tftp_hpa_request() {
+        let captured = b"\x00\x01hello.txt\x00netascii\x00";
+
+        assert_eq!(
+            Packet::deserialize(captured).unwrap(),
+            Packet::Rrq {
+                filename: "hello.txt".to_string(),
+                mode: "netascii".to_string(),
+                options: vec![],
+            }
+        );
+
+        let captured_error = b"\x00\x05\x00\x00Interrupted.\x00";
+
+        assert_eq!(
+            Packet::deserialize(captured_error).unwrap(),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "Interrupted.".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn golden_deserializes_request_with_trailing_garbage() {
+        let captured = b"\x00\x01pxelinux.0 \x00octet\x00\x00garbage";
+
+        assert_eq!(
+            Packet::deserialize(captured).unwrap(),
+            Packet::Rrq {
+                filename: "pxelinux.0 ".to_string(),
+                mode: "octet".to_string(),
+                options: vec![],
+            }
+        );
+        assert!(Packet::deserialize_strict(captured).is_err());
+    }
+
+    #[test]
+    fn round_trips_request_with_unknown_options() {
+        let captured = b"\x00\x01boot.img\x00octet\x00utimeout\x00500000\x00BLKSIZE\x001024\x00x-vendor-mac\x0000:11:22:33:44:55\x00windowsize\x004\x00";
+        let known = Packet::Rrq {
+            filename: "boot.img".to_string(),
+            mode: "octet".to_string(),
+            options: vec![
+                TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1024,
+                },
+                TransferOption {
+                    option: OptionType::Windowsize,
+                    value: 4,
+                },
+            ],
+        };
+
+        let packet = Packet::deserialize(captured).unwrap();
+
+        assert_eq!(packet, known);
+        assert_eq!(
+            packet.serialize().unwrap(),
+            b"\x00\x01boot.img\x00octet\x00blksize\x001024\x00windowsize\x004\x00"
+        );
+        assert_eq!(
+            Packet::deserialize(&known.serialize().unwrap()).unwrap(),
+            known
+        );
+    }
+
+    #[test]
+    fn skips_invalid_values_in_requests_only() {
+        let request =
+            b"\x00\x01boot.img\x00octet\x00blksize\x00large\x00tsize\x00-1\x00timeout\x003\x00";
+
+        assert_eq!(
+            Packet::deserialize(request).unwrap(),
+            Packet::Rrq {
+                filename: "boot.img".to_string(),
+                mode: "octet".to_string(),
+                options: vec![TransferOption {
+                    option: OptionType::Timeout,
+                    value: 3,
+                }],
+            }
+        );
+        assert!(Packet::deserialize(b"\x00\x06blksize\x00large\x00").is_err());
+    }
+
+    #[test]
+    fn tolerates_incomplete_option_pair() {
+        let captured = b"\x00\x01boot.img\x00octet\x00blksize\x001024\x00tsize\x00";
+
+        assert_eq!(
+            Packet::deserialize(captured).unwrap(),
+            Packet::Rrq {
+                filename: "boot.img".to_string(),
+                mode: "octet".to_string(),
+                options: vec![TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1024,
+                }],
+            }
+        );
+        assert!(Packet::deserialize_strict(captured).is_err());
+        assert!(Packet::deserialize_strict(b"\x00\x01boot.img\x00octet\x00").is_ok());
+        assert!(Packet::deserialize(b"\x00\x06blksize\x00").is_err());
+    }
+
+    #[test]
+    fn golden_deserializes_windows_wds_request() {
+        let captured = b"\x00\x01\\Boot\\x64\\wdsnbp.com\x00octet\x00tsize\x000\x00\
+            blksize\x001456\x00windowsize\x004\x00msftwindow\x0031416\x00";
+
+        assert_eq!(
+            Packet::deserialize(captured).unwrap(),
+            Packet::Rrq {
+                filename: "\\Boot\\x64\\wdsnbp.com".to_string(),
+                mode: "octet".to_string(),
+                options: vec![
+                    TransferOption {
+                        option: OptionType::TransferSize,
+                        value: 0,
+                    },
+                    TransferOption {
+                        option: OptionType::BlockSize,
+                        value: 1456,
+                    },
+                    TransferOption {
+                        option: OptionType::Windowsize,
+                        value: 4,
+                    },
+                ],
+            }
+        );
+
+        assert_eq!(
+            Packet::deserialize(b"\x00\x04\x00\x04").unwrap(),
+            Packet::Ack(4)
+        );
+    }
 }
@@ -0,0 +1,198 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::metrics::Metrics;
+
+/// Width of a bucket of a [`RateWindow`].
+const BUCKET_WIDTH: Duration = Duration::from_millis(100);
+/// Number of buckets of a [`RateWindow`], covering one second.
+const BUCKETS: usize = 10;
+
+/// RateWindow `struct` sums the amounts recorded over the last second, in
+/// a ring of buckets that are cleared as time moves on.
+#[derive(Debug, Default)]
+struct RateWindow {
+    buckets: [u64; BUCKETS],
+    /// Bucket of the last record, counted from the origin of the [`Peaks`]
+    last: u64,
+}
+
+impl RateWindow {
+    /// Adds `amount` in `bucket` and returns the sum over the last second.
+    fn record(&mut self, bucket: u64, amount: u64) -> u64 {
+        if bucket > self.last {
+            for stale in self.last + 1..=bucket.min(self.last + BUCKETS as u64) {
+                self.buckets[stale as usize % BUCKETS] = 0;
+            }
+            self.last = bucket;
+        }
+        // Records never go back in time, a late one counts as current.
+        self.buckets[self.last as usize % BUCKETS] += amount;
+        self.buckets.iter().sum()
+    }
+}
+
+/// Peaks `struct` tracks the high-water marks of the server: simultaneous
+/// sessions, read requests per second and bytes sent per second. The peaks
+/// and when they occurred are stored in the [`Metrics`].
+#[derive(Debug, Default)]
+pub(crate) struct Peaks {
+    /// Instant of the first record and the wall clock time then, from which
+    /// buckets and timestamps are counted
+    origin: Option<(Instant, SystemTime)>,
+    requests: RateWindow,
+    bytes: RateWindow,
+}
+
+impl Peaks {
+    /// Records the number of `sessions` now active.
+    pub(crate) fn record_sessions(&mut self, metrics: &Metrics, now: Instant, sessions: usize) {
+        let at = self.timestamp(now);
+        raise(
+            &metrics.peak_sessions,
+            &metrics.peak_sessions_at,
+            sessions as u64,
+            at,
+        );
+    }
+
+    /// Records a read request received now.
+    pub(crate) fn record_request(&mut self, metrics: &Metrics, now: Instant) {
+        let bucket = self.bucket(now);
+        let rate = self.requests.record(bucket, 1);
+        let at = self.timestamp(now);
+        raise(
+            &metrics.peak_request_rate,
+            &metrics.peak_request_rate_at,
+            rate,
+            at,
+        );
+    }
+
+    /// Records `bytes` of DATA sent now.
+    pub(crate) fn record_bytes(&mut self, metrics: &Metrics, now: Instant, bytes: u64) {
+        let bucket = self.bucket(now);
+        let throughput = self.bytes.record(bucket, bytes);
+        let at = self.timestamp(now);
+        raise(
+            &metrics.peak_throughput,
+            &metrics.peak_throughput_at,
+            throughput,
+            at,
+        );
+    }
+
+    /// Forgets the peaks, the number of `sessions` now active becoming the
+    /// peak of sessions.
+    pub(crate) fn reset(&mut self, metrics: &Metrics, now: Instant, sessions: usize) {
+        for peak in [
+            &metrics.peak_sessions,
+            &metrics.peak_sessions_at,
+            &metrics.peak_request_rate,
+            &metrics.peak_request_rate_at,
+            &metrics.peak_throughput,
+            &metrics.peak_throughput_at,
+        ] {
+            Metrics::set(peak, 0);
+        }
+        self.record_sessions(metrics, now, sessions);
+    }
+
+    fn bucket(&mut self, now: Instant) -> u64 {
+        let (origin, _) = *self.origin.get_or_insert((now, SystemTime::now()));
+        (now.saturating_duration_since(origin).as_millis() / BUCKET_WIDTH.as_millis()) as u64
+    }
+
+    /// Returns the wall clock time of `now` in milliseconds since the epoch.
+    fn timestamp(&mut self, now: Instant) -> u64 {
+        let (origin, wall) = *self.origin.get_or_insert((now, SystemTime::now()));
+        let at = wall + now.saturating_duration_since(origin);
+        at.duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64)
+    }
+}
+
+/// Stores `value` as the new peak if it exceeds the current one.
+fn raise(peak: &AtomicU64, peak_at: &AtomicU64, value: u64, at: u64) {
+    if value > peak.load(Ordering::Relaxed) {
+        Metrics::set(peak, value);
+        Metrics::set(peak_at, at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn records_peak_request_rate_over_one_second() {
+        let clock = MockClock::new();
+        let metrics = Metrics::default();
+        let mut peaks = Peaks::default();
+        let start = clock.now();
+        peaks.record_sessions(&metrics, start, 1);
+        let started_at = metrics.snapshot().peak_sessions_at.unwrap();
+
+        // A steady 5 requests per second.
+        for _ in 0..20 {
+            peaks.record_request(&metrics, clock.now());
+            clock.advance(ms(200));
+        }
+        assert_eq!(metrics.snapshot().peak_request_rate, 5);
+
+        // A lab powering on: 30 requests within half a second.
+        clock.advance(ms(1000));
+        for _ in 0..30 {
+            peaks.record_request(&metrics, clock.now());
+            clock.advance(ms(15));
+        }
+        let last = clock.now() - ms(15);
+        // Then quiet again.
+        clock.advance(ms(5000));
+        peaks.record_request(&metrics, clock.now());
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.peak_request_rate, 30);
+        assert_eq!(
+            snapshot.peak_request_rate_at,
+            Some(started_at + (last - start))
+        );
+    }
+
+    #[test]
+    fn records_peak_throughput_and_resets() {
+        let clock = MockClock::new();
+        let metrics = Metrics::default();
+        let mut peaks = Peaks::default();
+
+        for _ in 0..10 {
+            peaks.record_bytes(&metrics, clock.now(), 1000);
+            clock.advance(ms(50));
+        }
+        // Buckets older than a second are cleared.
+        clock.advance(ms(1000));
+        peaks.record_bytes(&metrics, clock.now(), 10);
+        assert_eq!(metrics.snapshot().peak_throughput, 10_000);
+
+        peaks.record_sessions(&metrics, clock.now(), 12);
+        peaks.record_sessions(&metrics, clock.now(), 3);
+        assert_eq!(metrics.snapshot().peak_sessions, 12);
+
+        peaks.reset(&metrics, clock.now(), 3);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.peak_throughput, 0);
+        assert_eq!(snapshot.peak_throughput_at, None);
+        assert_eq!(snapshot.peak_request_rate, 0);
+        assert_eq!(snapshot.peak_sessions, 3);
+
+        peaks.record_bytes(&metrics, clock.now(), 10);
+        assert_eq!(metrics.snapshot().peak_throughput, 20);
+    }
+}
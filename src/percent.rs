@@ -0,0 +1,54 @@
+/// Decodes the `%XX` sequences of a requested `filename`, as sent by clients
+/// URL-encoding spaces and UTF-8 characters.
+///
+/// Fails on truncated or non-hexadecimal sequences, on sequences decoding to
+/// a NUL byte and when the result is not valid UTF-8.
+pub(crate) fn decode(filename: &str) -> Result<String, String> {
+    let bytes = filename.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let byte = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("invalid percent sequence at offset {i}"))?;
+        if byte == 0 {
+            return Err(format!("percent sequence at offset {i} decodes to NUL"));
+        }
+        decoded.push(byte);
+        i += 3;
+    }
+
+    String::from_utf8(decoded).map_err(|_| "decoded filename is not valid UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_sequences() {
+        assert_eq!(decode("my%20image.img").unwrap(), "my image.img");
+        assert_eq!(decode("caf%C3%A9").unwrap(), "café");
+        assert_eq!(decode("%2e%2E%2f").unwrap(), "../");
+        assert_eq!(decode("plain.img").unwrap(), "plain.img");
+    }
+
+    #[test]
+    fn rejects_invalid_sequences() {
+        assert!(decode("%ZZ").is_err());
+        assert!(decode("image%2").is_err());
+        assert!(decode("%+1").is_err());
+        assert!(decode("image%00.img").is_err());
+        assert!(decode("%C3").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use std::io::{self, Read};
+
+/// Pipe `struct` holds the stream served under a virtual name for `--pipe`,
+/// standard input unless replaced.
+///
+/// A stream can only be read once, so it goes to the first client requesting
+/// it and later requests are refused.
+pub(crate) struct Pipe {
+    /// Requested filename that returns the stream
+    pub(crate) name: String,
+    source: Option<Box<dyn Read + Send>>,
+}
+
+impl Pipe {
+    /// Serves standard input under `name`.
+    pub(crate) fn stdin(name: String) -> Pipe {
+        Pipe {
+            name,
+            source: Some(Box::new(io::stdin())),
+        }
+    }
+
+    pub(crate) fn set_source(&mut self, source: Box<dyn Read + Send>) {
+        self.source = Some(source);
+    }
+
+    /// Returns the stream, or `None` once it was handed out.
+    pub(crate) fn take(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.source.take()
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{Config, OptionLimits, OptionType, TftpError, TsizeMode};
+use std::fmt;
+
+/// ConfigWarning `struct` describes a combination of settings that has no
+/// effect or works against another setting, returned by
+/// [`Config::validate()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigWarning {
+    /// Stable code of the rule, `W` for warnings and `E` for errors
+    pub code: &'static str,
+    /// Explanation of the problem
+    pub message: &'static str,
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Severity {
+    /// The server starts, the setting is ignored or surprising
+    Warning,
+    /// The settings contradict each other, the server does not start
+    Error,
+}
+
+/// Rule of [`RULES`], flagging the configurations for which `applies`
+/// returns `true`.
+struct Rule {
+    code: &'static str,
+    severity: Severity,
+    message: &'static str,
+    applies: fn(&Config) -> bool,
+}
+
+/// Conflicting or redundant combinations of settings, checked in order.
+/// Codes are never reused once released.
+const RULES: [Rule; 12] = [
+    Rule {
+        code: "E001",
+        severity: Severity::Error,
+        message: "--pipe and --listing-file use the same name, the listing would never be served",
+        applies: |config| config.pipe.is_some() && config.pipe == config.listing_file,
+    },
+    Rule {
+        code: "W001",
+        severity: Severity::Warning,
+        message: "--max-windowsize and --max-window-bytes have no effect with the windowsize option disabled",
+        applies: |config| {
+            let limits = &config.option_limits;
+            limits.disabled.contains(&OptionType::Windowsize)
+                && (limits.max_windowsize != OptionLimits::default().max_windowsize
+                    || limits.max_window_bytes.is_some())
+        },
+    },
+    Rule {
+        code: "W002",
+        severity: Severity::Warning,
+        message: "--max-blksize has no effect with the blksize option disabled",
+        applies: |config| {
+            let limits = &config.option_limits;
+            limits.disabled.contains(&OptionType::BlockSize)
+                && limits.max_blksize != OptionLimits::default().max_blksize
+        },
+    },
+    Rule {
+        code: "W003",
+        severity: Severity::Warning,
+        message: "--auto-shrink-blksize has no effect without blocks larger than 512 bytes",
+        applies: |config| {
+            let limits = &config.option_limits;
+            config.auto_shrink_blksize
+                && (limits.disabled.contains(&OptionType::BlockSize) || limits.max_blksize <= 512)
+        },
+    },
+    Rule {
+        code: "W004",
+        severity: Severity::Warning,
+        message: "--tsize has no effect with the tsize option disabled",
+        applies: |config| {
+            let limits = &config.option_limits;
+            limits.disabled.contains(&OptionType::TransferSize) && limits.tsize != TsizeMode::Echo
+        },
+    },
+    Rule {
+        code: "W005",
+        severity: Severity::Warning,
+        message: "--allow-mid-session-restart has no effect with --restart-threshold 0",
+        applies: |config| config.allow_mid_session_restart && config.restart_acks == 0,
+    },
+    Rule {
+        code: "W006",
+        severity: Severity::Warning,
+        message: "--listing-depth and --listing-max-bytes have no effect without --listing-file",
+        applies: |config| {
+            let default = Config::default();
+            config.listing_file.is_none()
+                && (config.listing_depth != default.listing_depth
+                    || config.listing_max_bytes != default.listing_max_bytes)
+        },
+    },
+    Rule {
+        code: "W007",
+        severity: Severity::Warning,
+        message: "--statsd-prefix and --statsd-tag have no effect without --statsd",
+        applies: |config| {
+            config.statsd.is_none()
+                && (config.statsd_prefix != Config::default().statsd_prefix
+                    || !config.statsd_tags.is_empty())
+        },
+    },
+    Rule {
+        code: "W008",
+        severity: Severity::Warning,
+        message: "--max-transfer-duration is not longer than --retransmit-timeout, transfers losing a packet are aborted",
+        applies: |config| {
+            config
+                .max_transfer_duration
+                .is_some_and(|max| max <= config.retransmit_timeout)
+        },
+    },
+    Rule {
+        code: "W009",
+        severity: Severity::Warning,
+        message: "--record writes into the served directory, recordings can be downloaded",
+        applies: |config| {
+            config
+                .record
+                .as_ref()
+                .is_some_and(|record| record.starts_with(&config.directory))
+        },
+    },
+    Rule {
+        code: "W010",
+        severity: Severity::Warning,
+        message: "--quota-hard has no effect without --quota",
+        applies: |config| config.quota_hard && config.quota.is_none(),
+    },
+    Rule {
+        code: "W011",
+        severity: Severity::Warning,
+        message: "--overwrite has no effect without --writable",
+        applies: |config| config.overwrite && !config.writable,
+    },
+];
+
+/// Checks `config` against [`RULES`], failing on the first error.
+pub(crate) fn check(config: &Config) -> Result<Vec<ConfigWarning>, TftpError> {
+    config.option_limits.validate()?;
+
+    let mut warnings = vec![];
+    for rule in RULES.iter().filter(|rule| (rule.applies)(config)) {
+        let warning = ConfigWarning {
+            code: rule.code,
+            message: rule.message,
+        };
+        match rule.severity {
+            Severity::Error => return Err(warning.to_string().into()),
+            Severity::Warning => warnings.push(warning),
+        }
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        Config::new(["/"].iter().chain(args).map(|s| s.to_string())).unwrap()
+    }
+
+    fn recording_to(record: &str) -> Config {
+        Config {
+            directory: "/srv/tftp".into(),
+            record: Some(record.into()),
+            ..Config::default()
+        }
+    }
+
+    /// Every rule with a configuration triggering it and a similar one that
+    /// does not.
+    fn cases() -> Vec<(&'static str, Config, Config)> {
+        vec![
+            (
+                "E001",
+                config(&["--pipe", "boot.img", "--listing-file", "boot.img"]),
+                config(&["--pipe", "boot.img", "--listing-file", ".dirlist"]),
+            ),
+            (
+                "W001",
+                config(&["--disable-option", "windowsize", "--max-windowsize", "4"]),
+                config(&["--max-windowsize", "4"]),
+            ),
+            (
+                "W001",
+                config(&[
+                    "--disable-option",
+                    "windowsize",
+                    "--max-window-bytes",
+                    "65536",
+                ]),
+                config(&["--disable-option", "windowsize"]),
+            ),
+            (
+                "W002",
+                config(&["--disable-option", "blksize", "--max-blksize", "1468"]),
+                config(&["--max-blksize", "1468"]),
+            ),
+            (
+                "W003",
+                config(&["--auto-shrink-blksize", "--max-blksize", "512"]),
+                config(&["--auto-shrink-blksize", "--max-blksize", "1468"]),
+            ),
+            (
+                "W003",
+                config(&["--auto-shrink-blksize", "--disable-option", "blksize"]),
+                config(&["--disable-option", "blksize"]),
+            ),
+            (
+                "W004",
+                config(&["--disable-option", "tsize", "--tsize", "zero"]),
+                config(&["--tsize", "zero"]),
+            ),
+            (
+                "W005",
+                config(&["--allow-mid-session-restart", "--restart-threshold", "0"]),
+                config(&["--allow-mid-session-restart"]),
+            ),
+            (
+                "W006",
+                config(&["--listing-depth", "3"]),
+                config(&["--listing-depth", "3", "--listing-file", ".dirlist"]),
+            ),
+            (
+                "W007",
+                config(&["--statsd-tag", "site:lab"]),
+                config(&["--statsd-tag", "site:lab", "--statsd", "127.0.0.1:8125"]),
+            ),
+            (
+                "W008",
+                config(&["--max-transfer-duration", "5"]),
+                config(&["--max-transfer-duration", "6"]),
+            ),
+            (
+                "W009",
+                recording_to("/srv/tftp/recordings"),
+                recording_to("/var/lib/tftpd"),
+            ),
+            (
+                "W010",
+                config(&["--quota-hard"]),
+                config(&["--quota-hard", "--quota", "1GB/1h"]),
+            ),
+            (
+                "W011",
+                config(&["--overwrite"]),
+                config(&["--overwrite", "--writable"]),
+            ),
+        ]
+    }
+
+    fn codes(config: &Config) -> Result<Vec<&'static str>, String> {
+        check(config)
+            .map(|warnings| warnings.iter().map(|warning| warning.code).collect())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn flags_each_rule_only_when_triggered() {
+        for (code, triggering, similar) in cases() {
+            match codes(&triggering) {
+                Ok(codes) => assert_eq!(codes, [code]),
+                Err(err) => assert!(err.starts_with(&format!("{code}: ")), "{code}: {err}"),
+            }
+            assert_eq!(codes(&similar), Ok(vec![]), "{code}");
+        }
+    }
+
+    #[test]
+    fn covers_every_rule() {
+        let tested: Vec<&str> = cases().iter().map(|(code, _, _)| *code).collect();
+        for rule in &RULES {
+            assert!(tested.contains(&rule.code), "{} is not tested", rule.code);
+            let expected = if rule.severity == Severity::Error {
+                'E'
+            } else {
+                'W'
+            };
+            assert!(rule.code.starts_with(expected), "{}", rule.code);
+        }
+    }
+
+    #[test]
+    fn default_config_has_no_warnings() {
+        assert_eq!(check(&Config::default()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn keeps_validating_option_limits() {
+        let mut config = config(&[]);
+        config.option_limits.max_timeout = 300;
+        config.max_transfer_duration = Some(Duration::from_secs(600));
+
+        assert!(check(&config).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! The items most code embedding the server or parsing TFTP needs, to be
+//! glob imported:
+//!
+//! ```
+//! use tftpd::prelude::*;
+//!
+//! let ack = Packet::Ack(1).serialize().unwrap();
+//! assert_eq!(Packet::deserialize(&ack).unwrap(), Packet::Ack(1));
+//! ```
+//!
+//! Everything here is also exported from the crate root, the rest of the
+//! public API is imported from there.
+
+pub use crate::ErrorCode;
+pub use crate::OptionType;
+pub use crate::Packet;
+pub use crate::Session;
+pub use crate::SessionAction;
+pub use crate::SessionEvent;
+pub use crate::SessionOptions;
+pub use crate::TftpError;
+pub use crate::TransferOption;
+
+#[cfg(feature = "server")]
+pub use crate::send_file;
+#[cfg(feature = "server")]
+pub use crate::Authorizer;
+#[cfg(feature = "server")]
+pub use crate::Client;
+#[cfg(feature = "server")]
+pub use crate::Config;
+#[cfg(feature = "server")]
+pub use crate::Decision;
+#[cfg(feature = "server")]
+pub use crate::MetricsSnapshot;
+#[cfg(feature = "server")]
+pub use crate::Observer;
+#[cfg(feature = "server")]
+pub use crate::RequestInfo;
+#[cfg(feature = "server")]
+pub use crate::Server;
+#[cfg(feature = "server")]
+pub use crate::Socket;
+#[cfg(feature = "server")]
+pub use crate::TransferEvent;
+#[cfg(feature = "server")]
+pub use crate::TransferHandle;
+#[cfg(feature = "server")]
+pub use crate::TransferProgress;
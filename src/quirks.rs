@@ -0,0 +1,314 @@
+use std::{fmt, net::IpAddr, str::FromStr, time::Duration};
+
+use crate::negotiation::MIN_BLOCK_SIZE;
+use crate::packet::MAX_BLOCK_SIZE;
+use crate::{Cidr, TftpError};
+
+/// Quirk `struct` enables compatibility workarounds for the clients it
+/// matches, written `<MATCHER>=<FLAG>,<FLAG>` such as
+/// `10.4.0.0/16=no-oack,loose-tid`.
+///
+/// The matcher is a block of client addresses in CIDR notation or, for
+/// anything else, a glob over the requested filename, `*` standing for any
+/// run of characters and `?` for a single one. The filename of a boot stage
+/// often tells the device model apart in PXE setups.
+///
+/// The flags are the ones of [`QuirkSet`].
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Quirk;
+///
+/// let quirk: Quirk = "efi/*.efi=max-blksize=1024,allow-restart".parse().unwrap();
+/// assert!(quirk.matches("10.0.0.5".parse().unwrap(), "efi/grubx64.efi"));
+/// assert!(!quirk.matches("10.0.0.5".parse().unwrap(), "pxelinux.0"));
+/// assert_eq!(quirk.set.max_blksize, Some(1024));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct Quirk {
+    /// Clients the workarounds apply to
+    pub matcher: QuirkMatcher,
+    /// Workarounds enabled for matching clients
+    pub set: QuirkSet,
+}
+
+/// QuirkMatcher `enum` selects the read requests a [`Quirk`] applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuirkMatcher {
+    /// Requests from an address of the block
+    Cidr(Cidr),
+    /// Requests for a filename matching the glob
+    Filename(String),
+}
+
+/// QuirkSet `struct` lists the compatibility workarounds of a session. Each
+/// one applies on top of the global flag of the same effect.
+///
+/// - `no-oack`: ignores the options of the request, so that no OACK is sent
+/// - `loose-tid`: follows the transfer to a new client port, as
+///   `--loose-tid`
+/// - `allow-restart`: resumes a transfer the client restarted, as
+///   `--allow-mid-session-restart`
+/// - `initial-delay=<MS>`: delays the first packet instead of
+///   `--initial-delay`
+/// - `max-blksize=<N>`: grants blocks of at most N bytes instead of
+///   `--max-blksize`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuirkSet {
+    /// Whether the options of the request are ignored
+    pub no_oack: bool,
+    /// Whether ACKs from another port of the client move the transfer
+    pub loose_tid: bool,
+    /// Whether a client going back to an earlier block is resumed
+    pub allow_restart: bool,
+    /// Delay before the first packet of the transfer
+    pub initial_delay: Option<Duration>,
+    /// Largest block size granted
+    pub max_blksize: Option<usize>,
+}
+
+impl Quirk {
+    /// Returns whether the quirk applies to a read request for `filename`
+    /// from `ip`.
+    pub fn matches(&self, ip: IpAddr, filename: &str) -> bool {
+        match &self.matcher {
+            QuirkMatcher::Cidr(cidr) => cidr.contains(ip),
+            QuirkMatcher::Filename(glob) => glob_matches(glob.as_bytes(), filename.as_bytes()),
+        }
+    }
+}
+
+impl QuirkSet {
+    /// Returns whether no workaround is enabled.
+    pub fn is_empty(&self) -> bool {
+        *self == QuirkSet::default()
+    }
+
+    /// Adds the workarounds of `other`, its values replacing the ones
+    /// already set.
+    fn merge(&mut self, other: &QuirkSet) {
+        self.no_oack |= other.no_oack;
+        self.loose_tid |= other.loose_tid;
+        self.allow_restart |= other.allow_restart;
+        self.initial_delay = other.initial_delay.or(self.initial_delay);
+        self.max_blksize = other.max_blksize.or(self.max_blksize);
+    }
+}
+
+/// Returns the workarounds of all the `quirks` matching a read request for
+/// `filename` from `ip`, later quirks winning.
+pub(crate) fn matching(quirks: &[Quirk], ip: IpAddr, filename: &str) -> QuirkSet {
+    let mut set = QuirkSet::default();
+    for quirk in quirks.iter().filter(|quirk| quirk.matches(ip, filename)) {
+        set.merge(&quirk.set);
+    }
+    set
+}
+
+/// Returns whether `name` matches `glob`, where `*` matches any run of
+/// characters and `?` a single one.
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    // Position of the last `*` and of the name when it was reached, to
+    // retry with the star matching one more character.
+    let mut star = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    g = star_g + 1;
+                    n = star_n + 1;
+                    star = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+impl FromStr for QuirkSet {
+    type Err = TftpError;
+
+    fn from_str(s: &str) -> Result<QuirkSet, TftpError> {
+        let mut set = QuirkSet::default();
+        for flag in s.split(',') {
+            match flag.split_once('=') {
+                None if flag == "no-oack" => set.no_oack = true,
+                None if flag == "loose-tid" => set.loose_tid = true,
+                None if flag == "allow-restart" => set.allow_restart = true,
+                Some(("initial-delay", millis)) => {
+                    set.initial_delay = Some(Duration::from_millis(millis.parse()?));
+                }
+                Some(("max-blksize", size)) => {
+                    let size = size.parse()?;
+                    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+                        return Err(format!(
+                            "Quirk max-blksize must be between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE}"
+                        )
+                        .into());
+                    }
+                    set.max_blksize = Some(size);
+                }
+                _ => return Err(format!("Invalid quirk flag {flag}").into()),
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl fmt::Display for QuirkSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut flags = vec![];
+        if self.no_oack {
+            flags.push("no-oack".to_string());
+        }
+        if self.loose_tid {
+            flags.push("loose-tid".to_string());
+        }
+        if self.allow_restart {
+            flags.push("allow-restart".to_string());
+        }
+        if let Some(delay) = self.initial_delay {
+            flags.push(format!("initial-delay={}", delay.as_millis()));
+        }
+        if let Some(size) = self.max_blksize {
+            flags.push(format!("max-blksize={size}"));
+        }
+        write!(f, "{}", flags.join(","))
+    }
+}
+
+impl FromStr for Quirk {
+    type Err = TftpError;
+
+    fn from_str(s: &str) -> Result<Quirk, TftpError> {
+        let Some((matcher, flags)) = s.split_once('=') else {
+            return Err(format!("Invalid quirk {s}, expected MATCHER=FLAGS").into());
+        };
+        if matcher.is_empty() {
+            return Err(format!("Invalid quirk {s}, missing matcher").into());
+        }
+        let matcher = match matcher.parse() {
+            Ok(cidr) => QuirkMatcher::Cidr(cidr),
+            Err(_) => QuirkMatcher::Filename(matcher.to_string()),
+        };
+        Ok(Quirk {
+            matcher,
+            set: flags.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.matcher {
+            QuirkMatcher::Cidr(cidr) => write!(f, "{cidr}={}", self.set),
+            QuirkMatcher::Filename(glob) => write!(f, "{glob}={}", self.set),
+        }
+    }
+}
+
+impl TryFrom<String> for Quirk {
+    type Error = TftpError;
+
+    fn try_from(s: String) -> Result<Quirk, TftpError> {
+        s.parse()
+    }
+}
+
+impl From<Quirk> for String {
+    fn from(quirk: Quirk) -> String {
+        quirk.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_matchers_and_flags() {
+        let quirk: Quirk = "10.4.0.0/16=no-oack,loose-tid,initial-delay=250"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            quirk.matcher,
+            QuirkMatcher::Cidr("10.4.0.0/16".parse().unwrap())
+        );
+        assert!(quirk.set.no_oack && quirk.set.loose_tid && !quirk.set.allow_restart);
+        assert_eq!(quirk.set.initial_delay, Some(Duration::from_millis(250)));
+        assert_eq!(quirk.to_string().parse::<Quirk>().unwrap(), quirk);
+
+        let quirk: Quirk = "pxelinux.cfg/01-*=max-blksize=1024".parse().unwrap();
+        assert_eq!(
+            quirk.matcher,
+            QuirkMatcher::Filename("pxelinux.cfg/01-*".to_string())
+        );
+        assert_eq!(quirk.set.max_blksize, Some(1024));
+
+        for s in [
+            "no-oack",
+            "=no-oack",
+            "10.0.0.0/8=",
+            "*.efi=fast",
+            "*=max-blksize=4",
+        ] {
+            assert!(s.parse::<Quirk>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn matches_globs() {
+        for (glob, name, expected) in [
+            ("*.efi", "grubx64.efi", true),
+            ("*.efi", "efi/grubx64.efi", true),
+            ("*.efi", "grubx64.efi.sig", false),
+            ("undionly.kpxe", "undionly.kpxe", true),
+            ("undionly.kpxe", "undionly.kpx", false),
+            ("pxelinux.cfg/01-??-*", "pxelinux.cfg/01-aa-bb", true),
+            ("pxelinux.cfg/01-??-*", "pxelinux.cfg/01-a-bb", false),
+            ("*a*b", "xaxxab", true),
+            ("*", "", true),
+        ] {
+            assert_eq!(
+                glob_matches(glob.as_bytes(), name.as_bytes()),
+                expected,
+                "{glob} {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn merges_matching_quirks() {
+        let quirks: Vec<Quirk> = [
+            "10.0.0.0/8=loose-tid,max-blksize=1024",
+            "*.efi=allow-restart,max-blksize=1468",
+            "192.168.0.0/16=no-oack",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+
+        let set = matching(&quirks, ip("10.1.2.3"), "grubx64.efi");
+        assert_eq!(set.to_string(), "loose-tid,allow-restart,max-blksize=1468");
+        let set = matching(&quirks, ip("10.1.2.3"), "pxelinux.0");
+        assert_eq!(set.to_string(), "loose-tid,max-blksize=1024");
+        assert!(matching(&quirks, ip("172.16.0.1"), "pxelinux.0").is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{fmt, net::IpAddr, str::FromStr, time::Duration};
+
+use crate::TftpError;
+
+/// Quota `struct` is the number of bytes a client host may download within
+/// a window of time, written `<BYTES>/<WINDOW>` such as `10GB/1h`.
+///
+/// Sizes take a `K`, `M`, `G` or `T` suffix in powers of 1000, or `Ki`,
+/// `Mi`, `Gi` or `Ti` in powers of 1024, optionally followed by `B`.
+/// Windows take an `s`, `m`, `h` or `d` suffix.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tftpd::Quota;
+///
+/// let quota: Quota = "10GB/1h".parse().unwrap();
+/// assert_eq!(quota.bytes, 10_000_000_000);
+/// assert_eq!(quota.window, Duration::from_secs(3600));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct Quota {
+    /// Number of bytes acknowledged by a client host within a window
+    pub bytes: u64,
+    /// Duration of a window, starting with the first bytes of the host
+    pub window: Duration,
+}
+
+/// QuotaUsage `struct` describes what a client host downloaded in its
+/// current quota window, returned by
+/// [`Server::quota_usage()`](crate::Server::quota_usage).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// Address of the client host
+    pub ip: IpAddr,
+    /// Number of bytes acknowledged in the window
+    pub bytes: u64,
+    /// Time until the window ends and the usage is forgotten
+    pub window_left: Duration,
+}
+
+const SIZE_UNITS: [(&str, u64); 8] = [
+    ("Ki", 1 << 10),
+    ("Mi", 1 << 20),
+    ("Gi", 1 << 30),
+    ("Ti", 1 << 40),
+    ("K", 1_000),
+    ("M", 1_000_000),
+    ("G", 1_000_000_000),
+    ("T", 1_000_000_000_000),
+];
+
+const WINDOW_UNITS: [(&str, u64); 4] = [("s", 1), ("m", 60), ("h", 3600), ("d", 86400)];
+
+/// Splits `s` into its number and the multiplier of its suffix among
+/// `units`, 1 without a suffix.
+fn split_unit(s: &str, units: &[(&str, u64)]) -> Result<(u64, u64), TftpError> {
+    let digits = s.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let suffix = &s[digits.len()..];
+    let multiplier = match units.iter().find(|(unit, _)| *unit == suffix) {
+        Some(&(_, multiplier)) => multiplier,
+        None if suffix.is_empty() => 1,
+        None => return Err(format!("Invalid unit {suffix} in {s}").into()),
+    };
+    Ok((digits.parse()?, multiplier))
+}
+
+impl FromStr for Quota {
+    type Err = TftpError;
+
+    fn from_str(s: &str) -> Result<Quota, TftpError> {
+        let Some((bytes, window)) = s.split_once('/') else {
+            return Err(format!("Invalid quota {s}, expected BYTES/WINDOW").into());
+        };
+        let (bytes, multiplier) =
+            split_unit(bytes.strip_suffix('B').unwrap_or(bytes), &SIZE_UNITS)?;
+        let bytes = bytes
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("Quota {s} is too large"))?;
+        let (window, multiplier) = split_unit(window, &WINDOW_UNITS)?;
+        let window = Duration::from_secs(window.saturating_mul(multiplier));
+        if bytes == 0 || window.is_zero() {
+            return Err(format!("Quota {s} must allow some bytes within some time").into());
+        }
+        Ok(Quota { bytes, window })
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}s", self.bytes, self.window.as_secs())
+    }
+}
+
+impl TryFrom<String> for Quota {
+    type Error = TftpError;
+
+    fn try_from(s: String) -> Result<Quota, TftpError> {
+        s.parse()
+    }
+}
+
+impl From<Quota> for String {
+    fn from(quota: Quota) -> String {
+        quota.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(bytes: u64, secs: u64) -> Quota {
+        Quota {
+            bytes,
+            window: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn parses_sizes_and_windows() {
+        for (s, expected) in [
+            ("10GB/1h", quota(10_000_000_000, 3600)),
+            ("500MiB/30m", quota(500 << 20, 1800)),
+            ("2T/1d", quota(2_000_000_000_000, 86400)),
+            ("4096/90", quota(4096, 90)),
+            ("1KiB/10s", quota(1024, 10)),
+        ] {
+            let parsed: Quota = s.parse().unwrap();
+            assert_eq!(parsed, expected, "{s}");
+            assert_eq!(parsed.to_string().parse::<Quota>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_quotas() {
+        for s in [
+            "10GB",
+            "10GB/",
+            "/1h",
+            "10XB/1h",
+            "10GB/1w",
+            "0GB/1h",
+            "10GB/0s",
+            "GB/1h",
+            "99999999TB/1h",
+        ] {
+            assert!(s.parse::<Quota>().is_err(), "{s}");
+        }
+    }
+}
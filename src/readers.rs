@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::netascii::Mode;
+use crate::TransferOption;
+
+/// Maximum number of read requests waiting for a free reader slot.
+pub(crate) const MAX_PENDING: usize = 64;
+
+/// ReaderLimit `struct` counts the active transfers of every file and holds
+/// the read requests waiting for one of them to finish.
+#[derive(Debug)]
+pub(crate) struct ReaderLimit {
+    /// Maximum number of concurrent transfers of the same file
+    pub(crate) max: usize,
+    counts: HashMap<PathBuf, usize>,
+    pending: VecDeque<PendingRequest>,
+}
+
+/// A read request held back until its file has a free reader slot.
+#[derive(Debug)]
+pub(crate) struct PendingRequest {
+    pub(crate) client: SocketAddr,
+    pub(crate) filename: String,
+    pub(crate) mode: Mode,
+    pub(crate) options: Vec<TransferOption>,
+    pub(crate) reader: PathBuf,
+    pub(crate) queued_at: Instant,
+    /// When the client last sent the request, it is dropped once it is older
+    /// than `patience`.
+    pub(crate) last_seen: Instant,
+    pub(crate) patience: Duration,
+}
+
+impl ReaderLimit {
+    pub(crate) fn new(max: usize) -> ReaderLimit {
+        ReaderLimit {
+            max,
+            counts: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether another transfer of `reader` can start.
+    pub(crate) fn has_room(&self, reader: &Path) -> bool {
+        self.counts.get(reader).copied().unwrap_or(0) < self.max
+    }
+
+    pub(crate) fn acquire(&mut self, reader: &Path) {
+        *self.counts.entry(reader.to_path_buf()).or_default() += 1;
+    }
+
+    pub(crate) fn release(&mut self, reader: &Path) {
+        if let Some(count) = self.counts.get_mut(reader) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(reader);
+            }
+        }
+    }
+
+    /// Returns the number of active transfers of every file being read.
+    pub(crate) fn counts(&self) -> &HashMap<PathBuf, usize> {
+        &self.counts
+    }
+
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Queues `request`, or refreshes the request already queued for the
+    /// same client. Returns `false` when the queue is full.
+    pub(crate) fn enqueue(&mut self, request: PendingRequest) -> bool {
+        if let Some(queued) = self
+            .pending
+            .iter_mut()
+            .find(|queued| queued.client == request.client)
+        {
+            let queued_at = queued.queued_at;
+            *queued = PendingRequest {
+                queued_at,
+                ..request
+            };
+            return true;
+        }
+
+        if self.pending.len() >= MAX_PENDING {
+            return false;
+        }
+        self.pending.push_back(request);
+        true
+    }
+
+    /// Removes and returns the oldest request whose file has a free slot.
+    pub(crate) fn next_ready(&mut self) -> Option<PendingRequest> {
+        let index = self
+            .pending
+            .iter()
+            .position(|request| self.has_room(&request.reader))?;
+        self.pending.remove(index)
+    }
+
+    /// Removes and returns the requests the clients stopped waiting for.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<PendingRequest> {
+        let (expired, pending) = self
+            .pending
+            .drain(..)
+            .partition(|request| now.duration_since(request.last_seen) >= request.patience);
+        self.pending = pending;
+        expired.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(port: u16, reader: &str, now: Instant) -> PendingRequest {
+        PendingRequest {
+            client: SocketAddr::from(([127, 0, 0, 1], port)),
+            filename: reader.to_string(),
+            mode: Mode::Octet,
+            options: vec![],
+            reader: PathBuf::from(reader),
+            queued_at: now,
+            last_seen: now,
+            patience: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn counts_readers_per_file() {
+        let mut limit = ReaderLimit::new(2);
+        let file = Path::new("/srv/image.bin");
+
+        limit.acquire(file);
+        assert!(limit.has_room(file));
+        limit.acquire(file);
+        assert!(!limit.has_room(file));
+        assert!(limit.has_room(Path::new("/srv/other.bin")));
+
+        limit.release(file);
+        assert!(limit.has_room(file));
+    }
+
+    #[test]
+    fn serves_pending_requests_in_order() {
+        let now = Instant::now();
+        let mut limit = ReaderLimit::new(1);
+        limit.acquire(Path::new("a"));
+
+        assert!(limit.enqueue(request(1, "a", now)));
+        assert!(limit.enqueue(request(2, "b", now)));
+        assert!(limit.enqueue(request(3, "a", now)));
+        // A retried request keeps its place.
+        assert!(limit.enqueue(request(1, "a", now)));
+        assert_eq!(limit.pending_len(), 3);
+
+        assert_eq!(limit.next_ready().unwrap().client.port(), 2);
+        assert!(limit.next_ready().is_none());
+
+        limit.release(Path::new("a"));
+        assert_eq!(limit.next_ready().unwrap().client.port(), 1);
+        limit.acquire(Path::new("a"));
+        assert!(limit.next_ready().is_none());
+    }
+
+    #[test]
+    fn expires_requests_after_patience() {
+        let now = Instant::now();
+        let mut limit = ReaderLimit::new(1);
+        limit.enqueue(request(1, "a", now));
+        limit.enqueue(request(2, "a", now + Duration::from_secs(3)));
+
+        let expired = limit.expire(now + Duration::from_secs(5));
+
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].client.port(), 1);
+        assert_eq!(limit.pending_len(), 1);
+    }
+
+    #[test]
+    fn rejects_when_queue_is_full() {
+        let now = Instant::now();
+        let mut limit = ReaderLimit::new(1);
+        for port in 0..MAX_PENDING as u16 {
+            assert!(limit.enqueue(request(port, "a", now)));
+        }
+
+        assert!(!limit.enqueue(request(MAX_PENDING as u16, "a", now)));
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::logger::elogln;
+use crate::{Opcode, Socket};
+
+/// First bytes of a recording file.
+const MAGIC: &[u8; 8] = b"TFTPREC1";
+
+/// Maximum number of recordings kept open, the oldest are closed first.
+const MAX_OPEN_RECORDINGS: usize = 64;
+
+/// Direction `enum` tells who sent a recorded [`Datagram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client to the server
+    ToServer,
+    /// Sent by the server to the client
+    ToClient,
+}
+
+/// Datagram `struct` is a datagram of a recorded session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// Who sent the datagram
+    pub direction: Direction,
+    /// Time since the read request starting the session
+    pub offset: Duration,
+    /// Content of the datagram
+    pub bytes: Vec<u8>,
+}
+
+/// Recording `struct` holds the datagrams of a session recorded with
+/// `--record`, which can be replayed against a server with
+/// [`Recording::replay()`].
+///
+/// A recording file starts with the 8 bytes `TFTPREC1`, followed by one
+/// record per datagram: the direction (0 to the server, 1 to the client),
+/// the offset in microseconds as a big-endian `u64`, the length as a
+/// big-endian `u32` and the datagram itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    /// Datagrams in the order they were sent or received
+    pub datagrams: Vec<Datagram>,
+}
+
+impl Recording {
+    /// Loads the recording file at `path`.
+    pub fn load(path: &Path) -> io::Result<Recording> {
+        let content = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut rest = content
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| invalid("not a recording file"))?;
+
+        let mut datagrams = vec![];
+        while !rest.is_empty() {
+            if rest.len() < 13 {
+                return Err(invalid("truncated record header"));
+            }
+            let direction = match rest[0] {
+                0 => Direction::ToServer,
+                1 => Direction::ToClient,
+                _ => return Err(invalid("invalid record direction")),
+            };
+            let micros = u64::from_be_bytes(rest[1..9].try_into().unwrap());
+            let len = u32::from_be_bytes(rest[9..13].try_into().unwrap()) as usize;
+            let bytes = rest
+                .get(13..13 + len)
+                .ok_or_else(|| invalid("truncated record"))?;
+            datagrams.push(Datagram {
+                direction,
+                offset: Duration::from_micros(micros),
+                bytes: bytes.to_vec(),
+            });
+            rest = &rest[13 + len..];
+        }
+
+        Ok(Recording { datagrams })
+    }
+}
+
+/// A recording file being written, with the instant its session started.
+struct OpenRecording {
+    file: File,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Recordings {
+    open: HashMap<SocketAddr, OpenRecording>,
+    order: VecDeque<SocketAddr>,
+    count: u64,
+}
+
+/// RecordingSocket `struct` wraps a [`Socket`], writing the datagrams
+/// exchanged with every client into one file per read request in a
+/// directory.
+pub(crate) struct RecordingSocket<S> {
+    inner: S,
+    directory: PathBuf,
+    recordings: Mutex<Recordings>,
+}
+
+impl<S: Socket> RecordingSocket<S> {
+    pub(crate) fn new(inner: S, directory: &Path) -> io::Result<RecordingSocket<S>> {
+        fs::create_dir_all(directory)?;
+
+        Ok(RecordingSocket {
+            inner,
+            directory: directory.to_path_buf(),
+            recordings: Mutex::new(Recordings::default()),
+        })
+    }
+
+    /// Records a datagram exchanged with `client`, starting a new recording
+    /// for every read request.
+    fn record(&self, client: SocketAddr, direction: Direction, buf: &[u8]) {
+        let mut recordings = self.recordings.lock().unwrap();
+        let is_rrq = buf.get(..2) == Some(&Opcode::Rrq.as_bytes());
+        if direction == Direction::ToServer && is_rrq {
+            if let Err(err) = self.start(&mut recordings, client) {
+                elogln!("{client}: Cannot start recording: {err}");
+            }
+        }
+
+        let Some(recording) = recordings.open.get_mut(&client) else {
+            return;
+        };
+        let offset = recording.started.elapsed().as_micros() as u64;
+        let record = [
+            &[(direction == Direction::ToClient) as u8][..],
+            &offset.to_be_bytes(),
+            &(buf.len() as u32).to_be_bytes(),
+            buf,
+        ]
+        .concat();
+        if let Err(err) = recording.file.write_all(&record) {
+            elogln!("{client}: Stopped recording: {err}");
+            recordings.open.remove(&client);
+            recordings.order.retain(|open| *open != client);
+        }
+    }
+
+    fn start(&self, recordings: &mut Recordings, client: SocketAddr) -> io::Result<()> {
+        recordings.order.retain(|open| *open != client);
+        if recordings.order.len() >= MAX_OPEN_RECORDINGS {
+            if let Some(oldest) = recordings.order.pop_front() {
+                recordings.open.remove(&oldest);
+            }
+        }
+
+        recordings.count += 1;
+        let name = format!(
+            "{:06}-{}.tftprec",
+            recordings.count,
+            client.to_string().replace([':', '[', ']'], "_")
+        );
+        let mut file = File::create(self.directory.join(name))?;
+        file.write_all(MAGIC)?;
+        recordings.open.insert(
+            client,
+            OpenRecording {
+                file,
+                started: Instant::now(),
+            },
+        );
+        recordings.order.push_back(client);
+        Ok(())
+    }
+}
+
+impl<S: Socket> Socket for RecordingSocket<S> {
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        let sent = self.inner.send_to(buf, addr)?;
+        self.record(*addr, Direction::ToClient, buf);
+        Ok(sent)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, from, _) = self.recv_with_destination(buf)?;
+        Ok((size, from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    fn enable_destination(&self) -> io::Result<()> {
+        self.inner.enable_destination()
+    }
+
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let (size, from, destination) = self.inner.recv_with_destination(buf)?;
+        self.record(from, Direction::ToServer, &buf[..size]);
+        Ok((size, from, destination))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    #[test]
+    fn records_datagrams_per_read_request() {
+        let directory = tempfile::tempdir().unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let socket = RecordingSocket::new(server, directory.path()).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let mut buf = [0; 512];
+
+        // Datagrams before a read request are not recorded.
+        socket.send_to(b"\x00\x04\x00\x01", &client_addr).unwrap();
+        client
+            .send_to(b"\x00\x01a\x00octet\x00", server_addr)
+            .unwrap();
+        socket.recv_from(&mut buf).unwrap();
+        socket.send_to(b"\x00\x03\x00\x01hi", &client_addr).unwrap();
+
+        let files: Vec<_> = fs::read_dir(directory.path()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        let recording = Recording::load(&path).unwrap();
+        let datagrams: Vec<_> = recording
+            .datagrams
+            .iter()
+            .map(|d| (d.direction, d.bytes.clone()))
+            .collect();
+        assert_eq!(
+            datagrams,
+            vec![
+                (Direction::ToServer, b"\x00\x01a\x00octet\x00".to_vec()),
+                (Direction::ToClient, b"\x00\x03\x00\x01hi".to_vec()),
+            ]
+        );
+        assert!(fs::read(path).unwrap().starts_with(MAGIC));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bad.tftprec");
+
+        fs::write(&path, b"NOTAREC!").unwrap();
+        assert!(Recording::load(&path).is_err());
+
+        fs::write(&path, [&MAGIC[..], &[0, 0, 0]].concat()).unwrap();
+        assert!(Recording::load(&path).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fmt, io,
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::record::{Direction, Recording};
+use crate::Packet;
+
+/// Time to wait for a datagram of the server beyond its recorded timing.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Divergence `struct` describes a datagram of the server that differs from
+/// the recording during [`Recording::replay()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the datagram in the recording
+    pub index: usize,
+    /// Datagram sent by the server in the recording
+    pub expected: Vec<u8>,
+    /// Datagram sent by the server during the replay, `None` if it sent
+    /// nothing in time
+    pub actual: Option<Vec<u8>>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let actual = match &self.actual {
+            Some(actual) => describe(actual),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "datagram {}: expected {}, got {actual}",
+            self.index,
+            describe(&self.expected)
+        )
+    }
+}
+
+/// Pretty-prints a datagram with the [`Packet`] parser, or as raw bytes if
+/// it does not parse.
+fn describe(buf: &[u8]) -> String {
+    match Packet::deserialize(buf) {
+        Ok(packet) => packet.to_string(),
+        Err(_) => format!("{buf:?}"),
+    }
+}
+
+impl Recording {
+    /// Replays the client side of the recording against the server at
+    /// `server`, honoring the recorded timing unless `no_delay` is set, and
+    /// returns where the datagrams of the server differ from the recording.
+    pub fn replay(&self, server: SocketAddr, no_delay: bool) -> io::Result<Vec<Divergence>> {
+        let bind: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        let started = Instant::now();
+        let mut divergences = vec![];
+        let mut buf = [0; 65536];
+
+        for (index, datagram) in self.datagrams.iter().enumerate() {
+            let due = started + datagram.offset;
+            match datagram.direction {
+                Direction::ToServer => {
+                    if !no_delay {
+                        thread::sleep(due.saturating_duration_since(Instant::now()));
+                    }
+                    socket.send_to(&datagram.bytes, server)?;
+                }
+                Direction::ToClient => {
+                    let wait = if no_delay {
+                        REPLY_TIMEOUT
+                    } else {
+                        due.saturating_duration_since(Instant::now()) + REPLY_TIMEOUT
+                    };
+                    socket.set_read_timeout(Some(wait))?;
+                    let actual = match socket.recv_from(&mut buf) {
+                        Ok((size, _)) => Some(buf[..size].to_vec()),
+                        Err(err)
+                            if matches!(
+                                err.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            None
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    if actual.as_ref() != Some(&datagram.bytes) {
+                        divergences.push(Divergence {
+                            index,
+                            expected: datagram.bytes.clone(),
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(divergences)
+    }
+}
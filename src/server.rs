@@ -1,12 +1,80 @@
-use crate::state::{parse_options, StateOptions, Window};
-use crate::{Config, Message, State};
-use crate::{ErrorCode, Packet, TransferOption};
-use std::collections::HashMap;
+use crate::beneath::{self, Beneath};
+use crate::blackholes::Blackholes;
+use crate::capture::DebugCapture;
+use crate::clients::{Anomaly, ClientRegistry};
+use crate::event::{ProgressTracker, PROGRESS_INTERVAL};
+use crate::gzip;
+use crate::health::STORAGE_UNAVAILABLE;
+use crate::hooks::Hooks;
+use crate::listeners::ListenerSet;
+use crate::listing::Listing;
+use crate::logger::{debuglog, elogln, logln, tracelog};
+use crate::manifest::Manifest;
+use crate::menu::Menu;
+use crate::metrics::Metrics;
+use crate::metrics_file::MetricsFile;
+use crate::missing::{MissingFiles, MAX_MISSING_FILES};
+use crate::negotiation;
+use crate::netascii::{Mode, NetasciiReader};
+use crate::packet::{MAX_BLOCK_SIZE, MAX_REQUEST_SIZE};
+use crate::peaks::Peaks;
+use crate::percent;
+use crate::pipe::Pipe;
+use crate::quirks;
+use crate::readers::{PendingRequest, ReaderLimit};
+use crate::record::RecordingSocket;
+#[cfg(feature = "cli")]
+use crate::signal::{self, Signal};
+use crate::sparse;
+use crate::state::State;
+use crate::state::{
+    self, block_count, parse_options, BlockSource, Sequential, DEFAULT_BLOCK_SIZE, DEFAULT_TIMEOUT,
+    MAX_UNREACHABLE_SENDS,
+};
+use crate::stats::{FileStatsMap, MAX_TRACKED_FILES};
+#[cfg(feature = "metrics")]
+use crate::statsd::Statsd;
+use crate::storage::{self, FsStorage, TimedSource};
+use crate::timers::Timers;
+use crate::tombstones::Tombstones;
+use crate::transfer::{self, Outcome, Transport};
+use crate::upload::{self, Receipt, Upload};
+use crate::watchdog::Watchdog;
+use crate::worker::{Limits, TransferHandle, Worker};
+use crate::{Authorizer, Decision, RequestInfo, Stall, TftpError};
+use crate::{
+    BroadcastPolicy, BusyStrategy, CaptureReason, CapturedPacket, Cidr, ClientSessions,
+    ConflictPolicy, FileStats, NegotiatedOption, OptionLimits, OptionType, TsizeMode,
+};
+use crate::{
+    Clock, Config, Direction, Journey, Message, MetricsSnapshot, MissingFile, Observer, Socket,
+};
+use crate::{ErrorCode, NegotiationNote, Packet, StorageProbe, TransferOption};
+use crate::{Quirk, QuirkSet, Quota, QuotaUsage};
+use crate::{Session, SessionAction, SessionEvent, SessionOptions};
+use crate::{SystemClock, TransferEvent, TransferProgress};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use std::net::{SocketAddr, UdpSocket};
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::mem;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Interval at which the listen loop wakes up to service timers when no
+/// packets arrive.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Maximum number of already queued packets handled in one batch.
+const MAX_BATCH: usize = 64;
+/// Number of files included in the statistics printed on `SIGUSR1`.
+#[cfg(feature = "cli")]
+const TOP_FILES: usize = 10;
+/// Number of ticks between two checks of the session bookkeeping. Debug
+/// builds check every tick, so that the tests exercise every state.
+const SELF_CHECK_TICKS: u64 = if cfg!(debug_assertions) { 1 } else { 64 };
 
 /// Server `struct` is used for handling incoming TFTP requests.
 ///
@@ -24,234 +92,3700 @@ use std::path::{Path, PathBuf};
 /// let server = Server::new(&config).unwrap();
 /// ```
 pub struct Server {
-    socket: UdpSocket,
+    socket: Box<dyn Socket>,
+    /// Listeners behind `socket` when created by [`Server::new()`] with
+    /// several addresses or a bind host, which can then be rebound
+    listeners: Option<Arc<ListenerSet>>,
+    bind_host: Option<String>,
     directory: PathBuf,
+    canonical_directory: PathBuf,
     connmap: HashMap<SocketAddr, State>,
+    /// Sessions of `connmap` grouped by client host
+    clients: ClientRegistry,
+    max_per_ip: Option<usize>,
+    quota: Option<Quota>,
+    quota_hard: bool,
+    loose_tid: bool,
+    clock: Arc<dyn Clock>,
+    metrics: Metrics,
+    observer: Option<Arc<dyn Observer>>,
+    listing: Option<Listing>,
+    menu: Option<Menu>,
+    pipe: Option<Pipe>,
+    oneshot: bool,
+    beneath: Option<Beneath>,
+    readers: Option<ReaderLimit>,
+    when_busy: BusyStrategy,
+    manifest: Option<Manifest>,
+    file_stats: FileStatsMap,
+    metrics_file: Option<MetricsFile>,
+    missing: MissingFiles,
+    retransmit_timeout: Duration,
+    max_retries: u32,
+    writable: bool,
+    overwrite: bool,
+    max_upload_size: Option<u64>,
+    threaded: bool,
+    rollover: u16,
+    follow_symlinks: bool,
+    conflicting_request: ConflictPolicy,
+    /// Files being received for write requests, with `--writable`
+    uploads: HashMap<SocketAddr, Upload>,
+    /// Uploads by the instant their last answer is sent again
+    upload_timers: Timers,
+    /// Datagram being received, large enough for a DATA packet of an upload
+    /// with `--writable` and for a request otherwise
+    receive_buffer: Vec<u8>,
+    /// Transfers running on [`Worker`] threads, with `--threaded`
+    delegated: Vec<(Delegated, TransferHandle)>,
+    answer_on: Vec<IpAddr>,
+    #[cfg(feature = "metrics")]
+    statsd: Option<Statsd>,
+    option_limits: OptionLimits,
+    max_in_flight_bytes: Option<usize>,
+    preserve_option_order: bool,
+    answer_broadcast: BroadcastPolicy,
+    compressed_fallback: bool,
+    hooks: Option<Hooks>,
+    percent_decode: bool,
+    trim_request_whitespace: bool,
+    strict_paths: bool,
+    empty_request_file: Option<String>,
+    /// Generation of the last started session
+    generation: u64,
+    /// Sessions by retransmission deadline
+    retransmit_timers: Timers,
+    /// Sessions by next progress report
+    progress_timers: Timers,
+    tick_budget: Duration,
+    duplicate_data: usize,
+    max_transfer_duration: Option<Duration>,
+    session_timeout: Option<Duration>,
+    deadline_timers: Timers,
+    idle_timers: Timers,
+    initial_delay: Duration,
+    initial_delay_for: Vec<(Cidr, Duration)>,
+    journey_window: Duration,
+    quirks: Vec<Quirk>,
+    abort_on_panic: bool,
+    reject_privileged_source_ports: bool,
+    allowed_source_ports: Vec<u16>,
+    debug_capture: DebugCapture,
+    /// Sessions by the end of their `--initial-delay`
+    start_timers: Timers,
+    /// Sessions recently terminated by the server
+    tombstones: Tombstones,
+    watchdog: Option<Watchdog>,
+    auto_shrink_blksize: bool,
+    negotiation_report: bool,
+    restart_acks: u32,
+    allow_mid_session_restart: bool,
+    no_rollover: bool,
+    slow_storage_warn: Option<Duration>,
+    /// Client hosts served with small blocks with `--auto-shrink-blksize`
+    blackholes: Blackholes,
+    #[cfg(target_os = "linux")]
+    skip_holes: bool,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    /// Whether the served directory could not be opened at the last tick,
+    /// read requests are then refused
+    storage_unavailable: bool,
+    peaks: Peaks,
+    /// Findings of the startup probe of the served directory
+    storage_probe: Option<StorageProbe>,
+    /// Whether the session bookkeeping is checked, see [`Server::audit`]
+    self_check: bool,
+    ticks_since_check: u64,
 }
 
 impl Server {
     /// Creates the TFTP Server with the supplied [`Config`].
-    pub fn new(config: &Config) -> Result<Server, Box<dyn Error>> {
-        let socket = UdpSocket::bind(SocketAddr::from((config.ip_address, config.port)))?;
+    ///
+    /// With [`Config::additional_ip_addresses`], one socket is bound per
+    /// address and every reply is sent from the socket the client sent its
+    /// request to.
+    ///
+    /// With [`Config::bind_host`], the server listens on the address of the
+    /// host instead of [`Config::ip_address`], and follows its changes with
+    /// [`Server::reload_bind_host()`].
+    pub fn new(config: &Config) -> Result<Server, TftpError> {
+        let ip_address = match &config.bind_host {
+            Some(host) => resolve_host(host)?,
+            None => IpAddr::V4(config.ip_address),
+        };
+        if config.bind_host.is_some() || !config.additional_ip_addresses.is_empty() {
+            let addresses: Vec<SocketAddr> = [ip_address]
+                .into_iter()
+                .chain(config.additional_ip_addresses.iter().map(|&ip| ip.into()))
+                .map(|ip_address| SocketAddr::new(ip_address, config.port))
+                .collect();
+            let listeners = Arc::new(ListenerSet::bind(&addresses).map_err(TftpError::Bind)?);
+            let mut server = Server::with_socket(config, listeners.clone())?;
+            server.listeners = Some(listeners);
+            #[cfg(feature = "cli")]
+            if config.bind_host.is_some() {
+                signal::watch(Signal::Hangup);
+            }
+            return Ok(server);
+        }
+
+        let socket =
+            UdpSocket::bind(SocketAddr::new(ip_address, config.port)).map_err(TftpError::Bind)?;
+
+        Server::with_socket(config, socket)
+    }
+
+    /// Creates the TFTP Server with the supplied [`Config`], serving on an
+    /// already bound [`Socket`]. The address and port of the [`Config`] are
+    /// ignored.
+    pub fn with_socket<S>(config: &Config, socket: S) -> Result<Server, TftpError>
+    where
+        S: Socket + 'static,
+    {
+        socket
+            .set_read_timeout(Some(TICK_INTERVAL))
+            .map_err(TftpError::Io)?;
+        #[cfg(not(feature = "gzip"))]
+        if config.compressed_fallback {
+            return Err("--serve-compressed-fallback requires the gzip feature".into());
+        }
+        #[cfg(not(feature = "metrics"))]
+        if config.statsd.is_some() {
+            return Err("--statsd requires the metrics feature".into());
+        }
+        config.validate()?;
+        if !config.answer_on.is_empty() {
+            socket.enable_destination().map_err(TftpError::Bind)?;
+        } else if config.answer_broadcast != BroadcastPolicy::Always {
+            if let Err(err) = socket.enable_destination() {
+                elogln!("Cannot detect broadcast requests, answering all of them: {err}");
+            }
+        }
+
+        let socket: Box<dyn Socket> = match &config.record {
+            Some(directory) => {
+                Box::new(RecordingSocket::new(socket, directory).map_err(|err| {
+                    TftpError::Directory(format!("{}: {err}", directory.display()))
+                })?)
+            }
+            None => Box::new(socket),
+        };
+        #[cfg(not(target_os = "linux"))]
+        if config.skip_holes {
+            elogln!("Skipping holes is only available on Linux, sparse files are read whole");
+        }
+
+        let mut server = Server {
+            socket,
+            listeners: None,
+            bind_host: config.bind_host.clone(),
+            directory: config.directory.clone(),
+            canonical_directory: fs::canonicalize(&config.directory)
+                .unwrap_or_else(|_| config.directory.clone()),
+            connmap: HashMap::new(),
+            clients: ClientRegistry::default(),
+            max_per_ip: config.max_per_ip,
+            quota: config.quota,
+            quota_hard: config.quota_hard,
+            loose_tid: config.loose_tid,
+            clock: Arc::new(SystemClock),
+            metrics: Metrics::default(),
+            observer: None,
+            listing: config.listing_file.as_ref().map(|name| Listing {
+                name: name.clone(),
+                depth: config.listing_depth,
+                max_bytes: config.listing_max_bytes,
+            }),
+            menu: config.autogen_menu.as_ref().map(|(name, scan_dir)| Menu {
+                name: name.clone(),
+                scan_dir: scan_dir.clone(),
+                header: config.autogen_menu_header.clone(),
+                footer: config.autogen_menu_footer.clone(),
+                cached: None,
+            }),
+            pipe: config.pipe.clone().map(Pipe::stdin),
+            oneshot: config.oneshot,
+            beneath: if config.beneath {
+                Some(Beneath::new(&config.directory).map_err(|err| {
+                    TftpError::Directory(format!("{}: {err}", config.directory.display()))
+                })?)
+            } else {
+                None
+            },
+            readers: config.max_readers_per_file.map(ReaderLimit::new),
+            when_busy: config.when_busy,
+            manifest: match &config.manifest {
+                Some(path) => {
+                    let manifest = Manifest::load(path)?;
+                    #[cfg(feature = "cli")]
+                    signal::watch(Signal::Hangup);
+                    Some(manifest)
+                }
+                None => None,
+            },
+            file_stats: FileStatsMap::new(MAX_TRACKED_FILES),
+            metrics_file: config
+                .metrics_snapshot
+                .clone()
+                .map(|path| MetricsFile::new(path, config.metrics_snapshot_interval)),
+            missing: MissingFiles::new(MAX_MISSING_FILES),
+            retransmit_timeout: config.retransmit_timeout,
+            max_retries: config.max_retries,
+            writable: config.writable,
+            overwrite: config.overwrite,
+            max_upload_size: config.max_upload_size,
+            threaded: config.threaded,
+            rollover: config.rollover,
+            follow_symlinks: !config.no_follow_symlinks,
+            conflicting_request: config.conflicting_request,
+            uploads: HashMap::new(),
+            upload_timers: Timers::new(),
+            receive_buffer: if config.writable {
+                vec![0; MAX_BLOCK_SIZE + 4]
+            } else {
+                vec![0; MAX_REQUEST_SIZE]
+            },
+            delegated: vec![],
+            answer_on: config.answer_on.clone(),
+            option_limits: config.option_limits.clone(),
+            max_in_flight_bytes: config.max_in_flight_bytes,
+            preserve_option_order: config.preserve_option_order,
+            answer_broadcast: config.answer_broadcast,
+            compressed_fallback: config.compressed_fallback,
+            percent_decode: config.percent_decode,
+            trim_request_whitespace: config.trim_request_whitespace,
+            strict_paths: config.strict_paths,
+            empty_request_file: config.empty_request_file.clone(),
+            generation: 0,
+            retransmit_timers: Timers::new(),
+            progress_timers: Timers::new(),
+            tick_budget: config.tick_budget,
+            duplicate_data: config.duplicate_data,
+            max_transfer_duration: config.max_transfer_duration,
+            session_timeout: config.session_timeout,
+            deadline_timers: Timers::new(),
+            idle_timers: Timers::new(),
+            initial_delay: config.initial_delay,
+            initial_delay_for: config.initial_delay_for.clone(),
+            journey_window: config.journey_window,
+            quirks: config.quirks.clone(),
+            abort_on_panic: config.abort_on_panic,
+            reject_privileged_source_ports: config.reject_privileged_source_ports,
+            allowed_source_ports: config.allowed_source_ports.clone(),
+            debug_capture: DebugCapture::new(config.debug_capture),
+            start_timers: Timers::new(),
+            tombstones: Tombstones::new(),
+            watchdog: config.watchdog_timeout.map(Watchdog::new),
+            auto_shrink_blksize: config.auto_shrink_blksize,
+            negotiation_report: config.negotiation_report,
+            restart_acks: config.restart_acks,
+            allow_mid_session_restart: config.allow_mid_session_restart,
+            no_rollover: config.no_rollover,
+            slow_storage_warn: config.slow_storage_warn,
+            blackholes: Blackholes::new(),
+            authorizer: None,
+            storage_unavailable: false,
+            peaks: Peaks::default(),
+            self_check: config.self_check || cfg!(debug_assertions),
+            ticks_since_check: 0,
+            storage_probe: storage::check(
+                &FsStorage,
+                &config.directory,
+                config.require_fast_storage,
+            )?,
+            #[cfg(target_os = "linux")]
+            skip_holes: config.skip_holes,
+            hooks: if config.exec_on_complete.is_some() || config.exec_on_fail.is_some() {
+                Some(Hooks::new(
+                    config.exec_on_complete.clone(),
+                    config.exec_on_fail.clone(),
+                ))
+            } else {
+                None
+            },
+            #[cfg(feature = "metrics")]
+            statsd: match &config.statsd {
+                Some(target) => Some(
+                    Statsd::new(target, &config.statsd_prefix, &config.statsd_tags)
+                        .map_err(|err| format!("Invalid statsd address {target}: {err}"))?,
+                ),
+                None => None,
+            },
+        };
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        Metrics::set(&server.metrics.started_at, started_at);
+        server.restore_metrics();
+
+        Ok(server)
+    }
+
+    /// Replaces the [`Clock`] used for retransmission timing.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the [`Observer`] notified of [`TransferEvent`]s.
+    pub fn set_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    /// Sets the [`Authorizer`] consulted for every read request, after the
+    /// manifest.
+    pub fn set_authorizer(&mut self, authorizer: Arc<dyn Authorizer>) {
+        self.authorizer = Some(authorizer);
+    }
+
+    /// Replaces the stream served for `--pipe`, standard input by default.
+    /// Does nothing without a pipe name.
+    pub fn set_pipe_source(&mut self, source: Box<dyn Read + Send>) {
+        if let Some(pipe) = &mut self.pipe {
+            pipe.set_source(source);
+        }
+    }
+
+    /// Replaces what happens when the listen loop stops polling for longer
+    /// than `--watchdog-timeout`, which is logging the [`Stall`] and
+    /// aborting the process. Must be called before the first poll, does
+    /// nothing without a watchdog timeout.
+    pub fn set_stall_handler(&mut self, handler: Arc<dyn Fn(&Stall) + Send + Sync>) {
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.set_handler(handler);
+        }
+    }
+
+    /// Returns a snapshot of the server counters.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Saves the counters and per-file stats to the `--metrics-snapshot`
+    /// file. This is done periodically and when [`Server::listen()`] stops.
+    /// Does nothing without a snapshot file.
+    pub fn save_metrics(&mut self) -> io::Result<()> {
+        match &mut self.metrics_file {
+            Some(file) => file.write(self.clock.now(), &self.metrics, &self.file_stats),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts from the counters of the `--metrics-snapshot` file, if any,
+    /// counting a restart. A snapshot that cannot be read is ignored.
+    fn restore_metrics(&mut self) {
+        let Some(file) = &self.metrics_file else {
+            return;
+        };
+        match file.load() {
+            Ok(Some(saved)) => {
+                saved.restore(&self.metrics, &mut self.file_stats);
+                Metrics::inc(&self.metrics.restarts);
+                logln!("Restored metrics from {}", file.path().display());
+                // The restored counters were already sent before the restart.
+                #[cfg(feature = "metrics")]
+                if let Some(statsd) = &mut self.statsd {
+                    statsd.resume_from(self.metrics.snapshot());
+                }
+            }
+            Ok(None) => {}
+            Err(err) => elogln!("Ignoring metrics snapshot {}: {err}", file.path().display()),
+        }
+    }
+
+    /// Saves the metrics snapshot once per snapshot interval.
+    fn save_metrics_periodically(&mut self) {
+        if self
+            .metrics_file
+            .as_ref()
+            .is_some_and(|file| file.due(self.clock.now()))
+        {
+            if let Err(err) = self.save_metrics() {
+                elogln!("Error while saving metrics: {err}");
+            }
+        }
+    }
+
+    /// Returns what the startup probe found out about the filesystem of the
+    /// served directory, `None` if it could not be probed.
+    pub fn storage_probe(&self) -> Option<StorageProbe> {
+        self.storage_probe
+    }
+
+    /// Forgets the peaks of sessions, request rate and throughput, the
+    /// sessions now active becoming the peak. This is done automatically on
+    /// `SIGUSR2`.
+    pub fn reset_peaks(&mut self) {
+        self.peaks
+            .reset(&self.metrics, self.clock.now(), self.connmap.len());
+    }
+
+    /// Loads the manifest file again, keeping the current manifest if the
+    /// file cannot be read or parsed. This is done automatically on
+    /// `SIGHUP`. Does nothing when no manifest is configured.
+    pub fn reload_manifest(&mut self) -> Result<(), TftpError> {
+        let Some(current) = &self.manifest else {
+            return Ok(());
+        };
+
+        let manifest = Manifest::load(&current.path)?;
+        logln!(
+            "Reloaded manifest {} with {} files",
+            manifest.path.display(),
+            manifest.len()
+        );
+        self.manifest = Some(manifest);
+        Ok(())
+    }
+
+    /// Resolves [`Config::bind_host`] again and [rebinds](Server::rebind) to
+    /// its address when it changed, keeping the current one if the host
+    /// cannot be resolved. This is done automatically on `SIGHUP`. Does
+    /// nothing without a bind host.
+    pub fn reload_bind_host(&mut self) -> Result<(), TftpError> {
+        let Some(host) = &self.bind_host else {
+            return Ok(());
+        };
+
+        let ip_address = resolve_host(host)?;
+        self.rebind(ip_address)
+    }
+
+    /// Moves the listener of [`Config::ip_address`] or [`Config::bind_host`]
+    /// to `ip_address`, on the same port. New requests are then only
+    /// accepted on the new address, while the previous one keeps serving
+    /// the sessions started on it and is closed once they all ended.
+    ///
+    /// Only a server created by [`Server::new()`] with a bind host or
+    /// several addresses can be rebound.
+    pub fn rebind(&mut self, ip_address: IpAddr) -> Result<(), TftpError> {
+        let Some(listeners) = &self.listeners else {
+            return Err("Rebinding requires a bind host or several ip addresses".into());
+        };
+
+        if let Some((previous, current)) = listeners.rebind(ip_address).map_err(TftpError::Bind)? {
+            logln!("Listening on {current} instead of {previous}, draining {previous}");
+            self.close_drained_listeners();
+        }
+        Ok(())
+    }
+
+    /// Returns the addresses the server listens on, including the ones
+    /// draining after a [rebind](Server::rebind).
+    pub fn listen_addresses(&self) -> Vec<SocketAddr> {
+        match &self.listeners {
+            Some(listeners) => listeners.addresses(),
+            None => self.socket.local_addr().into_iter().collect(),
+        }
+    }
+
+    /// Returns the popularity counters of the most recently requested files,
+    /// keyed by their path relative to the served directory, most requested
+    /// first.
+    pub fn file_stats(&self) -> Vec<(String, FileStats)> {
+        self.file_stats.sorted()
+    }
+
+    /// Returns the requests for the most recently requested files that do
+    /// not exist, keyed by their requested name, most requested first.
+    pub fn missing_files(&self) -> Vec<(String, MissingFile)> {
+        self.missing.sorted()
+    }
+
+    /// Returns the last malformed packets, denied requests and packets
+    /// answered with a protocol error, oldest first, up to
+    /// `--debug-capture` of them.
+    pub fn debug_capture(&self) -> Vec<CapturedPacket> {
+        self.debug_capture.entries()
+    }
+
+    /// Forgets the packets returned by [`Server::debug_capture()`].
+    pub fn clear_debug_capture(&self) {
+        self.debug_capture.clear();
+    }
+
+    /// Returns the client hosts whose packets look spread over several
+    /// servers by a NAT or load balancer, by address.
+    pub fn sprayed_clients(&self) -> Vec<IpAddr> {
+        self.clients.sprayed()
+    }
+
+    /// Returns the client hosts with bytes in their current `--quota`
+    /// window, by address, none without a quota.
+    pub fn quota_usage(&self) -> Vec<QuotaUsage> {
+        self.quota.map_or(vec![], |quota| {
+            self.clients.quota_usage(self.clock.now(), &quota)
+        })
+    }
+
+    /// Returns the number of transfers currently in progress, uploads and
+    /// transfers on [`Worker`] threads included.
+    pub fn session_count(&self) -> usize {
+        let delegated = self.delegated.iter().filter(|(d, _)| !d.aborted).count();
+        self.connmap.len() + self.uploads.len() + delegated
+    }
+
+    /// Returns the transfers currently in progress grouped by client host,
+    /// ordered by client address.
+    pub fn active_sessions(&self) -> Vec<ClientSessions> {
+        self.clients.groups()
+    }
+
+    /// Starts listening for connections. Note that this function does not finish running until termination,
+    /// or until a fatal error occurs.
+    ///
+    /// While listening, `SIGUSR1` prints the server counters and the most
+    /// requested files, and `SIGUSR2` resets their peaks.
+    ///
+    /// With `--oneshot`, returns once the first transfer ends, with an error
+    /// if it failed. With `--metrics-snapshot`, `SIGTERM` returns, and the
+    /// snapshot is saved whenever this returns.
+    pub fn listen(&mut self) -> Result<(), TftpError> {
+        #[cfg(feature = "cli")]
+        {
+            signal::watch(Signal::User1);
+            signal::watch(Signal::User2);
+            if self.metrics_file.is_some() {
+                signal::watch(Signal::Terminate);
+            }
+        }
+        let stopped = self.serve();
+        if let Err(err) = self.save_metrics() {
+            elogln!("Error while saving metrics: {err}");
+        }
+        stopped
+    }
+
+    /// Polls until `--oneshot` or `SIGTERM` stops the server.
+    fn serve(&mut self) -> Result<(), TftpError> {
+        loop {
+            self.poll()?;
+            #[cfg(feature = "cli")]
+            if signal::take(Signal::Terminate) {
+                logln!("Stopping on SIGTERM");
+                return Ok(());
+            }
+            if self.oneshot {
+                let metrics = self.metrics();
+                if metrics.completed > 0 {
+                    return Ok(());
+                }
+                if metrics.failed > 0 {
+                    return Err(TftpError::Io(io::Error::other("transfer failed")));
+                }
+            }
+        }
+    }
+
+    /// Waits for an incoming packet and handles it together with any packets
+    /// already queued behind it, then retransmits to or drops the sessions
+    /// whose timeout has expired.
+    ///
+    /// Several ACKs queued from the same client are coalesced, so only the
+    /// highest valid one is applied and a single window is sent in response.
+    ///
+    /// Errors of individual transfers are logged. An error is only returned
+    /// when the socket itself can no longer be used.
+    pub fn poll(&mut self) -> Result<(), TftpError> {
+        let mut batch = vec![];
+        if let Ok(received) = self.receive() {
+            batch.push(received);
+            if self.socket.set_nonblocking(true).is_ok() {
+                while batch.len() < MAX_BATCH {
+                    match self.receive() {
+                        Ok(received) => batch.push(received),
+                        Err(_) => break,
+                    }
+                }
+                self.socket.set_nonblocking(false).map_err(TftpError::Io)?;
+            }
+        }
+        let started = Instant::now();
+        let received = !batch.is_empty();
+        self.probe_directory();
+
+        let mut acked: HashMap<SocketAddr, Vec<u16>> = HashMap::new();
+        for (packet, from) in &batch {
+            if let Packet::Ack(block) = packet {
+                acked.entry(*from).or_default().push(*block);
+            }
+        }
+
+        for (packet, from) in batch {
+            match packet {
+                Packet::Ack(_) => {
+                    if let Some(blocks) = acked.remove(&from) {
+                        let block = self.coalesce_acks(&from, &blocks);
+                        self.dispatch(Packet::Ack(block), from);
+                    }
+                }
+                packet => self.dispatch(packet, from),
+            }
+        }
+
+        #[cfg(feature = "cli")]
+        self.handle_signals();
+        self.handle_delayed_starts();
+        self.handle_timeouts();
+        self.handle_deadlines();
+        self.handle_idle_sessions();
+        self.handle_upload_timeouts();
+        self.reap_delegated();
+        self.serve_pending();
+        self.report_progress();
+        self.report_missing();
+        self.report_journeys();
+        self.close_drained_listeners();
+        self.run_self_check();
+        #[cfg(feature = "metrics")]
+        self.flush_statsd();
+        self.save_metrics_periodically();
+        self.record_tick(started.elapsed());
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.beat(self.connmap.len(), received);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the served directory can still be opened, for example
+    /// after its network mount went away, and switches to or out of the
+    /// degraded mode where read requests are refused. Only the transitions
+    /// are logged.
+    fn probe_directory(&mut self) {
+        let probe = fs::read_dir(&self.directory);
+        match (&probe, self.storage_unavailable) {
+            (Err(err), false) => elogln!(
+                "Served directory {} is unavailable, refusing read requests until it returns: {err}",
+                self.directory.display()
+            ),
+            (Ok(_), true) => logln!(
+                "Served directory {} is available again, resuming service",
+                self.directory.display()
+            ),
+            _ => {}
+        }
+        self.storage_unavailable = probe.is_err();
+        Metrics::set(
+            &self.metrics.storage_unavailable,
+            self.storage_unavailable as u64,
+        );
+    }
+
+    /// Records how long a tick spent handling packets and timers, warning
+    /// when it is over the budget.
+    fn record_tick(&self, elapsed: Duration) {
+        self.metrics.record_tick(elapsed);
+        if elapsed > self.tick_budget {
+            elogln!(
+                "Tick took {elapsed:?}, over the {:?} budget",
+                self.tick_budget
+            );
+            Metrics::inc(&self.metrics.slow_ticks);
+        }
+    }
+
+    /// Receives the next packet, skipping the ones not sent to an address of
+    /// `--answer-on` and the broadcast requests not to be answered.
+    fn receive(&mut self) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        loop {
+            let buf = &mut self.receive_buffer;
+            let (size, from, destination) = self.socket.recv_with_destination(buf)?;
+            let packet = match Packet::deserialize(&buf[..size]) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    self.debug_capture.record(
+                        from,
+                        Direction::ToServer,
+                        &buf[..size],
+                        CaptureReason::Malformed,
+                        err.to_string(),
+                    );
+                    return Err(err);
+                }
+            };
+            tracelog!("{from}: [Packet] {packet}");
+            if !self.answer_on.is_empty()
+                && !destination.is_some_and(|destination| self.answer_on.contains(&destination))
+            {
+                logln!("{from}: Ignored packet not sent to an --answer-on address");
+                Metrics::inc(&self.metrics.wrong_destination);
+                continue;
+            }
+
+            if matches!(packet, Packet::Rrq { .. } | Packet::Wrq { .. })
+                && self.reject_privileged_source_ports
+                && is_privileged_source(from.port(), &self.allowed_source_ports)
+            {
+                logln!("{from}: Ignored request from a privileged source port");
+                Metrics::inc(&self.metrics.privileged_source_drops);
+                continue;
+            }
+
+            if let (Packet::Rrq { filename, .. }, Some(destination)) = (&packet, destination) {
+                if is_broadcast(destination) {
+                    if !self
+                        .decode_filename(filename)
+                        .is_ok_and(|filename| self.answers_discovery(&filename))
+                    {
+                        logln!("{from}: Ignored discovery request for {filename} sent to {destination}");
+                        Metrics::inc(&self.metrics.ignored_discovery);
+                        continue;
+                    }
+                    logln!(
+                        "{from}: Answering discovery request for {filename} sent to {destination}"
+                    );
+                }
+            }
+
+            return Ok((packet, from));
+        }
+    }
+
+    /// Keeps `packet` received from `from` in the debug capture, as a packet
+    /// answered with a protocol error.
+    fn capture_received(&self, packet: &Packet, from: SocketAddr, detail: String) {
+        if let Ok(bytes) = packet.serialize() {
+            self.debug_capture.record(
+                from,
+                Direction::ToServer,
+                &bytes,
+                CaptureReason::ProtocolError,
+                detail,
+            );
+        }
+    }
+
+    /// Returns whether a read request for `filename` sent to a broadcast
+    /// address is answered.
+    fn answers_discovery(&self, filename: &str) -> bool {
+        match self.answer_broadcast {
+            BroadcastPolicy::Always => true,
+            BroadcastPolicy::Never => false,
+            BroadcastPolicy::IfFileExists => {
+                if self.listing.as_ref().is_some_and(|l| l.name == filename)
+                    || self.menu.as_ref().is_some_and(|menu| menu.name == filename)
+                {
+                    return true;
+                }
+                self.manifest
+                    .as_ref()
+                    .is_none_or(|manifest| manifest.allows(filename))
+                    && self.request_path(filename).is_ok_and(|path| {
+                        check_file_exists(
+                            &path,
+                            &self.directory,
+                            &self.canonical_directory,
+                            self.follow_symlinks,
+                        ) == ErrorCode::FileExists
+                    })
+            }
+        }
+    }
+
+    /// Returns the requested `filename`, trimmed when
+    /// `--trim-request-whitespace` is set, percent-decoded when
+    /// `--percent-decode` is set and relative to the served directory
+    /// unless `--strict-paths` is set.
+    fn decode_filename(&self, filename: &str) -> Result<String, String> {
+        let filename = if self.trim_request_whitespace {
+            filename.trim_matches(|c: char| c.is_ascii_whitespace())
+        } else {
+            filename
+        };
+
+        let decoded = if self.percent_decode {
+            percent::decode(filename)?
+        } else {
+            filename.to_string()
+        };
+        // Absolute paths into the served directory keep working as before.
+        if self.strict_paths || Path::new(&decoded).starts_with(&self.directory) {
+            return Ok(decoded);
+        }
+        Ok(root_relative(&decoded).unwrap_or(&decoded).to_string())
+    }
+
+    /// Returns the path of the requested `filename` in the served
+    /// directory, see [`sanitize_request_path()`]. Absolute paths into the
+    /// served directory are taken relative to it.
+    fn request_path(&self, filename: &str) -> Result<PathBuf, ErrorCode> {
+        let relative = Path::new(filename)
+            .strip_prefix(&self.directory)
+            .ok()
+            .and_then(Path::to_str)
+            .unwrap_or(filename);
+        sanitize_request_path(relative).map(|relative| self.directory.join(relative))
+    }
+
+    /// Returns the decoded `filename`, or the `--empty-request-file` when it
+    /// is empty or names the bare root, `None` when there is none. Blank
+    /// filenames never reach the filesystem.
+    fn map_empty(&self, filename: String) -> Option<String> {
+        if is_blank(&filename) {
+            return self.empty_request_file.clone();
+        }
+        Some(filename)
+    }
+
+    /// Picks the ACK that acknowledges the most blocks of the current window,
+    /// or the last one when none of them is valid.
+    fn coalesce_acks(&self, from: &SocketAddr, blocks: &[u16]) -> u16 {
+        let last = blocks[blocks.len() - 1];
+        match self.connmap.get(from) {
+            Some(state) => blocks
+                .iter()
+                .filter(|&&block| state.session.acknowledges(block))
+                .max_by_key(|&&block| block.wrapping_sub(state.session.block_number()))
+                .copied()
+                .unwrap_or(last),
+            None => last,
+        }
+    }
+
+    /// Handles `packet`, catching a panic unless `--abort-on-panic` is set.
+    /// A panic aborts the session of the client alone, with an ERROR to it
+    /// when possible, and the server keeps serving the other clients.
+    fn dispatch(&mut self, packet: Packet, from: SocketAddr) {
+        if self.abort_on_panic {
+            return self.handle_packet(packet, from);
+        }
+
+        let summary = packet.to_string();
+        // The session of the client is dropped after a panic, so whatever
+        // half-updated state it left behind is not used again.
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| self.handle_packet(packet, from)));
+        let Err(payload) = handled else {
+            return;
+        };
+
+        let message = match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown panic".to_string(),
+        };
+        let session = match self.connmap.get(&from) {
+            Some(state) => format!(
+                "session of {} at {} bytes acknowledged",
+                state.filepath.display(),
+                state.session.bytes_acked()
+            ),
+            None => "no session".to_string(),
+        };
+        elogln!("{from}: Panic while handling {summary} ({session}): {message}");
+        Metrics::inc(&self.metrics.panics);
+
+        let reason = format!("internal error: {message}");
+        let sent = if self.connmap.contains_key(&from) {
+            self.terminate(&from, "internal error", &reason)
+        } else {
+            Message::send_error(
+                &*self.socket,
+                &from,
+                ErrorCode::NotDefined,
+                "internal error",
+            )
+        };
+        if let Err(err) = sent {
+            elogln!("{from}: Error while sending error: {err}");
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet, from: SocketAddr) {
+        match packet {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+            } => {
+                if self.is_retransmitted_request(&from, &filename, &options) {
+                    debuglog!("{from}: Retransmitted request for {filename}");
+                    Metrics::inc(&self.metrics.retransmitted_requests);
+                    if self
+                        .connmap
+                        .get(&from)
+                        .is_none_or(|state| state.deferred.is_some())
+                    {
+                        // The first answer is still held back, or a worker
+                        // thread answers again from its own port.
+                        return;
+                    }
+                    let request = Packet::Rrq {
+                        filename,
+                        mode,
+                        options,
+                    };
+                    if let Err(err) = self.drive(&from, SessionEvent::PacketReceived(request)) {
+                        elogln!("{from}: Error while answering again: {err}")
+                    }
+                    return;
+                }
+                if self.connmap.contains_key(&from) || self.delegated_to(&from).is_some() {
+                    match self.conflicting_request {
+                        ConflictPolicy::Reject => {
+                            logln!("{from}: Refused {filename}: transfer in progress");
+                            if let Err(err) = Message::send_error(
+                                &*self.socket,
+                                &from,
+                                ErrorCode::NotDefined,
+                                "transfer in progress",
+                            ) {
+                                elogln!("{from}: Error while sending error: {err}")
+                            }
+                            return;
+                        }
+                        ConflictPolicy::Replace => {
+                            self.fail_session(&from, "replaced by a new request");
+                            self.abort_delegated(&from);
+                        }
+                    }
+                }
+                Metrics::inc(&self.metrics.requests);
+                self.peaks.record_request(&self.metrics, self.clock.now());
+                if self.storage_unavailable {
+                    Metrics::inc(&self.metrics.unavailable_rejections);
+                    if let Err(err) = Message::send_error(
+                        &*self.socket,
+                        &from,
+                        ErrorCode::NotDefined,
+                        STORAGE_UNAVAILABLE,
+                    ) {
+                        elogln!("{from}: Error while sending error: {err}")
+                    }
+                    return;
+                }
+                let Some(transfer_mode) = Mode::parse(&mode) else {
+                    logln!("{from}: Refused {filename}: unsupported mode {mode}");
+                    if let Err(err) = Message::send_error(
+                        &*self.socket,
+                        &from,
+                        ErrorCode::IllegalOperation,
+                        "unsupported transfer mode",
+                    ) {
+                        elogln!("{from}: Error while sending error: {err}")
+                    }
+                    return;
+                };
+                let filename = match self.decode_filename(&filename) {
+                    Ok(decoded) => {
+                        if decoded != filename && root_relative(&filename).is_some() {
+                            debuglog!(
+                                "{from}: Reading {filename} from the served directory as {decoded}"
+                            );
+                        }
+                        decoded
+                    }
+                    Err(err) => {
+                        elogln!("{from}: Invalid filename {filename}: {err}");
+                        let request = Packet::Rrq {
+                            filename,
+                            mode,
+                            options,
+                        };
+                        self.capture_received(
+                            &request,
+                            from,
+                            format!("invalid filename encoding: {err}"),
+                        );
+                        if let Err(err) = Message::send_error(
+                            &*self.socket,
+                            &from,
+                            ErrorCode::IllegalOperation,
+                            "invalid filename encoding",
+                        ) {
+                            elogln!("{from}: Error while sending error: {err}")
+                        }
+                        return;
+                    }
+                };
+                let blank = is_blank(&filename);
+                if blank {
+                    Metrics::inc(&self.metrics.empty_requests);
+                }
+                let Some(filename) = self.map_empty(filename) else {
+                    logln!("{from}: Refused request for an empty filename");
+                    if let Err(err) = Message::send_error(
+                        &*self.socket,
+                        &from,
+                        ErrorCode::FileNotFound,
+                        "empty filename",
+                    ) {
+                        elogln!("{from}: Error while sending error: {err}")
+                    }
+                    return;
+                };
+                if blank {
+                    logln!("{from}: Serving {filename} for an empty filename");
+                }
+                let request = (filename.clone(), options.clone());
+                if let Err(err) = self.handle_rrq(filename, transfer_mode, options, &from) {
+                    elogln!("{from}: Error while sending file: {err}")
+                }
+                let generation = self.generation;
+                if let Some(state) = self
+                    .connmap
+                    .get_mut(&from)
+                    .filter(|state| state.generation == generation)
+                {
+                    state.request = Some(request);
+                } else if let Some((delegated, _)) =
+                    self.delegated.last_mut().filter(|(delegated, _)| {
+                        delegated.client == from && delegated.request.is_none()
+                    })
+                {
+                    delegated.request = Some(request);
+                }
+            }
+            Packet::Ack(block) => {
+                if !self.connmap.contains_key(&from) {
+                    self.adopt_port(block, &from);
+                }
+                if let Err(err) = self.handle_ack(block, &from) {
+                    elogln!("{from}: Error while handling ack: {err}")
+                }
+            }
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+            } if self.writable => {
+                if let Err(err) = self.handle_wrq(filename, &mode, options, &from) {
+                    elogln!("{from}: Error while receiving file: {err}")
+                }
+            }
+            Packet::Data { block_num, data } if self.uploads.contains_key(&from) => {
+                if let Err(err) = self.handle_upload_data(block_num, &data, &from) {
+                    elogln!("{from}: Error while receiving file: {err}")
+                }
+            }
+            Packet::Error { code, msg } => {
+                logln!("{from}: Received ERROR {code}: {msg}");
+                if let Some(upload) = self.end_upload(&from) {
+                    logln!("{from}: Upload of {} aborted", upload.path().display());
+                    return;
+                }
+                self.fail_session(&from, &format!("client sent error {code}: {msg}"));
+            }
+            _ => {
+                elogln!("{from}: Received invalid packet {packet}");
+                self.capture_received(&packet, from, "invalid request".to_string());
+                if let Err(err) = Message::send_error(
+                    &*self.socket,
+                    &from,
+                    ErrorCode::IllegalOperation,
+                    "invalid request",
+                ) {
+                    elogln!("{from}: Error while sending error: {err}")
+                }
+            }
+        };
+    }
+
+    /// Returns whether a read request repeats the one of a session from the
+    /// same client that is still negotiating, or of its transfer running on
+    /// a [`Worker`] thread.
+    fn is_retransmitted_request(
+        &self,
+        from: &SocketAddr,
+        filename: &str,
+        options: &[TransferOption],
+    ) -> bool {
+        let request = match self.connmap.get(from) {
+            Some(state) if state.session.negotiating() => state.request.as_ref(),
+            Some(_) => None,
+            None => self
+                .delegated_to(from)
+                .and_then(|delegated| delegated.request.as_ref()),
+        };
+        let Some((requested, requested_options)) = request else {
+            return false;
+        };
+        let Some(filename) = self
+            .decode_filename(filename)
+            .ok()
+            .and_then(|filename| self.map_empty(filename))
+        else {
+            return false;
+        };
+        *requested == filename && requested_options.as_slice() == options
+    }
+
+    /// Moves the only session of the client host of `from` to the port of
+    /// `from`, when `block` is valid for it and `--loose-tid` or its
+    /// `loose-tid` quirk is set. Some middleboxes rewrite the source port of
+    /// packets in the middle of a transfer.
+    fn adopt_port(&mut self, block: u16, from: &SocketAddr) {
+        let &[previous] = self.clients.sessions(from.ip()) else {
+            return;
+        };
+        if !self.connmap.get(&previous).is_some_and(|state| {
+            (self.loose_tid || state.quirks.loose_tid) && state.session.acknowledges(block)
+        }) {
+            return;
+        }
+
+        let state = self.connmap.remove(&previous).unwrap();
+        elogln!("{previous}: WARNING: ack {block} arrived from port {}, moving the transfer there (--loose-tid)", from.port());
+        Metrics::inc(&self.metrics.tid_migrations);
+        self.clients.remove(&previous);
+        self.clients.add(*from);
+
+        let retransmit_at = state.session.retransmit_at();
+        self.retransmit_timers
+            .cancel(retransmit_at, previous, state.generation);
+        self.retransmit_timers
+            .schedule(retransmit_at, *from, state.generation);
+        self.progress_timers
+            .schedule(state.progress.next_due(), *from, state.generation);
+        if let Some(max) = self.max_transfer_duration {
+            self.deadline_timers
+                .schedule(state.started + max, *from, state.generation);
+        }
+        if let Some(timeout) = self.session_timeout {
+            self.idle_timers
+                .schedule(state.last_activity + timeout, *from, state.generation);
+        }
+        self.connmap.insert(*from, state);
+    }
+
+    /// Returns whether `to` may read or write `filename`: the manifest,
+    /// which does not apply to the pipe, the listing and the boot menu,
+    /// then the [`Authorizer`] must allow it.
+    fn authorize(&self, request: &RequestInfo) -> Decision {
+        let filename = &request.filename;
+        let generated = self
+            .pipe
+            .as_ref()
+            .is_some_and(|pipe| pipe.name == *filename)
+            || self.listing.as_ref().is_some_and(|l| l.name == *filename)
+            || self
+                .menu
+                .as_ref()
+                .is_some_and(|menu| menu.name == *filename);
+        let manifest = self
+            .manifest
+            .as_ref()
+            .filter(|_| !generated)
+            .map(|manifest| manifest as &dyn Authorizer);
+
+        manifest
+            .into_iter()
+            .chain(self.authorizer.as_deref())
+            .map(|authorizer| authorizer.authorize_request(request))
+            .find(|decision| *decision != Decision::Allow)
+            .unwrap_or(Decision::Allow)
+    }
+
+    /// Describes a read or, if `write`, a write request from `to` for the
+    /// [`Authorizer`]s, with its options negotiated as they would be for a
+    /// file of unknown size.
+    fn request_info(
+        &mut self,
+        to: &SocketAddr,
+        filename: &str,
+        options: &[TransferOption],
+        quirks: &QuirkSet,
+        write: bool,
+    ) -> RequestInfo {
+        let mut negotiated = if quirks.no_oack {
+            vec![]
+        } else {
+            options.to_vec()
+        };
+        let (limits, _) = self.request_limits(to, options, quirks);
+        RequestInfo {
+            client: *to,
+            filename: filename.to_string(),
+            options: options.to_vec(),
+            negotiated: parse_options(&mut negotiated, None, self.retransmit_timeout, &limits).ok(),
+            transfer: self.generation + 1,
+            journey: self
+                .clients
+                .next_journey(to.ip(), self.clock.now(), self.journey_window),
+            quirks: quirks.clone(),
+            write,
+        }
+    }
+
+    /// Returns the option limits of a request from `to` for the `requested`
+    /// options, with the `max-blksize` of its `quirks`, and whether its
+    /// block size is clamped by `--auto-shrink-blksize`.
+    fn request_limits(
+        &mut self,
+        to: &SocketAddr,
+        requested: &[TransferOption],
+        quirks: &QuirkSet,
+    ) -> (OptionLimits, bool) {
+        let mut limits = self.option_limits.clone();
+        if let Some(max_blksize) = quirks.max_blksize {
+            limits.max_blksize = max_blksize;
+        }
+        let shrunk = self.auto_shrink_blksize
+            && requested.iter().any(|option| {
+                option.option == OptionType::BlockSize && option.value > DEFAULT_BLOCK_SIZE as u64
+            })
+            && self.blackholes.contains(to.ip(), self.clock.now());
+        if shrunk {
+            limits.max_blksize = limits.max_blksize.min(DEFAULT_BLOCK_SIZE);
+        }
+        (limits, shrunk)
+    }
+
+    /// Runs the checks a request of `request.client` passes before its
+    /// file is looked up: the manifest and [`Authorizer`]s, `--max-per-ip`
+    /// and `--quota`. Returns whether the request was admitted, having
+    /// answered the client with an ERROR otherwise.
+    fn admit(&mut self, request: RequestInfo) -> Result<bool, Box<dyn Error>> {
+        let to = &request.client;
+        let filename = &request.filename;
+        let decision = self.authorize(&request);
+        if decision != Decision::Allow {
+            logln!("{to}: Refused {filename}: {decision:?}");
+            let (code, message) = match &decision {
+                Decision::Deny { code, message } => (*code, message.as_str()),
+                _ => (ErrorCode::FileNotFound, "file does not exist"),
+            };
+            let error = Packet::Error {
+                code,
+                msg: message.to_string(),
+            };
+            if let Ok(bytes) = error.serialize() {
+                self.debug_capture.record(
+                    *to,
+                    Direction::ToClient,
+                    &bytes,
+                    CaptureReason::Denied,
+                    format!("refused {filename}: {decision:?}"),
+                );
+            }
+            self.emit(TransferEvent::Denied {
+                client: *to,
+                filename: filename.clone(),
+                decision: decision.clone(),
+            });
+            Message::send_error(&*self.socket, to, code, message)?;
+            return Ok(false);
+        }
+
+        if let Some(max) = self.max_per_ip {
+            if !self.clients.has_room(to, max) {
+                logln!("{to}: Client has {max} transfers, rejected request");
+                Metrics::inc(&self.metrics.client_rejections);
+                Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::NotDefined,
+                    "too many transfers, retry later",
+                )?;
+                return Ok(false);
+            }
+        }
+        // Uploads are not counted against the quota of downloads.
+        if let Some(left) = self
+            .quota
+            .filter(|_| !request.write)
+            .and_then(|quota| self.clients.over_quota(to.ip(), self.clock.now(), &quota))
+        {
+            logln!("{to}: Client used up its quota, rejected request");
+            Metrics::inc(&self.metrics.quota_rejections);
+            Message::send_error(
+                &*self.socket,
+                to,
+                ErrorCode::NotDefined,
+                &quota_message(left),
+            )?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn handle_rrq(
+        &mut self,
+        filename: String,
+        mode: Mode,
+        options: Vec<TransferOption>,
+        to: &SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        let quirks = quirks::matching(&self.quirks, to.ip(), &filename);
+        let request = self.request_info(to, &filename, &options, &quirks, false);
+        if !self.admit(request)? {
+            return Ok(());
+        }
+
+        if !quirks.is_empty() {
+            logln!("{to}: Quirks {quirks} for {filename}");
+        }
+        let file_path = &match self.request_path(&filename) {
+            Ok(path) => path,
+            Err(code) => {
+                logln!("{to}: Refused {filename}: invalid path");
+                return Message::send_error(&*self.socket, to, code, "file access violation");
+            }
+        };
+
+        if let Some(pipe) = self.pipe.as_mut().filter(|pipe| pipe.name == filename) {
+            let Some(source) = pipe.take() else {
+                logln!("{to}: {filename} was already streamed, rejected request");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::NotDefined,
+                    "server busy, stream already served",
+                );
+            };
+            logln!("{to}: Streaming the pipe as {filename}");
+            let (source, size) = translated(to, file_path, source, None, mode);
+            return self.start_transfer(to, file_path, source, size, options, None, quirks);
+        }
+
+        if let Some(listing) = self.listing.as_ref().filter(|l| l.name == filename) {
+            let content = match listing.generate(&self.directory, &|path| self.servable(to, path)) {
+                Ok(content) => content,
+                Err(err) => {
+                    elogln!("{to}: Cannot list {}: {err}", self.directory.display());
+                    return Message::send_error(
+                        &*self.socket,
+                        to,
+                        ErrorCode::NotDefined,
+                        "cannot generate listing",
+                    );
+                }
+            };
+            let size = content.len() as u64;
+            let content = Box::new(Cursor::new(content));
+            let (source, size) = translated(to, file_path, content, Some(size), mode);
+            return self.start_transfer(to, file_path, source, size, options, None, quirks);
+        }
+
+        if let Some(mut menu) = self.menu.take_if(|menu| menu.name == filename) {
+            // What an authorizer lets one client see, it may hide from
+            // the next one.
+            if self.authorizer.is_some() {
+                menu.cached = None;
+            }
+            let now = self.clock.now();
+            let content = menu.generate(&self.directory, now, &|path| self.servable(to, path));
+            self.menu = Some(menu);
+            let content = content?;
+            let size = content.len() as u64;
+            let content = Box::new(Cursor::new(content));
+            let (source, size) = translated(to, file_path, content, Some(size), mode);
+            return self.start_transfer(to, file_path, source, size, options, None, quirks);
+        }
+
+        if receiving(&self.uploads, file_path) {
+            logln!("{to}: Refused {filename}: upload in progress");
+            return Message::send_error(
+                &*self.socket,
+                to,
+                ErrorCode::FileNotFound,
+                "file does not exist",
+            );
+        }
+
+        // Name and path of the file read from disk.
+        let mut source_name = filename.clone();
+        let mut source_path = file_path.clone();
+        let mut compressed = false;
+        let mut storage_time = Duration::ZERO;
+        let clock = &*self.clock;
+        let metrics = &self.metrics;
+
+        match storage::timed(clock, metrics, &mut storage_time, || {
+            check_file_exists(
+                file_path,
+                &self.directory,
+                &self.canonical_directory,
+                self.follow_symlinks,
+            )
+        }) {
+            ErrorCode::FileNotFound => {
+                let gz_name = format!("{filename}.gz");
+                let mut gz_path = file_path.as_os_str().to_owned();
+                gz_path.push(".gz");
+                let gz_path = PathBuf::from(gz_path);
+                if !self.compressed_fallback
+                    || storage::timed(clock, metrics, &mut storage_time, || {
+                        check_file_exists(
+                            &gz_path,
+                            &self.directory,
+                            &self.canonical_directory,
+                            self.follow_symlinks,
+                        )
+                    }) != ErrorCode::FileExists
+                {
+                    self.record_missing(&filename, to);
+                    return Message::send_error(
+                        &*self.socket,
+                        to,
+                        ErrorCode::FileNotFound,
+                        "file does not exist",
+                    );
+                }
+                logln!("{to}: Serving {filename} from {gz_name}");
+                source_name = gz_name;
+                source_path = gz_path;
+                compressed = true;
+            }
+            ErrorCode::AccessViolation => {
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::AccessViolation,
+                    "file access violation",
+                );
+            }
+            ErrorCode::FileExists => {
+                // OK for sending
+            }
+            _ => {
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::NotDefined,
+                    "unexpected error",
+                );
+            }
+        }
+
+        // FIFOs would block the server opening or reading them, devices
+        // like /dev/zero would never end.
+        let regular = storage::timed(clock, metrics, &mut storage_time, || {
+            fs::metadata(&source_path).is_ok_and(|metadata| metadata.is_file())
+        });
+        if !regular {
+            return self.refuse_irregular(to, &source_name);
+        }
+
+        let reader = storage::timed(clock, metrics, &mut storage_time, || {
+            fs::canonicalize(&source_path)
+        })
+        .unwrap_or_else(|_| source_path.clone());
+        if let Some(readers) = &self.readers {
+            let resumed = self.connmap.get(to).and_then(|s| s.reader.as_ref()) == Some(&reader);
+            if !resumed && !readers.has_room(&reader) {
+                return self.defer_rrq(filename, mode, options, reader, to);
+            }
+        }
+
+        let clock = &*self.clock;
+        let metrics = &self.metrics;
+        // Beneath resolves relative to the served directory, so it gets the
+        // sanitized path rather than the name as the client spelled it. A
+        // path outside the directory is passed as is for the kernel to
+        // refuse.
+        let opened = storage::timed(clock, metrics, &mut storage_time, || match &self.beneath {
+            Some(beneath) => beneath.open(
+                source_path
+                    .strip_prefix(&self.directory)
+                    .unwrap_or(&source_path),
+            ),
+            None => File::open(&source_path),
+        });
+        let file = match opened {
+            Ok(file) => file,
+            Err(err) if self.beneath.is_some() && beneath::is_escape(&err) => {
+                elogln!("{to}: Refused to open {source_name}: {err}");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::AccessViolation,
+                    "file access violation",
+                );
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut allocated = None;
+        let (source, size) = if compressed {
+            let (decompressed, size) =
+                storage::timed(clock, metrics, &mut storage_time, || gzip::decompress(file))?;
+            translated(to, file_path, decompressed, size, mode)
+        } else {
+            let metadata = storage::timed(clock, metrics, &mut storage_time, || file.metadata())?;
+            if !metadata.is_file() {
+                return self.refuse_irregular(to, &source_name);
+            }
+            let size = metadata.len();
+            allocated = sparse::allocated_size(&metadata);
+            if let Some(allocated) = allocated {
+                logln!("{to}: {source_name} is sparse, {allocated} of {size} bytes allocated");
+            }
+            match allocated {
+                #[cfg(target_os = "linux")]
+                Some(_) if self.skip_holes => {
+                    let holes = Box::new(sparse::HoleReader::new(file, size));
+                    translated(to, file_path, holes, Some(size), mode)
+                }
+                // Blocks of netascii cannot be found again by seeking.
+                _ if mode == Mode::Netascii => {
+                    translated(to, file_path, Box::new(file), Some(size), mode)
+                }
+                _ => (state::file_source(file, size), Some(size)),
+            }
+        };
+        self.file_stats.record_request(&self.stats_key(&reader));
+        let generation = self.generation;
+        let delegated = self.delegated.len();
+        self.start_transfer(to, file_path, source, size, options, Some(reader), quirks)?;
+        if let Some(state) = self
+            .connmap
+            .get_mut(to)
+            .filter(|state| state.generation > generation)
+        {
+            state.allocated = allocated;
+            state.storage_time += storage_time;
+        } else if let Some((delegated, _)) = self.delegated.get_mut(delegated) {
+            delegated.allocated = allocated;
+            delegated.storage_time += storage_time;
+        }
+        Ok(())
+    }
+
+    /// Starts receiving the file of a write request with `--writable`,
+    /// answering with ACK 0, or an OACK for the accepted `blksize`,
+    /// `timeout` and `tsize` options. The path is checked as for a read
+    /// request, and an existing file is only replaced with `--overwrite`.
+    fn handle_wrq(
+        &mut self,
+        filename: String,
+        mode: &str,
+        options: Vec<TransferOption>,
+        to: &SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        if Mode::parse(mode) != Some(Mode::Octet) {
+            logln!("{to}: Refused upload of {filename}: unsupported mode {mode}");
+            return Message::send_error(
+                &*self.socket,
+                to,
+                ErrorCode::IllegalOperation,
+                "unsupported transfer mode",
+            );
+        }
+        let Some(filename) = self
+            .decode_filename(&filename)
+            .ok()
+            .filter(|filename| !is_blank(filename))
+        else {
+            logln!("{to}: Refused upload of {filename}: invalid filename");
+            return Message::send_error(
+                &*self.socket,
+                to,
+                ErrorCode::AccessViolation,
+                "invalid filename",
+            );
+        };
+        let path = match self.request_path(&filename) {
+            Ok(path) => path,
+            Err(code) => {
+                logln!("{to}: Refused upload of {filename}: invalid path");
+                return Message::send_error(&*self.socket, to, code, "file access violation");
+            }
+        };
+
+        if let Some(upload) = self
+            .uploads
+            .get(to)
+            .filter(|upload| upload.path() == path && upload.block() == 0)
+        {
+            logln!("{to}: Retransmitted write request for {filename}");
+            return Message::send_packet(&*self.socket, to, &upload.answer);
+        }
+        let quirks = quirks::matching(&self.quirks, to.ip(), &filename);
+        let request = self.request_info(to, &filename, &options, &quirks, true);
+        if !self.admit(request)? {
+            return Ok(());
+        }
+        let inside = path
+            .parent()
+            .and_then(|parent| fs::canonicalize(parent).ok())
+            .map(|parent| parent.starts_with(&self.canonical_directory));
+        match check_file_exists(
+            &path,
+            &self.directory,
+            &self.canonical_directory,
+            self.follow_symlinks,
+        ) {
+            ErrorCode::AccessViolation => {
+                logln!("{to}: Refused upload of {filename}: outside of the directory");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::AccessViolation,
+                    "file access violation",
+                );
+            }
+            _ if inside != Some(true) => {
+                logln!("{to}: Refused upload of {filename}: no such directory");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::AccessViolation,
+                    "file access violation",
+                );
+            }
+            ErrorCode::FileExists if !self.overwrite => {
+                logln!("{to}: Refused upload of {filename}: file exists");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::FileExists,
+                    "file already exists",
+                );
+            }
+            ErrorCode::FileExists if !path.is_file() => {
+                return self.refuse_irregular(to, &filename);
+            }
+            _ => {}
+        }
+
+        let mut options: Vec<TransferOption> = options
+            .into_iter()
+            .filter(|option| {
+                matches!(
+                    option.option,
+                    OptionType::BlockSize | OptionType::Timeout | OptionType::TransferSize
+                )
+            })
+            .collect();
+        // The size of an upload is the one announced by the client.
+        let size = options
+            .iter()
+            .find(|option| option.option == OptionType::TransferSize)
+            .map(|option| option.value);
+        let limits = OptionLimits {
+            tsize: TsizeMode::Echo,
+            ..self.option_limits.clone()
+        };
+        let negotiated = match parse_options(&mut options, size, self.retransmit_timeout, &limits) {
+            Ok(negotiated) => negotiated,
+            Err(err) => {
+                logln!("{to}: Refused upload of {filename}: {err}");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::OptionNegotiation,
+                    "option negotiation failed",
+                );
+            }
+        };
+
+        if let Some(max) = self
+            .max_upload_size
+            .filter(|&max| size.is_some_and(|size| size > max))
+        {
+            logln!("{to}: Refused upload of {filename}: larger than {max} bytes");
+            return Message::send_error(&*self.socket, to, ErrorCode::DiskFull, "file too large");
+        }
+
+        let answer = if options.is_empty() {
+            Packet::Ack(0)
+        } else {
+            Packet::Oack(options)
+        };
+        let directory = &self.directory;
+        let created = Upload::create(
+            &path,
+            |temporary| match &self.beneath {
+                Some(beneath) => {
+                    beneath.create_new(temporary.strip_prefix(directory).unwrap_or(temporary))
+                }
+                None => upload::create_new(temporary),
+            },
+            self.generation + 1,
+            negotiated.blk_size,
+            negotiated.timeout,
+            answer.clone(),
+            self.clock.now(),
+        );
+        let mut upload = match created {
+            Ok(upload) => upload,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                logln!("{to}: Refused upload of {filename}: already being uploaded");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::NotDefined,
+                    "file being uploaded, retry later",
+                );
+            }
+            Err(err) => {
+                elogln!("{to}: Cannot create {filename}: {err}");
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::AccessViolation,
+                    "cannot create file",
+                );
+            }
+        };
+        upload.max_size = size.into_iter().chain(self.max_upload_size).min();
+        upload.overwrite = self.overwrite;
+        upload.rollover = self.rollover;
+        logln!("{to}: Receiving {filename}");
+        self.generation = upload.generation;
+        self.upload_timers
+            .schedule(upload.answered_at + upload.timeout, *to, upload.generation);
+        self.uploads.insert(*to, upload);
+        self.clients.add(*to);
+        Message::send_packet(&*self.socket, to, &answer)
+    }
+
+    /// Forgets the upload of `to`.
+    fn end_upload(&mut self, to: &SocketAddr) -> Option<Upload> {
+        let upload = self.uploads.remove(to)?;
+        self.unregister(to);
+        Some(upload)
+    }
+
+    /// Writes a DATA packet of the upload of `from` and acknowledges it.
+    fn handle_upload_data(
+        &mut self,
+        block_num: u16,
+        data: &[u8],
+        from: &SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let upload = self.uploads.get_mut(from).ok_or("missing upload")?;
+        if data.len() > upload.blk_size {
+            let blk_size = upload.blk_size;
+            self.end_upload(from);
+            elogln!(
+                "{from}: Received {} bytes in a block of {blk_size}",
+                data.len()
+            );
+            return Message::send_error(
+                &*self.socket,
+                from,
+                ErrorCode::IllegalOperation,
+                "block larger than blksize",
+            );
+        }
+
+        let (beneath, directory) = (&self.beneath, &self.directory);
+        let received = upload.receive(
+            block_num,
+            data,
+            |temporary, path, overwrite| match beneath {
+                Some(beneath) => beneath.place(
+                    temporary.strip_prefix(directory).unwrap_or(temporary),
+                    path.strip_prefix(directory).unwrap_or(path),
+                    overwrite,
+                ),
+                None => upload::place(temporary, path, overwrite),
+            },
+        );
+        match received {
+            Ok(Receipt::Written { last }) => {
+                upload.answer = Packet::Ack(block_num);
+                upload.answered_at = now;
+                upload.retries = 0;
+                if last {
+                    logln!(
+                        "{from}: Received file {} ({} bytes)",
+                        upload.path().display(),
+                        upload.bytes
+                    );
+                }
+                Message::send_ack(&*self.socket, from, block_num)
+            }
+            Ok(Receipt::Duplicate) => Message::send_ack(&*self.socket, from, upload.block()),
+            Ok(Receipt::Ignored) => Ok(()),
+            Err(err) => {
+                elogln!(
+                    "{from}: Error while writing {}: {err}",
+                    upload.path().display()
+                );
+                self.end_upload(from);
+                let (code, message) = match err.kind() {
+                    io::ErrorKind::StorageFull => (ErrorCode::DiskFull, "disk full"),
+                    io::ErrorKind::AlreadyExists => (ErrorCode::FileExists, "file already exists"),
+                    _ if self.beneath.is_some() && beneath::is_escape(&err) => {
+                        (ErrorCode::AccessViolation, "file access violation")
+                    }
+                    _ => (ErrorCode::NotDefined, "write error"),
+                };
+                Message::send_error(&*self.socket, from, code, message)
+            }
+        }
+    }
+
+    /// Sends the last answer of the uploads whose client went silent again,
+    /// up to `--max-retries` times. A finished upload is kept for one more
+    /// timeout, to acknowledge its last block again if the ACK was lost.
+    /// The timer is not moved by every DATA packet, it is scheduled again
+    /// from the last answer when it fires early.
+    fn handle_upload_timeouts(&mut self) {
+        let now = self.clock.now();
+        for (to, generation) in self.upload_timers.take_due(now) {
+            let Some(upload) = self
+                .uploads
+                .get_mut(&to)
+                .filter(|upload| upload.generation == generation)
+            else {
+                continue;
+            };
+            let due_at = upload.answered_at + upload.timeout;
+            if due_at > now {
+                self.upload_timers.schedule(due_at, to, generation);
+                continue;
+            }
+            let sent = if upload.finished() {
+                self.end_upload(&to);
+                Ok(())
+            } else if upload.retries >= self.max_retries {
+                elogln!(
+                    "{to}: Upload of {} timed out after {} retries",
+                    upload.path().display(),
+                    self.max_retries
+                );
+                self.end_upload(&to);
+                Message::send_error(
+                    &*self.socket,
+                    &to,
+                    ErrorCode::NotDefined,
+                    "transfer timed out",
+                )
+            } else {
+                upload.retries += 1;
+                upload.answered_at = now;
+                self.upload_timers
+                    .schedule(now + upload.timeout, to, generation);
+                Message::send_packet(&*self.socket, &to, &upload.answer)
+            };
+            if let Err(err) = sent {
+                elogln!("{to}: Error while answering upload: {err}");
+            }
+        }
+    }
+
+    /// Returns the path of a served file relative to the served directory,
+    /// as used in the statistics.
+    fn stats_key(&self, reader: &Path) -> String {
+        match reader.strip_prefix(&self.canonical_directory) {
+            Ok(relative) => relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => reader.display().to_string(),
+        }
+    }
+
+    /// Queues or rejects a read request for a file at its reader limit,
+    /// depending on the configured [`BusyStrategy`].
+    fn defer_rrq(
+        &mut self,
+        filename: String,
+        mode: Mode,
+        options: Vec<TransferOption>,
+        reader: PathBuf,
+        to: &SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let timeout = options
+            .iter()
+            .find(|option| option.option == OptionType::Timeout && option.value > 0)
+            .map_or(DEFAULT_TIMEOUT, |option| Duration::from_secs(option.value));
+        let request = PendingRequest {
+            client: *to,
+            filename,
+            mode,
+            options,
+            reader,
+            queued_at: now,
+            last_seen: now,
+            patience: timeout,
+        };
+
+        let readers = self.readers.as_mut().unwrap();
+        if self.when_busy == BusyStrategy::Queue && readers.enqueue(request) {
+            logln!("{to}: File busy, queued request");
+            Metrics::set(&self.metrics.queue_length, readers.pending_len() as u64);
+            return Ok(());
+        }
+
+        logln!("{to}: File busy, rejected request");
+        Metrics::inc(&self.metrics.busy_rejections);
+        Message::send_error(
+            &*self.socket,
+            to,
+            ErrorCode::NotDefined,
+            "server busy, retry later",
+        )
+    }
+
+    /// Drops the queued requests the clients stopped waiting for, then
+    /// starts the ones whose file has a free reader slot.
+    fn serve_pending(&mut self) {
+        let Some(readers) = self.readers.as_mut() else {
+            return;
+        };
+        let now = self.clock.now();
+
+        for request in readers.expire(now) {
+            let to = request.client;
+            logln!("{to}: Gave up waiting for {}", request.filename);
+            Metrics::inc(&self.metrics.busy_rejections);
+            if let Err(err) = Message::send_error(
+                &*self.socket,
+                &to,
+                ErrorCode::NotDefined,
+                "server busy, retry later",
+            ) {
+                elogln!("{to}: Error while sending error: {err}");
+            }
+        }
+
+        while let Some(request) = self.readers.as_mut().and_then(ReaderLimit::next_ready) {
+            let to = request.client;
+            let waited = now.duration_since(request.queued_at);
+            logln!(
+                "{to}: Serving queued request after {}ms",
+                waited.as_millis()
+            );
+            Metrics::inc(&self.metrics.queue_served);
+            Metrics::add(&self.metrics.queue_wait_micros, waited.as_micros() as u64);
+            if let Err(err) = self.handle_rrq(request.filename, request.mode, request.options, &to)
+            {
+                elogln!("{to}: Error while sending file: {err}")
+            }
+        }
+
+        if let Some(readers) = &self.readers {
+            Metrics::set(&self.metrics.queue_length, readers.pending_len() as u64);
+        }
+    }
+
+    /// Refuses a read request for `name`, which is not a regular file.
+    fn refuse_irregular(&self, to: &SocketAddr, name: &str) -> Result<(), Box<dyn Error>> {
+        logln!("{to}: Refused {name}: not a regular file");
+        Message::send_error(
+            &*self.socket,
+            to,
+            ErrorCode::AccessViolation,
+            "not a regular file",
+        )
+    }
+
+    /// Registers the session of a read request and sends the OACK, or the
+    /// first window when no options were requested. The transfer size
+    /// option is left out when the `size` is unknown, which it is in
+    /// netascii mode.
+    #[allow(clippy::too_many_arguments)]
+    fn start_transfer(
+        &mut self,
+        to: &SocketAddr,
+        file_path: &Path,
+        source: Box<dyn BlockSource>,
+        size: Option<u64>,
+        mut options: Vec<TransferOption>,
+        reader: Option<PathBuf>,
+        quirks: QuirkSet,
+    ) -> Result<(), Box<dyn Error>> {
+        let requested = options.clone();
+        if quirks.no_oack {
+            options.clear();
+        }
+        let (limits, shrunk) = self.request_limits(to, &requested, &quirks);
+        if shrunk {
+            logln!("{to}: Clamping blksize to {DEFAULT_BLOCK_SIZE} after large blocks were lost");
+            Metrics::inc(&self.metrics.blksize_shrinks);
+        }
+        let state_options = parse_options(&mut options, size, self.retransmit_timeout, &limits)?;
+        if !requested.is_empty() {
+            logln!("{to}: Requested options {requested:?}, negotiated {options:?}");
+        }
+        if !state_options.duplicates.is_empty() {
+            elogln!(
+                "{to}: Ignored duplicate options {:?}",
+                state_options.duplicates
+            );
+            Metrics::add(
+                &self.metrics.duplicate_options,
+                state_options.duplicates.len() as u64,
+            );
+        }
+        if let Some(size) = size.filter(|_| self.no_rollover) {
+            let blocks = block_count(size, state_options.blk_size);
+            if blocks > u16::MAX as u64 {
+                elogln!(
+                    "{to}: {} needs {blocks} blocks of {} bytes, more than the block numbers without rollover",
+                    file_path.display(),
+                    state_options.blk_size
+                );
+                return Message::send_error(
+                    &*self.socket,
+                    to,
+                    ErrorCode::NotDefined,
+                    "file too large for the block size",
+                );
+            }
+        }
+        let negotiated = negotiation::negotiated(&requested, &options);
+        for option in &negotiated {
+            self.metrics.record_outcome(option.option, option.outcome());
+        }
+        let notes = NegotiationNote::notes(&negotiated);
+        if !notes.is_empty() {
+            let codes: Vec<_> = notes.iter().map(NegotiationNote::as_str).collect();
+            logln!("{to}: Negotiation notes {}", codes.join(","));
+            // Notes only explain requested options, clients that asked for
+            // none still get no OACK.
+            if self.negotiation_report {
+                options.push(TransferOption {
+                    option: OptionType::ServerNote,
+                    value: NegotiationNote::mask(&notes),
+                });
+            }
+        }
+        if !self.preserve_option_order {
+            negotiation::sort_oack(&mut options);
+        }
+        let now = self.clock.now();
+        let oack = if options.is_empty() {
+            None
+        } else {
+            Some(options)
+        };
+        let (journey, ended) =
+            self.clients
+                .join_journey(to.ip(), file_path, now, self.journey_window);
+        if let Some(ended) = ended {
+            self.end_journey(ended);
+        }
+        let options = SessionOptions {
+            blk_size: state_options.blk_size,
+            windowsize: state_options.windowsize,
+            timeout: state_options.timeout,
+            max_retries: self.max_retries,
+            duplicate_acks: self.duplicate_data > 1,
+            checksum: state_options.checksum,
+            restart_acks: self.restart_acks,
+            max_in_flight_bytes: self
+                .max_in_flight_bytes
+                .or(self.option_limits.max_window_bytes),
+            rollover: state_options.rollover.unwrap_or(self.rollover),
+            reread: source.rereadable(),
+        };
+        let delay = quirks
+            .initial_delay
+            .unwrap_or_else(|| self.initial_delay(to.ip()));
+        if self.threaded {
+            let delegated = Delegated {
+                client: *to,
+                file: file_path.to_path_buf(),
+                reader,
+                negotiated,
+                size,
+                started: now,
+                journey,
+                quirks,
+                allocated: None,
+                storage_time: Duration::ZERO,
+                request: None,
+                charged: 0,
+                aborted: false,
+            };
+            let limits = Limits {
+                initial_delay: delay,
+                max_duration: self.max_transfer_duration,
+                idle_timeout: self.session_timeout,
+            };
+            return self.delegate(delegated, source, options, oack, limits);
+        }
+        let (session, mut actions) = Session::new(options, oack, now + delay);
+        let state = State {
+            source,
+            filepath: file_path.to_path_buf(),
+            reader,
+            options: state_options,
+            negotiated,
+            generation: self.generation + 1,
+            started: now,
+            request: None,
+            session,
+            size,
+            allocated: None,
+            progress: ProgressTracker::new(now),
+            unreachable_sends: 0,
+            storage_time: Duration::ZERO,
+            network_wait: Duration::ZERO,
+            awaiting_since: None,
+            last_activity: now,
+            deferred: (!delay.is_zero()).then(|| mem::take(&mut actions)),
+            journey,
+            quirks,
+        };
+
+        if let (Some(readers), Some(reader)) = (self.readers.as_mut(), state.reader.as_ref()) {
+            readers.acquire(reader);
+        }
+        self.generation = state.generation;
+        self.tombstones.remove(to);
+        self.retransmit_timers
+            .schedule(state.session.retransmit_at(), *to, state.generation);
+        self.progress_timers
+            .schedule(now + PROGRESS_INTERVAL, *to, state.generation);
+        if let Some(max) = self.max_transfer_duration {
+            self.deadline_timers
+                .schedule(now + max, *to, state.generation);
+        }
+        if let Some(timeout) = self.session_timeout {
+            self.idle_timers
+                .schedule(now + timeout, *to, state.generation);
+        }
+        self.clients.add(*to);
+        if let Some(replaced) = self.connmap.insert(*to, state) {
+            self.release_reader(&replaced);
+        }
+        Metrics::set(&self.metrics.active_sessions, self.connmap.len() as u64);
+        self.peaks
+            .record_sessions(&self.metrics, now, self.connmap.len());
+        self.emit(TransferEvent::Started {
+            client: *to,
+            file: file_path.to_path_buf(),
+            journey,
+        });
+
+        if !delay.is_zero() {
+            logln!(
+                "{to}: Delaying the first packet by {} ms",
+                delay.as_millis()
+            );
+            self.start_timers
+                .schedule(now + delay, *to, self.generation);
+            return Ok(());
+        }
+        self.start_session(to, actions)
+    }
+
+    /// Hands the transfer of `delegated` to a [`Worker`] thread with
+    /// `--threaded`, sending from a new ephemeral port connected to the
+    /// client. The worker retransmits on its own, and enforces the initial
+    /// delay, the session timeout and `--max-transfer-duration` of `limits`.
+    fn delegate(
+        &mut self,
+        delegated: Delegated,
+        source: Box<dyn BlockSource>,
+        options: SessionOptions,
+        oack: Option<Vec<TransferOption>>,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let to = delegated.client;
+        let local = SocketAddr::new(self.socket.local_addr()?.ip(), 0);
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(to)?;
+        let worker =
+            Worker::with_session(socket, to, source, delegated.size, options, oack, limits)?;
+        logln!(
+            "{to}: Sending {} from port {}",
+            delegated.file.display(),
+            worker.local_addr()?.port()
+        );
+        if !limits.initial_delay.is_zero() {
+            logln!(
+                "{to}: Delaying the first packet by {} ms",
+                limits.initial_delay.as_millis()
+            );
+        }
+
+        if let (Some(readers), Some(reader)) = (self.readers.as_mut(), delegated.reader.as_ref()) {
+            readers.acquire(reader);
+        }
+        self.clients.add(to);
+        self.emit(TransferEvent::Started {
+            client: to,
+            file: delegated.file.clone(),
+            journey: delegated.journey,
+        });
+        self.delegated.push((delegated, worker.start()));
+        Ok(())
+    }
+
+    /// Charges the transfers on [`Worker`] threads to `--quota`, then
+    /// records the end of the ones whose thread finished.
+    fn reap_delegated(&mut self) {
+        if let Some(quota) = self.quota {
+            self.charge_delegated(&quota);
+        }
+        if self
+            .delegated
+            .iter()
+            .all(|(_, handle)| !handle.is_finished())
+        {
+            return;
+        }
+        let now = self.clock.now();
+        let (finished, running) = mem::take(&mut self.delegated)
+            .into_iter()
+            .partition(|(_, handle)| handle.is_finished());
+        self.delegated = running;
+
+        for (delegated, mut handle) in finished {
+            let to = delegated.client;
+            let bytes = handle.progress().bytes_acked;
+            let result = handle.join();
+            if let (Some(readers), Some(reader)) =
+                (self.readers.as_mut(), delegated.reader.as_ref())
+            {
+                readers.release(reader);
+            }
+            if let Some(reader) = &delegated.reader {
+                let key = self.stats_key(reader);
+                self.file_stats.record_end(&key, bytes, result.is_ok());
+            }
+            if let Some(quota) = self.quota {
+                let acked = bytes.saturating_sub(delegated.charged);
+                self.clients.charge(to.ip(), acked, now, &quota);
+            }
+            self.unregister(&to);
+            self.clients
+                .end_journey_transfer(to.ip(), bytes, result.is_ok(), now);
+            let duration = now.saturating_duration_since(delegated.started);
+            match result {
+                Ok(summary) => {
+                    let storage_time = delegated.storage_time + summary.storage_time;
+                    log_sent(
+                        &to,
+                        &delegated.file,
+                        delegated.allocated,
+                        storage_time,
+                        summary.network_wait,
+                        delegated.journey,
+                        &delegated.quirks,
+                    );
+                    Metrics::inc(&self.metrics.completed);
+                    self.emit(TransferEvent::Completed {
+                        client: to,
+                        file: delegated.file,
+                        bytes: summary.bytes,
+                        duration,
+                        options: delegated.negotiated,
+                        size: delegated.size,
+                        allocated: delegated.allocated,
+                        storage_time,
+                        network_wait: summary.network_wait,
+                        journey: delegated.journey,
+                    });
+                }
+                Err(reason) => {
+                    elogln!(
+                        "{to}: Transfer of {} failed after {bytes} bytes (journey {}): {reason}",
+                        delegated.file.display(),
+                        delegated.journey
+                    );
+                    Metrics::inc(&self.metrics.failed);
+                    self.emit(TransferEvent::Failed {
+                        client: to,
+                        file: delegated.file,
+                        bytes,
+                        duration,
+                        options: delegated.negotiated,
+                        reason,
+                        journey: delegated.journey,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Charges the bytes acknowledged to the [`Worker`] threads since the
+    /// last tick to `quota`, aborting their transfers once the client used
+    /// it up with `--quota-hard`.
+    fn charge_delegated(&mut self, quota: &Quota) {
+        let now = self.clock.now();
+        for (delegated, handle) in &mut self.delegated {
+            let acked = handle.progress().bytes_acked;
+            let to = delegated.client;
+            let over_quota =
+                self.clients
+                    .charge(to.ip(), acked.saturating_sub(delegated.charged), now, quota);
+            delegated.charged = acked;
+            if over_quota.is_some() && self.quota_hard && !delegated.aborted {
+                logln!("{to}: Client used up its quota, transfer aborted");
+                Metrics::inc(&self.metrics.quota_aborts);
+                delegated.aborted = true;
+                handle.abort();
+            }
+        }
+    }
+
+    /// Returns the transfer of `to` running on a [`Worker`] thread, if any
+    /// and not aborted.
+    fn delegated_to(&self, to: &SocketAddr) -> Option<&Delegated> {
+        self.delegated
+            .iter()
+            .map(|(delegated, _)| delegated)
+            .find(|delegated| delegated.client == *to && !delegated.aborted)
+    }
+
+    /// Aborts the transfer of `to` running on a [`Worker`] thread, if any.
+    /// Its end is recorded once the thread noticed.
+    fn abort_delegated(&mut self, to: &SocketAddr) {
+        for (delegated, handle) in &mut self.delegated {
+            if delegated.client == *to && !delegated.aborted {
+                delegated.aborted = true;
+                handle.abort();
+            }
+        }
+    }
+
+    /// Unregisters `to` from its client host once no session, upload or
+    /// transfer on a [`Worker`] thread of it is left.
+    fn unregister(&mut self, to: &SocketAddr) {
+        if !self.connmap.contains_key(to)
+            && !self.uploads.contains_key(to)
+            && !self
+                .delegated
+                .iter()
+                .any(|(delegated, _)| delegated.client == *to)
+        {
+            self.clients.remove(to);
+        }
+    }
+
+    /// Sends the OACK, or reads and sends the first window, of the session
+    /// of `to`. A session whose first packet could not be sent is failed,
+    /// so that the request retried by the client starts afresh.
+    fn start_session(
+        &mut self,
+        to: &SocketAddr,
+        actions: Vec<SessionAction>,
+    ) -> Result<(), Box<dyn Error>> {
+        let result = self.run(to, |_, _| actions);
+        if let Err(err) = &result {
+            if self.connmap.contains_key(to) {
+                Metrics::inc(&self.metrics.start_failures);
+                self.fail_session(to, &format!("first packet not sent: {err}"));
+            }
+        }
+        result
+    }
+
+    /// Returns whether the file at `path` may appear in the listing or the
+    /// boot menu sent to `to`: it must be servable, not being uploaded, and
+    /// allowed by the manifest and the [`Authorizer`].
+    fn servable(&self, to: &SocketAddr, path: &Path) -> bool {
+        let Some(relative) = path
+            .strip_prefix(&self.directory)
+            .ok()
+            .and_then(Path::to_str)
+        else {
+            return false;
+        };
+        check_file_exists(
+            path,
+            &self.directory,
+            &self.canonical_directory,
+            self.follow_symlinks,
+        ) == ErrorCode::FileExists
+            && !receiving(&self.uploads, path)
+            && self
+                .manifest
+                .as_ref()
+                .is_none_or(|manifest| manifest.allows_path(path, &self.directory))
+            && self
+                .authorizer
+                .as_deref()
+                .is_none_or(|authorizer| authorizer.authorize(to, relative) == Decision::Allow)
+    }
+
+    /// Returns the `--initial-delay` of the client host `ip`, from the
+    /// `--initial-delay-for` block with the longest matching prefix if any.
+    fn initial_delay(&self, ip: IpAddr) -> Duration {
+        self.initial_delay_for
+            .iter()
+            .filter(|(cidr, _)| cidr.contains(ip))
+            .max_by_key(|(cidr, _)| cidr.prefix_len())
+            .map_or(self.initial_delay, |&(_, delay)| delay)
+    }
+
+    /// Sends the first packet of the sessions whose `--initial-delay`
+    /// elapsed.
+    fn handle_delayed_starts(&mut self) {
+        let now = self.clock.now();
+        for (to, generation) in self.start_timers.take_due(now) {
+            let Some(actions) = self
+                .connmap
+                .get_mut(&to)
+                .filter(|state| state.generation == generation)
+                .and_then(|state| state.deferred.take())
+            else {
+                continue;
+            };
+            if let Err(err) = self.start_session(&to, actions) {
+                elogln!("{to}: Error while starting transfer: {err}");
+            }
+        }
+    }
+
+    fn handle_ack(&mut self, ack_block_number: u16, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        if self.exceeded_duration(to) {
+            return self.abort_overdue(to);
+        }
+        let Some(state) = self.connmap.get(to) else {
+            return match self.tombstones.take(to, self.clock.now()) {
+                Some(message) => {
+                    logln!("{to}: Received ack {ack_block_number} after the transfer ended");
+                    Message::send_error(&*self.socket, to, ErrorCode::NotDefined, &message)
+                }
+                None => {
+                    Metrics::inc(&self.metrics.orphan_acks);
+                    self.record_anomaly(to, Anomaly::OrphanAck);
+                    logln!("{to}: Received ack {ack_block_number} without a transfer");
+                    Message::send_error(
+                        &*self.socket,
+                        to,
+                        ErrorCode::UnknownId,
+                        "unknown transfer ID",
+                    )
+                }
+            };
+        };
+        let windowsize = state.options.windowsize;
+        let diff = ack_block_number.wrapping_sub(state.session.block_number());
+        tracelog!("{to}: Received ack {ack_block_number} (diff {diff}) (ws={windowsize})");
+
+        let now = self.clock.now();
+        if let Some(state) = self.connmap.get_mut(to) {
+            state.last_activity = now;
+            if let Some(since) = state.awaiting_since.take() {
+                let wait = now.saturating_duration_since(since);
+                state.network_wait += wait;
+                self.metrics.record_network_wait(wait);
+            }
+        }
+
+        self.drive(
+            to,
+            SessionEvent::PacketReceived(Packet::Ack(ack_block_number)),
+        )
+    }
+
+    fn end_session(&mut self, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        let state = self.connmap.remove(to).ok_or("missing state")?;
+        Metrics::set(&self.metrics.active_sessions, self.connmap.len() as u64);
+        self.unregister(to);
+        self.clients.end_journey_transfer(
+            to.ip(),
+            state.session.bytes_acked(),
+            true,
+            self.clock.now(),
+        );
+        self.release_reader(&state);
+        if let Some(reader) = &state.reader {
+            let key = self.stats_key(reader);
+            self.file_stats
+                .record_end(&key, state.session.bytes_acked(), true);
+        }
+        log_sent(
+            to,
+            &state.filepath,
+            state.allocated,
+            state.storage_time,
+            state.network_wait,
+            state.journey,
+            &state.quirks,
+        );
+        Metrics::inc(&self.metrics.completed);
+        self.emit(TransferEvent::Completed {
+            client: *to,
+            file: state.filepath,
+            bytes: state.session.bytes_acked(),
+            duration: state.progress.elapsed(self.clock.now()),
+            options: state.negotiated,
+            size: state.size,
+            allocated: state.allocated,
+            storage_time: state.storage_time,
+            network_wait: state.network_wait,
+            journey: state.journey,
+        });
+        Ok(())
+    }
+
+    /// Drops the session of the supplied client, if any, and records it as
+    /// a failed transfer.
+    fn fail_session(&mut self, to: &SocketAddr, reason: &str) {
+        if let Some(state) = self.connmap.remove(to) {
+            Metrics::set(&self.metrics.active_sessions, self.connmap.len() as u64);
+            self.unregister(to);
+            self.clients.end_journey_transfer(
+                to.ip(),
+                state.session.bytes_acked(),
+                false,
+                self.clock.now(),
+            );
+            self.release_reader(&state);
+            if let Some(reader) = &state.reader {
+                let key = self.stats_key(reader);
+                self.file_stats
+                    .record_end(&key, state.session.bytes_acked(), false);
+            }
+            elogln!(
+                "{to}: Transfer of {} failed after {} bytes (journey {}): {reason}",
+                state.filepath.display(),
+                state.session.bytes_acked(),
+                state.journey
+            );
+            Metrics::inc(&self.metrics.failed);
+            self.emit(TransferEvent::Failed {
+                client: *to,
+                file: state.filepath,
+                bytes: state.session.bytes_acked(),
+                duration: state.progress.elapsed(self.clock.now()),
+                options: state.negotiated,
+                reason: reason.to_string(),
+                journey: state.journey,
+            });
+        }
+    }
+
+    /// Prints the statistics on `SIGUSR1`, resets the peaks on `SIGUSR2` and
+    /// reloads the manifest and the bind host on `SIGHUP`.
+    #[cfg(feature = "cli")]
+    fn handle_signals(&mut self) {
+        if signal::take(Signal::User1) {
+            self.print_stats();
+        }
+        if signal::take(Signal::User2) {
+            logln!("Resetting peaks");
+            self.reset_peaks();
+        }
+        if signal::take(Signal::Hangup) {
+            if let Err(err) = self.reload_manifest() {
+                elogln!("Keeping previous manifest: {err}");
+            }
+            if let Err(err) = self.reload_bind_host() {
+                elogln!("Keeping previous address: {err}");
+            }
+        }
+    }
+
+    /// Sends the metrics to the statsd agent once per flush interval.
+    #[cfg(feature = "metrics")]
+    fn flush_statsd(&mut self) {
+        let now = self.clock.now();
+        let snapshot = self.metrics.snapshot();
+        let sessions = self.connmap.len();
+        if let Some(statsd) = self.statsd.as_mut().filter(|statsd| statsd.due(now)) {
+            statsd.flush(now, snapshot, sessions);
+        }
+    }
+
+    /// Prints the server counters, the most requested files and the most
+    /// requested missing files, on `SIGUSR1`.
+    #[cfg(feature = "cli")]
+    fn print_stats(&self) {
+        let metrics = self.metrics();
+        logln!(
+            "Stats: {} requests, {} completed, {} failed, {} bytes sent, {} retransmits, {} active",
+            metrics.requests,
+            metrics.completed,
+            metrics.failed,
+            metrics.bytes_sent,
+            metrics.retransmits,
+            self.connmap.len()
+        );
+        logln!(
+            "Peaks: {} sessions{}, {} requests/s{}, {} bytes/s{}",
+            metrics.peak_sessions,
+            format_peak_time(metrics.peak_sessions_at),
+            metrics.peak_request_rate,
+            format_peak_time(metrics.peak_request_rate_at),
+            metrics.peak_throughput,
+            format_peak_time(metrics.peak_throughput_at),
+        );
+        for client in self.clients.groups() {
+            let ports: Vec<String> = client
+                .sessions
+                .iter()
+                .map(|session| session.port().to_string())
+                .collect();
+            logln!(
+                "  {}: {} active from ports {}",
+                client.ip,
+                ports.len(),
+                ports.join(", ")
+            );
+        }
+        for ip in self.clients.sprayed() {
+            logln!("  {ip}: packets spread over several servers");
+        }
+        if let Some(quota) = &self.quota {
+            for usage in self.quota_usage() {
+                logln!(
+                    "  {}: {} of {} quota bytes used, window ends in {}s",
+                    usage.ip,
+                    usage.bytes,
+                    quota.bytes,
+                    usage.window_left.as_secs()
+                );
+            }
+        }
+        for (file, stats) in self.file_stats.sorted().iter().take(TOP_FILES) {
+            logln!(
+                "  {file}: {} requests, {} completed, {} bytes",
+                stats.requests,
+                stats.completed,
+                stats.bytes_served
+            );
+        }
+        for (file, missing) in self.missing.sorted().iter().take(TOP_FILES) {
+            logln!("  {file}: missing, {} requests", missing.requests);
+        }
+        for packet in self.debug_capture() {
+            logln!("  Captured {packet}");
+        }
+    }
+
+    /// Counts a request for the missing `filename`, logging only the first
+    /// one until [`Server::report_missing`] sums up the others.
+    fn record_missing(&mut self, filename: &str, to: &SocketAddr) {
+        Metrics::inc(&self.metrics.not_found);
+        if self.missing.record(filename, to.ip()) {
+            elogln!("{to}: Requested missing file {filename}");
+        } else {
+            Metrics::inc(&self.metrics.suppressed_not_found);
+        }
+        Metrics::set(&self.metrics.missing_files, self.missing.len() as u64);
+    }
+
+    /// Logs the roll-up of the requests for missing files that were not
+    /// logged, once per roll-up period.
+    fn report_missing(&mut self) {
+        for line in self.missing.roll_up(self.clock.now()) {
+            elogln!("{line}");
+        }
+    }
+
+    /// Ends the journeys of the client hosts that made no transfer for the
+    /// `--journey-window`.
+    fn report_journeys(&mut self) {
+        let ended = self
+            .clients
+            .end_quiet_journeys(self.clock.now(), self.journey_window);
+        for journey in ended {
+            self.end_journey(journey);
+        }
+    }
 
-        let server = Server {
-            socket,
-            directory: config.directory.clone(),
-            connmap: HashMap::new(),
+    /// Closes the listeners left draining by a rebind once no session uses
+    /// them.
+    fn close_drained_listeners(&self) {
+        let Some(listeners) = &self.listeners else {
+            return;
         };
+        for closed in listeners.close_drained(|client| self.connmap.contains_key(client)) {
+            logln!("Closed listener {closed}, its sessions ended");
+        }
+    }
 
-        Ok(server)
+    /// Logs the summary of `journey` and emits it.
+    fn end_journey(&self, journey: Journey) {
+        let files: Vec<_> = journey
+            .files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        logln!(
+            "{}: Journey {} ended: {} transfers ({} completed, {} failed), {} bytes in {:.1}s: {}",
+            journey.client,
+            journey.id,
+            journey.transfers,
+            journey.completed,
+            journey.failed,
+            journey.bytes,
+            journey.duration.as_secs_f64(),
+            files.join(", ")
+        );
+        self.emit(TransferEvent::JourneyEnded { journey });
     }
 
-    /// Starts listening for connections. Note that this function does not finish running until termination.
-    pub fn listen(&mut self) {
-        loop {
-            if let Ok((packet, from)) = Message::recv_from(&self.socket) {
-                match packet {
-                    Packet::Rrq {
-                        filename,
-                        mode: _,
-                        options,
-                    } => {
-                        if let Err(err) = self.handle_rrq(filename, options, &from) {
-                            eprintln!("{from}: Error while sending file: {err}")
-                        }
-                    }
-                    Packet::Ack(block) => {
-                        if let Err(err) = self.handle_ack(block, &from) {
-                            eprintln!("{from}: Error while handling ack: {err}")
-                        }
-                    }
-                    Packet::Error { code, msg } => {
-                        println!("{from}: Received ERROR {code}: {msg}");
-                    }
-                    _ => {
-                        eprintln!("{from}: Received invalid packet {packet}");
-                        if let Err(err) = Message::send_error(
-                            &self.socket,
-                            &from,
-                            ErrorCode::IllegalOperation,
-                            "invalid request",
-                        ) {
-                            eprintln!("{from}: Error while sending error: {err}")
-                        }
-                    }
-                };
-            }
+    /// Counts an `anomaly` of `from`, warning once its client host looks
+    /// sprayed across servers. Packets are handled as before.
+    fn record_anomaly(&mut self, from: &SocketAddr, anomaly: Anomaly) {
+        if let Some(pattern) = self
+            .clients
+            .record_anomaly(*from, anomaly, self.clock.now())
+        {
+            elogln!(
+                "{}: WARNING: {pattern}, a NAT or load balancer probably spreads its packets over several servers",
+                from.ip()
+            );
+            Metrics::inc(&self.metrics.sprayed_clients);
         }
     }
 
-    fn handle_rrq(
-        &mut self,
-        filename: String,
-        mut options: Vec<TransferOption>,
-        to: &SocketAddr,
-    ) -> Result<(), Box<dyn Error>> {
-        let file_path = &self.directory.join(filename);
-        match check_file_exists(file_path, &self.directory) {
-            ErrorCode::FileNotFound => {
-                return Message::send_error(
-                    &self.socket,
-                    to,
-                    ErrorCode::FileNotFound,
-                    "file does not exist",
-                );
+    /// Frees the reader slot held by a session that was removed.
+    fn release_reader(&mut self, state: &State) {
+        if let (Some(readers), Some(reader)) = (self.readers.as_mut(), state.reader.as_ref()) {
+            readers.release(reader);
+        }
+    }
+
+    fn emit(&self, event: TransferEvent) {
+        if let Some(hooks) = &self.hooks {
+            hooks.on_event(&event);
+        }
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event);
+        }
+    }
+
+    /// Audits the session bookkeeping every [`SELF_CHECK_TICKS`] ticks with
+    /// `--self-check` or in debug builds, logging every inconsistency with a
+    /// dump of the sessions. Unit tests panic instead.
+    fn run_self_check(&mut self) {
+        if !self.self_check {
+            return;
+        }
+        self.ticks_since_check += 1;
+        if self.ticks_since_check < SELF_CHECK_TICKS {
+            return;
+        }
+        self.ticks_since_check = 0;
+        let violations = self.audit();
+        if violations.is_empty() {
+            return;
+        }
+
+        Metrics::inc(&self.metrics.self_check_failures);
+        elogln!("Self-check failed, the session bookkeeping is out of step:");
+        for violation in &violations {
+            elogln!("  {violation}");
+        }
+        for (client, state) in &self.connmap {
+            elogln!(
+                "  session {client}: generation {}, {}, block {}, retransmit at {:?}",
+                state.generation,
+                state.filepath.display(),
+                state.session.block_number(),
+                state.session.retransmit_at()
+            );
+        }
+        if cfg!(test) {
+            panic!("self-check failed: {violations:?}");
+        }
+    }
+
+    /// Returns how the timers, the client registry, the tombstones, the
+    /// reader counts and the gauges disagree with the sessions.
+    ///
+    /// Timers are cancelled lazily: the entries of ended sessions stay until
+    /// they are due and are skipped then, so only entries from generations
+    /// never issued, or newer than the session of their client, are drift.
+    /// A live session needs exactly its retransmit entry, which would
+    /// otherwise retransmit early or never.
+    fn audit(&self) -> Vec<String> {
+        let mut violations = vec![];
+        let sessions = self.connmap.len();
+        let metrics = self.metrics.snapshot();
+
+        let active = metrics.active_sessions;
+        if active != sessions as u64 {
+            violations.push(format!(
+                "active_sessions gauge is {active} with {sessions} sessions"
+            ));
+        }
+
+        let mut registered = 0;
+        for group in self.clients.groups() {
+            if group.sessions.is_empty() {
+                violations.push(format!(
+                    "client {} is registered without sessions",
+                    group.ip
+                ));
             }
-            ErrorCode::AccessViolation => {
-                return Message::send_error(
-                    &self.socket,
-                    to,
-                    ErrorCode::AccessViolation,
-                    "file access violation",
-                );
+            for session in group.sessions {
+                registered += 1;
+                if session.ip() != group.ip {
+                    violations.push(format!("{session} is registered under {}", group.ip));
+                }
+                if !self.connmap.contains_key(&session)
+                    && !self.uploads.contains_key(&session)
+                    && !self.delegated.iter().any(|(d, _)| d.client == session)
+                {
+                    violations.push(format!("{session} is registered without a session"));
+                }
             }
-            ErrorCode::FileExists => {
-                () // OK for sending
+        }
+        // Uploads and transfers on worker threads are registered too,
+        // sharing the entry of a session on the same port.
+        let transfers = self
+            .connmap
+            .keys()
+            .chain(self.uploads.keys())
+            .chain(
+                self.delegated
+                    .iter()
+                    .map(|(delegated, _)| &delegated.client),
+            )
+            .collect::<HashSet<_>>()
+            .len();
+        if registered != transfers {
+            violations.push(format!(
+                "{registered} sessions are registered per client for {transfers} sessions"
+            ));
+        }
+        for client in self.connmap.keys() {
+            if !self.clients.sessions(client.ip()).contains(client) {
+                violations.push(format!("{client} is not registered"));
             }
-            _ => {
-                return Message::send_error(
-                    &self.socket,
-                    to,
-                    ErrorCode::NotDefined,
-                    "unexpected error",
-                );
+            if self.tombstones.contains(client) {
+                violations.push(format!("{client} has a tombstone while its transfer runs"));
             }
         }
 
-        let state_options = parse_options(&mut options, file_path.metadata()?.len() as usize)?;
-        let file = File::open(&file_path)?;
-        let state = State {
-            file,
-            filepath: file_path.to_path_buf(),
-            options: state_options,
-            block_number: if options.len() > 0 { 0 } else { 1 },
-            window: Window::new(),
-            finished: false,
-        };
-
-        self.connmap.insert(*to, state);
+        let timers = [
+            ("retransmit", &self.retransmit_timers, true),
+            ("progress", &self.progress_timers, true),
+            (
+                "deadline",
+                &self.deadline_timers,
+                self.max_transfer_duration.is_some(),
+            ),
+            ("idle", &self.idle_timers, self.session_timeout.is_some()),
+            ("start", &self.start_timers, false),
+        ];
+        for (name, timers, required) in timers {
+            let mut live = HashSet::new();
+            for (at, client, generation) in timers.entries() {
+                if generation > self.generation {
+                    violations.push(format!(
+                        "{name} timer of {client} has generation {generation}, never issued"
+                    ));
+                    continue;
+                }
+                let Some(state) = self.connmap.get(&client) else {
+                    continue;
+                };
+                if generation > state.generation {
+                    violations.push(format!(
+                        "{name} timer of {client} has generation {generation}, newer than its session"
+                    ));
+                } else if generation == state.generation {
+                    live.insert(client);
+                    if name == "retransmit" && at != state.session.retransmit_at() {
+                        violations.push(format!("{client} has a stray retransmit timer at {at:?}"));
+                    }
+                }
+            }
+            if required {
+                for client in self.connmap.keys() {
+                    if !live.contains(client) {
+                        violations.push(format!("{client} has no {name} timer"));
+                    }
+                }
+            }
+        }
 
-        if options.len() > 0 {
-            // Send OACK
-            if let Err(err) = Message::send_oack(&self.socket, to, options) {
-                eprintln!("{to}: Error while sending OACK: {err}");
+        if let Some(readers) = &self.readers {
+            let mut expected: HashMap<&Path, usize> = HashMap::new();
+            for reader in self
+                .connmap
+                .values()
+                .filter_map(|state| state.reader.as_ref())
+            {
+                *expected.entry(reader).or_default() += 1;
             }
-        } else {
-            // Send first Data
-            if let Err(err) = self.process_send(to) {
-                eprintln!("{to}: Error while sending first data: {err}");
+            for (reader, &count) in readers.counts() {
+                let sessions = expected.remove(reader.as_path()).unwrap_or(0);
+                if count != sessions {
+                    violations.push(format!(
+                        "{} is counted with {count} readers for {sessions} sessions",
+                        reader.display()
+                    ));
+                }
+            }
+            for (reader, sessions) in expected {
+                violations.push(format!(
+                    "{} is not counted for {sessions} sessions",
+                    reader.display()
+                ));
+            }
+            let queued = metrics.queue_length;
+            if queued != readers.pending_len() as u64 {
+                violations.push(format!(
+                    "queue_length gauge is {queued} with {} queued requests",
+                    readers.pending_len()
+                ));
             }
         }
 
-        return Ok(());
+        violations
     }
 
-    fn fill_window(
-        window: &mut Window,
-        options: &StateOptions,
-        mut file: &File,
-    ) -> Result<bool, Box<dyn Error>> {
-        let current = window.len() as u16;
-        let windowsize = options.windowsize;
-        let blk_size = options.blk_size;
+    /// Logs and emits the progress of the transfers whose last report is
+    /// older than a second, up to a bounded number per tick.
+    fn report_progress(&mut self) {
+        let now = self.clock.now();
+        let mut events = vec![];
+        for (client, generation) in self.progress_timers.take_due(now) {
+            let Some(state) = self
+                .connmap
+                .get_mut(&client)
+                .filter(|state| state.generation == generation)
+            else {
+                continue;
+            };
+            if state.progress.due(now) {
+                let progress = state.progress.sample(
+                    now,
+                    state.session.bytes_acked(),
+                    state.size,
+                    state.allocated,
+                    state.session.retransmits(),
+                );
+                logln!("{client}: {}", format_progress(&progress));
+                events.push(TransferEvent::Progress {
+                    client,
+                    file: state.filepath.clone(),
+                    progress,
+                });
+            }
+            self.progress_timers
+                .schedule(state.progress.next_due(), client, generation);
+        }
 
-        // If e.g. window has 3 chunks and windowsize is 4, we need to fill 1 more chunk
-        // Return false if
-        let to_fill = windowsize - current;
-        if to_fill == 0 {
-            return Ok(false);
+        for event in events {
+            self.emit(event);
         }
+    }
 
-        let mut unfilled = false;
-        for _ in 0..to_fill {
-            let mut buf = vec![0; blk_size];
-            let read = file.read(&mut buf)?;
-            if read == 0 || read < blk_size {
-                unfilled = true;
-            }
-            if read == 0 {
-                break;
+    /// Retransmits or fails the transfers whose deadline passed, up to a
+    /// bounded number per tick.
+    fn handle_timeouts(&mut self) {
+        let now = self.clock.now();
+        for (to, generation) in self.retransmit_timers.take_due(now) {
+            if self
+                .connmap
+                .get(&to)
+                .filter(|state| state.generation == generation)
+                .is_none()
+            {
+                continue;
             }
-            if read < blk_size {
-                buf.truncate(read);
+            if let Err(err) = self.drive(&to, SessionEvent::Tick) {
+                elogln!("{to}: Error while retransmitting: {err}");
             }
-            window.push(buf);
         }
+    }
 
-        return Ok(unfilled);
+    /// Aborts the transfers that reached `--max-transfer-duration`.
+    fn handle_deadlines(&mut self) {
+        let now = self.clock.now();
+        for (to, generation) in self.deadline_timers.take_due(now) {
+            if self
+                .connmap
+                .get(&to)
+                .is_some_and(|state| state.generation == generation)
+            {
+                if let Err(err) = self.abort_overdue(&to) {
+                    elogln!("{to}: Error while sending error: {err}");
+                }
+            }
+        }
     }
 
-    fn handle_ack(&mut self, ack_block_number: u16, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
-        let state = self.connmap.get_mut(to).ok_or("missing state")?;
-        let windowsize = state.options.windowsize;
-        let diff = ack_block_number.wrapping_sub(state.block_number);
-        println!("{to}: Received ack {ack_block_number} (diff {diff}) (ws={windowsize})");
-        if diff <= windowsize {
-            state.block_number = ack_block_number.wrapping_add(1);
-            // If diff is 3, then pop 3 elements from state.window
-            for _ in 0..(diff + 1) {
-                state.window.pop();
+    /// Aborts the transfers whose client sent nothing for
+    /// `--session-timeout`. The timer is not moved by every ACK, it is
+    /// scheduled again from the last one when it fires early.
+    fn handle_idle_sessions(&mut self) {
+        let Some(timeout) = self.session_timeout else {
+            return;
+        };
+        let now = self.clock.now();
+        for (to, generation) in self.idle_timers.take_due(now) {
+            let Some(state) = self
+                .connmap
+                .get(&to)
+                .filter(|state| state.generation == generation)
+            else {
+                continue;
+            };
+            let idle_until = state.last_activity + timeout;
+            if idle_until > now {
+                self.idle_timers.schedule(idle_until, to, generation);
+                continue;
+            }
+            Metrics::inc(&self.metrics.idle_sessions);
+            let reason = format!("client idle for {}s", timeout.as_secs());
+            if let Err(err) = self.terminate(&to, "transfer timed out", &reason) {
+                elogln!("{to}: Error while sending error: {err}");
             }
         }
+    }
 
-        if state.finished {
-            return self.end_session(to);
+    /// Returns whether the transfer of `to` lasted longer than
+    /// `--max-transfer-duration`.
+    fn exceeded_duration(&self, to: &SocketAddr) -> bool {
+        match (self.max_transfer_duration, self.connmap.get(to)) {
+            (Some(max), Some(state)) => self.clock.now().duration_since(state.started) >= max,
+            _ => false,
         }
+    }
 
-        return self.process_send(to);
+    fn abort_overdue(&mut self, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        let max = self.max_transfer_duration.unwrap_or_default();
+        self.terminate(
+            to,
+            "transfer exceeded maximum duration",
+            &format!("exceeded the maximum duration of {}s", max.as_secs()),
+        )
     }
 
-    fn end_session(&mut self, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+    /// Terminates a transfer that timed out. When not even the first window
+    /// was acknowledged and its blocks are larger than 512 bytes, the path
+    /// likely drops large datagrams: the client is told to retry with
+    /// smaller blocks, and its host is remembered with
+    /// `--auto-shrink-blksize`. A transfer without any acknowledged data
+    /// also counts as a stalled session of its client host.
+    fn abort_timed_out(&mut self, to: &SocketAddr, reason: &str) -> Result<(), Box<dyn Error>> {
         let state = self.connmap.get(to).ok_or("missing state")?;
-        let filepath: &String = &state.filepath.display().to_string();
-        println!("{to}: Sent file {filepath}");
-        self.connmap.remove(to);
-        return Ok(());
+        let blk_size = state.options.blk_size;
+        let awaiting_first_window = state.session.awaiting_first_window();
+        if state.session.bytes_acked() == 0 {
+            self.record_anomaly(to, Anomaly::StalledSession);
+        }
+        if !awaiting_first_window || blk_size <= DEFAULT_BLOCK_SIZE {
+            return self.terminate(to, "transfer timed out", reason);
+        }
+
+        elogln!("{to}: WARNING: no block of {blk_size} bytes was acknowledged, large datagrams are probably dropped on the path");
+        Metrics::inc(&self.metrics.blksize_blackholes);
+        if self.auto_shrink_blksize {
+            self.blackholes.remember(to.ip(), self.clock.now());
+        }
+        self.terminate(
+            to,
+            &format!("transfer timed out, blksize {blk_size} may exceed the path MTU, retry with blksize {DEFAULT_BLOCK_SIZE}"),
+            &format!("{reason} without an ACK for blksize {blk_size}, suspected MTU blackhole"),
+        )
+    }
+
+    /// Resumes the transfer of a client that went back to acknowledging
+    /// `block` with `--allow-mid-session-restart`, reading the file again
+    /// after it. Otherwise, or when the source cannot be read again, the
+    /// transfer is aborted so that the client sends a new read request.
+    fn client_restarted(&mut self, to: &SocketAddr, block: u16) -> Result<(), Box<dyn Error>> {
+        Metrics::inc(&self.metrics.client_restarts);
+        let state = self.connmap.get_mut(to).ok_or("missing state")?;
+        let reason = format!("client restarted at block {block}");
+        if !self.allow_mid_session_restart && !state.quirks.allow_restart {
+            return self.terminate(to, "client appears to have restarted", &reason);
+        }
+        if !state.source.rereadable() {
+            let reason = format!(
+                "{reason}, {} cannot be read again",
+                state.filepath.display()
+            );
+            return self.terminate(to, "client appears to have restarted", &reason);
+        }
+
+        logln!("{to}: Client restarted, resuming after block {block}");
+        let actions = state.session.rewind(self.clock.now(), block);
+        self.run(to, |_, _| actions)
+    }
+
+    /// Fails the transfer of a client which could not be reached several
+    /// times in a row, for example because it was powered off, instead of
+    /// retransmitting to it until the timeout. Other send errors are
+    /// returned.
+    fn send_failed(&mut self, to: &SocketAddr, err: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+        if !is_unreachable(&*err) {
+            return Err(err);
+        }
+        let state = self.connmap.get_mut(to).ok_or("missing state")?;
+        state.unreachable_sends += 1;
+        if state.unreachable_sends < MAX_UNREACHABLE_SENDS {
+            return Err(err);
+        }
+
+        Metrics::inc(&self.metrics.unreachable_clients);
+        self.fail_session(to, &format!("client unreachable: {err}"));
+        Ok(())
     }
 
-    fn process_send(&mut self, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
-        let state = self.connmap.get_mut(to).unwrap();
-        state.finished = Self::fill_window(&mut state.window, &state.options, &state.file)?;
-        Self::send_window(&self.socket, to, &state.window, state.block_number)
+    /// Aborts a transfer whose source failed to read, for example a corrupted
+    /// compressed file.
+    fn abort_read(&mut self, to: &SocketAddr, err: io::Error) -> Result<(), Box<dyn Error>> {
+        self.terminate(
+            to,
+            "error while reading file",
+            &format!("read error: {err}"),
+        )
     }
 
-    fn send_window(
-        socket: &UdpSocket,
+    /// Fails a transfer ended by the server and sends the ERROR `message`,
+    /// which is sent once more if the client keeps sending packets.
+    fn terminate(
+        &mut self,
         to: &SocketAddr,
-        window: &Window,
-        mut block_num: u16,
+        message: &str,
+        reason: &str,
     ) -> Result<(), Box<dyn Error>> {
-        for frame in window {
-            let size = frame.len();
-            println!("{to}: Sending block {block_num} with {size} bytes");
-            Message::send_data(socket, to, block_num, frame.to_vec())?;
-            block_num = block_num.wrapping_add(1);
+        self.fail_session(to, reason);
+        self.tombstones.bury(*to, message, self.clock.now());
+        Message::send_error(&*self.socket, to, ErrorCode::NotDefined, message)
+    }
+
+    /// Feeds `event` to the session of `to` and carries out its actions.
+    fn drive(&mut self, to: &SocketAddr, event: SessionEvent) -> Result<(), Box<dyn Error>> {
+        self.run(to, |session, now| session.handle(now, event))
+    }
+
+    /// Carries out the actions that `step` returns for the session of `to`
+    /// with the [`transfer`] driver, then moves its retransmission timer and
+    /// counts its retransmissions.
+    fn run<F>(&mut self, to: &SocketAddr, step: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce(&mut Session, Instant) -> Vec<SessionAction>,
+    {
+        let now = self.clock.now();
+        let state = self.connmap.get_mut(to).ok_or("missing state")?;
+        let retransmit_at = state.session.retransmit_at();
+        let retransmits = state.session.retransmits();
+        let bytes_acked = state.session.bytes_acked();
+        let actions = step(&mut state.session, now);
+
+        // Bytes acknowledged again after a rewind count again.
+        let acked = state.session.bytes_acked().saturating_sub(bytes_acked);
+        let over_quota = self
+            .quota
+            .and_then(|quota| self.clients.charge(to.ip(), acked, now, &quota));
+        if let Some(left) = over_quota.filter(|_| self.quota_hard) {
+            if !actions.contains(&SessionAction::Finished) {
+                Metrics::inc(&self.metrics.quota_aborts);
+                return self.terminate(to, &quota_message(left), "quota exceeded");
+            }
+        }
+
+        let mut transport = PortTransport {
+            socket: &*self.socket,
+            metrics: &self.metrics,
+            peaks: &mut self.peaks,
+            now,
+            to: *to,
+            copies: self.duplicate_data,
+            sent: false,
+        };
+        let mut source = TimedSource::new(&mut *state.source, &*self.clock, &self.metrics);
+        let outcome = transfer::execute(
+            &mut state.session,
+            &mut source,
+            &mut transport,
+            now,
+            actions,
+        );
+
+        state.storage_time += source.spent;
+        if let Some(threshold) = self.slow_storage_warn.filter(|&t| source.slowest > t) {
+            elogln!(
+                "{to}: WARNING: reading {} took {} ms, more than {} ms",
+                state.filepath.display(),
+                source.slowest.as_millis(),
+                threshold.as_millis()
+            );
         }
+        if transport.sent && state.awaiting_since.is_none() {
+            state.awaiting_since = Some(self.clock.now());
+        }
+
+        Metrics::add(
+            &self.metrics.retransmits,
+            state.session.retransmits() - retransmits,
+        );
+        if state.session.retransmit_at() != retransmit_at {
+            self.retransmit_timers
+                .cancel(retransmit_at, *to, state.generation);
+            self.retransmit_timers
+                .schedule(state.session.retransmit_at(), *to, state.generation);
+        }
+
+        if !matches!(outcome, Outcome::SendFailed(_)) {
+            state.unreachable_sends = 0;
+        }
+        match outcome {
+            Outcome::Pending => Ok(()),
+            Outcome::SendFailed(err) => self.send_failed(to, err),
+            Outcome::ReadFailed(err) => self.abort_read(to, err),
+            Outcome::Finished => self.end_session(to),
+            Outcome::Aborted(reason) => self.abort_timed_out(to, &reason),
+            Outcome::Restarted(block) => self.client_restarted(to, block),
+        }
+    }
+}
+
+/// Delegated `struct` is what the server keeps of a transfer running on a
+/// [`Worker`] thread, to report its end.
+struct Delegated {
+    client: SocketAddr,
+    file: PathBuf,
+    reader: Option<PathBuf>,
+    negotiated: Vec<NegotiatedOption>,
+    size: Option<u64>,
+    started: Instant,
+    journey: u64,
+    quirks: QuirkSet,
+    /// Size allocated on disk, when the served file is sparse
+    allocated: Option<u64>,
+    /// Time spent in filesystem calls before the transfer was handed over
+    storage_time: Duration,
+    /// Decoded filename and options of the request, to recognize it when
+    /// the client sends it again
+    request: Option<(String, Vec<TransferOption>)>,
+    /// Bytes acknowledged so far that were charged to `--quota`
+    charged: u64,
+    /// Whether the transfer was aborted and only its thread is left
+    aborted: bool,
+}
+
+/// Logs the completion of the transfer of `file` to `to`, with the time
+/// spent in filesystem calls and waiting for the client.
+fn log_sent(
+    to: &SocketAddr,
+    file: &Path,
+    allocated: Option<u64>,
+    storage_time: Duration,
+    network_wait: Duration,
+    journey: u64,
+    quirks: &QuirkSet,
+) {
+    let mut timing = format!(
+        "storage {} ms, network wait {} ms, journey {journey}",
+        storage_time.as_millis(),
+        network_wait.as_millis(),
+    );
+    if !quirks.is_empty() {
+        timing.push_str(&format!(", quirks {quirks}"));
+    }
+    match allocated {
+        Some(allocated) => logln!(
+            "{to}: Sent file {} ({allocated} bytes allocated, {timing})",
+            file.display()
+        ),
+        None => logln!("{to}: Sent file {} ({timing})", file.display()),
+    }
+}
+
+/// PortTransport `struct` sends the packets of a session from the single
+/// port of the server, with `--duplicate-data` copies of every DATA packet.
+struct PortTransport<'a> {
+    socket: &'a dyn Socket,
+    metrics: &'a Metrics,
+    peaks: &'a mut Peaks,
+    now: Instant,
+    to: SocketAddr,
+    copies: usize,
+    /// Whether a packet was sent
+    sent: bool,
+}
 
+impl Transport for PortTransport<'_> {
+    fn send(&mut self, packet: Packet) -> Result<(), Box<dyn Error>> {
+        let to = &self.to;
+        self.sent = true;
+        let Packet::Data { block_num, data } = &packet else {
+            return Message::send_packet(self.socket, to, &packet);
+        };
+
+        let size = data.len();
+        tracelog!("{to}: Sending block {block_num} with {size} bytes");
+        for copy in 0..self.copies {
+            Message::send_packet(self.socket, to, &packet)?;
+            Metrics::add(&self.metrics.bytes_sent, size as u64);
+            self.peaks.record_bytes(self.metrics, self.now, size as u64);
+            if copy > 0 {
+                Metrics::inc(&self.metrics.duplicate_data);
+            }
+        }
         Ok(())
     }
 }
 
-fn check_file_exists(file: &Path, directory: &PathBuf) -> ErrorCode {
+/// Returns the ERROR message refusing a client host over its quota, with
+/// the `left` time of its window in minutes.
+fn quota_message(left: Duration) -> String {
+    let minutes = left.as_secs().div_ceil(60).max(1);
+    format!("quota exceeded, retry after {minutes} minutes")
+}
+
+/// Returns the first address `host` resolves to.
+fn resolve_host(host: &str) -> Result<IpAddr, TftpError> {
+    let mut addresses = (host, 0)
+        .to_socket_addrs()
+        .map_err(|err| format!("Cannot resolve {host}: {err}"))?;
+    match addresses.next() {
+        Some(address) => Ok(address.ip()),
+        None => Err(format!("Cannot resolve {host}: no address").into()),
+    }
+}
+
+/// Returns the blocks of a `source` read in order, of `size` bytes if
+/// known, as sent in `mode` for `file_path`. The size of netascii is only
+/// known once the whole source is translated.
+fn translated(
+    to: &SocketAddr,
+    file_path: &Path,
+    source: Box<dyn Read + Send>,
+    size: Option<u64>,
+    mode: Mode,
+) -> (Box<dyn BlockSource>, Option<u64>) {
+    match mode {
+        Mode::Octet => (Box::new(Sequential::new(source)), size),
+        Mode::Netascii => {
+            logln!("{to}: Sending {} as netascii", file_path.display());
+            let translated = Box::new(NetasciiReader::new(source));
+            (Box::new(Sequential::new(translated)), None)
+        }
+    }
+}
+
+/// Returns whether a send failed because the client cannot be reached.
+fn is_unreachable(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>().is_some_and(|err| {
+        matches!(
+            err.kind(),
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable
+        )
+    })
+}
+
+/// Formats when a peak occurred as seconds since the epoch, for the
+/// statistics.
+#[cfg(feature = "cli")]
+fn format_peak_time(at: Option<SystemTime>) -> String {
+    at.and_then(|at| at.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|since| format!(" at {}", since.as_secs()))
+        .unwrap_or_default()
+}
+
+fn format_progress(progress: &TransferProgress) -> String {
+    let total = match (progress.tsize, progress.allocated) {
+        (Some(tsize), Some(allocated)) => {
+            format!("{}/{tsize} ({allocated} allocated)", progress.bytes_acked)
+        }
+        (Some(tsize), None) => format!("{}/{tsize}", progress.bytes_acked),
+        (None, _) => progress.bytes_acked.to_string(),
+    };
+    let eta = match progress.eta {
+        Some(eta) => format!("{}s", eta.as_secs()),
+        None => "unknown".to_string(),
+    };
+
+    format!(
+        "Sent {total} bytes ({:.1} KiB/s, average {:.1} KiB/s, {} retransmits, ETA {eta})",
+        progress.throughput / 1024.0,
+        progress.average_throughput / 1024.0,
+        progress.retransmits,
+    )
+}
+
+/// Returns whether the source `port` of a request is below 1024 and not
+/// one of the `allowed` ones. Spoofed requests from such ports aim the
+/// transfer at a well-known service of the victim, such as DNS or NTP.
+fn is_privileged_source(port: u16, allowed: &[u16]) -> bool {
+    port < 1024 && !allowed.contains(&port)
+}
+
+/// Returns whether `destination` is the limited broadcast address or a
+/// multicast address.
+fn is_broadcast(destination: IpAddr) -> bool {
+    match destination {
+        IpAddr::V4(ip) => ip.is_broadcast() || ip.is_multicast(),
+        IpAddr::V6(ip) => ip.is_multicast(),
+    }
+}
+
+/// Returns whether `file` exists and resolves inside `directory`, whose
+/// canonical path is `root`. It is `AccessViolation` when a symlink leads
+/// out of it, or when there is any symlink on the way without
+/// `follow_symlinks`. A missing file is resolved as far as it exists, so
+/// that a symlinked directory leading out is refused whether the file
+/// exists or not.
+fn check_file_exists(
+    file: &Path,
+    directory: &Path,
+    root: &Path,
+    follow_symlinks: bool,
+) -> ErrorCode {
     if !validate_file_path(file, directory) {
         return ErrorCode::AccessViolation;
     }
 
-    if !file.exists() {
+    let mut existing = file;
+    let resolved = loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(_) => match existing.parent() {
+                Some(parent) => existing = parent,
+                None => return ErrorCode::AccessViolation,
+            },
+        }
+    };
+    if !resolved.starts_with(root) {
+        return ErrorCode::AccessViolation;
+    }
+    if !follow_symlinks && traverses_symlink(file, directory) {
+        return ErrorCode::AccessViolation;
+    }
+    if existing != file {
         return ErrorCode::FileNotFound;
     }
 
     ErrorCode::FileExists
 }
 
-fn validate_file_path(file: &Path, directory: &PathBuf) -> bool {
-    !file.to_str().unwrap().contains("..") && file.ancestors().any(|a| a == directory)
+/// Returns whether one of the components of `file` below `directory` is a
+/// symlink.
+fn traverses_symlink(file: &Path, directory: &Path) -> bool {
+    let Ok(relative) = file.strip_prefix(directory) else {
+        return false;
+    };
+    let mut path = directory.to_path_buf();
+    relative.components().any(|component| {
+        path.push(component);
+        fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    })
+}
+
+/// Returns whether `path` is the temporary file of one of the `uploads` in
+/// progress, which is neither served nor listed.
+fn receiving(uploads: &HashMap<SocketAddr, Upload>, path: &Path) -> bool {
+    uploads.values().any(|upload| upload.temporary() == path)
+}
+
+/// Whether `filename` is empty, only whitespace or the bare root, which
+/// misconfigured clients send when their filename variable is unset.
+fn is_blank(filename: &str) -> bool {
+    filename
+        .trim_matches(|c: char| c.is_whitespace() || c == '/')
+        .is_empty()
+}
+
+/// Returns `filename` without its leading slashes, which clients mean
+/// relative to the served directory, if it has any and names more than
+/// the directory itself.
+fn root_relative(filename: &str) -> Option<&str> {
+    Some(filename.trim_start_matches('/'))
+        .filter(|relative| relative.len() < filename.len() && !relative.is_empty())
+}
+
+/// Turns a requested `filename` into a path relative to the served
+/// directory, taking both `/` and `\` as separators and decoding nothing.
+/// Absolute paths, UNC prefixes, `..` components and NUL bytes are refused
+/// with `AccessViolation`, so that joining the result onto the directory
+/// stays below it on every platform. On Windows any `:` is refused too,
+/// which covers drive letters and NTFS alternate data streams in every
+/// component; elsewhere it is an ordinary character, as in the per-MAC
+/// `00:11:22:33:44:55.ipxe` files of network bootloaders.
+fn sanitize_request_path(filename: &str) -> Result<PathBuf, ErrorCode> {
+    if filename.contains('\0')
+        || filename.starts_with(['/', '\\'])
+        || (cfg!(windows) && filename.contains(':'))
+    {
+        return Err(ErrorCode::AccessViolation);
+    }
+
+    let mut path = PathBuf::new();
+    for part in filename.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return Err(ErrorCode::AccessViolation),
+            part => path.push(part),
+        }
+    }
+    Ok(path)
+}
+
+/// Returns whether `file` is below `directory` as written, without any
+/// `..` component. Names merely containing two dots are fine.
+fn validate_file_path(file: &Path, directory: &Path) -> bool {
+    !file
+        .components()
+        .any(|component| component == Component::ParentDir)
+        && file.starts_with(directory)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Returns a server in the middle of sending a three-block file to the
+    /// returned client, with a reader limit and a transfer deadline.
+    fn transferring() -> (Server, SocketAddr, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("boot.img"), vec![7; 512 * 2 + 77]).unwrap();
+        let args = [
+            "/",
+            "-d",
+            dir.path().to_str().unwrap(),
+            "--max-readers-per-file",
+            "4",
+            "--max-transfer-duration",
+            "600",
+        ];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        let mut server =
+            Server::with_socket(&config, UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let rrq = Packet::Rrq {
+            filename: "boot.img".to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+        };
+        server.handle_packet(rrq, client);
+        assert_eq!(server.connmap.len(), 1);
+        (server, client, dir)
+    }
+
+    /// Asserts that the audit of `server` finds exactly `violation`.
+    fn assert_drift(server: &Server, violation: &str) {
+        assert_eq!(server.audit(), vec![violation.to_string()]);
+    }
+
+    #[test]
+    fn audits_consistent_bookkeeping() {
+        let (mut server, client, _dir) = transferring();
+        assert_eq!(server.audit(), Vec::<String>::new());
+
+        server.handle_packet(Packet::Ack(1), client);
+        server.handle_packet(Packet::Ack(2), client);
+        server.handle_packet(Packet::Ack(3), client);
+
+        assert!(server.connmap.is_empty());
+        assert_eq!(server.audit(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn audit_catches_registry_drift() {
+        let (mut server, client, _dir) = transferring();
+        server.clients.remove(&client);
+        assert_eq!(
+            server.audit(),
+            vec![
+                "0 sessions are registered per client for 1 sessions".to_string(),
+                format!("{client} is not registered"),
+            ]
+        );
+
+        let (mut server, client, _dir) = transferring();
+        let ghost = SocketAddr::new(client.ip(), client.port().wrapping_add(1));
+        server.clients.add(ghost);
+        assert_eq!(
+            server.audit(),
+            vec![
+                format!("{ghost} is registered without a session"),
+                "2 sessions are registered per client for 1 sessions".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn audit_catches_live_tombstone() {
+        let (mut server, client, _dir) = transferring();
+        server
+            .tombstones
+            .bury(client, "transfer timed out", server.clock.now());
+
+        assert_drift(
+            &server,
+            &format!("{client} has a tombstone while its transfer runs"),
+        );
+    }
+
+    #[test]
+    fn audit_catches_timer_drift() {
+        let (mut server, client, _dir) = transferring();
+        let generation = server.generation;
+        let retransmit_at = server.connmap[&client].session.retransmit_at();
+        server
+            .retransmit_timers
+            .cancel(retransmit_at, client, generation);
+        assert_drift(&server, &format!("{client} has no retransmit timer"));
+
+        let stray = server.clock.now();
+        server.retransmit_timers.schedule(stray, client, generation);
+        assert_drift(
+            &server,
+            &format!("{client} has a stray retransmit timer at {stray:?}"),
+        );
+
+        let (mut server, client, _dir) = transferring();
+        server
+            .progress_timers
+            .schedule(server.clock.now(), client, generation + 1);
+        assert_drift(
+            &server,
+            &format!(
+                "progress timer of {client} has generation {}, never issued",
+                generation + 1
+            ),
+        );
+    }
+
+    #[test]
+    fn audit_ignores_timers_of_ended_sessions() {
+        let (mut server, client, _dir) = transferring();
+        server.fail_session(&client, "test");
+
+        // The timers of the session are left to expire.
+        assert!(server.retransmit_timers.entries().count() > 0);
+        assert_eq!(server.audit(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn audit_catches_reader_drift() {
+        let (mut server, _client, dir) = transferring();
+        let reader = dir.path().canonicalize().unwrap().join("boot.img");
+        server.readers.as_mut().unwrap().acquire(&reader);
+
+        assert_drift(
+            &server,
+            &format!(
+                "{} is counted with 2 readers for 1 sessions",
+                reader.display()
+            ),
+        );
+    }
+
+    #[test]
+    fn audit_catches_gauge_drift() {
+        let (server, _client, _dir) = transferring();
+        Metrics::set(&server.metrics.active_sessions, 0);
+        assert_drift(&server, "active_sessions gauge is 0 with 1 sessions");
+
+        let (server, _client, _dir) = transferring();
+        Metrics::set(&server.metrics.queue_length, 3);
+        assert_drift(&server, "queue_length gauge is 3 with 0 queued requests");
+    }
+
+    #[test]
+    #[should_panic(expected = "self-check failed")]
+    fn self_check_panics_in_tests() {
+        let (mut server, client, _dir) = transferring();
+        server.clients.remove(&client);
+
+        server.run_self_check();
+    }
+
+    #[test]
+    fn strips_leading_slashes() {
+        assert_eq!(
+            root_relative("/firmware/phone.bin"),
+            Some("firmware/phone.bin")
+        );
+        assert_eq!(root_relative("//boot.img"), Some("boot.img"));
+        assert_eq!(root_relative("/../etc/passwd"), Some("../etc/passwd"));
+        assert_eq!(root_relative("boot.img"), None);
+        assert_eq!(root_relative("//"), None);
+    }
+
     #[test]
     fn validates_file_path() {
         assert!(validate_file_path(
@@ -273,5 +3807,118 @@ mod tests {
             &PathBuf::from("/dir/test/../file"),
             &PathBuf::from("/dir/test")
         ));
+
+        assert!(validate_file_path(
+            &PathBuf::from("/dir/test/my..config.bin"),
+            &PathBuf::from("/dir/test")
+        ));
+    }
+
+    #[test]
+    fn sanitizes_request_paths() {
+        assert_eq!(
+            sanitize_request_path("subdir/pxelinux.0"),
+            Ok(PathBuf::from("subdir/pxelinux.0"))
+        );
+        assert_eq!(
+            sanitize_request_path("subdir\\pxelinux.0"),
+            Ok(PathBuf::from("subdir/pxelinux.0"))
+        );
+        assert_eq!(
+            sanitize_request_path("./a//my..config.bin"),
+            Ok(PathBuf::from("a/my..config.bin"))
+        );
+        assert_eq!(
+            sanitize_request_path("boot%2F..%2Fkey"),
+            Ok(PathBuf::from("boot%2F..%2Fkey"))
+        );
+
+        for refused in [
+            "..\\..\\boot.ini",
+            "images/../../etc/passwd",
+            "images\\..",
+            "\\\\server\\share\\key",
+            "/etc/passwd",
+            "boot\0.img",
+        ] {
+            assert_eq!(
+                sanitize_request_path(refused),
+                Err(ErrorCode::AccessViolation),
+                "{refused}"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn refuses_colons_on_windows() {
+        for refused in [
+            "C:\\secrets\\key",
+            "c:key",
+            "sub\\C:x",
+            "sub/d:\\key",
+            "boot.img:stream",
+            "images/boot.img::$DATA",
+            "00:11:22:33:44:55.ipxe",
+        ] {
+            assert_eq!(
+                sanitize_request_path(refused),
+                Err(ErrorCode::AccessViolation),
+                "{refused}"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn keeps_colons_elsewhere() {
+        assert_eq!(
+            sanitize_request_path("pxelinux.cfg/01:00:11:22:33:44:55"),
+            Ok(PathBuf::from("pxelinux.cfg/01:00:11:22:33:44:55"))
+        );
+        assert_eq!(
+            sanitize_request_path("C:\\boot.img"),
+            Ok(PathBuf::from("C:/boot.img"))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resolves_files_inside_directory() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("shadow"), b"secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("my..config.bin"), b"config").unwrap();
+        fs::create_dir(root.join("images")).unwrap();
+        fs::write(root.join("images/boot.img"), b"boot").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("shadow"), root.join("shadow")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("images"), root.join("current")).unwrap();
+
+        let canonical = fs::canonicalize(root).unwrap();
+        let check = |name: &str, follow_symlinks| {
+            check_file_exists(&root.join(name), root, &canonical, follow_symlinks)
+        };
+
+        assert_eq!(check("my..config.bin", true), ErrorCode::FileExists);
+        assert_eq!(check("missing.bin", true), ErrorCode::FileNotFound);
+        assert_eq!(check("shadow", true), ErrorCode::AccessViolation);
+        assert_eq!(check("escape/shadow", true), ErrorCode::AccessViolation);
+        assert_eq!(check("escape/missing", true), ErrorCode::AccessViolation);
+        assert_eq!(check("../shadow", true), ErrorCode::AccessViolation);
+        assert_eq!(check("current/boot.img", true), ErrorCode::FileExists);
+        assert_eq!(check("current/boot.img", false), ErrorCode::AccessViolation);
+        assert_eq!(check("images/boot.img", false), ErrorCode::FileExists);
+    }
+
+    #[test]
+    fn classifies_privileged_source_ports() {
+        assert!(is_privileged_source(53, &[]));
+        assert!(is_privileged_source(123, &[69]));
+        assert!(is_privileged_source(1023, &[]));
+        assert!(!is_privileged_source(69, &[69]));
+        assert!(!is_privileged_source(1024, &[]));
+        assert!(!is_privileged_source(50123, &[]));
     }
 }
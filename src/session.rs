@@ -0,0 +1,1276 @@
+use std::time::{Duration, Instant};
+
+use crate::{Checksum, ChecksumAlgorithm, Packet, TransferOption, MAX_WINDOWSIZE};
+
+pub(crate) type Chunk = Vec<u8>;
+pub(crate) type Window = Vec<Chunk>;
+
+/// SessionOptions `struct` holds the negotiated settings of a [`Session`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionOptions {
+    /// Size of a DATA block
+    pub blk_size: usize,
+    /// Number of blocks sent before waiting for an ACK, clamped to
+    /// [`MAX_WINDOWSIZE`]
+    pub windowsize: u16,
+    /// Time without an ACK after which the window is sent again
+    pub timeout: Duration,
+    /// Number of retransmissions without an ACK before giving up
+    pub max_retries: u32,
+    /// Whether every DATA packet is sent several times, so that the ACK
+    /// repeating the one of the previous window is expected and ignored
+    pub duplicate_acks: bool,
+    /// Digest of the file sent in a [`Packet::Checksum`] once the client
+    /// acknowledged all of it
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Number of consecutive ACKs for a block more than a window below the
+    /// one in flight after which the client is taken to have restarted the
+    /// transfer, see [`SessionAction::ClientRestarted`]. 0 never does.
+    pub restart_acks: u32,
+    /// Largest number of bytes sent and not acknowledged, the window being
+    /// sent in parts when its blocks add up to more. At least one block is
+    /// always sent. `None` sends whole windows.
+    pub max_in_flight_bytes: Option<usize>,
+    /// Block number following 65535 in files of more blocks, 0 or 1 as
+    /// clients differ. Values above 1 count as 1.
+    pub rollover: u16,
+    /// Whether the blocks sent are read again with
+    /// [`SessionAction::ResendBlock`] to be sent again, instead of kept in
+    /// the window. Only for files that can be read at any offset.
+    pub reread: bool,
+}
+
+/// SessionEvent `enum` represents the inputs of a [`Session`].
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    /// A packet from the client of the session. Read requests are
+    /// retransmissions of the request that started it.
+    PacketReceived(Packet),
+    /// Time passed, the retransmission timeout may have expired.
+    Tick,
+    /// The data of a [`SessionAction::ReadFileBlock`], shorter than asked
+    /// for at the end of the file.
+    BlockRead {
+        /// Offset of the block in the file
+        offset: u64,
+        /// Data read
+        data: Vec<u8>,
+    },
+}
+
+/// SessionAction `enum` represents what the I/O driving a [`Session`] must
+/// do, in order.
+///
+/// New variants may be added, so it cannot be matched exhaustively outside
+/// of the crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionAction {
+    /// Send a packet to the client.
+    SendPacket(Packet),
+    /// Read up to `len` bytes of the file at `offset` and hand them back as
+    /// a [`SessionEvent::BlockRead`]. Blocks are requested in order, one at a
+    /// time.
+    ReadFileBlock {
+        /// Offset of the block in the file
+        offset: u64,
+        /// Number of bytes to read
+        len: usize,
+    },
+    /// The client acknowledged the whole file. Follows the
+    /// [`Packet::Checksum`] when one was negotiated.
+    Finished,
+    /// The transfer failed and must be dropped.
+    Abort(String),
+    /// Read `len` bytes of the file at `offset` again and send them as the
+    /// DATA of `block_num`, a block sent before. Only asked for with
+    /// [`SessionOptions::reread`].
+    ResendBlock {
+        /// Block number of the DATA
+        block_num: u16,
+        /// Offset of the block in the file
+        offset: u64,
+        /// Number of bytes of the block
+        len: usize,
+    },
+    /// The client went back to acknowledging `block`, which it did before,
+    /// as if it restarted reading the file. The transfer is either dropped
+    /// or resumed with [`Session::rewind`].
+    ClientRestarted {
+        /// Block acknowledged again
+        block: u16,
+    },
+}
+
+/// Session `struct` is the protocol state machine of a read transfer,
+/// without any socket or file.
+///
+/// It consumes [`SessionEvent`]s and returns the [`SessionAction`]s to
+/// carry out: window management, block number arithmetic and
+/// retransmission decisions live here, while the server only moves bytes
+/// and timers around. The
+/// window following the one in flight is read ahead, so that it is in
+/// memory when the ACK arrives. With [`SessionOptions::reread`], the blocks
+/// in flight are not kept once sent, only their length.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::{Duration, Instant};
+/// use tftpd::{Packet, Session, SessionAction, SessionEvent, SessionOptions};
+///
+/// let options = SessionOptions {
+///     blk_size: 512,
+///     windowsize: 1,
+///     timeout: Duration::from_secs(5),
+///     max_retries: 6,
+///     duplicate_acks: false,
+///     checksum: None,
+///     restart_acks: 3,
+///     max_in_flight_bytes: None,
+///     rollover: 0,
+///     reread: false,
+/// };
+/// let now = Instant::now();
+/// let (mut session, actions) = Session::new(options, None, now);
+/// assert_eq!(actions, vec![SessionAction::ReadFileBlock { offset: 0, len: 512 }]);
+///
+/// let data = b"hello".to_vec();
+/// let actions = session.handle(now, SessionEvent::BlockRead { offset: 0, data: data.clone() });
+/// assert_eq!(actions, vec![SessionAction::SendPacket(Packet::Data { block_num: 1, data })]);
+///
+/// let actions = session.handle(now, SessionEvent::PacketReceived(Packet::Ack(1)));
+/// assert_eq!(actions, vec![SessionAction::Finished]);
+/// ```
+#[derive(Debug)]
+pub struct Session {
+    options: SessionOptions,
+    /// Options to acknowledge, until the client acknowledges the OACK
+    oack: Option<Vec<TransferOption>>,
+    /// First block of the window, or 0 while the OACK is pending
+    block_number: u16,
+    window: Vec<Slot>,
+    /// Number of blocks at the start of the window sent and not
+    /// acknowledged yet, fewer than the window when they would exceed
+    /// `max_in_flight_bytes`
+    in_flight: usize,
+    /// Chunks read ahead of the window, sent once the window moves on
+    ahead: Window,
+    /// Offset of the next block to read
+    offset: u64,
+    /// Whether a read was requested and not answered yet
+    reading: bool,
+    /// Whether the last block was read: the first one shorter than the
+    /// block size, which is empty when the file ends on a block boundary
+    eof: bool,
+    /// Whether the window holds the last block, set when it is filled. The
+    /// session finishes once the client acknowledged it.
+    finished: bool,
+    /// Whether the window must be sent once it is filled
+    sending: bool,
+    last_sent: Instant,
+    retries: u32,
+    bytes_acked: u64,
+    /// Number of data blocks acknowledged, which tells the position of a
+    /// block number once they wrap around
+    blocks_acked: u64,
+    retransmits: u64,
+    /// Number of consecutive ACKs for blocks acknowledged before
+    regressions: u32,
+    /// Digest of the blocks read so far
+    checksum: Option<Checksum>,
+    /// Number of bytes of the file added to the digest, which are not added
+    /// again when they are read again after a rewind
+    checksummed: u64,
+}
+
+impl Session {
+    /// Creates the session of a read request, acknowledging `oack` first if
+    /// some options were negotiated, and returns its first actions.
+    pub fn new(
+        mut options: SessionOptions,
+        oack: Option<Vec<TransferOption>>,
+        now: Instant,
+    ) -> (Session, Vec<SessionAction>) {
+        options.windowsize = options.windowsize.min(MAX_WINDOWSIZE);
+        options.rollover = options.rollover.min(1);
+        let mut session = Session {
+            options,
+            block_number: if oack.is_some() { 0 } else { 1 },
+            oack,
+            window: Vec::new(),
+            in_flight: 0,
+            ahead: Window::new(),
+            offset: 0,
+            reading: false,
+            eof: false,
+            finished: false,
+            sending: false,
+            last_sent: now,
+            retries: 0,
+            bytes_acked: 0,
+            blocks_acked: 0,
+            retransmits: 0,
+            regressions: 0,
+            checksum: options.checksum.map(Checksum::new),
+            checksummed: 0,
+        };
+
+        let mut actions = vec![];
+        match &session.oack {
+            Some(options) => actions.push(SessionAction::SendPacket(Packet::Oack(options.clone()))),
+            None => {
+                session.sending = true;
+                session.pump(now, &mut actions);
+            }
+        }
+        (session, actions)
+    }
+
+    /// Handles an event and returns the actions to carry out.
+    pub fn handle(&mut self, now: Instant, event: SessionEvent) -> Vec<SessionAction> {
+        let mut actions = vec![];
+        match event {
+            SessionEvent::PacketReceived(Packet::Ack(block)) => {
+                self.handle_ack(now, block, &mut actions)
+            }
+            SessionEvent::PacketReceived(Packet::Rrq { .. }) => self.resend(now, &mut actions),
+            SessionEvent::PacketReceived(_) => {}
+            SessionEvent::Tick => self.handle_tick(now, &mut actions),
+            SessionEvent::BlockRead { offset, data } => {
+                if offset != self.offset {
+                    // Read before a rewind.
+                    return actions;
+                }
+                self.reading = false;
+                if let Some(checksum) = &mut self.checksum {
+                    let seen = (self.checksummed - offset).min(data.len() as u64);
+                    checksum.update(&data[seen as usize..]);
+                    self.checksummed = self.checksummed.max(offset + data.len() as u64);
+                }
+                self.offset += data.len() as u64;
+                // Sources like decompressors may return less than asked for
+                // before the end, the driver reads until the block is full.
+                // An empty block is sent as well, so that a file ending on a
+                // block boundary ends with a DATA shorter than the others.
+                if data.len() < self.options.blk_size {
+                    self.eof = true;
+                }
+                self.ahead.push(data);
+                self.pump(now, &mut actions);
+            }
+        }
+        actions
+    }
+
+    /// Returns the first block of the window in flight, 0 while the OACK is
+    /// pending.
+    pub fn block_number(&self) -> u16 {
+        self.block_number
+    }
+
+    /// Returns the number of bytes acknowledged by the client.
+    pub fn bytes_acked(&self) -> u64 {
+        self.bytes_acked
+    }
+
+    /// Returns the number of times the window or OACK was sent again after a
+    /// timeout or a stale ACK.
+    pub fn retransmits(&self) -> u64 {
+        self.retransmits
+    }
+
+    /// Returns when the window or OACK is sent again without an ACK.
+    pub fn retransmit_at(&self) -> Instant {
+        self.last_sent + self.options.timeout
+    }
+
+    /// Returns whether the client has not acknowledged anything past the
+    /// OACK yet, so that a repeated read request is a retransmission.
+    pub fn negotiating(&self) -> bool {
+        self.oack.is_some() || (self.block_number == 1 && self.bytes_acked == 0)
+    }
+
+    /// Returns whether the first data window was sent and nothing of it was
+    /// acknowledged yet.
+    pub fn awaiting_first_window(&self) -> bool {
+        self.oack.is_none() && self.block_number == 1 && self.bytes_acked == 0
+    }
+
+    /// Returns whether an ACK for `block` acknowledges data that has actually
+    /// been sent. The OACK counts as the block before the first data block
+    /// until it is acknowledged.
+    pub fn acknowledges(&self, block: u16) -> bool {
+        match self.oack {
+            Some(_) => block == self.block_number,
+            None => self.outstanding().any(|outstanding| outstanding == block),
+        }
+    }
+
+    /// Resumes the transfer after the block the client acknowledged again in
+    /// a [`SessionAction::ClientRestarted`], reading the file again from
+    /// [`Session::bytes_acked`], and returns the actions to carry out.
+    pub fn rewind(&mut self, now: Instant, block: u16) -> Vec<SessionAction> {
+        let back = self.back(block);
+        let acked = (self.blocks_acked + 1).saturating_sub(back);
+        self.blocks_acked = acked;
+        self.block_number = self.wire(acked + 1);
+        self.bytes_acked = acked * self.options.blk_size as u64;
+        self.offset = self.bytes_acked;
+        self.window.clear();
+        self.ahead.clear();
+        self.in_flight = 0;
+        self.reading = false;
+        self.eof = false;
+        self.finished = false;
+        self.retries = 0;
+        self.regressions = 0;
+
+        let mut actions = vec![];
+        self.sending = true;
+        self.pump(now, &mut actions);
+        actions
+    }
+
+    /// Returns whether an ACK for `block` goes back more than a window to
+    /// a block the client already acknowledged, 0 standing for the start
+    /// of the file.
+    fn regresses(&self, block: u16) -> bool {
+        let back = self.back(block);
+        self.oack.is_none()
+            && back > self.options.windowsize as u64
+            && back <= self.blocks_acked + 1
+    }
+
+    /// Returns the block numbers sent and not acknowledged yet, in order.
+    /// Compared one by one rather than by distance from the window start,
+    /// an ACK is accepted only for a block actually in flight.
+    fn outstanding(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.in_flight).map(|i| self.wire(self.blocks_acked + 1 + i as u64))
+    }
+
+    /// Returns the block number sent for the `index`th block of the file,
+    /// counted from 1, rolling over to [`SessionOptions::rollover`] after
+    /// 65535.
+    fn wire(&self, index: u64) -> u16 {
+        let rollover = self.options.rollover as u64;
+        if index <= u16::MAX as u64 {
+            return index as u16;
+        }
+        (rollover + (index - rollover) % self.period()) as u16
+    }
+
+    /// Returns the number of block numbers in a cycle once they roll over.
+    fn period(&self) -> u64 {
+        (1 << 16) - self.options.rollover as u64
+    }
+
+    /// Returns how many blocks before the first one of the window `block`
+    /// is, for the nearest block of that number. Block 0 stands for the
+    /// start of the file when the numbers roll over to 1.
+    fn back(&self, block: u16) -> u64 {
+        let start = self.blocks_acked + 1;
+        if block == 0 && self.options.rollover == 1 {
+            return start;
+        }
+        (self.wire(start) as u64 + self.period() - block as u64) % self.period()
+    }
+
+    fn handle_ack(&mut self, now: Instant, block: u16, actions: &mut Vec<SessionAction>) {
+        if self.options.duplicate_acks && block == self.wire(self.blocks_acked) {
+            // The client acknowledges every copy of the last block, only
+            // the first ACK counts.
+            return;
+        }
+
+        if !self.acknowledges(block) {
+            if self.regresses(block) {
+                self.regressions += 1;
+                if self.regressions == self.options.restart_acks {
+                    actions.push(SessionAction::ClientRestarted { block });
+                    return;
+                }
+            } else {
+                self.regressions = 0;
+            }
+            // Stale or bogus ack, send the current window again.
+            self.retransmits += 1;
+            self.resend(now, actions);
+            return;
+        }
+
+        if self.oack.take().is_none() {
+            let acked = self
+                .outstanding()
+                .position(|outstanding| outstanding == block)
+                .map_or(0, |position| position + 1);
+            for slot in self.window.drain(..acked) {
+                self.bytes_acked += slot.len() as u64;
+            }
+            self.in_flight -= acked;
+            self.blocks_acked += acked as u64;
+        }
+        self.block_number = self.wire(self.blocks_acked + 1);
+        self.retries = 0;
+        self.regressions = 0;
+
+        if self.finished && self.window.is_empty() {
+            if let Some(checksum) = &self.checksum {
+                actions.push(SessionAction::SendPacket(Packet::Checksum(
+                    checksum.digest(),
+                )));
+            }
+            actions.push(SessionAction::Finished);
+            return;
+        }
+
+        self.sending = true;
+        self.pump(now, actions);
+    }
+
+    fn handle_tick(&mut self, now: Instant, actions: &mut Vec<SessionAction>) {
+        if now < self.retransmit_at() {
+            return;
+        }
+        if self.retries >= self.options.max_retries {
+            actions.push(SessionAction::Abort(format!(
+                "timed out after {} retries",
+                self.options.max_retries
+            )));
+            return;
+        }
+
+        self.retries += 1;
+        self.retransmits += 1;
+        self.resend(now, actions);
+    }
+
+    /// Fills the window from the chunks read ahead and the file, sends it
+    /// once it is full, then reads the next window ahead.
+    fn pump(&mut self, now: Instant, actions: &mut Vec<SessionAction>) {
+        if self.sending {
+            let windowsize = self.options.windowsize as usize;
+            let from_ahead = windowsize
+                .saturating_sub(self.window.len())
+                .min(self.ahead.len());
+            self.window
+                .extend(self.ahead.drain(..from_ahead).map(Slot::Held));
+
+            if self.window.len() < windowsize && !self.eof {
+                self.read(actions);
+                return;
+            }
+            self.sending = false;
+            self.finished = self.eof && self.ahead.is_empty();
+            self.resend(now, actions);
+        }
+
+        if self.ahead.len() < self.options.windowsize as usize && !self.eof {
+            self.read(actions);
+        }
+    }
+
+    fn read(&mut self, actions: &mut Vec<SessionAction>) {
+        if !self.reading {
+            self.reading = true;
+            actions.push(SessionAction::ReadFileBlock {
+                offset: self.offset,
+                len: self.options.blk_size,
+            });
+        }
+    }
+
+    /// Sends the pending OACK or the current window again, as much of it
+    /// as `max_in_flight_bytes` allows, without reading further data, and
+    /// moves the retransmission deadline. With `reread`, the blocks sent
+    /// before are read again and the others are only kept as a length.
+    fn resend(&mut self, now: Instant, actions: &mut Vec<SessionAction>) {
+        self.last_sent = now;
+        if let Some(options) = &self.oack {
+            actions.push(SessionAction::SendPacket(Packet::Oack(options.clone())));
+            return;
+        }
+        self.in_flight = self.sendable();
+        let mut offset = self.bytes_acked;
+        for i in 0..self.in_flight {
+            let block_num = self.wire(self.blocks_acked + 1 + i as u64);
+            let slot = &mut self.window[i];
+            let len = slot.len();
+            actions.push(match slot {
+                Slot::Held(chunk) if self.options.reread => {
+                    let data = std::mem::take(chunk);
+                    *slot = Slot::Sent(len);
+                    SessionAction::SendPacket(Packet::Data { block_num, data })
+                }
+                Slot::Held(chunk) => SessionAction::SendPacket(Packet::Data {
+                    block_num,
+                    data: chunk.clone(),
+                }),
+                Slot::Sent(_) => SessionAction::ResendBlock {
+                    block_num,
+                    offset,
+                    len,
+                },
+            });
+            offset += len as u64;
+        }
+    }
+
+    /// Returns the number of blocks at the start of the window that fit in
+    /// `max_in_flight_bytes`, at least one.
+    fn sendable(&self) -> usize {
+        let Some(max) = self.options.max_in_flight_bytes else {
+            return self.window.len();
+        };
+        let mut bytes = 0;
+        let fitting = self
+            .window
+            .iter()
+            .take_while(|slot| {
+                bytes += slot.len();
+                bytes <= max
+            })
+            .count();
+        fitting.max(1).min(self.window.len())
+    }
+}
+
+/// Slot `enum` is a block of the window: its data, or only its length once
+/// it was sent with [`SessionOptions::reread`].
+#[derive(Debug)]
+enum Slot {
+    Held(Chunk),
+    Sent(usize),
+}
+
+impl Slot {
+    fn len(&self) -> usize {
+        match self {
+            Slot::Held(chunk) => chunk.len(),
+            Slot::Sent(len) => *len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OptionType;
+    use std::collections::{HashSet, VecDeque};
+
+    fn options(blk_size: usize, windowsize: u16) -> SessionOptions {
+        SessionOptions {
+            blk_size,
+            windowsize,
+            timeout: Duration::from_secs(5),
+            max_retries: 2,
+            duplicate_acks: false,
+            checksum: None,
+            restart_acks: 3,
+            max_in_flight_bytes: None,
+            rollover: 0,
+            reread: false,
+        }
+    }
+
+    fn read(offset: u64, len: usize) -> SessionAction {
+        SessionAction::ReadFileBlock { offset, len }
+    }
+
+    fn data(block_num: u16, data: &[u8]) -> SessionAction {
+        SessionAction::SendPacket(Packet::Data {
+            block_num,
+            data: data.to_vec(),
+        })
+    }
+
+    fn block(offset: u64, data: &[u8]) -> SessionEvent {
+        SessionEvent::BlockRead {
+            offset,
+            data: data.to_vec(),
+        }
+    }
+
+    fn ack(block: u16) -> SessionEvent {
+        SessionEvent::PacketReceived(Packet::Ack(block))
+    }
+
+    /// Runs `events` in order against a session of `contents`, answering
+    /// every read from it, and returns the actions other than reads of
+    /// every event.
+    fn run(
+        session: &mut Session,
+        first: Vec<SessionAction>,
+        contents: &[u8],
+        events: Vec<(Instant, SessionEvent)>,
+    ) -> Vec<Vec<SessionAction>> {
+        let serve = |session: &mut Session, now, mut pending: Vec<SessionAction>| {
+            let mut done = vec![];
+            while !pending.is_empty() {
+                let mut next = vec![];
+                for action in pending {
+                    match action {
+                        SessionAction::ReadFileBlock { offset, len } => {
+                            let start = (offset as usize).min(contents.len());
+                            let end = (start + len).min(contents.len());
+                            next.extend(session.handle(now, block(offset, &contents[start..end])));
+                        }
+                        action => done.push(action),
+                    }
+                }
+                pending = next;
+            }
+            done
+        };
+
+        let mut outputs = vec![serve(session, session.last_sent, first)];
+        for (at, event) in events {
+            let actions = session.handle(at, event);
+            outputs.push(serve(session, at, actions));
+        }
+        outputs
+    }
+
+    #[test]
+    fn reads_ahead_of_window() {
+        let now = Instant::now();
+        let (mut session, actions) = Session::new(options(2, 2), None, now);
+        assert_eq!(actions, vec![read(0, 2)]);
+
+        let mut actions = session.handle(now, block(0, &[0, 1]));
+        assert_eq!(actions, vec![read(2, 2)]);
+        actions = session.handle(now, block(2, &[2, 3]));
+        assert_eq!(
+            actions,
+            vec![data(1, &[0, 1]), data(2, &[2, 3]), read(4, 2)]
+        );
+        assert!(session.awaiting_first_window());
+        actions = session.handle(now, block(4, &[4, 5]));
+        assert_eq!(actions, vec![read(6, 2)]);
+        actions = session.handle(now, block(6, &[6, 7]));
+        assert!(actions.is_empty());
+
+        // A partial ACK only frees one slot, the other chunk stays ahead.
+        actions = session.handle(now, ack(1));
+        assert_eq!(
+            actions,
+            vec![data(2, &[2, 3]), data(3, &[4, 5]), read(8, 2)]
+        );
+        assert!(!session.awaiting_first_window());
+        actions = session.handle(now, block(8, &[8]));
+        assert!(actions.is_empty());
+
+        actions = session.handle(now, ack(3));
+        assert_eq!(actions, vec![data(4, &[6, 7]), data(5, &[8])]);
+        assert_eq!(session.handle(now, ack(5)), vec![SessionAction::Finished]);
+        assert_eq!(session.bytes_acked(), 9);
+    }
+
+    #[test]
+    fn finishes_with_short_chunk_read_ahead() {
+        let now = Instant::now();
+        let (mut session, first) = Session::new(options(2, 1), None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            &[1, 2, 3],
+            vec![(now, ack(1)), (now, ack(2))],
+        );
+
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, &[1, 2])],
+                vec![data(2, &[3])],
+                vec![SessionAction::Finished],
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_file_on_block_boundary_with_empty_block() {
+        let now = Instant::now();
+        let (mut session, first) = Session::new(options(2, 1), None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            &[1, 2, 3, 4],
+            vec![(now, ack(1)), (now, ack(2)), (now, ack(3))],
+        );
+
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, &[1, 2])],
+                vec![data(2, &[3, 4])],
+                vec![data(3, &[])],
+                vec![SessionAction::Finished],
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_file_on_window_boundary_with_empty_window() {
+        let now = Instant::now();
+        let (mut session, first) = Session::new(options(2, 2), None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            &[1, 2, 3, 4],
+            vec![(now, ack(2)), (now, ack(3))],
+        );
+
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, &[1, 2]), data(2, &[3, 4])],
+                vec![data(3, &[])],
+                vec![SessionAction::Finished],
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_empty_file_as_empty_block() {
+        let now = Instant::now();
+        let (mut session, first) = Session::new(options(2, 4), None, now);
+
+        let outputs = run(&mut session, first, &[], vec![(now, ack(1))]);
+
+        assert_eq!(
+            outputs,
+            vec![vec![data(1, &[])], vec![SessionAction::Finished]]
+        );
+    }
+
+    #[test]
+    fn sends_window_in_parts_within_byte_cap() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(6);
+        let capped = SessionOptions {
+            max_in_flight_bytes: Some(8),
+            ..options(4, 4)
+        };
+        let (mut session, first) = Session::new(capped, None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            b"0123456789abcd",
+            vec![
+                (now, ack(1)),
+                (later, SessionEvent::Tick),
+                (later, ack(3)),
+                (later, ack(4)),
+            ],
+        );
+
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, b"0123"), data(2, b"4567")],
+                vec![data(2, b"4567"), data(3, b"89ab")],
+                vec![data(2, b"4567"), data(3, b"89ab")],
+                vec![data(4, b"cd")],
+                vec![SessionAction::Finished],
+            ]
+        );
+
+        // A block larger than the cap is still sent, alone.
+        let capped = SessionOptions {
+            max_in_flight_bytes: Some(3),
+            ..options(4, 4)
+        };
+        let (mut session, first) = Session::new(capped, None, now);
+        let outputs = run(&mut session, first, b"0123456", vec![(now, ack(1))]);
+        assert_eq!(outputs, vec![vec![data(1, b"0123")], vec![data(2, b"456")]]);
+    }
+
+    #[test]
+    fn sends_checksum_after_last_ack() {
+        let now = Instant::now();
+        let options = SessionOptions {
+            checksum: Some(ChecksumAlgorithm::Crc32c),
+            ..options(4, 2)
+        };
+        let (mut session, first) = Session::new(options, None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            b"123456789",
+            vec![(now, ack(2)), (now, ack(3))],
+        );
+
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, b"1234"), data(2, b"5678")],
+                vec![data(3, b"9")],
+                vec![
+                    SessionAction::SendPacket(Packet::Checksum(vec![0xE3, 0x06, 0x92, 0x83])),
+                    SessionAction::Finished,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_client_restart_and_rewinds() {
+        let now = Instant::now();
+        let options = SessionOptions {
+            checksum: Some(ChecksumAlgorithm::Crc32c),
+            ..options(2, 1)
+        };
+        let (mut session, first) = Session::new(options, None, now);
+        let (mut uninterrupted, first_uninterrupted) = Session::new(options, None, now);
+        let contents = b"abcdefg";
+
+        // ACKs of the previous block, then of a later one, are only stale.
+        let outputs = run(
+            &mut session,
+            first,
+            contents,
+            vec![
+                (now, ack(1)),
+                (now, ack(2)),
+                (now, ack(2)),
+                (now, ack(0)),
+                (now, ack(9)),
+                (now, ack(1)),
+                (now, ack(0)),
+                (now, ack(1)),
+            ],
+        );
+        assert_eq!(outputs[3], vec![data(3, b"ef")]);
+        assert_eq!(outputs[4], vec![data(3, b"ef")]);
+        assert_eq!(outputs[7], vec![data(3, b"ef")]);
+        assert_eq!(
+            outputs[8],
+            vec![SessionAction::ClientRestarted { block: 1 }]
+        );
+
+        let rewound = session.rewind(now, 1);
+        assert_eq!(rewound, vec![read(2, 2)]);
+        assert_eq!(session.bytes_acked(), 2);
+        let outputs = run(
+            &mut session,
+            rewound,
+            contents,
+            vec![(now, ack(2)), (now, ack(3)), (now, ack(4))],
+        );
+        let expected = run(
+            &mut uninterrupted,
+            first_uninterrupted,
+            contents,
+            vec![(now, ack(1)), (now, ack(2)), (now, ack(3)), (now, ack(4))],
+        );
+        assert_eq!(outputs, expected[1..]);
+        assert_eq!(session.bytes_acked(), 7);
+    }
+
+    #[test]
+    fn transitions() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+        let oack = vec![TransferOption {
+            option: OptionType::BlockSize,
+            value: 4,
+        }];
+        let oack_packet = || SessionAction::SendPacket(Packet::Oack(oack.clone()));
+        let rrq = SessionEvent::PacketReceived(Packet::Rrq {
+            filename: "image.bin".to_string(),
+            mode: "octet".to_string(),
+            options: oack.clone(),
+        });
+        let contents = b"0123456789";
+
+        type Case = (
+            &'static str,
+            Option<Vec<TransferOption>>,
+            SessionOptions,
+            Vec<(Instant, SessionEvent)>,
+            Vec<Vec<SessionAction>>,
+        );
+        let cases: Vec<Case> = vec![
+            (
+                "oack then data",
+                Some(oack.clone()),
+                options(4, 1),
+                vec![(now, ack(0)), (now, ack(1))],
+                vec![
+                    vec![oack_packet()],
+                    vec![data(1, b"0123")],
+                    vec![data(2, b"4567")],
+                ],
+            ),
+            (
+                "repeated request resends the oack",
+                Some(oack.clone()),
+                options(4, 1),
+                vec![(now, rrq)],
+                vec![vec![oack_packet()], vec![oack_packet()]],
+            ),
+            (
+                "ack of the oack only counts as block 0",
+                Some(oack.clone()),
+                options(4, 1),
+                vec![(now, ack(1))],
+                vec![vec![oack_packet()], vec![oack_packet()]],
+            ),
+            (
+                "stale ack resends the window",
+                None,
+                options(4, 2),
+                vec![(now, ack(7)), (now, ack(1))],
+                vec![
+                    vec![data(1, b"0123"), data(2, b"4567")],
+                    vec![data(1, b"0123"), data(2, b"4567")],
+                    vec![data(2, b"4567"), data(3, b"89")],
+                ],
+            ),
+            (
+                "timeout resends the window",
+                None,
+                options(4, 1),
+                vec![(now, SessionEvent::Tick), (later, SessionEvent::Tick)],
+                vec![vec![data(1, b"0123")], vec![], vec![data(1, b"0123")]],
+            ),
+            (
+                "gives up after the retries",
+                None,
+                options(4, 1),
+                vec![
+                    (later, SessionEvent::Tick),
+                    (later + Duration::from_secs(5), SessionEvent::Tick),
+                    (later + Duration::from_secs(10), SessionEvent::Tick),
+                ],
+                vec![
+                    vec![data(1, b"0123")],
+                    vec![data(1, b"0123")],
+                    vec![data(1, b"0123")],
+                    vec![SessionAction::Abort(
+                        "timed out after 2 retries".to_string(),
+                    )],
+                ],
+            ),
+            (
+                "duplicate ack of the previous block is ignored",
+                None,
+                SessionOptions {
+                    duplicate_acks: true,
+                    ..options(4, 1)
+                },
+                vec![(now, ack(1)), (now, ack(1)), (now, ack(2))],
+                vec![
+                    vec![data(1, b"0123")],
+                    vec![data(2, b"4567")],
+                    vec![],
+                    vec![data(3, b"89")],
+                ],
+            ),
+            (
+                "last ack finishes",
+                None,
+                options(8, 4),
+                vec![(now, ack(2))],
+                vec![
+                    vec![data(1, b"01234567"), data(2, b"89")],
+                    vec![SessionAction::Finished],
+                ],
+            ),
+        ];
+
+        for (name, oack, options, events, expected) in cases {
+            let (mut session, first) = Session::new(options, oack, now);
+            let outputs = run(&mut session, first, contents, events);
+            assert_eq!(outputs, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn wraps_block_numbers() {
+        let now = Instant::now();
+        let contents = vec![7; 2 * 65536 + 1];
+        let (mut session, first) = Session::new(options(2, 1), None, now);
+        let events = (1..=65537u32)
+            .map(|block| (now, ack(block as u16)))
+            .collect();
+
+        let outputs = run(&mut session, first, &contents, events);
+
+        assert_eq!(outputs[65534], vec![data(65535, &[7, 7])]);
+        assert_eq!(outputs[65535], vec![data(0, &[7, 7])]);
+        assert_eq!(outputs[65536], vec![data(1, &[7])]);
+        assert_eq!(outputs[65537], vec![SessionAction::Finished]);
+        assert_eq!(session.bytes_acked(), 2 * 65536 + 1);
+    }
+
+    #[test]
+    fn rolls_over_to_one() {
+        let now = Instant::now();
+        let contents = vec![7; 2 * 65536 + 1];
+        let options = SessionOptions {
+            rollover: 1,
+            ..options(2, 1)
+        };
+        let (mut session, first) = Session::new(options, None, now);
+        let events = (1..=65537u32)
+            .map(|index| {
+                (
+                    now,
+                    ack(if index > 65535 { index - 65535 } else { index } as u16),
+                )
+            })
+            .collect();
+
+        let outputs = run(&mut session, first, &contents, events);
+
+        assert_eq!(outputs[65534], vec![data(65535, &[7, 7])]);
+        assert_eq!(outputs[65535], vec![data(1, &[7, 7])]);
+        assert_eq!(outputs[65536], vec![data(2, &[7])]);
+        assert_eq!(outputs[65537], vec![SessionAction::Finished]);
+        assert_eq!(session.bytes_acked(), 2 * 65536 + 1);
+    }
+
+    /// Xorshift generator, so that failures are reproducible from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn accepts_only_acks_of_outstanding_blocks() {
+        for (windowsize, seed) in [(u16::MAX, 0x9E37_79B9), (300, 0xDEAD_BEEF)] {
+            let now = Instant::now();
+            // Enough 8 byte blocks to wrap the block numbers.
+            let contents: Vec<u8> = (0..70_000 * 8 + 3).map(|i| i as u8).collect();
+            // Random ACKs of earlier blocks would look like a restart.
+            let options = SessionOptions {
+                restart_acks: 0,
+                ..options(8, windowsize)
+            };
+            let (mut session, mut pending) = Session::new(options, None, now);
+            assert_eq!(session.options.windowsize, windowsize.min(MAX_WINDOWSIZE));
+
+            let mut rng = Rng(seed);
+            // Block numbers sent and not acknowledged, in order
+            let mut outstanding = VecDeque::new();
+            let mut sent = HashSet::new();
+            let mut reading = None;
+            let mut finished = false;
+            loop {
+                for action in pending.drain(..) {
+                    match action {
+                        SessionAction::ReadFileBlock { offset, len } => {
+                            reading = Some((offset, len))
+                        }
+                        SessionAction::SendPacket(Packet::Data { block_num, .. }) => {
+                            if sent.insert(block_num) {
+                                outstanding.push_back(block_num);
+                            }
+                        }
+                        SessionAction::Finished => finished = true,
+                        action => panic!("unexpected {action:?}"),
+                    }
+                }
+                if finished {
+                    break;
+                }
+
+                // Reads complete at random, so that ACKs also arrive while
+                // the next window is partly filled and not sent yet.
+                if reading.is_some() && (outstanding.is_empty() || rng.below(2) == 0) {
+                    let (offset, len) = reading.take().unwrap();
+                    let start = (offset as usize).min(contents.len());
+                    let end = (start + len).min(contents.len());
+                    pending = session.handle(now, block(offset, &contents[start..end]));
+                    continue;
+                }
+
+                let start = session.block_number();
+                let candidate = match rng.below(6) {
+                    // A block in flight, usually near the start.
+                    0 | 1 => {
+                        let near_start = rng.below(2) == 0;
+                        let len = outstanding.len();
+                        outstanding[rng.below(if near_start { len.min(8) } else { len })]
+                    }
+                    // Just before or after the blocks in flight.
+                    2 => start.wrapping_add(outstanding.len() as u16 + rng.below(16) as u16),
+                    3 => start.wrapping_sub(1 + rng.below(16) as u16),
+                    // Half a sequence space away.
+                    4 => start.wrapping_add(32768).wrapping_add(rng.below(64) as u16),
+                    _ => rng.next() as u16,
+                };
+
+                let expected = sent.contains(&candidate);
+                assert_eq!(
+                    session.acknowledges(candidate),
+                    expected,
+                    "ack {candidate} with {} blocks from {start} outstanding",
+                    outstanding.len()
+                );
+                if expected {
+                    while let Some(acked) = outstanding.pop_front() {
+                        sent.remove(&acked);
+                        if acked == candidate {
+                            break;
+                        }
+                    }
+                }
+                pending = session.handle(now, ack(candidate));
+            }
+
+            assert_eq!(session.bytes_acked(), contents.len() as u64);
+        }
+    }
+
+    /// Sends 4 MiB in windows of 16 blocks of 512 bytes, losing every
+    /// tenth DATA and ACK, and returns the most bytes the session held.
+    fn peak_buffered(reread: bool) -> usize {
+        let mut now = Instant::now();
+        let contents: Vec<u8> = (0..4 << 20).map(|i| (i % 251) as u8).collect();
+        let options = SessionOptions {
+            max_retries: 100,
+            reread,
+            ..options(512, 16)
+        };
+        let (mut session, first) = Session::new(options, None, now);
+
+        let mut rng = Rng(0x5EED);
+        let mut received = vec![];
+        let mut next = 1u16;
+        let mut peak = 0;
+        let mut pending = VecDeque::from(first);
+        loop {
+            let mut acked = None;
+            let mut finished = false;
+            while let Some(action) = pending.pop_front() {
+                let (block_num, data) = match action {
+                    SessionAction::ReadFileBlock { offset, len } => {
+                        let start = (offset as usize).min(contents.len());
+                        let end = (start + len).min(contents.len());
+                        pending.extend(session.handle(now, block(offset, &contents[start..end])));
+                        continue;
+                    }
+                    SessionAction::SendPacket(Packet::Data { block_num, data }) => {
+                        (block_num, data)
+                    }
+                    SessionAction::ResendBlock {
+                        block_num,
+                        offset,
+                        len,
+                    } => {
+                        let start = offset as usize;
+                        (block_num, contents[start..start + len].to_vec())
+                    }
+                    SessionAction::Finished => {
+                        finished = true;
+                        continue;
+                    }
+                    action => panic!("unexpected {action:?}"),
+                };
+                // Every tenth DATA is lost, the client acknowledges the last
+                // block it received in order.
+                if rng.below(10) != 0 {
+                    if block_num == next {
+                        received.extend(data);
+                        next = next.wrapping_add(1);
+                    }
+                    acked = Some(next.wrapping_sub(1));
+                }
+                let held: usize = session
+                    .window
+                    .iter()
+                    .map(|slot| match slot {
+                        Slot::Held(chunk) => chunk.len(),
+                        Slot::Sent(_) => 0,
+                    })
+                    .sum();
+                let ahead: usize = session.ahead.iter().map(Vec::len).sum();
+                peak = peak.max(held + ahead);
+            }
+            if finished {
+                break;
+            }
+
+            // Every tenth ACK is lost too.
+            pending = match acked.filter(|_| rng.below(10) != 0) {
+                Some(block) => session.handle(now, ack(block)),
+                None => {
+                    now += options.timeout;
+                    session.handle(now, SessionEvent::Tick)
+                }
+            }
+            .into();
+        }
+
+        assert_eq!(received, contents);
+        assert!(session.retransmits() > 0);
+        peak
+    }
+
+    #[test]
+    fn buffers_at_most_two_windows() {
+        let peak = peak_buffered(false);
+        assert!(peak <= 2 * 16 * 512, "{peak} bytes buffered");
+    }
+
+    #[test]
+    fn buffers_one_window_when_rereading() {
+        let peak = peak_buffered(true);
+        assert!(peak <= 16 * 512, "{peak} bytes buffered");
+    }
+
+    #[test]
+    fn rereads_blocks_resent_after_partial_ack() {
+        let now = Instant::now();
+        let options = SessionOptions {
+            reread: true,
+            ..options(2, 3)
+        };
+        let (mut session, first) = Session::new(options, None, now);
+
+        let outputs = run(
+            &mut session,
+            first,
+            b"abcdefghij",
+            vec![
+                (now, ack(1)),
+                (now + Duration::from_secs(5), SessionEvent::Tick),
+            ],
+        );
+
+        let resend = |block_num, offset| SessionAction::ResendBlock {
+            block_num,
+            offset,
+            len: 2,
+        };
+        assert_eq!(
+            outputs,
+            vec![
+                vec![data(1, b"ab"), data(2, b"cd"), data(3, b"ef")],
+                vec![resend(2, 2), resend(3, 4), data(4, b"gh")],
+                vec![resend(2, 2), resend(3, 4), resend(4, 6)],
+            ]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+static USER1: AtomicBool = AtomicBool::new(false);
+static USER2: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+/// Signal `enum` lists the signals the [`Server`](crate::Server) reacts to
+/// between two packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Signal {
+    /// `SIGHUP`, reloads the manifest
+    Hangup,
+    /// `SIGUSR1`, prints the statistics
+    User1,
+    /// `SIGUSR2`, resets the peaks of the statistics
+    User2,
+    /// `SIGTERM`, stops the server after saving the metrics snapshot
+    Terminate,
+}
+
+impl Signal {
+    fn flag(self) -> &'static AtomicBool {
+        match self {
+            Signal::Hangup => &HANGUP,
+            Signal::User1 => &USER1,
+            Signal::User2 => &USER2,
+            Signal::Terminate => &TERMINATE,
+        }
+    }
+}
+
+/// Installs a handler recording `signal`, to be checked with [`take()`].
+#[cfg(target_os = "linux")]
+pub(crate) fn watch(signal: Signal) {
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn on_user1(_: libc::c_int) {
+        USER1.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn on_user2(_: libc::c_int) {
+        USER2.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn on_terminate(_: libc::c_int) {
+        TERMINATE.store(true, Ordering::Relaxed);
+    }
+
+    let (signum, handler): (libc::c_int, extern "C" fn(libc::c_int)) = match signal {
+        Signal::Hangup => (libc::SIGHUP, on_hangup),
+        Signal::User1 => (libc::SIGUSR1, on_user1),
+        Signal::User2 => (libc::SIGUSR2, on_user2),
+        Signal::Terminate => (libc::SIGTERM, on_terminate),
+    };
+    // SAFETY: the handlers only store to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(signum, handler as libc::sighandler_t);
+    }
+}
+
+/// Signals are only handled on Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn watch(_signal: Signal) {}
+
+/// Returns whether `signal` was received since the last call.
+pub(crate) fn take(signal: Signal) -> bool {
+    signal.flag().swap(false, Ordering::Relaxed)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_received_signals() {
+        watch(Signal::Hangup);
+        watch(Signal::User1);
+        assert!(!take(Signal::Hangup));
+
+        // SAFETY: raising a signal with an installed handler.
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+
+        assert!(take(Signal::Hangup));
+        assert!(!take(Signal::Hangup));
+        assert!(!take(Signal::User1));
+    }
+}
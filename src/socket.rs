@@ -0,0 +1,288 @@
+#[cfg(target_os = "linux")]
+use crate::pktinfo;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Socket `trait` abstracts the datagram operations the TFTP server
+/// performs, so that the transport can be substituted.
+///
+/// It is implemented for [`UdpSocket`] and for the fault-injecting
+/// [`FaultySocket`] used in tests.
+pub trait Socket: Send + Sync {
+    /// Sends a datagram to the supplied [`SocketAddr`].
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize>;
+
+    /// Receives a single datagram, returning its size and source.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Returns the local address of the socket.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Sets the timeout of [`Socket::recv_from()`].
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Moves the socket into or out of nonblocking mode.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Enables reporting the local address datagrams were sent to by
+    /// [`Socket::recv_with_destination()`]. Unsupported by default.
+    fn enable_destination(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "destination addresses are not available on this socket",
+        ))
+    }
+
+    /// Receives a single datagram, returning its size, source and the local
+    /// address it was sent to, if known.
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let (size, from) = self.recv_from(buf)?;
+        Ok((size, from, None))
+    }
+}
+
+impl Socket for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_destination(&self) -> io::Result<()> {
+        pktinfo::enable(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        pktinfo::recv(self, buf)
+    }
+}
+
+impl<T: Socket + ?Sized> Socket for Arc<T> {
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        (**self).send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        (**self).recv_from(buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+
+    fn enable_destination(&self) -> io::Result<()> {
+        (**self).enable_destination()
+    }
+
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        (**self).recv_with_destination(buf)
+    }
+}
+
+type Filter = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// FaultySocket `struct` wraps a [`UdpSocket`], records every datagram
+/// sent through it and can drop datagrams in either direction or corrupt
+/// outgoing ones.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::UdpSocket;
+/// use tftpd::FaultySocket;
+///
+/// let socket = FaultySocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+/// // Drop every outgoing datagram larger than 600 bytes.
+/// socket.drop_outgoing_if(|buf| buf.len() > 600);
+/// ```
+pub struct FaultySocket {
+    inner: UdpSocket,
+    sent: Mutex<Vec<(SocketAddr, Vec<u8>)>>,
+    drop_outgoing: Mutex<Option<Filter>>,
+    drop_incoming: Mutex<Option<Filter>>,
+    fail_outgoing: Mutex<Option<(Filter, io::ErrorKind)>>,
+    corrupt_outgoing: Mutex<Option<Filter>>,
+    destination: Mutex<Option<IpAddr>>,
+    source_port: Mutex<Option<u16>>,
+}
+
+impl FaultySocket {
+    /// Creates a [`FaultySocket`] which passes every datagram through.
+    pub fn new(inner: UdpSocket) -> FaultySocket {
+        FaultySocket {
+            inner,
+            sent: Mutex::new(vec![]),
+            drop_outgoing: Mutex::new(None),
+            drop_incoming: Mutex::new(None),
+            fail_outgoing: Mutex::new(None),
+            corrupt_outgoing: Mutex::new(None),
+            destination: Mutex::new(None),
+            source_port: Mutex::new(None),
+        }
+    }
+
+    /// Silently drops outgoing datagrams for which `filter` returns `true`.
+    /// Dropped datagrams are still recorded in [`FaultySocket::sent()`].
+    pub fn drop_outgoing_if<F>(&self, filter: F)
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        *self.drop_outgoing.lock().unwrap() = Some(Box::new(filter));
+    }
+
+    /// Silently drops incoming datagrams for which `filter` returns `true`.
+    pub fn drop_incoming_if<F>(&self, filter: F)
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        *self.drop_incoming.lock().unwrap() = Some(Box::new(filter));
+    }
+
+    /// Fails sending the datagrams for which `filter` returns `true` with an
+    /// error of `kind`. Failed datagrams are still recorded in
+    /// [`FaultySocket::sent()`].
+    pub fn fail_outgoing_if<F>(&self, filter: F, kind: io::ErrorKind)
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        *self.fail_outgoing.lock().unwrap() = Some((Box::new(filter), kind));
+    }
+
+    /// Flips the bits of the last byte of the outgoing datagrams for which
+    /// `filter` returns `true`. The intact datagrams are recorded in
+    /// [`FaultySocket::sent()`].
+    pub fn corrupt_outgoing_if<F>(&self, filter: F)
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        *self.corrupt_outgoing.lock().unwrap() = Some(Box::new(filter));
+    }
+
+    /// Reports `destination` as the destination address of every received
+    /// datagram, instead of the one reported by the kernel.
+    pub fn set_destination(&self, destination: IpAddr) {
+        *self.destination.lock().unwrap() = Some(destination);
+    }
+
+    /// Reports `port` as the source port of every received datagram, for
+    /// clients that cannot bind it.
+    pub fn set_source_port(&self, port: u16) {
+        *self.source_port.lock().unwrap() = Some(port);
+    }
+
+    /// Returns every datagram sent through the socket, in order.
+    pub fn sent(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Forgets the datagrams recorded so far.
+    pub fn clear_sent(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+}
+
+impl Socket for FaultySocket {
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        self.sent.lock().unwrap().push((*addr, buf.to_vec()));
+
+        if let Some((filter, kind)) = self.fail_outgoing.lock().unwrap().as_ref() {
+            if filter(buf) {
+                return Err((*kind).into());
+            }
+        }
+
+        if let Some(filter) = self.drop_outgoing.lock().unwrap().as_ref() {
+            if filter(buf) {
+                return Ok(buf.len());
+            }
+        }
+
+        if let Some(filter) = self.corrupt_outgoing.lock().unwrap().as_ref() {
+            if filter(buf) && !buf.is_empty() {
+                let mut corrupted = buf.to_vec();
+                *corrupted.last_mut().unwrap() ^= 0xFF;
+                return self.inner.send_to(&corrupted, addr);
+            }
+        }
+
+        self.inner.send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, from, _) = self.recv_with_destination(buf)?;
+        Ok((size, from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    fn enable_destination(&self) -> io::Result<()> {
+        Socket::enable_destination(&self.inner)
+    }
+
+    fn recv_with_destination(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        loop {
+            let (size, from, destination) = Socket::recv_with_destination(&self.inner, buf)?;
+            match self.drop_incoming.lock().unwrap().as_ref() {
+                Some(filter) if filter(&buf[..size]) => continue,
+                _ => {
+                    let destination = self.destination.lock().unwrap().or(destination);
+                    let mut from = from;
+                    if let Some(port) = *self.source_port.lock().unwrap() {
+                        from.set_port(port);
+                    }
+                    return Ok((size, from, destination));
+                }
+            }
+        }
+    }
+}
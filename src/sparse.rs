@@ -0,0 +1,166 @@
+use std::fs::Metadata;
+#[cfg(target_os = "linux")]
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+/// Returns the size of the blocks allocated to a sparse file, or `None`
+/// when it is not sparse or the platform does not report allocations.
+pub(crate) fn allocated_size(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // st_blocks is always counted in 512-byte units.
+        Some(metadata.blocks() * 512).filter(|&allocated| allocated < metadata.len())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// HoleReader `struct` reads a sparse file, filling its holes with zeros
+/// instead of reading them. The regions are found with `SEEK_DATA` and
+/// `SEEK_HOLE`, so the bytes are the same as those of a plain read.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub(crate) struct HoleReader {
+    file: File,
+    len: u64,
+    offset: u64,
+    /// End of the data region or hole containing `offset`
+    region_end: u64,
+    in_hole: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl HoleReader {
+    /// Creates a reader of the first `len` bytes of `file`, its length when
+    /// it was opened.
+    pub(crate) fn new(file: File, len: u64) -> HoleReader {
+        HoleReader {
+            file,
+            len,
+            offset: 0,
+            region_end: 0,
+            in_hole: false,
+        }
+    }
+
+    /// Finds the data region or hole starting at `offset`.
+    fn locate(&mut self) {
+        match seek(&self.file, self.offset, libc::SEEK_DATA) {
+            Ok(data) if data > self.offset => {
+                self.in_hole = true;
+                self.region_end = data.min(self.len);
+            }
+            Ok(_) => {
+                self.in_hole = false;
+                self.region_end = seek(&self.file, self.offset, libc::SEEK_HOLE)
+                    .map_or(self.len, |hole| hole.min(self.len));
+            }
+            // No data after the offset, the rest of the file is a hole.
+            Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+                self.in_hole = true;
+                self.region_end = self.len;
+            }
+            // Without support from the file system, read everything.
+            Err(_) => {
+                self.in_hole = false;
+                self.region_end = self.len;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Read for HoleReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        if self.offset >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.offset >= self.region_end {
+            self.locate();
+        }
+
+        // Regions may be larger than `usize` on 32-bit targets.
+        let len = (self.region_end - self.offset).min(buf.len() as u64) as usize;
+        let read = if self.in_hole {
+            buf[..len].fill(0);
+            len
+        } else {
+            self.file.read_at(&mut buf[..len], self.offset)?
+        };
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Returns the offset of the next data region or hole at or after `offset`.
+#[cfg(target_os = "linux")]
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: lseek only moves the offset of a descriptor owned by `file`,
+    // which is otherwise only read with pread.
+    let result = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as u64)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{fs, os::unix::fs::FileExt};
+
+    use super::*;
+
+    #[test]
+    fn reads_like_a_plain_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sparse.img");
+        let file = File::create(&path).unwrap();
+        file.set_len(1 << 20).unwrap();
+        file.write_all_at(&[0xAB; 5000], 0).unwrap();
+        file.write_all_at(&[0xCD; 3000], 300_000).unwrap();
+        file.write_all_at(&[0xEF; 10], (1 << 20) - 10).unwrap();
+
+        // Odd buffer sizes cross the region boundaries.
+        let mut reader = HoleReader::new(File::open(&path).unwrap(), 1 << 20);
+        let mut contents = vec![];
+        let mut buf = [0; 1468];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                read => contents.extend_from_slice(&buf[..read]),
+            }
+        }
+
+        assert_eq!(contents, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn reports_allocation_of_sparse_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sparse.img");
+        let file = File::create(&path).unwrap();
+        file.set_len(1 << 24).unwrap();
+        file.write_all_at(b"boot", 0).unwrap();
+
+        let allocated = allocated_size(&file.metadata().unwrap());
+        // File systems without holes allocate everything.
+        if let Some(allocated) = allocated {
+            assert!(allocated < 1 << 24);
+        }
+
+        fs::write(&path, [1; 8192]).unwrap();
+        assert_eq!(allocated_size(&fs::metadata(&path).unwrap()), None);
+    }
+}
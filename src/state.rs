@@ -1,72 +1,441 @@
-use std::{error::Error, fs::File, path::PathBuf};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use crate::{OptionType, TransferOption};
+use crate::event::ProgressTracker;
+use crate::negotiation::MIN_BLOCK_SIZE;
+use crate::session::Session;
+use crate::{
+    ChecksumAlgorithm, DuplicatePolicy, NegotiatedOption, OptionLimits, OptionType, QuirkSet,
+    SessionAction, TransferOption, TsizeMode,
+};
 
-pub type Chunk = Vec<u8>;
-pub type Window = Vec<Chunk>;
-
-/// Window `struct` is used to store chunks of data from a file.
-/// It is used to store the data that is being sent for Windowsize option.
-pub struct State {
-    pub(crate) file: File,
+/// State `struct` holds a transfer on the server side: the source read for
+/// its [`Session`] and the bookkeeping around it.
+pub(crate) struct State {
+    /// Data being transferred, read block by block as windows are filled.
+    pub(crate) source: Box<dyn BlockSource>,
     pub(crate) filepath: PathBuf,
+    /// Canonical path of the served file, used for its reader limit and
+    /// statistics. `None` for generated content.
+    pub(crate) reader: Option<PathBuf>,
     pub(crate) options: StateOptions,
-    pub(crate) block_number: u16,
-    pub(crate) window: Window,
-    pub(crate) finished: bool,
+    /// Distinguishes this transfer from earlier ones of the same client in
+    /// the timers.
+    pub(crate) generation: u64,
+    /// When the read request starting the transfer was accepted.
+    pub(crate) started: Instant,
+    /// Filename and options of the read request, to recognize its
+    /// retransmissions.
+    pub(crate) request: Option<(String, Vec<TransferOption>)>,
+    /// Options requested by the client and acknowledged in the OACK.
+    pub(crate) negotiated: Vec<NegotiatedOption>,
+    /// Protocol state of the transfer, driven by the server.
+    pub(crate) session: Session,
+    /// Size of the transfer, if known.
+    pub(crate) size: Option<u64>,
+    /// Size allocated on disk, when the served file is sparse.
+    pub(crate) allocated: Option<u64>,
+    pub(crate) progress: ProgressTracker,
+    /// Number of consecutive sends that failed because the client could
+    /// not be reached.
+    pub(crate) unreachable_sends: u32,
+    /// Time spent in filesystem calls for the transfer.
+    pub(crate) storage_time: Duration,
+    /// Time spent waiting for the client to acknowledge what was sent.
+    pub(crate) network_wait: Duration,
+    /// When the server last sent something the client has not answered
+    /// yet.
+    pub(crate) awaiting_since: Option<Instant>,
+    /// When the client last sent a packet for the transfer, or when the
+    /// transfer started.
+    pub(crate) last_activity: Instant,
+    /// First actions of the session, held back until its
+    /// `--initial-delay` elapsed.
+    pub(crate) deferred: Option<Vec<SessionAction>>,
+    /// ID of the journey of the client host.
+    pub(crate) journey: u64,
+    /// Compatibility workarounds of the `--quirk`s matching the request.
+    pub(crate) quirks: QuirkSet,
 }
 
-// const MAX_RETRIES: u32 = 6;
-const DEFAULT_TIMEOUT_SECS: u64 = 5;
+pub(crate) const MAX_RETRIES: u32 = 6;
+/// Number of consecutive ACKs for a block more than a window back after
+/// which the client is taken to have restarted the transfer.
+pub(crate) const RESTART_ACKS: u32 = 3;
+/// Number of consecutive sends failing with an unreachable client after
+/// which its transfer is failed, instead of retrying until the timeout.
+pub(crate) const MAX_UNREACHABLE_SENDS: u32 = 3;
+/// Retransmission timeout when neither the client nor the server
+/// configuration sets one.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 // const TIMEOUT_BUFFER_SECS: u64 = 1;
-const DEFAULT_BLOCK_SIZE: usize = 512;
+pub(crate) const DEFAULT_BLOCK_SIZE: usize = 512;
 
+/// StateOptions `struct` holds the transfer parameters negotiated from the
+/// options of a read request.
 #[derive(Debug, PartialEq, Eq)]
 pub struct StateOptions {
+    /// Number of data bytes in a full block, from the `blksize` option
     pub blk_size: usize,
-    pub t_size: usize,
-    pub timeout: u64,
+    /// Size of the file, acknowledged for the `tsize` option
+    pub t_size: u64,
+    /// Retransmission timeout, from the `timeout` option
+    pub timeout: Duration,
+    /// Number of blocks sent before waiting for an ACK, from the
+    /// `windowsize` option
     pub windowsize: u16,
+    /// Digest sent after the last block, from the `xsum` option
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Block number following 65535, from the `rollover` option
+    pub rollover: Option<u16>,
+    /// Repeated options ignored with [`DuplicatePolicy::First`]
+    pub duplicates: Vec<TransferOption>,
 }
 
-pub fn parse_options(
+/// Negotiates the requested `options` within the `limits`, rewriting them
+/// into the values to acknowledge in the OACK. The transfer size option is
+/// left out when the `file_size` is unknown.
+///
+/// Repeated options are rejected or ignored depending on
+/// [`OptionLimits::duplicates`], the ignored ones are returned in
+/// [`StateOptions::duplicates`].
+pub(crate) fn parse_options(
     options: &mut Vec<TransferOption>,
-    file_size: usize,
+    file_size: Option<u64>,
+    default_timeout: Duration,
+    limits: &OptionLimits,
 ) -> Result<StateOptions, Box<dyn Error>> {
     let mut state_options = StateOptions {
         blk_size: DEFAULT_BLOCK_SIZE,
-        t_size: 0,
-        timeout: DEFAULT_TIMEOUT_SECS,
+        t_size: file_size.unwrap_or(0),
+        timeout: default_timeout,
         windowsize: 1,
+        checksum: None,
+        rollover: None,
+        duplicates: vec![],
     };
 
-    for option in &mut *options {
-        let TransferOption { option, value } = option;
+    let size: usize = options.iter().map(|option| option.as_bytes().len()).sum();
+    if size > limits.max_options_bytes {
+        return Err(format!(
+            "Options of {size} bytes exceed the limit of {} bytes",
+            limits.max_options_bytes
+        )
+        .into());
+    }
+
+    let mut seen = Vec::with_capacity(options.len());
+    let mut acknowledged = Vec::with_capacity(options.len());
+    for TransferOption { option, value } in options.drain(..) {
+        if seen.contains(&option) {
+            match limits.duplicates {
+                DuplicatePolicy::Reject => {
+                    return Err(format!("Duplicate {} option", option.as_str()).into())
+                }
+                DuplicatePolicy::First => {
+                    state_options
+                        .duplicates
+                        .push(TransferOption { option, value });
+                    continue;
+                }
+            }
+        }
+        seen.push(option);
+
+        if !limits.allows(option) {
+            continue;
+        }
 
-        match option {
-            OptionType::BlockSize => state_options.blk_size = *value,
-            OptionType::TransferSize => {
-                *value = file_size;
-                state_options.t_size = file_size;
+        let value = match option {
+            OptionType::BlockSize => {
+                // Below the range of RFC 2348, the default block size is
+                // kept rather than failing the request.
+                if value < MIN_BLOCK_SIZE as u64 {
+                    continue;
+                }
+                state_options.blk_size = value.min(limits.max_blksize as u64) as usize;
+                state_options.blk_size as u64
             }
+            OptionType::TransferSize => match (limits.tsize, file_size) {
+                (TsizeMode::Omit, _) | (_, None) => continue,
+                (TsizeMode::Zero, Some(_)) => 0,
+                (TsizeMode::Echo, Some(size)) => size,
+            },
             OptionType::Timeout => {
-                if *value == 0 {
-                    return Err("Invalid timeout value".into());
+                // Zero and values outside of the configured range keep
+                // the server timeout, like a blksize below the minimum.
+                if value == 0 || !(limits.min_timeout..=limits.max_timeout).contains(&value) {
+                    continue;
                 }
-                state_options.timeout = *value as u64;
+                state_options.timeout = Duration::from_secs(value);
+                value
             }
             OptionType::Windowsize => {
-                if *value == 0 || *value > u16::MAX as usize {
-                    return Err("Invalid windowsize value".into());
+                if value == 0 || value > u16::MAX as u64 {
+                    continue;
                 }
-                state_options.windowsize = *value as u16;
+                state_options.windowsize = value as u16;
+                value
             }
+            // Only ever sent by the server.
+            OptionType::ServerNote => continue,
+            OptionType::Checksum => match ChecksumAlgorithm::from_value(value) {
+                Some(algorithm) => {
+                    state_options.checksum = Some(algorithm);
+                    value
+                }
+                None => continue,
+            },
+            OptionType::Rollover => match value {
+                0 | 1 => {
+                    state_options.rollover = Some(value as u16);
+                    value
+                }
+                _ => continue,
+            },
+        };
+        acknowledged.push(TransferOption { option, value });
+    }
+
+    // The byte limit depends on the block size, which may come after the
+    // window size in the request.
+    let max_windowsize = limits.max_windowsize_for(state_options.blk_size);
+    if state_options.windowsize > max_windowsize {
+        state_options.windowsize = max_windowsize;
+        set_acknowledged(
+            &mut acknowledged,
+            OptionType::Windowsize,
+            max_windowsize as u64,
+        );
+    }
+
+    // Every timeout sends the whole window again, stretch the timeout then
+    // shrink the window until that fits the retransmit rate.
+    if let Some(rate) = limits.max_retransmit_rate {
+        let window_bytes = state_options.blk_size as u64 * state_options.windowsize as u64;
+        let negotiated_timeout = acknowledged
+            .iter()
+            .any(|option| option.option == OptionType::Timeout);
+        if negotiated_timeout && window_bytes > rate * state_options.timeout.as_secs() {
+            let timeout = window_bytes.div_ceil(rate).min(limits.max_timeout);
+            state_options.timeout = Duration::from_secs(timeout);
+            set_acknowledged(&mut acknowledged, OptionType::Timeout, timeout);
+        }
+        let fitting_windows =
+            rate as f64 * state_options.timeout.as_secs_f64() / state_options.blk_size as f64;
+        if (state_options.windowsize as f64) > fitting_windows {
+            state_options.windowsize = (fitting_windows as u16).max(1);
+            set_acknowledged(
+                &mut acknowledged,
+                OptionType::Windowsize,
+                state_options.windowsize as u64,
+            );
         }
     }
 
+    *options = acknowledged;
     Ok(state_options)
 }
 
+/// Replaces the value acknowledged for `option`, if acknowledged at all.
+fn set_acknowledged(acknowledged: &mut [TransferOption], option: OptionType, value: u64) {
+    for acknowledged in acknowledged.iter_mut() {
+        if acknowledged.option == option {
+            acknowledged.value = value;
+        }
+    }
+}
+
+/// Returns the number of DATA packets sending `size` bytes in blocks of
+/// `blk_size` bytes takes, including the short block ending the transfer.
+pub(crate) fn block_count(size: u64, blk_size: usize) -> u64 {
+    size / blk_size as u64 + 1
+}
+
+/// BlockSource `trait` produces the bytes of a transfer for the blocks its
+/// [`Session`] asks for, by offset in the transferred data.
+pub(crate) trait BlockSource: Send {
+    /// Reads up to `len` bytes at `offset`, shorter only at the end.
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Returns whether blocks before the last one read can be read again,
+    /// to send them again rather than keep them in memory, and to resume a
+    /// client that restarted the transfer.
+    fn rereadable(&self) -> bool {
+        false
+    }
+}
+
+/// Sequential `struct` reads the blocks of a source which can only be read
+/// in order, like a pipe, a decompressor or a netascii translation.
+pub(crate) struct Sequential {
+    inner: Box<dyn Read + Send>,
+    /// Offset of the next byte of `inner`
+    position: u64,
+}
+
+impl Sequential {
+    pub(crate) fn new(inner: Box<dyn Read + Send>) -> Sequential {
+        Sequential { inner, position: 0 }
+    }
+}
+
+impl BlockSource for Sequential {
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if offset != self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "cannot read at {offset}, the source is at {}",
+                    self.position
+                ),
+            ));
+        }
+        let data = read_full(&mut *self.inner, len)?;
+        self.position += data.len() as u64;
+        Ok(data)
+    }
+}
+
+/// Seeking `struct` reads the blocks of a plain file at any offset, seeking
+/// only when a block does not follow the previous one.
+pub(crate) struct Seeking {
+    file: File,
+    /// Offset of the file, unknown after a failed read
+    position: Option<u64>,
+}
+
+impl Seeking {
+    pub(crate) fn new(file: File) -> Seeking {
+        Seeking {
+            file,
+            position: None,
+        }
+    }
+}
+
+impl BlockSource for Seeking {
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if self.position != Some(offset) {
+            self.file.seek(SeekFrom::Start(offset))?;
+        }
+        self.position = None;
+        let data = read_full(&mut self.file, len)?;
+        self.position = Some(offset + data.len() as u64);
+        Ok(data)
+    }
+
+    fn rereadable(&self) -> bool {
+        true
+    }
+}
+
+/// Mapped `struct` reads the blocks of a plain file from a read-only
+/// memory mapping of it, with the `mmap` feature.
+///
+/// Truncating the file while it is mapped makes reading past its new end
+/// crash the server with `SIGBUS`, serve files which are only ever
+/// replaced.
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub(crate) struct Mapped {
+    address: *mut libc::c_void,
+    len: usize,
+}
+
+// SAFETY: the mapping is private to the `Mapped` owning it and only read.
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+unsafe impl Send for Mapped {}
+
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+impl Mapped {
+    /// Maps the `len` bytes of `file`, which must not be empty.
+    pub(crate) fn new(file: &File, len: u64) -> io::Result<Mapped> {
+        use std::os::fd::AsRawFd;
+        use std::ptr;
+
+        let len = usize::try_from(len).map_err(|_| io::ErrorKind::InvalidInput)?;
+        // SAFETY: a new read-only mapping of a valid descriptor, which
+        // outlives closing it.
+        let address = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapped { address, len })
+    }
+}
+
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+impl BlockSource for Mapped {
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        // SAFETY: the mapping is `self.len` bytes long until dropped.
+        let mapped = unsafe { std::slice::from_raw_parts(self.address as *const u8, self.len) };
+        let start = offset.min(self.len as u64) as usize;
+        let end = start + len.min(self.len - start);
+        Ok(mapped[start..end].to_vec())
+    }
+
+    fn rereadable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        // SAFETY: unmaps the mapping created in `new`, no slice of it
+        // outlives `read_block`.
+        unsafe { libc::munmap(self.address, self.len) };
+    }
+}
+
+/// Returns the source of a plain `file` of `size` bytes sent as is, which
+/// is mapped in memory with the `mmap` feature.
+#[cfg_attr(
+    not(all(feature = "mmap", target_os = "linux")),
+    allow(unused_variables)
+)]
+pub(crate) fn file_source(file: File, size: u64) -> Box<dyn BlockSource> {
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    if size > 0 {
+        if let Ok(mapped) = Mapped::new(&file, size) {
+            return Box::new(mapped);
+        }
+    }
+    Box::new(Seeking::new(file))
+}
+
+/// Reads up to `len` bytes from `source`, shorter only at the end.
+pub(crate) fn read_full(source: &mut dyn Read, len: usize) -> io::Result<Vec<u8>> {
+    // Sources like decompressors may return less than asked for before the
+    // end, so only a read of 0 bytes marks the end.
+    let mut buf = vec![0; len];
+    let mut read = 0;
+    while read < len {
+        match source.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(size) => read += size,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    buf.truncate(read);
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,23 +457,471 @@ mod tests {
             },
         ];
 
-        let worker_options = parse_options(&mut options, 12345).unwrap();
+        let worker_options = parse_options(
+            &mut options,
+            Some(12345),
+            Duration::from_millis(200),
+            &OptionLimits::default(),
+        )
+        .unwrap();
 
-        assert_eq!(options[0].value, worker_options.blk_size);
+        assert_eq!(options[0].value, worker_options.blk_size as u64);
         assert_eq!(12345, worker_options.t_size);
-        assert_eq!(options[2].value as u64, worker_options.timeout);
+        assert_eq!(Duration::from_secs(5), worker_options.timeout);
+    }
+
+    #[test]
+    fn echoes_sizes_beyond_32_bits() {
+        let size = (5 << 32) + 7;
+        let mut options = vec![TransferOption {
+            option: OptionType::TransferSize,
+            value: 0,
+        }];
+
+        let state_options = parse_options(
+            &mut options,
+            Some(size),
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(options[0].value, size);
+        assert_eq!(state_options.t_size, size);
+    }
+
+    /// Returns a file holding `contents`, and `contents`.
+    fn file_of(size: usize) -> (File, Vec<u8>) {
+        use std::io::Write;
+
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&contents).unwrap();
+        (file, contents)
+    }
+
+    #[test]
+    fn reads_file_blocks_at_any_offset() {
+        let (file, contents) = file_of(1000);
+        let mut source = Seeking::new(file);
+
+        assert_eq!(source.read_block(0, 512).unwrap(), contents[..512]);
+        assert_eq!(source.read_block(512, 512).unwrap(), contents[512..]);
+        assert_eq!(source.read_block(100, 10).unwrap(), contents[100..110]);
+        assert!(source.read_block(1000, 512).unwrap().is_empty());
+        assert!(source.rereadable());
+    }
+
+    #[test]
+    fn reads_sequential_source_in_order_only() {
+        let contents: Vec<u8> = (0..20).collect();
+        let mut source = Sequential::new(Box::new(io::Cursor::new(contents.clone())));
+
+        assert_eq!(source.read_block(0, 8).unwrap(), contents[..8]);
+        assert_eq!(
+            source.read_block(0, 8).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(source.read_block(8, 16).unwrap(), contents[8..]);
+        assert!(!source.rereadable());
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn reads_mapped_file_blocks() {
+        let (file, contents) = file_of(1000);
+        let mut source = Mapped::new(&file, 1000).unwrap();
+        drop(file);
+
+        assert_eq!(source.read_block(512, 512).unwrap(), contents[512..]);
+        assert_eq!(source.read_block(0, 512).unwrap(), contents[..512]);
+        assert!(source.read_block(1000, 512).unwrap().is_empty());
+        assert!(source.read_block(5000, 512).unwrap().is_empty());
+    }
+
+    #[test]
+    fn counts_blocks_of_large_files() {
+        assert_eq!(block_count(0, 512), 1);
+        assert_eq!(block_count(1023, 512), 2);
+        assert_eq!(block_count(1024, 512), 3);
+        assert_eq!(block_count(65535 * 512 - 1, 512), 65535);
+        assert_eq!(block_count(65535 * 512, 512), 65536);
+        assert_eq!(block_count(5 << 32, 65464), (5 << 32) / 65464 + 1);
+    }
+
+    #[test]
+    fn applies_tsize_mode() {
+        let requested = vec![
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            },
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            },
+        ];
+
+        let limits = |tsize| OptionLimits {
+            tsize,
+            ..OptionLimits::default()
+        };
+
+        let mut options = requested.clone();
+        parse_options(
+            &mut options,
+            Some(4096),
+            DEFAULT_TIMEOUT,
+            &limits(TsizeMode::Zero),
+        )
+        .unwrap();
+        assert_eq!(options[0].value, 0);
+
+        let mut options = requested.clone();
+        parse_options(
+            &mut options,
+            Some(4096),
+            DEFAULT_TIMEOUT,
+            &limits(TsizeMode::Omit),
+        )
+        .unwrap();
+        assert_eq!(options, requested[1..]);
+
+        let mut options = requested.clone();
+        parse_options(
+            &mut options,
+            None,
+            DEFAULT_TIMEOUT,
+            &limits(TsizeMode::Echo),
+        )
+        .unwrap();
+        assert_eq!(options, requested[1..]);
     }
 
     #[test]
     fn parses_default_options() {
         assert_eq!(
-            parse_options(&mut vec![], 12345678).unwrap(),
+            parse_options(
+                &mut vec![],
+                Some(12345678),
+                DEFAULT_TIMEOUT,
+                &OptionLimits::default()
+            )
+            .unwrap(),
             StateOptions {
                 blk_size: DEFAULT_BLOCK_SIZE,
                 t_size: 12345678,
-                timeout: DEFAULT_TIMEOUT_SECS,
+                timeout: DEFAULT_TIMEOUT,
                 windowsize: 1,
+                checksum: None,
+                rollover: None,
+                duplicates: vec![],
             }
         );
     }
+
+    #[test]
+    fn negotiates_within_limits() {
+        let option = |option, value| TransferOption { option, value };
+        let requested = vec![
+            option(OptionType::BlockSize, 9000),
+            option(OptionType::TransferSize, 0),
+            option(OptionType::Timeout, 2),
+            option(OptionType::Windowsize, 64),
+        ];
+        let default = OptionLimits::default();
+        let cases = [
+            (
+                "defaults",
+                default.clone(),
+                vec![9000, 4096, 2, 64],
+                (9000, Duration::from_secs(2), 64),
+            ),
+            (
+                "clamped block size",
+                OptionLimits {
+                    max_blksize: 1468,
+                    ..default.clone()
+                },
+                vec![1468, 4096, 2, 64],
+                (1468, Duration::from_secs(2), 64),
+            ),
+            (
+                "window bytes",
+                OptionLimits {
+                    max_window_bytes: Some(65536),
+                    ..default.clone()
+                },
+                vec![9000, 4096, 2, 7],
+                (9000, Duration::from_secs(2), 7),
+            ),
+            (
+                "window bytes below one block",
+                OptionLimits {
+                    max_window_bytes: Some(1024),
+                    max_windowsize: 16,
+                    ..default.clone()
+                },
+                vec![9000, 4096, 2, 1],
+                (9000, Duration::from_secs(2), 1),
+            ),
+            (
+                "short timeout",
+                OptionLimits {
+                    min_timeout: 3,
+                    ..default.clone()
+                },
+                vec![9000, 4096, 64],
+                (9000, DEFAULT_TIMEOUT, 64),
+            ),
+            (
+                "disabled options",
+                OptionLimits {
+                    disabled: vec![OptionType::BlockSize, OptionType::Windowsize],
+                    ..default.clone()
+                },
+                vec![4096, 2],
+                (DEFAULT_BLOCK_SIZE, Duration::from_secs(2), 1),
+            ),
+            (
+                "retransmit rate stretching timeout",
+                OptionLimits {
+                    max_retransmit_rate: Some(100_000),
+                    ..default.clone()
+                },
+                vec![9000, 4096, 6, 64],
+                (9000, Duration::from_secs(6), 64),
+            ),
+            (
+                "retransmit rate shrinking window",
+                OptionLimits {
+                    max_retransmit_rate: Some(100_000),
+                    max_timeout: 4,
+                    ..default.clone()
+                },
+                vec![9000, 4096, 4, 44],
+                (9000, Duration::from_secs(4), 44),
+            ),
+            (
+                "retransmit rate without timeout",
+                OptionLimits {
+                    max_retransmit_rate: Some(100_000),
+                    min_timeout: 3,
+                    ..default.clone()
+                },
+                vec![9000, 4096, 55],
+                (9000, DEFAULT_TIMEOUT, 55),
+            ),
+        ];
+
+        for (name, limits, values, (blk_size, timeout, windowsize)) in cases {
+            let mut options = requested.clone();
+            let state_options =
+                parse_options(&mut options, Some(4096), DEFAULT_TIMEOUT, &limits).unwrap();
+            let acknowledged: Vec<u64> = options.iter().map(|option| option.value).collect();
+            assert_eq!(acknowledged, values, "{name}");
+            assert_eq!(
+                (
+                    state_options.blk_size,
+                    state_options.timeout,
+                    state_options.windowsize
+                ),
+                (blk_size, timeout, windowsize),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn guards_retransmit_rate() {
+        let option = |option, value| TransferOption { option, value };
+        let requested = vec![
+            option(OptionType::BlockSize, 65464),
+            option(OptionType::Timeout, 1),
+            option(OptionType::Windowsize, 64),
+        ];
+
+        let mut options = requested.clone();
+        parse_options(
+            &mut options,
+            None,
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(options, requested);
+
+        let limits = OptionLimits {
+            max_retransmit_rate: Some(1_000_000),
+            ..OptionLimits::default()
+        };
+        let mut options = requested.clone();
+        let state_options = parse_options(&mut options, None, DEFAULT_TIMEOUT, &limits).unwrap();
+        assert_eq!(
+            options,
+            [
+                option(OptionType::BlockSize, 65464),
+                option(OptionType::Timeout, 5),
+                option(OptionType::Windowsize, 64),
+            ]
+        );
+        assert_eq!(state_options.timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn drops_blksize_below_range() {
+        let mut options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 4,
+            },
+            TransferOption {
+                option: OptionType::Timeout,
+                value: 3,
+            },
+        ];
+
+        let state_options = parse_options(
+            &mut options,
+            Some(0),
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(state_options.blk_size, DEFAULT_BLOCK_SIZE);
+        assert_eq!(
+            options,
+            [TransferOption {
+                option: OptionType::Timeout,
+                value: 3,
+            }]
+        );
+    }
+
+    /// Parses `options` after a blksize of 1024 with the default limits,
+    /// returning the acknowledged options and the parsed values.
+    fn parse_after_blksize(options: Vec<TransferOption>) -> (Vec<TransferOption>, StateOptions) {
+        let mut options = [
+            vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            }],
+            options,
+        ]
+        .concat();
+        let state_options = parse_options(
+            &mut options,
+            Some(0),
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )
+        .unwrap();
+        (options, state_options)
+    }
+
+    #[test]
+    fn drops_zero_timeout() {
+        let (options, state_options) = parse_after_blksize(vec![TransferOption {
+            option: OptionType::Timeout,
+            value: 0,
+        }]);
+
+        assert_eq!(state_options.timeout, DEFAULT_TIMEOUT);
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].option, OptionType::BlockSize);
+    }
+
+    #[test]
+    fn drops_zero_windowsize() {
+        let (options, state_options) = parse_after_blksize(vec![TransferOption {
+            option: OptionType::Windowsize,
+            value: 0,
+        }]);
+
+        assert_eq!(state_options.windowsize, 1);
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].option, OptionType::BlockSize);
+    }
+
+    #[test]
+    fn drops_windowsize_above_range() {
+        let (options, state_options) = parse_after_blksize(vec![TransferOption {
+            option: OptionType::Windowsize,
+            value: 70000,
+        }]);
+
+        assert_eq!(state_options.windowsize, 1);
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].option, OptionType::BlockSize);
+    }
+
+    #[test]
+    fn applies_duplicate_policy() {
+        let option = |option, value| TransferOption { option, value };
+        let requested = vec![
+            option(OptionType::BlockSize, 512),
+            option(OptionType::Windowsize, 4),
+            option(OptionType::BlockSize, 8192),
+            option(OptionType::BlockSize, 16),
+        ];
+
+        let mut options = requested.clone();
+        let state_options = parse_options(
+            &mut options,
+            Some(0),
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(options, requested[..2]);
+        assert_eq!(state_options.blk_size, 512);
+        assert_eq!(state_options.duplicates, requested[2..]);
+
+        // The first occurrence wins even when it is disabled.
+        let limits = OptionLimits {
+            disabled: vec![OptionType::BlockSize],
+            ..OptionLimits::default()
+        };
+        let mut options = requested.clone();
+        let state_options = parse_options(&mut options, Some(0), DEFAULT_TIMEOUT, &limits).unwrap();
+        assert_eq!(options, requested[1..2]);
+        assert_eq!(state_options.blk_size, DEFAULT_BLOCK_SIZE);
+
+        let limits = OptionLimits {
+            duplicates: DuplicatePolicy::Reject,
+            ..OptionLimits::default()
+        };
+        assert!(parse_options(&mut requested.clone(), Some(0), DEFAULT_TIMEOUT, &limits).is_err());
+        assert!(parse_options(
+            &mut requested[..2].to_vec(),
+            Some(0),
+            DEFAULT_TIMEOUT,
+            &limits
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn limits_options_size() {
+        let limits = OptionLimits {
+            max_options_bytes: 24,
+            ..OptionLimits::default()
+        };
+        // "blksize\01024\0" and "timeout\05\0" take 13 and 10 bytes.
+        let mut options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            },
+            TransferOption {
+                option: OptionType::Timeout,
+                value: 5,
+            },
+        ];
+        assert!(parse_options(&mut options.clone(), Some(0), DEFAULT_TIMEOUT, &limits).is_ok());
+
+        options[1].value = 255;
+        assert!(parse_options(&mut options, Some(0), DEFAULT_TIMEOUT, &limits).is_err());
+    }
 }
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, time::SystemTime};
+
+/// Number of files tracked before the least recently requested one is
+/// forgotten.
+pub(crate) const MAX_TRACKED_FILES: usize = 10_000;
+
+/// FileStats `struct` holds the popularity counters of a served file,
+/// returned by [`Server::file_stats()`](crate::Server::file_stats).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileStats {
+    /// Number of transfers started
+    pub requests: u64,
+    /// Number of transfers that were fully acknowledged
+    pub completed: u64,
+    /// Number of bytes acknowledged by the clients
+    pub bytes_served: u64,
+    /// When a transfer of the file last completed
+    pub last_served: Option<SystemTime>,
+}
+
+/// Keeps the [`FileStats`] of the most recently requested files, keyed by
+/// their path relative to the served directory.
+#[derive(Debug)]
+pub(crate) struct FileStatsMap {
+    capacity: usize,
+    /// Counters and the tick of the last request of every file
+    entries: HashMap<String, (FileStats, u64)>,
+    tick: u64,
+}
+
+impl FileStatsMap {
+    pub(crate) fn new(capacity: usize) -> FileStatsMap {
+        FileStatsMap {
+            capacity,
+            entries: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Counts a started transfer of `file`, forgetting the least recently
+    /// requested file when the map is full.
+    pub(crate) fn record_request(&mut self, file: &str) {
+        self.tick += 1;
+        if !self.entries.contains_key(file) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, tick))| *tick)
+                .map(|(file, _)| file.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        let (stats, tick) = self.entries.entry(file.to_string()).or_default();
+        stats.requests += 1;
+        *tick = self.tick;
+    }
+
+    /// Counts the bytes of a finished transfer of `file`, and the transfer
+    /// itself if it `completed`.
+    pub(crate) fn record_end(&mut self, file: &str, bytes: u64, completed: bool) {
+        if let Some((stats, _)) = self.entries.get_mut(file) {
+            stats.bytes_served += bytes;
+            if completed {
+                stats.completed += 1;
+                stats.last_served = Some(SystemTime::now());
+            }
+        }
+    }
+
+    /// Sets the counters of `file` to `stats`, as read from a snapshot.
+    pub(crate) fn restore(&mut self, file: String, stats: FileStats) {
+        if self.entries.len() < self.capacity {
+            self.tick += 1;
+            self.entries.insert(file, (stats, self.tick));
+        }
+    }
+
+    /// Returns the counters of every tracked file, most requested first.
+    pub(crate) fn sorted(&self) -> Vec<(String, FileStats)> {
+        let mut stats: Vec<(String, FileStats)> = self
+            .entries
+            .iter()
+            .map(|(file, (stats, _))| (file.clone(), *stats))
+            .collect();
+        stats.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(&b.0)));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_requests_and_completions() {
+        let mut map = FileStatsMap::new(10);
+        map.record_request("a");
+        map.record_request("b");
+        map.record_request("b");
+        map.record_end("b", 100, true);
+        map.record_end("b", 40, false);
+
+        let stats = map.sorted();
+        assert_eq!(stats[0].0, "b");
+        assert_eq!(stats[0].1.requests, 2);
+        assert_eq!(stats[0].1.completed, 1);
+        assert_eq!(stats[0].1.bytes_served, 140);
+        assert!(stats[0].1.last_served.is_some());
+        assert_eq!(stats[1].0, "a");
+        assert_eq!(stats[1].1.last_served, None);
+    }
+
+    #[test]
+    fn forgets_least_recently_requested_file() {
+        let mut map = FileStatsMap::new(2);
+        map.record_request("a");
+        map.record_request("b");
+        map.record_request("a");
+        map.record_request("c");
+
+        let files: Vec<String> = map.sorted().into_iter().map(|(file, _)| file).collect();
+        assert_eq!(files, vec!["a".to_string(), "c".to_string()]);
+    }
+}
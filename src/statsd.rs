@@ -0,0 +1,211 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::logger::elogln;
+use crate::MetricsSnapshot;
+
+/// Interval between two flushes of the metrics.
+pub(crate) const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Maximum payload of a statsd datagram, to stay below common MTUs.
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+/// Statsd `struct` pushes the server metrics to a statsd agent over UDP.
+///
+/// Counters are sent as the increase since the previous flush, gauges as
+/// their current value. Send failures are ignored.
+#[derive(Debug)]
+pub(crate) struct Statsd {
+    socket: UdpSocket,
+    target: SocketAddr,
+    prefix: String,
+    /// Tags appended in the DogStatsD `|#tag,tag` format, if any
+    tags: String,
+    last: MetricsSnapshot,
+    last_flush: Option<Instant>,
+}
+
+impl Statsd {
+    /// Resolves `target` and opens the socket sending the metrics.
+    pub(crate) fn new(target: &str, prefix: &str, tags: &[String]) -> io::Result<Statsd> {
+        let target = target.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {target}"))
+        })?;
+        let socket = match target {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+        };
+        socket.set_nonblocking(true)?;
+
+        Ok(Statsd {
+            socket,
+            target,
+            prefix: prefix.to_string(),
+            tags: if tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", tags.join(","))
+            },
+            last: MetricsSnapshot::default(),
+            last_flush: None,
+        })
+    }
+
+    /// Returns whether the metrics are due to be flushed, which they are
+    /// right away before the first flush.
+    pub(crate) fn due(&self, now: Instant) -> bool {
+        self.last_flush
+            .is_none_or(|last_flush| now.duration_since(last_flush) >= FLUSH_INTERVAL)
+    }
+
+    /// Takes `snapshot` as the counters already sent, so that the next
+    /// flush only sends the increase since.
+    pub(crate) fn resume_from(&mut self, snapshot: MetricsSnapshot) {
+        self.last = snapshot;
+    }
+
+    /// Sends the metrics of `snapshot` and the number of active sessions.
+    pub(crate) fn flush(&mut self, now: Instant, snapshot: MetricsSnapshot, sessions: usize) {
+        let mut lines = vec![];
+        for ((name, value), (_, last)) in snapshot.counters().into_iter().zip(self.last.counters())
+        {
+            lines.push(self.line(name, value.saturating_sub(last), "c"));
+        }
+        for ((name, value), (_, last)) in snapshot
+            .tick_counters()
+            .into_iter()
+            .zip(self.last.tick_counters())
+        {
+            lines.push(self.line(name, value.saturating_sub(last), "c"));
+        }
+        for ((name, value), (_, last)) in snapshot
+            .latency_counters()
+            .into_iter()
+            .zip(self.last.latency_counters())
+        {
+            lines.push(self.line(name, value.saturating_sub(last), "c"));
+        }
+        for ((name, value), (_, last)) in snapshot
+            .option_counters()
+            .into_iter()
+            .zip(self.last.option_counters())
+        {
+            lines.push(self.line(&name, value.saturating_sub(last), "c"));
+        }
+        for (name, value) in snapshot.gauges() {
+            lines.push(self.line(name, value, "g"));
+        }
+        lines.push(self.line("active_sessions", sessions as u64, "g"));
+        lines.push(self.build_info_line());
+
+        for datagram in batch(&lines) {
+            if let Err(err) = self.socket.send_to(datagram.as_bytes(), self.target) {
+                elogln!("Error while sending metrics to {}: {err}", self.target);
+                break;
+            }
+        }
+
+        self.last = snapshot;
+        self.last_flush = Some(now);
+    }
+
+    fn line(&self, name: &str, value: u64, kind: &str) -> String {
+        format!("{}.{name}:{value}|{kind}{}", self.prefix, self.tags)
+    }
+
+    /// Returns the `build_info` gauge, always 1, tagged with the version.
+    fn build_info_line(&self) -> String {
+        let separator = if self.tags.is_empty() { "|#" } else { "," };
+        format!(
+            "{}.build_info:1|g{}{separator}version:{}",
+            self.prefix,
+            self.tags,
+            crate::build_info().version
+        )
+    }
+}
+
+/// Joins `lines` with newlines into datagrams of at most
+/// `MAX_DATAGRAM_SIZE` bytes.
+fn batch(lines: &[String]) -> Vec<String> {
+    let mut datagrams = vec![];
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM_SIZE {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_lines_below_datagram_size() {
+        let lines: Vec<String> = (0..100)
+            .map(|i| format!("tftpd.metric{i:03}:1|c"))
+            .collect();
+
+        let datagrams = batch(&lines);
+
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM_SIZE));
+        assert_eq!(datagrams.join("\n"), lines.join("\n"));
+    }
+
+    #[test]
+    fn sends_counter_increases() {
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sink.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let now = Instant::now();
+        let mut statsd = Statsd::new(
+            &sink.local_addr().unwrap().to_string(),
+            "tftpd",
+            &["instance:a".to_string()],
+        )
+        .unwrap();
+
+        let mut snapshot = MetricsSnapshot {
+            requests: 3,
+            ..MetricsSnapshot::default()
+        };
+        statsd.flush(now, snapshot, 2);
+        snapshot.requests = 5;
+        statsd.flush(now, snapshot, 0);
+
+        let first = recv_flush(&sink);
+        assert!(first.contains("tftpd.requests:3|c|#instance:a"));
+        assert!(first.contains("tftpd.active_sessions:2|g|#instance:a"));
+        assert!(first.contains(&format!(
+            "tftpd.build_info:1|g|#instance:a,version:{}",
+            env!("CARGO_PKG_VERSION")
+        )));
+
+        let second = recv_flush(&sink);
+        assert!(second.contains("tftpd.requests:2|c|#instance:a"));
+    }
+
+    /// Receives the datagrams of a flush, which ends with the build info.
+    fn recv_flush(sink: &UdpSocket) -> String {
+        let mut buf = [0; 2048];
+        let mut flush = String::new();
+        while !flush.contains("build_info") {
+            let size = sink.recv(&mut buf).unwrap();
+            flush.push_str(std::str::from_utf8(&buf[..size]).unwrap());
+            flush.push('\n');
+        }
+        flush
+    }
+}
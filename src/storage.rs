@@ -0,0 +1,423 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+    process,
+    time::{Duration, Instant},
+};
+
+use crate::logger::elogln;
+use crate::metrics::Metrics;
+use crate::state::BlockSource;
+use crate::{Clock, TftpError};
+
+/// Latency of the probe above which a warning is logged even without
+/// `--require-fast-storage`.
+const SLOW_STORAGE: Duration = Duration::from_millis(100);
+/// Bytes read from an existing file when the directory is not writable.
+const PROBE_READ_SIZE: usize = 4096;
+const PROBE_CONTENTS: &[u8] = b"tftpd storage probe";
+
+/// StorageProbe `struct` is what the startup probe found out about the
+/// filesystem of the served directory. Filesystems like FUSE mounts of
+/// object stores answer slowly and do not behave like local disks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageProbe {
+    /// Time taken to open and read a file of the directory, or to list it
+    /// when it has no file
+    pub latency: Duration,
+    /// Whether names differing only in case open the same file, if it
+    /// could be found out
+    pub case_insensitive: Option<bool>,
+    /// Whether the size in the metadata of a file matches its contents, if
+    /// it could be found out
+    pub stable_sizes: Option<bool>,
+}
+
+impl StorageProbe {
+    /// Returns the findings worth a warning, slow meaning above `threshold`
+    /// or 100 ms.
+    pub fn warnings(&self, threshold: Option<Duration>) -> Vec<String> {
+        let mut warnings = vec![];
+        let threshold = threshold.unwrap_or(SLOW_STORAGE);
+        if self.latency > threshold {
+            warnings.push(format!(
+                "reading a file took {} ms, more than {} ms: transfers may time out",
+                self.latency.as_millis(),
+                threshold.as_millis()
+            ));
+        }
+        if self.case_insensitive == Some(true) {
+            warnings.push(
+                "names are case-insensitive: names differing in case serve the same file"
+                    .to_string(),
+            );
+        }
+        if self.stable_sizes == Some(false) {
+            warnings.push(
+                "reported sizes do not match the contents: tsize answers may be wrong".to_string(),
+            );
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for StorageProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known = |value: Option<bool>, yes: &str, no: &str| match value {
+            Some(true) => yes.to_string(),
+            Some(false) => no.to_string(),
+            None => "unknown".to_string(),
+        };
+        write!(
+            f,
+            "{} ms to read, case {}, sizes {}",
+            self.latency.as_millis(),
+            known(self.case_insensitive, "insensitive", "sensitive"),
+            known(self.stable_sizes, "stable", "unstable"),
+        )
+    }
+}
+
+/// Storage `trait` is the filesystem access measured by the probe, so that
+/// tests can substitute a slow one.
+pub(crate) trait Storage {
+    /// Returns the names of the regular files of `dir`.
+    fn files(&self, dir: &Path) -> io::Result<Vec<String>>;
+    /// Returns the size of `path` in its metadata.
+    fn len(&self, path: &Path) -> io::Result<u64>;
+    /// Opens `path` and reads up to `max` bytes of it.
+    fn read(&self, path: &Path, max: usize) -> io::Result<Vec<u8>>;
+    /// Creates or replaces `path` with `contents`.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    /// Returns whether `path` names an existing file.
+    fn exists(&self, path: &Path) -> bool;
+    /// Removes the file `path`.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+/// FsStorage `struct` is the [`Storage`] of the real filesystem.
+pub(crate) struct FsStorage;
+
+impl Storage for FsStorage {
+    fn files(&self, dir: &Path) -> io::Result<Vec<String>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    files.push(name.to_string());
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn read(&self, path: &Path, max: usize) -> io::Result<Vec<u8>> {
+        let mut contents = vec![];
+        fs::File::open(path)?
+            .take(max as u64)
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+/// Probes the filesystem of `dir`. A probe file is written, read and
+/// looked up under another case when the directory is writable, otherwise
+/// an existing file is read and the case is guessed from its name.
+pub(crate) fn probe(storage: &dyn Storage, dir: &Path) -> io::Result<StorageProbe> {
+    let name = format!(".tftpd-probe-{}", process::id());
+    let path = dir.join(&name);
+    if storage.write(&path, PROBE_CONTENTS).is_ok() {
+        let result =
+            probe_file(storage, &path, PROBE_CONTENTS.len()).map(|(latency, stable_sizes)| {
+                StorageProbe {
+                    latency,
+                    case_insensitive: Some(storage.exists(&dir.join(name.to_uppercase()))),
+                    stable_sizes: Some(stable_sizes),
+                }
+            });
+        let _ = storage.remove(&path);
+        return result;
+    }
+
+    let started = Instant::now();
+    let files = storage.files(dir)?;
+    let Some(file) = files.first() else {
+        return Ok(StorageProbe {
+            latency: started.elapsed(),
+            case_insensitive: None,
+            stable_sizes: None,
+        });
+    };
+    let (latency, stable_sizes) = probe_file(storage, &dir.join(file), PROBE_READ_SIZE)?;
+    // Only a name whose other case is not a file of its own tells.
+    let swapped = swap_case(file);
+    let case_insensitive = (swapped != *file && !files.contains(&swapped))
+        .then(|| storage.exists(&dir.join(&swapped)));
+
+    Ok(StorageProbe {
+        latency,
+        case_insensitive,
+        stable_sizes: Some(stable_sizes),
+    })
+}
+
+/// Returns how long opening and reading up to `max` bytes of `path` took,
+/// and whether its size was reported the same twice and matches what was
+/// read.
+fn probe_file(storage: &dyn Storage, path: &Path, max: usize) -> io::Result<(Duration, bool)> {
+    let before = storage.len(path)?;
+    let started = Instant::now();
+    let contents = storage.read(path, max)?;
+    let latency = started.elapsed();
+    let after = storage.len(path)?;
+
+    let stable = before == after && contents.len() as u64 == before.min(max as u64);
+    Ok((latency, stable))
+}
+
+fn swap_case(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+/// Probes the served `directory` at startup and logs the findings worth a
+/// warning. Fails when the probe is slower than `require_fast`, or cannot
+/// be run while a threshold is required.
+pub(crate) fn check(
+    storage: &dyn Storage,
+    directory: &Path,
+    require_fast: Option<Duration>,
+) -> Result<Option<StorageProbe>, TftpError> {
+    let probe = match probe(storage, directory) {
+        Ok(probe) => probe,
+        Err(err) if require_fast.is_some() => {
+            return Err(TftpError::Directory(format!(
+                "{}: cannot probe storage: {err}",
+                directory.display()
+            )))
+        }
+        Err(err) => {
+            elogln!("{}: Cannot probe storage: {err}", directory.display());
+            return Ok(None);
+        }
+    };
+
+    for warning in probe.warnings(require_fast) {
+        elogln!("WARNING: {}: {warning}", directory.display());
+    }
+    if let Some(threshold) = require_fast.filter(|&threshold| probe.latency > threshold) {
+        return Err(TftpError::Directory(format!(
+            "{}: storage too slow, reading a file took {} ms, more than the required {} ms",
+            directory.display(),
+            probe.latency.as_millis(),
+            threshold.as_millis()
+        )));
+    }
+    Ok(Some(probe))
+}
+
+/// Runs the filesystem call `call`, records its duration in the storage
+/// histogram of `metrics` and adds it to `spent`.
+pub(crate) fn timed<T>(
+    clock: &dyn Clock,
+    metrics: &Metrics,
+    spent: &mut Duration,
+    call: impl FnOnce() -> T,
+) -> T {
+    let started = clock.now();
+    let result = call();
+    let elapsed = clock.now().saturating_duration_since(started);
+    metrics.record_storage(elapsed);
+    *spent += elapsed;
+    result
+}
+
+/// TimedSource `struct` measures every read of the source of a transfer
+/// with the clock of the server, so that slow storage can be told apart
+/// from a slow network.
+pub(crate) struct TimedSource<'a> {
+    inner: &'a mut dyn BlockSource,
+    clock: &'a dyn Clock,
+    metrics: &'a Metrics,
+    /// Total time spent reading
+    pub(crate) spent: Duration,
+    /// Longest single read
+    pub(crate) slowest: Duration,
+}
+
+impl<'a> TimedSource<'a> {
+    pub(crate) fn new(
+        inner: &'a mut dyn BlockSource,
+        clock: &'a dyn Clock,
+        metrics: &'a Metrics,
+    ) -> TimedSource<'a> {
+        TimedSource {
+            inner,
+            clock,
+            metrics,
+            spent: Duration::ZERO,
+            slowest: Duration::ZERO,
+        }
+    }
+}
+
+impl BlockSource for TimedSource<'_> {
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let before = self.spent;
+        let result = timed(self.clock, self.metrics, &mut self.spent, || {
+            self.inner.read_block(offset, len)
+        });
+        self.slowest = self.slowest.max(self.spent - before);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, thread};
+
+    /// A read-only [`FsStorage`] taking `delay` to read, whose reported
+    /// sizes grow with every lookup when `growing`.
+    struct SlowStorage {
+        delay: Duration,
+        growing: bool,
+        lookups: Cell<u64>,
+    }
+
+    impl SlowStorage {
+        fn new(delay: Duration) -> SlowStorage {
+            SlowStorage {
+                delay,
+                growing: false,
+                lookups: Cell::new(0),
+            }
+        }
+    }
+
+    impl Storage for SlowStorage {
+        fn files(&self, dir: &Path) -> io::Result<Vec<String>> {
+            FsStorage.files(dir)
+        }
+
+        fn len(&self, path: &Path) -> io::Result<u64> {
+            self.lookups.set(self.lookups.get() + 1);
+            let growth = if self.growing { self.lookups.get() } else { 0 };
+            Ok(FsStorage.len(path)? + growth)
+        }
+
+        fn read(&self, path: &Path, max: usize) -> io::Result<Vec<u8>> {
+            thread::sleep(self.delay);
+            FsStorage.read(path, max)
+        }
+
+        fn write(&self, _: &Path, _: &[u8]) -> io::Result<()> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            FsStorage.exists(path)
+        }
+
+        fn remove(&self, _: &Path) -> io::Result<()> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+    }
+
+    #[test]
+    fn probes_writable_directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let probe = probe(&FsStorage, dir.path()).unwrap();
+
+        assert_eq!(probe.stable_sizes, Some(true));
+        assert!(probe.case_insensitive.is_some());
+        assert!(probe.latency < SLOW_STORAGE);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn probes_read_only_directory_with_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Boot.img"), vec![7; 5000]).unwrap();
+
+        let storage = SlowStorage::new(Duration::ZERO);
+        let found = probe(&storage, dir.path()).unwrap();
+        assert_eq!(found.stable_sizes, Some(true));
+        assert_eq!(
+            found.case_insensitive,
+            Some(dir.path().join("bOOT.IMG").exists())
+        );
+
+        // Both cases being files of their own tells nothing.
+        fs::write(dir.path().join("bOOT.IMG"), b"other").unwrap();
+        let found = probe(&storage, dir.path()).unwrap();
+        assert_eq!(found.case_insensitive, None);
+    }
+
+    #[test]
+    fn detects_unstable_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kernel"), b"vmlinuz").unwrap();
+        let storage = SlowStorage {
+            growing: true,
+            ..SlowStorage::new(Duration::ZERO)
+        };
+
+        let probe = probe(&storage, dir.path()).unwrap();
+
+        assert_eq!(probe.stable_sizes, Some(false));
+        assert_eq!(
+            probe.warnings(None),
+            vec!["reported sizes do not match the contents: tsize answers may be wrong"]
+        );
+    }
+
+    #[test]
+    fn refuses_slow_storage_when_required() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kernel"), b"vmlinuz").unwrap();
+        let storage = SlowStorage::new(Duration::from_millis(30));
+
+        let probe = check(&storage, dir.path(), None).unwrap().unwrap();
+        assert!(probe.latency >= Duration::from_millis(30));
+        assert_eq!(probe.warnings(Some(Duration::from_millis(10))).len(), 1);
+
+        let err = check(&storage, dir.path(), Some(Duration::from_millis(10))).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        assert!(err.to_string().contains("storage too slow"), "{err}");
+    }
+
+    #[test]
+    fn refuses_unprobed_storage_when_required() {
+        let missing = Path::new("/nonexistent/tftpd");
+
+        assert_eq!(check(&FsStorage, missing, None).unwrap(), None);
+        assert!(check(&FsStorage, missing, Some(Duration::from_millis(10))).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! Scaffolding for testing code that embeds the server or talks to it,
+//! enabled by the `test-util` feature.
+//!
+//! - [`TestDir`]: a temporary served directory, removed when dropped.
+//! - [`TestServer`]: a server on an ephemeral loopback port, running on its
+//!   own thread until dropped or [shut down](TestServer::shutdown).
+//! - [`ScriptedClient`]: a client sending a programmed sequence of
+//!   [`Packet`]s and recording the answers.
+//! - [`FaultySocket`] and [`MockClock`]: for driving an in-process
+//!   [`Server`] step by step with [`Server::poll()`].
+//! - [`assert_file_eq()`] and [`assert_file_matches()`]: comparing a
+//!   received file without printing it whole.
+//!
+//! # Example
+//!
+//! A downstream test serving a directory and checking what a client
+//! receives, in `tests/boot.rs` with `tftpd` as a dev-dependency with the
+//! `test-util` feature:
+//!
+//! ```rust
+//! use tftpd::test_util::{assert_file_matches, ScriptedClient, TestDir, TestServer};
+//! use tftpd::{Client, ErrorCode, Packet};
+//!
+//! let dir = TestDir::new();
+//! dir.create_file("pxelinux.0", 3000);
+//! let server = TestServer::start(dir.path());
+//!
+//! // A complete download with the bundled client.
+//! let download = Client::new(server.addr()).get("pxelinux.0", vec![]).unwrap();
+//! assert_file_matches(&download.data, dir.path().join("pxelinux.0"));
+//!
+//! // A single request, packet by packet.
+//! let mut client = ScriptedClient::new(server.addr()).rrq("missing.cfg", vec![]);
+//! let responses = client.run();
+//! assert!(matches!(
+//!     responses[0][..],
+//!     [Packet::Error { code: ErrorCode::FileNotFound, .. }]
+//! ));
+//!
+//! let server = server.shutdown();
+//! assert_eq!(server.metrics().completed, 1);
+//! ```
+
+use std::{
+    env, fs,
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{Config, Packet, Server, TransferOption};
+
+pub use crate::{FaultySocket, MockClock};
+
+/// Time a [`ScriptedClient`] waits for the first answer to a packet.
+const FIRST_ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time without a datagram after which a [`ScriptedClient`] takes the
+/// server to be done answering a packet.
+const QUIET_PERIOD: Duration = Duration::from_millis(50);
+
+/// TestDir `struct` is a directory created under the system temporary
+/// directory and removed with its contents when dropped.
+#[derive(Debug)]
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Creates an empty directory with a name unique to the process.
+    pub fn new() -> TestDir {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let path = env::temp_dir().join(format!(
+            "tftpd-test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    /// Returns the path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a file of `size` bytes with a repeating pattern, so that
+    /// misplaced blocks show up, and returns its contents.
+    pub fn create_file(&self, name: &str, size: usize) -> Vec<u8> {
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        self.write(name, &contents);
+        contents
+    }
+
+    /// Writes `contents` into the file `name`, creating its parent
+    /// directories.
+    pub fn write(&self, name: &str, contents: &[u8]) {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+}
+
+impl Default for TestDir {
+    fn default() -> TestDir {
+        TestDir::new()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// TestServer `struct` runs a [`Server`] on an ephemeral port of the
+/// loopback address, on a thread polling it until the guard is dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Server>>,
+}
+
+impl TestServer {
+    /// Starts a server with the default configuration serving `dir`.
+    pub fn start<P: AsRef<Path>>(dir: P) -> TestServer {
+        TestServer::with_config(&Config {
+            directory: dir.as_ref().to_path_buf(),
+            port: 0,
+            ..Config::default()
+        })
+    }
+
+    /// Starts a server with `config`, on its address and port, port 0
+    /// picking a free one.
+    pub fn with_config(config: &Config) -> TestServer {
+        let socket = UdpSocket::bind((config.ip_address, config.port)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut server = Server::with_socket(config, socket).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    server.poll().unwrap();
+                }
+                // Handles what arrived before the shutdown, such as the last
+                // ACK of a client that returned.
+                server.poll().unwrap();
+                server
+            }
+        });
+        TestServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the address the server answers on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the server once it handled the datagrams already received and
+    /// returns it, to look at its metrics or statistics.
+    pub fn shutdown(mut self) -> Server {
+        self.stop().expect("the server thread is only joined once")
+    }
+
+    fn stop(&mut self) -> Option<Server> {
+        self.stop.store(true, Ordering::Relaxed);
+        let server = self.thread.take()?.join();
+        match server {
+            Ok(server) => Some(server),
+            Err(panic) if !thread::panicking() => std::panic::resume_unwind(panic),
+            Err(_) => None,
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// ScriptedClient `struct` sends a programmed sequence of packets to a
+/// server and records what comes back after each of them. Packets are
+/// sent from one port, to the port of the server.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::net::SocketAddr;
+/// use tftpd::test_util::ScriptedClient;
+/// use tftpd::Packet;
+///
+/// let server = SocketAddr::from(([127, 0, 0, 1], 6969));
+/// let mut client = ScriptedClient::new(server)
+///     .rrq("boot.img", vec![])
+///     .then_send(Packet::Ack(1));
+/// let responses = client.run();
+/// assert_eq!(responses.len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct ScriptedClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    script: Vec<Packet>,
+    timeout: Duration,
+}
+
+impl ScriptedClient {
+    /// Creates a client of `server` on an ephemeral loopback port, with an
+    /// empty script.
+    pub fn new(server: SocketAddr) -> ScriptedClient {
+        let local: SocketAddr = if server.is_ipv4() {
+            ([127, 0, 0, 1], 0).into()
+        } else {
+            ([0u16, 0, 0, 0, 0, 0, 0, 1], 0).into()
+        };
+        ScriptedClient {
+            socket: UdpSocket::bind(local).unwrap(),
+            server,
+            script: vec![],
+            timeout: FIRST_ANSWER_TIMEOUT,
+        }
+    }
+
+    /// Appends `packet` to the script.
+    pub fn then_send(mut self, packet: Packet) -> ScriptedClient {
+        self.script.push(packet);
+        self
+    }
+
+    /// Appends an octet mode read request for `filename` to the script.
+    pub fn rrq(self, filename: &str, options: Vec<TransferOption>) -> ScriptedClient {
+        self.then_send(Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options,
+        })
+    }
+
+    /// Sets how long the client waits for the first answer to a packet.
+    /// (default: 2s)
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the address the client sends from.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    /// Sends the packets of the script in order, emptying it, and returns
+    /// the packets received after each of them.
+    pub fn run(&mut self) -> Vec<Vec<Packet>> {
+        let script = std::mem::take(&mut self.script);
+        script
+            .into_iter()
+            .map(|packet| self.send(&packet))
+            .collect()
+    }
+
+    /// Sends `packet` and returns the packets received until the server
+    /// goes quiet, none if it does not answer within the timeout.
+    pub fn send(&mut self, packet: &Packet) -> Vec<Packet> {
+        self.socket
+            .send_to(&packet.serialize().unwrap(), self.server)
+            .unwrap();
+
+        let mut received = vec![];
+        let mut buf = [0; 65536];
+        let mut wait = self.timeout;
+        loop {
+            self.socket.set_read_timeout(Some(wait)).unwrap();
+            let Ok((size, _)) = self.socket.recv_from(&mut buf) else {
+                return received;
+            };
+            received.push(Packet::deserialize(&buf[..size]).unwrap());
+            wait = QUIET_PERIOD;
+        }
+    }
+}
+
+/// Panics unless `received` equals `expected`, naming the lengths or the
+/// first differing offset instead of printing both files.
+#[track_caller]
+pub fn assert_file_eq(received: &[u8], expected: &[u8]) {
+    if let Some(offset) = received.iter().zip(expected).position(|(r, e)| r != e) {
+        panic!(
+            "received file differs from offset {offset}: {:#04x} instead of {:#04x}",
+            received[offset], expected[offset]
+        );
+    }
+    if received.len() != expected.len() {
+        panic!(
+            "received {} bytes instead of {}",
+            received.len(),
+            expected.len()
+        );
+    }
+}
+
+/// Panics unless `received` equals the contents of the file at `path`, see
+/// [`assert_file_eq()`].
+#[track_caller]
+pub fn assert_file_matches<P: AsRef<Path>>(received: &[u8], path: P) {
+    let expected = fs::read(path.as_ref()).unwrap();
+    assert_file_eq(received, &expected);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_test_dir_on_drop() {
+        let dir = TestDir::new();
+        let path = dir.path().to_path_buf();
+        assert_ne!(path, TestDir::new().path());
+        dir.write("pxelinux.cfg/default", b"default linux");
+        assert_eq!(dir.create_file("boot.img", 300)[260], 9);
+
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "differs from offset 2: 0x07 instead of 0x03")]
+    fn reports_first_difference() {
+        assert_file_eq(&[1, 2, 7, 4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "received 2 bytes instead of 4")]
+    fn reports_short_file() {
+        assert_file_eq(&[1, 2], &[1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::BTreeSet, net::SocketAddr, time::Instant};
+
+/// Maximum number of due timers handled per tick, the rest wait for the
+/// next one.
+pub(crate) const MAX_TIMERS_PER_TICK: usize = 256;
+
+/// Timers `struct` orders sessions by the instant they are due, so that a
+/// tick only looks at the due ones instead of every session.
+///
+/// Every entry carries the generation of its session. Entries are not
+/// removed when a session ends or is replaced by a new request from the same
+/// client, the caller skips the ones whose generation does not match the
+/// current session.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    entries: BTreeSet<(Instant, SocketAddr, u64)>,
+}
+
+impl Timers {
+    pub(crate) fn new() -> Timers {
+        Timers::default()
+    }
+
+    pub(crate) fn schedule(&mut self, at: Instant, client: SocketAddr, generation: u64) {
+        self.entries.insert((at, client, generation));
+    }
+
+    /// Removes the entry scheduled with the same arguments, if any.
+    pub(crate) fn cancel(&mut self, at: Instant, client: SocketAddr, generation: u64) {
+        self.entries.remove(&(at, client, generation));
+    }
+
+    /// Returns every entry, earliest first.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Instant, SocketAddr, u64)> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Removes and returns the client and generation of the earliest entry
+    /// due at `now`.
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<(SocketAddr, u64)> {
+        let &(at, client, generation) = self.entries.first()?;
+        if at > now {
+            return None;
+        }
+        self.entries.pop_first();
+        Some((client, generation))
+    }
+
+    /// Removes and returns up to [`MAX_TIMERS_PER_TICK`] entries due at
+    /// `now`.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<(SocketAddr, u64)> {
+        let mut due = vec![];
+        while due.len() < MAX_TIMERS_PER_TICK {
+            match self.pop_due(now) {
+                Some(entry) => due.push(entry),
+                None => break,
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn pops_due_entries_in_order() {
+        let clock = MockClock::new();
+        let now = clock.now();
+        let mut timers = Timers::new();
+        timers.schedule(now + Duration::from_secs(2), client(1000), 1);
+        timers.schedule(now + Duration::from_secs(1), client(2000), 2);
+
+        assert_eq!(timers.pop_due(clock.now()), None);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(
+            timers.take_due(clock.now()),
+            vec![(client(2000), 2), (client(1000), 1)]
+        );
+        assert_eq!(timers.pop_due(clock.now()), None);
+    }
+
+    #[test]
+    fn rescheduling_cancels_previous_deadline() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut timers = Timers::new();
+        timers.schedule(start + Duration::from_secs(5), client(1000), 1);
+
+        // An ACK after 3 seconds moves the deadline.
+        clock.advance(Duration::from_secs(3));
+        timers.cancel(start + Duration::from_secs(5), client(1000), 1);
+        timers.schedule(clock.now() + Duration::from_secs(5), client(1000), 1);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(timers.pop_due(clock.now()), None);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(timers.pop_due(clock.now()), Some((client(1000), 1)));
+        assert!(timers.entries.is_empty());
+    }
+
+    #[test]
+    fn keeps_generations_apart() {
+        let clock = MockClock::new();
+        let now = clock.now();
+        let mut timers = Timers::new();
+        timers.schedule(now, client(1000), 1);
+        timers.schedule(now, client(1000), 2);
+
+        assert_eq!(
+            timers.take_due(now),
+            vec![(client(1000), 1), (client(1000), 2)]
+        );
+    }
+
+    #[test]
+    fn interleaves_many_deadlines() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut timers = Timers::new();
+        for port in 0..1000u16 {
+            let at = start + Duration::from_millis(((port as u64) * 7919) % 1000);
+            timers.schedule(at, client(port), port as u64);
+        }
+
+        let mut popped = 0;
+        let mut last = start;
+        for step in 1..=10 {
+            let now = start + Duration::from_millis(step * 100);
+            for (client, generation) in timers.take_due(now) {
+                let at = start + Duration::from_millis((generation * 7919) % 1000);
+                assert_eq!(client.port() as u64, generation);
+                assert!(at <= now);
+                assert!(at >= last);
+                last = at;
+                popped += 1;
+            }
+        }
+
+        assert_eq!(popped, 1000);
+        assert!(timers.entries.is_empty());
+    }
+
+    #[test]
+    fn caps_entries_per_tick() {
+        let now = MockClock::new().now();
+        let mut timers = Timers::new();
+        for port in 0..MAX_TIMERS_PER_TICK as u16 + 10 {
+            timers.schedule(now, client(port), 1);
+        }
+
+        assert_eq!(timers.take_due(now).len(), MAX_TIMERS_PER_TICK);
+        assert_eq!(timers.take_due(now).len(), 10);
+    }
+
+    /// Compares finding the due sessions among 10k with a scan of every
+    /// deadline, printing both to standard error, run with
+    /// `cargo test --release --lib timers -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_against_scan() {
+        let start = MockClock::new().now();
+        let mut timers = Timers::new();
+        let mut deadlines = HashMap::new();
+        for port in 0..10_000u16 {
+            let at = start + Duration::from_secs(5) + Duration::from_micros(port as u64 * 100);
+            timers.schedule(at, client(port), 1);
+            deadlines.insert(client(port), at);
+        }
+
+        let ticks = 1000;
+        let now = start + Duration::from_secs(5);
+        let scan_started = std::time::Instant::now();
+        let mut scanned = 0;
+        for _ in 0..ticks {
+            scanned += deadlines.values().filter(|&&at| at <= now).count();
+        }
+        let scan = scan_started.elapsed();
+
+        let timers_started = std::time::Instant::now();
+        let mut popped = 0;
+        for _ in 0..ticks {
+            popped += timers.pop_due(now).map_or(0, |_| 1);
+        }
+        let timers_elapsed = timers_started.elapsed();
+
+        eprintln!(
+            "{ticks} ticks over 10k sessions: scan {:?} per tick, timers {:?} per tick",
+            scan / ticks,
+            timers_elapsed / ticks
+        );
+        assert_eq!(scanned, ticks as usize);
+        assert_eq!(popped, 1);
+        assert!(timers_elapsed < scan, "timers slower than a scan");
+    }
+}
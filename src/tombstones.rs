@@ -0,0 +1,113 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Maximum number of remembered terminated sessions, the oldest are
+/// forgotten first.
+pub(crate) const MAX_TOMBSTONES: usize = 1024;
+
+/// Time during which packets of a terminated session are answered with the
+/// reason of the termination.
+pub(crate) const TOMBSTONE_GRACE: Duration = Duration::from_secs(30);
+
+/// Tombstones `struct` remembers the sessions terminated by the server, so
+/// that a client still sending packets learns why its transfer ended instead
+/// of being ignored.
+#[derive(Debug, Default)]
+pub(crate) struct Tombstones {
+    reasons: HashMap<SocketAddr, (String, Instant)>,
+    order: VecDeque<SocketAddr>,
+}
+
+impl Tombstones {
+    pub(crate) fn new() -> Tombstones {
+        Tombstones::default()
+    }
+
+    /// Remembers that the session of `client` was terminated with the error
+    /// `message`, for [`TOMBSTONE_GRACE`] after `now`.
+    pub(crate) fn bury(&mut self, client: SocketAddr, message: &str, now: Instant) {
+        if self.reasons.len() >= MAX_TOMBSTONES && !self.reasons.contains_key(&client) {
+            self.evict_oldest();
+        }
+        self.order.retain(|&buried| buried != client);
+        self.order.push_back(client);
+        self.reasons
+            .insert(client, (message.to_string(), now + TOMBSTONE_GRACE));
+    }
+
+    /// Removes the tombstone of `client` and returns its message, unless it
+    /// expired at `now`.
+    pub(crate) fn take(&mut self, client: &SocketAddr, now: Instant) -> Option<String> {
+        let (message, expires) = self.reasons.remove(client)?;
+        self.order.retain(|buried| buried != client);
+        (now < expires).then_some(message)
+    }
+
+    pub(crate) fn contains(&self, client: &SocketAddr) -> bool {
+        self.reasons.contains_key(client)
+    }
+
+    /// Forgets the tombstone of `client`, for example when it starts a new
+    /// transfer.
+    pub(crate) fn remove(&mut self, client: &SocketAddr) {
+        if self.reasons.remove(client).is_some() {
+            self.order.retain(|buried| buried != client);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.order.pop_front() {
+            self.reasons.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn answers_once_within_grace() {
+        let clock = MockClock::new();
+        let mut tombstones = Tombstones::new();
+        tombstones.bury(client(1000), "transfer timed out", clock.now());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            tombstones.take(&client(1000), clock.now()),
+            Some("transfer timed out".to_string())
+        );
+        assert_eq!(tombstones.take(&client(1000), clock.now()), None);
+    }
+
+    #[test]
+    fn expires_after_grace() {
+        let clock = MockClock::new();
+        let mut tombstones = Tombstones::new();
+        tombstones.bury(client(1000), "transfer timed out", clock.now());
+
+        clock.advance(TOMBSTONE_GRACE);
+        assert_eq!(tombstones.take(&client(1000), clock.now()), None);
+    }
+
+    #[test]
+    fn forgets_oldest_when_full() {
+        let now = MockClock::new().now();
+        let mut tombstones = Tombstones::new();
+        for port in 0..MAX_TOMBSTONES as u16 + 1 {
+            tombstones.bury(client(port), "transfer timed out", now);
+        }
+
+        assert_eq!(tombstones.reasons.len(), MAX_TOMBSTONES);
+        assert_eq!(tombstones.take(&client(0), now), None);
+        assert!(tombstones.take(&client(1), now).is_some());
+    }
+}
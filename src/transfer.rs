@@ -0,0 +1,272 @@
+use std::{collections::VecDeque, error::Error, io, time::Instant};
+
+use crate::state::BlockSource;
+use crate::{Packet, Session, SessionAction, SessionEvent};
+
+/// Transport `trait` sends the packets of a transfer, either from the
+/// single port of the [`Server`](crate::Server), which dispatches the
+/// packets of every client itself, or from a socket dedicated to the
+/// transfer, as the [`Worker`](crate::Worker) does.
+pub(crate) trait Transport {
+    /// Sends `packet` to the client of the transfer.
+    fn send(&mut self, packet: Packet) -> Result<(), Box<dyn Error>>;
+}
+
+/// Outcome `enum` tells how a transfer stands once the actions of its
+/// [`Session`] were carried out.
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    /// Waiting for the client or the retransmission timeout
+    Pending,
+    /// The client acknowledged the whole file
+    Finished,
+    /// The session gave up, for the reason given
+    Aborted(String),
+    /// The client went back to acknowledging an earlier block
+    Restarted(u16),
+    /// The source failed to read
+    ReadFailed(io::Error),
+    /// A packet could not be sent, the session is still usable
+    SendFailed(Box<dyn Error>),
+}
+
+/// Carries out the `actions` of `session`: sends its packets through
+/// `transport` and feeds it the blocks it asks for from `source`, until it
+/// waits for the client again.
+///
+/// Both the single port and the dedicated socket modes drive their sessions
+/// through here, so window, retransmission and termination semantics are
+/// only defined by the [`Session`].
+pub(crate) fn execute(
+    session: &mut Session,
+    source: &mut dyn BlockSource,
+    transport: &mut dyn Transport,
+    now: Instant,
+    actions: Vec<SessionAction>,
+) -> Outcome {
+    let mut actions = VecDeque::from(actions);
+    while let Some(action) = actions.pop_front() {
+        match action {
+            SessionAction::SendPacket(packet) => {
+                if let Err(err) = transport.send(packet) {
+                    return Outcome::SendFailed(err);
+                }
+            }
+            SessionAction::ReadFileBlock { offset, len } => match source.read_block(offset, len) {
+                Ok(data) => {
+                    actions.extend(session.handle(now, SessionEvent::BlockRead { offset, data }))
+                }
+                Err(err) => return Outcome::ReadFailed(err),
+            },
+            SessionAction::ResendBlock {
+                block_num,
+                offset,
+                len,
+            } => {
+                let data = match source.read_block(offset, len) {
+                    Ok(data) if data.len() == len => data,
+                    Ok(_) => {
+                        let err = io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "file shrank while it was sent",
+                        );
+                        return Outcome::ReadFailed(err);
+                    }
+                    Err(err) => return Outcome::ReadFailed(err),
+                };
+                if let Err(err) = transport.send(Packet::Data { block_num, data }) {
+                    return Outcome::SendFailed(err);
+                }
+            }
+            SessionAction::Finished => return Outcome::Finished,
+            SessionAction::Abort(reason) => return Outcome::Aborted(reason),
+            SessionAction::ClientRestarted { block } => return Outcome::Restarted(block),
+        }
+    }
+    Outcome::Pending
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Read},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::state::Sequential;
+    use crate::SessionOptions;
+
+    #[derive(Default)]
+    struct Sent(Vec<Packet>);
+
+    impl Transport for Sent {
+        fn send(&mut self, packet: Packet) -> Result<(), Box<dyn Error>> {
+            self.0.push(packet);
+            Ok(())
+        }
+    }
+
+    /// Returns at most 3 bytes per read, like a decompressor might.
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    /// Reads blocks at any offset, keeping the offset and length of every
+    /// read.
+    struct Recorded {
+        contents: Vec<u8>,
+        reads: Vec<(u64, usize)>,
+    }
+
+    impl BlockSource for Recorded {
+        fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            self.reads.push((offset, len));
+            let start = (offset as usize).min(self.contents.len());
+            let end = (start + len).min(self.contents.len());
+            Ok(self.contents[start..end].to_vec())
+        }
+
+        fn rereadable(&self) -> bool {
+            true
+        }
+    }
+
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::InvalidData.into())
+        }
+    }
+
+    fn session(now: Instant) -> (Session, Vec<SessionAction>) {
+        session_with(now, false)
+    }
+
+    fn session_with(now: Instant, reread: bool) -> (Session, Vec<SessionAction>) {
+        let options = SessionOptions {
+            blk_size: 8,
+            windowsize: 2,
+            timeout: Duration::from_secs(5),
+            max_retries: 6,
+            duplicate_acks: false,
+            checksum: None,
+            restart_acks: 3,
+            max_in_flight_bytes: None,
+            rollover: 0,
+            reread,
+        };
+        Session::new(options, None, now)
+    }
+
+    #[test]
+    fn reads_full_blocks_and_finishes() {
+        let now = Instant::now();
+        let (mut session, actions) = session(now);
+        let mut source = Sequential::new(Box::new(Trickle(Cursor::new((0..20).collect()))));
+        let mut sent = Sent::default();
+
+        let outcome = execute(&mut session, &mut source, &mut sent, now, actions);
+        assert!(matches!(outcome, Outcome::Pending));
+        assert_eq!(
+            sent.0,
+            vec![
+                Packet::Data {
+                    block_num: 1,
+                    data: (0..8).collect()
+                },
+                Packet::Data {
+                    block_num: 2,
+                    data: (8..16).collect()
+                },
+            ]
+        );
+
+        let actions = session.handle(now, SessionEvent::PacketReceived(Packet::Ack(2)));
+        let outcome = execute(&mut session, &mut source, &mut sent, now, actions);
+        assert!(matches!(outcome, Outcome::Pending));
+        assert_eq!(
+            sent.0[2],
+            Packet::Data {
+                block_num: 3,
+                data: (16..20).collect()
+            }
+        );
+
+        let actions = session.handle(now, SessionEvent::PacketReceived(Packet::Ack(3)));
+        let outcome = execute(&mut session, &mut source, &mut sent, now, actions);
+        assert!(matches!(outcome, Outcome::Finished));
+    }
+
+    #[test]
+    fn rereads_blocks_resent_after_partial_ack() {
+        let now = Instant::now();
+        let (mut session, actions) = session_with(now, true);
+        let mut source = Recorded {
+            contents: (0..20).collect(),
+            reads: vec![],
+        };
+        let mut sent = Sent::default();
+
+        execute(&mut session, &mut source, &mut sent, now, actions);
+        let actions = session.handle(now, SessionEvent::PacketReceived(Packet::Ack(1)));
+        let outcome = execute(&mut session, &mut source, &mut sent, now, actions);
+
+        assert!(matches!(outcome, Outcome::Pending));
+        assert_eq!(source.reads, [(0, 8), (8, 8), (16, 8), (8, 8)]);
+        let blocks: Vec<_> = sent
+            .0
+            .iter()
+            .map(|packet| match packet {
+                Packet::Data { block_num, data } => (*block_num, data[0]),
+                packet => panic!("unexpected {packet:?}"),
+            })
+            .collect();
+        assert_eq!(blocks, [(1, 0), (2, 8), (2, 8), (3, 16)]);
+    }
+
+    #[test]
+    fn stops_when_reread_block_shrank() {
+        let now = Instant::now();
+        let (mut session, actions) = session_with(now, true);
+        let mut source = Recorded {
+            contents: (0..20).collect(),
+            reads: vec![],
+        };
+        let mut sent = Sent::default();
+
+        execute(&mut session, &mut source, &mut sent, now, actions);
+        source.contents.truncate(12);
+        let actions = session.handle(now, SessionEvent::PacketReceived(Packet::Ack(1)));
+        let outcome = execute(&mut session, &mut source, &mut sent, now, actions);
+
+        assert!(
+            matches!(outcome, Outcome::ReadFailed(err) if err.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        assert_eq!(sent.0.len(), 2);
+    }
+
+    #[test]
+    fn stops_at_read_errors() {
+        let now = Instant::now();
+        let (mut session, actions) = session(now);
+        let mut sent = Sent::default();
+
+        let outcome = execute(
+            &mut session,
+            &mut Sequential::new(Box::new(Broken)),
+            &mut sent,
+            now,
+            actions,
+        );
+
+        assert!(matches!(outcome, Outcome::ReadFailed(_)));
+        assert!(sent.0.is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::Packet;
+
+/// Upload `struct` receives the file of a write request accepted with
+/// `--writable`. The blocks are written to a temporary file next to the
+/// target, moved into place once the last block arrived, so that readers
+/// never see a partial file and an abandoned upload leaves nothing behind.
+#[derive(Debug)]
+pub(crate) struct Upload {
+    file: File,
+    temporary: PathBuf,
+    path: PathBuf,
+    /// Generation of the transfer, matching the timers of the upload
+    pub(crate) generation: u64,
+    pub(crate) blk_size: usize,
+    /// Time without a DATA packet after which the last answer is sent again
+    pub(crate) timeout: Duration,
+    /// Last block written
+    block: u16,
+    pub(crate) bytes: u64,
+    finished: bool,
+    /// ACK or OACK sent last, sent again when the client stops sending
+    pub(crate) answer: Packet,
+    pub(crate) answered_at: Instant,
+    pub(crate) retries: u32,
+    /// Largest file accepted, from the announced `tsize` and
+    /// `--max-upload-size`
+    pub(crate) max_size: Option<u64>,
+    /// Whether the file may replace one created since the request
+    pub(crate) overwrite: bool,
+    /// Block number following 65535, from `--rollover` or the `rollover`
+    /// option
+    pub(crate) rollover: u16,
+}
+
+/// What became of a DATA packet of an upload.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Receipt {
+    /// The block was written and is acknowledged, `last` when it was the
+    /// short one ending the file
+    Written { last: bool },
+    /// The block was already written, its ACK was probably lost
+    Duplicate,
+    /// The block is not the next one and is dropped
+    Ignored,
+}
+
+impl Upload {
+    /// Starts receiving `path` as transfer `generation`, answering with
+    /// `answer`. The temporary file
+    /// is created by `create_new`, which fails when it exists, that is when
+    /// another upload of the same file is in progress.
+    pub(crate) fn create<F>(
+        path: &Path,
+        create_new: F,
+        generation: u64,
+        blk_size: usize,
+        timeout: Duration,
+        answer: Packet,
+        now: Instant,
+    ) -> io::Result<Upload>
+    where
+        F: FnOnce(&Path) -> io::Result<File>,
+    {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".part");
+        let temporary = path.with_file_name(name);
+        let file = create_new(&temporary)?;
+        Ok(Upload {
+            file,
+            temporary,
+            path: path.to_path_buf(),
+            generation,
+            blk_size,
+            timeout,
+            block: 0,
+            bytes: 0,
+            finished: false,
+            answer,
+            answered_at: now,
+            retries: 0,
+            max_size: None,
+            overwrite: false,
+            rollover: 0,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the temporary file receiving the blocks.
+    pub(crate) fn temporary(&self) -> &Path {
+        &self.temporary
+    }
+
+    pub(crate) fn block(&self) -> u16 {
+        self.block
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Writes the DATA packet `block_num` if it is the next block, and
+    /// moves the file into place with `place` after a block shorter than
+    /// `blk_size`. A block going past `max_size` fails with `StorageFull`,
+    /// and without `overwrite` a file created at the target since the
+    /// request fails the upload with `AlreadyExists`.
+    pub(crate) fn receive<F>(
+        &mut self,
+        block_num: u16,
+        data: &[u8],
+        place: F,
+    ) -> io::Result<Receipt>
+    where
+        F: FnOnce(&Path, &Path, bool) -> io::Result<()>,
+    {
+        if block_num == self.block && (self.finished || self.bytes > 0) {
+            return Ok(Receipt::Duplicate);
+        }
+        let next = self.block.checked_add(1).unwrap_or(self.rollover);
+        if self.finished || block_num != next {
+            return Ok(Receipt::Ignored);
+        }
+        let bytes = self.bytes + data.len() as u64;
+        if self.max_size.is_some_and(|max| bytes > max) {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "file larger than allowed",
+            ));
+        }
+
+        self.file.write_all(data)?;
+        self.block = block_num;
+        self.bytes = bytes;
+        if data.len() < self.blk_size {
+            self.file.sync_all()?;
+            place(&self.temporary, &self.path, self.overwrite)?;
+            self.finished = true;
+        }
+        Ok(Receipt::Written {
+            last: self.finished,
+        })
+    }
+}
+
+/// Creates `path` for writing, failing if it already exists.
+pub(crate) fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Moves the received `temporary` file to `path`, replacing an existing
+/// file only with `overwrite`.
+pub(crate) fn place(temporary: &Path, path: &Path, overwrite: bool) -> io::Result<()> {
+    if overwrite {
+        fs::rename(temporary, path)
+    } else {
+        // Unlike a rename, linking does not replace an existing file.
+        fs::hard_link(temporary, path)?;
+        fs::remove_file(temporary)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upload(dir: &Path) -> Upload {
+        Upload::create(
+            &dir.join("switch.cfg"),
+            create_new,
+            1,
+            4,
+            Duration::from_secs(1),
+            Packet::Ack(0),
+            Instant::now(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn assembles_blocks_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+
+        assert_eq!(upload.receive(2, b"late", place).unwrap(), Receipt::Ignored);
+        assert_eq!(
+            upload.receive(1, b"host", place).unwrap(),
+            Receipt::Written { last: false }
+        );
+        assert_eq!(
+            upload.receive(1, b"host", place).unwrap(),
+            Receipt::Duplicate
+        );
+        assert!(!dir.path().join("switch.cfg").exists());
+        assert_eq!(
+            upload.receive(2, b"1\n", place).unwrap(),
+            Receipt::Written { last: true }
+        );
+
+        assert_eq!(fs::read(dir.path().join("switch.cfg")).unwrap(), b"host1\n");
+        assert!(!dir.path().join("switch.cfg.part").exists());
+        assert_eq!(
+            upload.receive(2, b"1\n", place).unwrap(),
+            Receipt::Duplicate
+        );
+        assert_eq!(upload.bytes, 6);
+    }
+
+    #[test]
+    fn ends_on_empty_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+
+        upload.receive(1, b"abcd", place).unwrap();
+
+        assert_eq!(
+            upload.receive(2, b"", place).unwrap(),
+            Receipt::Written { last: true }
+        );
+        assert_eq!(fs::read(dir.path().join("switch.cfg")).unwrap(), b"abcd");
+    }
+
+    #[test]
+    fn removes_abandoned_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+        upload.receive(1, b"abcd", place).unwrap();
+
+        assert!(Upload::create(
+            &dir.path().join("switch.cfg"),
+            create_new,
+            1,
+            4,
+            Duration::from_secs(1),
+            Packet::Ack(0),
+            Instant::now()
+        )
+        .is_err());
+        drop(upload);
+
+        assert!(fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn rolls_over_to_configured_block() {
+        let dir = tempfile::tempdir().unwrap();
+        for rollover in [0, 1] {
+            let mut upload = upload(dir.path());
+            upload.rollover = rollover;
+            upload.receive(1, b"abcd", place).unwrap();
+            upload.block = u16::MAX;
+
+            assert_eq!(
+                upload.receive(1 - rollover, b"efgh", place).unwrap(),
+                Receipt::Ignored
+            );
+            assert_eq!(
+                upload.receive(rollover, b"efgh", place).unwrap(),
+                Receipt::Written { last: false }
+            );
+            assert_eq!(upload.block(), rollover);
+        }
+    }
+
+    #[test]
+    fn refuses_blocks_past_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+        upload.max_size = Some(6);
+
+        upload.receive(1, b"abcd", place).unwrap();
+        let err = upload.receive(2, b"efgh", place).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(upload.bytes, 4);
+    }
+
+    #[test]
+    fn keeps_file_created_during_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+        upload.receive(1, b"abcd", place).unwrap();
+        fs::write(dir.path().join("switch.cfg"), b"other").unwrap();
+
+        let err = upload.receive(2, b"", place).unwrap_err();
+        drop(upload);
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(dir.path().join("switch.cfg")).unwrap(), b"other");
+        assert!(!dir.path().join("switch.cfg.part").exists());
+    }
+
+    #[test]
+    fn replaces_file_created_during_upload_with_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut upload = upload(dir.path());
+        upload.overwrite = true;
+        upload.receive(1, b"abcd", place).unwrap();
+        fs::write(dir.path().join("switch.cfg"), b"other").unwrap();
+
+        upload.receive(2, b"", place).unwrap();
+
+        assert_eq!(fs::read(dir.path().join("switch.cfg")).unwrap(), b"abcd");
+    }
+}
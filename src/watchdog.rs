@@ -0,0 +1,153 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::logger::{self, elogln};
+
+/// Value of [`Heartbeat::last_packet`] before the first packet.
+const NEVER: u64 = u64::MAX;
+
+/// Longest wait for the log lines to be written before aborting, as the
+/// output itself may be what wedged the loop.
+const ABORT_FLUSH_LIMIT: Duration = Duration::from_secs(1);
+
+/// Stall `struct` describes the state of a listen loop that stopped
+/// polling, passed to the handler set with
+/// [`Server::set_stall_handler()`](crate::Server::set_stall_handler).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    /// Time since the loop last polled
+    pub since: Duration,
+    /// Number of transfers in progress at the last poll
+    pub sessions: u64,
+    /// Time since the last packet was received, if any was
+    pub last_packet: Option<Duration>,
+}
+
+/// Handler called by the watchdog thread when the listen loop stalls.
+pub(crate) type StallHandler = Arc<dyn Fn(&Stall) + Send + Sync>;
+
+/// Updated by the listen loop on every poll and read by the watchdog
+/// thread, as milliseconds since `started`.
+#[derive(Debug)]
+struct Heartbeat {
+    started: Instant,
+    last_beat: AtomicU64,
+    last_packet: AtomicU64,
+    sessions: AtomicU64,
+    stopped: AtomicBool,
+}
+
+impl Heartbeat {
+    fn elapsed(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+/// Watchdog `struct` runs a thread calling its handler once when the
+/// listen loop has not polled for longer than `timeout`. By default the
+/// handler logs the last known state and aborts the process, so that a
+/// supervisor restarts it.
+///
+/// The thread starts at the first heartbeat and stops when the watchdog is
+/// dropped.
+pub(crate) struct Watchdog {
+    timeout: Duration,
+    heartbeat: Arc<Heartbeat>,
+    handler: StallHandler,
+    running: bool,
+}
+
+impl Watchdog {
+    pub(crate) fn new(timeout: Duration) -> Watchdog {
+        Watchdog {
+            timeout,
+            heartbeat: Arc::new(Heartbeat {
+                started: Instant::now(),
+                last_beat: AtomicU64::new(0),
+                last_packet: AtomicU64::new(NEVER),
+                sessions: AtomicU64::new(0),
+                stopped: AtomicBool::new(false),
+            }),
+            handler: Arc::new(abort),
+            running: false,
+        }
+    }
+
+    /// Replaces the handler, which must be done before the first heartbeat.
+    pub(crate) fn set_handler(&mut self, handler: StallHandler) {
+        self.handler = handler;
+    }
+
+    /// Records that the listen loop polled, with `sessions` transfers in
+    /// progress, and whether it received a packet.
+    pub(crate) fn beat(&mut self, sessions: usize, received: bool) {
+        let now = self.heartbeat.elapsed();
+        self.heartbeat.last_beat.store(now, Ordering::Relaxed);
+        self.heartbeat
+            .sessions
+            .store(sessions as u64, Ordering::Relaxed);
+        if received {
+            self.heartbeat.last_packet.store(now, Ordering::Relaxed);
+        }
+
+        if !self.running {
+            self.running = true;
+            let heartbeat = self.heartbeat.clone();
+            let handler = self.handler.clone();
+            let timeout = self.timeout;
+            thread::spawn(move || monitor(&heartbeat, timeout, &*handler));
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.heartbeat.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Checks the heartbeat a few times per `timeout` until it is stale, then
+/// calls `handler`.
+fn monitor(heartbeat: &Heartbeat, timeout: Duration, handler: &(dyn Fn(&Stall) + Send + Sync)) {
+    let interval = (timeout / 4).max(Duration::from_millis(10));
+    while !heartbeat.stopped.load(Ordering::Relaxed) {
+        thread::sleep(interval);
+
+        let now = heartbeat.elapsed();
+        let since = now.saturating_sub(heartbeat.last_beat.load(Ordering::Relaxed));
+        if since > timeout.as_millis() as u64 && !heartbeat.stopped.load(Ordering::Relaxed) {
+            let last_packet = match heartbeat.last_packet.load(Ordering::Relaxed) {
+                NEVER => None,
+                at => Some(Duration::from_millis(now.saturating_sub(at))),
+            };
+            handler(&Stall {
+                since: Duration::from_millis(since),
+                sessions: heartbeat.sessions.load(Ordering::Relaxed),
+                last_packet,
+            });
+            return;
+        }
+    }
+}
+
+/// Default handler, logs the stall and aborts the process.
+fn abort(stall: &Stall) {
+    let last_packet = match stall.last_packet {
+        Some(elapsed) => format!("{}ms ago", elapsed.as_millis()),
+        None => "never".to_string(),
+    };
+    elogln!(
+        "FATAL: listen loop stalled for {}ms with {} active sessions, last packet {last_packet}, aborting",
+        stall.since.as_millis(),
+        stall.sessions
+    );
+    logger::flush_logs_within(ABORT_FLUSH_LIMIT);
+    process::abort();
+}
@@ -1,112 +1,524 @@
 use std::{
     error::Error,
     fs::File,
-    net::{SocketAddr, UdpSocket},
-    path::PathBuf,
-    thread,
-    time::{Duration, SystemTime},
+    io::{self, Read},
+    net::{IpAddr, SocketAddr, UdpSocket},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use crate::{ErrorCode, Message, OptionType, Packet, TransferOption, Window};
+use crate::event::ProgressTracker;
+use crate::logger::elogln;
+use crate::negotiation;
+use crate::packet::MAX_REQUEST_SIZE;
+use crate::state::{
+    parse_options, BlockSource, Sequential, DEFAULT_TIMEOUT, MAX_RETRIES, RESTART_ACKS,
+};
+use crate::transfer::{self, Outcome, Transport};
+use crate::{
+    ErrorCode, Message, OptionLimits, Packet, Session, SessionAction, SessionEvent, SessionOptions,
+    TransferOption, TransferProgress,
+};
+
+/// Longest wait for a packet before checking whether the transfer was
+/// aborted through its [`TransferHandle`].
+const ABORT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sends the file at `path` from `local` to `remote`, a client waiting for
+/// it in client mode, as if it had requested the file with `options`.
+///
+/// The transfer runs on its own thread and starts with the OACK of the
+/// negotiated options, or the first block without any. Port 0 of `local`
+/// binds an ephemeral port.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::{net::SocketAddr, path::Path};
+///
+/// let local = SocketAddr::from(([0, 0, 0, 0], 0));
+/// let device = SocketAddr::from(([10, 4, 0, 12], 69));
+/// let transfer = tftpd::send_file(local, device, Path::new("config.bin"), vec![]).unwrap();
+/// println!("sent {} bytes", transfer.join().unwrap().bytes);
+/// ```
+pub fn send_file(
+    local: SocketAddr,
+    remote: SocketAddr,
+    path: &Path,
+    options: Vec<TransferOption>,
+) -> Result<TransferHandle, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let worker = Worker::bind(local, remote, Box::new(file), Some(size), options)?;
+    Ok(worker.start())
+}
+
+/// TransferHandle `struct` controls a transfer running on its own thread,
+/// returned by [`send_file()`] and [`Worker::start()`].
+#[derive(Debug)]
+pub struct TransferHandle {
+    local_addr: SocketAddr,
+    tsize: Option<u64>,
+    control: Arc<Control>,
+    tracker: ProgressTracker,
+    thread: JoinHandle<Result<TransferSummary, String>>,
+}
+
+/// TransferSummary `struct` describes a transfer that a [`Worker`]
+/// completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferSummary {
+    /// Number of bytes acknowledged by the client
+    pub bytes: u64,
+    /// Time spent reading the source
+    pub storage_time: Duration,
+    /// Time spent waiting for the client to acknowledge what was sent
+    pub network_wait: Duration,
+}
+
+impl TransferHandle {
+    /// Returns the address of the socket dedicated to the transfer.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns how far along the transfer is, the throughput being
+    /// measured since the previous call.
+    pub fn progress(&mut self) -> TransferProgress {
+        self.tracker.sample(
+            Instant::now(),
+            self.control.bytes_acked.load(Ordering::Relaxed),
+            self.tsize,
+            None,
+            self.control.retransmits.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns whether the transfer ended, so that [`TransferHandle::join()`]
+    /// returns without waiting.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
 
-pub struct WorkState {
-    pub file: PathBuf,
-    pub options: Vec<TransferOption>,
+    /// Stops the transfer, sending the client an ERROR. The transfer
+    /// notices it within `ABORT_POLL_INTERVAL`.
+    pub fn abort(&self) {
+        self.control.aborted.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the end of the transfer and returns its summary, see
+    /// [`Worker::run()`].
+    pub fn join(self) -> Result<TransferSummary, String> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err("transfer thread panicked".to_string()))
+    }
 }
 
-pub struct Worker;
+/// Limits of a transfer that the [`Server`](crate::Server) handed to a
+/// [`Worker`] thread with `--threaded`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Limits {
+    /// Wait before the first packet, from `--initial-delay`.
+    pub(crate) initial_delay: Duration,
+    /// Longest transfer, from `--max-transfer-duration`.
+    pub(crate) max_duration: Option<Duration>,
+    /// Longest silence of the client, from `--session-timeout`.
+    pub(crate) idle_timeout: Option<Duration>,
+}
 
-#[derive(PartialEq, Eq)]
-enum WorkType {
-    Send(u64),
+/// What a [`Worker`] shares with its [`TransferHandle`].
+#[derive(Debug, Default)]
+struct Control {
+    aborted: AtomicBool,
+    bytes_acked: AtomicU64,
+    retransmits: AtomicU64,
 }
 
-const MAX_RETRIES: u32 = 6;
-const DEFAULT_TIMEOUT_SECS: u64 = 5;
-const TIMEOUT_BUFFER_SECS: u64 = 1;
-const DEFAULT_BLOCK_SIZE: usize = 512;
+/// Worker `struct` sends a file from a socket dedicated to the transfer,
+/// bound to an ephemeral port as in RFC 1350, instead of the single port of
+/// the [`Server`](crate::Server).
+///
+/// Both modes drive the same [`Session`], so windows, retransmissions and
+/// termination behave the same. Retries are counted per window in both:
+/// every acknowledged window resets them.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::{io::Cursor, net::{IpAddr, SocketAddr}};
+/// use tftpd::Worker;
+///
+/// let remote = SocketAddr::from(([127, 0, 0, 1], 1234));
+/// let source = Box::new(Cursor::new(b"hello".to_vec()));
+/// let worker = Worker::new(IpAddr::from([127, 0, 0, 1]), remote, source, Some(5), vec![]).unwrap();
+/// worker.spawn();
+/// ```
+pub struct Worker {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    remote: SocketAddr,
+    source: Box<dyn BlockSource>,
+    size: Option<u64>,
+    session: Session,
+    actions: Vec<SessionAction>,
+    control: Arc<Control>,
+    limits: Limits,
+    started: Instant,
+    last_activity: Instant,
+    storage_time: Duration,
+    network_wait: Duration,
+    /// When the last packet was sent while no ACK was awaited
+    awaiting_since: Option<Instant>,
+}
 
 impl Worker {
-    /// Sends a file to the remote [`SocketAddr`] that has sent a read request using
-    /// a random port, asynchronously.
-    pub fn send(
-        addr: SocketAddr,
+    /// Binds an ephemeral port on `ip` and negotiates the `options` that
+    /// `remote` requested for sending `source`, of `size` bytes if known.
+    pub fn new(
+        ip: IpAddr,
         remote: SocketAddr,
-        file_path: PathBuf,
+        source: Box<dyn Read + Send>,
+        size: Option<u64>,
+        options: Vec<TransferOption>,
+    ) -> Result<Worker, Box<dyn Error>> {
+        Worker::bind(SocketAddr::new(ip, 0), remote, source, size, options)
+    }
+
+    /// Binds `local` and negotiates the `options` that `remote` requested
+    /// for sending `source`, of `size` bytes if known.
+    pub fn bind(
+        local: SocketAddr,
+        remote: SocketAddr,
+        source: Box<dyn Read + Send>,
+        size: Option<u64>,
         mut options: Vec<TransferOption>,
-    ) {
-        thread::spawn(move || {
-            let mut handle_send = || -> Result<(), Box<dyn Error>> {
-                let work_type = WorkType::Send(file_path.metadata()?.len());
-                let worker_options = parse_options(&mut options, &work_type)?;
+    ) -> Result<Worker, Box<dyn Error>> {
+        let socket = UdpSocket::bind(local)?;
+        let state_options = parse_options(
+            &mut options,
+            size,
+            DEFAULT_TIMEOUT,
+            &OptionLimits::default(),
+        )?;
+        negotiation::sort_oack(&mut options);
+        let oack = if options.is_empty() {
+            None
+        } else {
+            Some(options)
+        };
+        let options = SessionOptions {
+            blk_size: state_options.blk_size,
+            windowsize: state_options.windowsize,
+            timeout: state_options.timeout,
+            max_retries: MAX_RETRIES,
+            duplicate_acks: false,
+            checksum: state_options.checksum,
+            restart_acks: RESTART_ACKS,
+            max_in_flight_bytes: None,
+            rollover: state_options.rollover.unwrap_or(0),
+            reread: false,
+        };
+        let source = Box::new(Sequential::new(source));
+        Worker::with_session(
+            socket,
+            remote,
+            source,
+            size,
+            options,
+            oack,
+            Limits::default(),
+        )
+    }
+
+    /// Sends `source` from `socket` with options already negotiated by the
+    /// [`Server`](crate::Server), starting with `oack` if any once the
+    /// initial delay of `limits` elapsed.
+    pub(crate) fn with_session(
+        socket: UdpSocket,
+        remote: SocketAddr,
+        source: Box<dyn BlockSource>,
+        size: Option<u64>,
+        options: SessionOptions,
+        oack: Option<Vec<TransferOption>>,
+        limits: Limits,
+    ) -> Result<Worker, Box<dyn Error>> {
+        let local_addr = socket.local_addr()?;
+        let now = Instant::now();
+        let (session, actions) = Session::new(options, oack, now + limits.initial_delay);
 
-                accept_request(&socket, &options, &work_type)?;
-                send_file(&socket, File::open(&file_path)?, &worker_options)?;
+        Ok(Worker {
+            socket,
+            local_addr,
+            remote,
+            source,
+            size,
+            session,
+            actions,
+            control: Arc::default(),
+            limits,
+            started: now,
+            last_activity: now,
+            storage_time: Duration::ZERO,
+            network_wait: Duration::ZERO,
+            awaiting_since: None,
+        })
+    }
 
-                Ok(())
+    /// Returns the address of the socket dedicated to the transfer.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    /// Sends the file until the client acknowledged all of it, and returns
+    /// the number of bytes sent with the time spent reading and waiting for
+    /// the client. The client is sent an ERROR when the transfer times out,
+    /// exceeds its limits, the source fails to read or the transfer is
+    /// aborted.
+    pub fn run(mut self) -> Result<TransferSummary, Box<dyn Error>> {
+        let mut actions = std::mem::take(&mut self.actions);
+        if !self.delay_start() {
+            self.send_error("transfer aborted");
+            return Err("transfer aborted".into());
+        }
+        loop {
+            let mut transport = SocketTransport {
+                socket: &self.socket,
+                remote: self.remote,
+                sent: false,
+            };
+            let mut source = Timed {
+                source: &mut *self.source,
+                spent: &mut self.storage_time,
             };
+            let outcome = transfer::execute(
+                &mut self.session,
+                &mut source,
+                &mut transport,
+                Instant::now(),
+                actions,
+            );
+            if transport.sent && self.awaiting_since.is_none() {
+                self.awaiting_since = Some(Instant::now());
+            }
 
-            match handle_send() {
-                Ok(_) => {
-                    println!(
-                        "Sent {} to {}",
-                        file_path.file_name().unwrap().to_str().unwrap(),
-                        remote
-                    );
+            match outcome {
+                Outcome::Pending => {}
+                Outcome::Finished => {
+                    return Ok(TransferSummary {
+                        bytes: self.session.bytes_acked(),
+                        storage_time: self.storage_time,
+                        network_wait: self.network_wait,
+                    })
+                }
+                Outcome::Aborted(reason) => {
+                    self.send_error("transfer timed out");
+                    return Err(reason.into());
+                }
+                Outcome::Restarted(block) => {
+                    self.send_error("client appears to have restarted");
+                    return Err(format!("client restarted at block {block}").into());
                 }
-                Err(err) => {
-                    eprintln!("{err}");
+                Outcome::ReadFailed(err) => {
+                    self.send_error("error while reading file");
+                    return Err(format!("read error: {err}").into());
                 }
+                Outcome::SendFailed(err) => return Err(err),
+            }
+
+            if self.control.aborted.load(Ordering::Relaxed) {
+                self.send_error("transfer aborted");
+                return Err("transfer aborted".into());
+            }
+
+            let event = self.recv()?;
+            let now = Instant::now();
+            if matches!(event, SessionEvent::PacketReceived(_)) {
+                self.last_activity = now;
             }
-        });
+            if matches!(event, SessionEvent::PacketReceived(Packet::Ack(_))) {
+                if let Some(since) = self.awaiting_since.take() {
+                    self.network_wait += now.saturating_duration_since(since);
+                }
+            }
+            if let Some((message, reason)) = self.exceeded_limit(now) {
+                self.send_error(message);
+                return Err(reason.into());
+            }
+            actions = self.session.handle(now, event);
+            self.control
+                .bytes_acked
+                .store(self.session.bytes_acked(), Ordering::Relaxed);
+            self.control
+                .retransmits
+                .store(self.session.retransmits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Runs the transfer on its own thread, see [`Worker::run()`].
+    pub fn spawn(self) -> JoinHandle<Result<TransferSummary, String>> {
+        thread::spawn(move || self.run().map_err(|err| err.to_string()))
+    }
+
+    /// Runs the transfer on its own thread, and returns the handle to
+    /// follow or abort it.
+    pub fn start(self) -> TransferHandle {
+        TransferHandle {
+            local_addr: self.local_addr,
+            tsize: self.size,
+            control: self.control.clone(),
+            tracker: ProgressTracker::new(Instant::now()),
+            thread: self.spawn(),
+        }
     }
-}
 
-fn send_file(
-    socket: &UdpSocket,
-    file: File,
-    worker_options: &WorkerOptions,
-) -> Result<(), Box<dyn Error>> {
-    let mut block_number = 1;
-    let mut window = Window::new(worker_options.windowsize, worker_options.blk_size, file);
+    /// Waits for the initial delay of the transfer to elapse, and returns
+    /// false when the transfer was aborted meanwhile.
+    fn delay_start(&self) -> bool {
+        let start = self.started + self.limits.initial_delay;
+        loop {
+            if self.control.aborted.load(Ordering::Relaxed) {
+                return false;
+            }
+            let wait = start.saturating_duration_since(Instant::now());
+            if wait.is_zero() {
+                return true;
+            }
+            thread::sleep(wait.min(ABORT_POLL_INTERVAL));
+        }
+    }
+
+    /// Returns the ERROR message for the client and the reason of the
+    /// failure once the transfer lasted longer than its maximum duration,
+    /// or its client sent nothing for the session timeout.
+    fn exceeded_limit(&self, now: Instant) -> Option<(&'static str, String)> {
+        if let Some(max) = self.limits.max_duration {
+            if now.duration_since(self.started) >= max {
+                return Some((
+                    "transfer exceeded maximum duration",
+                    format!("exceeded the maximum duration of {}s", max.as_secs()),
+                ));
+            }
+        }
+        if let Some(timeout) = self.limits.idle_timeout {
+            if now.duration_since(self.last_activity) >= timeout {
+                return Some((
+                    "transfer timed out",
+                    format!("client idle for {}s", timeout.as_secs()),
+                ));
+            }
+        }
+        None
+    }
 
-    loop {
-        let filled = window.fill()?;
+    /// Returns when the next limit of the transfer is reached, if any.
+    fn limit_at(&self) -> Option<Instant> {
+        let deadline = self.limits.max_duration.map(|max| self.started + max);
+        let idle_until = self
+            .limits
+            .idle_timeout
+            .map(|timeout| self.last_activity + timeout);
+        deadline.into_iter().chain(idle_until).min()
+    }
 
-        let mut retry_cnt = 0;
-        let mut time =
-            SystemTime::now() - Duration::from_secs(DEFAULT_TIMEOUT_SECS + TIMEOUT_BUFFER_SECS);
+    /// Waits for the next packet of the client, for the retransmission
+    /// timeout, for a limit of the transfer or for the transfer to be
+    /// aborted. Packets from other ports are answered with an ERROR and
+    /// otherwise ignored.
+    fn recv(&self) -> Result<SessionEvent, Box<dyn Error>> {
+        let mut buf = [0; MAX_REQUEST_SIZE];
         loop {
-            if time.elapsed()? >= Duration::from_secs(DEFAULT_TIMEOUT_SECS) {
-                send_window(socket, &window, block_number)?;
-                time = SystemTime::now();
+            let wake_at = self.limit_at().map_or(self.session.retransmit_at(), |at| {
+                at.min(self.session.retransmit_at())
+            });
+            let wait = wake_at.saturating_duration_since(Instant::now());
+            if wait.is_zero() || self.control.aborted.load(Ordering::Relaxed) {
+                return Ok(SessionEvent::Tick);
             }
+            self.socket
+                .set_read_timeout(Some(wait.min(ABORT_POLL_INTERVAL)))?;
 
-            match Message::recv(socket) {
-                Ok(Packet::Ack(received_block_number)) => {
-                    let diff = received_block_number.wrapping_sub(block_number);
-                    if diff <= worker_options.windowsize {
-                        block_number = received_block_number.wrapping_add(1);
-                        window.remove(diff + 1)?;
-                        break;
-                    }
+            let (size, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
                 }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if from != self.remote {
+                elogln!("{from}: Packet for the transfer of {}", self.remote);
+                let _ = Message::send_error(
+                    &self.socket,
+                    &from,
+                    ErrorCode::UnknownId,
+                    "unknown transfer ID",
+                );
+                continue;
+            }
+
+            match Packet::deserialize(&buf[..size]) {
                 Ok(Packet::Error { code, msg }) => {
-                    return Err(format!("Received error code {code}: {msg}").into());
-                }
-                _ => {
-                    retry_cnt += 1;
-                    if retry_cnt == MAX_RETRIES {
-                        return Err(format!("Transfer timed out after {MAX_RETRIES} tries").into());
-                    }
+                    return Err(format!("Received error code {code}: {msg}").into())
                 }
+                Ok(packet) => return Ok(SessionEvent::PacketReceived(packet)),
+                Err(err) => elogln!("{from}: Ignoring malformed packet: {err}"),
             }
         }
+    }
 
-        if !filled && window.is_empty() {
-            break;
+    fn send_error(&self, message: &str) {
+        if let Err(err) =
+            Message::send_error(&self.socket, &self.remote, ErrorCode::NotDefined, message)
+        {
+            elogln!("{}: Error while sending ERROR: {err}", self.remote);
         }
     }
+}
 
-    Ok(())
+/// SocketTransport `struct` sends the packets of a [`Worker`] from its
+/// dedicated socket.
+struct SocketTransport<'a> {
+    socket: &'a UdpSocket,
+    remote: SocketAddr,
+    /// Whether a packet was sent
+    sent: bool,
+}
+
+impl Transport for SocketTransport<'_> {
+    fn send(&mut self, packet: Packet) -> Result<(), Box<dyn Error>> {
+        Message::send_packet(self.socket, &self.remote, &packet)?;
+        self.sent = true;
+        Ok(())
+    }
+}
+
+/// Timed `struct` adds the time spent reading the source of a [`Worker`]
+/// to `spent`.
+struct Timed<'a> {
+    source: &'a mut dyn BlockSource,
+    spent: &'a mut Duration,
+}
+
+impl BlockSource for Timed<'_> {
+    fn read_block(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let started = Instant::now();
+        let data = self.source.read_block(offset, len);
+        *self.spent += started.elapsed();
+        data
+    }
+
+    fn rereadable(&self) -> bool {
+        self.source.rereadable()
+    }
 }
@@ -0,0 +1,53 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet};
+
+#[test]
+fn back_to_back_acks_produce_a_single_window() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 12);
+
+    harness.rrq("image.bin", vec![option(OptionType::Windowsize, 4)]);
+    harness.ack(0);
+    assert_eq!(harness.take_sent().len(), 1 + 4);
+
+    // A per-block ACKer acknowledges every block of the window.
+    harness.send_batch(vec![Packet::Ack(1), Packet::Ack(2), Packet::Ack(4)]);
+
+    assert_eq!(
+        harness.take_sent(),
+        (5..=8)
+            .map(|block| data(
+                block,
+                &contents[(block as usize - 1) * 512..block as usize * 512]
+            ))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(harness.server.metrics().retransmits, 0);
+}
+
+#[test]
+fn stale_ack_in_batch_is_ignored() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 12);
+
+    harness.rrq("image.bin", vec![option(OptionType::Windowsize, 4)]);
+    harness.ack(0);
+    harness.take_sent();
+
+    // A retransmitted ACK of the OACK races a fresh partial ACK.
+    harness.send_batch(vec![Packet::Ack(0), Packet::Ack(2)]);
+
+    assert_eq!(
+        harness.take_sent(),
+        (3..=6)
+            .map(|block| data(
+                block,
+                &contents[(block as usize - 1) * 512..block as usize * 512]
+            ))
+            .collect::<Vec<_>>()
+    );
+}
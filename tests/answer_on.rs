@@ -0,0 +1,71 @@
+#![cfg(feature = "test-util")]
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+
+use common::{data, Harness};
+use tftpd::Packet;
+
+fn rrq_to(harness: &mut Harness, client: &UdpSocket, to: SocketAddr) {
+    let packet = Packet::Rrq {
+        filename: "image.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client.send_to(&packet.serialize().unwrap(), to).unwrap();
+    harness.server.poll().unwrap();
+}
+
+#[test]
+fn answers_only_on_listed_address() {
+    let mut harness = Harness::bound_to("0.0.0.0:0", &["--answer-on", "127.0.0.2"]);
+    let contents = harness.create_file("image.bin", 100);
+    let port = harness.server_addr().port();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    rrq_to(
+        &mut harness,
+        &client,
+        SocketAddr::from(([127, 0, 0, 3], port)),
+    );
+    assert!(harness.take_sent().is_empty());
+    assert_eq!(harness.server.metrics().wrong_destination, 1);
+
+    rrq_to(
+        &mut harness,
+        &client,
+        SocketAddr::from(([127, 0, 0, 2], port)),
+    );
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    assert_eq!(harness.server.metrics().wrong_destination, 1);
+}
+
+#[test]
+fn answers_on_listed_ipv6_address() {
+    let Ok(client) = UdpSocket::bind("[::1]:0") else {
+        // IPv6 is not available.
+        return;
+    };
+    let mut harness = Harness::bound_to("[::]:0", &["--answer-on", "::1"]);
+    let contents = harness.create_file("image.bin", 100);
+    let port = harness.server_addr().port();
+
+    rrq_to(
+        &mut harness,
+        &client,
+        SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+    );
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+
+    // IPv4 clients of the dual-stack socket are reported with their IPv4
+    // destination, which is not listed.
+    rrq_to(
+        &mut harness,
+        &UdpSocket::bind("127.0.0.1:0").unwrap(),
+        SocketAddr::from(([127, 0, 0, 1], port)),
+    );
+    assert!(harness.take_sent().is_empty());
+    assert_eq!(harness.server.metrics().wrong_destination, 1);
+}
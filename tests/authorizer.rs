@@ -0,0 +1,181 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    fs,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+};
+
+use common::{data, error, Harness, Recorder};
+use tftpd::test_util::TestDir;
+use tftpd::{Decision, ErrorCode, Packet, TransferEvent};
+
+/// Sends a read request for `filename` from another client of the harness
+/// and returns what the server sent it.
+fn rrq_from(harness: &mut Harness, client: &UdpSocket, filename: &str) -> Vec<Vec<u8>> {
+    let request = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&request.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+
+    let to = client.local_addr().unwrap();
+    let sent = harness.socket.sent();
+    harness.socket.clear_sent();
+    sent.into_iter()
+        .filter(|(addr, _)| *addr == to)
+        .map(|(_, buf)| buf)
+        .collect()
+}
+
+#[test]
+fn allows_one_client_and_denies_another() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("ipxe.efi", 100);
+    let allowed = harness.client.local_addr().unwrap();
+    harness
+        .server
+        .set_authorizer(Arc::new(move |client: &SocketAddr, _: &str| {
+            if *client == allowed {
+                Decision::Allow
+            } else {
+                Decision::Deny {
+                    code: ErrorCode::AccessViolation,
+                    message: "not provisioned yet".to_string(),
+                }
+            }
+        }));
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("ipxe.efi", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+    assert_eq!(
+        rrq_from(&mut harness, &other, "ipxe.efi"),
+        vec![error(ErrorCode::AccessViolation, "not provisioned yet")]
+    );
+    assert!(recorder.events().contains(&TransferEvent::Denied {
+        client: other.local_addr().unwrap(),
+        filename: "ipxe.efi".to_string(),
+        decision: Decision::Deny {
+            code: ErrorCode::AccessViolation,
+            message: "not provisioned yet".to_string(),
+        },
+    }));
+}
+
+#[test]
+fn masks_files_as_missing() {
+    let mut harness = Harness::new();
+    harness.create_file("secret.img", 100);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, filename: &str| {
+            if filename.starts_with("secret") {
+                Decision::NotFoundMask
+            } else {
+                Decision::Allow
+            }
+        }));
+
+    harness.rrq("secret.img", vec![]);
+    harness.rrq("missing.img", vec![]);
+
+    let not_found = error(ErrorCode::FileNotFound, "file does not exist");
+    assert_eq!(harness.take_sent(), vec![not_found.clone(), not_found]);
+}
+
+#[test]
+fn consults_the_manifest_first() {
+    let manifest_dir = TestDir::new();
+    let manifest = manifest_dir.path().join("manifest");
+    fs::write(&manifest, "listed.img\n").unwrap();
+    let mut harness = Harness::with_args(&["--manifest", manifest.to_str().unwrap()]);
+    let contents = harness.create_file("listed.img", 100);
+    harness.create_file("unlisted.img", 100);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, _: &str| Decision::Allow));
+
+    harness.rrq("unlisted.img", vec![]);
+    harness.rrq("listed.img", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![
+            error(ErrorCode::AccessViolation, "file access violation"),
+            data(1, &contents)
+        ]
+    );
+}
+
+/// Returns the payload of the single DATA packet in `sent`.
+fn payload(sent: &[Vec<u8>]) -> String {
+    let [buf] = sent else {
+        panic!("expected one packet, got {sent:?}");
+    };
+    let Packet::Data { data, .. } = Packet::deserialize(buf).unwrap() else {
+        panic!("expected DATA");
+    };
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn hides_denied_and_masked_files_from_listing() {
+    let mut harness = Harness::with_args(&["--listing-file", ".dirlist"]);
+    harness.create_file("ipxe.efi", 100);
+    harness.create_file("secret.img", 100);
+    harness.create_file("private.img", 100);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, filename: &str| match filename {
+            "secret.img" => Decision::NotFoundMask,
+            "private.img" => Decision::Deny {
+                code: ErrorCode::AccessViolation,
+                message: "private".to_string(),
+            },
+            _ => Decision::Allow,
+        }));
+
+    harness.rrq(".dirlist", vec![]);
+    let listing = payload(&harness.take_sent());
+
+    assert_eq!(listing.lines().count(), 1, "{listing}");
+    assert!(listing.starts_with("ipxe.efi\t100\t"));
+}
+
+#[test]
+fn hides_denied_images_from_menu_of_each_client() {
+    let mut harness = Harness::with_args(&["--autogen-menu", "autogen/menu.ipxe=images"]);
+    for image in ["debian", "alpine"] {
+        harness.create_file(&format!("images/{image}/vmlinuz"), 100);
+        harness.create_file(&format!("images/{image}/initrd"), 100);
+    }
+    let trusted = harness.client.local_addr().unwrap();
+    harness
+        .server
+        .set_authorizer(Arc::new(move |client: &SocketAddr, filename: &str| {
+            if *client == trusted || !filename.starts_with("images/debian/") {
+                Decision::Allow
+            } else {
+                Decision::NotFoundMask
+            }
+        }));
+
+    harness.rrq("autogen/menu.ipxe", vec![]);
+    let full = payload(&harness.take_sent());
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let restricted = payload(&rrq_from(&mut harness, &other, "autogen/menu.ipxe"));
+
+    assert!(full.contains("item debian debian"));
+    assert!(restricted.contains("item alpine alpine"));
+    assert!(!restricted.contains("debian"));
+}
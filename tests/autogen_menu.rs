@@ -0,0 +1,92 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{option, Harness};
+use tftpd::test_util::TestDir;
+use tftpd::{OptionType, Packet};
+
+/// Reads the single-block file `filename` from the harness.
+fn read(harness: &mut Harness, filename: &str) -> String {
+    harness.rrq(filename, vec![option(OptionType::TransferSize, 0)]);
+    let sent = harness.take_sent();
+    let Packet::Oack(options) = Packet::deserialize(&sent[0]).unwrap() else {
+        panic!("expected OACK");
+    };
+    harness.ack(0);
+    let Packet::Data { data, .. } = Packet::deserialize(&harness.take_sent()[0]).unwrap() else {
+        panic!("expected DATA");
+    };
+    harness.ack(1);
+    assert_eq!(options[0].value, data.len() as u64);
+    String::from_utf8(data).unwrap()
+}
+
+fn lay_out_images(harness: &Harness) {
+    for image in ["debian", "alpine"] {
+        harness.create_file(&format!("images/{image}/vmlinuz"), 100);
+        harness.create_file(&format!("images/{image}/initrd"), 100);
+    }
+    harness.create_file("images/broken/vmlinuz", 100);
+}
+
+#[test]
+fn serves_ipxe_menu_of_images() {
+    let mut harness = Harness::with_args(&["--autogen-menu", "autogen/menu.ipxe=images"]);
+    lay_out_images(&harness);
+
+    let menu = read(&mut harness, "autogen/menu.ipxe");
+
+    assert!(menu.starts_with("#!ipxe\nmenu\nitem alpine alpine\nitem debian debian\n"));
+    assert!(menu.contains(":alpine\nkernel /images/alpine/vmlinuz\ninitrd /images/alpine/initrd\n"));
+    assert!(menu.contains(":debian\nkernel /images/debian/vmlinuz\ninitrd /images/debian/initrd\n"));
+    assert!(!menu.contains("broken"));
+}
+
+#[test]
+fn serves_pxelinux_menu_between_header_and_footer() {
+    let templates = TestDir::new();
+    templates.write("header", b"DEFAULT menu.c32\n");
+    templates.write("footer", b"TIMEOUT 50\n");
+    let header = templates.path().join("header");
+    let footer = templates.path().join("footer");
+    let mut harness = Harness::with_args(&[
+        "--autogen-menu",
+        "pxelinux.cfg/default=images",
+        "--autogen-menu-header",
+        header.to_str().unwrap(),
+        "--autogen-menu-footer",
+        footer.to_str().unwrap(),
+    ]);
+    lay_out_images(&harness);
+
+    assert_eq!(
+        read(&mut harness, "pxelinux.cfg/default"),
+        "DEFAULT menu.c32\n\
+         LABEL alpine\n  KERNEL images/alpine/vmlinuz\n  INITRD images/alpine/initrd\n\
+         LABEL debian\n  KERNEL images/debian/vmlinuz\n  INITRD images/debian/initrd\n\
+         TIMEOUT 50\n"
+    );
+}
+
+#[test]
+fn leaves_out_images_outside_manifest() {
+    let manifest_dir = TestDir::new();
+    manifest_dir.write(
+        "manifest",
+        b"images/debian/vmlinuz\nimages/debian/initrd\nimages/alpine/vmlinuz\n",
+    );
+    let manifest = manifest_dir.path().join("manifest");
+    let mut harness = Harness::with_args(&[
+        "--autogen-menu",
+        "menu.ipxe=images",
+        "--manifest",
+        manifest.to_str().unwrap(),
+    ]);
+    lay_out_images(&harness);
+
+    let menu = read(&mut harness, "menu.ipxe");
+
+    assert!(menu.contains(":debian\n"));
+    assert!(!menu.contains("alpine"));
+}
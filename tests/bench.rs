@@ -0,0 +1,58 @@
+#![cfg(feature = "test-util")]
+
+use std::time::Duration;
+
+use tftpd::test_util::{TestDir, TestServer};
+use tftpd::{bench, BenchOptions};
+
+#[test]
+fn runs_tiny_bench_against_server() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("boot.img", 512 * 4 + 77);
+    let server = TestServer::start(dir.path());
+    let options = BenchOptions {
+        clients: 2,
+        duration: Duration::from_secs(1),
+        ramp: Duration::from_millis(100),
+        blk_size: Some(1024),
+        timeout: Duration::from_secs(2),
+        ..BenchOptions::new(server.addr(), "boot.img")
+    };
+
+    let report = bench(&options);
+
+    assert_eq!(report.clients, 2);
+    assert!(report.transfers > 0);
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.bytes, report.transfers * contents.len() as u64);
+    assert_eq!(report.packets, report.transfers * 3);
+    assert_eq!(report.latencies.len() as u64, report.transfers);
+    assert!(report.elapsed >= Duration::from_secs(1));
+    assert!(report.percentile(50) <= report.percentile(99));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["clients"], 2);
+    assert_eq!(json["transfers"], report.transfers);
+    assert_eq!(json["errors"], 0);
+    assert_eq!(json["bytes"], report.bytes);
+    assert!(json["latency_ms"]["p99"].as_f64().unwrap() > 0.0);
+    assert!(report.to_string().starts_with("2 clients, "));
+    assert_eq!(server.shutdown().metrics().completed, report.transfers);
+}
+
+#[test]
+fn counts_errors_for_missing_file() {
+    let dir = TestDir::new();
+    let server = TestServer::start(dir.path());
+    let options = BenchOptions {
+        duration: Duration::from_millis(300),
+        ramp: Duration::ZERO,
+        ..BenchOptions::new(server.addr(), "missing.img")
+    };
+
+    let report = bench(&options);
+
+    assert_eq!(report.transfers, 0);
+    assert!(report.errors > 0);
+    assert_eq!(report.percentile(50), None);
+}
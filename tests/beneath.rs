@@ -0,0 +1,34 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, Harness};
+
+#[test]
+fn serves_files_in_beneath_mode() {
+    let mut harness = Harness::with_args(&["--beneath"]);
+    let contents = harness.create_file("image.bin", 700);
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+    harness.ack(2);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(1, &contents[..512]), data(2, &contents[512..])]
+    );
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn serves_backslash_paths_in_beneath_mode() {
+    let mut harness = Harness::with_args(&["--beneath"]);
+    std::fs::create_dir(harness.dir.path().join("subdir")).unwrap();
+    let contents = harness.create_file("subdir/pxelinux.0", 100);
+
+    harness.rrq("subdir\\pxelinux.0", vec![]);
+    harness.ack(1);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
@@ -0,0 +1,103 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+/// Requests `image.bin` with a block size of 1468 through a path dropping
+/// every datagram larger than 600 bytes, acknowledges the OACK and waits
+/// for the server to give up. Returns the ERROR it sent.
+fn blackholed_transfer(harness: &mut Harness) -> Packet {
+    harness.socket.drop_outgoing_if(|buf| buf.len() > 600);
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 1468)]);
+    harness.ack(0);
+    for _ in 0..7 {
+        harness.advance(Duration::from_secs(5));
+    }
+
+    let sent = harness.take_sent();
+    Packet::deserialize(sent.last().unwrap()).unwrap()
+}
+
+#[test]
+fn hints_at_mtu_when_first_window_is_lost() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 4000);
+
+    let Packet::Error { code, msg } = blackholed_transfer(&mut harness) else {
+        panic!("expected an error");
+    };
+    assert_eq!(code, ErrorCode::NotDefined);
+    assert!(msg.contains("MTU"), "{msg}");
+    assert!(msg.contains("blksize 512"), "{msg}");
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.blksize_blackholes, 1);
+    assert_eq!(metrics.failed, 1);
+
+    // Without --auto-shrink-blksize, the next request is served as asked.
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 1468)]);
+    assert_eq!(
+        harness.take_sent(),
+        vec![Packet::Oack(vec![option(OptionType::BlockSize, 1468)])
+            .serialize()
+            .unwrap()]
+    );
+    assert_eq!(harness.server.metrics().blksize_shrinks, 0);
+}
+
+#[test]
+fn plain_timeout_with_small_blocks() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 4000);
+    harness.socket.drop_outgoing_if(|buf| buf.len() > 600);
+
+    // Small blocks get through, the client is simply gone.
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 512)]);
+    harness.ack(0);
+    for _ in 0..7 {
+        harness.advance(Duration::from_secs(5));
+    }
+    let sent = harness.take_sent();
+    assert_eq!(
+        Packet::deserialize(sent.last().unwrap()).unwrap(),
+        Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "transfer timed out".to_string(),
+        }
+    );
+    assert_eq!(harness.server.metrics().blksize_blackholes, 0);
+}
+
+#[test]
+fn shrinks_blksize_of_next_request() {
+    let mut harness = Harness::with_args(&["--auto-shrink-blksize"]);
+    let contents = harness.create_file("image.bin", 512 * 2 + 77);
+
+    blackholed_transfer(&mut harness);
+
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 1468)]);
+    harness.ack(0);
+    harness.ack(1);
+    harness.ack(2);
+    harness.ack(3);
+    assert_eq!(
+        harness.take_sent(),
+        vec![
+            Packet::Oack(vec![option(OptionType::BlockSize, 512)])
+                .serialize()
+                .unwrap(),
+            data(1, &contents[..512]),
+            data(2, &contents[512..1024]),
+            data(3, &contents[1024..]),
+        ]
+    );
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.blksize_blackholes, 1);
+    assert_eq!(metrics.blksize_shrinks, 1);
+    assert_eq!(metrics.completed, 1);
+}
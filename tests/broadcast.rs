@@ -0,0 +1,60 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use common::{data, Harness};
+
+fn discovery_harness(args: &[&str]) -> Harness {
+    let harness = Harness::with_args(args);
+    harness
+        .socket
+        .set_destination(IpAddr::V4(Ipv4Addr::BROADCAST));
+    harness
+}
+
+#[test]
+fn answers_discovery_for_existing_file_by_default() {
+    let mut harness = discovery_harness(&[]);
+    let contents = harness.create_file("phone.cfg", 100);
+
+    harness.rrq("missing.cfg", vec![]);
+    assert!(harness.take_sent().is_empty());
+    assert_eq!(harness.server.metrics().ignored_discovery, 1);
+
+    harness.rrq("phone.cfg", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn never_answers_discovery() {
+    let mut harness = discovery_harness(&["--answer-broadcast", "never"]);
+    harness.create_file("phone.cfg", 100);
+
+    harness.rrq("phone.cfg", vec![]);
+
+    assert!(harness.take_sent().is_empty());
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(harness.server.metrics().ignored_discovery, 1);
+}
+
+#[test]
+fn always_answers_discovery() {
+    let mut harness = discovery_harness(&["--answer-broadcast", "always"]);
+
+    harness.rrq("missing.cfg", vec![]);
+
+    assert_eq!(harness.take_sent().len(), 1);
+    assert_eq!(harness.server.metrics().ignored_discovery, 0);
+}
+
+#[test]
+fn unicast_requests_are_not_affected() {
+    let mut harness = Harness::with_args(&["--answer-broadcast", "never"]);
+    let contents = harness.create_file("phone.cfg", 100);
+
+    harness.rrq("phone.cfg", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{net::UdpSocket, thread, time::Duration};
+
+use common::{data, option, Harness};
+use tftpd::{ChecksumAlgorithm, Client, Download, OptionType, Packet, TransferOption};
+
+fn xsum() -> TransferOption {
+    option(OptionType::Checksum, ChecksumAlgorithm::Crc32c.value())
+}
+
+/// Downloads `filename` with a [`Client`] while the server polls.
+fn download(
+    harness: &mut Harness,
+    filename: &'static str,
+    options: Vec<TransferOption>,
+) -> Result<Download, String> {
+    let mut client = Client::new(harness.server_addr());
+    client.set_timeout(Duration::from_secs(2));
+    let download =
+        thread::spawn(move || client.get(filename, options).map_err(|err| err.to_string()));
+    while !download.is_finished() {
+        harness.server.poll().unwrap();
+    }
+    download.join().unwrap()
+}
+
+#[test]
+fn verifies_checksum_of_intact_file() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 512 * 6 + 77);
+
+    let download = download(
+        &mut harness,
+        "boot.img",
+        vec![xsum(), option(OptionType::Windowsize, 4)],
+    )
+    .unwrap();
+
+    assert_eq!(download.data, contents);
+    assert_eq!(download.checksum, Some(ChecksumAlgorithm::Crc32c));
+    assert_eq!(
+        download.options,
+        vec![option(OptionType::Windowsize, 4), xsum()]
+    );
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn detects_block_corrupted_in_flight() {
+    let mut harness = Harness::new();
+    harness.create_file("boot.img", 512 * 2 + 77);
+    harness
+        .socket
+        .corrupt_outgoing_if(|buf| buf.starts_with(&[0, 3, 0, 2]));
+
+    let err = download(&mut harness, "boot.img", vec![xsum()]).unwrap_err();
+
+    assert!(err.contains("checksum mismatch"), "{err}");
+}
+
+#[test]
+fn sends_no_checksum_unless_requested() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    harness.ack(1);
+    assert!(harness.take_sent().is_empty());
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn ignores_unknown_algorithm() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+
+    let request = [&[0, 1][..], b"boot.img\0octet\0xsum\0md5\0"].concat();
+    harness.send_raw(&request);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn counts_malformed_packets() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut client = Client::new(server.local_addr().unwrap());
+    client.set_timeout(Duration::from_secs(2));
+    let download = thread::spawn(move || client.get("boot.cfg", vec![]).unwrap());
+
+    let mut buf = [0; 512];
+    let (_, from) = server.recv_from(&mut buf).unwrap();
+    server.send_to(&[0, 42, 1, 2], from).unwrap();
+    server.send_to(&data(1, b"default linux\n"), from).unwrap();
+    let (size, _) = server.recv_from(&mut buf).unwrap();
+    let download = download.join().unwrap();
+
+    assert_eq!(Packet::deserialize(&buf[..size]).unwrap(), Packet::Ack(1));
+    assert_eq!(download.data, b"default linux\n");
+    assert_eq!(download.malformed, 1);
+}
@@ -0,0 +1,133 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::io::Cursor;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+fn restarted() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "client appears to have restarted")
+}
+
+/// Starts a transfer of a five-block file and acknowledges its first three
+/// blocks, returning the contents.
+fn transfer_three_blocks(harness: &mut Harness) -> Vec<u8> {
+    let contents = harness.create_file("boot.img", 512 * 4 + 77);
+    harness.rrq("boot.img", vec![]);
+    for block in 1..=3 {
+        harness.ack(block);
+    }
+    harness.take_sent();
+    contents
+}
+
+#[test]
+fn aborts_restarted_client() {
+    let mut harness = Harness::new();
+    let contents = transfer_three_blocks(&mut harness);
+
+    // The bootloader starts over and acknowledges the first block again.
+    harness.ack(1);
+    harness.ack(1);
+    assert_eq!(
+        harness.take_sent(),
+        vec![
+            data(4, &contents[1536..2048]),
+            data(4, &contents[1536..2048])
+        ]
+    );
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), vec![restarted()]);
+    assert_eq!(harness.server.session_count(), 0);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.client_restarts, 1);
+    assert_eq!(metrics.failed, 1);
+
+    // It then asks for the file again.
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+}
+
+#[test]
+fn resumes_restarted_client() {
+    let mut harness = Harness::with_args(&["--allow-mid-session-restart"]);
+    let contents = transfer_three_blocks(&mut harness);
+
+    for _ in 0..3 {
+        harness.ack(0);
+    }
+    let sent = harness.take_sent();
+    assert_eq!(sent.last(), Some(&data(1, &contents[..512])));
+
+    let mut received = vec![];
+    for block in 1..=5 {
+        harness.ack(block);
+        received.extend(harness.take_sent());
+    }
+    assert_eq!(
+        received,
+        vec![
+            data(2, &contents[512..1024]),
+            data(3, &contents[1024..1536]),
+            data(4, &contents[1536..2048]),
+            data(5, &contents[2048..]),
+        ]
+    );
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.client_restarts, 1);
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.failed, 0);
+}
+
+#[test]
+fn resumes_after_acknowledged_block() {
+    let mut harness =
+        Harness::with_args(&["--allow-mid-session-restart", "--restart-threshold", "2"]);
+    let contents = transfer_three_blocks(&mut harness);
+
+    harness.ack(1);
+    harness.ack(1);
+    assert_eq!(
+        harness.take_sent().last(),
+        Some(&data(2, &contents[512..1024]))
+    );
+    harness.ack(2);
+    assert_eq!(harness.take_sent(), vec![data(3, &contents[1024..1536])]);
+}
+
+#[test]
+fn aborts_restart_of_pipe() {
+    let mut harness =
+        Harness::with_args(&["--allow-mid-session-restart", "--pipe", "firmware.bin"]);
+    harness
+        .server
+        .set_pipe_source(Box::new(Cursor::new(vec![7; 512 * 4 + 77])));
+
+    harness.rrq("firmware.bin", vec![]);
+    for block in 1..=3 {
+        harness.ack(block);
+    }
+    harness.take_sent();
+    for _ in 0..3 {
+        harness.ack(1);
+    }
+
+    assert_eq!(harness.take_sent().last(), Some(&restarted()));
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn threshold_zero_keeps_ignoring_old_acks() {
+    let mut harness = Harness::with_args(&["--restart-threshold", "0"]);
+    let contents = transfer_three_blocks(&mut harness);
+
+    for _ in 0..5 {
+        harness.ack(1);
+    }
+
+    assert_eq!(harness.take_sent(), vec![data(4, &contents[1536..2048]); 5]);
+    assert_eq!(harness.server.session_count(), 1);
+    assert_eq!(harness.server.metrics().client_restarts, 0);
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::UdpSocket;
+
+use common::{data, error, Harness};
+use tftpd::{ClientSessions, ErrorCode, Packet};
+
+/// Sends a read request for `filename` from another port of the client host.
+fn rrq_from(harness: &mut Harness, socket: &UdpSocket, filename: &str) {
+    let rrq = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+#[test]
+fn sessions_of_one_host_share_the_limit() {
+    let mut harness = Harness::with_args(&["--max-per-ip", "2"]);
+    let first = harness.create_file("first.bin", 700);
+    harness.create_file("second.bin", 700);
+    harness.create_file("third.bin", 700);
+    let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let third = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("first.bin", vec![]);
+    rrq_from(&mut harness, &second, "second.bin");
+    assert_eq!(
+        harness.server.active_sessions(),
+        vec![ClientSessions {
+            ip: [127, 0, 0, 1].into(),
+            sessions: vec![
+                harness.client.local_addr().unwrap(),
+                second.local_addr().unwrap(),
+            ],
+        }]
+    );
+
+    harness.take_sent();
+    rrq_from(&mut harness, &third, "third.bin");
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(
+            ErrorCode::NotDefined,
+            "too many transfers, retry later"
+        )]
+    );
+    assert_eq!(harness.server.metrics().client_rejections, 1);
+    assert_eq!(harness.server.session_count(), 2);
+
+    // Once the first transfer completes, the host has room again.
+    assert_eq!(harness.recv().unwrap(), data(1, &first[..512]));
+    harness.ack(1);
+    harness.ack(2);
+    assert_eq!(harness.server.metrics().completed, 1);
+    rrq_from(&mut harness, &third, "third.bin");
+    let sessions = harness.server.active_sessions();
+    assert_eq!(
+        sessions[0].sessions,
+        vec![second.local_addr().unwrap(), third.local_addr().unwrap()]
+    );
+}
+
+#[test]
+fn forgets_clients_without_sessions() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 100);
+
+    harness.rrq("image.bin", vec![]);
+    assert_eq!(harness.server.active_sessions().len(), 1);
+    harness.ack(1);
+    assert_eq!(harness.server.active_sessions(), vec![]);
+}
@@ -0,0 +1,28 @@
+#![cfg(all(feature = "test-util", target_os = "linux"))]
+
+mod common;
+
+use std::fs;
+
+use common::{data, Harness};
+
+#[test]
+fn serves_per_mac_file() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("00:11:22:33:44:55.ipxe", 100);
+
+    harness.rrq("00:11:22:33:44:55.ipxe", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn serves_per_mac_file_in_subdirectory() {
+    let mut harness = Harness::new();
+    fs::create_dir(harness.dir.path().join("pxelinux.cfg")).unwrap();
+    let contents = harness.create_file("pxelinux.cfg/01:00:11:22:33:44:55", 100);
+
+    harness.rrq("pxelinux.cfg\\01:00:11:22:33:44:55", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
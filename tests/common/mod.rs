@@ -0,0 +1,192 @@
+#![allow(dead_code)]
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tftpd::test_util::{FaultySocket, MockClock, TestDir};
+use tftpd::{
+    Config, ErrorCode, Observer, OptionType, Packet, Server, TransferEvent, TransferOption,
+};
+
+/// An [`Observer`] keeping every event it receives.
+#[derive(Default)]
+pub struct Recorder {
+    events: Mutex<Vec<TransferEvent>>,
+}
+
+impl Recorder {
+    pub fn events(&self) -> Vec<TransferEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Observer for Recorder {
+    fn on_event(&self, event: &TransferEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// An in-process server on a loopback [`FaultySocket`] driven by a
+/// [`MockClock`], together with a plain client socket.
+pub struct Harness {
+    pub server: Server,
+    pub socket: Arc<FaultySocket>,
+    pub clock: Arc<MockClock>,
+    pub client: UdpSocket,
+    pub dir: TestDir,
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Harness::with_args(&[])
+    }
+
+    pub fn with_args(args: &[&str]) -> Harness {
+        Harness::bound_to("127.0.0.1:0", args)
+    }
+
+    /// Creates the harness with the server socket bound to `bind`.
+    pub fn bound_to(bind: &str, args: &[&str]) -> Harness {
+        let dir = TestDir::new();
+        let dir_arg = dir.path().to_str().unwrap().to_string();
+        let config = Config::new(
+            ["/", "-d", &dir_arg]
+                .iter()
+                .chain(args.iter())
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        let socket = Arc::new(FaultySocket::new(UdpSocket::bind(bind).unwrap()));
+        let clock = Arc::new(MockClock::new());
+        let mut server = Server::with_socket(&config, socket.clone()).unwrap();
+        server.set_clock(clock.clone());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        Harness {
+            server,
+            socket,
+            clock,
+            client,
+            dir,
+        }
+    }
+
+    /// Writes a file of `size` bytes with a repeating pattern into the
+    /// served directory and returns its contents.
+    pub fn create_file(&self, name: &str, size: usize) -> Vec<u8> {
+        self.dir.create_file(name, size)
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        use tftpd::Socket;
+        self.socket.local_addr().unwrap()
+    }
+
+    /// Sends a raw datagram from the client and lets the server handle it.
+    pub fn send_raw(&mut self, buf: &[u8]) {
+        self.client.send_to(buf, self.server_addr()).unwrap();
+        self.server.poll().unwrap();
+    }
+
+    /// Sends several packets back to back before the server gets to handle
+    /// any of them.
+    pub fn send_batch(&mut self, packets: Vec<Packet>) {
+        for packet in packets {
+            self.client
+                .send_to(&packet.serialize().unwrap(), self.server_addr())
+                .unwrap();
+        }
+        self.server.poll().unwrap();
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        let buf = packet.serialize().unwrap();
+        self.send_raw(&buf);
+    }
+
+    pub fn rrq(&mut self, filename: &str, options: Vec<TransferOption>) {
+        self.send(Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options,
+        });
+    }
+
+    pub fn ack(&mut self, block: u16) {
+        self.send(Packet::Ack(block));
+    }
+
+    /// Advances the mock clock and lets the server service its timers.
+    pub fn advance(&mut self, duration: Duration) {
+        self.clock.advance(duration);
+        self.server.poll().unwrap();
+    }
+
+    /// Returns the datagrams the server sent since the last call.
+    pub fn take_sent(&self) -> Vec<Vec<u8>> {
+        let sent = self.socket.sent().into_iter().map(|(_, buf)| buf).collect();
+        self.socket.clear_sent();
+        sent
+    }
+
+    /// Receives the next datagram at the client, or `None` after a timeout.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        let mut buf = [0; 65536];
+        self.client
+            .recv_from(&mut buf)
+            .ok()
+            .map(|(size, _)| buf[..size].to_vec())
+    }
+
+    /// Discards every datagram waiting at the client.
+    pub fn drain_client(&self) {
+        self.client
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        while self.recv().is_some() {}
+        self.client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+    }
+}
+
+/// Fails the test when a self-check of the server found its session
+/// bookkeeping out of step, which debug builds do on every tick.
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            assert_eq!(self.server.metrics().self_check_failures, 0);
+        }
+    }
+}
+
+pub fn option(option: OptionType, value: u64) -> TransferOption {
+    TransferOption { option, value }
+}
+
+pub fn data(block_num: u16, data: &[u8]) -> Vec<u8> {
+    Packet::Data {
+        block_num,
+        data: data.to_vec(),
+    }
+    .serialize()
+    .unwrap()
+}
+
+pub fn error(code: ErrorCode, msg: &str) -> Vec<u8> {
+    Packet::Error {
+        code,
+        msg: msg.to_string(),
+    }
+    .serialize()
+    .unwrap()
+}
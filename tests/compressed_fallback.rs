@@ -0,0 +1,111 @@
+#![cfg(all(feature = "gzip", feature = "test-util"))]
+
+mod common;
+
+use std::{fs, io::Write};
+
+use common::{data, error, option, Harness};
+use flate2::{write::GzEncoder, Compression};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+fn gzip(contents: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(contents).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn serves_decompressed_variant() {
+    let mut harness = Harness::with_args(&["--serve-compressed-fallback"]);
+    let contents: Vec<u8> = (0..512 * 3 + 100).map(|i| (i % 13) as u8).collect();
+    fs::write(harness.dir.path().join("initrd.img.gz"), gzip(&contents)).unwrap();
+
+    harness.rrq(
+        "initrd.img",
+        vec![
+            option(OptionType::TransferSize, 0),
+            option(OptionType::Windowsize, 2),
+        ],
+    );
+    assert_eq!(
+        harness.take_sent(),
+        vec![Packet::Oack(vec![
+            option(OptionType::TransferSize, contents.len() as u64),
+            option(OptionType::Windowsize, 2),
+        ])
+        .serialize()
+        .unwrap()]
+    );
+
+    harness.ack(0);
+    harness.ack(2);
+    harness.ack(4);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![
+            data(1, &contents[..512]),
+            data(2, &contents[512..1024]),
+            data(3, &contents[1024..1536]),
+            data(4, &contents[1536..]),
+        ]
+    );
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn corrupted_variant_aborts_transfer() {
+    let mut harness = Harness::with_args(&["--serve-compressed-fallback"]);
+    let mut seed = 7u32;
+    let contents: Vec<u8> = (0..4000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    let mut compressed = gzip(&contents);
+    let middle = compressed.len() / 2;
+    compressed[middle] ^= 0xff;
+    fs::write(harness.dir.path().join("initrd.img.gz"), compressed).unwrap();
+    let error = error(ErrorCode::NotDefined, "error while reading file");
+
+    harness.rrq("initrd.img", vec![]);
+    let mut sent = harness.take_sent();
+    for block in 1..=8 {
+        if sent.contains(&error) {
+            break;
+        }
+        harness.ack(block);
+        sent.extend(harness.take_sent());
+    }
+
+    assert_eq!(sent.last(), Some(&error));
+    assert_eq!(harness.server.session_count(), 0);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.completed, 0);
+}
+
+#[test]
+fn uncompressed_file_takes_precedence() {
+    let mut harness = Harness::with_args(&["--serve-compressed-fallback"]);
+    let contents = harness.create_file("initrd.img", 100);
+    fs::write(harness.dir.path().join("initrd.img.gz"), gzip(b"other")).unwrap();
+
+    harness.rrq("initrd.img", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn compressed_name_is_not_synthesized() {
+    let mut harness = Harness::with_args(&["--serve-compressed-fallback"]);
+    harness.create_file("initrd.img", 100);
+
+    harness.rrq("initrd.img.gz", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::FileNotFound, "file does not exist")]
+    );
+}
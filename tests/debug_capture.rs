@@ -0,0 +1,92 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use common::{error, Harness};
+use tftpd::{CaptureReason, Decision, Direction, ErrorCode, Packet, CAPTURED_BYTES};
+
+#[test]
+fn captures_malformed_packets() {
+    let mut harness = Harness::new();
+    let client = harness.client.local_addr().unwrap();
+
+    harness.send_raw(b"\x00\x09garbage");
+
+    let captured = harness.server.debug_capture();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].peer, client);
+    assert_eq!(captured[0].direction, Direction::ToServer);
+    assert_eq!(captured[0].reason, CaptureReason::Malformed);
+    assert_eq!(captured[0].bytes, b"\x00\x09garbage");
+    assert_eq!(captured[0].len, 9);
+    assert!(harness.take_sent().is_empty());
+}
+
+#[test]
+fn captures_denied_requests() {
+    let mut harness = Harness::new();
+    harness.create_file("ipxe.efi", 100);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, _: &str| Decision::Deny {
+            code: ErrorCode::AccessViolation,
+            message: "not provisioned yet".to_string(),
+        }));
+
+    harness.rrq("ipxe.efi", vec![]);
+
+    let error = error(ErrorCode::AccessViolation, "not provisioned yet");
+    let captured = harness.server.debug_capture();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].direction, Direction::ToClient);
+    assert_eq!(captured[0].reason, CaptureReason::Denied);
+    assert_eq!(captured[0].bytes, error);
+    assert!(captured[0].detail.starts_with("refused ipxe.efi"));
+    assert_eq!(harness.take_sent(), [error]);
+}
+
+#[test]
+fn captures_packets_answered_with_protocol_error() {
+    let mut harness = Harness::new();
+    let packet = Packet::Data {
+        block_num: 1,
+        data: vec![0; 200],
+    }
+    .serialize()
+    .unwrap();
+
+    harness.send_raw(&packet);
+
+    let captured = harness.server.debug_capture();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].reason, CaptureReason::ProtocolError);
+    assert_eq!(captured[0].len, 204);
+    assert_eq!(captured[0].bytes, packet[..CAPTURED_BYTES]);
+}
+
+#[test]
+fn keeps_configured_number_of_packets() {
+    let mut harness = Harness::with_args(&["--debug-capture", "2"]);
+
+    for opcode in [7, 8, 9] {
+        harness.send_raw(&[0, opcode]);
+    }
+
+    let captured = harness.server.debug_capture();
+    let bytes: Vec<_> = captured.iter().map(|packet| packet.bytes.clone()).collect();
+    assert_eq!(bytes, [vec![0, 8], vec![0, 9]]);
+
+    harness.server.clear_debug_capture();
+    assert!(harness.server.debug_capture().is_empty());
+}
+
+#[test]
+fn captures_nothing_when_disabled() {
+    let mut harness = Harness::with_args(&["--debug-capture", "0"]);
+
+    harness.send_raw(b"\x00\x09garbage");
+
+    assert!(harness.server.debug_capture().is_empty());
+}
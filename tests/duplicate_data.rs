@@ -0,0 +1,76 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use common::Harness;
+use tftpd::{MetricsSnapshot, Packet};
+
+const BLOCKS: usize = 100;
+
+/// Transfers a file of [`BLOCKS`] blocks with a client acknowledging every
+/// DATA packet it receives, including copies, while the server drops about
+/// `loss_percent` of its DATA packets. Returns the server metrics.
+fn transfer(args: &[&str], loss_percent: u64) -> MetricsSnapshot {
+    let mut harness = Harness::with_args(args);
+    let contents = harness.create_file("image.bin", 512 * BLOCKS + 100);
+    let counter = AtomicU64::new(0);
+    harness.socket.drop_outgoing_if(move |buf| {
+        let n = counter.fetch_add(1, Ordering::Relaxed);
+        let hash = n.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+        buf[1] == 3 && hash % 100 < loss_percent
+    });
+    harness
+        .client
+        .set_read_timeout(Some(Duration::from_millis(20)))
+        .unwrap();
+
+    harness.rrq("image.bin", vec![]);
+    let mut received = vec![];
+    let mut expected = 1u16;
+    while received.len() < contents.len() {
+        match harness.recv() {
+            Some(buf) => match Packet::deserialize(&buf).unwrap() {
+                Packet::Data { block_num, data } => {
+                    if block_num == expected {
+                        received.extend_from_slice(&data);
+                        expected += 1;
+                    }
+                    harness.ack(block_num);
+                }
+                packet => panic!("unexpected {packet:?}"),
+            },
+            None => harness.advance(Duration::from_secs(5)),
+        }
+    }
+
+    assert_eq!(received, contents);
+    assert_eq!(harness.server.session_count(), 0);
+    harness.server.metrics()
+}
+
+#[test]
+fn duplicate_acks_do_not_trigger_retransmits() {
+    let metrics = transfer(&["--duplicate-data", "2"], 0);
+
+    assert_eq!(metrics.retransmits, 0);
+    assert_eq!(metrics.duplicate_data, BLOCKS as u64 + 1);
+    assert_eq!(metrics.bytes_sent, 2 * (512 * BLOCKS as u64 + 100));
+}
+
+#[test]
+fn duplicates_reduce_timeouts_on_lossy_links() {
+    let single = transfer(&[], 30);
+    let double = transfer(&["--duplicate-data", "2"], 30);
+
+    println!(
+        "retransmits with 1 copy: {}, with 2 copies: {}",
+        single.retransmits, double.retransmits
+    );
+    assert!(single.retransmits >= 15);
+    assert!(double.retransmits * 2 < single.retransmits);
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+fn empty_filename() -> Vec<u8> {
+    error(ErrorCode::FileNotFound, "empty filename")
+}
+
+#[test]
+fn refuses_empty_filename() {
+    let mut harness = Harness::new();
+
+    harness.rrq("", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![empty_filename()]);
+    assert_eq!(harness.server.session_count(), 0);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.empty_requests, 1);
+    assert_eq!(metrics.failed, 0);
+}
+
+#[test]
+fn refuses_whitespace_and_bare_root() {
+    let mut harness = Harness::new();
+    harness.create_file(" ", 100);
+
+    for filename in [" ", "\t\n", "/", "// "] {
+        harness.rrq(filename, vec![]);
+
+        assert_eq!(harness.take_sent(), vec![empty_filename()], "{filename:?}");
+    }
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(harness.server.metrics().empty_requests, 4);
+}
+
+#[test]
+fn maps_empty_filename_to_file() {
+    let mut harness = Harness::with_args(&["--empty-request-file", "pxelinux.0"]);
+    let contents = harness.create_file("pxelinux.0", 100);
+
+    for filename in ["", "  ", "/"] {
+        harness.rrq(filename, vec![]);
+        assert_eq!(
+            harness.take_sent(),
+            vec![data(1, &contents)],
+            "{filename:?}"
+        );
+        harness.ack(1);
+    }
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.empty_requests, 3);
+    assert_eq!(metrics.completed, 3);
+}
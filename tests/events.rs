@@ -0,0 +1,57 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{Harness, Recorder};
+use tftpd::TransferEvent;
+
+#[test]
+fn emits_progress_at_most_once_per_second() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 512 * 10);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_millis(500));
+    harness.ack(1);
+    harness.ack(2);
+    harness.advance(Duration::from_millis(500));
+    harness.ack(3);
+    harness.ack(4);
+
+    let progress: Vec<_> = recorder
+        .events()
+        .into_iter()
+        .filter_map(|event| match event {
+            TransferEvent::Progress { progress, .. } => Some(progress),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(progress.len(), 1);
+    assert_eq!(progress[0].bytes_acked, 1024);
+    assert_eq!(progress[0].tsize, Some(5120));
+    assert_eq!(progress[0].average_throughput, 1024.0);
+    assert_eq!(progress[0].eta, Some(Duration::from_secs(4)));
+}
+
+#[test]
+fn emits_lifecycle_events() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 100);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+
+    let events = recorder.events();
+    assert!(matches!(events[0], TransferEvent::Started { .. }));
+    assert!(matches!(
+        events[1],
+        TransferEvent::Completed { bytes: 100, .. }
+    ));
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "test-util")]
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::Harness;
+use tftpd::test_util::TestDir;
+use tftpd::{ErrorCode, Packet};
+
+/// Returns a command writing the `TFTP_` environment variables to `path`.
+fn dump_env(path: &Path) -> String {
+    let path = path.display();
+    format!("env | grep ^TFTP_ > {path}.tmp && mv {path}.tmp {path}")
+}
+
+/// Waits for the hook to write `path` and parses the variables.
+fn read_env(path: &Path) -> HashMap<String, String> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !path.exists() {
+        assert!(Instant::now() < deadline, "hook did not run");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn runs_hooks_with_transfer_environment() {
+    let out = TestDir::new();
+    let complete = out.path().join("complete.env");
+    let fail = out.path().join("fail.env");
+    let mut harness = Harness::with_args(&[
+        "--exec-on-complete",
+        &dump_env(&complete),
+        "--exec-on-fail",
+        &dump_env(&fail),
+    ]);
+    harness.create_file("image.bin", 700);
+    let client = harness.client.local_addr().unwrap().to_string();
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_millis(1500));
+    harness.ack(1);
+    harness.ack(2);
+
+    let env = read_env(&complete);
+    assert_eq!(env["TFTP_CLIENT"], client);
+    assert!(env["TFTP_FILE"].ends_with("image.bin"));
+    assert_eq!(env["TFTP_BYTES"], "700");
+    assert_eq!(env["TFTP_DURATION_MS"], "1500");
+    assert_eq!(env["TFTP_RESULT"], "complete");
+    assert_eq!(env["TFTP_JOURNEY"], "1");
+    assert!(!fail.exists());
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+    harness.send(Packet::Error {
+        code: ErrorCode::NotDefined,
+        msg: "cancelled".to_string(),
+    });
+
+    let env = read_env(&fail);
+    assert_eq!(env["TFTP_BYTES"], "512");
+    assert_eq!(env["TFTP_RESULT"], "failed");
+    assert_eq!(env["TFTP_JOURNEY"], "1");
+}
@@ -0,0 +1,242 @@
+#![cfg(feature = "cli-min")]
+
+use std::net::UdpSocket;
+use std::process::Command;
+
+fn run(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_tftpd-read-only-docker"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn exits_2_on_invalid_flag() {
+    assert_eq!(run(&["--no-such-flag"]), Some(2));
+    assert_eq!(run(&["-p", "not-a-port"]), Some(2));
+}
+
+#[cfg(not(feature = "cli"))]
+#[test]
+fn minimal_build_exits_2_on_full_build_flag() {
+    assert_eq!(run(&["--loose-tid"]), Some(2));
+    assert_eq!(run(&["-p", "6969", "--dry-run"]), Some(2));
+}
+
+#[test]
+fn serves_file_with_minimal_flags() {
+    use std::process::Stdio;
+    use std::time::Duration;
+    use tftpd::{Packet, TransferOption};
+
+    let dir = tempfile::tempdir().unwrap();
+    let contents: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.path().join("pxelinux.0"), &contents).unwrap();
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tftpd-read-only-docker"))
+        .args([
+            "-i",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+            "-d",
+            dir.path().to_str().unwrap(),
+            "--max-blksize",
+            "1024",
+            "--retransmit-timeout",
+            "1000",
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let rrq = Packet::Rrq {
+        filename: "pxelinux.0".to_string(),
+        mode: "octet".to_string(),
+        options: vec![TransferOption {
+            option: tftpd::OptionType::BlockSize,
+            value: 1468,
+        }],
+    };
+    let mut buf = [0; 2048];
+    // Retry until the server is listening.
+    let (size, from) = loop {
+        client
+            .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+            .unwrap();
+        if let Ok(received) = client.recv_from(&mut buf) {
+            break received;
+        }
+    };
+    let Packet::Oack(options) = Packet::deserialize(&buf[..size]).unwrap() else {
+        panic!("expected OACK");
+    };
+    assert_eq!(options[0].value, 1024);
+
+    let mut received = vec![];
+    let mut block = 0;
+    loop {
+        client
+            .send_to(&Packet::Ack(block).serialize().unwrap(), from)
+            .unwrap();
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        let Packet::Data { block_num, data } = Packet::deserialize(&buf[..size]).unwrap() else {
+            panic!("expected data");
+        };
+        assert_eq!(block_num, block + 1);
+        received.extend_from_slice(&data);
+        block = block_num;
+        if data.len() < 1024 {
+            break;
+        }
+    }
+    client
+        .send_to(&Packet::Ack(block).serialize().unwrap(), from)
+        .unwrap();
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(received, contents);
+}
+
+#[test]
+fn exits_3_on_unavailable_address() {
+    // TEST-NET-1 is never assigned to a local interface.
+    assert_eq!(run(&["-i", "192.0.2.1", "-p", "6969"]), Some(3));
+}
+
+#[test]
+fn exits_3_on_privileged_port() {
+    if UdpSocket::bind("127.0.0.1:69").is_ok() {
+        // Running with the privilege to bind low ports, e.g. as root.
+        return;
+    }
+
+    assert_eq!(run(&["-p", "69"]), Some(3));
+}
+
+#[test]
+fn exits_4_on_missing_directory() {
+    assert_eq!(run(&["-d", "/this/does/not/exist"]), Some(4));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn dry_run_prints_warnings_and_exits_0() {
+    let output = Command::new(env!("CARGO_BIN_EXE_tftpd-read-only-docker"))
+        .args(["--dry-run", "--statsd-tag", "site:lab"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Warning W007: "), "{stderr}");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn exits_2_on_contradictory_options() {
+    assert_eq!(
+        run(&[
+            "--dry-run",
+            "--pipe",
+            "boot.img",
+            "--listing-file",
+            "boot.img"
+        ]),
+        Some(2)
+    );
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn oneshot_pipe_streams_stdin_and_exits_0() {
+    use std::io::Write;
+    use std::process::Stdio;
+    use std::time::Duration;
+    use tftpd::Packet;
+
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tftpd-read-only-docker"))
+        .args([
+            "-p",
+            &port.to_string(),
+            "--oneshot",
+            "--pipe",
+            "firmware.bin",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let contents: Vec<u8> = (0..2 * 1024 * 1024 + 77).map(|i| (i % 253) as u8).collect();
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn({
+        let contents = contents.clone();
+        move || stdin.write_all(&contents).unwrap()
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let server = ("127.0.0.1", port);
+    let rrq = Packet::Rrq {
+        filename: "firmware.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    let mut buf = [0; 1024];
+    // Retry until the server is listening.
+    let (mut size, mut from) = loop {
+        client.send_to(&rrq.serialize().unwrap(), server).unwrap();
+        if let Ok(received) = client.recv_from(&mut buf) {
+            break received;
+        }
+    };
+
+    let mut received = vec![];
+    loop {
+        let Packet::Data { block_num, data } = Packet::deserialize(&buf[..size]).unwrap() else {
+            panic!("expected data");
+        };
+        if block_num as usize == received.len() / 512 + 1 {
+            received.extend_from_slice(&data);
+        }
+        client
+            .send_to(&Packet::Ack(block_num).serialize().unwrap(), from)
+            .unwrap();
+        if data.len() < 512 {
+            break;
+        }
+        (size, from) = client.recv_from(&mut buf).unwrap();
+    }
+
+    writer.join().unwrap();
+    assert_eq!(received, contents);
+    assert_eq!(child.wait().unwrap().code(), Some(0));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn bench_exits_2_without_file() {
+    assert_eq!(run(&["bench", "--clients", "2"]), Some(2));
+    assert_eq!(
+        run(&["bench", "--file", "boot.img", "--clients", "0"]),
+        Some(2)
+    );
+}
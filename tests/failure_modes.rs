@@ -0,0 +1,110 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, error, Harness};
+use tftpd::{ErrorCode, Packet};
+
+#[test]
+fn client_error_mid_transfer_drops_session() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 4);
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+    harness.ack(2);
+    assert_eq!(
+        harness.take_sent(),
+        vec![
+            data(1, &contents[..512]),
+            data(2, &contents[512..1024]),
+            data(3, &contents[1024..1536]),
+        ]
+    );
+
+    harness.send(Packet::Error {
+        code: ErrorCode::DiskFull,
+        msg: "disk full".to_string(),
+    });
+
+    assert_eq!(harness.server.session_count(), 0);
+    assert!(harness.take_sent().is_empty());
+
+    // Nothing is retransmitted once the client gave up.
+    harness.advance(Duration::from_secs(10));
+    assert!(harness.take_sent().is_empty());
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.requests, 1);
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.completed, 0);
+    assert_eq!(metrics.retransmits, 0);
+}
+
+#[test]
+fn ack_for_unsent_block_does_not_advance() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 2 + 100);
+
+    harness.rrq("image.bin", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+
+    // Block 5 was never sent; the server must resend block 1.
+    harness.ack(5);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+    assert_eq!(harness.server.session_count(), 1);
+
+    harness.ack(1);
+    harness.ack(2);
+    harness.ack(3);
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(2, &contents[512..1024]), data(3, &contents[1024..])]
+    );
+
+    let metrics = harness.server.metrics();
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.failed, 0);
+    assert_eq!(metrics.retransmits, 1);
+}
+
+#[test]
+fn vanished_client_is_retried_then_dropped() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 3);
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(1, &contents[..512]), data(2, &contents[512..1024])]
+    );
+
+    // Not yet expired.
+    harness.advance(Duration::from_secs(4));
+    assert!(harness.take_sent().is_empty());
+
+    for _ in 0..6 {
+        harness.advance(Duration::from_secs(5));
+        assert_eq!(harness.take_sent(), vec![data(2, &contents[512..1024])]);
+        assert_eq!(harness.server.session_count(), 1);
+    }
+
+    harness.advance(Duration::from_secs(5));
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::NotDefined, "transfer timed out")]
+    );
+    assert_eq!(harness.server.session_count(), 0);
+
+    harness.advance(Duration::from_secs(5));
+    assert!(harness.take_sent().is_empty());
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.retransmits, 6);
+    assert_eq!(metrics.bytes_sent, 512 * 8);
+}
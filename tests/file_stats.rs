@@ -0,0 +1,57 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::Harness;
+
+#[test]
+fn counts_fetches_per_file() {
+    let mut harness = Harness::new();
+    harness.create_file("kernel", 700);
+    harness.create_file("initrd", 100);
+
+    for _ in 0..3 {
+        harness.rrq("kernel", vec![]);
+        harness.ack(1);
+        harness.ack(2);
+    }
+    harness.rrq("initrd", vec![]);
+    harness.ack(1);
+    // Requests for missing files are not tracked.
+    harness.rrq("missing", vec![]);
+
+    let stats = harness.server.file_stats();
+    assert_eq!(stats.len(), 2);
+
+    let (file, kernel) = &stats[0];
+    assert_eq!(file, "kernel");
+    assert_eq!(kernel.requests, 3);
+    assert_eq!(kernel.completed, 3);
+    assert_eq!(kernel.bytes_served, 3 * 700);
+    assert!(kernel.last_served.is_some());
+
+    let (file, initrd) = &stats[1];
+    assert_eq!(file, "initrd");
+    assert_eq!(initrd.requests, 1);
+    assert_eq!(initrd.completed, 1);
+    assert_eq!(initrd.bytes_served, 100);
+}
+
+#[test]
+fn counts_abandoned_transfers_as_requests_only() {
+    let mut harness = Harness::new();
+    harness.create_file("kernel", 700);
+
+    harness.rrq("kernel", vec![]);
+    harness.ack(1);
+    harness.send(tftpd::Packet::Error {
+        code: tftpd::ErrorCode::NotDefined,
+        msg: "cancelled".to_string(),
+    });
+
+    let stats = harness.server.file_stats();
+    assert_eq!(stats[0].1.requests, 1);
+    assert_eq!(stats[0].1.completed, 0);
+    assert_eq!(stats[0].1.bytes_served, 512);
+    assert_eq!(stats[0].1.last_served, None);
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, option, Harness};
+use tftpd::test_util::{assert_file_eq, TestDir, TestServer};
+use tftpd::{Client, Config, OptionType, Packet};
+
+/// Returns the DATA packets the server sent since the last call, as block
+/// number and length.
+fn sent_blocks(harness: &Harness) -> Vec<(u16, usize)> {
+    harness
+        .take_sent()
+        .iter()
+        .filter_map(|datagram| match Packet::deserialize(datagram).unwrap() {
+            Packet::Data { block_num, data } => Some((block_num, data.len())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn sends_large_blocks_within_byte_cap() {
+    let mut harness = Harness::with_args(&["--max-in-flight-bytes", "20000"]);
+    let contents = harness.create_file("rootfs.img", 8192 * 6 + 100);
+    let options = vec![
+        option(OptionType::BlockSize, 8192),
+        option(OptionType::Windowsize, 8),
+    ];
+
+    harness.rrq("rootfs.img", options.clone());
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(options).serialize().unwrap()
+    );
+    harness.take_sent();
+
+    harness.ack(0);
+    assert_eq!(sent_blocks(&harness), [(1, 8192), (2, 8192)]);
+    harness.ack(2);
+    assert_eq!(sent_blocks(&harness), [(3, 8192), (4, 8192)]);
+
+    harness.ack(4);
+    assert_eq!(sent_blocks(&harness), [(5, 8192), (6, 8192), (7, 100)]);
+
+    // An ACK inside the part sent moves the window on from there.
+    harness.drain_client();
+    harness.ack(6);
+    assert_eq!(harness.recv().unwrap(), data(7, &contents[8192 * 6..]));
+    harness.ack(7);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn overrides_max_window_bytes() {
+    let options = vec![
+        option(OptionType::BlockSize, 4096),
+        option(OptionType::Windowsize, 8),
+    ];
+    // Windows clamped to --max-window-bytes already fit the default cap.
+    let mut harness = Harness::with_args(&["--max-window-bytes", "16384"]);
+    harness.create_file("rootfs.img", 4096 * 6 + 100);
+    harness.rrq("rootfs.img", options.clone());
+    harness.take_sent();
+    harness.ack(0);
+    assert_eq!(
+        sent_blocks(&harness),
+        [(1, 4096), (2, 4096), (3, 4096), (4, 4096)]
+    );
+
+    let mut harness = Harness::with_args(&[
+        "--max-window-bytes",
+        "16384",
+        "--max-in-flight-bytes",
+        "8192",
+    ]);
+    harness.create_file("rootfs.img", 4096 * 6 + 100);
+    harness.rrq("rootfs.img", options);
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(vec![
+            option(OptionType::BlockSize, 4096),
+            option(OptionType::Windowsize, 4),
+        ])
+        .serialize()
+        .unwrap()
+    );
+    harness.take_sent();
+    harness.ack(0);
+    assert_eq!(sent_blocks(&harness), [(1, 4096), (2, 4096)]);
+}
+
+#[test]
+fn downloads_whole_file_sent_in_parts() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("rootfs.img", 8192 * 12 + 100);
+    let server = TestServer::with_config(&Config {
+        directory: dir.path().to_path_buf(),
+        port: 0,
+        max_in_flight_bytes: Some(20000),
+        ..Config::default()
+    });
+
+    let mut client = Client::new(server.addr());
+    client.set_timeout(Duration::from_millis(100));
+    let download = client
+        .get(
+            "rootfs.img",
+            vec![
+                option(OptionType::BlockSize, 8192),
+                option(OptionType::Windowsize, 8),
+            ],
+        )
+        .unwrap();
+
+    assert_file_eq(&download.data, &contents);
+    assert_eq!(
+        download.options,
+        [
+            option(OptionType::BlockSize, 8192),
+            option(OptionType::Windowsize, 8),
+        ]
+    );
+}
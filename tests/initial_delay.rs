@@ -0,0 +1,95 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet};
+
+#[test]
+fn defers_first_data_only() {
+    let mut harness = Harness::with_args(&["--initial-delay", "200"]);
+    let contents = harness.create_file("boot.img", 512 * 2 + 77);
+
+    harness.rrq("boot.img", vec![]);
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(199));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(1));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), vec![data(2, &contents[512..1024])]);
+    harness.ack(2);
+    assert_eq!(harness.take_sent(), vec![data(3, &contents[1024..])]);
+}
+
+#[test]
+fn defers_oack() {
+    let mut harness = Harness::with_args(&["--initial-delay", "100"]);
+    let contents = harness.create_file("boot.img", 700);
+    let oack = Packet::Oack(vec![option(OptionType::BlockSize, 1024)])
+        .serialize()
+        .unwrap();
+
+    harness.rrq("boot.img", vec![option(OptionType::BlockSize, 1024)]);
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(100));
+    assert_eq!(harness.take_sent(), vec![oack]);
+
+    harness.ack(0);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn counts_retransmit_timeout_from_deferred_packet() {
+    let mut harness =
+        Harness::with_args(&["--initial-delay", "300", "--retransmit-timeout", "200"]);
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("boot.img", vec![]);
+    // A retransmitted request does not send the first packet early.
+    harness.rrq("boot.img", vec![]);
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(300));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+
+    harness.advance(Duration::from_millis(199));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(1));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    assert_eq!(harness.server.metrics().retransmits, 1);
+}
+
+#[test]
+fn scopes_delay_to_client_block() {
+    let mut harness = Harness::with_args(&["--initial-delay-for", "127.0.0.0/8=150"]);
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("boot.img", vec![]);
+    assert!(harness.take_sent().is_empty());
+    harness.advance(Duration::from_millis(150));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn does_not_delay_clients_outside_block() {
+    let mut harness = Harness::with_args(&[
+        "--initial-delay",
+        "150",
+        "--initial-delay-for",
+        "10.0.0.0/8=500",
+        "--initial-delay-for",
+        "127.0.0.1/32=0",
+    ]);
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
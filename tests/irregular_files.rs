@@ -0,0 +1,69 @@
+#![cfg(all(feature = "test-util", unix))]
+
+mod common;
+
+use std::{fs, os::unix::fs::symlink, process::Command};
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+fn not_regular() -> Vec<u8> {
+    error(ErrorCode::AccessViolation, "not a regular file")
+}
+
+#[test]
+fn refuses_fifo() {
+    let mut harness = Harness::new();
+    let fifo = harness.dir.path().join("pipe.bin");
+    assert!(Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap()
+        .success());
+
+    // Opening the FIFO would block the server until a writer shows up.
+    harness.rrq("pipe.bin", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![not_regular()]);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn refuses_symlink_to_device() {
+    let mut harness = Harness::new();
+    symlink("/dev/zero", harness.dir.path().join("zero.bin")).unwrap();
+    symlink("/dev/null", harness.dir.path().join("null.bin")).unwrap();
+
+    harness.rrq("zero.bin", vec![]);
+    harness.rrq("null.bin", vec![]);
+
+    // The devices are outside of the directory, which is checked first.
+    let escape = error(ErrorCode::AccessViolation, "file access violation");
+    assert_eq!(harness.take_sent(), vec![escape.clone(), escape]);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn refuses_directory() {
+    let mut harness = Harness::new();
+    fs::create_dir(harness.dir.path().join("pxelinux.cfg")).unwrap();
+
+    harness.rrq("pxelinux.cfg", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![not_regular()]);
+}
+
+#[test]
+fn serves_regular_file_and_symlink_to_it() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+    symlink("boot.img", harness.dir.path().join("link.img")).unwrap();
+
+    harness.rrq("boot.img", vec![]);
+    harness.rrq("link.img", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(1, &contents), data(1, &contents)]
+    );
+}
@@ -0,0 +1,102 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{Harness, Recorder};
+use tftpd::{ErrorCode, Journey, Packet, TransferEvent};
+
+fn ended_journeys(recorder: &Recorder) -> Vec<Journey> {
+    recorder
+        .events()
+        .into_iter()
+        .filter_map(|event| match event {
+            TransferEvent::JourneyEnded { journey } => Some(journey),
+            _ => None,
+        })
+        .collect()
+}
+
+fn started_journeys(recorder: &Recorder) -> Vec<u64> {
+    recorder
+        .events()
+        .into_iter()
+        .filter_map(|event| match event {
+            TransferEvent::Started { journey, .. } => Some(journey),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn sums_up_fetches_of_a_client_host() {
+    let mut harness = Harness::with_args(&["--journey-window", "10"]);
+    harness.create_file("pxelinux.0", 100);
+    harness.create_file("vmlinuz", 700);
+    harness.create_file("initrd.img", 600);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("pxelinux.0", vec![]);
+    harness.ack(1);
+    harness.advance(Duration::from_secs(2));
+    harness.rrq("vmlinuz", vec![]);
+    harness.ack(1);
+    harness.ack(2);
+    harness.advance(Duration::from_secs(1));
+    harness.rrq("initrd.img", vec![]);
+    harness.ack(1);
+    harness.send(Packet::Error {
+        code: ErrorCode::NotDefined,
+        msg: "cancelled".to_string(),
+    });
+
+    harness.advance(Duration::from_secs(9));
+    assert!(ended_journeys(&recorder).is_empty());
+    harness.advance(Duration::from_secs(1));
+
+    let ended = ended_journeys(&recorder);
+    assert_eq!(ended.len(), 1);
+    let journey = &ended[0];
+    assert_eq!(journey.id, 1);
+    assert_eq!(journey.client, harness.server_addr().ip());
+    let files: Vec<_> = journey
+        .files
+        .iter()
+        .map(|file| file.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(files, ["pxelinux.0", "vmlinuz", "initrd.img"]);
+    assert_eq!(journey.transfers, 3);
+    assert_eq!(journey.completed, 2);
+    assert_eq!(journey.failed, 1);
+    assert_eq!(journey.bytes, 100 + 700 + 512);
+    assert_eq!(journey.duration, Duration::from_secs(3));
+    assert_eq!(started_journeys(&recorder), [1, 1, 1]);
+
+    harness.rrq("pxelinux.0", vec![]);
+    harness.ack(1);
+    assert_eq!(started_journeys(&recorder), [1, 1, 1, 2]);
+}
+
+#[test]
+fn keeps_journey_going_during_long_transfer() {
+    let mut harness = Harness::with_args(&["--journey-window", "5"]);
+    harness.create_file("rootfs.img", 512 * 3 + 10);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("rootfs.img", vec![]);
+    for block in 1..=3 {
+        harness.advance(Duration::from_secs(3));
+        harness.ack(block);
+    }
+    assert!(ended_journeys(&recorder).is_empty());
+    harness.ack(4);
+
+    harness.advance(Duration::from_secs(4));
+    harness.rrq("rootfs.img", vec![]);
+    harness.advance(Duration::from_secs(6));
+    assert!(ended_journeys(&recorder).is_empty());
+    assert_eq!(started_journeys(&recorder), [1, 1]);
+}
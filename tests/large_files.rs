@@ -0,0 +1,170 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{fs::File, time::Duration};
+
+use common::{option, Harness};
+use tftpd::{test_util::assert_file_eq, ErrorCode, OptionType, Packet};
+
+/// Largest file sent in 512-byte blocks without the block number wrapping
+/// around: 65534 full blocks and a short one.
+const MAX_UNWRAPPED: u64 = 65535 * 512 - 1;
+
+const BLK_SIZE: usize = 1024;
+
+/// Creates a sparse file of `len` bytes, without writing its contents.
+fn create_sparse_file(harness: &Harness, name: &str, len: u64) {
+    let file = File::create(harness.dir.path().join(name)).unwrap();
+    file.set_len(len).unwrap();
+}
+
+#[test]
+fn echoes_tsize_beyond_4_gib() {
+    let mut harness = Harness::new();
+    let len = (5 << 32) + 7;
+    create_sparse_file(&harness, "disk.img", len);
+
+    harness.rrq(
+        "disk.img",
+        vec![
+            option(OptionType::BlockSize, 65464),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+
+    assert_eq!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Oack(vec![
+            option(OptionType::BlockSize, 65464),
+            option(OptionType::TransferSize, len),
+        ])
+    );
+}
+
+#[test]
+fn refuses_files_needing_rollover_when_disabled() {
+    let mut harness = Harness::with_args(&["--no-rollover"]);
+    create_sparse_file(&harness, "fits.img", MAX_UNWRAPPED);
+    create_sparse_file(&harness, "large.img", MAX_UNWRAPPED + 1);
+
+    harness.rrq("large.img", vec![]);
+    assert_eq!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "file too large for the block size".to_string(),
+        }
+    );
+
+    harness.rrq("fits.img", vec![]);
+    assert!(matches!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Data { block_num: 1, .. }
+    ));
+}
+
+#[test]
+fn serves_files_needing_rollover_with_larger_blocks() {
+    let mut harness = Harness::with_args(&["--no-rollover"]);
+    create_sparse_file(&harness, "large.img", MAX_UNWRAPPED + 1);
+
+    harness.rrq("large.img", vec![option(OptionType::BlockSize, 1024)]);
+
+    assert_eq!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Oack(vec![option(OptionType::BlockSize, 1024)])
+    );
+}
+
+#[test]
+fn wraps_block_numbers_by_default() {
+    let mut harness = Harness::new();
+    create_sparse_file(&harness, "large.img", MAX_UNWRAPPED + 1);
+
+    harness.rrq("large.img", vec![]);
+
+    assert!(matches!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Data { block_num: 1, .. }
+    ));
+}
+
+/// Downloads `image.bin` as a client losing every twentieth ACK. Once it
+/// received `restart_after` blocks, it goes back to acknowledging block
+/// `restart_at` as if it restarted, and keeps what came before.
+fn download(harness: &mut Harness, restart_after: u16, restart_at: u16) -> Vec<u8> {
+    harness.rrq(
+        "image.bin",
+        vec![
+            option(OptionType::BlockSize, BLK_SIZE as u64),
+            option(OptionType::Windowsize, 16),
+        ],
+    );
+    harness.take_sent();
+    harness.ack(0);
+
+    let mut received = vec![];
+    let mut next = 1u16;
+    let mut acks = 0;
+    let mut restarted = false;
+    let mut last = false;
+    loop {
+        for buf in harness.take_sent() {
+            if let Packet::Data { block_num, data } = Packet::deserialize(&buf).unwrap() {
+                if block_num == next {
+                    last = data.len() < BLK_SIZE;
+                    received.extend(data);
+                    next += 1;
+                }
+            }
+        }
+
+        if !restarted && next > restart_after {
+            restarted = true;
+            received.truncate(restart_at as usize * BLK_SIZE);
+            next = restart_at + 1;
+            for _ in 0..3 {
+                harness.ack(restart_at);
+            }
+            continue;
+        }
+
+        acks += 1;
+        if acks % 20 == 0 {
+            harness.advance(Duration::from_secs(6));
+            continue;
+        }
+        harness.ack(next - 1);
+        if last {
+            return received;
+        }
+    }
+}
+
+#[test]
+fn sends_large_file_losing_acks() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 3 * 1024 * 1024 + 100);
+
+    let received = download(&mut harness, u16::MAX, 0);
+
+    assert_file_eq(&received, &contents);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.completed, 1);
+    assert!(metrics.retransmits > 0);
+}
+
+#[test]
+fn reads_large_file_again_after_restart() {
+    let mut harness = Harness::with_args(&["--allow-mid-session-restart"]);
+    let contents = harness.create_file("image.bin", 3 * 1024 * 1024 + 100);
+
+    let received = download(&mut harness, 2000, 1200);
+
+    assert_file_eq(&received, &contents);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.client_restarts, 1);
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.failed, 0);
+}
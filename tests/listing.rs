@@ -0,0 +1,68 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{error, option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+#[test]
+fn serves_listing_with_tsize() {
+    let mut harness = Harness::with_args(&["--listing-file", ".dirlist"]);
+    harness.create_file("pxelinux.0", 1234);
+    harness.create_file("ldlinux.c32", 42);
+
+    harness.rrq(".dirlist", vec![option(OptionType::TransferSize, 0)]);
+    let sent = harness.take_sent();
+    let Packet::Oack(options) = Packet::deserialize(&sent[0]).unwrap() else {
+        panic!("expected OACK");
+    };
+
+    harness.ack(0);
+    let Packet::Data { block_num, data } = Packet::deserialize(&harness.take_sent()[0]).unwrap()
+    else {
+        panic!("expected DATA");
+    };
+    assert_eq!(block_num, 1);
+    assert_eq!(options[0].value, data.len() as u64);
+
+    let listing = String::from_utf8(data).unwrap();
+    let entries: Vec<Vec<&str>> = listing
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0][..2], ["ldlinux.c32", "42"]);
+    assert_eq!(entries[1][..2], ["pxelinux.0", "1234"]);
+}
+
+#[test]
+fn listing_is_disabled_by_default() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 1234);
+
+    harness.rrq(".dirlist", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::FileNotFound, "file does not exist")]
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn does_not_follow_symlinked_directories() {
+    let mut harness = Harness::with_args(&["--listing-file", ".dirlist", "--listing-depth", "4"]);
+    harness.create_file("pxelinux.0", 1234);
+    std::os::unix::fs::symlink("/proc", harness.dir.path().join("p")).unwrap();
+
+    harness.rrq(".dirlist", vec![]);
+    let Packet::Data { block_num, data } = Packet::deserialize(&harness.take_sent()[0]).unwrap()
+    else {
+        panic!("expected DATA");
+    };
+
+    assert_eq!(block_num, 1);
+    let listing = String::from_utf8(data).unwrap();
+    assert_eq!(listing.lines().count(), 1);
+    assert!(listing.starts_with("pxelinux.0\t1234\t"));
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use common::{data, error, Harness};
+use tftpd::{ErrorCode, Packet};
+
+/// Acknowledges `block` from another port of the client host, as a
+/// middlebox rewriting the source port would.
+fn ack_from(harness: &mut Harness, socket: &UdpSocket, block: u16) {
+    socket
+        .send_to(
+            &Packet::Ack(block).serialize().unwrap(),
+            harness.server_addr(),
+        )
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+fn unknown_id() -> Vec<u8> {
+    error(ErrorCode::UnknownId, "unknown transfer ID")
+}
+
+fn rewritten_socket() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    socket
+}
+
+fn recv(socket: &UdpSocket) -> Option<Vec<u8>> {
+    let mut buf = [0; 1024];
+    socket
+        .recv_from(&mut buf)
+        .ok()
+        .map(|(size, _)| buf[..size].to_vec())
+}
+
+#[test]
+fn loose_mode_follows_the_rewritten_port() {
+    let mut harness = Harness::with_args(&["--loose-tid"]);
+    let contents = harness.create_file("image.bin", 512 * 2 + 100);
+    let rewritten = rewritten_socket();
+
+    harness.rrq("image.bin", vec![]);
+    assert_eq!(harness.recv().unwrap(), data(1, &contents[..512]));
+    harness.ack(1);
+    assert_eq!(harness.recv().unwrap(), data(2, &contents[512..1024]));
+
+    ack_from(&mut harness, &rewritten, 2);
+    assert_eq!(recv(&rewritten).unwrap(), data(3, &contents[1024..]));
+    assert_eq!(
+        harness.server.active_sessions()[0].sessions,
+        vec![rewritten.local_addr().unwrap()]
+    );
+
+    ack_from(&mut harness, &rewritten, 3);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.tid_migrations, 1);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn strict_mode_ignores_the_rewritten_port() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 512 * 2 + 100);
+    let rewritten = rewritten_socket();
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+    assert_eq!(harness.take_sent().len(), 2);
+
+    ack_from(&mut harness, &rewritten, 2);
+    assert_eq!(harness.take_sent(), vec![unknown_id()]);
+    assert_eq!(recv(&rewritten), Some(unknown_id()));
+    assert_eq!(
+        harness.server.active_sessions()[0].sessions,
+        vec![harness.client.local_addr().unwrap()]
+    );
+
+    harness.drain_client();
+    harness.ack(2);
+    assert_eq!(harness.recv().unwrap(), data(3, &contents[1024..]));
+    assert_eq!(harness.server.metrics().tid_migrations, 0);
+}
+
+#[test]
+fn loose_mode_needs_a_single_session_and_a_valid_block() {
+    let mut harness = Harness::with_args(&["--loose-tid"]);
+    harness.create_file("image.bin", 512 * 2 + 100);
+    let other = rewritten_socket();
+    let rewritten = rewritten_socket();
+
+    harness.rrq("image.bin", vec![]);
+    ack_from(&mut harness, &rewritten, 5);
+    assert_eq!(recv(&rewritten), Some(unknown_id()));
+
+    let rrq = Packet::Rrq {
+        filename: "image.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    other
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    ack_from(&mut harness, &rewritten, 1);
+    assert_eq!(recv(&rewritten), Some(unknown_id()));
+
+    assert_eq!(harness.server.metrics().tid_migrations, 0);
+    assert_eq!(harness.server.active_sessions()[0].sessions.len(), 2);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::fs;
+
+use common::{data, error, Harness};
+use tftpd::test_util::TestDir;
+use tftpd::ErrorCode;
+
+fn with_manifest(content: &str) -> (Harness, TestDir) {
+    let manifest_dir = TestDir::new();
+    let manifest = manifest_dir.path().join("manifest");
+    fs::write(&manifest, content).unwrap();
+
+    let harness = Harness::with_args(&["--manifest", manifest.to_str().unwrap()]);
+    (harness, manifest_dir)
+}
+
+#[test]
+fn serves_listed_files() {
+    let (mut harness, _manifest) = with_manifest("# boot\npxelinux.0\n");
+    let contents = harness.create_file("pxelinux.0", 100);
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn refuses_files_not_listed() {
+    let (mut harness, _manifest) = with_manifest("pxelinux.0\n");
+    harness.create_file("secret.key", 100);
+
+    harness.rrq("secret.key", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::AccessViolation, "file access violation")]
+    );
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn listed_but_missing_file_is_not_found() {
+    let (mut harness, _manifest) = with_manifest("pxelinux.0\n");
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::FileNotFound, "file does not exist")]
+    );
+}
+
+#[test]
+fn reload_adds_new_entries() {
+    let (mut harness, manifest) = with_manifest("pxelinux.0\n");
+    let contents = harness.create_file("kernel", 100);
+
+    harness.rrq("kernel", vec![]);
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::AccessViolation, "file access violation")]
+    );
+
+    fs::write(manifest.path().join("manifest"), "pxelinux.0\nkernel\n").unwrap();
+    harness.server.reload_manifest().unwrap();
+    harness.rrq("kernel", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn failed_reload_keeps_previous_manifest() {
+    let (mut harness, manifest) = with_manifest("kernel\n");
+    let contents = harness.create_file("kernel", 100);
+
+    fs::write(manifest.path().join("manifest"), "kernel\n/etc/passwd\n").unwrap();
+    let err = harness.server.reload_manifest().unwrap_err();
+    assert!(err.to_string().contains("line 2"));
+    harness.rrq("kernel", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
@@ -0,0 +1,30 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet};
+
+#[test]
+fn transfers_maximum_block_size_over_loopback() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 65464 + 1000);
+
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 65464)]);
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(vec![option(OptionType::BlockSize, 65464)])
+            .serialize()
+            .unwrap()
+    );
+
+    harness.ack(0);
+    let block = harness.recv().unwrap();
+    assert_eq!(block.len(), 65468);
+    assert_eq!(block, data(1, &contents[..65464]));
+
+    harness.ack(1);
+    assert_eq!(harness.recv().unwrap(), data(2, &contents[65464..]));
+    harness.ack(2);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{data, error, Harness, Recorder};
+use tftpd::{ErrorCode, TransferEvent};
+
+fn exceeded() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "transfer exceeded maximum duration")
+}
+
+#[test]
+fn finishes_just_under_the_deadline() {
+    let mut harness = Harness::with_args(&["--max-transfer-duration", "10"]);
+    let contents = harness.create_file("image.bin", 512 * 2 + 100);
+
+    harness.rrq("image.bin", vec![]);
+    for block in 1..=2u16 {
+        harness.advance(Duration::from_secs(3));
+        harness.ack(block);
+    }
+    harness.advance(Duration::from_millis(3900));
+    harness.ack(3);
+
+    let sent = harness.take_sent();
+    assert_eq!(sent[0], data(1, &contents[..512]));
+    assert_eq!(sent.last(), Some(&data(3, &contents[1024..])));
+    assert_eq!(harness.server.metrics().completed, 1);
+    assert_eq!(harness.server.metrics().failed, 0);
+}
+
+#[test]
+fn trickling_client_is_cut_off_on_ack() {
+    let mut harness = Harness::with_args(&["--max-transfer-duration", "10"]);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+    harness.create_file("image.bin", 512 * 10);
+
+    harness.rrq("image.bin", vec![]);
+    for block in 1..=2u16 {
+        harness.clock.advance(Duration::from_secs(4));
+        harness.ack(block);
+    }
+    harness.clock.advance(Duration::from_secs(4));
+    harness.take_sent();
+    harness.ack(3);
+
+    assert_eq!(harness.take_sent(), vec![exceeded()]);
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(harness.server.metrics().failed, 1);
+    let failed = recorder.events().into_iter().find_map(|event| match event {
+        TransferEvent::Failed { bytes, reason, .. } => Some((bytes, reason)),
+        _ => None,
+    });
+    assert_eq!(
+        failed,
+        Some((1024, "exceeded the maximum duration of 10s".to_string()))
+    );
+}
+
+#[test]
+fn silent_client_is_cut_off_on_tick() {
+    let mut harness = Harness::with_args(&[
+        "--max-transfer-duration",
+        "10",
+        "--retransmit-timeout",
+        "4000",
+    ]);
+    harness.create_file("image.bin", 512 * 10);
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_secs(4));
+    harness.advance(Duration::from_secs(4));
+    harness.take_sent();
+    harness.advance(Duration::from_secs(2));
+
+    assert_eq!(harness.take_sent(), vec![exceeded()]);
+    assert_eq!(harness.server.session_count(), 0);
+}
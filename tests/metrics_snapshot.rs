@@ -0,0 +1,92 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{fs, path::Path, time::Duration};
+
+use common::{data, Harness};
+use tftpd::test_util::TestDir;
+
+fn harness_saving_to(snapshot: &Path) -> Harness {
+    Harness::with_args(&["--metrics-snapshot", snapshot.to_str().unwrap()])
+}
+
+/// Serves `pxelinux.0` once to the end.
+fn serve_once(harness: &mut Harness) {
+    let contents = harness.create_file("pxelinux.0", 100);
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+    harness.ack(1);
+}
+
+#[test]
+fn restores_counters_after_restart() {
+    let state = TestDir::new();
+    let snapshot = state.path().join("metrics.json");
+    let mut harness = harness_saving_to(&snapshot);
+    serve_once(&mut harness);
+    harness.server.save_metrics().unwrap();
+    drop(harness);
+
+    let mut harness = harness_saving_to(&snapshot);
+
+    let metrics = harness.server.metrics();
+    assert_eq!((metrics.requests, metrics.completed), (1, 1));
+    assert_eq!(metrics.bytes_sent, 100);
+    assert_eq!(metrics.restarts, 1);
+    assert!(metrics.started_at.is_some());
+    let stats = harness.server.file_stats();
+    assert_eq!(stats[0].0, "pxelinux.0");
+    assert_eq!((stats[0].1.requests, stats[0].1.completed), (1, 1));
+
+    serve_once(&mut harness);
+    assert_eq!(harness.server.metrics().requests, 2);
+    assert_eq!(harness.server.file_stats()[0].1.requests, 2);
+}
+
+#[test]
+fn saves_snapshot_periodically() {
+    let state = TestDir::new();
+    let snapshot = state.path().join("metrics.json");
+    let mut harness = Harness::with_args(&[
+        "--metrics-snapshot",
+        snapshot.to_str().unwrap(),
+        "--metrics-snapshot-interval",
+        "30",
+    ]);
+    harness.advance(Duration::ZERO);
+    let first = fs::read_to_string(&snapshot).unwrap();
+    assert!(first.contains("\"requests\":0"));
+
+    serve_once(&mut harness);
+    harness.advance(Duration::from_secs(10));
+    assert_eq!(fs::read_to_string(&snapshot).unwrap(), first);
+
+    harness.advance(Duration::from_secs(20));
+    assert!(fs::read_to_string(&snapshot)
+        .unwrap()
+        .contains("\"requests\":1"));
+}
+
+#[test]
+fn ignores_truncated_snapshot() {
+    let state = TestDir::new();
+    let snapshot = state.path().join("metrics.json");
+    let mut harness = harness_saving_to(&snapshot);
+    serve_once(&mut harness);
+    harness.server.save_metrics().unwrap();
+    drop(harness);
+    let content = fs::read(&snapshot).unwrap();
+    fs::write(&snapshot, &content[..content.len() / 2]).unwrap();
+
+    let mut harness = harness_saving_to(&snapshot);
+
+    let metrics = harness.server.metrics();
+    assert_eq!((metrics.requests, metrics.restarts), (0, 0));
+    assert!(harness.server.file_stats().is_empty());
+
+    serve_once(&mut harness);
+    harness.server.save_metrics().unwrap();
+    let restarted = harness_saving_to(&snapshot).server.metrics();
+    assert_eq!((restarted.requests, restarted.restarts), (1, 1));
+}
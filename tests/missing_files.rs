@@ -0,0 +1,68 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{error, Harness};
+use tftpd::ErrorCode;
+
+fn not_found() -> Vec<u8> {
+    error(ErrorCode::FileNotFound, "file does not exist")
+}
+
+#[test]
+fn answers_every_request_but_logs_only_the_first() {
+    let mut harness = Harness::new();
+
+    for _ in 0..50 {
+        harness.rrq("pxelinux.0", vec![]);
+        assert_eq!(harness.take_sent(), vec![not_found()]);
+    }
+    harness.rrq("ldlinux.c32", vec![]);
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.not_found, 51);
+    assert_eq!(metrics.suppressed_not_found, 49);
+    assert_eq!(metrics.missing_files, 2);
+
+    let missing = harness.server.missing_files();
+    assert_eq!(missing[0].0, "pxelinux.0");
+    assert_eq!(missing[0].1.requests, 50);
+    assert!(missing[0].1.first_requested <= missing[0].1.last_requested);
+    assert_eq!(missing[1].0, "ldlinux.c32");
+    assert_eq!(missing[1].1.requests, 1);
+}
+
+#[test]
+fn logs_again_after_a_quiet_roll_up_period() {
+    let mut harness = Harness::new();
+
+    harness.rrq("pxelinux.0", vec![]);
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.server.metrics().suppressed_not_found, 1);
+
+    // Still requested during the next period, then quiet for a whole one.
+    harness.advance(Duration::from_secs(60));
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.server.metrics().suppressed_not_found, 2);
+    harness.advance(Duration::from_secs(60));
+    harness.advance(Duration::from_secs(60));
+
+    harness.rrq("pxelinux.0", vec![]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.not_found, 4);
+    assert_eq!(metrics.suppressed_not_found, 2);
+    assert_eq!(harness.server.missing_files()[0].1.requests, 4);
+}
+
+#[test]
+fn existing_files_are_not_tracked() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 100);
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert_eq!(harness.server.metrics().not_found, 0);
+    assert!(harness.server.missing_files().is_empty());
+}
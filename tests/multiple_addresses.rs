@@ -0,0 +1,103 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+    fs,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tftpd::test_util::TestDir;
+use tftpd::{Config, Packet, Server};
+
+/// Requests `filename` from `server` and acknowledges every block, checking
+/// that every reply comes from `server`.
+fn download(server: SocketAddr, filename: &str) -> Vec<u8> {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let rrq = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client.send_to(&rrq.serialize().unwrap(), server).unwrap();
+
+    let mut contents = vec![];
+    let mut buf = [0; 1024];
+    loop {
+        let (size, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(from, server);
+        let Packet::Data { block_num, data } = Packet::deserialize(&buf[..size]).unwrap() else {
+            panic!("expected data from {server}");
+        };
+        contents.extend_from_slice(&data);
+        client
+            .send_to(&Packet::Ack(block_num).serialize().unwrap(), server)
+            .unwrap();
+        if data.len() < 512 {
+            return contents;
+        }
+    }
+}
+
+#[test]
+fn serves_independent_transfers_on_each_address() {
+    let dir = TestDir::new();
+    let first: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let second = vec![7; 700];
+    fs::write(dir.path().join("first.bin"), &first).unwrap();
+    fs::write(dir.path().join("second.bin"), &second).unwrap();
+
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config::new(
+        [
+            "/",
+            "-i",
+            "127.0.0.1,127.0.0.2",
+            "-p",
+            &port.to_string(),
+            "-d",
+            dir.path().to_str().unwrap(),
+        ]
+        .iter()
+        .map(|s| s.to_string()),
+    )
+    .unwrap();
+    let mut server = Server::new(&config).unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handle = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                server.poll().unwrap();
+            }
+            server
+        }
+    });
+
+    let one = SocketAddr::from(([127, 0, 0, 1], port));
+    let two = SocketAddr::from(([127, 0, 0, 2], port));
+    let (from_one, from_two) = thread::scope(|scope| {
+        let one = scope.spawn(|| download(one, "first.bin"));
+        let two = scope.spawn(|| download(two, "second.bin"));
+        (one.join().unwrap(), two.join().unwrap())
+    });
+
+    stop.store(true, Ordering::Relaxed);
+    let server = handle.join().unwrap();
+
+    assert_eq!(from_one, first);
+    assert_eq!(from_two, second);
+    assert_eq!(server.metrics().requests, 2);
+}
@@ -0,0 +1,132 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{data, option, Harness};
+use tftpd::{Client, Download, NegotiationNote, OptionType, Packet, TransferOption};
+
+/// Downloads `filename` with a [`Client`] while the server polls.
+fn download(
+    harness: &mut Harness,
+    filename: &'static str,
+    options: Vec<TransferOption>,
+    strict: bool,
+) -> Result<Download, String> {
+    let mut client = Client::new(harness.server_addr());
+    client.set_timeout(Duration::from_secs(2));
+    client.set_strict(strict);
+    let download =
+        thread::spawn(move || client.get(filename, options).map_err(|err| err.to_string()));
+    while !download.is_finished() {
+        harness.server.poll().unwrap();
+    }
+    // Handles the last ACK or the ERROR of the client.
+    harness.server.poll().unwrap();
+    download.join().unwrap()
+}
+
+#[test]
+fn sends_no_note_by_default() {
+    let mut harness = Harness::with_args(&["--max-blksize", "1024"]);
+    let contents = harness.create_file("boot.img", 1024 * 2 + 77);
+
+    harness.rrq("boot.img", vec![option(OptionType::BlockSize, 1468)]);
+    assert_eq!(
+        harness.take_sent(),
+        vec![Packet::Oack(vec![option(OptionType::BlockSize, 1024)])
+            .serialize()
+            .unwrap()]
+    );
+
+    let download = download(
+        &mut harness,
+        "boot.img",
+        vec![option(OptionType::BlockSize, 1468)],
+        true,
+    )
+    .unwrap();
+    assert_eq!(download.data, contents);
+    assert!(download.notes.is_empty());
+}
+
+#[test]
+fn notes_clamped_and_dropped_options_in_oack() {
+    let mut harness = Harness::with_args(&[
+        "--negotiation-report",
+        "--max-blksize",
+        "1024",
+        "--tsize",
+        "omit",
+    ]);
+    harness.create_file("boot.img", 100);
+
+    harness.rrq(
+        "boot.img",
+        vec![
+            option(OptionType::BlockSize, 1468),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![b"\x00\x06blksize\x001024\x00srvnote\x00BKCL,TSDR\x00".to_vec()]
+    );
+}
+
+#[test]
+fn sends_no_note_when_nothing_was_lost() {
+    let mut harness = Harness::with_args(&["--negotiation-report"]);
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    harness.ack(1);
+
+    harness.rrq("boot.img", vec![option(OptionType::BlockSize, 1024)]);
+    assert_eq!(
+        harness.take_sent(),
+        vec![Packet::Oack(vec![option(OptionType::BlockSize, 1024)])
+            .serialize()
+            .unwrap()]
+    );
+}
+
+#[test]
+fn client_decodes_note() {
+    let mut harness = Harness::with_args(&["--negotiation-report", "--max-windowsize", "2"]);
+    let contents = harness.create_file("boot.img", 512 * 5 + 77);
+
+    let download = download(
+        &mut harness,
+        "boot.img",
+        vec![option(OptionType::Windowsize, 8)],
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(download.data, contents);
+    assert_eq!(download.notes, vec![NegotiationNote::WindowsizeClamped]);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn strict_client_refuses_note() {
+    let mut harness = Harness::with_args(&["--negotiation-report", "--max-windowsize", "2"]);
+    harness.create_file("boot.img", 512 * 5 + 77);
+
+    let err = download(
+        &mut harness,
+        "boot.img",
+        vec![option(OptionType::Windowsize, 8)],
+        true,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, "Refused OACK: unrequested option srvnote");
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.completed, 0);
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, error, option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet, TransferOption};
+
+fn rrq(harness: &mut Harness, mode: &str, options: Vec<TransferOption>) {
+    harness.send(Packet::Rrq {
+        filename: "motd.txt".to_string(),
+        mode: mode.to_string(),
+        options,
+    });
+}
+
+#[test]
+fn translates_line_endings() {
+    let mut harness = Harness::new();
+    harness.dir.write("motd.txt", b"boot\nok\r");
+
+    rrq(&mut harness, "netascii", vec![]);
+
+    assert_eq!(harness.take_sent(), [data(1, b"boot\r\nok\r\0")]);
+    harness.ack(1);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn cuts_blocks_in_the_translated_stream() {
+    let mut harness = Harness::new();
+    let mut text = vec![b'x'; 511];
+    text.push(b'\n');
+    harness.dir.write("motd.txt", &text);
+
+    rrq(&mut harness, "NETASCII", vec![]);
+    let mut first = vec![b'x'; 511];
+    first.push(b'\r');
+    assert_eq!(harness.take_sent(), [data(1, &first)]);
+
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), [data(2, b"\n")]);
+}
+
+#[test]
+fn leaves_out_transfer_size() {
+    let mut harness = Harness::new();
+    harness.dir.write("motd.txt", b"boot\n");
+
+    rrq(
+        &mut harness,
+        "netascii",
+        vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+
+    let oack = Packet::Oack(vec![option(OptionType::BlockSize, 1024)]);
+    assert_eq!(harness.take_sent(), [oack.serialize().unwrap()]);
+}
+
+#[test]
+fn refuses_mail_and_unknown_modes() {
+    let mut harness = Harness::new();
+    harness.dir.write("motd.txt", b"boot\n");
+    let error = error(ErrorCode::IllegalOperation, "unsupported transfer mode");
+
+    for mode in ["mail", "binary"] {
+        rrq(&mut harness, mode, vec![]);
+        assert_eq!(harness.take_sent(), vec![error.clone()]);
+    }
+    assert_eq!(harness.server.session_count(), 0);
+}
@@ -0,0 +1,62 @@
+#![cfg(all(feature = "test-util", unix))]
+
+mod common;
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    net::UdpSocket,
+    os::unix::ffi::OsStrExt,
+    time::Duration,
+};
+
+use common::data;
+use tftpd::test_util::TestDir;
+use tftpd::{Config, Packet, Server};
+
+#[test]
+fn serves_from_non_utf8_directory() {
+    let root = TestDir::new();
+    let directory = root.path().join(OsStr::from_bytes(b"images-\xff"));
+    fs::create_dir(&directory).unwrap();
+    fs::write(directory.join("boot.bin"), b"hello").unwrap();
+
+    let config = Config::new(
+        [
+            OsString::from("/"),
+            OsString::from("-d"),
+            directory.clone().into(),
+        ]
+        .into_iter(),
+    )
+    .unwrap();
+    assert_eq!(config.directory, directory);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let mut server = Server::with_socket(&config, socket).unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+
+    let rrq = Packet::Rrq {
+        filename: "boot.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&rrq.serialize().unwrap(), server_addr)
+        .unwrap();
+    server.poll().unwrap();
+
+    let mut buf = [0; 1024];
+    let (size, _) = client.recv_from(&mut buf).unwrap();
+    assert_eq!(buf[..size], data(1, b"hello"));
+
+    client
+        .send_to(&Packet::Ack(1).serialize().unwrap(), server_addr)
+        .unwrap();
+    server.poll().unwrap();
+    assert_eq!(server.metrics().completed, 1);
+}
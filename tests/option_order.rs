@@ -0,0 +1,66 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{option, Harness};
+use tftpd::{OptionType, TransferOption};
+
+/// Options of a request in the reverse of the canonical order.
+fn reversed_options() -> Vec<TransferOption> {
+    vec![
+        option(OptionType::Windowsize, 4),
+        option(OptionType::TransferSize, 0),
+        option(OptionType::Timeout, 3),
+        option(OptionType::BlockSize, 1024),
+    ]
+}
+
+#[test]
+fn acknowledges_options_in_canonical_order() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 4000);
+
+    harness.rrq("pxelinux.0", reversed_options());
+
+    assert_eq!(
+        harness.recv().unwrap(),
+        b"\x00\x06blksize\x001024\x00timeout\x003\x00tsize\x004000\x00windowsize\x004\x00"
+    );
+}
+
+#[test]
+fn mirrors_request_order_on_demand() {
+    let mut harness = Harness::with_args(&["--preserve-option-order"]);
+    harness.create_file("pxelinux.0", 4000);
+
+    harness.rrq("pxelinux.0", reversed_options());
+
+    assert_eq!(
+        harness.recv().unwrap(),
+        b"\x00\x06windowsize\x004\x00tsize\x004000\x00timeout\x003\x00blksize\x001024\x00"
+    );
+}
+
+#[test]
+fn appends_nonstandard_options() {
+    let mut harness = Harness::with_args(&["--negotiation-report", "--max-blksize", "512"]);
+    harness.create_file("pxelinux.0", 4000);
+
+    harness.rrq(
+        "pxelinux.0",
+        vec![
+            option(OptionType::Checksum, 1),
+            option(OptionType::TransferSize, 0),
+            option(OptionType::BlockSize, 1024),
+        ],
+    );
+
+    let oack = harness.recv().unwrap();
+    let names: Vec<_> = oack[2..]
+        .split(|&byte| byte == 0)
+        .step_by(2)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect();
+    assert_eq!(names, ["blksize", "tsize", "xsum", "srvnote"]);
+}
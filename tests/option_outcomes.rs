@@ -0,0 +1,141 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{option, Harness, Recorder};
+use tftpd::{NegotiatedOption, OptionOutcome, OptionType, Packet, TransferEvent};
+
+#[test]
+fn counts_outcomes_per_option() {
+    let mut harness = Harness::with_args(&["--tsize", "omit"]);
+    harness.create_file("image.bin", 100);
+
+    harness.rrq(
+        "image.bin",
+        vec![
+            option(OptionType::Windowsize, 4),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+
+    let metrics = harness.server.metrics();
+    let outcome = |option| {
+        OptionOutcome::ALL
+            .into_iter()
+            .find(|&outcome| metrics.option_outcome(option, outcome) == 1)
+    };
+    assert_eq!(
+        outcome(OptionType::Windowsize),
+        Some(OptionOutcome::Granted)
+    );
+    assert_eq!(
+        outcome(OptionType::TransferSize),
+        Some(OptionOutcome::Dropped)
+    );
+    assert_eq!(outcome(OptionType::BlockSize), Some(OptionOutcome::Absent));
+    assert_eq!(outcome(OptionType::Timeout), Some(OptionOutcome::Absent));
+    assert!(metrics
+        .option_counters()
+        .contains(&("options.tsize.dropped".to_string(), 1)));
+}
+
+#[test]
+fn reports_negotiated_options_on_completion() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 100);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("image.bin", vec![option(OptionType::BlockSize, 1024)]);
+    harness.ack(0);
+    harness.ack(1);
+
+    let options = recorder
+        .events()
+        .into_iter()
+        .find_map(|event| match event {
+            TransferEvent::Completed { options, .. } => Some(options),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(
+        options[0],
+        NegotiatedOption {
+            option: OptionType::BlockSize,
+            requested: Some(1024),
+            granted: Some(1024),
+        }
+    );
+    assert!(options[1..]
+        .iter()
+        .all(|option| option.outcome() == OptionOutcome::Absent));
+}
+
+#[test]
+fn clamps_options_to_limits() {
+    let mut harness = Harness::with_args(&[
+        "--max-blksize",
+        "1468",
+        "--max-window-bytes",
+        "8192",
+        "--disable-option",
+        "timeout",
+    ]);
+    harness.create_file("image.bin", 100);
+
+    harness.rrq(
+        "image.bin",
+        vec![
+            option(OptionType::BlockSize, 9000),
+            option(OptionType::Timeout, 2),
+            option(OptionType::Windowsize, 16),
+        ],
+    );
+
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(vec![
+            option(OptionType::BlockSize, 1468),
+            option(OptionType::Windowsize, 5),
+        ])
+        .serialize()
+        .unwrap()
+    );
+    let metrics = harness.server.metrics();
+    assert_eq!(
+        metrics.option_outcome(OptionType::BlockSize, OptionOutcome::Clamped),
+        1
+    );
+    assert_eq!(
+        metrics.option_outcome(OptionType::Timeout, OptionOutcome::Dropped),
+        1
+    );
+    assert_eq!(
+        metrics.option_outcome(OptionType::Windowsize, OptionOutcome::Clamped),
+        1
+    );
+}
+
+#[test]
+fn acknowledges_first_of_repeated_options() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 100);
+
+    harness.rrq(
+        "image.bin",
+        vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::BlockSize, 8192),
+        ],
+    );
+
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(vec![option(OptionType::BlockSize, 1024)])
+            .serialize()
+            .unwrap()
+    );
+    assert_eq!(harness.server.metrics().duplicate_options, 1);
+}
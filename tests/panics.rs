@@ -0,0 +1,94 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    io::{self, Read},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use common::{data, error, Harness};
+use tftpd::{Decision, ErrorCode};
+
+fn internal_error() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "internal error")
+}
+
+/// Harness whose authorizer panics for `crash.bin`.
+fn crashing_harness(args: &[&str]) -> Harness {
+    let mut harness = Harness::with_args(args);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, filename: &str| {
+            if filename == "crash.bin" {
+                panic!("authorizer broke on {filename}");
+            }
+            Decision::Allow
+        }));
+    harness
+}
+
+/// Stream returning two blocks, then panicking.
+struct BrokenStream {
+    served: usize,
+}
+
+impl Read for BrokenStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        assert!(self.served < 1024, "stream broke after two blocks");
+        let size = buf.len().min(1024 - self.served);
+        buf[..size].fill(7);
+        self.served += size;
+        Ok(size)
+    }
+}
+
+#[test]
+fn keeps_serving_after_panicking_request() {
+    let mut harness = crashing_harness(&[]);
+    harness.create_file("crash.bin", 100);
+    let contents = harness.create_file("pxelinux.0", 300);
+
+    harness.rrq("crash.bin", vec![]);
+    assert_eq!(harness.take_sent(), [internal_error()]);
+    assert_eq!(harness.server.metrics().panics, 1);
+    assert_eq!(harness.server.session_count(), 0);
+
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+    harness.ack(1);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn aborts_session_panicking_mid_transfer() {
+    let mut harness = Harness::with_args(&["--pipe", "stream.bin"]);
+    harness
+        .server
+        .set_pipe_source(Box::new(BrokenStream { served: 0 }));
+    let contents = harness.create_file("pxelinux.0", 300);
+
+    harness.rrq("stream.bin", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &[7; 512])]);
+    // The stream breaks while reading ahead of the second block.
+    harness.ack(1);
+
+    assert_eq!(harness.take_sent(), [data(2, &[7; 512]), internal_error()]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.panics, 1);
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(harness.server.session_count(), 0);
+
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+}
+
+#[test]
+#[should_panic(expected = "authorizer broke on crash.bin")]
+fn stops_on_panic_when_asked() {
+    let mut harness = crashing_harness(&["--abort-on-panic"]);
+    harness.create_file("crash.bin", 100);
+
+    harness.rrq("crash.bin", vec![]);
+}
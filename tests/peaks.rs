@@ -0,0 +1,75 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{net::UdpSocket, time::Duration};
+
+use common::Harness;
+use tftpd::Packet;
+
+/// Sends a read request for `filename` from another client.
+fn rrq_from(harness: &mut Harness, client: &UdpSocket, filename: &str) {
+    let rrq = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+fn ack_from(harness: &mut Harness, client: &UdpSocket, block: u16) {
+    client
+        .send_to(
+            &Packet::Ack(block).serialize().unwrap(),
+            harness.server_addr(),
+        )
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+#[test]
+fn records_morning_burst_until_reset() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 700);
+    let lab: Vec<UdpSocket> = (0..8)
+        .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+        .collect();
+
+    // The lab powers on within 80 ms.
+    for client in &lab {
+        rrq_from(&mut harness, client, "pxelinux.0");
+        harness.clock.advance(Duration::from_millis(10));
+    }
+    for client in &lab {
+        ack_from(&mut harness, client, 1);
+        ack_from(&mut harness, client, 2);
+    }
+
+    // A single client later on does not move the peaks.
+    harness.clock.advance(Duration::from_secs(5));
+    rrq_from(&mut harness, &lab[0], "pxelinux.0");
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.completed, 8);
+    assert_eq!(metrics.peak_sessions, 8);
+    assert_eq!(metrics.peak_request_rate, 8);
+    assert_eq!(metrics.peak_throughput, 8 * 700);
+    let first_at = metrics.peak_request_rate_at.unwrap();
+    assert_eq!(metrics.peak_sessions_at, Some(first_at));
+    assert!(metrics.gauges().contains(&("peak_sessions", 8)));
+    assert!(metrics.gauges().contains(&("peak_throughput", 8 * 700)));
+
+    harness.server.reset_peaks();
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.peak_sessions, 1);
+    assert_eq!(metrics.peak_request_rate, 0);
+    assert_eq!(metrics.peak_request_rate_at, None);
+    assert_eq!(metrics.peak_throughput, 0);
+    assert_eq!(
+        metrics.peak_sessions_at,
+        Some(first_at + Duration::from_millis(5010))
+    );
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::fs;
+
+use common::{data, error, Harness};
+use tftpd::test_util::TestDir;
+use tftpd::ErrorCode;
+
+#[test]
+fn serves_encoded_space() {
+    let mut harness = Harness::with_args(&["--percent-decode"]);
+    let contents = harness.create_file("my image.img", 100);
+
+    harness.rrq("my%20image.img", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn keeps_percent_literal_by_default() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("my%20image.img", 100);
+    harness.create_file("my image.img", 50);
+
+    harness.rrq("my%20image.img", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn rejects_invalid_sequence() {
+    let mut harness = Harness::with_args(&["--percent-decode"]);
+    harness.create_file("image.img", 100);
+
+    harness.rrq("image%ZZ.img", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(
+            ErrorCode::IllegalOperation,
+            "invalid filename encoding"
+        )]
+    );
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn decoded_traversal_is_refused() {
+    let mut harness = Harness::with_args(&["--percent-decode"]);
+    let outside = TestDir::new();
+    fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+    let escape = format!(
+        "%2e%2e%2f{}%2fsecret.txt",
+        outside.path().file_name().unwrap().to_str().unwrap()
+    );
+
+    harness.rrq(&escape, vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::AccessViolation, "file access violation")]
+    );
+    assert_eq!(harness.server.session_count(), 0);
+}
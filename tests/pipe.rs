@@ -0,0 +1,73 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{io::Cursor, net::UdpSocket};
+
+use common::{option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+/// Acknowledges every block received by `client` from `server` and returns
+/// the received bytes.
+fn download(harness: &mut Harness, block_size: usize) -> Vec<u8> {
+    let mut received = vec![];
+    loop {
+        let buf = harness.recv().unwrap();
+        let Packet::Data { block_num, data } = Packet::deserialize(&buf).unwrap() else {
+            panic!("expected data");
+        };
+        received.extend_from_slice(&data);
+        harness.ack(block_num);
+        if data.len() < block_size {
+            return received;
+        }
+    }
+}
+
+#[test]
+fn streams_the_pipe_once() {
+    let mut harness = Harness::with_args(&["--pipe", "firmware.bin"]);
+    let contents: Vec<u8> = (0..3 * 1024 * 1024 + 77).map(|i| (i % 253) as u8).collect();
+    harness
+        .server
+        .set_pipe_source(Box::new(Cursor::new(contents.clone())));
+
+    harness.rrq(
+        "firmware.bin",
+        vec![
+            option(OptionType::BlockSize, 1468),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+    // The size of a stream is unknown, so the transfer size is left out.
+    assert_eq!(
+        harness.recv().unwrap(),
+        Packet::Oack(vec![option(OptionType::BlockSize, 1468)])
+            .serialize()
+            .unwrap()
+    );
+    harness.ack(0);
+
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "firmware.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    other
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    let mut buf = [0; 1024];
+    let size = other.recv(&mut buf).unwrap();
+    assert_eq!(
+        Packet::deserialize(&buf[..size]).unwrap(),
+        Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "server busy, stream already served".to_string(),
+        }
+    );
+
+    assert_eq!(download(&mut harness, 1468), contents);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
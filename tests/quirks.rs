@@ -0,0 +1,94 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::UdpSocket;
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet};
+
+/// Sends a read request for `filename` with a 1024 bytes blksize from
+/// `client` and returns the first datagram the server answered with.
+fn first_answer(harness: &mut Harness, client: &UdpSocket, filename: &str) -> Vec<u8> {
+    let request = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![option(OptionType::BlockSize, 1024)],
+    };
+    harness.take_sent();
+    client
+        .send_to(&request.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    harness.take_sent().remove(0)
+}
+
+#[test]
+fn scopes_quirk_to_matching_block() {
+    let mut harness = Harness::with_args(&["--quirk", "127.0.0.2/32=no-oack"]);
+    let contents = harness.create_file("pxelinux.0", 700);
+
+    let matching = UdpSocket::bind("127.0.0.2:0").unwrap();
+    assert_eq!(
+        first_answer(&mut harness, &matching, "pxelinux.0"),
+        data(1, &contents[..512])
+    );
+
+    let other = UdpSocket::bind("127.0.0.3:0").unwrap();
+    assert_eq!(
+        first_answer(&mut harness, &other, "pxelinux.0"),
+        b"\x00\x06blksize\x001024\x00"
+    );
+    assert_eq!(harness.server.session_count(), 2);
+}
+
+#[test]
+fn scopes_quirk_to_matching_filename() {
+    let mut harness = Harness::with_args(&["--quirk", "efi/*.efi=max-blksize=600"]);
+    harness.create_file("efi/grubx64.efi", 2000);
+    harness.create_file("pxelinux.0", 2000);
+
+    let client = UdpSocket::bind("127.0.0.2:0").unwrap();
+    assert_eq!(
+        first_answer(&mut harness, &client, "efi/grubx64.efi"),
+        b"\x00\x06blksize\x00600\x00"
+    );
+    assert_eq!(
+        first_answer(&mut harness, &client, "pxelinux.0"),
+        b"\x00\x06blksize\x001024\x00"
+    );
+}
+
+#[test]
+fn resumes_restarted_client_of_matching_block_only() {
+    let mut harness = Harness::with_args(&["--quirk", "127.0.0.0/8=allow-restart"]);
+    let contents = harness.create_file("rootfs.img", 512 * 5 + 10);
+
+    harness.rrq("rootfs.img", vec![]);
+    for block in 1..=4 {
+        harness.ack(block);
+    }
+    harness.take_sent();
+    for _ in 0..3 {
+        harness.ack(1);
+    }
+
+    assert_eq!(
+        harness.take_sent().last().unwrap(),
+        &data(2, &contents[512..1024])
+    );
+    assert_eq!(harness.server.metrics().failed, 0);
+
+    let mut harness = Harness::with_args(&["--quirk", "10.0.0.0/8=allow-restart"]);
+    harness.create_file("rootfs.img", 512 * 5 + 10);
+
+    harness.rrq("rootfs.img", vec![]);
+    for block in 1..=4 {
+        harness.ack(block);
+    }
+    for _ in 0..3 {
+        harness.ack(1);
+    }
+
+    assert_eq!(harness.server.metrics().failed, 1);
+}
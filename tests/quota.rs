@@ -0,0 +1,98 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use common::{error, Harness};
+use tftpd::{ErrorCode, Packet, QuotaUsage};
+
+fn quota_error(minutes: u64) -> Vec<u8> {
+    error(
+        ErrorCode::NotDefined,
+        &format!("quota exceeded, retry after {minutes} minutes"),
+    )
+}
+
+/// Downloads the 600 bytes of `boot.img`.
+fn download(harness: &mut Harness) {
+    harness.rrq("boot.img", vec![]);
+    harness.ack(1);
+    harness.ack(2);
+}
+
+#[test]
+fn refuses_requests_over_quota_until_window_ends() {
+    let mut harness = Harness::with_args(&["--quota", "1000/1h"]);
+    harness.create_file("boot.img", 600);
+
+    download(&mut harness);
+    harness.advance(Duration::from_secs(60));
+    // Started under the quota, the transfer completes past it.
+    download(&mut harness);
+    assert_eq!(harness.server.metrics().completed, 2);
+
+    harness.advance(Duration::from_secs(29 * 60));
+    harness.take_sent();
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), [quota_error(30)]);
+    assert_eq!(harness.server.metrics().quota_rejections, 1);
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(
+        harness.server.quota_usage(),
+        [QuotaUsage {
+            ip: harness.server_addr().ip(),
+            bytes: 1200,
+            window_left: Duration::from_secs(30 * 60),
+        }]
+    );
+
+    // The window opened with the first bytes, an hour ago.
+    harness.advance(Duration::from_secs(30 * 60));
+    assert!(harness.server.quota_usage().is_empty());
+    download(&mut harness);
+    assert_eq!(harness.server.metrics().completed, 3);
+    assert_eq!(harness.server.metrics().quota_rejections, 1);
+}
+
+#[test]
+fn counts_each_host_apart() {
+    let mut harness = Harness::with_args(&["--quota", "500/10m"]);
+    harness.create_file("boot.img", 600);
+    download(&mut harness);
+
+    let other = UdpSocket::bind("127.0.0.2:0").unwrap();
+    let request = Packet::Rrq {
+        filename: "boot.img".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    harness.take_sent();
+    other
+        .send_to(&request.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    assert_eq!(harness.take_sent().len(), 1);
+    assert_eq!(harness.server.session_count(), 1);
+
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), [quota_error(10)]);
+}
+
+#[test]
+fn aborts_running_transfers_in_hard_mode() {
+    let mut harness = Harness::with_args(&["--quota", "1000/1h", "--quota-hard"]);
+    harness.create_file("boot.img", 2000);
+
+    harness.rrq("boot.img", vec![]);
+    harness.ack(1);
+    harness.take_sent();
+    harness.ack(2);
+
+    assert_eq!(harness.take_sent(), [quota_error(60)]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.quota_aborts, 1);
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(harness.server.session_count(), 0);
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use common::{data, error, Harness};
+use tftpd::{ErrorCode, Packet};
+
+fn busy() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "server busy, retry later")
+}
+
+/// Sends a read request from another client socket.
+fn rrq_from(harness: &mut Harness, client: &UdpSocket, filename: &str) {
+    let packet = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&packet.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+fn sent_to(harness: &Harness, to: SocketAddr) -> Vec<Vec<u8>> {
+    let sent = harness
+        .socket
+        .sent()
+        .into_iter()
+        .filter(|(addr, _)| *addr == to)
+        .map(|(_, buf)| buf)
+        .collect();
+    harness.socket.clear_sent();
+    sent
+}
+
+#[test]
+fn queued_request_is_served_when_reader_finishes() {
+    let mut harness = Harness::with_args(&["--max-readers-per-file", "1"]);
+    let contents = harness.create_file("image.bin", 700);
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("image.bin", vec![]);
+    rrq_from(&mut harness, &other, "image.bin");
+
+    // Only the first client is served while the second one waits.
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+    assert_eq!(harness.server.session_count(), 1);
+    assert_eq!(harness.server.metrics().queue_length, 1);
+
+    // A retried request keeps waiting instead of being queued twice.
+    harness.advance(Duration::from_secs(2));
+    rrq_from(&mut harness, &other, "image.bin");
+    assert_eq!(harness.server.metrics().queue_length, 1);
+
+    harness.ack(1);
+    harness.ack(2);
+
+    assert_eq!(
+        sent_to(&harness, other.local_addr().unwrap()),
+        vec![data(1, &contents[..512])]
+    );
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.queue_length, 0);
+    assert_eq!(metrics.queue_served, 1);
+    assert_eq!(metrics.queue_wait, Duration::from_secs(2));
+    assert_eq!(harness.server.session_count(), 1);
+}
+
+#[test]
+fn other_files_are_not_limited() {
+    let mut harness = Harness::with_args(&["--max-readers-per-file", "1"]);
+    harness.create_file("a.bin", 100);
+    let contents = harness.create_file("b.bin", 100);
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("a.bin", vec![]);
+    harness.take_sent();
+    rrq_from(&mut harness, &other, "b.bin");
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    assert_eq!(harness.server.session_count(), 2);
+}
+
+#[test]
+fn rejects_busy_file_when_configured() {
+    let mut harness = Harness::with_args(&["--max-readers-per-file", "1", "--when-busy", "reject"]);
+    harness.create_file("image.bin", 700);
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("image.bin", vec![]);
+    harness.take_sent();
+    rrq_from(&mut harness, &other, "image.bin");
+
+    assert_eq!(sent_to(&harness, other.local_addr().unwrap()), vec![busy()]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.busy_rejections, 1);
+    assert_eq!(metrics.queue_length, 0);
+}
+
+#[test]
+fn queued_request_expires_when_client_stops_retrying() {
+    let mut harness = Harness::with_args(&["--max-readers-per-file", "1"]);
+    harness.create_file("image.bin", 700);
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("image.bin", vec![]);
+    rrq_from(&mut harness, &other, "image.bin");
+    harness.take_sent();
+
+    harness.advance(Duration::from_secs(5));
+
+    assert_eq!(sent_to(&harness, other.local_addr().unwrap()), vec![busy()]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.queue_length, 0);
+    assert_eq!(metrics.busy_rejections, 1);
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use tftpd::test_util::TestDir;
+use tftpd::{Config, Packet, Server};
+
+/// Sends `packet` from `client` to `to` and polls `server` until an answer
+/// arrives, returning it with its source, `None` without an answer.
+fn exchange(
+    server: &mut Server,
+    client: &UdpSocket,
+    packet: Packet,
+    to: SocketAddr,
+) -> Option<(Packet, SocketAddr)> {
+    client.send_to(&packet.serialize().unwrap(), to).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    let mut buf = [0; 1024];
+    for _ in 0..10 {
+        server.poll().unwrap();
+        match client.recv_from(&mut buf) {
+            Ok((size, from)) => return Some((Packet::deserialize(&buf[..size]).unwrap(), from)),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => panic!("{err}"),
+        }
+    }
+    None
+}
+
+fn rrq(filename: &str) -> Packet {
+    Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    }
+}
+
+fn block_of(answer: Option<(Packet, SocketAddr)>, expected_from: SocketAddr) -> u16 {
+    match answer {
+        Some((Packet::Data { block_num, .. }, from)) if from == expected_from => block_num,
+        other => panic!("expected data from {expected_from}, got {other:?}"),
+    }
+}
+
+#[test]
+fn drains_previous_address_after_rebind() {
+    let dir = TestDir::new();
+    dir.create_file("rootfs.img", 512 * 3 + 100);
+    dir.create_file("pxelinux.0", 700);
+    let mut server = Server::new(&Config {
+        directory: dir.path().to_path_buf(),
+        bind_host: Some("127.0.0.1".to_string()),
+        port: 0,
+        ..Config::default()
+    })
+    .unwrap();
+    let previous = server.listen_addresses()[0];
+    let current = SocketAddr::from(([127, 0, 0, 2], previous.port()));
+
+    let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let answer = exchange(&mut server, &first, rrq("rootfs.img"), previous);
+    assert_eq!(block_of(answer, previous), 1);
+
+    server.rebind(current.ip()).unwrap();
+    assert_eq!(server.listen_addresses(), [previous, current]);
+
+    let answer = exchange(&mut server, &first, Packet::Ack(1), previous);
+    assert_eq!(block_of(answer, previous), 2);
+
+    let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+    assert!(exchange(&mut server, &second, rrq("pxelinux.0"), previous).is_none());
+    let answer = exchange(&mut server, &second, rrq("pxelinux.0"), current);
+    assert_eq!(block_of(answer, current), 1);
+
+    for block in 2..=3 {
+        let answer = exchange(&mut server, &first, Packet::Ack(block), previous);
+        assert_eq!(block_of(answer, previous), block + 1);
+    }
+    assert_eq!(server.listen_addresses(), [previous, current]);
+    assert!(exchange(&mut server, &first, Packet::Ack(4), previous).is_none());
+
+    assert_eq!(server.listen_addresses(), [current]);
+    assert_eq!(server.metrics().completed, 1);
+}
+
+#[test]
+fn refuses_rebind_of_single_socket() {
+    let dir = TestDir::new();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let mut server = Server::with_socket(
+        &Config {
+            directory: dir.path().to_path_buf(),
+            ..Config::default()
+        },
+        socket,
+    )
+    .unwrap();
+
+    assert!(server.rebind("127.0.0.2".parse().unwrap()).is_err());
+    assert_eq!(server.listen_addresses(), [address]);
+}
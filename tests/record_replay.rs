@@ -0,0 +1,100 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    fs,
+    net::{SocketAddr, UdpSocket},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use common::{data, Harness};
+use tftpd::test_util::TestDir;
+use tftpd::{Config, Direction, Recording, Server};
+
+/// Runs a server on the system clock for `directory` in a thread until
+/// `stop` is set.
+fn spawn_server(directory: &Path, stop: Arc<AtomicBool>) -> (SocketAddr, thread::JoinHandle<()>) {
+    let config = Config::new(
+        ["/", "-d", directory.to_str().unwrap()]
+            .iter()
+            .map(|s| s.to_string()),
+    )
+    .unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let mut server = Server::with_socket(&config, socket).unwrap();
+    let handle = thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            server.poll().unwrap();
+        }
+    });
+    (addr, handle)
+}
+
+/// Records a transfer of a 1000 byte file and returns the recording.
+fn record_transfer(harness: &mut Harness, records: &Path) -> Recording {
+    let content = harness.create_file("boot.bin", 1000);
+    harness.rrq("boot.bin", vec![]);
+    assert_eq!(harness.recv(), Some(data(1, &content[..512])));
+    harness.ack(1);
+    assert_eq!(harness.recv(), Some(data(2, &content[512..])));
+    harness.ack(2);
+
+    let files: Vec<_> = fs::read_dir(records).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    Recording::load(&files[0].as_ref().unwrap().path()).unwrap()
+}
+
+#[test]
+fn replays_recorded_transfer_without_divergence() {
+    let records = TestDir::new();
+    let mut harness = Harness::with_args(&["--record", records.path().to_str().unwrap()]);
+    let recording = record_transfer(&mut harness, records.path());
+
+    let directions: Vec<_> = recording.datagrams.iter().map(|d| d.direction).collect();
+    assert_eq!(
+        directions,
+        vec![
+            Direction::ToServer,
+            Direction::ToClient,
+            Direction::ToServer,
+            Direction::ToClient,
+            Direction::ToServer,
+        ]
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (addr, handle) = spawn_server(harness.dir.path(), stop.clone());
+    let divergences = recording.replay(addr, true).unwrap();
+    stop.store(true, Ordering::Relaxed);
+    handle.join().unwrap();
+
+    assert_eq!(divergences, vec![]);
+}
+
+#[test]
+fn reports_changed_answers() {
+    let records = TestDir::new();
+    let mut harness = Harness::with_args(&["--record", records.path().to_str().unwrap()]);
+    let recording = record_transfer(&mut harness, records.path());
+    fs::write(harness.dir.path().join("boot.bin"), vec![7; 1000]).unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (addr, handle) = spawn_server(harness.dir.path(), stop.clone());
+    let divergences = recording.replay(addr, true).unwrap();
+    stop.store(true, Ordering::Relaxed);
+    handle.join().unwrap();
+
+    assert_eq!(divergences.len(), 2);
+    assert_eq!(divergences[0].index, 1);
+    assert_eq!(divergences[0].actual, Some(data(1, &[7; 512])));
+    assert!(divergences[0]
+        .to_string()
+        .starts_with("datagram 1: expected "));
+}
@@ -0,0 +1,132 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use common::{data, error, option, Harness};
+use tftpd::{Authorizer, Decision, ErrorCode, OptionType, RequestInfo, TransferOption};
+
+/// What the authorizer saw of a request: its raw options, negotiated window
+/// size and block size, and its transfer and journey IDs.
+type Seen = (Vec<TransferOption>, Option<(u16, usize)>, u64, u64);
+
+/// Serves iPXE, which asks for a window size, and sends vendor ROMs to
+/// `undionly.kpxe` instead.
+#[derive(Default)]
+struct IpxeOnly {
+    seen: Mutex<Vec<Seen>>,
+}
+
+impl Authorizer for IpxeOnly {
+    fn authorize(&self, _: &SocketAddr, _: &str) -> Decision {
+        unreachable!("authorize_request() is implemented")
+    }
+
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        self.seen.lock().unwrap().push((
+            request.options.clone(),
+            request
+                .negotiated
+                .as_ref()
+                .map(|negotiated| (negotiated.windowsize, negotiated.blk_size)),
+            request.transfer,
+            request.journey,
+        ));
+        match &request.negotiated {
+            Some(negotiated) if negotiated.windowsize > 1 => Decision::Allow,
+            _ => Decision::Deny {
+                code: ErrorCode::AccessViolation,
+                message: "chainload undionly.kpxe".to_string(),
+            },
+        }
+    }
+}
+
+fn ipxe_harness(args: &[&str]) -> (Harness, Arc<IpxeOnly>) {
+    let mut harness = Harness::with_args(args);
+    let authorizer = Arc::new(IpxeOnly::default());
+    harness.server.set_authorizer(authorizer.clone());
+    (harness, authorizer)
+}
+
+fn chainload_error() -> Vec<u8> {
+    error(ErrorCode::AccessViolation, "chainload undionly.kpxe")
+}
+
+#[test]
+fn branches_on_negotiated_windowsize() {
+    let (mut harness, authorizer) = ipxe_harness(&[]);
+    harness.create_file("boot.ipxe", 100);
+
+    harness.rrq("boot.ipxe", vec![option(OptionType::BlockSize, 1024)]);
+    assert_eq!(harness.take_sent(), [chainload_error()]);
+
+    let options = vec![
+        option(OptionType::BlockSize, 1024),
+        option(OptionType::Windowsize, 4),
+    ];
+    harness.rrq("boot.ipxe", options.clone());
+    assert_eq!(
+        harness.take_sent(),
+        [b"\x00\x06blksize\x001024\x00windowsize\x004\x00".to_vec()]
+    );
+
+    let seen = authorizer.seen.lock().unwrap();
+    assert_eq!(seen[0].0, [option(OptionType::BlockSize, 1024)]);
+    assert_eq!(seen[0].1, Some((1, 1024)));
+    assert_eq!(seen[1].0, options);
+    assert_eq!(seen[1].1, Some((4, 1024)));
+    assert_eq!((seen[1].2, seen[1].3), (1, 1));
+}
+
+#[test]
+fn previews_quirks_and_limits() {
+    let (mut harness, authorizer) = ipxe_harness(&["--quirk", "127.0.0.0/8=no-oack"]);
+    harness.create_file("boot.ipxe", 100);
+
+    harness.rrq("boot.ipxe", vec![option(OptionType::Windowsize, 4)]);
+    assert_eq!(harness.take_sent(), [chainload_error()]);
+
+    let seen = authorizer.seen.lock().unwrap();
+    assert_eq!(seen[0].0, [option(OptionType::Windowsize, 4)]);
+    assert_eq!(seen[0].1, Some((1, 512)));
+}
+
+#[test]
+fn counts_transfers_of_journey() {
+    let mut harness = Harness::new();
+    let seen = Arc::new(Mutex::new(vec![]));
+    let recorder = seen.clone();
+    harness.server.set_authorizer(Arc::new(RecordIds(recorder)));
+    let first = harness.create_file("pxelinux.0", 100);
+    let second = harness.create_file("ldlinux.c32", 100);
+
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &first)]);
+    harness.ack(1);
+    harness.rrq("ldlinux.c32", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &second)]);
+
+    assert_eq!(*seen.lock().unwrap(), [(1, 1), (2, 1)]);
+}
+
+/// Records the transfer and journey IDs of every request.
+struct RecordIds(Arc<Mutex<Vec<(u64, u64)>>>);
+
+impl Authorizer for RecordIds {
+    fn authorize(&self, _: &SocketAddr, _: &str) -> Decision {
+        Decision::Allow
+    }
+
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        self.0
+            .lock()
+            .unwrap()
+            .push((request.transfer, request.journey));
+        Decision::Allow
+    }
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+/// Read request captured from a bootloader, with a trailing space in the
+/// filename and garbage after the mode.
+const CAPTURED: &[u8] = b"\x00\x01pxelinux.0 \x00octet\x00\x00garbage";
+
+#[test]
+fn serves_trimmed_filename() {
+    let mut harness = Harness::with_args(&["--trim-request-whitespace"]);
+    let contents = harness.create_file("pxelinux.0", 100);
+
+    harness.send_raw(CAPTURED);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn keeps_whitespace_by_default() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 100);
+
+    harness.send_raw(CAPTURED);
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::FileNotFound, "file does not exist")]
+    );
+}
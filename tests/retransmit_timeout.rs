@@ -0,0 +1,105 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, error, option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+#[test]
+fn server_default_timeout_triggers_resends() {
+    let mut harness = Harness::with_args(&["--retransmit-timeout", "200"]);
+    let contents = harness.create_file("image.bin", 700);
+
+    harness.rrq("image.bin", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+
+    harness.advance(Duration::from_millis(150));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(50));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+
+    harness.advance(Duration::from_millis(200));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+    assert_eq!(harness.server.metrics().retransmits, 2);
+}
+
+#[test]
+fn negotiated_timeout_overrides_server_default() {
+    let mut harness = Harness::with_args(&["--retransmit-timeout", "200"]);
+    harness.create_file("image.bin", 700);
+    let oack = Packet::Oack(vec![option(OptionType::Timeout, 3)])
+        .serialize()
+        .unwrap();
+
+    harness.rrq("image.bin", vec![option(OptionType::Timeout, 3)]);
+    assert_eq!(harness.take_sent(), vec![oack.clone()]);
+
+    harness.advance(Duration::from_millis(2900));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_millis(100));
+    assert_eq!(harness.take_sent(), vec![oack]);
+}
+
+#[test]
+fn gives_up_after_max_retries() {
+    let mut harness = Harness::with_args(&["--retransmit-timeout", "200", "--max-retries", "2"]);
+    let contents = harness.create_file("image.bin", 700);
+
+    harness.rrq("image.bin", vec![]);
+    harness.take_sent();
+    for _ in 0..2 {
+        harness.advance(Duration::from_millis(200));
+        assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+    }
+
+    harness.advance(Duration::from_millis(200));
+    assert_eq!(
+        harness.take_sent(),
+        vec![error(ErrorCode::NotDefined, "transfer timed out")]
+    );
+    assert_eq!(harness.server.session_count(), 0);
+    assert_eq!(harness.server.metrics().retransmits, 2);
+}
+
+#[test]
+fn ack_moves_the_deadline() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 1500);
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_secs(3));
+    harness.ack(1);
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(1, &contents[..512]), data(2, &contents[512..1024])]
+    );
+
+    harness.advance(Duration::from_secs(4));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.take_sent(), vec![data(2, &contents[512..1024])]);
+    assert_eq!(harness.server.metrics().retransmits, 1);
+}
+
+#[test]
+fn restarted_transfer_ignores_previous_deadline() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("image.bin", 700);
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_secs(3));
+    harness.rrq("image.bin", vec![]);
+    harness.take_sent();
+
+    harness.advance(Duration::from_secs(2));
+    assert!(harness.take_sent().is_empty());
+
+    harness.advance(Duration::from_secs(3));
+    assert_eq!(harness.take_sent(), vec![data(1, &contents[..512])]);
+    assert_eq!(harness.server.metrics().retransmits, 1);
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{data, option, Harness, Recorder};
+use tftpd::{OptionType, Packet, TransferEvent};
+
+#[test]
+fn triple_rrq_counts_as_one_request() {
+    let mut harness = Harness::new();
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+    let contents = harness.create_file("pxelinux.0", 700);
+    let options = vec![
+        option(OptionType::BlockSize, 1024),
+        option(OptionType::TransferSize, 0),
+    ];
+
+    for _ in 0..3 {
+        harness.rrq("pxelinux.0", options.clone());
+    }
+
+    let oack = Packet::Oack(vec![
+        option(OptionType::BlockSize, 1024),
+        option(OptionType::TransferSize, 700),
+    ])
+    .serialize()
+    .unwrap();
+    assert_eq!(harness.take_sent(), vec![oack.clone(), oack.clone(), oack]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.requests, 1);
+    assert_eq!(metrics.retransmitted_requests, 2);
+
+    harness.ack(0);
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    let started = recorder
+        .events()
+        .iter()
+        .filter(|event| matches!(event, TransferEvent::Started { .. }))
+        .count();
+    assert_eq!(started, 1);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
+
+#[test]
+fn different_or_late_rrq_is_a_new_request() {
+    let mut harness = Harness::new();
+    harness.create_file("pxelinux.0", 1500);
+
+    harness.rrq("pxelinux.0", vec![]);
+    harness.rrq("pxelinux.0", vec![option(OptionType::BlockSize, 1024)]);
+    assert_eq!(harness.server.metrics().requests, 2);
+
+    harness.ack(0);
+    harness.ack(1);
+    harness.rrq("pxelinux.0", vec![option(OptionType::BlockSize, 1024)]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.requests, 3);
+    assert_eq!(metrics.retransmitted_requests, 0);
+}
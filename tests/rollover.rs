@@ -0,0 +1,125 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{net::UdpSocket, time::Duration};
+
+use common::option;
+use tftpd::test_util::{assert_file_eq, TestDir, TestServer};
+use tftpd::{Client, Config, OptionType, Packet, TransferOption};
+
+/// Block size small enough for the block numbers to wrap around quickly.
+const BLK_SIZE: usize = 8;
+/// Two blocks past the last block number, and a short one.
+const LEN: usize = (65535 + 2) * BLK_SIZE + 3;
+
+fn start(dir: &TestDir, rollover: u16) -> TestServer {
+    TestServer::with_config(&Config {
+        directory: dir.path().to_path_buf(),
+        port: 0,
+        rollover,
+        ..Config::default()
+    })
+}
+
+/// Downloads `filename` with `options`, checking that every DATA carries
+/// the block number expected with `rollover`, and returns its contents.
+fn download(
+    server: &TestServer,
+    filename: &str,
+    options: Vec<TransferOption>,
+    rollover: u16,
+) -> Vec<u8> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let rrq = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options,
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), server.addr())
+        .unwrap();
+
+    let mut buf = [0; 1024];
+    let mut contents = vec![];
+    let mut expected: u16 = 1;
+    loop {
+        let (size, from) = socket.recv_from(&mut buf).unwrap();
+        let (ack, last) = match Packet::deserialize(&buf[..size]).unwrap() {
+            Packet::Oack(_) => (0, false),
+            Packet::Data { block_num, data } => {
+                assert_eq!(block_num, expected, "after {} bytes", contents.len());
+                contents.extend_from_slice(&data);
+                expected = block_num.checked_add(1).unwrap_or(rollover);
+                (block_num, data.len() < BLK_SIZE)
+            }
+            packet => panic!("unexpected {packet:?}"),
+        };
+        socket
+            .send_to(&Packet::Ack(ack).serialize().unwrap(), from)
+            .unwrap();
+        if last {
+            return contents;
+        }
+    }
+}
+
+#[test]
+fn sends_past_last_block_number_rolling_over_to_zero() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("disk.img", LEN);
+    let server = start(&dir, 0);
+
+    let mut client = Client::new(server.addr());
+    client.set_timeout(Duration::from_millis(200));
+    let download = client
+        .get(
+            "disk.img",
+            vec![
+                option(OptionType::BlockSize, BLK_SIZE as u64),
+                option(OptionType::Windowsize, 16),
+            ],
+        )
+        .unwrap();
+
+    assert_file_eq(&download.data, &contents);
+    assert_eq!(server.shutdown().metrics().completed, 1);
+}
+
+#[test]
+fn rolls_over_to_one_with_flag() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("disk.img", LEN);
+    let server = start(&dir, 1);
+
+    let received = download(
+        &server,
+        "disk.img",
+        vec![option(OptionType::BlockSize, BLK_SIZE as u64)],
+        1,
+    );
+
+    assert_file_eq(&received, &contents);
+}
+
+#[test]
+fn honors_requested_rollover() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("disk.img", LEN);
+    let server = start(&dir, 0);
+
+    let received = download(
+        &server,
+        "disk.img",
+        vec![
+            option(OptionType::BlockSize, BLK_SIZE as u64),
+            option(OptionType::Rollover, 1),
+        ],
+        1,
+    );
+
+    assert_file_eq(&received, &contents);
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::fs;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+fn access_violation() -> Vec<u8> {
+    error(ErrorCode::AccessViolation, "file access violation")
+}
+
+#[test]
+fn serves_leading_slash_from_directory() {
+    let mut harness = Harness::new();
+    fs::create_dir(harness.dir.path().join("firmware")).unwrap();
+    let contents = harness.create_file("firmware/phone.bin", 100);
+
+    harness.rrq("/firmware/phone.bin", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn serves_repeated_leading_slashes_from_directory() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("//boot.img", vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn serves_absolute_path_into_directory() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+    let absolute = harness.dir.path().join("boot.img");
+
+    harness.rrq(absolute.to_str().unwrap(), vec![]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn refuses_escape_after_leading_slash() {
+    let mut harness = Harness::new();
+    harness.create_file("boot.img", 100);
+
+    for filename in ["/../etc/passwd", "//../boot.img"] {
+        harness.rrq(filename, vec![]);
+
+        assert_eq!(harness.take_sent(), vec![access_violation()], "{filename}");
+    }
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn strict_paths_refuse_leading_slash() {
+    let mut harness = Harness::with_args(&["--strict-paths"]);
+    let contents = harness.create_file("boot.img", 100);
+
+    harness.rrq("/boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![access_violation()]);
+
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
@@ -0,0 +1,150 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+use tftpd::test_util::TestDir;
+use tftpd::{send_file, ErrorCode, OptionType, Packet, TransferOption};
+
+/// A device waiting in client mode for a file pushed to it.
+struct Receiver {
+    socket: UdpSocket,
+}
+
+impl Receiver {
+    fn new() -> Receiver {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        Receiver { socket }
+    }
+
+    fn addr(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    fn recv(&self) -> (Packet, SocketAddr) {
+        let mut buf = [0; 65536];
+        let (size, from) = self.socket.recv_from(&mut buf).unwrap();
+        (Packet::deserialize(&buf[..size]).unwrap(), from)
+    }
+
+    fn ack(&self, block: u16, to: SocketAddr) {
+        let buf = Packet::Ack(block).serialize().unwrap();
+        self.socket.send_to(&buf, to).unwrap();
+    }
+
+    /// Acknowledges every block until the last one and returns the file.
+    fn receive_file(&self, blk_size: usize) -> Vec<u8> {
+        let mut contents = vec![];
+        loop {
+            let (packet, from) = self.recv();
+            let Packet::Data { block_num, data } = packet else {
+                panic!("expected DATA, got {packet}");
+            };
+            contents.extend(&data);
+            self.ack(block_num, from);
+            if data.len() < blk_size {
+                return contents;
+            }
+        }
+    }
+}
+
+fn local() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[test]
+fn pushes_file_to_waiting_client() {
+    let dir = TestDir::new();
+    let contents: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    dir.write("config.bin", &contents);
+    let path = dir.path().join("config.bin");
+    let receiver = Receiver::new();
+
+    let transfer = send_file(local(), receiver.addr(), &path, vec![]).unwrap();
+
+    assert_eq!(receiver.receive_file(512), contents);
+    let sent = transfer.join().unwrap();
+    assert_eq!(sent.bytes, 1300);
+}
+
+#[test]
+fn reports_time_waiting_for_client() {
+    let dir = TestDir::new();
+    dir.write("config.bin", &[7; 600]);
+    let path = dir.path().join("config.bin");
+    let receiver = Receiver::new();
+
+    let transfer = send_file(local(), receiver.addr(), &path, vec![]).unwrap();
+    for block in 1..=2 {
+        let (packet, from) = receiver.recv();
+        assert!(matches!(packet, Packet::Data { block_num, .. } if block_num == block));
+        thread::sleep(Duration::from_millis(100));
+        receiver.ack(block, from);
+    }
+
+    let sent = transfer.join().unwrap();
+    assert_eq!(sent.bytes, 600);
+    assert!(
+        sent.network_wait >= Duration::from_millis(200),
+        "{:?}",
+        sent.network_wait
+    );
+}
+
+#[test]
+fn negotiates_requested_options() {
+    let dir = TestDir::new();
+    let contents = vec![7; 3000];
+    dir.write("config.bin", &contents);
+    let path = dir.path().join("config.bin");
+    let receiver = Receiver::new();
+    let options = vec![TransferOption {
+        option: OptionType::BlockSize,
+        value: 1024,
+    }];
+
+    let transfer = send_file(local(), receiver.addr(), &path, options.clone()).unwrap();
+
+    let (oack, from) = receiver.recv();
+    assert_eq!(oack, Packet::Oack(options));
+    assert_eq!(from, transfer.local_addr());
+    receiver.ack(0, from);
+    assert_eq!(receiver.receive_file(1024), contents);
+    assert_eq!(transfer.join().map(|sent| sent.bytes), Ok(3000));
+}
+
+#[test]
+fn reports_progress_and_aborts() {
+    let dir = TestDir::new();
+    dir.write("image.bin", &[1; 2048]);
+    let path = dir.path().join("image.bin");
+    let receiver = Receiver::new();
+
+    let mut transfer = send_file(local(), receiver.addr(), &path, vec![]).unwrap();
+    let (packet, from) = receiver.recv();
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+    receiver.ack(1, from);
+    receiver.recv();
+
+    let progress = transfer.progress();
+    assert_eq!(progress.bytes_acked, 512);
+    assert_eq!(progress.tsize, Some(2048));
+    assert!(!transfer.is_finished());
+
+    transfer.abort();
+    assert_eq!(
+        receiver.recv().0,
+        Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "transfer aborted".to_string(),
+        }
+    );
+    assert_eq!(transfer.join(), Err("transfer aborted".to_string()));
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, error, option, Harness};
+use tftpd::{ErrorCode, OptionType};
+
+fn timed_out() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "transfer timed out")
+}
+
+#[test]
+fn expires_session_of_vanished_client() {
+    let mut harness = Harness::with_args(&["--session-timeout", "30"]);
+    harness.create_file("image.bin", 700);
+    // Retransmissions alone would keep the session for minutes.
+    harness.rrq("image.bin", vec![option(OptionType::Timeout, 255)]);
+    harness.take_sent();
+
+    harness.advance(Duration::from_secs(29));
+    assert_eq!(harness.server.session_count(), 1);
+
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.take_sent(), [timed_out()]);
+    assert_eq!(harness.server.session_count(), 0);
+    let metrics = harness.server.metrics();
+    assert_eq!((metrics.idle_sessions, metrics.failed), (1, 1));
+}
+
+#[test]
+fn acks_keep_session_alive() {
+    let mut harness = Harness::with_args(&["--session-timeout", "30"]);
+    let contents = harness.create_file("image.bin", 1500);
+    harness.rrq("image.bin", vec![option(OptionType::Timeout, 255)]);
+    harness.take_sent();
+
+    harness.advance(Duration::from_secs(20));
+    harness.ack(0);
+    assert_eq!(harness.take_sent(), [data(1, &contents[..512])]);
+    harness.advance(Duration::from_secs(20));
+    harness.ack(1);
+    harness.advance(Duration::from_secs(20));
+
+    assert!(harness.take_sent().iter().all(|sent| *sent != timed_out()));
+    assert_eq!(harness.server.session_count(), 1);
+
+    harness.advance(Duration::from_secs(10));
+    assert_eq!(harness.take_sent(), [timed_out()]);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn never_expires_with_zero() {
+    let mut harness = Harness::with_args(&["--session-timeout", "0"]);
+    harness.create_file("image.bin", 700);
+    harness.rrq("image.bin", vec![option(OptionType::Timeout, 255)]);
+
+    harness.advance(Duration::from_secs(200));
+
+    assert_eq!(harness.server.session_count(), 1);
+    assert_eq!(harness.server.metrics().idle_sessions, 0);
+}
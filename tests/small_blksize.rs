@@ -0,0 +1,72 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{option, Harness};
+use tftpd::{ErrorCode, OptionType, Packet};
+
+const BLKSIZE: usize = 128;
+const WINDOWSIZE: u16 = 16;
+
+#[test]
+fn grants_small_block_sizes_as_requested() {
+    let mut harness = Harness::new();
+    harness.create_file("rom.bin", 1000);
+
+    for blksize in [8, 128, 511, 512] {
+        harness.rrq("rom.bin", vec![option(OptionType::BlockSize, blksize)]);
+        assert_eq!(
+            harness.take_sent(),
+            vec![Packet::Oack(vec![option(OptionType::BlockSize, blksize)])
+                .serialize()
+                .unwrap()]
+        );
+        harness.send(Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "done".to_string(),
+        });
+    }
+}
+
+#[test]
+fn sends_100_mb_across_many_rollovers() {
+    let mut harness = Harness::new();
+    // 819200 blocks, the block number wraps around 12 times.
+    let size = (100 << 20) + 77;
+    let contents = harness.create_file("rom.bin", size);
+
+    harness.rrq(
+        "rom.bin",
+        vec![
+            option(OptionType::BlockSize, BLKSIZE as u64),
+            option(OptionType::Windowsize, WINDOWSIZE as u64),
+        ],
+    );
+    harness.take_sent();
+    harness.ack(0);
+
+    let mut received = 0;
+    let mut expected_block: u16 = 1;
+    loop {
+        let sent = harness.take_sent();
+        assert!(!sent.is_empty() && sent.len() <= WINDOWSIZE as usize);
+        let mut last = false;
+        for buf in sent {
+            let Packet::Data { block_num, data } = Packet::deserialize(&buf).unwrap() else {
+                panic!("expected a DATA packet");
+            };
+            assert_eq!(block_num, expected_block);
+            assert_eq!(data, contents[received..received + data.len()]);
+            received += data.len();
+            last = data.len() < BLKSIZE;
+            expected_block = expected_block.wrapping_add(1);
+        }
+        harness.ack(expected_block.wrapping_sub(1));
+        if last {
+            break;
+        }
+    }
+
+    assert_eq!(received, size);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
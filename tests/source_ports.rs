@@ -0,0 +1,47 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, Harness};
+
+#[test]
+fn drops_requests_from_privileged_source_ports() {
+    let mut harness = Harness::with_args(&["--reject-privileged-source-ports"]);
+    harness.create_file("pxelinux.0", 100);
+    harness.socket.set_source_port(53);
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert!(harness.take_sent().is_empty());
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.privileged_source_drops, 1);
+    assert_eq!(metrics.requests, 0);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn serves_allowed_privileged_source_port() {
+    let mut harness = Harness::with_args(&[
+        "--reject-privileged-source-ports",
+        "--allow-source-port",
+        "1001",
+    ]);
+    let contents = harness.create_file("pxelinux.0", 100);
+    harness.socket.set_source_port(1001);
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+    assert_eq!(harness.server.metrics().privileged_source_drops, 0);
+}
+
+#[test]
+fn serves_privileged_source_ports_by_default() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("pxelinux.0", 100);
+    harness.socket.set_source_port(123);
+
+    harness.rrq("pxelinux.0", vec![]);
+
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+}
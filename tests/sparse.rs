@@ -0,0 +1,105 @@
+#![cfg(all(feature = "test-util", target_os = "linux"))]
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    hash::{DefaultHasher, Hasher},
+    os::unix::fs::{FileExt, MetadataExt},
+    sync::Arc,
+};
+
+use common::{option, Harness, Recorder};
+use tftpd::{OptionType, Packet, TransferEvent};
+
+const BLKSIZE: usize = 65464;
+const LEN: u64 = (4 << 20) + 77;
+
+/// Writes a 4 MiB sparse file with a few data regions, some of them not
+/// aligned on blocks, and returns whether the file system kept it sparse.
+fn create_sparse_file(harness: &Harness) -> bool {
+    let path = harness.dir.path().join("disk.img");
+    let file = File::create(&path).unwrap();
+    file.set_len(LEN).unwrap();
+    file.write_all_at(&[0x55; 4096], 0).unwrap();
+    file.write_all_at(&[0xAA; 10_000], (1 << 20) + 100).unwrap();
+    file.write_all_at(&[0x33; 77], LEN - 77).unwrap();
+
+    let metadata = fs::metadata(&path).unwrap();
+    metadata.blocks() * 512 < metadata.len()
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Downloads `disk.img` with the largest blocks and returns its contents.
+fn download(harness: &mut Harness) -> Vec<u8> {
+    harness.rrq(
+        "disk.img",
+        vec![option(OptionType::BlockSize, BLKSIZE as u64)],
+    );
+    assert!(matches!(
+        Packet::deserialize(&harness.recv().unwrap()).unwrap(),
+        Packet::Oack(_)
+    ));
+    harness.ack(0);
+
+    let mut contents = vec![];
+    loop {
+        let Packet::Data { block_num, data } =
+            Packet::deserialize(&harness.recv().unwrap()).unwrap()
+        else {
+            panic!("expected data");
+        };
+        contents.extend_from_slice(&data);
+        harness.ack(block_num);
+        if data.len() < BLKSIZE {
+            return contents;
+        }
+    }
+}
+
+#[test]
+fn serves_sparse_file_like_a_plain_read() {
+    for args in [&[][..], &["--skip-holes"][..]] {
+        let mut harness = Harness::with_args(args);
+        create_sparse_file(&harness);
+        let expected = fs::read(harness.dir.path().join("disk.img")).unwrap();
+
+        let contents = download(&mut harness);
+
+        assert_eq!(contents.len() as u64, LEN, "{args:?}");
+        assert_eq!(checksum(&contents), checksum(&expected), "{args:?}");
+        assert_eq!(harness.server.metrics().completed, 1);
+    }
+}
+
+#[test]
+fn reports_allocated_size() {
+    let mut harness = Harness::with_args(&["--skip-holes"]);
+    let sparse = create_sparse_file(&harness);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    download(&mut harness);
+
+    let completed = recorder
+        .events()
+        .into_iter()
+        .find_map(|event| match event {
+            TransferEvent::Completed {
+                size, allocated, ..
+            } => Some((size, allocated)),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(completed.0, Some(LEN));
+    // File systems without holes allocate the whole file.
+    assert_eq!(completed.1.is_some(), sparse);
+    if let Some(allocated) = completed.1 {
+        assert!(allocated < LEN);
+    }
+}
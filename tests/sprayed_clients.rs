@@ -0,0 +1,67 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use common::Harness;
+use tftpd::Packet;
+
+/// Sends `packet` from a new port of the client host, as a load balancer
+/// spreading the client over several servers would let through.
+fn send_from_new_port(harness: &mut Harness, packet: Packet) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .send_to(&packet.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+}
+
+#[test]
+fn flags_client_with_stalled_sessions_and_orphan_acks() {
+    let mut harness = Harness::new();
+    harness.create_file("boot.img", 512 * 4);
+
+    for _ in 0..4 {
+        send_from_new_port(
+            &mut harness,
+            Packet::Rrq {
+                filename: "boot.img".to_string(),
+                mode: "octet".to_string(),
+                options: vec![],
+            },
+        );
+        send_from_new_port(&mut harness, Packet::Ack(1));
+    }
+    assert_eq!(harness.server.metrics().sprayed_clients, 0);
+    for _ in 0..10 {
+        harness.advance(Duration::from_secs(5));
+    }
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.orphan_acks, 4);
+    assert_eq!(metrics.failed, 4);
+    assert_eq!(metrics.sprayed_clients, 1);
+    assert_eq!(
+        harness.server.sprayed_clients(),
+        vec![harness.server_addr().ip()]
+    );
+}
+
+#[test]
+fn does_not_flag_client_completing_transfers() {
+    let mut harness = Harness::new();
+    harness.create_file("boot.img", 100);
+
+    for _ in 0..10 {
+        harness.rrq("boot.img", vec![]);
+        harness.ack(1);
+    }
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.completed, 10);
+    assert_eq!(metrics.orphan_acks, 0);
+    assert_eq!(metrics.sprayed_clients, 0);
+    assert!(harness.server.sprayed_clients().is_empty());
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{io, sync::Arc, time::Duration};
+
+use common::{data, option, Harness, Recorder};
+use tftpd::{OptionType, TransferEvent};
+
+const OACK: [u8; 2] = [0, 6];
+const DATA: [u8; 2] = [0, 3];
+
+/// Harness whose sends of packets starting with `opcode` fail.
+fn failing(args: &[&str], opcode: [u8; 2]) -> (Harness, Arc<Recorder>) {
+    let mut harness = Harness::with_args(args);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+    harness.socket.fail_outgoing_if(
+        move |buf| buf[..2] == opcode,
+        io::ErrorKind::PermissionDenied,
+    );
+    (harness, recorder)
+}
+
+fn assert_start_failed(harness: &Harness, recorder: &Recorder) {
+    assert_eq!(harness.server.session_count(), 0);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.start_failures, 1);
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.active_sessions, 0);
+    let reason = recorder.events().into_iter().find_map(|event| match event {
+        TransferEvent::Failed { reason, .. } => Some(reason),
+        _ => None,
+    });
+    assert!(reason.unwrap().starts_with("first packet not sent"));
+}
+
+#[test]
+fn drops_session_whose_oack_was_not_sent() {
+    let (mut harness, recorder) = failing(&[], OACK);
+    harness.create_file("pxelinux.0", 700);
+    let blksize = vec![option(OptionType::BlockSize, 1024)];
+
+    harness.rrq("pxelinux.0", blksize.clone());
+    assert_start_failed(&harness, &recorder);
+
+    harness
+        .socket
+        .fail_outgoing_if(|_| false, io::ErrorKind::PermissionDenied);
+    harness.take_sent();
+    harness.rrq("pxelinux.0", blksize);
+    assert_eq!(
+        harness.take_sent(),
+        [b"\x00\x06blksize\x001024\x00".to_vec()]
+    );
+    assert_eq!(harness.server.session_count(), 1);
+}
+
+#[test]
+fn drops_session_whose_first_data_was_not_sent() {
+    let (mut harness, recorder) = failing(&[], DATA);
+    let contents = harness.create_file("pxelinux.0", 300);
+
+    harness.rrq("pxelinux.0", vec![]);
+    assert_start_failed(&harness, &recorder);
+
+    harness
+        .socket
+        .fail_outgoing_if(|_| false, io::ErrorKind::PermissionDenied);
+    harness.take_sent();
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.take_sent(), [data(1, &contents)]);
+}
+
+#[test]
+fn drops_delayed_session_whose_first_data_was_not_sent() {
+    let (mut harness, recorder) = failing(&["--initial-delay", "100"], DATA);
+    harness.create_file("pxelinux.0", 300);
+
+    harness.rrq("pxelinux.0", vec![]);
+    assert_eq!(harness.server.session_count(), 1);
+    harness.advance(Duration::from_millis(100));
+
+    assert_start_failed(&harness, &recorder);
+}
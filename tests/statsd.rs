@@ -0,0 +1,93 @@
+#![cfg(all(feature = "metrics", feature = "test-util"))]
+
+mod common;
+
+use std::{collections::HashMap, net::UdpSocket, time::Duration};
+
+use common::Harness;
+
+/// Receives every line sent to `sink` and sums up the values of each metric.
+fn collect(sink: &UdpSocket) -> HashMap<String, u64> {
+    let mut totals = HashMap::new();
+    let mut buf = [0; 2048];
+    while let Ok(size) = sink.recv(&mut buf) {
+        let datagram = String::from_utf8(buf[..size].to_vec()).unwrap();
+        for line in datagram.lines() {
+            let (name, rest) = line.split_once(':').unwrap();
+            let fields: Vec<&str> = rest.split('|').collect();
+            assert_eq!(fields.len(), 3, "malformed line {line}");
+            assert!(
+                fields[1] == "c" || fields[1] == "g",
+                "malformed line {line}"
+            );
+            if name.ends_with(".build_info") {
+                let version = env!("CARGO_PKG_VERSION");
+                assert_eq!(fields[2], format!("#instance:test,version:{version}"));
+            } else {
+                assert_eq!(fields[2], "#instance:test");
+            }
+            let value: u64 = fields[0].parse().unwrap();
+            match fields[1] {
+                "c" => *totals.entry(name.to_string()).or_default() += value,
+                _ => {
+                    totals.insert(name.to_string(), value);
+                }
+            }
+        }
+    }
+    totals
+}
+
+#[test]
+fn sends_metrics_during_transfer() {
+    let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sink.set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let sink_addr = sink.local_addr().unwrap().to_string();
+    let mut harness = Harness::with_args(&[
+        "--statsd",
+        &sink_addr,
+        "--statsd-prefix",
+        "pxe",
+        "--statsd-tag",
+        "instance:test",
+    ]);
+    harness.create_file("image.bin", 700);
+
+    harness.rrq("image.bin", vec![]);
+    let totals = collect(&sink);
+    assert_eq!(totals["pxe.requests"], 1);
+    assert_eq!(totals["pxe.active_sessions"], 1);
+
+    harness.ack(1);
+    harness.ack(2);
+    harness.advance(Duration::from_secs(1));
+
+    let totals = collect(&sink);
+    assert_eq!(totals["pxe.requests"], 0);
+    assert_eq!(totals["pxe.completed"], 1);
+    assert_eq!(totals["pxe.failed"], 0);
+    assert_eq!(totals["pxe.bytes_sent"], 700 - 512);
+    assert_eq!(totals["pxe.retransmits"], 0);
+    assert_eq!(totals["pxe.active_sessions"], 0);
+}
+
+#[test]
+fn unreachable_agent_does_not_affect_transfers() {
+    // Nothing listens on the sink port once it is dropped.
+    let sink_addr = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut harness = Harness::with_args(&["--statsd", &sink_addr]);
+    harness.create_file("image.bin", 100);
+
+    for _ in 0..3 {
+        harness.rrq("image.bin", vec![]);
+        harness.ack(1);
+        harness.advance(Duration::from_secs(1));
+    }
+
+    assert_eq!(harness.server.metrics().completed, 3);
+}
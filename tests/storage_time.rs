@@ -0,0 +1,103 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    io::{self, Cursor, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::{Harness, Recorder};
+use tftpd::{MockClock, TransferEvent};
+
+/// A source taking 30 ms of the mock clock for every read, like a file on
+/// slow storage.
+struct SlowSource {
+    inner: Cursor<Vec<u8>>,
+    clock: Arc<MockClock>,
+    reads: Arc<AtomicU64>,
+}
+
+impl Read for SlowSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.clock.advance(Duration::from_millis(30));
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.inner.read(buf)
+    }
+}
+
+/// Returns the storage time and network wait of the completed transfer.
+fn completion(recorder: &Recorder) -> (Duration, Duration) {
+    recorder
+        .events()
+        .into_iter()
+        .find_map(|event| match event {
+            TransferEvent::Completed {
+                storage_time,
+                network_wait,
+                ..
+            } => Some((storage_time, network_wait)),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn attributes_time_to_slow_storage() {
+    let mut harness = Harness::with_args(&["--pipe", "firmware.bin", "--slow-storage-warn", "20"]);
+    let reads = Arc::new(AtomicU64::new(0));
+    harness.server.set_pipe_source(Box::new(SlowSource {
+        inner: Cursor::new(vec![7; 512 * 2 + 77]),
+        clock: harness.clock.clone(),
+        reads: reads.clone(),
+    }));
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("firmware.bin", vec![]);
+    for block in 1..=3 {
+        harness.advance(Duration::from_millis(5));
+        harness.ack(block);
+    }
+
+    let reads = reads.load(Ordering::Relaxed);
+    assert!(reads >= 3, "{reads} reads");
+    let (storage_time, network_wait) = completion(&recorder);
+    assert_eq!(storage_time, Duration::from_millis(30) * reads as u32);
+    assert_eq!(network_wait, Duration::from_millis(15));
+
+    let latency = harness.server.metrics().latency_counters();
+    // Each of the 3 blocks is one storage call, however many reads it
+    // took.
+    assert!(latency.contains(&("storage.le_100ms", 3)));
+    assert!(latency.contains(&("network_wait.le_5ms", 3)));
+}
+
+#[test]
+fn attributes_time_to_network() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 512 + 77);
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+
+    harness.rrq("image.bin", vec![]);
+    harness.advance(Duration::from_millis(250));
+    harness.ack(1);
+    harness.advance(Duration::from_millis(1500));
+    harness.ack(2);
+
+    // The mock clock does not move while the file is read.
+    let (storage_time, network_wait) = completion(&recorder);
+    assert_eq!(storage_time, Duration::ZERO);
+    assert_eq!(network_wait, Duration::from_millis(1750));
+
+    let latency = harness.server.metrics().latency_counters();
+    assert!(latency.contains(&("network_wait.le_1000ms", 1)));
+    assert!(latency.contains(&("network_wait.gt_1000ms", 1)));
+    let storage_calls: u64 = latency[..6].iter().map(|(_, count)| count).sum();
+    assert!(storage_calls >= 4, "{storage_calls} storage calls");
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{fs, path::PathBuf, thread, time::Duration};
+
+use common::{data, error, Harness};
+use tftpd::{check_health, ErrorCode, Health};
+
+fn unavailable() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "storage temporarily unavailable")
+}
+
+/// Moves the served directory away, as when its mount goes away, and
+/// returns where it went.
+fn unmount(harness: &Harness) -> PathBuf {
+    let away = harness.dir.path().with_extension("away");
+    fs::rename(harness.dir.path(), &away).unwrap();
+    away
+}
+
+fn remount(harness: &Harness, away: PathBuf) {
+    fs::rename(away, harness.dir.path()).unwrap();
+}
+
+/// Runs [`check_health()`] against the server while it polls.
+fn health(harness: &mut Harness) -> Health {
+    let server = harness.server_addr();
+    let check = thread::spawn(move || check_health(server, Duration::from_secs(2)));
+    while !check.is_finished() {
+        harness.server.poll().unwrap();
+    }
+    check.join().unwrap().unwrap()
+}
+
+#[test]
+fn refuses_requests_until_directory_returns() {
+    let mut harness = Harness::new();
+    let contents = harness.create_file("boot.img", 100);
+
+    let away = unmount(&harness);
+    harness.rrq("boot.img", vec![]);
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![unavailable(), unavailable()]);
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.storage_unavailable, 1);
+    assert_eq!(metrics.unavailable_rejections, 2);
+
+    remount(&harness, away);
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+    assert_eq!(harness.server.metrics().storage_unavailable, 0);
+}
+
+#[test]
+fn refuses_requests_when_directory_is_replaced_by_a_file() {
+    let mut harness = Harness::new();
+    harness.create_file("boot.img", 100);
+
+    let away = unmount(&harness);
+    fs::write(harness.dir.path(), b"not a directory").unwrap();
+    harness.rrq("boot.img", vec![]);
+    assert_eq!(harness.take_sent(), vec![unavailable()]);
+
+    fs::remove_file(harness.dir.path()).unwrap();
+    remount(&harness, away);
+}
+
+#[test]
+fn healthcheck_fails_while_degraded() {
+    let mut harness = Harness::new();
+    assert_eq!(health(&mut harness), Health::Serving);
+
+    let away = unmount(&harness);
+    assert_eq!(health(&mut harness), Health::StorageUnavailable);
+
+    remount(&harness, away);
+    assert_eq!(health(&mut harness), Health::Serving);
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "test-util")]
+
+use std::time::Duration;
+
+use tftpd::test_util::{assert_file_eq, assert_file_matches, ScriptedClient, TestDir, TestServer};
+use tftpd::{Client, Config, ErrorCode, OptionType, Packet, TransferOption};
+
+fn data(block_num: u16, data: &[u8]) -> Packet {
+    Packet::Data {
+        block_num,
+        data: data.to_vec(),
+    }
+}
+
+#[test]
+fn scripted_client_records_answers_per_packet() {
+    let dir = TestDir::new();
+    let contents = dir.create_file("boot.img", 1024 + 77);
+    let server = TestServer::start(dir.path());
+
+    let mut client = ScriptedClient::new(server.addr())
+        .rrq(
+            "boot.img",
+            vec![TransferOption {
+                option: OptionType::Windowsize,
+                value: 2,
+            }],
+        )
+        .then_send(Packet::Ack(0))
+        .then_send(Packet::Ack(2));
+    let responses = client.run();
+
+    assert_eq!(
+        responses,
+        vec![
+            vec![Packet::Oack(vec![TransferOption {
+                option: OptionType::Windowsize,
+                value: 2,
+            }])],
+            vec![data(1, &contents[..512]), data(2, &contents[512..1024])],
+            vec![data(3, &contents[1024..])],
+        ]
+    );
+    assert!(client.run().is_empty());
+}
+
+#[test]
+fn scripted_client_records_errors_and_silence() {
+    let dir = TestDir::new();
+    let server = TestServer::start(dir.path());
+
+    let mut client = ScriptedClient::new(server.addr());
+    client.set_timeout(Duration::from_millis(200));
+    let refused = client.send(&Packet::Rrq {
+        filename: "missing.img".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    });
+    let ignored = client.send(&Packet::Error {
+        code: ErrorCode::NotDefined,
+        msg: "done".to_string(),
+    });
+
+    assert!(matches!(
+        refused[..],
+        [Packet::Error {
+            code: ErrorCode::FileNotFound,
+            ..
+        }]
+    ));
+    assert!(ignored.is_empty());
+}
+
+#[test]
+fn test_server_serves_until_shut_down() {
+    let dir = TestDir::new();
+    dir.write("pxelinux.cfg/default", b"default linux\n");
+    let contents = dir.create_file("vmlinuz", 512 * 40 + 1);
+    let server = TestServer::with_config(&Config {
+        directory: dir.path().to_path_buf(),
+        port: 0,
+        ..Config::default()
+    });
+    let client = Client::new(server.addr());
+
+    let config = client.get("pxelinux.cfg/default", vec![]).unwrap();
+    let kernel = client.get("vmlinuz", vec![]).unwrap();
+
+    assert_file_eq(&config.data, b"default linux\n");
+    assert_file_eq(&kernel.data, &contents);
+    assert_file_matches(&kernel.data, dir.path().join("vmlinuz"));
+    let server = server.shutdown();
+    assert_eq!(server.metrics().completed, 2);
+    assert_eq!(server.session_count(), 0);
+}
@@ -0,0 +1,354 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    net::UdpSocket,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{error, option, Harness, Recorder};
+use tftpd::test_util::{assert_file_eq, TestDir, TestServer};
+use tftpd::{Client, Config, ErrorCode, OptionType, Packet, Server, TransferEvent};
+
+fn config(dir: &TestDir, threaded: bool) -> Config {
+    Config {
+        directory: dir.path().to_path_buf(),
+        port: 0,
+        threaded,
+        ..Config::default()
+    }
+}
+
+fn start(dir: &TestDir, threaded: bool) -> TestServer {
+    TestServer::with_config(&config(dir, threaded))
+}
+
+/// Receives the next datagram at `client`, with the port it came from.
+fn recv_from(client: &UdpSocket) -> (Packet, u16) {
+    let mut buf = [0; 1024];
+    let (size, from) = client.recv_from(&mut buf).unwrap();
+    (Packet::deserialize(&buf[..size]).unwrap(), from.port())
+}
+
+/// Downloads a file with blksize, windowsize and timeout from a server in
+/// either mode, which must behave the same.
+fn downloads_with_options(threaded: bool) {
+    let dir = TestDir::new();
+    let contents = dir.create_file("rootfs.img", 1024 * 20 + 100);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let mut server = Server::with_socket(&config(&dir, threaded), socket).unwrap();
+    let recorder = Arc::new(Recorder::default());
+    server.set_observer(recorder.clone());
+    let options = vec![
+        option(OptionType::BlockSize, 1024),
+        option(OptionType::Timeout, 2),
+        option(OptionType::Windowsize, 4),
+    ];
+
+    let download = thread::spawn({
+        let options = options.clone();
+        move || {
+            let mut client = Client::new(addr);
+            client.set_timeout(Duration::from_millis(200));
+            client.get("rootfs.img", options).unwrap()
+        }
+    });
+    // The end of a delegated transfer is noticed on a later poll.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while server.metrics().completed == 0 {
+        assert!(Instant::now() < deadline, "transfer never completed");
+        server.poll().unwrap();
+    }
+    let download = download.join().unwrap();
+
+    assert_file_eq(&download.data, &contents);
+    assert_eq!(download.options, options);
+    let metrics = server.metrics();
+    assert_eq!((metrics.completed, metrics.failed), (1, 0));
+    let network_wait = recorder.events().into_iter().find_map(|event| match event {
+        TransferEvent::Completed { network_wait, .. } => Some(network_wait),
+        _ => None,
+    });
+    assert!(network_wait.is_some_and(|wait| wait > Duration::ZERO));
+}
+
+#[test]
+fn downloads_from_single_port() {
+    downloads_with_options(false);
+}
+
+#[test]
+fn downloads_from_worker_thread() {
+    downloads_with_options(true);
+}
+
+#[test]
+fn sends_from_ephemeral_port() {
+    let dir = TestDir::new();
+    dir.write("boot.cfg", b"default linux\n");
+    let server = start(&dir, true);
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+
+    let rrq = Packet::Rrq {
+        filename: "boot.cfg".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&rrq.serialize().unwrap(), server.addr())
+        .unwrap();
+    let mut buf = [0; 1024];
+    let (size, from) = client.recv_from(&mut buf).unwrap();
+
+    assert_ne!(from.port(), server.addr().port());
+    assert_eq!(
+        Packet::deserialize(&buf[..size]).unwrap(),
+        Packet::Data {
+            block_num: 1,
+            data: b"default linux\n".to_vec()
+        }
+    );
+    client
+        .send_to(&Packet::Ack(1).serialize().unwrap(), from)
+        .unwrap();
+}
+
+#[test]
+fn counts_transfers_on_worker_threads() {
+    let mut harness = Harness::with_args(&["--threaded"]);
+    harness.create_file("kernel.img", 2000);
+
+    harness.rrq("kernel.img", vec![]);
+
+    assert_eq!(harness.server.session_count(), 1);
+    let sessions = harness.server.active_sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].sessions, [harness.client.local_addr().unwrap()]);
+}
+
+#[test]
+fn ignores_retransmitted_request_of_worker() {
+    let mut harness = Harness::with_args(&["--threaded"]);
+    harness.create_file("kernel.img", 2000);
+
+    harness.rrq("kernel.img", vec![]);
+    let (first, port) = recv_from(&harness.client);
+    harness.rrq("kernel.img", vec![]);
+
+    assert!(matches!(first, Packet::Data { block_num: 1, .. }));
+    assert_eq!(harness.server.session_count(), 1);
+    assert_eq!(harness.server.metrics().retransmitted_requests, 1);
+    harness
+        .client
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(
+        harness.client.recv_from(&mut buf).is_err(),
+        "a second worker answered"
+    );
+    assert_ne!(port, harness.server_addr().port());
+}
+
+#[test]
+fn rejects_conflicting_request_of_worker() {
+    let mut harness = Harness::with_args(&["--threaded", "--conflicting-request", "reject"]);
+    harness.create_file("kernel.img", 2000);
+    harness.create_file("initrd.img", 2000);
+
+    harness.rrq("kernel.img", vec![]);
+    harness.rrq("initrd.img", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::NotDefined, "transfer in progress")]
+    );
+    assert_eq!(harness.server.session_count(), 1);
+}
+
+#[test]
+fn limits_transfers_on_worker_threads_per_client() {
+    let mut harness = Harness::with_args(&["--threaded", "--max-per-ip", "1"]);
+    harness.create_file("kernel.img", 2000);
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    harness.rrq("kernel.img", vec![]);
+    let rrq = Packet::Rrq {
+        filename: "kernel.img".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    other
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(
+            ErrorCode::NotDefined,
+            "too many transfers, retry later"
+        )]
+    );
+}
+
+#[test]
+fn replaces_transfer_of_worker() {
+    let mut harness = Harness::with_args(&["--threaded"]);
+    harness.create_file("kernel.img", 2000);
+    harness.create_file("initrd.img", 100);
+
+    harness.rrq("kernel.img", vec![]);
+    let (_, replaced) = recv_from(&harness.client);
+    harness.rrq("initrd.img", vec![]);
+
+    assert_eq!(harness.server.session_count(), 1);
+    let mut answers = [recv_from(&harness.client), recv_from(&harness.client)];
+    answers.sort_by_key(|(packet, _)| matches!(packet, Packet::Data { .. }));
+    assert_eq!(
+        answers[0],
+        (
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "transfer aborted".to_string()
+            },
+            replaced
+        )
+    );
+    assert!(matches!(answers[1].0, Packet::Data { block_num: 1, .. }));
+}
+
+#[test]
+fn charges_transfers_on_worker_threads_to_quota() {
+    let mut harness = Harness::with_args(&["--threaded", "--quota", "1000/1h"]);
+    harness.create_file("kernel.img", 1500);
+
+    harness.rrq("kernel.img", vec![]);
+    loop {
+        let (packet, port) = recv_from(&harness.client);
+        let Packet::Data { block_num, data } = packet else {
+            panic!("unexpected {packet:?}");
+        };
+        let mut worker = harness.server_addr();
+        worker.set_port(port);
+        harness
+            .client
+            .send_to(&Packet::Ack(block_num).serialize().unwrap(), worker)
+            .unwrap();
+        if data.len() < 512 {
+            break;
+        }
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while harness.server.metrics().completed == 0 {
+        assert!(Instant::now() < deadline, "transfer never completed");
+        harness.server.poll().unwrap();
+    }
+    harness.take_sent();
+    harness.rrq("kernel.img", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(
+            ErrorCode::NotDefined,
+            "quota exceeded, retry after 60 minutes"
+        )]
+    );
+    assert_eq!(harness.server.quota_usage()[0].bytes, 1500);
+}
+
+/// Requests `filename` from `server` with a client that never answers.
+fn request(server: &TestServer, filename: &str) -> UdpSocket {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let rrq = Packet::Rrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&rrq.serialize().unwrap(), server.addr())
+        .unwrap();
+    client
+}
+
+#[test]
+fn delays_first_packet_of_worker() {
+    let dir = TestDir::new();
+    dir.create_file("kernel.img", 2000);
+    let server = TestServer::with_config(&Config {
+        initial_delay: Duration::from_millis(300),
+        ..config(&dir, true)
+    });
+
+    let requested = Instant::now();
+    let client = request(&server, "kernel.img");
+    let (first, _) = recv_from(&client);
+
+    assert!(matches!(first, Packet::Data { block_num: 1, .. }));
+    assert!(requested.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn aborts_worker_transfer_exceeding_max_duration() {
+    let dir = TestDir::new();
+    dir.create_file("kernel.img", 2000);
+    let server = TestServer::with_config(&Config {
+        max_transfer_duration: Some(Duration::from_millis(300)),
+        ..config(&dir, true)
+    });
+
+    let client = request(&server, "kernel.img");
+    let (first, port) = recv_from(&client);
+
+    assert!(matches!(first, Packet::Data { block_num: 1, .. }));
+    assert_eq!(
+        recv_from(&client),
+        (
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "transfer exceeded maximum duration".to_string()
+            },
+            port
+        )
+    );
+}
+
+#[test]
+fn aborts_worker_transfer_of_idle_client() {
+    let dir = TestDir::new();
+    dir.create_file("kernel.img", 2000);
+    let server = TestServer::with_config(&Config {
+        session_timeout: Some(Duration::from_millis(300)),
+        ..config(&dir, true)
+    });
+
+    let client = request(&server, "kernel.img");
+    let (first, port) = recv_from(&client);
+    let sent = Instant::now();
+
+    assert!(matches!(first, Packet::Data { block_num: 1, .. }));
+    assert_eq!(
+        recv_from(&client),
+        (
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "transfer timed out".to_string()
+            },
+            port
+        )
+    );
+    assert!(
+        sent.elapsed() < Duration::from_secs(1),
+        "retransmitted instead"
+    );
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use common::Harness;
+use tftpd::Packet;
+
+/// Number of due timers the server handles per tick.
+const TIMERS_PER_TICK: u64 = 256;
+
+/// Starts a transfer for a new client socket, which is returned.
+fn start_session(harness: &mut Harness) -> UdpSocket {
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "image.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    client
+        .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    client
+}
+
+#[test]
+fn spreads_retransmissions_over_ticks() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 1000);
+    let _clients: Vec<_> = (0..300).map(|_| start_session(&mut harness)).collect();
+    assert_eq!(harness.server.session_count(), 300);
+
+    harness.advance(Duration::from_secs(5));
+    assert_eq!(harness.server.metrics().retransmits, TIMERS_PER_TICK);
+
+    harness.advance(Duration::ZERO);
+    assert_eq!(harness.server.metrics().retransmits, 300);
+
+    harness.advance(Duration::ZERO);
+    assert_eq!(harness.server.metrics().retransmits, 300);
+}
+
+#[test]
+fn records_tick_durations() {
+    let mut harness = Harness::with_args(&["--tick-budget", "0"]);
+    harness.create_file("image.bin", 100);
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+
+    let metrics = harness.server.metrics();
+    let ticks: u64 = metrics.tick_counters().iter().map(|(_, count)| count).sum();
+    assert_eq!(ticks, 2);
+    assert_eq!(metrics.slow_ticks, 2);
+}
+
+/// Benchmark of the ticks with 10k idle sessions, run with
+/// `cargo test --release --test tick_budget -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_ticks_with_idle_sessions() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 1000);
+    for _ in 0..10_000 {
+        start_session(&mut harness);
+    }
+    let before = harness.server.metrics();
+
+    // Every session is due for a progress report, then for a retransmission.
+    let mut slowest = Duration::ZERO;
+    for step in [Duration::from_secs(1), Duration::from_secs(4)] {
+        harness.clock.advance(step);
+        for _ in 0..50 {
+            let started = Instant::now();
+            harness.server.poll().unwrap();
+            // Every poll waits for a packet until the tick interval passes.
+            slowest = slowest.max(started.elapsed().saturating_sub(Duration::from_millis(100)));
+        }
+    }
+
+    let metrics = harness.server.metrics();
+    println!("Slowest tick: {slowest:?}");
+    for ((name, count), (_, before)) in metrics.tick_counters().iter().zip(before.tick_counters()) {
+        println!("{name}: {}", count - before);
+    }
+    // Ports of the dropped client sockets can be reused by later ones.
+    assert_eq!(metrics.retransmits, harness.server.session_count() as u64);
+    assert_eq!(metrics.tick_counters()[5].1, before.tick_counters()[5].1);
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+fn timed_out() -> Vec<u8> {
+    error(ErrorCode::NotDefined, "transfer timed out")
+}
+
+fn unknown_id() -> Vec<u8> {
+    error(ErrorCode::UnknownId, "unknown transfer ID")
+}
+
+/// Starts a transfer and lets it time out.
+fn time_out(harness: &mut Harness) {
+    harness.create_file("image.bin", 700);
+    harness.rrq("image.bin", vec![]);
+    for _ in 0..7 {
+        harness.advance(Duration::from_secs(5));
+    }
+    assert_eq!(harness.take_sent().last(), Some(&timed_out()));
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn late_ack_gets_reason_once() {
+    let mut harness = Harness::new();
+    time_out(&mut harness);
+
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), vec![timed_out()]);
+
+    harness.ack(1);
+    assert_eq!(harness.take_sent(), vec![unknown_id()]);
+}
+
+#[test]
+fn tombstone_expires() {
+    let mut harness = Harness::new();
+    time_out(&mut harness);
+
+    harness.advance(Duration::from_secs(30));
+    harness.ack(1);
+
+    assert_eq!(harness.take_sent(), vec![unknown_id()]);
+}
+
+#[test]
+fn new_transfer_clears_tombstone() {
+    let mut harness = Harness::new();
+    time_out(&mut harness);
+
+    harness.rrq("image.bin", vec![]);
+    harness.ack(1);
+
+    let contents = std::fs::read(harness.dir.path().join("image.bin")).unwrap();
+    assert_eq!(
+        harness.take_sent(),
+        vec![data(1, &contents[..512]), data(2, &contents[512..])]
+    );
+    harness.ack(2);
+    assert_eq!(harness.server.metrics().completed, 1);
+
+    harness.ack(2);
+    assert_eq!(harness.take_sent(), vec![unknown_id()]);
+}
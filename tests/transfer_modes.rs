@@ -0,0 +1,259 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    net::{SocketAddr, UdpSocket},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet, TransferOption, TransferSummary, Worker};
+
+/// Mode `trait` is a client talking to the server in one of its modes, so
+/// that the same protocol cases run against both.
+trait Mode {
+    /// Requests a file of `size` bytes with `options` and returns its
+    /// contents.
+    fn request(&mut self, size: usize, options: Vec<TransferOption>) -> Vec<u8>;
+    /// Returns the next datagram sent to the client.
+    fn recv(&mut self) -> Vec<u8>;
+    fn ack(&mut self, block: u16);
+    /// Lets `duration` pass without the client answering.
+    fn wait(&mut self, duration: Duration);
+    /// Returns whether the transfer completed.
+    fn completed(&mut self) -> bool;
+}
+
+/// The single port of the server, driven with a mock clock.
+struct SinglePort {
+    harness: Harness,
+    sent: VecDeque<Vec<u8>>,
+}
+
+impl SinglePort {
+    fn new() -> SinglePort {
+        SinglePort {
+            harness: Harness::new(),
+            sent: VecDeque::new(),
+        }
+    }
+}
+
+impl Mode for SinglePort {
+    fn request(&mut self, size: usize, options: Vec<TransferOption>) -> Vec<u8> {
+        let contents = self.harness.create_file("file.bin", size);
+        self.harness.rrq("file.bin", options);
+        contents
+    }
+
+    fn recv(&mut self) -> Vec<u8> {
+        self.sent.extend(self.harness.take_sent());
+        self.sent.pop_front().expect("no datagram sent")
+    }
+
+    fn ack(&mut self, block: u16) {
+        self.harness.ack(block);
+    }
+
+    fn wait(&mut self, duration: Duration) {
+        self.harness.advance(duration);
+    }
+
+    fn completed(&mut self) -> bool {
+        self.harness.server.metrics().completed == 1
+    }
+}
+
+/// A [`Worker`] on its own port, driven in real time.
+struct DedicatedSocket {
+    client: UdpSocket,
+    worker: Option<SocketAddr>,
+    handle: Option<JoinHandle<Result<TransferSummary, String>>>,
+}
+
+impl DedicatedSocket {
+    fn new() -> DedicatedSocket {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        DedicatedSocket {
+            client,
+            worker: None,
+            handle: None,
+        }
+    }
+}
+
+impl Mode for DedicatedSocket {
+    fn request(&mut self, size: usize, options: Vec<TransferOption>) -> Vec<u8> {
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let worker = Worker::new(
+            [127, 0, 0, 1].into(),
+            self.client.local_addr().unwrap(),
+            Box::new(Cursor::new(contents.clone())),
+            Some(size as u64),
+            options,
+        )
+        .unwrap();
+        self.worker = Some(worker.local_addr().unwrap());
+        self.handle = Some(worker.spawn());
+        contents
+    }
+
+    fn recv(&mut self) -> Vec<u8> {
+        let mut buf = [0; 65536];
+        let (size, from) = self.client.recv_from(&mut buf).unwrap();
+        assert_eq!(Some(from), self.worker);
+        buf[..size].to_vec()
+    }
+
+    fn ack(&mut self, block: u16) {
+        let buf = Packet::Ack(block).serialize().unwrap();
+        self.client.send_to(&buf, self.worker.unwrap()).unwrap();
+    }
+
+    fn wait(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn completed(&mut self) -> bool {
+        let handle = self.handle.take().unwrap();
+        handle.join().unwrap().is_ok()
+    }
+}
+
+fn oack(options: Vec<TransferOption>) -> Vec<u8> {
+    Packet::Oack(options).serialize().unwrap()
+}
+
+fn sends_file_in_blocks(mode: &mut dyn Mode) {
+    let contents = mode.request(512 * 2 + 77, vec![]);
+
+    assert_eq!(mode.recv(), data(1, &contents[..512]));
+    mode.ack(1);
+    assert_eq!(mode.recv(), data(2, &contents[512..1024]));
+    mode.ack(2);
+    assert_eq!(mode.recv(), data(3, &contents[1024..]));
+    mode.ack(3);
+
+    assert!(mode.completed());
+}
+
+fn negotiates_options(mode: &mut dyn Mode) {
+    let contents = mode.request(
+        4000,
+        vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::Windowsize, 2),
+            option(OptionType::TransferSize, 0),
+        ],
+    );
+
+    assert_eq!(
+        mode.recv(),
+        oack(vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::TransferSize, 4000),
+            option(OptionType::Windowsize, 2),
+        ])
+    );
+    mode.ack(0);
+    assert_eq!(mode.recv(), data(1, &contents[..1024]));
+    assert_eq!(mode.recv(), data(2, &contents[1024..2048]));
+    mode.ack(2);
+    assert_eq!(mode.recv(), data(3, &contents[2048..3072]));
+    assert_eq!(mode.recv(), data(4, &contents[3072..]));
+    mode.ack(4);
+
+    assert!(mode.completed());
+}
+
+fn slides_window_on_partial_ack(mode: &mut dyn Mode) {
+    let contents = mode.request(512 * 2 + 77, vec![option(OptionType::Windowsize, 2)]);
+
+    assert_eq!(mode.recv(), oack(vec![option(OptionType::Windowsize, 2)]));
+    mode.ack(0);
+    assert_eq!(mode.recv(), data(1, &contents[..512]));
+    assert_eq!(mode.recv(), data(2, &contents[512..1024]));
+
+    // Block 2 was lost, the window restarts from it.
+    mode.ack(1);
+    assert_eq!(mode.recv(), data(2, &contents[512..1024]));
+    assert_eq!(mode.recv(), data(3, &contents[1024..]));
+    mode.ack(3);
+
+    assert!(mode.completed());
+}
+
+fn resends_lost_tail_of_window(mode: &mut dyn Mode) {
+    let contents = mode.request(512 * 4 + 100, vec![option(OptionType::Windowsize, 4)]);
+
+    assert_eq!(mode.recv(), oack(vec![option(OptionType::Windowsize, 4)]));
+    mode.ack(0);
+    for block in 1..=4 {
+        let start = (block as usize - 1) * 512;
+        assert_eq!(mode.recv(), data(block, &contents[start..start + 512]));
+    }
+
+    // Blocks 3 and 4 were lost, they are sent again with their numbers
+    // before the block that was never sent.
+    mode.ack(2);
+    assert_eq!(mode.recv(), data(3, &contents[1024..1536]));
+    assert_eq!(mode.recv(), data(4, &contents[1536..2048]));
+    assert_eq!(mode.recv(), data(5, &contents[2048..]));
+    mode.ack(5);
+
+    assert!(mode.completed());
+}
+
+fn retransmits_after_timeout(mode: &mut dyn Mode) {
+    let contents = mode.request(600, vec![option(OptionType::Timeout, 1)]);
+
+    assert_eq!(mode.recv(), oack(vec![option(OptionType::Timeout, 1)]));
+    mode.ack(0);
+    assert_eq!(mode.recv(), data(1, &contents[..512]));
+
+    mode.wait(Duration::from_millis(1200));
+    assert_eq!(mode.recv(), data(1, &contents[..512]));
+    mode.ack(1);
+    assert_eq!(mode.recv(), data(2, &contents[512..]));
+    mode.ack(2);
+
+    assert!(mode.completed());
+}
+
+/// Runs `case` against the single port of the server and a [`Worker`].
+fn in_both_modes(case: fn(&mut dyn Mode)) {
+    case(&mut SinglePort::new());
+    case(&mut DedicatedSocket::new());
+}
+
+#[test]
+fn sends_file_in_blocks_in_both_modes() {
+    in_both_modes(sends_file_in_blocks);
+}
+
+#[test]
+fn negotiates_options_in_both_modes() {
+    in_both_modes(negotiates_options);
+}
+
+#[test]
+fn slides_window_on_partial_ack_in_both_modes() {
+    in_both_modes(slides_window_on_partial_ack);
+}
+
+#[test]
+fn resends_lost_tail_of_window_in_both_modes() {
+    in_both_modes(resends_lost_tail_of_window);
+}
+
+#[test]
+fn retransmits_after_timeout_in_both_modes() {
+    in_both_modes(retransmits_after_timeout);
+}
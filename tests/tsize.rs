@@ -0,0 +1,79 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, option, Harness};
+use tftpd::{OptionType, Packet};
+
+fn oack(options: Vec<(OptionType, u64)>) -> Vec<u8> {
+    Packet::Oack(
+        options
+            .into_iter()
+            .map(|(option_type, value)| option(option_type, value))
+            .collect(),
+    )
+    .serialize()
+    .unwrap()
+}
+
+fn requested() -> Vec<tftpd::TransferOption> {
+    vec![
+        option(OptionType::BlockSize, 1024),
+        option(OptionType::TransferSize, 0),
+    ]
+}
+
+#[test]
+fn echoes_file_size_by_default() {
+    let mut harness = Harness::new();
+    harness.create_file("image.bin", 3000);
+
+    harness.rrq("image.bin", requested());
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![oack(vec![
+            (OptionType::BlockSize, 1024),
+            (OptionType::TransferSize, 3000)
+        ])]
+    );
+}
+
+#[test]
+fn omits_tsize() {
+    let mut harness = Harness::with_args(&["--tsize", "omit"]);
+    harness.create_file("image.bin", 3000);
+
+    harness.rrq("image.bin", requested());
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![oack(vec![(OptionType::BlockSize, 1024)])]
+    );
+}
+
+#[test]
+fn omitting_only_option_skips_oack() {
+    let mut harness = Harness::with_args(&["--tsize", "omit"]);
+    let contents = harness.create_file("image.bin", 100);
+
+    harness.rrq("image.bin", vec![option(OptionType::TransferSize, 0)]);
+
+    assert_eq!(harness.take_sent(), vec![data(1, &contents)]);
+}
+
+#[test]
+fn answers_zero_tsize() {
+    let mut harness = Harness::with_args(&["--tsize", "zero"]);
+    harness.create_file("image.bin", 3000);
+
+    harness.rrq("image.bin", requested());
+
+    assert_eq!(
+        harness.take_sent(),
+        vec![oack(vec![
+            (OptionType::BlockSize, 1024),
+            (OptionType::TransferSize, 0)
+        ])]
+    );
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{data, error, Harness};
+use tftpd::ErrorCode;
+
+#[test]
+fn answers_stray_ack_with_unknown_id() {
+    let mut harness = Harness::new();
+
+    harness.ack(3);
+
+    assert_eq!(
+        harness.recv().unwrap(),
+        error(ErrorCode::UnknownId, "unknown transfer ID")
+    );
+    assert_eq!(harness.server.metrics().orphan_acks, 1);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn replaces_transfer_of_requesting_port() {
+    let mut harness = Harness::new();
+    let first = harness.create_file("first.bin", 512 * 2 + 100);
+    let second = harness.create_file("second.bin", 100);
+
+    harness.rrq("first.bin", vec![]);
+    assert_eq!(harness.recv().unwrap(), data(1, &first[..512]));
+    harness.rrq("second.bin", vec![]);
+    assert_eq!(harness.recv().unwrap(), data(1, &second));
+
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.requests, 2);
+    assert_eq!(harness.server.session_count(), 1);
+
+    harness.ack(1);
+    assert_eq!(harness.server.metrics().completed, 1);
+    assert_eq!(harness.server.session_count(), 0);
+}
+
+#[test]
+fn rejects_request_from_port_with_transfer() {
+    let mut harness = Harness::with_args(&["--conflicting-request", "reject"]);
+    let first = harness.create_file("first.bin", 512 * 2 + 100);
+    harness.create_file("second.bin", 100);
+
+    harness.rrq("first.bin", vec![]);
+    assert_eq!(harness.recv().unwrap(), data(1, &first[..512]));
+    harness.rrq("second.bin", vec![]);
+    assert_eq!(
+        harness.recv().unwrap(),
+        error(ErrorCode::NotDefined, "transfer in progress")
+    );
+
+    harness.ack(1);
+    assert_eq!(harness.recv().unwrap(), data(2, &first[512..1024]));
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 0);
+    assert_eq!(metrics.requests, 1);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{io, sync::Arc, time::Duration};
+
+use common::{Harness, Recorder};
+use tftpd::TransferEvent;
+
+/// Starts a transfer whose client then stops being reachable, sends to it
+/// failing with `kind`.
+fn unplugged(kind: io::ErrorKind) -> (Harness, Arc<Recorder>) {
+    let mut harness = Harness::new();
+    let recorder = Arc::new(Recorder::default());
+    harness.server.set_observer(recorder.clone());
+    harness.create_file("image.bin", 512 * 2 + 77);
+
+    harness.rrq("image.bin", vec![]);
+    harness.socket.fail_outgoing_if(|_| true, kind);
+    (harness, recorder)
+}
+
+fn failure_reason(recorder: &Recorder) -> Option<String> {
+    recorder.events().into_iter().find_map(|event| match event {
+        TransferEvent::Failed { reason, .. } => Some(reason),
+        _ => None,
+    })
+}
+
+#[test]
+fn fails_unreachable_client_early() {
+    let (mut harness, recorder) = unplugged(io::ErrorKind::HostUnreachable);
+
+    harness.advance(Duration::from_secs(5));
+    harness.advance(Duration::from_secs(5));
+    assert_eq!(harness.server.metrics().failed, 0);
+
+    harness.advance(Duration::from_secs(5));
+    let metrics = harness.server.metrics();
+    assert_eq!(metrics.failed, 1);
+    assert_eq!(metrics.unreachable_clients, 1);
+    let reason = failure_reason(&recorder).unwrap();
+    assert!(reason.starts_with("client unreachable"), "{reason}");
+
+    // Nothing is left to retransmit.
+    harness.take_sent();
+    harness.advance(Duration::from_secs(5));
+    assert!(harness.take_sent().is_empty());
+}
+
+#[test]
+fn fails_unreachable_network_early() {
+    let (mut harness, _) = unplugged(io::ErrorKind::NetworkUnreachable);
+
+    for _ in 0..3 {
+        harness.advance(Duration::from_secs(5));
+    }
+
+    assert_eq!(harness.server.metrics().unreachable_clients, 1);
+}
+
+#[test]
+fn keeps_retrying_after_other_send_errors() {
+    let (mut harness, recorder) = unplugged(io::ErrorKind::PermissionDenied);
+
+    for _ in 0..5 {
+        harness.advance(Duration::from_secs(5));
+    }
+    assert_eq!(harness.server.metrics().failed, 0);
+
+    for _ in 0..2 {
+        harness.advance(Duration::from_secs(5));
+    }
+    assert_eq!(harness.server.metrics().unreachable_clients, 0);
+    let reason = failure_reason(&recorder).unwrap();
+    assert!(reason.contains("timed out"), "{reason}");
+}
+
+#[test]
+fn counts_only_consecutive_failures() {
+    let (mut harness, _) = unplugged(io::ErrorKind::HostUnreachable);
+
+    harness.advance(Duration::from_secs(5));
+    harness.advance(Duration::from_secs(5));
+    harness
+        .socket
+        .fail_outgoing_if(|_| false, io::ErrorKind::HostUnreachable);
+    harness.advance(Duration::from_secs(5));
+    harness
+        .socket
+        .fail_outgoing_if(|_| true, io::ErrorKind::HostUnreachable);
+    harness.advance(Duration::from_secs(5));
+    harness.advance(Duration::from_secs(5));
+
+    assert_eq!(harness.server.metrics().failed, 0);
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::Harness;
+use tftpd::Stall;
+
+/// Set in the environment of the child process of
+/// `aborts_when_the_loop_stalls`.
+#[cfg(unix)]
+const CHILD: &str = "TFTPD_WATCHDOG_CHILD";
+
+/// Returns a harness whose stall handler reports to the returned receiver.
+fn watched() -> (Harness, mpsc::Receiver<Stall>) {
+    let mut harness = Harness::with_args(&["--watchdog-timeout", "1"]);
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    harness
+        .server
+        .set_stall_handler(Arc::new(move |stall: &Stall| {
+            sender.lock().unwrap().send(*stall).unwrap();
+        }));
+    (harness, receiver)
+}
+
+#[test]
+fn reports_a_blocked_loop() {
+    let (mut harness, stalls) = watched();
+    harness.create_file("image.bin", 1000);
+
+    harness.rrq("image.bin", vec![]);
+    // The loop is blocked for as long as the server is not polled.
+    let stall = stalls.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert!(stall.since > Duration::from_secs(1));
+    assert_eq!(stall.sessions, 1);
+    assert!(stall.last_packet.is_some());
+    assert!(stalls.recv_timeout(Duration::from_millis(600)).is_err());
+}
+
+#[test]
+fn stays_quiet_while_the_loop_polls() {
+    let (mut harness, stalls) = watched();
+
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(2500) {
+        harness.server.poll().unwrap();
+    }
+
+    assert!(stalls.try_recv().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn aborts_when_the_loop_stalls() {
+    use std::{env, os::unix::process::ExitStatusExt, process::Command, thread};
+
+    if env::var_os(CHILD).is_some() {
+        let mut harness = Harness::with_args(&["--watchdog-timeout", "1"]);
+        harness.server.poll().unwrap();
+        thread::sleep(Duration::from_secs(10));
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["aborts_when_the_loop_stalls", "--exact", "--nocapture"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.signal(), Some(6), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("listen loop stalled"), "{stderr}");
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use common::{option, Harness};
+use tftpd::{OptionType, Packet};
+
+/// Reads `filename` with `blksize` and `windowsize`, acknowledging the last
+/// block of every window, and returns the DATA packets received in order.
+fn read_blocks(
+    harness: &mut Harness,
+    filename: &str,
+    blksize: u64,
+    windowsize: u64,
+) -> Vec<Vec<u8>> {
+    harness.rrq(
+        filename,
+        vec![
+            option(OptionType::BlockSize, blksize),
+            option(OptionType::Windowsize, windowsize),
+        ],
+    );
+    assert!(matches!(
+        Packet::deserialize(&harness.take_sent()[0]).unwrap(),
+        Packet::Oack(_)
+    ));
+
+    let mut blocks: Vec<Vec<u8>> = vec![];
+    let mut acked = 0;
+    for _ in 0..100 {
+        harness.ack(acked);
+        for sent in harness.take_sent() {
+            let Packet::Data { block_num, data } = Packet::deserialize(&sent).unwrap() else {
+                panic!("expected DATA");
+            };
+            assert_eq!(block_num as usize, blocks.len() + 1, "blocks out of order");
+            blocks.push(data);
+        }
+        acked = blocks.len() as u16;
+        if blocks
+            .last()
+            .is_some_and(|block| (block.len() as u64) < blksize)
+        {
+            harness.ack(acked);
+            return blocks;
+        }
+    }
+    panic!(
+        "no block shorter than {blksize} bytes after {} blocks",
+        blocks.len()
+    );
+}
+
+#[test]
+fn ends_transfers_of_whole_windows_with_empty_block() {
+    for (blksize, windowsize) in [(512, 1), (512, 4), (1024, 2), (1468, 8)] {
+        for windows in 1..=3 {
+            let mut harness = Harness::new();
+            let size = (blksize * windowsize * windows) as usize;
+            let contents = harness.create_file("image.bin", size);
+
+            let blocks = read_blocks(&mut harness, "image.bin", blksize, windowsize);
+
+            let case = format!("blksize {blksize}, windowsize {windowsize}, {windows} windows");
+            assert_eq!(blocks.len() as u64, windowsize * windows + 1, "{case}");
+            assert!(blocks.last().unwrap().is_empty(), "{case}");
+            assert_eq!(blocks.concat(), contents, "{case}");
+            let metrics = harness.server.metrics();
+            assert_eq!(metrics.completed, 1, "{case}");
+            assert_eq!(metrics.failed, 0, "{case}");
+            assert_eq!(harness.server.session_count(), 0, "{case}");
+        }
+    }
+}
+
+#[test]
+fn ends_transfers_one_byte_past_window_with_short_block() {
+    for (blksize, windowsize) in [(512, 1), (1024, 2), (1468, 8)] {
+        let mut harness = Harness::new();
+        let contents = harness.create_file("image.bin", (blksize * windowsize) as usize + 1);
+
+        let blocks = read_blocks(&mut harness, "image.bin", blksize, windowsize);
+
+        assert_eq!(blocks.len() as u64, windowsize + 1);
+        assert_eq!(blocks.last().unwrap().len(), 1);
+        assert_eq!(blocks.concat(), contents);
+        assert_eq!(harness.server.metrics().completed, 1);
+    }
+}
+
+#[test]
+fn sends_empty_file_as_single_empty_block() {
+    let mut harness = Harness::new();
+    harness.create_file("empty.bin", 0);
+
+    let blocks = read_blocks(&mut harness, "empty.bin", 512, 4);
+
+    assert_eq!(blocks, [Vec::<u8>::new()]);
+    assert_eq!(harness.server.metrics().completed, 1);
+}
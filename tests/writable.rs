@@ -0,0 +1,485 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{
+    fs,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+
+use common::{error, option, Harness};
+use tftpd::{
+    test_util::TestDir, Authorizer, Decision, ErrorCode, OptionType, Packet, RequestInfo,
+    TransferOption,
+};
+
+fn wrq(harness: &mut Harness, filename: &str, options: Vec<TransferOption>) {
+    harness.send(Packet::Wrq {
+        filename: filename.to_string(),
+        mode: "octet".to_string(),
+        options,
+    });
+}
+
+fn ack(block: u16) -> Vec<u8> {
+    Packet::Ack(block).serialize().unwrap()
+}
+
+fn send_data(harness: &mut Harness, block_num: u16, data: &[u8]) {
+    harness.send(Packet::Data {
+        block_num,
+        data: data.to_vec(),
+    });
+}
+
+#[test]
+fn refuses_write_requests_by_default() {
+    let mut harness = Harness::new();
+
+    wrq(&mut harness, "upload.bin", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::IllegalOperation, "invalid request")]
+    );
+    assert!(!harness.dir.path().join("upload.bin").exists());
+}
+
+#[test]
+fn receives_file() {
+    let mut harness = Harness::with_args(&["--writable"]);
+    let contents: Vec<u8> = (0..700).map(|i| i as u8).collect();
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    assert_eq!(harness.take_sent(), [ack(0)]);
+    send_data(&mut harness, 1, &contents[..512]);
+    assert!(!harness.dir.path().join("upload.bin").exists());
+    send_data(&mut harness, 2, &contents[512..]);
+
+    assert_eq!(harness.take_sent(), [ack(1), ack(2)]);
+    assert_eq!(
+        fs::read(harness.dir.path().join("upload.bin")).unwrap(),
+        contents
+    );
+    assert!(!harness.dir.path().join("upload.bin.part").exists());
+}
+
+#[test]
+fn acknowledges_duplicate_blocks_again() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    send_data(&mut harness, 1, &[1; 512]);
+    send_data(&mut harness, 1, &[1; 512]);
+    send_data(&mut harness, 2, b"end");
+    send_data(&mut harness, 2, b"end");
+
+    assert_eq!(
+        harness.take_sent(),
+        [ack(0), ack(1), ack(1), ack(2), ack(2)]
+    );
+    assert_eq!(
+        fs::metadata(harness.dir.path().join("upload.bin"))
+            .unwrap()
+            .len(),
+        515
+    );
+}
+
+#[test]
+fn keeps_existing_file_without_overwrite() {
+    let mut harness = Harness::with_args(&["--writable"]);
+    let contents = harness.create_file("switch.cfg", 100);
+
+    wrq(&mut harness, "switch.cfg", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::FileExists, "file already exists")]
+    );
+    assert_eq!(
+        fs::read(harness.dir.path().join("switch.cfg")).unwrap(),
+        contents
+    );
+}
+
+#[test]
+fn replaces_existing_file_with_overwrite() {
+    let mut harness = Harness::with_args(&["--writable", "--overwrite"]);
+    harness.create_file("switch.cfg", 100);
+
+    wrq(&mut harness, "switch.cfg", vec![]);
+    send_data(&mut harness, 1, b"hostname sw1\n");
+
+    assert_eq!(harness.take_sent(), [ack(0), ack(1)]);
+    assert_eq!(
+        fs::read(harness.dir.path().join("switch.cfg")).unwrap(),
+        b"hostname sw1\n"
+    );
+}
+
+#[test]
+fn negotiates_receive_options() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(
+        &mut harness,
+        "upload.bin",
+        vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::TransferSize, 1500),
+            option(OptionType::Windowsize, 4),
+        ],
+    );
+    assert_eq!(
+        harness.take_sent(),
+        [Packet::Oack(vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::TransferSize, 1500),
+        ])
+        .serialize()
+        .unwrap()]
+    );
+    send_data(&mut harness, 1, &[7; 1024]);
+    send_data(&mut harness, 2, &[7; 476]);
+
+    assert_eq!(harness.take_sent(), [ack(1), ack(2)]);
+    assert_eq!(
+        fs::read(harness.dir.path().join("upload.bin")).unwrap(),
+        [7; 1500]
+    );
+}
+
+#[test]
+fn refuses_paths_outside_directory() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(&mut harness, "../escaped.bin", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::AccessViolation, "file access violation")]
+    );
+    assert!(!harness.dir.path().join("../escaped.bin").exists());
+}
+
+#[test]
+fn gives_up_on_silent_client() {
+    let mut harness = Harness::with_args(&["--writable", "--max-retries", "2"]);
+
+    wrq(
+        &mut harness,
+        "upload.bin",
+        vec![option(OptionType::Timeout, 1)],
+    );
+    assert_eq!(
+        harness.take_sent(),
+        [Packet::Oack(vec![option(OptionType::Timeout, 1)])
+            .serialize()
+            .unwrap()]
+    );
+    send_data(&mut harness, 1, &[1; 512]);
+    harness.take_sent();
+
+    harness.advance(Duration::from_secs(1));
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.take_sent(), [ack(1), ack(1)]);
+    harness.advance(Duration::from_secs(1));
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::NotDefined, "transfer timed out")]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn refuses_uploads_missing_from_manifest() {
+    let manifest_dir = TestDir::new();
+    let manifest = manifest_dir.path().join("manifest");
+    fs::write(&manifest, "switch.cfg\n").unwrap();
+    let mut harness = Harness::with_args(&["--writable", "--manifest", manifest.to_str().unwrap()]);
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    wrq(&mut harness, "switch.cfg", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [
+            error(ErrorCode::AccessViolation, "file access violation"),
+            ack(0)
+        ]
+    );
+    assert!(!harness.dir.path().join("upload.bin.part").exists());
+}
+
+/// Allows reading every file and writing none.
+struct ReadOnly;
+
+impl Authorizer for ReadOnly {
+    fn authorize(&self, _: &SocketAddr, _: &str) -> Decision {
+        Decision::Allow
+    }
+
+    fn authorize_request(&self, request: &RequestInfo) -> Decision {
+        if request.write {
+            Decision::Deny {
+                code: ErrorCode::AccessViolation,
+                message: "read-only".to_string(),
+            }
+        } else {
+            Decision::Allow
+        }
+    }
+}
+
+#[test]
+fn asks_the_authorizer_about_uploads() {
+    let mut harness = Harness::with_args(&["--writable"]);
+    harness.server.set_authorizer(Arc::new(ReadOnly));
+
+    wrq(&mut harness, "upload.bin", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::AccessViolation, "read-only")]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn counts_uploads_against_max_per_ip() {
+    let mut harness = Harness::with_args(&["--writable", "--max-per-ip", "1"]);
+    harness.create_file("image.bin", 700);
+    let uploader = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let request = Packet::Wrq {
+        filename: "upload.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+    };
+    uploader
+        .send_to(&request.serialize().unwrap(), harness.server_addr())
+        .unwrap();
+    harness.server.poll().unwrap();
+    assert_eq!(harness.take_sent(), [ack(0)]);
+
+    harness.rrq("image.bin", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(
+            ErrorCode::NotDefined,
+            "too many transfers, retry later"
+        )]
+    );
+}
+
+#[test]
+fn receives_file_in_beneath_mode() {
+    let mut harness = Harness::with_args(&["--writable", "--beneath"]);
+    fs::create_dir(harness.dir.path().join("configs")).unwrap();
+
+    wrq(&mut harness, "configs\\switch.cfg", vec![]);
+    send_data(&mut harness, 1, b"hostname sw1\n");
+
+    assert_eq!(harness.take_sent(), [ack(0), ack(1)]);
+    assert_eq!(
+        fs::read(harness.dir.path().join("configs/switch.cfg")).unwrap(),
+        b"hostname sw1\n"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn keeps_upload_below_directory_swapped_for_symlink() {
+    let mut harness = Harness::with_args(&["--writable", "--beneath"]);
+    let outside = tempfile::tempdir().unwrap();
+    let configs = harness.dir.path().join("configs");
+    fs::create_dir(&configs).unwrap();
+
+    wrq(&mut harness, "configs/switch.cfg", vec![]);
+    send_data(&mut harness, 1, &[1; 512]);
+    fs::rename(&configs, harness.dir.path().join("real")).unwrap();
+    std::os::unix::fs::symlink(outside.path(), &configs).unwrap();
+    fs::write(outside.path().join("switch.cfg.part"), b"planted").unwrap();
+    send_data(&mut harness, 2, b"hostname sw1\n");
+
+    assert_eq!(
+        harness.take_sent(),
+        [
+            ack(0),
+            ack(1),
+            error(ErrorCode::AccessViolation, "file access violation")
+        ]
+    );
+    assert!(!outside.path().join("switch.cfg").exists());
+}
+
+#[test]
+fn waits_a_full_timeout_after_each_block() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(
+        &mut harness,
+        "upload.bin",
+        vec![option(OptionType::Timeout, 2)],
+    );
+    harness.advance(Duration::from_secs(1));
+    send_data(&mut harness, 1, &[1; 512]);
+    harness.take_sent();
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.take_sent(), Vec::<Vec<u8>>::new());
+
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.take_sent(), [ack(1)]);
+}
+
+#[test]
+fn refuses_uploads_to_filename_authorizers() {
+    let mut harness = Harness::with_args(&["--writable"]);
+    harness
+        .server
+        .set_authorizer(Arc::new(|_: &SocketAddr, _: &str| Decision::Allow));
+
+    wrq(&mut harness, "upload.bin", vec![]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::AccessViolation, "file access violation")]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn refuses_announced_size_above_max_upload_size() {
+    let mut harness = Harness::with_args(&["--writable", "--max-upload-size", "1000"]);
+
+    wrq(
+        &mut harness,
+        "upload.bin",
+        vec![option(OptionType::TransferSize, 1001)],
+    );
+
+    assert_eq!(
+        harness.take_sent(),
+        [error(ErrorCode::DiskFull, "file too large")]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn aborts_upload_past_max_upload_size() {
+    let mut harness = Harness::with_args(&["--writable", "--max-upload-size", "600"]);
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    send_data(&mut harness, 1, &[1; 512]);
+    send_data(&mut harness, 2, &[2; 100]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [ack(0), ack(1), error(ErrorCode::DiskFull, "disk full")]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn aborts_upload_past_announced_size() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(
+        &mut harness,
+        "upload.bin",
+        vec![option(OptionType::TransferSize, 100)],
+    );
+    send_data(&mut harness, 1, &[1; 101]);
+
+    assert_eq!(
+        harness.take_sent(),
+        [
+            Packet::Oack(vec![option(OptionType::TransferSize, 100)])
+                .serialize()
+                .unwrap(),
+            error(ErrorCode::DiskFull, "disk full")
+        ]
+    );
+    assert!(fs::read_dir(harness.dir.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn keeps_file_created_during_upload_without_overwrite() {
+    let mut harness = Harness::with_args(&["--writable"]);
+
+    wrq(&mut harness, "switch.cfg", vec![]);
+    let contents = harness.create_file("switch.cfg", 100);
+    send_data(&mut harness, 1, b"hostname sw1\n");
+
+    assert_eq!(
+        harness.take_sent(),
+        [ack(0), error(ErrorCode::FileExists, "file already exists")]
+    );
+    assert_eq!(
+        fs::read(harness.dir.path().join("switch.cfg")).unwrap(),
+        contents
+    );
+    assert!(!harness.dir.path().join("switch.cfg.part").exists());
+}
+
+#[test]
+fn hides_upload_in_progress() {
+    let mut harness = Harness::with_args(&["--writable", "--listing-file", ".dirlist"]);
+    harness.create_file("pxelinux.0", 100);
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    send_data(&mut harness, 1, &[1; 512]);
+    harness.take_sent();
+    let reader = UdpSocket::bind("127.0.0.1:0").unwrap();
+    reader
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut receive = |filename: &str| {
+        let rrq = Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+        };
+        reader
+            .send_to(&rrq.serialize().unwrap(), harness.server_addr())
+            .unwrap();
+        harness.server.poll().unwrap();
+        let mut buf = [0; 1024];
+        let size = reader.recv(&mut buf).unwrap();
+        Packet::deserialize(&buf[..size]).unwrap()
+    };
+
+    assert_eq!(
+        receive("upload.bin.part"),
+        Packet::Error {
+            code: ErrorCode::FileNotFound,
+            msg: "file does not exist".to_string()
+        }
+    );
+    let Packet::Data { data, .. } = receive(".dirlist") else {
+        panic!("expected DATA");
+    };
+    let listing = String::from_utf8(data).unwrap();
+    assert!(listing.starts_with("pxelinux.0\t100\t"));
+    assert_eq!(listing.lines().count(), 1);
+}
+
+#[test]
+fn keeps_uploads_out_of_quota() {
+    let mut harness = Harness::with_args(&["--writable", "--quota", "100/1h"]);
+    harness.create_file("pxelinux.0", 150);
+    harness.rrq("pxelinux.0", vec![]);
+    harness.ack(1);
+    harness.take_sent();
+
+    wrq(&mut harness, "upload.bin", vec![]);
+    send_data(&mut harness, 1, &[1; 512]);
+    send_data(&mut harness, 2, &[2; 100]);
+
+    assert_eq!(harness.take_sent(), [ack(0), ack(1), ack(2)]);
+    assert_eq!(harness.server.quota_usage()[0].bytes, 150);
+}